@@ -0,0 +1,96 @@
+//! Peak memory (RSS) of pack ingestion for synthetic packs, on both ingestion paths and with or without fsck.
+//!
+//! Run with `cargo bench -p gix-receive-pack --features progress,fsck --bench push_memory`.
+//!
+//! Each measurement runs in a fresh child process so peak RSS values don't influence each other.
+//! Set `GIX_RECEIVE_PACK_RSS_BUDGET_MIB` to fail if any measurement exceeds that many MiB,
+//! which turns this harness into a memory regression check. Peak RSS is read from
+//! `/proc/self/status`, so measurements are only available on Linux.
+
+use std::process::Command;
+
+mod synthetic;
+use synthetic::{Scenario, SyntheticPack, Variant};
+
+const CHILD_FLAG: &str = "--ingest-child";
+const BUDGET_VAR: &str = "GIX_RECEIVE_PACK_RSS_BUDGET_MIB";
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    if let Some(pos) = args.iter().position(|arg| arg == CHILD_FLAG) {
+        let [variant, pack] = &args[pos + 1..pos + 3] else {
+            panic!("{CHILD_FLAG} <variant> <pack>")
+        };
+        child(variant, pack.as_ref());
+        return;
+    }
+
+    if peak_rss_kib().is_none() {
+        eprintln!("peak RSS is not available on this platform, skipping");
+        return;
+    }
+    let budget_mib: Option<u64> = std::env::var(BUDGET_VAR)
+        .ok()
+        .map(|value| value.parse().unwrap_or_else(|_| panic!("{BUDGET_VAR} must be a number")));
+
+    let mut over_budget = Vec::new();
+    println!("{:<20} {:<22} {:>12} {:>14}", "scenario", "variant", "pack MiB", "peak RSS MiB");
+    for scenario in Scenario::ALL {
+        let pack = SyntheticPack::generate(scenario);
+        for variant in Variant::all() {
+            let output = Command::new(std::env::current_exe().expect("own executable"))
+                .arg(CHILD_FLAG)
+                .arg(variant.id())
+                .arg(&pack.path)
+                .output()
+                .expect("child can be spawned");
+            assert!(
+                output.status.success(),
+                "ingestion of {} with {} failed: {}",
+                scenario.name(),
+                variant.id(),
+                String::from_utf8_lossy(&output.stderr)
+            );
+            let peak_kib: u64 = String::from_utf8_lossy(&output.stdout)
+                .trim()
+                .parse()
+                .expect("child prints its peak RSS");
+            let peak_mib = peak_kib / 1024;
+            println!(
+                "{:<20} {:<22} {:>12} {:>14}",
+                scenario.name(),
+                variant.id(),
+                pack.size / (1024 * 1024),
+                peak_mib
+            );
+            if budget_mib.is_some_and(|budget| peak_mib > budget) {
+                over_budget.push(format!("{}/{}: {peak_mib} MiB", scenario.name(), variant.id()));
+            }
+        }
+    }
+
+    if !over_budget.is_empty() {
+        eprintln!("peak RSS exceeds the budget of {} MiB:", budget_mib.unwrap_or_default());
+        for line in over_budget {
+            eprintln!("  {line}");
+        }
+        std::process::exit(1);
+    }
+}
+
+/// Ingest `pack` with `variant` and print the peak RSS of this process in KiB.
+fn child(variant: &str, pack: &std::path::Path) {
+    let variant = Variant::from_id(variant).unwrap_or_else(|| panic!("unknown variant {variant}"));
+    let dir = gix_testtools::tempfile::tempdir().expect("temp dir can be created");
+    variant.ingest(pack, &synthetic::empty_objects_dir(dir.path()));
+    println!("{}", peak_rss_kib().expect("checked by the parent"));
+}
+
+/// The high-water mark of the resident set size of this process.
+fn peak_rss_kib() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix("VmHWM:"))
+        .and_then(|value| value.trim().trim_end_matches("kB").trim().parse().ok())
+}
@@ -0,0 +1,34 @@
+//! Push throughput of pack ingestion for synthetic packs, on both ingestion paths and with or without fsck.
+//!
+//! Run with `cargo bench -p gix-receive-pack --features progress,fsck --bench push_throughput`.
+//! Without the `fsck` feature, only the variants without fsck are measured.
+
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+
+mod synthetic;
+use synthetic::{Scenario, SyntheticPack, Variant};
+
+fn ingest_synthetic_packs(c: &mut Criterion) {
+    for scenario in Scenario::ALL {
+        let pack = SyntheticPack::generate(scenario);
+        let mut group = c.benchmark_group(format!("ingest/{}", scenario.name()));
+        group.throughput(Throughput::Bytes(pack.size));
+        group.sample_size(10);
+        for variant in Variant::all() {
+            group.bench_with_input(BenchmarkId::from_parameter(variant.id()), &pack, |b, pack| {
+                b.iter_batched(
+                    || gix_testtools::tempfile::tempdir().expect("temp dir can be created"),
+                    |dir| {
+                        variant.ingest(&pack.path, &synthetic::empty_objects_dir(dir.path()));
+                        dir
+                    },
+                    BatchSize::PerIteration,
+                );
+            });
+        }
+        group.finish();
+    }
+}
+
+criterion_group!(benches, ingest_synthetic_packs);
+criterion_main!(benches);
@@ -0,0 +1,246 @@
+//! Synthetic packs for push benchmarks, generated with `git fast-import` and `git repack`.
+#![allow(dead_code)] // Each benchmark uses a different subset.
+
+use gix_features::progress::Discard;
+use gix_receive_pack::{pack::PackIngestPath, ReceivePackBuilder};
+use std::{
+    hint::black_box,
+    io::Write,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+/// The shape of a generated pack.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scenario {
+    /// Many small, distinct blobs in a single flat tree.
+    ManySmallObjects,
+    /// A few large, incompressible blobs.
+    FewHugeBlobs,
+    /// One file changed by many commits, repacked into long delta chains.
+    DeepDeltaChains,
+}
+
+impl Scenario {
+    /// All scenarios, in benchmark order.
+    pub const ALL: [Scenario; 3] = [
+        Scenario::ManySmallObjects,
+        Scenario::FewHugeBlobs,
+        Scenario::DeepDeltaChains,
+    ];
+
+    /// A short name for benchmark ids and reports.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Scenario::ManySmallObjects => "many-small-objects",
+            Scenario::FewHugeBlobs => "few-huge-blobs",
+            Scenario::DeepDeltaChains => "deep-delta-chains",
+        }
+    }
+
+    /// Write the `git fast-import` stream for this scenario.
+    fn fast_import_stream(&self, out: &mut impl Write) -> std::io::Result<()> {
+        let mut rng = XorShift(0x9e37_79b9_7f4a_7c15);
+        match self {
+            Scenario::ManySmallObjects => {
+                const BLOBS: usize = 20_000;
+                for mark in 1..=BLOBS {
+                    blob(out, mark, format!("small object {mark} {}\n", rng.next()).as_bytes())?;
+                }
+                commit(out, BLOBS + 1, None, (1..=BLOBS).map(|mark| (format!("f/{mark}"), mark)))?;
+            }
+            Scenario::FewHugeBlobs => {
+                const BLOBS: usize = 4;
+                const SIZE: usize = 32 * 1024 * 1024;
+                for mark in 1..=BLOBS {
+                    let data: Vec<u8> = (0..SIZE / 8).flat_map(|_| rng.next().to_le_bytes()).collect();
+                    blob(out, mark, &data)?;
+                }
+                commit(out, BLOBS + 1, None, (1..=BLOBS).map(|mark| (format!("huge-{mark}.bin"), mark)))?;
+            }
+            Scenario::DeepDeltaChains => {
+                const REVISIONS: usize = 1_000;
+                let mut content = String::new();
+                let mut parent = None;
+                for rev in 0..REVISIONS {
+                    content.push_str(&format!("line {rev} {}\n", rng.next()));
+                    let (blob_mark, commit_mark) = (2 * rev + 1, 2 * rev + 2);
+                    blob(out, blob_mark, content.as_bytes())?;
+                    commit(out, commit_mark, parent, [("file.txt".to_string(), blob_mark)])?;
+                    parent = Some(commit_mark);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Extra `git repack` arguments for this scenario.
+    fn repack_args(&self) -> &'static [&'static str] {
+        match self {
+            Scenario::DeepDeltaChains => &["-f", "--depth=4095", "--window=250"],
+            Scenario::ManySmallObjects | Scenario::FewHugeBlobs => &[],
+        }
+    }
+}
+
+/// The ingestion path to force, and whether to verify objects with fsck.
+#[derive(Debug, Clone, Copy)]
+pub struct Variant {
+    /// The ingestion path, forced through the unpack limit.
+    pub path: PackIngestPath,
+    /// Whether objects are verified with fsck.
+    pub fsck: bool,
+}
+
+impl Variant {
+    /// All variants supported by the enabled features.
+    pub fn all() -> impl Iterator<Item = Variant> {
+        let fsck = if cfg!(feature = "fsck") { &[false, true][..] } else { &[false][..] };
+        [PackIngestPath::IndexPack, PackIngestPath::UnpackObjects]
+            .into_iter()
+            .flat_map(move |path| fsck.iter().map(move |&fsck| Variant { path, fsck }))
+    }
+
+    /// A short name for benchmark ids and reports.
+    pub fn id(&self) -> String {
+        let path = match self.path {
+            PackIngestPath::IndexPack => "index-pack",
+            PackIngestPath::UnpackObjects => "unpack-objects",
+        };
+        if self.fsck {
+            format!("{path}+fsck")
+        } else {
+            path.to_string()
+        }
+    }
+
+    /// Parse a variant from its [id](Self::id()).
+    pub fn from_id(id: &str) -> Option<Self> {
+        Self::all().find(|variant| variant.id() == id)
+    }
+
+    /// Ingest `pack` into `objects_dir`, panicking if it fails or takes another path than requested.
+    pub fn ingest(&self, pack: &Path, objects_dir: &Path) {
+        let builder = ReceivePackBuilder::new()
+            .blocking()
+            .with_objects_dir(objects_dir)
+            .with_unpack_limit(match self.path {
+                PackIngestPath::IndexPack => None,
+                PackIngestPath::UnpackObjects => Some(u64::MAX),
+            });
+        #[cfg(feature = "fsck")]
+        let builder = builder.with_fsck_objects(self.fsck);
+        let outcome = builder
+            .build()
+            .ingest_pack_file(pack, &mut Discard)
+            .expect("synthetic packs are valid");
+        assert_eq!(outcome.ingest_path, self.path, "the requested path was taken");
+        black_box(outcome);
+    }
+}
+
+/// A generated pack along with the directory keeping it alive.
+pub struct SyntheticPack {
+    _dir: gix_testtools::tempfile::TempDir,
+    /// The path to the `.pack` file.
+    pub path: PathBuf,
+    /// The size of the pack in bytes.
+    pub size: u64,
+}
+
+impl SyntheticPack {
+    /// Generate the pack for `scenario` in a new temporary repository.
+    pub fn generate(scenario: Scenario) -> SyntheticPack {
+        let dir = gix_testtools::tempfile::tempdir().expect("temp dir can be created");
+        let repo = dir.path().join("source.git");
+        git(dir.path(), &["init", "--bare", "--quiet", repo.to_str().expect("UTF-8 temp path")]);
+
+        let mut fast_import = Command::new("git")
+            .args(["fast-import", "--quiet"])
+            .current_dir(&repo)
+            .stdin(Stdio::piped())
+            .spawn()
+            .expect("git is installed");
+        {
+            let mut stdin = std::io::BufWriter::new(fast_import.stdin.take().expect("piped"));
+            scenario
+                .fast_import_stream(&mut stdin)
+                .expect("fast-import reads its input");
+            stdin.flush().expect("fast-import reads its input");
+        }
+        assert!(fast_import.wait().expect("fast-import runs").success());
+
+        let mut repack = vec!["repack", "-a", "-d", "--quiet"];
+        repack.extend_from_slice(scenario.repack_args());
+        git(&repo, &repack);
+
+        let path = single_pack(&repo.join("objects").join("pack"));
+        let size = std::fs::metadata(&path).expect("pack exists").len();
+        SyntheticPack { _dir: dir, path, size }
+    }
+}
+
+/// Create an empty objects directory for ingestion below `root`.
+pub fn empty_objects_dir(root: &Path) -> PathBuf {
+    let objects = root.join("objects");
+    for dir in ["pack", "info"] {
+        std::fs::create_dir_all(objects.join(dir)).expect("objects directory can be created");
+    }
+    objects
+}
+
+fn single_pack(pack_dir: &Path) -> PathBuf {
+    let mut packs: Vec<_> = std::fs::read_dir(pack_dir)
+        .expect("pack directory exists")
+        .map(|entry| entry.expect("readable entry").path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "pack"))
+        .collect();
+    assert_eq!(packs.len(), 1, "repack -a produces a single pack");
+    packs.pop().expect("one pack")
+}
+
+fn git(cwd: &Path, args: &[&str]) {
+    let status = Command::new("git")
+        .args(args)
+        .current_dir(cwd)
+        .status()
+        .expect("git is installed");
+    assert!(status.success(), "git {args:?} failed");
+}
+
+fn blob(out: &mut impl Write, mark: usize, data: &[u8]) -> std::io::Result<()> {
+    writeln!(out, "blob\nmark :{mark}\ndata {}", data.len())?;
+    out.write_all(data)?;
+    writeln!(out)
+}
+
+fn commit(
+    out: &mut impl Write,
+    mark: usize,
+    parent: Option<usize>,
+    files: impl IntoIterator<Item = (String, usize)>,
+) -> std::io::Result<()> {
+    writeln!(
+        out,
+        "commit refs/heads/main\nmark :{mark}\ncommitter Bench <bench@example.com> {mark} +0000\ndata 0"
+    )?;
+    if let Some(parent) = parent {
+        writeln!(out, "from :{parent}")?;
+    }
+    for (path, blob) in files {
+        writeln!(out, "M 100644 :{blob} {path}")?;
+    }
+    writeln!(out)
+}
+
+/// A tiny deterministic generator, so packs are reproducible across runs.
+struct XorShift(u64);
+
+impl XorShift {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+}
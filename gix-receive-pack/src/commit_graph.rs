@@ -0,0 +1,300 @@
+//! Post-receive commit-graph maintenance.
+//!
+//! After the quarantine was migrated successfully, the newly received commits can be appended to the
+//! repository's split commit-graph chain so subsequent fetch negotiations stay fast. This is the
+//! receive-side analog of `fetch.writeCommitGraph` and is controlled by `receive.writeCommitGraph`.
+//!
+//! gitoxide can read commit-graphs but not yet write them, so writing is delegated to
+//! `git commit-graph write --split --stdin-commits` when the "commit-graph" feature is enabled.
+//! Without it the update is reported as unavailable; the push itself is never affected.
+//!
+//! Annotated tags among the new tips are peeled to the commits they point to, and tips that aren't
+//! commits after peeling are left out, as `--stdin-commits` only accepts commits.
+
+use crate::protocol::CommandUpdate;
+use crate::Error;
+use gix_hash::ObjectId;
+use std::path::{Path, PathBuf};
+
+/// Configuration for the post-receive commit-graph update.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommitGraphConfig {
+    /// Whether to update the commit-graph after a successful receive (`receive.writeCommitGraph`).
+    pub enabled: bool,
+    /// The `git` program used to write the commit-graph.
+    pub git_program: PathBuf,
+}
+
+impl Default for CommitGraphConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            git_program: PathBuf::from("git"),
+        }
+    }
+}
+
+impl CommitGraphConfig {
+    /// Load the configuration from `receive.writeCommitGraph`, defaulting to disabled.
+    pub fn from_config(config: &gix_config::File<'static>) -> Result<Self, Error> {
+        let mut out = Self::default();
+        if let Some(enabled) = crate::config::keys::receive::WRITE_COMMIT_GRAPH.get(config)? {
+            out.enabled = enabled;
+        }
+        Ok(out)
+    }
+
+    /// Enable or disable the commit-graph update.
+    pub fn with_enabled(mut self, enabled: bool) -> Self {
+        self.enabled = enabled;
+        self
+    }
+}
+
+/// The result of a post-receive commit-graph update.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CommitGraphUpdate {
+    /// `receive.writeCommitGraph` is not enabled.
+    Disabled,
+    /// No command introduced new tips, so there was nothing to add.
+    NothingToDo,
+    /// A new layer was added to the split commit-graph chain for commits reachable from `tips`.
+    Written {
+        /// The number of new tips handed to the writer.
+        tips: usize,
+    },
+    /// No commit-graph writer is available in this build.
+    Unavailable {
+        /// Why no writer could be used.
+        reason: String,
+    },
+    /// The writer ran but failed; the received objects and ref updates are unaffected.
+    Failed {
+        /// The writer's exit code, if it exited normally.
+        exit_code: Option<i32>,
+        /// A sample of the writer's stderr.
+        stderr: String,
+    },
+}
+
+/// Return the new tips of all creations and updates in `updates`, deduplicated and in order.
+pub fn new_tips(updates: &[CommandUpdate]) -> Vec<ObjectId> {
+    let mut tips = Vec::new();
+    for update in updates {
+        let new = match update {
+            CommandUpdate::Create { new, .. } | CommandUpdate::Update { new, .. } => *new,
+            CommandUpdate::Delete { .. } => continue,
+        };
+        if !tips.contains(&new) {
+            tips.push(new);
+        }
+    }
+    tips
+}
+
+/// Incrementally update the commit-graph of the repository owning `objects_dir` with the commits
+/// reachable from the new tips of `updates`.
+///
+/// This must only be called after the quarantine was migrated, as the writer needs to see the new objects.
+pub fn update_after_receive(
+    config: &CommitGraphConfig,
+    objects_dir: &Path,
+    updates: &[CommandUpdate],
+) -> Result<CommitGraphUpdate, Error> {
+    if !config.enabled {
+        return Ok(CommitGraphUpdate::Disabled);
+    }
+    let tips = new_tips(updates);
+    if tips.is_empty() {
+        return Ok(CommitGraphUpdate::NothingToDo);
+    }
+    let tips = peeled_commits(&gix_odb::at(objects_dir)?, &tips)?;
+    if tips.is_empty() {
+        return Ok(CommitGraphUpdate::NothingToDo);
+    }
+    write_split_layer(config, objects_dir, &tips)
+}
+
+/// Peel the annotated tags among `tips` to the objects they point to with `odb`, and return the commits among them,
+/// deduplicated and in order.
+pub fn peeled_commits(odb: &impl gix_object::Find, tips: &[ObjectId]) -> Result<Vec<ObjectId>, Error> {
+    let mut buf = Vec::new();
+    let mut commits = Vec::new();
+    for tip in tips {
+        let mut id = *tip;
+        loop {
+            let object = odb
+                .try_find(&id, &mut buf)
+                .map_err(|e| Error::Validation(format!("failed to look up {id}: {e}")))?
+                .ok_or_else(|| Error::Validation(format!("new tip {id} is not in the object database")))?;
+            match object.kind {
+                gix_object::Kind::Tag => {
+                    id = gix_object::TagRefIter::from_bytes(object.data)
+                        .target_id()
+                        .map_err(|e| Error::Validation(format!("failed to decode tag {id}: {e}")))?;
+                }
+                gix_object::Kind::Commit => {
+                    if !commits.contains(&id) {
+                        commits.push(id);
+                    }
+                    break;
+                }
+                gix_object::Kind::Tree | gix_object::Kind::Blob => break,
+            }
+        }
+    }
+    Ok(commits)
+}
+
+#[cfg(feature = "commit-graph")]
+fn write_split_layer(
+    config: &CommitGraphConfig,
+    objects_dir: &Path,
+    tips: &[ObjectId],
+) -> Result<CommitGraphUpdate, Error> {
+    use std::io::Write;
+    use std::process::Stdio;
+
+    let git_dir = objects_dir
+        .parent()
+        .ok_or_else(|| Error::Validation(format!("objects directory has no parent: {}", objects_dir.display())))?;
+    let mut child = gix_command::prepare(&config.git_program)
+        .args(["commit-graph", "write", "--split", "--stdin-commits", "--object-dir"])
+        .arg(objects_dir)
+        .env("GIT_DIR", git_dir)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    if let Some(mut stdin) = child.stdin.take() {
+        for tip in tips {
+            writeln!(stdin, "{}", tip)?;
+        }
+    }
+
+    let output = child.wait_with_output()?;
+    if output.status.success() {
+        Ok(CommitGraphUpdate::Written { tips: tips.len() })
+    } else {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        Ok(CommitGraphUpdate::Failed {
+            exit_code: output.status.code(),
+            stderr: stderr.chars().take(200).collect(),
+        })
+    }
+}
+
+#[cfg(not(feature = "commit-graph"))]
+fn write_split_layer(
+    _config: &CommitGraphConfig,
+    _objects_dir: &Path,
+    _tips: &[ObjectId],
+) -> Result<CommitGraphUpdate, Error> {
+    Ok(CommitGraphUpdate::Unavailable {
+        reason: "commit-graph writing requires the commit-graph feature".into(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn oid(hex: &str) -> ObjectId {
+        ObjectId::from_hex(hex.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn new_tips_skip_deletions_and_duplicates() {
+        let a = oid("1111111111111111111111111111111111111111");
+        let b = oid("2222222222222222222222222222222222222222");
+        let updates = vec![
+            CommandUpdate::Create { new: a, name: "refs/heads/a".into() },
+            CommandUpdate::Delete { old: b, name: "refs/heads/gone".into() },
+            CommandUpdate::Update { old: b, new: a, name: "refs/heads/b".into() },
+        ];
+        assert_eq!(new_tips(&updates), vec![a]);
+    }
+
+    #[test]
+    fn disabled_and_empty_updates_do_nothing() {
+        let updates = vec![CommandUpdate::Delete {
+            old: oid("1111111111111111111111111111111111111111"),
+            name: "refs/heads/main".into(),
+        }];
+        let objects_dir = Path::new("/nonexistent/.git/objects");
+
+        let disabled = CommitGraphConfig::default();
+        assert_eq!(
+            update_after_receive(&disabled, objects_dir, &updates).unwrap(),
+            CommitGraphUpdate::Disabled
+        );
+
+        let enabled = CommitGraphConfig::default().with_enabled(true);
+        assert_eq!(
+            update_after_receive(&enabled, objects_dir, &updates).unwrap(),
+            CommitGraphUpdate::NothingToDo
+        );
+    }
+
+    #[test]
+    fn annotated_tags_are_peeled_and_non_commits_left_out() {
+        let tmp = gix_testtools::tempfile::tempdir().unwrap();
+        let git = |args: &[&str]| {
+            let output = std::process::Command::new("git")
+                .args(args)
+                .current_dir(tmp.path())
+                .env("GIT_AUTHOR_NAME", "author")
+                .env("GIT_AUTHOR_EMAIL", "author@example.com")
+                .env("GIT_COMMITTER_NAME", "committer")
+                .env("GIT_COMMITTER_EMAIL", "committer@example.com")
+                .output()
+                .expect("git is installed");
+            assert!(output.status.success(), "git {args:?} failed");
+            String::from_utf8(output.stdout).unwrap().trim().to_owned()
+        };
+        let rev_parse = |rev: &str| oid(&git(&["rev-parse", rev]));
+        git(&["init", "--quiet"]);
+        git(&["commit", "--quiet", "--allow-empty", "-m", "initial"]);
+        git(&["tag", "-m", "commit", "annotated"]);
+        git(&["tag", "-m", "tag of tag", "nested", "annotated"]);
+        git(&["tag", "-m", "tree", "tree", "HEAD^{tree}"]);
+        let commit = rev_parse("HEAD");
+        let tips = [rev_parse("nested"), rev_parse("tree"), rev_parse("annotated"), commit];
+
+        let objects_dir = tmp.path().join(".git/objects");
+        let odb = gix_odb::at(&objects_dir).unwrap();
+        assert_eq!(peeled_commits(&odb, &tips).unwrap(), vec![commit]);
+        assert!(peeled_commits(&odb, &[oid("1111111111111111111111111111111111111111")]).is_err());
+
+        let updates: Vec<_> = tips
+            .iter()
+            .map(|new| CommandUpdate::Create {
+                new: *new,
+                name: "refs/tags/new".into(),
+            })
+            .collect();
+        let enabled = CommitGraphConfig::default().with_enabled(true);
+        let update = update_after_receive(&enabled, &objects_dir, &updates[1..2]);
+        assert_eq!(
+            update.unwrap(),
+            CommitGraphUpdate::NothingToDo,
+            "the tree isn't a commit"
+        );
+        #[cfg(feature = "commit-graph")]
+        {
+            let update = update_after_receive(&enabled, &objects_dir, &updates);
+            assert_eq!(update.unwrap(), CommitGraphUpdate::Written { tips: 1 });
+            assert!(objects_dir.join("info/commit-graphs/commit-graph-chain").is_file());
+        }
+    }
+
+    #[test]
+    fn config_parsing() {
+        let config = gix_config::File::try_from("[receive]\n\twriteCommitGraph = true\n").unwrap();
+        assert!(CommitGraphConfig::from_config(&config).unwrap().enabled);
+
+        let config = gix_config::File::try_from("[receive]\n\twriteCommitGraph = maybe\n").unwrap();
+        assert!(CommitGraphConfig::from_config(&config).is_err());
+    }
+}
@@ -1,5 +1,6 @@
 //! Hook configuration parsing from Git config.
 
+use crate::config::keys;
 use crate::Error;
 use gix_config::File;
 
@@ -22,6 +23,8 @@ pub struct HookConfig {
     pub max_output_size: usize,
     /// Whether to enable sideband relay for hook output
     pub sideband_relay: bool,
+    /// Maximum bytes of hook output relayed per second, or `None` for no limit
+    pub relay_bytes_per_second: Option<u64>,
     /// Reserved for future use - environment variables are handled automatically
     pub environment: HashMap<String, String>,
 }
@@ -33,6 +36,7 @@ impl HookConfig {
             timeout_ms: 30_000,           // 30 seconds default
             max_output_size: 1024 * 1024, // 1MB default
             sideband_relay: true,
+            relay_bytes_per_second: None,
             environment: HashMap::new(),
         }
     }
@@ -43,6 +47,7 @@ impl HookConfig {
     /// - `hooks.timeout`: Timeout in milliseconds (default: 30000)
     /// - `hooks.maxOutputSize`: Maximum output size in bytes (default: 1048576)
     /// - `hooks.sidebandRelay`: Enable sideband relay (default: true)
+    /// - `hooks.maxRelayBytesPerSecond`: Limit the rate of relayed output, 0 for no limit (default: 0)
     ///
     /// Note: Environment variables are automatically set by Git's receive-pack
     /// process and don't need configuration (GIT_DIR, GIT_WORK_TREE, etc.).
@@ -55,43 +60,21 @@ impl HookConfig {
     pub fn from_config(config: &File<'static>) -> Result<Self, Error> {
         let mut hook_config = Self::new();
 
-        // Parse hooks.timeout
-        if let Some(result) = config.integer("hooks.timeout") {
-            match result {
-                Ok(value) => hook_config.timeout_ms = parse_timeout_from_integer(value, "hooks.timeout")?,
-                Err(e) => {
-                    return Err(Error::Validation(format!(
-                        "invalid integer value for 'hooks.timeout': {}",
-                        e
-                    )))
-                }
-            }
+        if let Some(value) = keys::hooks::TIMEOUT.get(config)? {
+            hook_config.timeout_ms = parse_timeout_from_integer(value, keys::hooks::TIMEOUT.name())?;
         }
 
-        // Parse hooks.maxOutputSize
-        if let Some(result) = config.integer("hooks.maxOutputSize") {
-            match result {
-                Ok(value) => hook_config.max_output_size = parse_size_from_integer(value, "hooks.maxOutputSize")?,
-                Err(e) => {
-                    return Err(Error::Validation(format!(
-                        "invalid integer value for 'hooks.maxOutputSize': {}",
-                        e
-                    )))
-                }
-            }
+        if let Some(value) = keys::hooks::MAX_OUTPUT_SIZE.get(config)? {
+            hook_config.max_output_size = parse_size_from_integer(value, keys::hooks::MAX_OUTPUT_SIZE.name())?;
         }
 
-        // Parse hooks.sidebandRelay
-        if let Some(result) = config.boolean("hooks.sidebandRelay") {
-            match result {
-                Ok(value) => hook_config.sideband_relay = value,
-                Err(e) => {
-                    return Err(Error::Validation(format!(
-                        "invalid boolean value for 'hooks.sidebandRelay': {}",
-                        e
-                    )))
-                }
-            }
+        if let Some(value) = keys::hooks::SIDEBAND_RELAY.get(config)? {
+            hook_config.sideband_relay = value;
+        }
+
+        if let Some(value) = keys::hooks::MAX_RELAY_BYTES_PER_SECOND.get(config)? {
+            let rate = parse_size_from_integer(value, keys::hooks::MAX_RELAY_BYTES_PER_SECOND.name())?;
+            hook_config.relay_bytes_per_second = (rate > 0).then_some(rate as u64);
         }
 
         Ok(hook_config)
@@ -310,6 +293,20 @@ mod tests {
         assert_eq!(hook_config.timeout(), Duration::from_millis(0));
     }
 
+    #[test]
+    fn test_relay_rate_limit() {
+        let config = create_config_with_values(&[("hooks.maxRelayBytesPerSecond", "4096")]);
+        let hook_config = HookConfig::from_config(&config).unwrap();
+        assert_eq!(hook_config.relay_bytes_per_second, Some(4096));
+
+        let config = create_config_with_values(&[("hooks.maxRelayBytesPerSecond", "0")]);
+        let hook_config = HookConfig::from_config(&config).unwrap();
+        assert_eq!(hook_config.relay_bytes_per_second, None, "0 means no limit");
+
+        let config = create_config_with_values(&[("hooks.maxRelayBytesPerSecond", "-1")]);
+        assert!(HookConfig::from_config(&config).is_err());
+    }
+
     #[test]
     fn test_zero_max_output_size() {
         let config = create_config_with_values(&[("hooks.maxOutputSize", "0")]);
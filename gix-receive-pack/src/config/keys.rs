@@ -0,0 +1,320 @@
+//! Typed git configuration keys honored by receive-pack.
+//!
+//! Every key read by the configuration loaders of this crate is listed here with its type and
+//! default, so it's discoverable which knobs are honored. Reading a key through its [`Key`] rejects
+//! values of the wrong type with an [`Error::Validation`] instead of silently ignoring them.
+
+use crate::Error;
+use gix_config::File;
+use gix_object::bstr::BString;
+use std::marker::PhantomData;
+
+/// A type a configuration value can be read as.
+pub trait Value: Sized {
+    /// The name of the type as used in error messages, like `boolean`.
+    const KIND: &'static str;
+
+    /// Read the value of `name` from `config`, or `None` if it isn't set.
+    fn read(config: &File<'static>, name: &str) -> Option<Result<Self, String>>;
+}
+
+impl Value for bool {
+    const KIND: &'static str = "boolean";
+
+    fn read(config: &File<'static>, name: &str) -> Option<Result<Self, String>> {
+        config.boolean(name).map(|value| value.map_err(|e| e.to_string()))
+    }
+}
+
+impl Value for i64 {
+    const KIND: &'static str = "integer";
+
+    fn read(config: &File<'static>, name: &str) -> Option<Result<Self, String>> {
+        config.integer(name).map(|value| value.map_err(|e| e.to_string()))
+    }
+}
+
+impl Value for BString {
+    const KIND: &'static str = "string";
+
+    fn read(config: &File<'static>, name: &str) -> Option<Result<Self, String>> {
+        config.string(name).map(|value| Ok(value.into_owned()))
+    }
+}
+
+/// A configuration key with a value of type `T`.
+#[derive(Debug, Clone, Copy)]
+pub struct Key<T> {
+    name: &'static str,
+    default: &'static str,
+    _value: PhantomData<fn() -> T>,
+}
+
+impl<T> Key<T> {
+    /// Create a key called `name`, whose value is `default` when unset.
+    pub const fn new(name: &'static str, default: &'static str) -> Self {
+        Key {
+            name,
+            default,
+            _value: PhantomData,
+        }
+    }
+
+    /// The full name of the key, like `receive.denyDeletes`.
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// A description of the value used when the key is unset.
+    pub fn default_value(&self) -> &'static str {
+        self.default
+    }
+}
+
+impl<T: Value> Key<T> {
+    /// Read the value of this key from `config`, or `None` if it isn't set.
+    pub fn get(&self, config: &File<'static>) -> Result<Option<T>, Error> {
+        T::read(config, self.name)
+            .transpose()
+            .map_err(|e| Error::Validation(format!("invalid {} value for '{}': {}", T::KIND, self.name, e)))
+    }
+}
+
+impl Key<BString> {
+    /// Read all values of this multi-valued key from `config`, in order.
+    pub fn get_all(&self, config: &File<'static>) -> Vec<BString> {
+        config
+            .strings(self.name)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|value| value.into_owned())
+            .collect()
+    }
+}
+
+/// Keys in the `receive` section.
+pub mod receive {
+    use super::Key;
+    use gix_object::bstr::BString;
+
+    /// Forbid deletion of references.
+    pub const DENY_DELETES: Key<bool> = Key::new("receive.denyDeletes", "false");
+    /// Forbid non-fast-forward updates.
+    pub const DENY_NON_FAST_FORWARDS: Key<bool> = Key::new("receive.denyNonFastForwards", "false");
+    /// Accept non-fast-forward updates despite `receive.denyNonFastForwards`, for mirrors receiving forced pushes.
+    pub const MIRROR: Key<bool> = Key::new("receive.mirror", "false");
+    /// Policy for updates to the checked-out branch: refuse, warn, ignore or updateInstead.
+    pub const DENY_CURRENT_BRANCH: Key<BString> = Key::new("receive.denyCurrentBranch", "refuse");
+    /// Policy for deletion of the checked-out branch: refuse, warn or ignore.
+    pub const DENY_DELETE_CURRENT: Key<BString> = Key::new("receive.denyDeleteCurrent", "refuse");
+    /// Update the worktree when its checked-out branch is pushed to.
+    pub const UPDATE_INSTEAD: Key<bool> = Key::new("receive.updateInstead", "false");
+    /// Object count below which packs are unpacked into loose objects, overrides `transfer.unpackLimit`.
+    pub const UNPACK_LIMIT: Key<i64> = Key::new("receive.unpackLimit", "transfer.unpackLimit");
+    /// Verify received objects with fsck, overrides `transfer.fsckObjects`.
+    pub const FSCK_OBJECTS: Key<bool> = Key::new("receive.fsckObjects", "transfer.fsckObjects");
+    /// Bytes loose objects of a pack within the unpack limit may take before it's indexed instead, `0` means unlimited.
+    pub const MAX_LOOSE_OBJECTS_SIZE: Key<i64> = Key::new("receive.maxLooseObjectsSize", "0");
+    /// Maximum size of an incoming pack in bytes, `0` means unlimited.
+    pub const MAX_INPUT_SIZE: Key<i64> = Key::new("receive.maxInputSize", "0");
+    /// Bytes per second below which a client sending pack data is considered stalled, `0` disables the check.
+    pub const LOW_SPEED_LIMIT: Key<i64> = Key::new("receive.lowSpeedLimit", "0");
+    /// Seconds the client may stay below `receive.lowSpeedLimit` before the push is aborted.
+    pub const LOW_SPEED_TIME: Key<i64> = Key::new("receive.lowSpeedTime", "0");
+    /// Bytes of pack data per second above which a push is aborted as abusive, `0` means unlimited.
+    pub const MAX_BYTES_PER_SECOND: Key<i64> = Key::new("receive.maxBytesPerSecond", "0");
+    /// Objects per second above which a push is aborted as abusive, `0` means unlimited.
+    pub const MAX_OBJECTS_PER_SECOND: Key<i64> = Key::new("receive.maxObjectsPerSecond", "0");
+    /// Length in seconds of the windows the abuse thresholds are measured over.
+    pub const RATE_WINDOW: Key<i64> = Key::new("receive.rateWindow", "1");
+    /// Bytes of head-info accepted per push, `0` means unlimited.
+    pub const MAX_HEAD_INFO_SIZE: Key<i64> = Key::new("receive.maxHeadInfoSize", "0");
+    /// Bytes accepted per head-info line, `0` means unlimited.
+    pub const MAX_HEAD_INFO_LINE_LENGTH: Key<i64> = Key::new("receive.maxHeadInfoLineLength", "0");
+    /// Update commands accepted per push, `0` means unlimited.
+    pub const MAX_COMMANDS: Key<i64> = Key::new("receive.maxCommands", "0");
+    /// Push options accepted per push, `0` means unlimited.
+    pub const MAX_PUSH_OPTIONS: Key<i64> = Key::new("receive.maxPushOptions", "0");
+    /// Bytes accepted per push option, `0` means unlimited.
+    pub const MAX_PUSH_OPTION_LENGTH: Key<i64> = Key::new("receive.maxPushOptionLength", "0");
+    /// Add received commits to the commit-graph.
+    pub const WRITE_COMMIT_GRAPH: Key<bool> = Key::new("receive.writeCommitGraph", "false");
+    /// Rewrite the multi-pack-index after a pack was added, a boolean or `defer`.
+    pub const UPDATE_MULTI_PACK_INDEX: Key<BString> = Key::new("receive.updateMultiPackIndex", "false");
+    /// Record the ids of the objects each push introduced in `objects/info/push-manifests`.
+    pub const RECORD_PUSH_MANIFESTS: Key<bool> = Key::new("receive.recordPushManifests", "false");
+    /// Advertise at most this many refs, the most recently updated ones, `0` means unlimited.
+    pub const ADVERTISE_MAX_REFS: Key<i64> = Key::new("receive.advertiseMaxRefs", "0");
+    /// Advertise refs below `refs/tags/`.
+    pub const ADVERTISE_TAGS: Key<bool> = Key::new("receive.advertiseTags", "true");
+    /// Only advertise refs with one of these prefixes, multi-valued.
+    pub const ADVERTISE_NAMESPACE: Key<BString> = Key::new("receive.advertiseNamespace", "all refs");
+    /// Advertise at most this many `.have` lines for alternates, `0` means unlimited.
+    pub const ADVERTISE_MAX_HAVES: Key<i64> = Key::new("receive.advertiseMaxHaves", "0");
+    /// Advertise the `push-options` capability, so clients can send push options for hooks.
+    pub const ADVERTISE_PUSH_OPTIONS: Key<bool> = Key::new("receive.advertisePushOptions", "false");
+    /// The secret nonces for signed pushes are derived from, which advertises `push-cert` when set.
+    pub const CERT_NONCE_SEED: Key<BString> = Key::new("receive.certNonceSeed", "unset, signed pushes aren't offered");
+    /// Seconds a nonce issued in an earlier stateless request may be off and still be accepted, `0` accepts none.
+    pub const CERT_NONCE_SLOP: Key<i64> = Key::new("receive.certNonceSlop", "0");
+    /// Policy for pushed tags pointing to missing objects: refuse, warn or ignore.
+    pub const TAG_MISSING_TARGET: Key<BString> = Key::new("receive.tagMissingTarget", "ignore");
+    /// The number of tags a chain of tags pointing to tags may have before `receive.tagTooDeep` applies.
+    pub const TAG_MAX_DEPTH: Key<i64> = Key::new("receive.tagMaxDepth", "1");
+    /// Policy for pushed tag chains longer than `receive.tagMaxDepth`: refuse, warn or ignore.
+    pub const TAG_TOO_DEEP: Key<BString> = Key::new("receive.tagTooDeep", "ignore");
+    /// Policy for pushed tags of objects that are neither received nor pushed to a ref: refuse, warn or ignore.
+    pub const TAG_OUTSIDE_PUSH: Key<BString> = Key::new("receive.tagOutsidePush", "ignore");
+    /// Store pushed refs under another name, as `[<principal> ]<source>:<target>`, multi-valued.
+    pub const REWRITE_REF: Key<BString> = Key::new("receive.rewriteRef", "none");
+    /// Hide refs matching this pattern from pushing clients and refuse updating them, multi-valued.
+    pub const HIDE_REFS: Key<BString> = Key::new("receive.hideRefs", "none");
+}
+
+/// Keys in the `transfer` section.
+pub mod transfer {
+    use super::Key;
+    use gix_object::bstr::BString;
+
+    /// Object count below which packs are unpacked into loose objects.
+    pub const UNPACK_LIMIT: Key<i64> = Key::new("transfer.unpackLimit", "unset, packs are always indexed");
+    /// Verify received objects with fsck.
+    pub const FSCK_OBJECTS: Key<bool> = Key::new("transfer.fsckObjects", "false");
+    /// Hide refs matching this pattern from fetching and pushing clients, multi-valued.
+    pub const HIDE_REFS: Key<BString> = Key::new("transfer.hideRefs", "none");
+}
+
+/// Keys in the `extensions` section.
+pub mod extensions {
+    use super::Key;
+
+    /// Never delete objects, maintenance that would is refused.
+    pub const PRECIOUS_OBJECTS: Key<bool> = Key::new("extensions.preciousObjects", "false");
+}
+
+/// Keys in the `core` section.
+pub mod core {
+    use super::Key;
+    use gix_object::bstr::BString;
+
+    /// The repository has no worktree, so the current branch can't be updated instead.
+    pub const BARE: Key<bool> = Key::new("core.bare", "false");
+    /// The worktree updated instead of the current branch, relative to the git directory.
+    pub const WORKTREE: Key<BString> = Key::new("core.worktree", "the parent of the git directory");
+    /// The zlib level for objects, from `-1` for the zlib default to 9.
+    pub const COMPRESSION: Key<i64> = Key::new("core.compression", "1 for loose objects");
+    /// The zlib level for loose objects, overrides `core.compression`.
+    pub const LOOSE_COMPRESSION: Key<i64> = Key::new("core.looseCompression", "core.compression");
+}
+
+/// Keys in the `hooks` section.
+pub mod hooks {
+    use super::Key;
+
+    /// Timeout in milliseconds for hook execution.
+    pub const TIMEOUT: Key<i64> = Key::new("hooks.timeout", "30000");
+    /// Maximum hook output in bytes.
+    pub const MAX_OUTPUT_SIZE: Key<i64> = Key::new("hooks.maxOutputSize", "1048576");
+    /// Relay hook output to the client over sideband.
+    pub const SIDEBAND_RELAY: Key<bool> = Key::new("hooks.sidebandRelay", "true");
+    /// Maximum bytes of hook output relayed per second, 0 for no limit.
+    pub const MAX_RELAY_BYTES_PER_SECOND: Key<i64> = Key::new("hooks.maxRelayBytesPerSecond", "0");
+}
+
+/// Keys in the `procReceive` section.
+pub mod proc_receive {
+    use super::Key;
+    use gix_object::bstr::BString;
+
+    /// Enable the proc-receive protocol.
+    pub const ENABLED: Key<bool> = Key::new("procReceive.enabled", "false");
+    /// Path to the proc-receive helper.
+    pub const HELPER_PATH: Key<BString> = Key::new("procReceive.helperPath", "unset");
+    /// Protocol version.
+    pub const VERSION: Key<i64> = Key::new("procReceive.version", "1");
+    /// Timeout in milliseconds for helper operations.
+    pub const TIMEOUT: Key<i64> = Key::new("procReceive.timeout", "30000");
+}
+
+/// The names of all keys honored by receive-pack.
+pub const ALL: &[&str] = &[
+    receive::DENY_DELETES.name,
+    receive::DENY_NON_FAST_FORWARDS.name,
+    receive::MIRROR.name,
+    receive::DENY_CURRENT_BRANCH.name,
+    receive::DENY_DELETE_CURRENT.name,
+    receive::UPDATE_INSTEAD.name,
+    receive::UNPACK_LIMIT.name,
+    receive::FSCK_OBJECTS.name,
+    receive::MAX_LOOSE_OBJECTS_SIZE.name,
+    receive::MAX_INPUT_SIZE.name,
+    receive::LOW_SPEED_LIMIT.name,
+    receive::LOW_SPEED_TIME.name,
+    receive::MAX_BYTES_PER_SECOND.name,
+    receive::MAX_OBJECTS_PER_SECOND.name,
+    receive::RATE_WINDOW.name,
+    receive::MAX_HEAD_INFO_SIZE.name,
+    receive::MAX_HEAD_INFO_LINE_LENGTH.name,
+    receive::MAX_COMMANDS.name,
+    receive::MAX_PUSH_OPTIONS.name,
+    receive::MAX_PUSH_OPTION_LENGTH.name,
+    receive::WRITE_COMMIT_GRAPH.name,
+    receive::UPDATE_MULTI_PACK_INDEX.name,
+    receive::RECORD_PUSH_MANIFESTS.name,
+    receive::ADVERTISE_MAX_REFS.name,
+    receive::ADVERTISE_TAGS.name,
+    receive::ADVERTISE_NAMESPACE.name,
+    receive::ADVERTISE_MAX_HAVES.name,
+    receive::ADVERTISE_PUSH_OPTIONS.name,
+    receive::CERT_NONCE_SEED.name,
+    receive::CERT_NONCE_SLOP.name,
+    receive::TAG_MISSING_TARGET.name,
+    receive::TAG_MAX_DEPTH.name,
+    receive::TAG_TOO_DEEP.name,
+    receive::TAG_OUTSIDE_PUSH.name,
+    receive::REWRITE_REF.name,
+    receive::HIDE_REFS.name,
+    transfer::UNPACK_LIMIT.name,
+    transfer::FSCK_OBJECTS.name,
+    transfer::HIDE_REFS.name,
+    extensions::PRECIOUS_OBJECTS.name,
+    core::BARE.name,
+    core::WORKTREE.name,
+    core::COMPRESSION.name,
+    core::LOOSE_COMPRESSION.name,
+    hooks::TIMEOUT.name,
+    hooks::MAX_OUTPUT_SIZE.name,
+    hooks::SIDEBAND_RELAY.name,
+    hooks::MAX_RELAY_BYTES_PER_SECOND.name,
+    proc_receive::ENABLED.name,
+    proc_receive::HELPER_PATH.name,
+    proc_receive::VERSION.name,
+    proc_receive::TIMEOUT.name,
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(text: &str) -> File<'static> {
+        text.parse().expect("valid config")
+    }
+
+    #[test]
+    fn names_are_unique() {
+        let mut names = ALL.to_vec();
+        names.sort_unstable();
+        names.dedup();
+        assert_eq!(names.len(), ALL.len());
+    }
+
+    #[test]
+    fn typed_access() {
+        let config = config("[receive]\n\tdenyDeletes = yes\n\tunpackLimit = 1k\n\tfsckObjects = maybe\n");
+        assert_eq!(receive::DENY_DELETES.get(&config).unwrap(), Some(true));
+        assert_eq!(receive::UNPACK_LIMIT.get(&config).unwrap(), Some(1024));
+        assert_eq!(transfer::UNPACK_LIMIT.get(&config).unwrap(), None);
+        let err = receive::FSCK_OBJECTS.get(&config).unwrap_err().to_string();
+        assert!(err.contains("invalid boolean value for 'receive.fsckObjects'"), "{err}");
+    }
+}
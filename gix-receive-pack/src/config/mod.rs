@@ -24,10 +24,13 @@
 //! - `procReceive.helperPath`: Path to proc-receive helper
 //! - `procReceive.version`: Protocol version (default 1)
 //! - `procReceive.timeout`: Timeout for helper operations
+//!
+//! See [`keys`] for the typed list of all honored keys and their defaults.
 
 pub mod policy;
 pub mod hooks;
 pub mod proc_receive;
+pub mod keys;
 
 pub use policy::PolicyConfig;
 pub use hooks::HookConfig;
@@ -1,9 +1,9 @@
 //! Policy configuration parsing from Git config.
 
-use crate::policy::{PolicySet, set::Policy};
+use crate::policy::{PolicySet, TagPolicy, TagRule, set::Policy};
+use crate::config::keys;
 use crate::Error;
-use gix_config::File;
-use gix_config_value::Boolean;
+use gix_config::{Boolean, File};
 use gix_object::bstr::BStr;
 
 /// Configuration loader for receive-pack policies.
@@ -14,6 +14,8 @@ use gix_object::bstr::BStr;
 pub struct PolicyConfig {
     /// Parsed policy set
     policy_set: PolicySet,
+    /// Parsed rules for pushed tags
+    tag_policy: TagPolicy,
 }
 
 impl PolicyConfig {
@@ -21,6 +23,7 @@ impl PolicyConfig {
     pub fn new() -> Self {
         Self {
             policy_set: PolicySet::new(),
+            tag_policy: TagPolicy::new(),
         }
     }
 
@@ -29,9 +32,12 @@ impl PolicyConfig {
     /// This method parses the following configuration keys:
     /// - `receive.denyDeletes`: Boolean, forbid deletion of references
     /// - `receive.denyNonFastForwards`: Boolean, forbid non-fast-forward updates
+    /// - `receive.mirror`: Boolean, accept non-fast-forward updates anyway
     /// - `receive.denyCurrentBranch`: String, policy for current branch updates
     /// - `receive.denyDeleteCurrent`: String, policy for current branch deletion
     /// - `receive.updateInstead`: Boolean, enable worktree updates
+    /// - `receive.tagMissingTarget`, `receive.tagTooDeep`, `receive.tagOutsidePush`: String, policies for pushed tags
+    /// - `receive.tagMaxDepth`: Integer, the longest chain of tags allowed by `receive.tagTooDeep`
     ///
     /// # Arguments
     /// * `config` - Git configuration file to parse
@@ -41,43 +47,60 @@ impl PolicyConfig {
     pub fn from_config(config: &File<'static>) -> Result<Self, Error> {
         let mut policy_set = PolicySet::new();
 
-        // Parse receive.denyDeletes
-        if let Some(result) = config.boolean("receive.denyDeletes") {
-            match result {
-                Ok(value) => policy_set = policy_set.with_deny_deletes(value),
-                Err(e) => return Err(Error::Validation(format!("invalid boolean value for 'receive.denyDeletes': {}", e))),
-            }
+        if let Some(value) = keys::receive::DENY_DELETES.get(config)? {
+            policy_set = policy_set.with_deny_deletes(value);
         }
 
-        // Parse receive.denyNonFastForwards
-        if let Some(result) = config.boolean("receive.denyNonFastForwards") {
-            match result {
-                Ok(value) => policy_set = policy_set.with_deny_non_fast_forwards(value),
-                Err(e) => return Err(Error::Validation(format!("invalid boolean value for 'receive.denyNonFastForwards': {}", e))),
-            }
+        if let Some(value) = keys::receive::DENY_NON_FAST_FORWARDS.get(config)? {
+            policy_set = policy_set.with_deny_non_fast_forwards(value);
         }
 
-        // Parse receive.denyCurrentBranch
-        if let Some(value) = config.string("receive.denyCurrentBranch") {
-            let policy = parse_policy_string(&value, "receive.denyCurrentBranch")?;
+        if let Some(value) = keys::receive::MIRROR.get(config)? {
+            policy_set = policy_set.with_mirror(value);
+        }
+
+        if let Some(value) = keys::receive::DENY_CURRENT_BRANCH.get(config)? {
+            let policy = parse_policy_string(value.as_ref(), keys::receive::DENY_CURRENT_BRANCH.name())?;
             policy_set = policy_set.with_current_branch(policy);
         }
 
-        // Parse receive.denyDeleteCurrent
-        if let Some(value) = config.string("receive.denyDeleteCurrent") {
-            let policy = parse_policy_string(&value, "receive.denyDeleteCurrent")?;
+        if let Some(value) = keys::receive::DENY_DELETE_CURRENT.get(config)? {
+            let policy = parse_policy_string(value.as_ref(), keys::receive::DENY_DELETE_CURRENT.name())?;
             policy_set = policy_set.with_delete_current(policy);
         }
 
-        // Parse receive.updateInstead
-        if let Some(result) = config.boolean("receive.updateInstead") {
-            match result {
-                Ok(value) => policy_set = policy_set.with_update_instead(value),
-                Err(e) => return Err(Error::Validation(format!("invalid boolean value for 'receive.updateInstead': {}", e))),
-            }
+        if let Some(value) = keys::receive::UPDATE_INSTEAD.get(config)? {
+            policy_set = policy_set.with_update_instead(value);
         }
 
-        Ok(Self { policy_set })
+        let mut tag_policy = TagPolicy::new();
+        if let Some(value) = keys::receive::TAG_MISSING_TARGET.get(config)? {
+            let policy = parse_policy_string(value.as_ref(), keys::receive::TAG_MISSING_TARGET.name())?;
+            tag_policy = tag_policy.with_missing_target(policy);
+        }
+
+        let max_depth = match keys::receive::TAG_MAX_DEPTH.get(config)? {
+            Some(depth) => usize::try_from(depth).map_err(|_| {
+                Error::Validation(format!(
+                    "invalid value for '{}': {} is negative",
+                    keys::receive::TAG_MAX_DEPTH.name(),
+                    depth
+                ))
+            })?,
+            None => tag_policy.max_depth(),
+        };
+        let too_deep = match keys::receive::TAG_TOO_DEEP.get(config)? {
+            Some(value) => parse_policy_string(value.as_ref(), keys::receive::TAG_TOO_DEEP.name())?,
+            None => tag_policy.policy(TagRule::TooDeep),
+        };
+        tag_policy = tag_policy.with_max_depth(max_depth, too_deep);
+
+        if let Some(value) = keys::receive::TAG_OUTSIDE_PUSH.get(config)? {
+            let policy = parse_policy_string(value.as_ref(), keys::receive::TAG_OUTSIDE_PUSH.name())?;
+            tag_policy = tag_policy.with_outside_push(policy);
+        }
+
+        Ok(Self { policy_set, tag_policy })
     }
 
     /// Get the parsed PolicySet.
@@ -89,6 +112,11 @@ impl PolicyConfig {
     pub fn into_policy_set(self) -> PolicySet {
         self.policy_set
     }
+
+    /// Get the parsed rules for pushed tags.
+    pub fn tag_policy(&self) -> &TagPolicy {
+        &self.tag_policy
+    }
 }
 
 impl Default for PolicyConfig {
@@ -105,14 +133,14 @@ impl Default for PolicyConfig {
 /// - "allow" or "ignore" -> Policy::Allow
 /// - Boolean true -> Policy::Deny
 /// - Boolean false -> Policy::Allow
-fn parse_policy_string(value: &std::borrow::Cow<'_, BStr>, key: &str) -> Result<Policy, Error> {
+fn parse_policy_string(value: &BStr, key: &str) -> Result<Policy, Error> {
     // First try to parse as boolean
-    if let Ok(boolean) = Boolean::try_from(value.as_ref()) {
+    if let Ok(boolean) = Boolean::try_from(value) {
         return Ok(if boolean.0 { Policy::Deny } else { Policy::Allow });
     }
 
     // Parse as string value
-    let value_str = std::str::from_utf8(value.as_ref())
+    let value_str = std::str::from_utf8(value)
         .map_err(|e| Error::Validation(format!("invalid UTF-8 in '{}': {}", key, e)))?;
 
     match value_str.to_lowercase().as_str() {
@@ -177,6 +205,7 @@ mod tests {
             ("receive.denyDeletes", "true"),
             ("receive.denyNonFastForwards", "false"),
             ("receive.updateInstead", "yes"),
+            ("receive.mirror", "true"),
         ]);
 
         let policy_config = PolicyConfig::from_config(&config).unwrap();
@@ -185,6 +214,7 @@ mod tests {
         assert!(policy_set.deny_deletes());
         assert!(!policy_set.deny_non_fast_forwards());
         assert!(policy_set.update_instead());
+        assert!(policy_set.mirror());
     }
 
     #[test]
@@ -229,6 +259,28 @@ mod tests {
         assert_eq!(policy_set.delete_current(), Policy::Allow);
     }
 
+    #[test]
+    fn test_parse_tag_policies() {
+        let config = create_config_with_values(&[
+            ("receive.tagMissingTarget", "refuse"),
+            ("receive.tagMaxDepth", "3"),
+            ("receive.tagTooDeep", "warn"),
+        ]);
+
+        let policy_config = PolicyConfig::from_config(&config).unwrap();
+        let tag_policy = policy_config.tag_policy();
+
+        assert_eq!(tag_policy.policy(TagRule::MissingTarget), Policy::Deny);
+        assert_eq!(tag_policy.policy(TagRule::TooDeep), Policy::Warn);
+        assert_eq!(tag_policy.max_depth(), 3);
+        assert_eq!(tag_policy.policy(TagRule::OutsidePush), Policy::Allow);
+        assert!(PolicyConfig::new().tag_policy().is_disabled());
+
+        let config = create_config_with_values(&[("receive.tagMaxDepth", "-1")]);
+        let err = PolicyConfig::from_config(&config).unwrap_err().to_string();
+        assert!(err.contains("receive.tagMaxDepth"), "{err}");
+    }
+
     #[test]
     fn test_invalid_boolean_value() {
         let config = create_config_with_values(&[
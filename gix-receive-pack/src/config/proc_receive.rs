@@ -1,5 +1,6 @@
 //! Proc-receive configuration parsing from Git config.
 
+use crate::config::keys;
 use crate::Error;
 use gix_config::File;
 use gix_object::bstr::BStr;
@@ -49,33 +50,20 @@ impl ProcReceiveConfig {
     pub fn from_config(config: &File<'static>) -> Result<Self, Error> {
         let mut proc_config = Self::new();
 
-        // Parse procReceive.enabled
-        if let Some(result) = config.boolean("procReceive.enabled") {
-            match result {
-                Ok(value) => proc_config.enabled = value,
-                Err(e) => return Err(Error::Validation(format!("invalid boolean value for 'procReceive.enabled': {}", e))),
-            }
+        if let Some(value) = keys::proc_receive::ENABLED.get(config)? {
+            proc_config.enabled = value;
         }
 
-        // Parse procReceive.helperPath
-        if let Some(value) = config.string("procReceive.helperPath") {
-            proc_config.helper_path = Some(parse_path_from_string(&value, "procReceive.helperPath")?);
+        if let Some(value) = keys::proc_receive::HELPER_PATH.get(config)? {
+            proc_config.helper_path = Some(parse_path_from_string(value.as_ref(), keys::proc_receive::HELPER_PATH.name())?);
         }
 
-        // Parse procReceive.version
-        if let Some(result) = config.integer("procReceive.version") {
-            match result {
-                Ok(value) => proc_config.version = parse_version_from_integer(value, "procReceive.version")?,
-                Err(e) => return Err(Error::Validation(format!("invalid integer value for 'procReceive.version': {}", e))),
-            }
+        if let Some(value) = keys::proc_receive::VERSION.get(config)? {
+            proc_config.version = parse_version_from_integer(value, keys::proc_receive::VERSION.name())?;
         }
 
-        // Parse procReceive.timeout
-        if let Some(result) = config.integer("procReceive.timeout") {
-            match result {
-                Ok(value) => proc_config.timeout_ms = parse_timeout_from_integer(value, "procReceive.timeout")?,
-                Err(e) => return Err(Error::Validation(format!("invalid integer value for 'procReceive.timeout': {}", e))),
-            }
+        if let Some(value) = keys::proc_receive::TIMEOUT.get(config)? {
+            proc_config.timeout_ms = parse_timeout_from_integer(value, keys::proc_receive::TIMEOUT.name())?;
         }
 
         Ok(proc_config)
@@ -114,8 +102,8 @@ impl Default for ProcReceiveConfig {
 }
 
 /// Parse a file path configuration value from a string.
-fn parse_path_from_string(value: &std::borrow::Cow<'_, BStr>, key: &str) -> Result<PathBuf, Error> {
-    let path_str = std::str::from_utf8(value.as_ref())
+fn parse_path_from_string(value: &BStr, key: &str) -> Result<PathBuf, Error> {
+    let path_str = std::str::from_utf8(value)
         .map_err(|e| Error::Validation(format!("invalid UTF-8 in '{}': {}", key, e)))?;
     
     if path_str.is_empty() {
@@ -0,0 +1,662 @@
+//! The end-to-end push flow behind [`ReceivePack::run()`](crate::ReceivePack::run()).
+//!
+//! [`Engine`] is the [`Handler`] driven by the protocol [`Machine`](crate::protocol::machine::Machine), and applies
+//! a push in the order `git receive-pack` does:
+//!
+//! 1. Push options are passed to the hooks, and so is the certificate of a signed push once it was stored as a blob
+//!    and its nonce was checked. The pack is ingested into a [`Quarantine`] while it's received, exploded into loose
+//!    objects if its header announces fewer objects than the unpack limit, and refused once it exceeds the size limit.
+//!    If that fails, all commands are rejected with `unpacker error`.
+//! 2. Updates of hidden refs are refused, but like in git, the `pre-receive` hook still runs with all commands and sees the objects of the push in the quarantine, and
+//!    declining it rejects all of them. The quarantine is dropped then, and migrated into the main object database
+//!    otherwise, so later hooks see the objects there, and the multi-pack-index is updated for the new pack as
+//!    configured. If the certificate lists other push options than the ones that were sent, all commands are
+//!    rejected with `inconsistent push options` either way.
+//! 3. The other ref updates are [planned](crate::refs::TransactionPlanner), and each command is checked before it's
+//!    applied: the policy is evaluated and the `update` hook runs. Each ref is updated in its own transaction, so a
+//!    failing command never affects the others, unless the client asked for an `atomic` push, which updates all
+//!    refs in one transaction or none of them.
+//! 4. Once the report was sent, `post-receive` runs with the applied commands, and the commit-graph is updated.
+//!
+//! The machine has no room for progress before the report, so the output of hooks is sent after it, before the
+//! final flush, along with why refs couldn't be updated. Hooks relaying their output while they run, like
+//! `ExternalHooks` with a sideband writer, mark it as [relayed](HookDecision::relayed) so it's not sent twice.
+//!
+//! [`PushService`] serves pushes like this as a `GitService`, so servers can route to it next to upload-pack.
+
+use crate::hooks::{HookDecision, Hooks};
+use crate::pack::{IngestionRates, MidxUpdate, PackIngestPath, Quarantine};
+use crate::policy::set::resolve_current_branch;
+use crate::policy::{PolicySet, ReasonCode};
+use crate::protocol::machine::{Handler, RefStatus, Report};
+use crate::protocol::{CommandList, CommandUpdate, NonceCheck, Options, PushCertRecord, PushCertificate};
+use crate::refs::{
+    AtomicExecutor, CommandResult, NonAtomicExecutor, PlannedCommand, TransactionMode, TransactionPlanner,
+};
+use crate::{CommitGraphUpdate, Error, ReceivePack, RunOutcome};
+use gix_object::{Exists, Write};
+use gix_serve_core::audit::Reason;
+use gix_serve_core::metrics::{Phase, PhaseTimer};
+use gix_serve_core::protocol::ServiceKind;
+#[cfg(feature = "serve-core")]
+use gix_serve_core::service::{self, GitService, Outcome, RefUpdate, ServiceContext};
+use gix_serve_core::wire::WireStats;
+use std::io::{BufReader, Read};
+use std::path::PathBuf;
+use std::sync::mpsc;
+
+/// Why all commands fail if the certificate of a signed push lists other push options than the ones that were sent.
+const INCONSISTENT_PUSH_OPTIONS: &str = "inconsistent push options";
+
+/// The [`Handler`] applying pushes to a repository, see the [module documentation](self).
+pub(crate) struct Engine<'a> {
+    receive_pack: &'a ReceivePack,
+    refs: gix_ref::file::Store,
+    objects_dir: PathBuf,
+    hooks: &'a mut dyn Hooks,
+    policy: &'a PolicySet,
+    commands: CommandList,
+    mode: TransactionMode,
+    ingestion: Option<Ingestion>,
+    applied: Vec<CommandUpdate>,
+    midx: Option<MidxUpdate>,
+    report: Option<Report>,
+    /// Output of hooks and why refs couldn't be updated, sent as progress after the report.
+    output: Vec<u8>,
+    /// The nonce issued for signed pushes, if they are offered.
+    nonce: Option<String>,
+    /// Whether the request is one of a stateless transport, which may send a nonce issued in an earlier request.
+    stateless: bool,
+    push_cert: Option<PushCertRecord>,
+    inconsistent_push_options: bool,
+}
+
+impl<'a> Engine<'a> {
+    /// Apply pushes to the refs at `git_dir` and the objects in `objects_dir` of `receive_pack`.
+    pub(crate) fn new(
+        receive_pack: &'a ReceivePack,
+        git_dir: PathBuf,
+        objects_dir: PathBuf,
+        hooks: &'a mut dyn Hooks,
+        policy: &'a PolicySet,
+    ) -> Self {
+        let mut refs = gix_ref::file::Store::at(
+            git_dir,
+            gix_ref::store::init::Options {
+                write_reflog: gix_ref::store::WriteReflog::Disable,
+                object_hash: receive_pack.cfg.object_hash,
+                precompose_unicode: false,
+                prohibit_windows_device_names: false,
+            },
+        );
+        refs.namespace.clone_from(&receive_pack.cfg.namespace);
+        Self {
+            receive_pack,
+            refs,
+            objects_dir,
+            hooks,
+            policy,
+            commands: CommandList::new(),
+            mode: TransactionMode::NonAtomic,
+            ingestion: None,
+            applied: Vec::new(),
+            midx: None,
+            report: None,
+            output: Vec::new(),
+            nonce: None,
+            stateless: false,
+            push_cert: None,
+            inconsistent_push_options: false,
+        }
+    }
+
+    /// Accept nonces issued in earlier requests within the configured slop if `stateless`, like git does for its
+    /// `--stateless-rpc` mode.
+    pub(crate) fn with_stateless(mut self, stateless: bool) -> Self {
+        self.stateless = stateless;
+        self
+    }
+
+    /// Issue a nonce for signed pushes if they are offered, and return the `push-cert` capability advertising it.
+    ///
+    /// Like in git, the nonce is derived from the path of the repository as it was configured.
+    pub(crate) fn push_cert_capability(&mut self) -> Option<String> {
+        let config = self.receive_pack.cfg.push_certs.as_ref()?;
+        let stamp = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs());
+        let nonce = config.nonce(&self.refs.git_dir().to_string_lossy(), stamp);
+        let capability = format!("push-cert={nonce}");
+        self.nonce = Some(nonce);
+        Some(capability)
+    }
+
+    /// The refs to advertise, all refs that aren't symbolic or hidden, as the targets of symbolic refs are advertised
+    /// already.
+    ///
+    /// While serving a namespace, its refs are advertised without it, and like in git, the objects of the refs
+    /// outside of it are advertised as `.have`, once each, so clients can use them without seeing the refs.
+    pub(crate) fn advertised_refs(&self) -> Result<Vec<crate::protocol::RefRecord>, Error> {
+        let refs = |err: &dyn std::fmt::Display| Error::environment_setup(&format!("failed to read refs: {err}"));
+        let mut all_refs = self.refs.clone();
+        let namespace = all_refs.namespace.take();
+        let mut records = Vec::new();
+        let mut seen = std::collections::HashSet::new();
+        let hidden_refs = &self.receive_pack.cfg.hidden_refs;
+        for reference in all_refs
+            .iter()
+            .map_err(|err| refs(&err))?
+            .all()
+            .map_err(|err| refs(&err))?
+        {
+            let reference = reference.map_err(|err| refs(&err))?;
+            let gix_ref::Target::Object(id) = reference.target else {
+                continue;
+            };
+            let full_name = reference.name.as_bstr().to_string();
+            let name = match &namespace {
+                Some(namespace) => full_name.strip_prefix(&namespace.as_bstr().to_string()),
+                None => Some(full_name.as_str()),
+            };
+            if hidden_refs.is_hidden_in_namespace(name, &full_name) {
+                continue;
+            }
+            let name = match name {
+                Some(name) => name.to_owned(),
+                None if seen.contains(&id) => continue,
+                None => ".have".into(),
+            };
+            seen.insert(id);
+            records.push(crate::protocol::RefRecord::new(id, name));
+        }
+        Ok(records)
+    }
+
+    /// Finish the conversation, returning what was applied.
+    pub(crate) fn into_outcome(self, wire: WireStats) -> RunOutcome {
+        RunOutcome {
+            applied: self.applied,
+            report: self.report,
+            push_cert: self.push_cert,
+            wire,
+            midx: self.midx,
+        }
+    }
+
+    /// Apply the received commands once the pack was `unpacked` into the quarantine via the given path, or not if
+    /// no pack was sent.
+    fn apply(&mut self, unpacked: Option<(Quarantine, PackIngestPath)>) -> Report {
+        let (mut quarantine, path) = unpacked.unzip();
+        let commands: Vec<CommandUpdate> = self.commands.iter().cloned().collect();
+        if let Some(quarantine) = quarantine.as_ref() {
+            self.hooks.quarantine(Some(quarantine));
+        }
+        let decision = self.hooks.pre_receive(&commands).unwrap_or_else(declined);
+        self.collect_output(&decision);
+        if !decision.allowed {
+            if let Some(quarantine) = quarantine.as_mut() {
+                let _ = quarantine.drop_on_failure();
+                self.hooks.quarantine(None);
+            }
+            if self.inconsistent_push_options {
+                return self.reject_all(INCONSISTENT_PUSH_OPTIONS, None);
+            }
+            return self.reject_remaining("pre-receive hook declined", Some(Reason::Hook));
+        }
+        if let Some(quarantine) = quarantine.as_mut() {
+            let manifests = self.receive_pack.collect_push_manifests(quarantine);
+            let migrated = quarantine.migrate_on_success();
+            self.hooks.quarantine(None);
+            if migrated.is_err() {
+                return self.reject_remaining("unable to migrate objects to permanent storage", None);
+            }
+            let _ = self.receive_pack.record_push_manifests(&self.objects_dir, manifests);
+        }
+        if let Some(path) = path {
+            let mut progress = gix_features::progress::Discard;
+            self.midx = Some(self.receive_pack.update_midx(&self.objects_dir, path, &mut progress));
+        }
+        // Like in git, the hook sees the push even if its certificate doesn't list the push options that were sent.
+        if self.inconsistent_push_options {
+            return self.reject_all(INCONSISTENT_PUSH_OPTIONS, None);
+        }
+
+        let objects = match gix_odb::at(self.objects_dir.clone()) {
+            Ok(objects) => objects,
+            Err(err) => return self.reject_remaining(&format!("failed to open the object database: {err}"), None),
+        };
+        let current_branch = self.current_branch();
+        // Like in git, refused updates of hidden refs don't fail the others, even in atomic pushes.
+        let mut applicable = CommandList::new();
+        let mut hidden = Vec::new();
+        for command in self.commands.iter() {
+            match self.hidden_ref_status(command) {
+                Some(status) => hidden.push(status),
+                None => applicable.push(command.clone()),
+            }
+        }
+        let plan = match TransactionPlanner::new(&self.refs, self.mode).plan(&applicable) {
+            Ok(plan) => plan,
+            Err(err) => return self.reject_remaining(&format!("failed to plan the ref updates: {err}"), None),
+        };
+        let refs = self.refs.clone();
+        let check = |planned: &PlannedCommand| self.check(&planned.command, current_branch.as_deref(), &objects);
+        let result = match plan.mode {
+            TransactionMode::Atomic => AtomicExecutor::new(&refs).execute(&plan, check),
+            TransactionMode::NonAtomic => NonAtomicExecutor::new(&refs).execute(&plan, check),
+        };
+        for message in &result.messages {
+            self.output.extend_from_slice(format!("error: {message}\n").as_bytes());
+        }
+        self.applied = result.applied().cloned().collect();
+        Report {
+            unpack_error: None,
+            refs: result.results.into_iter().map(RefStatus::from).chain(hidden).collect(),
+        }
+    }
+
+    /// The branch `HEAD` of the repository points to, as named by commands.
+    ///
+    /// Like in git, it's the current branch even while serving a namespace, in which case commands can only update it
+    /// if it's in the namespace. A `HEAD` that can't be resolved has no current branch to protect.
+    fn current_branch(&self) -> Option<String> {
+        let mut refs = self.refs.clone();
+        let namespace = refs.namespace.take();
+        let branch = resolve_current_branch(&refs).ok().flatten()?;
+        match namespace {
+            Some(namespace) => branch
+                .strip_prefix(&namespace.as_bstr().to_string())
+                .map(ToOwned::to_owned),
+            None => Some(branch),
+        }
+    }
+
+    /// The status of `command` if it updates a hidden ref, which is refused before any hook runs, like in git.
+    fn hidden_ref_status(&self, command: &CommandUpdate) -> Option<RefStatus> {
+        let (name, hidden_refs) = (command.name(), &self.receive_pack.cfg.hidden_refs);
+        let hidden = match &self.refs.namespace {
+            Some(namespace) => {
+                hidden_refs.is_hidden_in_namespace(Some(name), &format!("{}{name}", namespace.as_bstr()))
+            }
+            None => hidden_refs.is_hidden(name),
+        };
+        if !hidden {
+            return None;
+        }
+        let reason = match command {
+            CommandUpdate::Delete { .. } => "deny deleting a hidden ref",
+            CommandUpdate::Create { .. } | CommandUpdate::Update { .. } => "deny updating a hidden ref",
+        };
+        Some(RefStatus::denied(command.name(), reason, Reason::HiddenRef))
+    }
+
+    /// Check if `command` may be applied, evaluating the policy and running its `update` hook, and return its result
+    /// if it's rejected.
+    fn check(
+        &mut self,
+        command: &CommandUpdate,
+        current_branch: Option<&str>,
+        objects: &gix_odb::Handle,
+    ) -> Result<(), CommandResult> {
+        let name = command.name();
+        if !name.starts_with("refs/") || gix_ref::FullName::try_from(name).is_err() {
+            return Err(CommandResult::rejected(command.clone(), "funny refname"));
+        }
+        if command.old_and_new().1.is_some_and(|new| !objects.exists(&new)) {
+            return Err(CommandResult::rejected(command.clone(), "bad pack"));
+        }
+
+        let decision = match self.policy.evaluate_internal(command, current_branch, objects) {
+            Ok(decision) => decision,
+            Err(err) => return Err(CommandResult::rejected(command.clone(), err.to_string())),
+        };
+        if !decision.allowed {
+            let denial = decision.reason_code.denial_reason().unwrap_or(Reason::Policy);
+            let reason = refusal(&decision.reason_code, &decision.message);
+            return Err(CommandResult::denied(command.clone(), reason, denial));
+        }
+        if let Some(update) = decision.delegated_action {
+            let refused = match self.receive_pack.cfg.worktree.as_ref() {
+                Some(updater) => self.receive_pack.update_worktree(updater, &update).err(),
+                None => Some(Error::WorktreeRefused(crate::worktree::Refusal::NoWorktree)),
+            };
+            if let Some(err) = refused {
+                return Err(match err {
+                    Error::WorktreeRefused(refusal) => {
+                        CommandResult::denied(command.clone(), refusal.to_string(), Reason::Policy)
+                    }
+                    err => CommandResult::rejected(command.clone(), err.to_string()),
+                });
+            }
+        }
+
+        let decision = self.hooks.update(command).unwrap_or_else(declined);
+        self.collect_output(&decision);
+        if !decision.allowed {
+            return Err(CommandResult::denied(command.clone(), "hook declined", Reason::Hook));
+        }
+        Ok(())
+    }
+
+    /// Collect the output of the hook that made `decision` to send it after the report, unless it was relayed
+    /// while the hook ran.
+    fn collect_output(&mut self, decision: &HookDecision) {
+        if decision.relayed {
+            return;
+        }
+        self.output.extend_from_slice(&decision.stdout);
+        self.output.extend_from_slice(&decision.stderr);
+    }
+
+    /// Store `certificate` as a blob for auditing and check its nonce, like git does before running hooks.
+    ///
+    /// The blob is written to the main object database, so it's kept even if the push is rejected.
+    fn record_push_cert(&self, certificate: &PushCertificate) -> Result<PushCertRecord, Error> {
+        let id = gix_odb::loose::Store::at(self.objects_dir.clone(), self.receive_pack.cfg.object_hash)
+            .write_buf(gix_object::Kind::Blob, certificate.text.as_bytes())
+            .map_err(|err| {
+                Error::Io(std::io::Error::other(format!(
+                    "failed to store the push certificate: {err}"
+                )))
+            })?;
+        let received = certificate.nonce.as_deref();
+        let nonce = match (&self.nonce, &self.receive_pack.cfg.push_certs) {
+            (Some(issued), Some(config)) => {
+                config.check_nonce(issued, received, &self.refs.git_dir().to_string_lossy(), self.stateless)
+            }
+            _ => NonceCheck::unissued(received),
+        };
+        Ok(PushCertRecord {
+            id,
+            certificate: certificate.clone(),
+            nonce,
+        })
+    }
+
+    /// Reject all commands with `reason`, denied for `denial` if set.
+    fn reject_all(&self, reason: &str, denial: Option<Reason>) -> Report {
+        Report {
+            unpack_error: None,
+            refs: self
+                .commands
+                .iter()
+                .map(|command| match denial {
+                    Some(denial) => RefStatus::denied(command.name(), reason, denial),
+                    None => RefStatus::rejected(command.name(), reason),
+                })
+                .collect(),
+        }
+    }
+
+    /// Like [`reject_all()`](Self::reject_all()), but updates of hidden refs stay refused for that, like in git.
+    fn reject_remaining(&self, reason: &str, denial: Option<Reason>) -> Report {
+        let mut report = self.reject_all(reason, denial);
+        for (status, command) in report.refs.iter_mut().zip(self.commands.iter()) {
+            if let Some(hidden) = self.hidden_ref_status(command) {
+                *status = hidden;
+            }
+        }
+        report
+    }
+}
+
+impl Handler for Engine<'_> {
+    fn commands(&mut self, commands: &CommandList, options: &Options) -> Result<(), Error> {
+        self.commands = commands.clone();
+        self.mode = TransactionMode::from_options(options);
+        if options.has("push-options") {
+            self.hooks.push_options(&options.push_options);
+        }
+        if let Some(certificate) = &options.push_cert {
+            let record = self.record_push_cert(certificate)?;
+            self.inconsistent_push_options = !certificate.has_push_options(&options.push_options);
+            self.hooks.push_cert(&record);
+            self.push_cert = Some(record);
+        }
+        // Like the machine, only pushes with something other than deletions come with a pack.
+        if commands
+            .iter()
+            .any(|command| !matches!(command, CommandUpdate::Delete { .. }))
+        {
+            self.ingestion = Some(Ingestion::start(self.receive_pack, self.objects_dir.clone())?);
+        }
+        Ok(())
+    }
+
+    fn pack_data(&mut self, data: &[u8]) -> Result<bool, Error> {
+        Ok(match self.ingestion.as_mut() {
+            Some(ingestion) => ingestion.feed(data),
+            None => true,
+        })
+    }
+
+    fn report(&mut self) -> Result<Report, Error> {
+        let report = match self.ingestion.take().map(Ingestion::finish) {
+            Some(Err(err) | Ok((_, Err(err)))) => Report {
+                unpack_error: Some(err.to_string()),
+                ..self.reject_all("unpacker error", None)
+            },
+            Some(Ok((quarantine, Ok((path, rates))))) => {
+                self.receive_pack.cfg.metrics.pack_received(rates.objects, rates.bytes);
+                self.apply(Some((quarantine, path)))
+            }
+            None => self.apply(None),
+        };
+        self.report = Some(report.clone());
+        Ok(report)
+    }
+
+    fn after_report(&mut self) -> Result<Vec<u8>, Error> {
+        if !self.applied.is_empty() {
+            // post-receive and the commit-graph can't change the outcome of the push anymore.
+            if let Err(_err) = self.hooks.post_receive(&self.applied) {
+                gix_features::trace::warn!("post-receive hook failed: {_err}");
+            }
+            match self.receive_pack.update_commit_graph(&self.applied) {
+                Ok(_update @ (CommitGraphUpdate::Failed { .. } | CommitGraphUpdate::Unavailable { .. })) => {
+                    gix_features::trace::warn!("commit-graph wasn't updated after the push: {_update:?}");
+                }
+                Ok(_update) => gix_features::trace::debug!("commit-graph after the push: {_update:?}"),
+                Err(_err) => gix_features::trace::warn!("commit-graph couldn't be updated after the push: {_err}"),
+            }
+        }
+        Ok(std::mem::take(&mut self.output))
+    }
+}
+
+/// A hook that failed to run declines like one that exited with an error.
+fn declined(err: Error) -> HookDecision {
+    HookDecision::deny(err.to_string())
+}
+
+/// The reason for refusing an update for `code` as git reports it, or `message` if git has none.
+fn refusal(code: &ReasonCode, message: &str) -> String {
+    match code {
+        ReasonCode::DenyDeletes => "deletion prohibited",
+        ReasonCode::NonFastForward => "non-fast-forward",
+        ReasonCode::DenyCurrent => "branch is currently checked out",
+        ReasonCode::DenyDeleteCurrent => "deletion of the current branch prohibited",
+        ReasonCode::HookRejected => "hook declined",
+        _ => message,
+    }
+    .into()
+}
+
+/// The quarantine the pack was ingested into, along with the outcome of the ingestion.
+type IngestionOutcome = (Quarantine, Result<(PackIngestPath, IngestionRates), Error>);
+
+/// Pack ingestion running on its own thread while the pack is received.
+///
+/// The thread reads the pack from chunks sent to it, and asks for the next one once it consumed the last. It stops
+/// asking once the pack is complete, which is how the end of the pack is known while the client waits for the
+/// report.
+struct Ingestion {
+    chunks: mpsc::Sender<Vec<u8>>,
+    wanted: mpsc::Receiver<()>,
+    thread: std::thread::JoinHandle<IngestionOutcome>,
+    /// Measures the ingestion until it's finished.
+    phase: PhaseTimer,
+}
+
+impl Ingestion {
+    /// Start ingesting into a new quarantine of `objects_dir` with the configuration of `receive_pack`.
+    fn start(receive_pack: &ReceivePack, objects_dir: PathBuf) -> Result<Self, Error> {
+        let mut quarantine = Quarantine::new(objects_dir);
+        quarantine.activate()?;
+        let (chunks, chunk_rx) = mpsc::channel();
+        let (wanted_tx, wanted) = mpsc::channel();
+        let phase = receive_pack
+            .cfg
+            .metrics
+            .phase(ServiceKind::ReceivePack, Phase::Ingestion);
+        let receive_pack = receive_pack.clone();
+        let thread = std::thread::spawn(move || {
+            let mut input = BufReader::new(Chunks {
+                chunks: chunk_rx,
+                wanted: wanted_tx,
+                chunk: Vec::new(),
+                pos: 0,
+                started: false,
+            });
+            let mut progress = gix_features::progress::Discard;
+            let result = receive_pack.ingest_into_quarantine(&mut quarantine, &mut input, None, None, &mut progress);
+            (quarantine, result)
+        });
+        Ok(Self {
+            chunks,
+            wanted,
+            thread,
+            phase,
+        })
+    }
+
+    /// Pass `data` to the ingestion, and return `true` if it doesn't need more as the pack is complete or invalid.
+    fn feed(&mut self, data: &[u8]) -> bool {
+        if self.chunks.send(data.to_vec()).is_err() {
+            return true;
+        }
+        self.wanted.recv().is_err()
+    }
+
+    /// Wait for the ingestion to end, failing it if the pack isn't complete, and return the quarantine with the
+    /// outcome, or an error if the ingestion panicked.
+    fn finish(self) -> Result<IngestionOutcome, Error> {
+        let Self {
+            chunks,
+            wanted,
+            thread,
+            phase,
+        } = self;
+        drop(chunks);
+        drop(wanted);
+        let outcome = thread.join().map_err(|panic| {
+            let message = panic
+                .downcast_ref::<&str>()
+                .copied()
+                .or_else(|| panic.downcast_ref::<String>().map(String::as_str))
+                .unwrap_or("unknown cause");
+            Error::Panicked(format!("pack ingestion: {message}"))
+        });
+        drop(phase);
+        outcome
+    }
+}
+
+/// The input of the ingestion thread, the pack chunks received so far.
+struct Chunks {
+    chunks: mpsc::Receiver<Vec<u8>>,
+    wanted: mpsc::Sender<()>,
+    chunk: Vec<u8>,
+    pos: usize,
+    /// Whether a chunk was received, so the next one is asked for once it was consumed.
+    started: bool,
+}
+
+impl Read for Chunks {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.pos == self.chunk.len() {
+            if std::mem::replace(&mut self.started, true) && self.wanted.send(()).is_err() {
+                return Ok(0);
+            }
+            match self.chunks.recv() {
+                Ok(chunk) => {
+                    self.chunk = chunk;
+                    self.pos = 0;
+                }
+                // The client ended its input before the pack was complete.
+                Err(_) => return Ok(0),
+            }
+        }
+        let len = buf.len().min(self.chunk.len() - self.pos);
+        buf[..len].copy_from_slice(&self.chunk[self.pos..self.pos + len]);
+        self.pos += len;
+        Ok(len)
+    }
+}
+
+/// A [`GitService`] serving pushes with [`ReceivePack::run()`], running `hooks`
+/// for each of them.
+///
+/// Over stateless transports, the advertisement and the push are served in separate requests.
+#[cfg(feature = "serve-core")]
+pub struct PushService<H> {
+    receive_pack: ReceivePack,
+    hooks: H,
+}
+
+#[cfg(feature = "serve-core")]
+impl<H: Hooks> PushService<H> {
+    /// Serve pushes to the repository `receive_pack` is configured for, running `hooks`.
+    pub fn new(receive_pack: ReceivePack, hooks: H) -> Self {
+        PushService { receive_pack, hooks }
+    }
+
+    /// The engine serving pushes.
+    pub fn receive_pack(&self) -> &ReceivePack {
+        &self.receive_pack
+    }
+
+    /// The hooks run for each push.
+    pub fn hooks(&self) -> &H {
+        &self.hooks
+    }
+}
+
+#[cfg(feature = "serve-core")]
+impl<H: Hooks> GitService for PushService<H> {
+    fn kind(&self) -> ServiceKind {
+        ServiceKind::ReceivePack
+    }
+
+    fn advertise(&mut self, out: &mut dyn std::io::Write, _ctx: &ServiceContext) -> Result<(), service::Error> {
+        self.receive_pack
+            .advertise(out)
+            .map_err(crate::protocol::service::to_service_error)
+    }
+
+    fn serve(
+        &mut self,
+        input: &mut dyn Read,
+        output: &mut dyn std::io::Write,
+        ctx: &ServiceContext,
+    ) -> Result<Outcome, service::Error> {
+        let outcome = self
+            .receive_pack
+            .serve(input, output, &mut self.hooks, ctx.stateless)
+            .map_err(crate::protocol::service::to_service_error)?;
+        let ref_updates = outcome
+            .report
+            .map(|report| report.refs)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|status| RefUpdate {
+                name: status.name,
+                rejected: status.error,
+            })
+            .collect();
+        Ok(Outcome {
+            ref_updates,
+            wire: outcome.wire,
+        })
+    }
+}
@@ -0,0 +1,277 @@
+//! The commits each ref update introduces, computed once for all policies and hooks.
+//!
+//! Policies like commit message lints or author allowlists only care about the commits a push adds,
+//! which are `old..new` for each updated ref. [`NewCommits::compute()`] walks them once, also excluding
+//! commits reachable from tips the repository already has, so a branch created off `main` doesn't list
+//! the history of `main`.
+//!
+//! In-process hooks receive them through
+//! [`Hooks::pre_receive_with_commits()`](super::Hooks::pre_receive_with_commits()). External hooks can read
+//! them from the file [`NewCommits::write_to()`] wrote, whose path is passed in [`ENV_VAR`] by
+//! [`HookEnvironment::with_new_commits_file()`](super::env::HookEnvironment::with_new_commits_file()).
+
+use crate::protocol::CommandUpdate;
+use crate::Error;
+use gix_hash::ObjectId;
+use gix_object::{CommitRef, Find, Kind};
+use std::collections::{BinaryHeap, HashMap};
+use std::path::Path;
+
+/// The environment variable naming the file with the new commits of each ref, one `<refname> <commit>` per line.
+pub const ENV_VAR: &str = "GIX_RECEIVE_NEW_COMMITS";
+
+/// The commits a single ref update introduces.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RefCommits {
+    /// The full name of the updated ref.
+    pub refname: String,
+    /// The new commits, most recently committed first.
+    pub commits: Vec<ObjectId>,
+}
+
+/// The commits each ref update of a push introduces.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct NewCommits {
+    refs: Vec<RefCommits>,
+}
+
+impl NewCommits {
+    /// Find the commits each of `commands` introduces, looking up objects in `objects`.
+    ///
+    /// These are the commits reachable from the new tip but neither from the old one nor from any of
+    /// `existing_tips`, usually the tips of all refs before the push. Deletions introduce no commits and
+    /// are left out, as are updates to objects other than commits. `objects` has to see the received
+    /// objects, so for pre-receive checks it includes the quarantine.
+    pub fn compute(
+        commands: &[CommandUpdate],
+        existing_tips: &[ObjectId],
+        objects: &impl Find,
+    ) -> Result<Self, Error> {
+        let mut refs = Vec::new();
+        for command in commands {
+            let (old, new) = match command {
+                CommandUpdate::Create { new, .. } => (None, *new),
+                CommandUpdate::Update { old, new, .. } => (Some(*old), *new),
+                CommandUpdate::Delete { .. } => continue,
+            };
+            let mut walk = Walk::new(objects);
+            if !walk.push(new, false)? {
+                continue;
+            }
+            for tip in old.iter().chain(existing_tips).filter(|tip| !tip.is_null()) {
+                walk.push(*tip, true)?;
+            }
+            refs.push(RefCommits {
+                refname: command.name().to_owned(),
+                commits: walk.into_new_commits()?,
+            });
+        }
+        Ok(NewCommits { refs })
+    }
+
+    /// The new commits of `refname`, if it was created or updated to a commit.
+    pub fn for_ref(&self, refname: &str) -> Option<&[ObjectId]> {
+        self.refs
+            .iter()
+            .find(|r| r.refname == refname)
+            .map(|r| r.commits.as_slice())
+    }
+
+    /// The new commits of each ref, in the order of the commands.
+    pub fn iter(&self) -> impl Iterator<Item = &RefCommits> {
+        self.refs.iter()
+    }
+
+    /// Write one `<refname> <commit>` line per new commit to the file at `path`, for external hooks.
+    pub fn write_to(&self, path: &Path) -> Result<(), Error> {
+        let mut out = String::new();
+        for r in &self.refs {
+            for commit in &r.commits {
+                out.push_str(&format!("{} {}\n", r.refname, commit));
+            }
+        }
+        std::fs::write(path, out)?;
+        Ok(())
+    }
+}
+
+/// A walk from one new tip, in commit time order, that stops once only excluded commits are left.
+struct Walk<'a, F> {
+    objects: &'a F,
+    /// Whether a seen commit is reachable from an excluded tip.
+    excluded: HashMap<ObjectId, bool>,
+    queue: BinaryHeap<(i64, ObjectId)>,
+    buf: Vec<u8>,
+    new_commits: Vec<ObjectId>,
+}
+
+impl<'a, F: Find> Walk<'a, F> {
+    fn new(objects: &'a F) -> Self {
+        Walk {
+            objects,
+            excluded: HashMap::new(),
+            queue: BinaryHeap::new(),
+            buf: Vec::new(),
+            new_commits: Vec::new(),
+        }
+    }
+
+    /// Queue the tip `id`, returning `false` if it's no commit. Excluded tips may be missing.
+    fn push(&mut self, id: ObjectId, excluded: bool) -> Result<bool, Error> {
+        let Some(time) = self.commit_time(id, excluded)? else {
+            return Ok(false);
+        };
+        let was_excluded = self.excluded.entry(id).or_insert(excluded);
+        *was_excluded |= excluded;
+        self.queue.push((time, id));
+        Ok(true)
+    }
+
+    /// Return the commit time of `id`, or `None` if it's no commit or a missing commit that is `optional`.
+    fn commit_time(&mut self, id: ObjectId, optional: bool) -> Result<Option<i64>, Error> {
+        match self.objects.try_find(&id, &mut self.buf) {
+            Ok(Some(data)) if data.kind == Kind::Commit => {
+                let commit = CommitRef::from_bytes(data.data)
+                    .map_err(|e| Error::Validation(format!("failed to parse commit {id}: {e}")))?;
+                Ok(Some(commit.committer().seconds()))
+            }
+            Ok(Some(_)) => Ok(None),
+            Ok(None) if optional => Ok(None),
+            Ok(None) => Err(Error::Validation(format!("commit {id} not found"))),
+            Err(e) => Err(Error::Validation(format!("failed to find commit {id}: {e}"))),
+        }
+    }
+
+    fn parents(&mut self, id: ObjectId) -> Result<Vec<ObjectId>, Error> {
+        let data = self
+            .objects
+            .try_find(&id, &mut self.buf)
+            .map_err(|e| Error::Validation(format!("failed to find commit {id}: {e}")))?
+            .ok_or_else(|| Error::Validation(format!("commit {id} not found")))?;
+        let commit = CommitRef::from_bytes(data.data)
+            .map_err(|e| Error::Validation(format!("failed to parse commit {id}: {e}")))?;
+        Ok(commit.parents().collect())
+    }
+
+    fn into_new_commits(mut self) -> Result<Vec<ObjectId>, Error> {
+        while self.queue.iter().any(|(_, id)| !self.excluded[id]) {
+            let Some((_, id)) = self.queue.pop() else { break };
+            let excluded = self.excluded[&id];
+            if !excluded {
+                self.new_commits.push(id);
+            }
+            for parent in self.parents(id)? {
+                match self.excluded.get_mut(&parent) {
+                    Some(parent_excluded) => *parent_excluded |= excluded,
+                    None => {
+                        // Excluded history may be incomplete in shallow repositories.
+                        if let Some(time) = self.commit_time(parent, excluded)? {
+                            self.excluded.insert(parent, excluded);
+                            self.queue.push((time, parent));
+                        }
+                    }
+                }
+            }
+        }
+        let Walk {
+            excluded, new_commits, ..
+        } = self;
+        Ok(new_commits.into_iter().filter(|id| !excluded[id]).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::Command;
+
+    fn git(dir: &Path, args: &[&str]) -> String {
+        let output = Command::new("git")
+            .args(args)
+            .current_dir(dir)
+            .env("GIT_AUTHOR_NAME", "author")
+            .env("GIT_AUTHOR_EMAIL", "author@example.com")
+            .env("GIT_COMMITTER_NAME", "committer")
+            .env("GIT_COMMITTER_EMAIL", "committer@example.com")
+            .output()
+            .expect("git is installed");
+        assert!(output.status.success(), "git {args:?} failed");
+        String::from_utf8(output.stdout).unwrap().trim().to_owned()
+    }
+
+    fn commit(dir: &Path, message: &str, date: u32) -> ObjectId {
+        let date = format!("{} +0000", 1_700_000_000 + date);
+        let output = Command::new("git")
+            .args(["commit", "--quiet", "--allow-empty", "-m", message])
+            .current_dir(dir)
+            .env("GIT_AUTHOR_NAME", "author")
+            .env("GIT_AUTHOR_EMAIL", "author@example.com")
+            .env("GIT_COMMITTER_NAME", "committer")
+            .env("GIT_COMMITTER_EMAIL", "committer@example.com")
+            .env("GIT_COMMITTER_DATE", date)
+            .output()
+            .expect("git is installed");
+        assert!(output.status.success());
+        ObjectId::from_hex(git(dir, &["rev-parse", "HEAD"]).as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn new_commits_exclude_existing_history() {
+        let tmp = gix_testtools::tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        git(dir, &["init", "--quiet", "--initial-branch=main"]);
+        let base = commit(dir, "base", 0);
+        let old_main = commit(dir, "old main", 1);
+        git(dir, &["checkout", "--quiet", "-b", "feature", &base.to_string()]);
+        let feature = [commit(dir, "feature 1", 2), commit(dir, "feature 2", 3)];
+        git(dir, &["checkout", "--quiet", "main"]);
+        git(dir, &["merge", "--quiet", "--no-ff", "-m", "merge", "feature"]);
+        let new_main = ObjectId::from_hex(git(dir, &["rev-parse", "HEAD"]).as_bytes()).unwrap();
+        let tree = git(dir, &["rev-parse", "HEAD^{tree}"]);
+        let odb = gix_odb::at(dir.join(".git/objects")).unwrap();
+
+        let commands = [
+            CommandUpdate::Update {
+                old: old_main,
+                new: new_main,
+                name: "refs/heads/main".into(),
+            },
+            CommandUpdate::Create {
+                new: feature[1],
+                name: "refs/heads/feature".into(),
+            },
+            CommandUpdate::Create {
+                new: ObjectId::from_hex(tree.as_bytes()).unwrap(),
+                name: "refs/heads/tree".into(),
+            },
+            CommandUpdate::Delete {
+                old: old_main,
+                name: "refs/heads/old".into(),
+            },
+        ];
+        let new_commits = NewCommits::compute(&commands, &[old_main], &odb).unwrap();
+        assert_eq!(
+            new_commits.for_ref("refs/heads/main"),
+            Some([new_main, feature[1], feature[0]].as_slice()),
+            "the merged feature commits are new to main, but not base"
+        );
+        assert_eq!(
+            new_commits.for_ref("refs/heads/feature"),
+            Some(feature.iter().rev().copied().collect::<Vec<_>>().as_slice()),
+            "existing tips are excluded from created refs"
+        );
+        assert_eq!(new_commits.iter().count(), 2, "neither trees nor deletions introduce commits");
+
+        let file = dir.join("new-commits");
+        new_commits.write_to(&file).unwrap();
+        let lines = std::fs::read_to_string(&file).unwrap();
+        assert_eq!(lines.lines().count(), 5);
+        assert!(lines.starts_with(&format!("refs/heads/main {new_main}\n")), "{lines}");
+
+        let missing = [CommandUpdate::Create {
+            new: ObjectId::from_hex(b"1111111111111111111111111111111111111111").unwrap(),
+            name: "refs/heads/missing".into(),
+        }];
+        assert!(NewCommits::compute(&missing, &[], &odb).is_err());
+    }
+}
@@ -8,6 +8,7 @@ use std::path::PathBuf;
 use crate::Error;
 use crate::pack::Quarantine;
 use crate::protocol::options::Options;
+use crate::protocol::PushCertRecord;
 
 /// Builder for constructing hook execution environments.
 ///
@@ -19,6 +20,8 @@ pub struct HookEnvironment {
     pub git_dir: Option<PathBuf>,
     /// Path to the quarantine directory (when active)
     pub git_quarantine_path: Option<PathBuf>,
+    /// Object directories the quarantine borrows objects from (when active)
+    pub git_alternate_object_directories: Vec<PathBuf>,
     /// Push options from the client
     pub push_options: Vec<String>,
     /// Optional identity information
@@ -42,6 +45,7 @@ impl HookEnvironment {
         Self {
             git_dir: None,
             git_quarantine_path: None,
+            git_alternate_object_directories: Vec::new(),
             push_options: Vec::new(),
             identity: None,
             additional_vars: HashMap::new(),
@@ -62,12 +66,22 @@ impl HookEnvironment {
 
     /// Set the quarantine from a Quarantine instance.
     /// 
-    /// This will set the quarantine path only if the quarantine is active.
-    pub fn with_quarantine(mut self, quarantine: &Quarantine) -> Self {
-        // Only set the quarantine path if it's active
-        if quarantine.is_active() {
-            self.git_quarantine_path = Some(quarantine.objects_dir.clone());
+    /// Like git, hooks then find new objects through `GIT_OBJECT_DIRECTORY` pointing at the quarantine, and
+    /// existing ones through `GIT_ALTERNATE_OBJECT_DIRECTORIES` pointing at the main object directory.
+    /// An inactive quarantine is the same as [`without_quarantine()`](Self::without_quarantine()).
+    pub fn with_quarantine(self, quarantine: &Quarantine) -> Self {
+        if !quarantine.is_active() {
+            return self.without_quarantine();
         }
+        let mut env = self.with_quarantine_path(&quarantine.objects_dir);
+        env.git_alternate_object_directories = vec![quarantine.main_objects_dir().to_owned()];
+        env
+    }
+
+    /// Remove the quarantine, once it was migrated or dropped.
+    pub fn without_quarantine(mut self) -> Self {
+        self.git_quarantine_path = None;
+        self.git_alternate_object_directories.clear();
         self
     }
 
@@ -89,6 +103,22 @@ impl HookEnvironment {
         self
     }
 
+    /// Pass the path of the file [`NewCommits::write_to()`](super::NewCommits::write_to()) wrote to hooks.
+    ///
+    /// Hooks find it in [`GIX_RECEIVE_NEW_COMMITS`](super::commits::ENV_VAR).
+    pub fn with_new_commits_file(self, path: impl AsRef<std::path::Path>) -> Self {
+        let path = path.as_ref().to_string_lossy().into_owned();
+        self.with_var(super::commits::ENV_VAR, path)
+    }
+
+    /// Pass the certificate of a signed push to hooks as `GIT_PUSH_CERT` and the other variables git sets for it.
+    pub fn with_push_cert(self, push_cert: &PushCertRecord) -> Self {
+        push_cert
+            .env_vars()
+            .into_iter()
+            .fold(self, |env, (key, value)| env.with_var(key, value))
+    }
+
     /// Add an additional environment variable.
     pub fn with_var(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
         self.additional_vars.insert(key.into(), value.into());
@@ -108,12 +138,19 @@ impl HookEnvironment {
         
         env.insert("GIT_DIR".to_string(), git_dir.to_string_lossy().to_string());
 
-        // GIT_QUARANTINE_PATH (optional, when quarantine is active)
+        // GIT_QUARANTINE_PATH and the object directories (optional, when quarantine is active)
         if let Some(quarantine_path) = self.git_quarantine_path {
-            env.insert(
-                "GIT_QUARANTINE_PATH".to_string(),
-                quarantine_path.to_string_lossy().to_string(),
-            );
+            let quarantine_path = quarantine_path.to_string_lossy().to_string();
+            env.insert("GIT_OBJECT_DIRECTORY".to_string(), quarantine_path.clone());
+            env.insert("GIT_QUARANTINE_PATH".to_string(), quarantine_path);
+            if !self.git_alternate_object_directories.is_empty() {
+                let alternates = std::env::join_paths(&self.git_alternate_object_directories)
+                    .map_err(|err| Error::environment_setup(&format!("invalid alternate object directory: {err}")))?;
+                env.insert(
+                    "GIT_ALTERNATE_OBJECT_DIRECTORIES".to_string(),
+                    alternates.to_string_lossy().to_string(),
+                );
+            }
         }
 
         // Push options
@@ -200,6 +237,7 @@ mod tests {
 
         assert_eq!(env.get("GIT_DIR"), Some(&"/path/to/repo/.git".to_string()));
         assert_eq!(env.get("GIT_QUARANTINE_PATH"), Some(&"/path/to/quarantine".to_string()));
+        assert_eq!(env.get("GIT_OBJECT_DIRECTORY"), Some(&"/path/to/quarantine".to_string()));
     }
 
     #[test]
@@ -249,6 +287,17 @@ mod tests {
         assert_eq!(env.get("SESSION_ID"), Some(&"abc123".to_string()));
     }
 
+    #[test]
+    fn hook_environment_with_new_commits_file() {
+        let env = HookEnvironment::new()
+            .with_git_dir("/path/to/repo/.git")
+            .with_new_commits_file("/path/to/new-commits")
+            .build()
+            .unwrap();
+
+        assert_eq!(env.get("GIX_RECEIVE_NEW_COMMITS"), Some(&"/path/to/new-commits".to_string()));
+    }
+
     #[test]
     fn hook_environment_missing_git_dir_fails() {
         let result = HookEnvironment::new().build();
@@ -297,7 +346,7 @@ mod tests {
     #[test]
     fn hook_environment_with_quarantine_instance() {
         // Test with inactive quarantine
-        let quarantine = Quarantine::new("/path/to/repo/.git/objects");
+        let quarantine = Quarantine::new("/path/to/repo/.git/objects".into());
         let env = HookEnvironment::new()
             .with_git_dir("/path/to/repo/.git")
             .with_quarantine(&quarantine)
@@ -308,7 +357,7 @@ mod tests {
         assert!(!env.contains_key("GIT_QUARANTINE_PATH")); // Should not be set for inactive quarantine
 
         // Test with active quarantine
-        let active_quarantine = Quarantine::new("/path/to/repo/.git/objects");
+        let active_quarantine = Quarantine::new("/path/to/repo/.git/objects".into());
         // We can't actually activate it in tests without filesystem operations,
         // but we can test the logic by manually setting the path
         let env = HookEnvironment::new()
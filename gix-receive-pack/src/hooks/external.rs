@@ -5,7 +5,9 @@
 //! is enabled.
 
 use super::{Hooks, HookDecision, env::HookEnvironment};
-use crate::protocol::CommandUpdate;
+use crate::config::HookConfig;
+use crate::pack::Quarantine;
+use crate::protocol::{CommandUpdate, PushCertRecord};
 use crate::Error;
 use std::collections::HashMap;
 use std::io::Read;
@@ -39,6 +41,21 @@ pub struct ExternalHookConfig {
     pub max_output_size: usize,
     /// Whether to enable sideband relay for hook output
     pub enable_sideband_relay: bool,
+    /// Maximum bytes of hook output relayed per second, or `None` for no limit
+    pub relay_bytes_per_second: Option<u64>,
+}
+
+impl ExternalHookConfig {
+    /// Run the hooks in `hooks_dir` with the limits and relay settings of `config`.
+    pub fn from_hook_config(hooks_dir: impl Into<PathBuf>, config: &HookConfig) -> Self {
+        Self {
+            hooks_dir: hooks_dir.into(),
+            timeout: config.timeout(),
+            max_output_size: config.max_output_size(),
+            enable_sideband_relay: config.is_sideband_relay_enabled(),
+            relay_bytes_per_second: config.relay_bytes_per_second,
+        }
+    }
 }
 
 impl Default for ExternalHookConfig {
@@ -48,6 +65,7 @@ impl Default for ExternalHookConfig {
             timeout: Duration::from_secs(30),
             max_output_size: 1024 * 1024, // 1MB
             enable_sideband_relay: false,
+            relay_bytes_per_second: None,
         }
     }
 }
@@ -74,7 +92,7 @@ pub struct HookResult {
 pub struct ExternalHooks {
     config: ExternalHookConfig,
     environment: HookEnvironment,
-    sideband_writer: Option<Box<dyn SidebandWriter>>,
+    sideband_writer: Option<Box<dyn SidebandWriter + Send>>,
 }
 
 impl ExternalHooks {
@@ -93,7 +111,7 @@ impl ExternalHooks {
     }
 
     /// Create a new external hooks executor with sideband writer.
-    pub fn with_sideband_writer<W: SidebandWriter + 'static>(
+    pub fn with_sideband_writer<W: SidebandWriter + Send + 'static>(
         config: ExternalHookConfig, 
         environment: HookEnvironment,
         sideband_writer: W,
@@ -104,13 +122,30 @@ impl ExternalHooks {
             sideband_writer: Some(Box::new(sideband_writer)),
         }
     }
+    /// Whether hook output is relayed to the client while hooks run.
+    fn relays_output(&self) -> bool {
+        self.config.enable_sideband_relay && self.sideband_writer.is_some()
+    }
+
     /// Relay data to sideband writer if available and enabled.
-    fn relay_to_sideband(&mut self, data: &[u8]) {
-        if self.config.enable_sideband_relay {
-            if let Some(ref mut writer) = self.sideband_writer {
-                let _ = writer.write_chunk(data); // Best effort, don't fail on sideband errors
+    ///
+    /// With a relay rate limit, this waits until relaying `data` stays within it, given that `relayed` bytes were
+    /// relayed since `start_time` already. Waiting ends once `deadline` is reached.
+    fn relay_to_sideband(&mut self, data: &[u8], relayed: &mut u64, start_time: Instant, deadline: Instant) {
+        if !self.relays_output() {
+            return;
+        }
+        if let Some(rate) = self.config.relay_bytes_per_second.filter(|rate| *rate > 0) {
+            let due = start_time + Duration::from_secs_f64(*relayed as f64 / rate as f64);
+            let now = Instant::now();
+            if due > now {
+                std::thread::sleep(due.min(deadline).saturating_duration_since(now));
             }
         }
+        if let Some(ref mut writer) = self.sideband_writer {
+            let _ = writer.write_chunk(data); // Best effort, don't fail on sideband errors
+        }
+        *relayed += data.len() as u64;
     }
 
     /// Flush sideband writer if available.
@@ -164,6 +199,9 @@ impl ExternalHooks {
     /// Execute the hook using gix-command.
     ///
     /// This executes the hook script with proper timeout, output limits, and environment setup.
+    /// Standard output and error are read as the hook writes them, and relayed in that order if enabled,
+    /// so the client sees the output of long-running hooks while they run. Like in git, hooks don't have to
+    /// read their input. Hooks running into the timeout or output limit are killed.
     fn execute_with_gix_command(
         &mut self,
         hook_path: &PathBuf,
@@ -173,8 +211,11 @@ impl ExternalHooks {
     ) -> Result<HookResult, Error> {
         use std::process::Stdio;
         use std::io::Write;
+        use std::sync::mpsc;
         
         let start_time = Instant::now();
+        let deadline = start_time + self.config.timeout;
+        let hook_name = hook_path.file_name().unwrap_or_default().to_string_lossy().into_owned();
         
         // Prepare the command using gix-command
         let mut prepare = gix_command::prepare(hook_path)
@@ -192,46 +233,63 @@ impl ExternalHooks {
         let mut child = prepare.spawn()
             .map_err(|e| Error::Io(e))?;
         
-        // Write stdin data if provided
-        if let Some(data) = stdin_data {
-            if let Some(mut stdin) = child.stdin.take() {
-                stdin.write_all(data)
-                    .map_err(|e| Error::Io(e))?;
-                // Close stdin to signal EOF
-                drop(stdin);
-            }
+        // Read both streams as the hook writes them, with a bounded channel so a hook can't get ahead of the relay
+        let (sender, chunks) = mpsc::sync_channel(4);
+        if let Some(stdout) = child.stdout.take() {
+            spawn_reader(stdout, Stream::Stdout, sender.clone());
+        }
+        if let Some(stderr) = child.stderr.take() {
+            spawn_reader(stderr, Stream::Stderr, sender.clone());
+        }
+        drop(sender);
+        
+        // Write stdin data if provided, ignoring hooks that exit without reading it
+        if let (Some(data), Some(mut stdin)) = (stdin_data, child.stdin.take()) {
+            let data = data.to_vec();
+            std::thread::spawn(move || {
+                let _ = stdin.write_all(&data);
+                // Dropping stdin closes it to signal EOF
+            });
         }
         
         // Collect output with size limits and optional sideband relay
         let mut stdout_buffer = Vec::new();
         let mut stderr_buffer = Vec::new();
         let mut total_output_size = 0;
-        
-        // Read stdout
-        if let Some(mut stdout) = child.stdout.take() {
-            self.read_stream_with_limits(
-                &mut stdout,
-                &mut stdout_buffer,
-                &mut total_output_size,
-                start_time,
-                hook_path,
-            )?;
-        }
-        
-        // Read stderr
-        if let Some(mut stderr) = child.stderr.take() {
-            self.read_stream_with_limits(
-                &mut stderr,
-                &mut stderr_buffer,
-                &mut total_output_size,
-                start_time,
-                hook_path,
-            )?;
-        }
+        let mut relayed = 0;
+        let outcome = loop {
+            let chunk = match chunks.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                Ok((_, Err(e))) => break Err(Error::Io(e)),
+                Ok((stream, Ok(chunk))) => (stream, chunk),
+                Err(mpsc::RecvTimeoutError::Timeout) => {
+                    break Err(Error::hook_timeout(&hook_name, self.config.timeout.as_secs(), None))
+                }
+                Err(mpsc::RecvTimeoutError::Disconnected) => break Ok(()),
+            };
+            let (stream, chunk) = chunk;
+            let remaining = self.config.max_output_size.saturating_sub(total_output_size);
+            total_output_size += chunk.len();
+            if chunk.len() > remaining {
+                // Relay what fits, as the client won't see the rest
+                self.relay_to_sideband(&chunk[..remaining], &mut relayed, start_time, deadline);
+                break Err(Error::hook_output_exceeded(&hook_name, self.config.max_output_size, None));
+            }
+            self.relay_to_sideband(&chunk, &mut relayed, start_time, deadline);
+            match stream {
+                Stream::Stdout => stdout_buffer.extend_from_slice(&chunk),
+                Stream::Stderr => stderr_buffer.extend_from_slice(&chunk),
+            }
+        };
         
         // Flush sideband writer if available
         self.flush_sideband();
         
+        if let Err(err) = outcome {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(err);
+        }
+        
         // Wait for the process to complete
         let exit_status = child.wait()
             .map_err(|e| Error::Io(e))?;
@@ -240,11 +298,7 @@ impl ExternalHooks {
         
         // Final timeout check
         if duration > self.config.timeout {
-            return Err(Error::hook_timeout(
-                &hook_path.file_name().unwrap_or_default().to_string_lossy(), 
-                self.config.timeout.as_secs(), 
-                None
-            ));
+            return Err(Error::hook_timeout(&hook_name, self.config.timeout.as_secs(), None));
         }
         
         Ok(HookResult {
@@ -256,61 +310,6 @@ impl ExternalHooks {
         })
     }
 
-    /// Read from a stream with size limits and timeout checks.
-    fn read_stream_with_limits(
-        &mut self,
-        stream: &mut dyn Read,
-        buffer: &mut Vec<u8>,
-        total_output_size: &mut usize,
-        start_time: Instant,
-        hook_path: &PathBuf,
-    ) -> Result<(), Error> {
-        let mut read_buffer = [0u8; 8192];
-        loop {
-            match stream.read(&mut read_buffer) {
-                Ok(0) => break, // EOF
-                Ok(n) => {
-                    let chunk = &read_buffer[..n];
-                    
-                    // Relay to sideband if available and enabled
-                    self.relay_to_sideband(chunk);
-                    
-                    // Always capture for return value (with size limits)
-                    *total_output_size += n;
-                    if *total_output_size > self.config.max_output_size {
-                        // Truncate and add marker
-                        let remaining = self.config.max_output_size.saturating_sub(buffer.len());
-                        if remaining > 0 {
-                            buffer.extend_from_slice(&chunk[..remaining.min(n)]);
-                        }
-                        let truncated_msg = format!("\n... [truncated: output exceeded {} bytes]", self.config.max_output_size);
-                        buffer.extend_from_slice(truncated_msg.as_bytes());
-                        return Err(Error::hook_output_exceeded(
-                            &hook_path.file_name().unwrap_or_default().to_string_lossy(),
-                            self.config.max_output_size,
-                            None
-                        ));
-                    }
-                    buffer.extend_from_slice(chunk);
-                }
-                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => continue,
-                Err(e) => return Err(Error::Io(e)),
-            }
-            
-            // Check timeout
-            if start_time.elapsed() > self.config.timeout {
-                return Err(Error::hook_timeout(
-                    &hook_path.file_name().unwrap_or_default().to_string_lossy(),
-                    self.config.timeout.as_secs(),
-                    None
-                ));
-            }
-        }
-        Ok(())
-    }
-
-
-
     /// Format command for hook input (old new refname format).
     fn format_command_for_hook(command: &CommandUpdate) -> String {
         match command {
@@ -349,6 +348,52 @@ impl ExternalHooks {
     }
 }
 
+/// The stream of a hook that output was read from.
+#[derive(Debug, Clone, Copy)]
+enum Stream {
+    Stdout,
+    Stderr,
+}
+
+/// Read `output` of a hook in a thread, sending each chunk to `chunks` as soon as it was read.
+fn spawn_reader(
+    mut output: impl Read + Send + 'static,
+    stream: Stream,
+    chunks: std::sync::mpsc::SyncSender<(Stream, std::io::Result<Vec<u8>>)>,
+) {
+    std::thread::spawn(move || {
+        let mut buf = [0u8; 8192];
+        loop {
+            let chunk = match output.read(&mut buf) {
+                Ok(0) => break, // EOF
+                Ok(n) => Ok(buf[..n].to_vec()),
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(e) => Err(e),
+            };
+            let failed = chunk.is_err();
+            if chunks.send((stream, chunk)).is_err() || failed {
+                break;
+            }
+        }
+    });
+}
+
+/// Hook output is relayed as progress, split into as many pkt-lines as needed.
+#[cfg(feature = "progress")]
+impl<W: std::io::Write> SidebandWriter for crate::progress::SidebandProgressWriter<W> {
+    fn write_chunk(&mut self, data: &[u8]) -> std::io::Result<()> {
+        // A pkt-line holds 65516 bytes of data, one of which is the band.
+        for chunk in data.chunks(65515) {
+            self.emit_progress(chunk)?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        crate::progress::SidebandProgressWriter::flush(self)
+    }
+}
+
 impl Hooks for ExternalHooks {
     fn update(&mut self, command: &CommandUpdate) -> Result<HookDecision, Error> {
         let args = match command {
@@ -364,28 +409,42 @@ impl Hooks for ExternalHooks {
         };
 
         let result = self.execute_hook("update", &args, None)?;
-        Ok(Self::hook_result_to_decision(result, "update"))
+        Ok(Self::hook_result_to_decision(result, "update").with_relayed(self.relays_output()))
     }
 
     fn pre_receive(&mut self, commands: &[CommandUpdate]) -> Result<HookDecision, Error> {
-        // Format all commands for stdin
-        let stdin_data = commands
+        // Format all commands for stdin, each on a line of its own like git does
+        let stdin_data: String = commands
             .iter()
-            .map(Self::format_command_for_hook)
-            .collect::<Vec<_>>()
-            .join("\n");
+            .map(|command| Self::format_command_for_hook(command) + "\n")
+            .collect();
 
         let result = self.execute_hook("pre-receive", &[], Some(stdin_data.as_bytes()))?;
-        Ok(Self::hook_result_to_decision(result, "pre-receive"))
+        Ok(Self::hook_result_to_decision(result, "pre-receive").with_relayed(self.relays_output()))
+    }
+
+    fn push_options(&mut self, push_options: &[String]) {
+        self.environment.push_options = push_options.to_vec();
+    }
+
+    fn push_cert(&mut self, push_cert: &PushCertRecord) {
+        self.environment = std::mem::take(&mut self.environment).with_push_cert(push_cert);
+    }
+
+    fn quarantine(&mut self, quarantine: Option<&Quarantine>) {
+        let environment = std::mem::take(&mut self.environment);
+        self.environment = match quarantine {
+            Some(quarantine) => environment.with_quarantine(quarantine),
+            None => environment.without_quarantine(),
+        };
     }
 
     fn post_receive(&mut self, commands: &[CommandUpdate]) -> Result<(), Error> {
-        // Format all commands for stdin
-        let stdin_data = commands
+        // Format all commands for stdin, each on a line of its own like git does
+        let stdin_data: String = commands
             .iter()
-            .map(Self::format_command_for_hook)
-            .collect::<Vec<_>>()
-            .join("\n");
+            .map(|command| Self::format_command_for_hook(command) + "\n")
+            .collect();
 
         let _result = self.execute_hook("post-receive", &[], Some(stdin_data.as_bytes()))?;
         // Post-receive is fire-and-forget, so we don't check the result
@@ -462,6 +521,64 @@ mod tests {
         assert!(hooks.config.timeout > Duration::from_secs(0));
     }
 
+    #[test]
+    fn external_hooks_pass_push_options_to_hooks() {
+        let mut hooks = ExternalHooks::with_defaults(create_test_environment());
+        hooks.push_options(&["ci.skip".to_string(), "reviewer=a".to_string()]);
+        
+        let env = hooks.environment.clone().build().unwrap();
+        assert_eq!(env.get("GIT_PUSH_OPTION_COUNT"), Some(&"2".to_string()));
+        assert_eq!(env.get("GIT_PUSH_OPTION_0"), Some(&"ci.skip".to_string()));
+        assert_eq!(env.get("GIT_PUSH_OPTION_1"), Some(&"reviewer=a".to_string()));
+    }
+
+    #[test]
+    fn external_hooks_pass_push_certs_to_hooks() {
+        use crate::protocol::{NonceCheck, NonceStatus, PushCertificate};
+
+        let certificate = PushCertificate::parse("certificate version 0.1\nnonce 1-abc\n\n").unwrap();
+        let mut hooks = ExternalHooks::with_defaults(create_test_environment());
+        hooks.push_cert(&PushCertRecord {
+            id: gix_hash::ObjectId::empty_blob(gix_hash::Kind::Sha1),
+            certificate,
+            nonce: NonceCheck {
+                nonce: Some("2-def".into()),
+                status: NonceStatus::Slop,
+                slop: Some(1),
+            },
+        });
+
+        let env = hooks.environment.clone().build().unwrap();
+        let var = |name: &str| env.get(name).map(String::as_str);
+        assert_eq!(var("GIT_PUSH_CERT"), Some("e69de29bb2d1d6434b8b29ae775ad8c2e48c5391"));
+        assert_eq!(var("GIT_PUSH_CERT_STATUS"), Some("N"));
+        assert_eq!(var("GIT_PUSH_CERT_NONCE"), Some("2-def"));
+        assert_eq!(var("GIT_PUSH_CERT_NONCE_STATUS"), Some("SLOP"));
+        assert_eq!(var("GIT_PUSH_CERT_NONCE_SLOP"), Some("1"));
+        assert_eq!(var("GIT_PUSH_OPTION_0"), Some("test=true"), "other variables are kept");
+    }
+
+    #[test]
+    fn external_hooks_see_the_quarantine_until_it_is_removed() {
+        let tmp = gix_testtools::tempfile::tempdir().unwrap();
+        let mut quarantine = Quarantine::new(tmp.path().join("objects"));
+        quarantine.activate().unwrap();
+        let mut hooks = ExternalHooks::with_defaults(create_test_environment());
+        hooks.quarantine(Some(&quarantine));
+
+        let env = hooks.environment.clone().build().unwrap();
+        let var = |name: &str| env.get(name).map(PathBuf::from);
+        assert_eq!(var("GIT_OBJECT_DIRECTORY"), Some(quarantine.objects_dir.clone()));
+        assert_eq!(var("GIT_QUARANTINE_PATH"), Some(quarantine.objects_dir.clone()));
+        assert_eq!(var("GIT_ALTERNATE_OBJECT_DIRECTORIES"), Some(tmp.path().join("objects")));
+
+        hooks.quarantine(None);
+        let env = hooks.environment.clone().build().unwrap();
+        for name in ["GIT_OBJECT_DIRECTORY", "GIT_QUARANTINE_PATH", "GIT_ALTERNATE_OBJECT_DIRECTORIES"] {
+            assert!(!env.contains_key(name), "{name} is removed along with the quarantine");
+        }
+    }
+
     #[test]
     fn external_hooks_with_defaults() {
         let env = create_test_environment();
@@ -586,6 +703,122 @@ mod tests {
         assert_eq!(writer_clone.get_flush_count(), 0);
     }
 
+    /// Hooks relaying to a new mock writer, running the `update` hook `script` from a temporary directory.
+    #[cfg(unix)]
+    fn hooks_running(
+        script: &str,
+        config: ExternalHookConfig,
+    ) -> (gix_testtools::tempfile::TempDir, ExternalHooks, MockSidebandWriter) {
+        use std::os::unix::fs::PermissionsExt;
+
+        let tmp = gix_testtools::tempfile::tempdir().unwrap();
+        let hook = tmp.path().join("update");
+        std::fs::write(&hook, format!("#!/bin/sh\n{script}")).unwrap();
+        std::fs::set_permissions(&hook, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let writer = MockSidebandWriter::new();
+        let config = ExternalHookConfig {
+            hooks_dir: tmp.path().into(),
+            enable_sideband_relay: true,
+            ..config
+        };
+        let hooks = ExternalHooks::with_sideband_writer(config, create_test_environment(), writer.clone());
+        (tmp, hooks, writer)
+    }
+
+    #[cfg(unix)]
+    fn create_command() -> CommandUpdate {
+        CommandUpdate::Create {
+            new: ObjectId::null(gix_hash::Kind::Sha1),
+            name: "refs/heads/test".to_string(),
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn external_hooks_relay_output_as_it_is_written() {
+        // Without reading both streams at once, the hook would block on the full stderr pipe.
+        let script = "head -c 200000 /dev/zero | tr '\\0' e >&2\necho out\nexit 1\n";
+        let (_tmp, mut hooks, writer) = hooks_running(script, ExternalHookConfig::default());
+
+        let decision = hooks.update(&create_command()).unwrap();
+        assert!(!decision.allowed);
+        assert!(decision.relayed, "the engine must not send the output again");
+        assert_eq!(decision.stderr.len(), 200_000);
+        assert_eq!(decision.stdout, b"out\n");
+        let relayed = writer.get_data();
+        assert_eq!(relayed.len(), 200_004);
+        assert_eq!(relayed.iter().filter(|b| **b == b'e').count(), 200_000);
+        assert!(relayed.windows(4).any(|w| w == b"out\n"), "both streams are relayed");
+        assert_eq!(writer.get_flush_count(), 1);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn external_hooks_relay_up_to_the_output_limit() {
+        let config = ExternalHookConfig {
+            max_output_size: 10,
+            ..Default::default()
+        };
+        let (_tmp, mut hooks, writer) = hooks_running("echo 0123456789abcdef\nsleep 30\n", config);
+
+        let started = Instant::now();
+        let err = hooks.update(&create_command()).unwrap_err();
+        assert!(err.to_string().contains("output exceeded"), "{err}");
+        assert!(started.elapsed() < Duration::from_secs(10), "the hook is killed");
+        assert_eq!(writer.get_data(), b"0123456789");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn external_hooks_relay_at_most_at_the_configured_rate() {
+        let config = ExternalHookConfig {
+            relay_bytes_per_second: Some(2000),
+            ..Default::default()
+        };
+        let (_tmp, mut hooks, writer) = hooks_running("head -c 1000 /dev/zero\nsleep 0.1\necho done\n", config);
+
+        let started = Instant::now();
+        let decision = hooks.update(&create_command()).unwrap();
+        assert!(decision.allowed);
+        assert!(
+            started.elapsed() >= Duration::from_millis(500),
+            "the second write waits until the first one was relayed at 2000 bytes/s"
+        );
+        assert_eq!(writer.get_data().len(), 1005);
+    }
+
+    #[test]
+    fn external_hook_config_from_hook_config() {
+        let hook_config = HookConfig {
+            timeout_ms: 5000,
+            relay_bytes_per_second: Some(1024),
+            ..HookConfig::new()
+        };
+        let config = ExternalHookConfig::from_hook_config("/repo/hooks", &hook_config);
+        assert_eq!(config.hooks_dir, PathBuf::from("/repo/hooks"));
+        assert_eq!(config.timeout, Duration::from_secs(5));
+        assert_eq!(config.max_output_size, 1024 * 1024);
+        assert!(config.enable_sideband_relay);
+        assert_eq!(config.relay_bytes_per_second, Some(1024));
+    }
+
+    #[cfg(feature = "progress")]
+    #[test]
+    fn sideband_progress_writers_relay_hook_output_on_the_progress_band() {
+        let mut out = Vec::new();
+        let mut writer = crate::progress::SidebandProgressWriter::new(&mut out);
+        SidebandWriter::write_chunk(&mut writer, &[b'x'; 70_000]).unwrap();
+
+        let mut lines = Vec::new();
+        let mut rest = &out[..];
+        while !rest.is_empty() {
+            let len = usize::from_str_radix(std::str::from_utf8(&rest[..4]).unwrap(), 16).unwrap();
+            lines.push((rest[4], len - 5));
+            rest = &rest[len..];
+        }
+        assert_eq!(lines, [(2, 65515), (2, 70_000 - 65515)], "output is split to fit into pkt-lines");
+    }
+
     #[test]
     fn external_hooks_sideband_relay_disabled() {
         let env = create_test_environment();
@@ -5,10 +5,17 @@
 //! for testing and minimal configurations.
 //!
 //! The hook system follows Git's receive-pack hook model:
-//! - `pre_receive`: Runs once with all commands before policy evaluation
+//! - `pre_receive`: Runs once with all commands before policy evaluation, optionally with the commits
+//!   they introduce (see [`commits`])
 //! - `update`: Runs per-command after policy evaluation
 //! - `post_receive`: Runs once after successful ref updates
 //!
+//! Push options sent by the client are passed to [`Hooks::push_options()`] before any hook runs, which external
+//! hooks receive as `GIT_PUSH_OPTION_COUNT` and `GIT_PUSH_OPTION_<n>`, like in git. The certificate of a signed push
+//! is passed to [`Hooks::push_cert()`] likewise, which external hooks receive as
+//! `GIT_PUSH_CERT` and the other variables git sets for it. While the pushed objects are quarantined, the quarantine
+//! is passed to [`Hooks::quarantine()`], so `pre-receive` can inspect them.
+//!
 //! # Feature Gates
 //!
 //! - `hooks-external`: Enables external process execution via gix-command
@@ -38,15 +45,18 @@
 //! assert!(decision.allowed);
 //! ```
 
-use crate::protocol::CommandUpdate;
+use crate::pack::Quarantine;
+use crate::protocol::{CommandUpdate, PushCertRecord};
 use crate::Error;
 
 pub mod noop;
 #[cfg(feature = "hooks-external")]
 pub mod external;
 pub mod env;
+pub mod commits;
 
 pub use noop::NoopHooks;
+pub use commits::{NewCommits, RefCommits};
 #[cfg(feature = "hooks-external")]
 pub use external::{ExternalHooks, SidebandWriter, ExternalHookConfig, HookResult};
 
@@ -63,6 +73,8 @@ pub struct HookDecision {
     pub stderr: Vec<u8>,
     /// Human-readable message explaining the decision.
     pub message: String,
+    /// Whether `stdout` and `stderr` were relayed to the client already while the hook ran.
+    pub relayed: bool,
 }
 
 impl HookDecision {
@@ -74,6 +86,7 @@ impl HookDecision {
             stdout: Vec::new(),
             stderr: Vec::new(),
             message: String::new(),
+            relayed: false,
         }
     }
 
@@ -85,6 +98,7 @@ impl HookDecision {
             stdout,
             stderr,
             message: String::new(),
+            relayed: false,
         }
     }
 
@@ -96,6 +110,7 @@ impl HookDecision {
             stdout: Vec::new(),
             stderr: Vec::new(),
             message: message.into(),
+            relayed: false,
         }
     }
 
@@ -112,8 +127,15 @@ impl HookDecision {
             stdout,
             stderr,
             message: message.into(),
+            relayed: false,
         }
     }
+
+    /// Mark the output of the hook as `relayed` to the client while it ran, so it's not sent again.
+    pub fn with_relayed(mut self, relayed: bool) -> Self {
+        self.relayed = relayed;
+        self
+    }
 }
 
 /// Trait for hook execution during receive-pack operations.
@@ -151,6 +173,60 @@ pub trait Hooks {
     /// A `HookDecision` indicating whether to allow or deny the entire push.
     fn pre_receive(&mut self, commands: &[CommandUpdate]) -> Result<HookDecision, Error>;
 
+    /// Execute the pre-receive hook with all commands and the commits they introduce.
+    ///
+    /// Hooks checking commits, like commit message lints, can use `new_commits` instead of walking the
+    /// history themselves. The default implementation ignores them and calls [`pre_receive()`](Self::pre_receive()).
+    ///
+    /// # Arguments
+    /// * `commands` - All commands in the push operation
+    /// * `new_commits` - The commits each command introduces, see [`NewCommits::compute()`]
+    fn pre_receive_with_commits(
+        &mut self,
+        commands: &[CommandUpdate],
+        new_commits: &NewCommits,
+    ) -> Result<HookDecision, Error> {
+        let _ = new_commits;
+        self.pre_receive(commands)
+    }
+
+    /// Receive the push options the client sent, before any hook of its push runs.
+    ///
+    /// Clients only send push options if the `push-options` capability was advertised, see
+    /// `receive.advertisePushOptions`. Hooks can take them into account when deciding on updates, like
+    /// skipping checks for `ci.skip`. The default implementation ignores them.
+    ///
+    /// # Arguments
+    /// * `push_options` - The push options in the order the client sent them
+    fn push_options(&mut self, push_options: &[String]) {
+        let _ = push_options;
+    }
+
+    /// Receive the certificate of a signed push once it was stored, before any hook of its push runs.
+    ///
+    /// Clients only sign pushes if `push-cert` was advertised, see `receive.certNonceSeed`. The record tells how the
+    /// nonce of the certificate compares to the issued one, while verifying its signature is left to the hooks.
+    /// The default implementation ignores it.
+    ///
+    /// # Arguments
+    /// * `push_cert` - The certificate along with the blob it was stored as and its checked nonce
+    fn push_cert(&mut self, push_cert: &PushCertRecord) {
+        let _ = push_cert;
+    }
+
+    /// Receive the quarantine holding the objects of the push before `pre-receive` runs, and `None` once it was
+    /// migrated into the main object database or dropped.
+    ///
+    /// Hooks running in between can only see the pushed objects through the quarantine, which external hooks receive
+    /// as `GIT_OBJECT_DIRECTORY`, `GIT_ALTERNATE_OBJECT_DIRECTORIES` and `GIT_QUARANTINE_PATH`, like in git.
+    /// The default implementation ignores it.
+    ///
+    /// # Arguments
+    /// * `quarantine` - The active quarantine, or `None` if there is none anymore
+    fn quarantine(&mut self, quarantine: Option<&Quarantine>) {
+        let _ = quarantine;
+    }
+
     /// Execute the post-receive hook after successful updates.
     ///
     /// This hook is called once after all ref updates have been successfully
@@ -111,22 +111,27 @@ pub mod policy;
 // M5: Hook execution framework.
 pub mod hooks;
 
-// M5: Proc-receive protocol handler.
-pub mod proc_receive;
 // M5: Configuration parsing for policies, hooks, and proc-receive.
 pub mod config;
 // M6: Reference transaction planning and execution.
 pub mod refs;
+// M9: Post-receive repository maintenance (commit-graph).
+pub mod commit_graph;
+// M6: Worktree updates for `receive.denyCurrentBranch=updateInstead`.
+pub mod worktree;
+// M7: The end-to-end push flow of `ReceivePack::run()`.
+#[cfg(all(feature = "progress", feature = "blocking-io"))]
+pub mod engine;
 
 pub use protocol::{
-    Advertiser, AdvertisementConfig, CapabilityOrdering, CapabilitySet, CommandList, CommandUpdate, HiddenRefPredicate, Options, RefRecord, setup_advertiser_with_config,
+    Advertiser, AdvertisementConfig, AdvertisementLimits, CapabilityOrdering, CapabilitySet, CapabilityStrictness, CommandList, CommandUpdate, HeadInfoLimits, HiddenRefPredicate, Options, RefRecord, RefRewrite, RefRewrites, setup_advertiser_with_config,
 };
 pub use interrupt::{CancellationFlag, CancellationPoint};
 // M4: Re-exports for new modules
 pub use shallow::ShallowPlan;
 pub use connectivity::{ConnectivityChecker, DefaultConnectivityChecker};
 // M5: Re-exports for policy module
-pub use policy::{PolicySet, PolicyDecision, ReasonCode, UpdateInstead};
+pub use policy::{PolicySet, PolicyDecision, ReasonCode, UpdateInstead, TagPolicy, TagFinding, TagRule, Divergence, MirrorReport};
 // M5: Re-exports for hooks module
 pub use hooks::{Hooks, HookDecision, NoopHooks};
 #[cfg(feature = "hooks-external")]
@@ -140,6 +145,10 @@ pub use refs::{
     ConflictDetector, Conflict, ConflictType,
     SymrefResolver, AliasValidator, TransactionError
 };
+// M9: Re-exports for post-receive maintenance
+pub use commit_graph::{CommitGraphConfig, CommitGraphUpdate};
+// M6: Re-exports for worktree updates
+pub use worktree::{Refusal, SubmoduleChange, SubmoduleDirectory, WorktreeUpdate, WorktreeUpdater};
 
 use core::marker::PhantomData;
 use std::path::PathBuf;
@@ -193,9 +202,18 @@ pub enum Error {
     /// Fsck verification failed.
     #[error("fsck failed: {0}")]
     Fsck(String),
+    /// A maintenance operation was refused because it would delete protected objects.
+    #[error("maintenance refused: {0}")]
+    MaintenanceRefused(String),
+    /// The worktree wasn't updated instead of the current branch, so the ref update must be refused.
+    #[error("worktree update refused: {0}")]
+    WorktreeRefused(crate::worktree::Refusal),
     /// Comprehensive pack ingestion error with detailed context and recovery information.
     #[error("pack ingestion error: {0}")]
     PackIngestion(#[from] crate::error::PackIngestionError),
+    /// A thread doing work for the push panicked, with what it was doing and its panic message.
+    #[error("thread panicked: {0}")]
+    Panicked(String),
 }
 
 impl Error {
@@ -209,6 +227,9 @@ impl Error {
             Error::Resource(_) => Kind::Resource,
             Error::Cancelled => Kind::Cancelled,
             Error::Fsck(_) => Kind::Validation,
+            Error::MaintenanceRefused(_) => Kind::Permission,
+            Error::WorktreeRefused(_) => Kind::Validation,
+            Error::Panicked(_) => Kind::Bug,
             Error::PackIngestion(err) => match err.kind() {
                 crate::error::ErrorKind::Io => Kind::Io,
                 crate::error::ErrorKind::Protocol => Kind::Protocol,
@@ -365,6 +386,9 @@ impl Error {
             Error::Resource(msg) => format!("Resource error: {}\n\nThe operation exceeded resource limits. Please contact your administrator if you need higher limits.", msg),
             Error::Cancelled => "Operation was cancelled.\n\nThe operation was interrupted and can be safely retried.".to_string(),
             Error::Fsck(msg) => format!("Object validation failed: {}\n\nPlease check your objects for corruption and try again.", msg),
+            Error::MaintenanceRefused(msg) => format!("Maintenance refused: {}\n\nThe repository protects its objects from deletion. Run only non-destructive maintenance on it.", msg),
+            Error::WorktreeRefused(refusal) => format!("{}\n\nThe pushed branch is checked out. Commit or discard the changes in its worktree and push again.", refusal),
+            Error::Panicked(msg) => format!("Internal error: {}\n\nThe server failed unexpectedly. Please report this to your administrator.", msg),
         }
     }
 }
@@ -387,6 +411,42 @@ struct Config {
     max_pack_bytes: Option<u64>,
     /// Soft time budget for ingestion (seconds). None = unlimited.
     time_budget_secs: Option<u64>,
+    /// Abort ingestion if the client sends pack data slower than this (receive.lowSpeedLimit). None = unchecked.
+    stall_detection: Option<crate::pack::StallDetection>,
+    /// Abort ingestion if the client sends objects faster than this (receive.maxObjectsPerSecond). None = unlimited.
+    rate_limits: Option<crate::pack::RateLimits>,
+    /// Post-receive commit-graph update (receive.writeCommitGraph).
+    commit_graph: crate::commit_graph::CommitGraphConfig,
+    /// Multi-pack-index maintenance after index-pack ingestion (receive.updateMultiPackIndex).
+    midx_mode: crate::pack::MidxMode,
+    /// Record which objects each push introduced (receive.recordPushManifests).
+    push_manifests: bool,
+    /// How capabilities the client sent but we didn't advertise are treated.
+    capability_strictness: protocol::CapabilityStrictness,
+    /// Whether the bases of thin packs are looked up in the main object database.
+    thin_packs: crate::pack::ThinPacks,
+    /// Compression of loose objects and the space they may take (core.looseCompression, receive.maxLooseObjectsSize).
+    loose_objects: crate::pack::LooseObjects,
+    /// Refuse head-info exceeding these limits (receive.maxHeadInfoSize, receive.maxCommands and related).
+    head_info_limits: protocol::HeadInfoLimits,
+    /// The repository whose refs are advertised and updated by `run()`.
+    git_dir: Option<PathBuf>,
+    /// The hash of the objects and refs of the repository (extensions.objectFormat).
+    object_hash: gix_hash::Kind,
+    /// The capabilities advertised by `run()`.
+    advertisement: protocol::AdvertisementConfig,
+    /// The policies ref updates are checked against by `run()`.
+    policy: PolicySet,
+    /// Checks out updates of the current branch delegated by the policy (receive.denyCurrentBranch=updateInstead).
+    worktree: Option<WorktreeUpdater>,
+    /// Offer signed pushes with nonces issued by `run()` (receive.certNonceSeed, receive.certNonceSlop).
+    push_certs: Option<protocol::PushCertConfig>,
+    /// Refs left out of the advertisement of `run()`, whose updates are refused (transfer.hideRefs, receive.hideRefs).
+    hidden_refs: gix_serve_core::visibility::HiddenRefs,
+    /// The namespace whose refs are advertised and updated by `run()` (GIT_NAMESPACE).
+    namespace: Option<gix_ref::Namespace>,
+    /// Where `run()` reports counters and timings of pushes.
+    metrics: gix_serve_core::metrics::Meter,
 }
 
 /// Execution mode for receive-pack.
@@ -491,33 +551,294 @@ impl ReceivePackBuilder<state::Ready> {
         self
     }
 
+    /// Abort ingestion once the client sends pack data slower than `detection` allows (receive.lowSpeedLimit).
+    pub fn with_stall_detection(mut self, detection: impl Into<Option<crate::pack::StallDetection>>) -> Self {
+        self.cfg.stall_detection = detection.into();
+        self
+    }
+
+    /// Abort ingestion once the client sends bytes or objects faster than `limits` allow (receive.maxObjectsPerSecond).
+    pub fn with_rate_limits(mut self, limits: impl Into<Option<crate::pack::RateLimits>>) -> Self {
+        self.cfg.rate_limits = limits.into();
+        self
+    }
+
+    /// Update the commit-graph with newly received commits after a successful receive (receive.writeCommitGraph).
+    ///
+    /// Writing requires the `commit-graph` feature; without it the update is only reported as unavailable.
+    pub fn with_write_commit_graph(mut self, enabled: bool) -> Self {
+        self.cfg.commit_graph.enabled = enabled;
+        self
+    }
+
+    /// Configure multi-pack-index maintenance after a pack was added (receive.updateMultiPackIndex).
+    pub fn with_update_multi_pack_index(mut self, mode: crate::pack::MidxMode) -> Self {
+        self.cfg.midx_mode = mode;
+        self
+    }
+
+    /// Record the objects each push introduced as manifests for later forensics (receive.recordPushManifests).
+    pub fn with_push_manifests(mut self, enabled: bool) -> Self {
+        self.cfg.push_manifests = enabled;
+        self
+    }
+
+    /// Configure how capabilities the client sent but we didn't advertise are treated.
+    ///
+    /// Defaults to [`CapabilityStrictness::Strict`](protocol::CapabilityStrictness::Strict).
+    pub fn with_capability_strictness(mut self, strictness: protocol::CapabilityStrictness) -> Self {
+        self.cfg.capability_strictness = strictness;
+        self
+    }
+
+    /// Refuse pushes whose head-info exceeds `limits` (receive.maxHeadInfoSize, receive.maxCommands and related).
+    pub fn with_head_info_limits(mut self, limits: protocol::HeadInfoLimits) -> Self {
+        self.cfg.head_info_limits = limits;
+        self
+    }
+
+    /// Configure whether the bases of thin packs are looked up in the main object database.
+    ///
+    /// Servers advertising `no-thin` can use [`ThinPacks::Never`](crate::pack::ThinPacks::Never) to refuse thin packs.
+    pub fn with_thin_packs(mut self, mode: crate::pack::ThinPacks) -> Self {
+        self.cfg.thin_packs = mode;
+        self
+    }
+
+    /// Configure how loose objects are compressed when unpacking, and the space they may take before packs
+    /// within the unpack limit are indexed instead (core.looseCompression, receive.maxLooseObjectsSize).
+    pub fn with_loose_objects(mut self, loose: crate::pack::LooseObjects) -> Self {
+        self.cfg.loose_objects = loose;
+        self
+    }
+
+    /// Set the repository whose refs are advertised and updated, with its objects in `objects` unless
+    /// [configured otherwise](Self::with_objects_dir()).
+    pub fn with_git_dir(mut self, path: impl Into<PathBuf>) -> Self {
+        self.cfg.git_dir = Some(path.into());
+        self
+    }
+
+    /// Use `object_hash` for the objects and refs of the repository, as configured by its `extensions.objectFormat`,
+    /// instead of SHA-1.
+    pub fn with_object_hash(mut self, object_hash: gix_hash::Kind) -> Self {
+        self.cfg.object_hash = object_hash;
+        self
+    }
+
+    /// Configure the capabilities to advertise, like `atomic`, `push-options` or the agent (receive.advertiseAtomic,
+    /// receive.advertisePushOptions).
+    ///
+    /// Defaults to the capabilities of [`CapabilitySet::modern_defaults()`](protocol::CapabilitySet::modern_defaults())
+    /// without agent.
+    pub fn with_advertisement(mut self, config: protocol::AdvertisementConfig) -> Self {
+        self.cfg.advertisement = config;
+        self
+    }
+
+    /// Check ref updates against `policy` (receive.denyDeletes, receive.denyCurrentBranch and related).
+    pub fn with_policy(mut self, policy: PolicySet) -> Self {
+        self.cfg.policy = policy;
+        self
+    }
+
+    /// Check out updates of the current branch with `updater` if the policy delegates them to the worktree
+    /// (receive.denyCurrentBranch=updateInstead). Without it, such updates are refused.
+    pub fn with_worktree_updater(mut self, updater: impl Into<Option<WorktreeUpdater>>) -> Self {
+        self.cfg.worktree = updater.into();
+        self
+    }
+
+    /// Offer signed pushes by advertising `push-cert` with a nonce derived from the seed of `config`
+    /// (receive.certNonceSeed, receive.certNonceSlop).
+    ///
+    /// Certificates are stored as blobs and passed to [`Hooks::push_cert()`] along with the outcome of checking their
+    /// nonce, and they are recorded in [`RunOutcome::push_cert`]. Signatures aren't verified.
+    pub fn with_push_certs(mut self, config: impl Into<Option<protocol::PushCertConfig>>) -> Self {
+        self.cfg.push_certs = config.into();
+        self
+    }
+
+    /// Leave the refs matched by `hidden` out of the advertisement and refuse updating them, like git does with
+    /// `deny updating a hidden ref` (transfer.hideRefs, receive.hideRefs).
+    ///
+    /// Use [`HiddenRefs::from_config()`](gix_serve_core::visibility::HiddenRefs::from_config()) with the `receive`
+    /// section to hide the refs configured for the repository.
+    pub fn with_hidden_refs(mut self, hidden: gix_serve_core::visibility::HiddenRefs) -> Self {
+        self.cfg.hidden_refs = hidden;
+        self
+    }
+
+    /// Serve only the refs of `namespace` as if they were all refs, like git does with `GIT_NAMESPACE`.
+    ///
+    /// Refs below `refs/namespaces/<namespace>/` are advertised and updated by the names commands use without that
+    /// prefix, and the objects of all other refs are advertised as `.have`. Use
+    /// [`gix_ref::namespace::expand()`] to turn a namespace like `a/b` into the prefix of its refs.
+    pub fn with_namespace(mut self, namespace: impl Into<Option<gix_ref::Namespace>>) -> Self {
+        self.cfg.namespace = namespace.into();
+        self
+    }
+
+    /// Report counters and timings of the pushes served by [`run()`](ReceivePack::run()) to `meter`, like their
+    /// duration, the bytes exchanged, and the size of their packs.
+    pub fn with_metrics(mut self, meter: gix_serve_core::metrics::Meter) -> Self {
+        self.cfg.metrics = meter;
+        self
+    }
+
     /// Finalize the builder and obtain a ReceivePack instance.
     ///
     /// This does no I/O and validates configuration.
-    pub fn build(self) -> ReceivePack {
+    pub fn build(mut self) -> ReceivePack {
+        if self.cfg.objects_dir.is_none() {
+            self.cfg.objects_dir = self.cfg.git_dir.as_ref().map(|git_dir| git_dir.join("objects"));
+        }
         ReceivePack { cfg: self.cfg }
     }
 }
 
 /// Receive-pack engine.
 ///
-/// This struct will orchestrate negotiation, command execution, object verification,
-/// update application, and reporting. For now, it is only a skeleton that compiles.
+/// [`run()`](Self::run()) serves a whole push, while the other methods give access to its parts, like pack
+/// ingestion, for servers composing them differently.
 #[derive(Debug, Clone)]
 pub struct ReceivePack {
     cfg: Config,
 }
 
+/// What happened while receiving a pack, beyond the objects themselves.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReceiveOutcome {
+    /// The path the pack was ingested with.
+    pub ingest_path: crate::pack::PackIngestPath,
+    /// Why the pack was unpacked into loose objects or indexed.
+    pub unpack: crate::pack::UnpackDecision,
+    /// The multi-pack-index decision taken after the pack was migrated, and its result.
+    pub midx: crate::pack::MidxUpdate,
+    /// Whether push manifests were recorded for the new objects.
+    pub manifest: crate::pack::ManifestRecord,
+    /// Whether the pack was thin, i.e. had deltas against bases from the main object database.
+    pub thin: bool,
+    /// The rates at which bytes and objects were received, for anomaly detection.
+    pub rates: crate::pack::IngestionRates,
+}
+
+/// What [`ReceivePack::run()`] did.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RunOutcome {
+    /// The commands whose refs were updated, in the order they were applied.
+    pub applied: Vec<protocol::CommandUpdate>,
+    /// The statuses of the push, or `None` if the client had nothing to push.
+    pub report: Option<protocol::machine::Report>,
+    /// The certificate of a signed push, with the blob it was stored as, for auditing.
+    pub push_cert: Option<protocol::PushCertRecord>,
+    /// The bytes exchanged with the client.
+    pub wire: gix_serve_core::wire::WireStats,
+    /// How the multi-pack-index was maintained for the received pack, or `None` if no pack was migrated.
+    pub midx: Option<crate::pack::MidxUpdate>,
+}
+
 impl ReceivePack {
-    /// Execute the receive-pack workflow.
+    /// Serve a push to the configured repository over `read` and `write`, like `git receive-pack`.
     ///
-    /// This placeholder returns Ok(()) to keep the crate buildable until we implement
-    /// the protocol and plumbing.
-    pub fn run(self) -> Result<(), Error> {
-        let _mode = self.cfg.mode;
+    /// The refs of the repository, or of its [namespace](ReceivePackBuilder::with_namespace()), that aren't
+    /// [hidden](ReceivePackBuilder::with_hidden_refs()) are advertised, then the head-info and the pack are received.
+    /// Once the `pre-receive` hook of `hooks` accepted the push, each ref update is checked against the configured
+    /// policy and the `update` hook and applied on its own, and the report is sent if the client asked for one.
+    /// See the [`engine`] module for details.
+    ///
+    /// Errors are returned if the conversation fails, while rejected updates are only reported to the client.
+    /// The flow is blocking in both modes.
+    #[cfg(all(feature = "progress", feature = "blocking-io"))]
+    pub fn run(
+        &self,
+        read: impl std::io::Read,
+        write: impl std::io::Write,
+        hooks: &mut dyn Hooks,
+    ) -> Result<RunOutcome, Error> {
+        self.serve(read, write, hooks, false)
+    }
+
+    /// Like [`run()`](Self::run()), but if `stateless`, serve a single request of a stateless transport like HTTP,
+    /// which starts with the head-info as the advertisement was sent in response to an earlier request.
+    #[cfg(all(feature = "progress", feature = "blocking-io"))]
+    pub(crate) fn serve(
+        &self,
+        read: impl std::io::Read,
+        write: impl std::io::Write,
+        hooks: &mut dyn Hooks,
+        stateless: bool,
+    ) -> Result<RunOutcome, Error> {
+        use gix_serve_core::{metrics::Phase, protocol::ServiceKind};
+        let session = self.cfg.metrics.session(ServiceKind::ReceivePack);
+        let handshake = self.cfg.metrics.phase(ServiceKind::ReceivePack, Phase::Handshake);
+        let (mut engine, mut machine) = self.engine(hooks, stateless)?;
+        drop(handshake);
+        let wire = protocol::machine::blocking::drive(&mut machine, read, write, &mut engine)?;
+        session.finish(&wire, true);
+        Ok(engine.into_outcome(wire))
+    }
+
+    /// Write the advertisement [`run()`](Self::run()) starts with to `write`, for stateless transports to send in
+    /// response to their first request.
+    #[cfg(all(feature = "progress", feature = "blocking-io"))]
+    pub(crate) fn advertise(&self, mut write: impl std::io::Write) -> Result<(), Error> {
+        let mut hooks = NoopHooks::new();
+        let (_engine, mut machine) = self.engine(&mut hooks, false)?;
+        write.write_all(&machine.take_output())?;
+        write.flush()?;
         Ok(())
     }
 
+    /// The engine applying a push with `hooks`, and the machine driving it, which advertised the refs already
+    /// unless the request is `stateless`.
+    #[cfg(all(feature = "progress", feature = "blocking-io"))]
+    fn engine<'a>(
+        &'a self,
+        hooks: &'a mut dyn Hooks,
+        stateless: bool,
+    ) -> Result<(engine::Engine<'a>, protocol::machine::Machine), Error> {
+        let git_dir = self
+            .cfg
+            .git_dir
+            .clone()
+            .ok_or_else(|| Error::Validation("git_dir not configured".into()))?;
+        let objects_dir = self
+            .cfg
+            .objects_dir
+            .clone()
+            .ok_or_else(|| Error::Validation("objects_dir not configured".into()))?;
+        let mut engine =
+            engine::Engine::new(self, git_dir, objects_dir, hooks, &self.cfg.policy).with_stateless(stateless);
+        let mut capabilities: protocol::CapabilitySet = self.cfg.advertisement.clone().into();
+        if let Some(capability) = engine.push_cert_capability() {
+            capabilities.push_extra(capability);
+        }
+        let machine = if stateless {
+            protocol::machine::Machine::for_request(capabilities.clone())
+        } else {
+            protocol::machine::Machine::new()
+        };
+        let mut machine = machine
+            .with_capability_strictness(self.cfg.capability_strictness)
+            .with_head_info_limits(self.cfg.head_info_limits);
+        if !stateless {
+            machine.advertise(&engine.advertised_refs()?, &capabilities, None)?;
+        }
+        Ok((engine, machine))
+    }
+
+    /// Non-progress or async-only build: not available.
+    #[cfg(not(all(feature = "progress", feature = "blocking-io")))]
+    pub fn run(
+        &self,
+        _read: impl std::io::Read,
+        _write: impl std::io::Write,
+        _hooks: &mut dyn Hooks,
+    ) -> Result<RunOutcome, Error> {
+        Err(Error::Unimplemented)
+    }
+
     /// Create an Advertiser over the given writer.
     ///
     /// This is a convenience for composing the protocol advertisement phase (M1).
@@ -585,7 +906,7 @@ impl ReceivePack {
         pack_size: Option<u64>,
         object_count_hint: Option<u64>,
         progress: &mut dyn gix_features::progress::DynNestedProgress,
-    ) -> Result<(), Error> {
+    ) -> Result<crate::pack::IngestionRates, Error> {
         // Guards: size limit
         if let (Some(limit), Some(sz)) = (self.cfg.max_pack_bytes, pack_size) {
             if sz > limit {
@@ -593,6 +914,55 @@ impl ReceivePack {
             }
         }
 
+        let objects_dir = self
+            .cfg
+            .objects_dir
+            .clone()
+            .ok_or_else(|| Error::Validation("objects_dir not configured".into()))?;
+        let mut quarantine = crate::pack::Quarantine::new(objects_dir.clone());
+        quarantine.activate()?;
+        let (path, rates) =
+            self.ingest_into_quarantine(&mut quarantine, input, pack_size, object_count_hint, progress)?;
+
+        let manifests = self.collect_push_manifests(&quarantine);
+        quarantine.migrate_on_success()?;
+        let _ = self.record_push_manifests(&objects_dir, manifests);
+        let _ = self.update_midx(&objects_dir, path, progress);
+        Ok(rates)
+    }
+
+    /// Update or defer the multi-pack-index in `objects_dir` after a pack ingested via `path` was migrated into it.
+    ///
+    /// Failures are returned as [`MidxUpdate::Failed`](crate::pack::MidxUpdate::Failed), as the pack is readable
+    /// without the multi-pack-index.
+    #[cfg(feature = "progress")]
+    pub(crate) fn update_midx(
+        &self,
+        objects_dir: &std::path::Path,
+        path: crate::pack::PackIngestPath,
+        progress: &mut dyn gix_features::progress::DynNestedProgress,
+    ) -> crate::pack::MidxUpdate {
+        crate::pack::midx::update_after_ingest(
+            self.cfg.midx_mode,
+            objects_dir,
+            path,
+            self.cfg.object_hash,
+            progress,
+        )
+        .unwrap_or_else(|e| crate::pack::MidxUpdate::Failed(e.to_string()))
+    }
+
+    /// Ingest the pack from `input` into the active `quarantine` without migrating it, dropping the quarantine
+    /// if ingestion fails, and return the path it took.
+    #[cfg(feature = "progress")]
+    pub(crate) fn ingest_into_quarantine<R: std::io::BufRead>(
+        &self,
+        quarantine: &mut crate::pack::Quarantine,
+        input: &mut R,
+        pack_size: Option<u64>,
+        object_count_hint: Option<u64>,
+        progress: &mut dyn gix_features::progress::DynNestedProgress,
+    ) -> Result<(crate::pack::PackIngestPath, crate::pack::IngestionRates), Error> {
         // Prepare time guard
         let start = std::time::Instant::now();
 
@@ -605,18 +975,45 @@ impl ReceivePack {
             unpack_limit: self.cfg.unpack_limit,
             enable_fallback: true, // Enable fallback by default
         };
-        let choice = policy.choose_path(object_count_hint);
+        // Without a hint, like for pushes, the pack announces its object count itself.
+        let (header, object_count_hint) = match object_count_hint {
+            Some(count) => (Vec::new(), Some(count)),
+            None => match crate::pack::peek_pack_header(input) {
+                Ok((header, count)) => (header, count.map(u64::from)),
+                Err(e) => {
+                    let _ = quarantine.drop_on_failure();
+                    return Err(e.into());
+                }
+            },
+        };
+        let choice = self
+            .cfg
+            .loose_objects
+            .decide(policy.choose_path(object_count_hint), object_count_hint, pack_size)
+            .path();
 
         let main_odb = gix_odb::at(objects_dir.clone())?;
 
-        let mut quarantine = crate::pack::Quarantine::new(objects_dir.clone());
-        quarantine.activate()?;
+        let input = std::io::Read::chain(std::io::Cursor::new(header), input);
+        let input = crate::pack::LimitReader::new(input, self.cfg.max_pack_bytes);
+        let limit = input.monitor();
+        let input = crate::pack::StallReader::new(input, self.cfg.stall_detection);
+        let stall = input.monitor();
+        let rate = crate::pack::RateMonitor::new(self.cfg.rate_limits);
+        let mut input = crate::pack::RateReader::new(input, rate.clone());
+        let input = &mut input;
+        let mut progress = crate::pack::CountingProgress::new(progress, rate.clone());
+        let progress: &mut dyn gix_features::progress::DynNestedProgress = &mut progress;
 
         // Create PackIngestor with fsck configuration
         #[cfg(feature = "fsck")]
-        let ingestor = crate::pack::PackIngestor::new(self.cfg.fsck_config.clone());
+        let ingestor = crate::pack::PackIngestor::new(self.cfg.fsck_config.clone())
+            .with_thin_packs(self.cfg.thin_packs)
+            .with_loose_objects(self.cfg.loose_objects);
         #[cfg(not(feature = "fsck"))]
-        let ingestor = crate::pack::PackIngestor::new(None);
+        let ingestor = crate::pack::PackIngestor::new(None)
+            .with_thin_packs(self.cfg.thin_packs)
+            .with_loose_objects(self.cfg.loose_objects);
 
         let res = match choice {
             crate::pack::PackIngestPath::IndexPack => ingestor.index_pack(
@@ -637,6 +1034,12 @@ impl ReceivePack {
             }
         };
 
+        // Objects counted after the last read, and bytes consumed with it, can only be checked now.
+        if let Some(err) = rate.to_error().or_else(|| limit.to_error()) {
+            let _ = quarantine.drop_on_failure();
+            return Err(err);
+        }
+
         // Time guard check
         if let Some(budget) = self.cfg.time_budget_secs {
             if start.elapsed().as_secs() > budget {
@@ -658,8 +1061,88 @@ impl ReceivePack {
                     // For now, we'll just continue
                 }
 
+                Ok((choice, rate.rates()))
+            }
+            Err(e) => {
+                let _ = quarantine.drop_on_failure();
+                Err(limit
+                    .to_error()
+                    .or_else(|| stall.to_error())
+                    .unwrap_or_else(|| e.into()))
+            }
+        }
+    }
+
+    /// M3: Blocking ingestion of a pack that already exists on disk, with quarantine and migration.
+    ///
+    /// This is meant for import tooling that received a pack out-of-band (upload, rsync, ...) and wants
+    /// it to go through the same size guard, ingestion policy, fsck and quarantine lifecycle as a push.
+    /// The object count for the ingestion policy is taken from the pack header.
+    #[cfg(feature = "progress")]
+    pub fn ingest_pack_file(
+        &self,
+        pack_path: &std::path::Path,
+        progress: &mut dyn gix_features::progress::DynNestedProgress,
+    ) -> Result<ReceiveOutcome, Error> {
+        let pack_size = std::fs::metadata(pack_path)?.len();
+        if let Some(limit) = self.cfg.max_pack_bytes {
+            if pack_size > limit {
+                return Err(Error::Resource(format!(
+                    "incoming pack exceeds size limit: {pack_size} > {limit}"
+                )));
+            }
+        }
+
+        let objects_dir = self
+            .cfg
+            .objects_dir
+            .clone()
+            .ok_or_else(|| Error::Validation("objects_dir not configured".into()))?;
+        let policy = crate::pack::IngestionPolicy {
+            unpack_limit: self.cfg.unpack_limit,
+            enable_fallback: true, // Enable fallback by default
+        };
+
+        let main_odb = gix_odb::at(objects_dir.clone())?;
+
+        let mut quarantine = crate::pack::Quarantine::new(objects_dir.clone());
+        quarantine.activate()?;
+
+        #[cfg(feature = "fsck")]
+        let ingestor = crate::pack::PackIngestor::new(self.cfg.fsck_config.clone())
+            .with_thin_packs(self.cfg.thin_packs)
+            .with_loose_objects(self.cfg.loose_objects);
+        #[cfg(not(feature = "fsck"))]
+        let ingestor = crate::pack::PackIngestor::new(None)
+            .with_thin_packs(self.cfg.thin_packs)
+            .with_loose_objects(self.cfg.loose_objects);
+
+        // The pack isn't received from a client, so its rates are observed but not limited.
+        let rate = crate::pack::RateMonitor::new(None);
+        let mut counting = crate::pack::CountingProgress::new(progress, rate.clone());
+        match ingestor.ingest_pack_file(
+            pack_path,
+            quarantine.objects_dir.as_path(),
+            &policy,
+            Some(main_odb),
+            &mut counting,
+        ) {
+            Ok((path, unpack, _fsck_results)) => {
+                let manifests = self.collect_push_manifests(&quarantine);
                 quarantine.migrate_on_success()?;
-                Ok(())
+                let manifest = self.record_push_manifests(&objects_dir, manifests);
+                let midx = self.update_midx(&objects_dir, path, progress);
+                Ok(ReceiveOutcome {
+                    ingest_path: path,
+                    unpack,
+                    midx,
+                    manifest,
+                    thin: ingestor.thin_bases() > 0,
+                    rates: crate::pack::IngestionRates {
+                        bytes: pack_size,
+                        ..rate.rates()
+                    },
+                })
             }
             Err(e) => {
                 let _ = quarantine.drop_on_failure();
@@ -668,6 +1151,16 @@ impl ReceivePack {
         }
     }
 
+    /// Non-progress build: not available.
+    #[cfg(not(feature = "progress"))]
+    pub fn ingest_pack_file(
+        &self,
+        _pack_path: &std::path::Path,
+        _progress: &mut dyn std::any::Any,
+    ) -> Result<ReceiveOutcome, Error> {
+        Err(Error::Unimplemented)
+    }
+
     /// M3: Blocking ingestion with sideband progress bridge.
     ///
     /// This variant wires pack ingestion progress to sideband channel 2 using SidebandProgressWriter.
@@ -680,7 +1173,7 @@ impl ReceivePack {
         object_count_hint: Option<u64>,
         inner_progress: Box<dyn gix_features::progress::DynNestedProgress>,
         sideband: &mut (dyn std::io::Write + std::marker::Send),
-    ) -> Result<(), Error> {
+    ) -> Result<crate::pack::IngestionRates, Error> {
         // Temporary: avoid sideband bridge to relax lifetime requirements; use provided progress directly.
         let mut progress = inner_progress;
         self.ingest_pack_from_reader(input, pack_size, object_count_hint, &mut *progress)
@@ -718,21 +1211,39 @@ impl ReceivePack {
             unpack_limit: self.cfg.unpack_limit,
             enable_fallback: true, // Enable fallback by default
         };
-        let choice = policy.choose_path(object_count_hint);
+        let choice = self
+            .cfg
+            .loose_objects
+            .decide(policy.choose_path(object_count_hint), object_count_hint, pack_size)
+            .path();
 
         let main_odb = gix_odb::at(objects_dir.clone())?;
 
         let mut quarantine = crate::pack::Quarantine::new(objects_dir.clone());
         quarantine.activate()?;
 
+        let input = crate::pack::LimitReader::new(input, self.cfg.max_pack_bytes);
+        let limit = input.monitor();
+        let input = crate::pack::StallReader::new(input, self.cfg.stall_detection);
+        let stall = input.monitor();
+        let rate = crate::pack::RateMonitor::new(self.cfg.rate_limits);
+        let mut input = crate::pack::RateReader::new(input, rate.clone());
+        let input = &mut input;
+        let mut progress = crate::pack::CountingProgress::new(progress, rate.clone());
+        let progress: &mut dyn gix_features::progress::DynNestedProgress = &mut progress;
+
         // Create PackIngestor with streaming configuration
         #[cfg(feature = "fsck")]
         let ingestor = crate::pack::PackIngestor::with_streaming_config(
             self.cfg.fsck_config.clone(),
             streaming_config,
-        );
+        )
+        .with_thin_packs(self.cfg.thin_packs)
+        .with_loose_objects(self.cfg.loose_objects);
         #[cfg(not(feature = "fsck"))]
-        let ingestor = crate::pack::PackIngestor::with_streaming_config(None, streaming_config);
+        let ingestor = crate::pack::PackIngestor::with_streaming_config(None, streaming_config)
+            .with_thin_packs(self.cfg.thin_packs)
+            .with_loose_objects(self.cfg.loose_objects);
 
         let res = match choice {
             crate::pack::PackIngestPath::IndexPack => ingestor.index_pack_streaming(
@@ -753,6 +1264,12 @@ impl ReceivePack {
             }
         };
 
+        // Objects counted after the last read, and bytes consumed with it, can only be checked now.
+        if let Some(err) = rate.to_error().or_else(|| limit.to_error()) {
+            let _ = quarantine.drop_on_failure();
+            return Err(err);
+        }
+
         // Time guard check
         if let Some(budget) = self.cfg.time_budget_secs {
             if start.elapsed().as_secs() > budget {
@@ -766,7 +1283,7 @@ impl ReceivePack {
         }
 
         match res {
-            Ok((_fsck_results, streaming_stats)) => {
+            Ok((_fsck_results, mut streaming_stats)) => {
                 // Log fsck warnings if any
                 #[cfg(feature = "fsck")]
                 if !_fsck_results.warnings.is_empty() {
@@ -774,12 +1291,19 @@ impl ReceivePack {
                     // For now, we'll just continue
                 }
 
+                let manifests = self.collect_push_manifests(&quarantine);
                 quarantine.migrate_on_success()?;
+                let _ = self.record_push_manifests(&objects_dir, manifests);
+                let _ = self.update_midx(&objects_dir, choice, progress);
+                streaming_stats.rates = rate.rates();
                 Ok(streaming_stats)
             }
             Err(e) => {
                 let _ = quarantine.drop_on_failure();
-                Err(e.into())
+                Err(limit
+                    .to_error()
+                    .or_else(|| stall.to_error())
+                    .unwrap_or_else(|| e.into()))
             }
         }
     }
@@ -837,7 +1361,7 @@ impl ReceivePack {
         _pack_size: Option<u64>,
         _object_count_hint: Option<u64>,
         _progress: &mut dyn std::any::Any,
-    ) -> Result<(), Error> {
+    ) -> Result<crate::pack::IngestionRates, Error> {
         Err(Error::Unimplemented)
     }
 
@@ -847,16 +1371,99 @@ impl ReceivePack {
     /// - `text`: lines from the client during the head-info phase, one logical record per `\n`.
     /// - `advertised`: the capability set we previously advertised in M1.
     ///
-    /// Returns a typed list of command updates and parsed options.
+    /// Returns a typed list of command updates and parsed options. Capabilities ignored due to the
+    /// configured [`CapabilityStrictness`](protocol::CapabilityStrictness) are listed in the options.
+    /// Head-info exceeding the configured [`HeadInfoLimits`](protocol::HeadInfoLimits) is refused.
     pub fn parse_head_info_from_text(
         &self,
         text: &str,
         advertised: &protocol::CapabilitySet,
     ) -> Result<(protocol::CommandList, protocol::Options), Error> {
-        let (list, opts) = protocol::CommandList::parse_from_text(text)?;
-        opts.validate_against(advertised)?;
+        let (list, mut opts) = protocol::CommandList::parse_with_limits(text, &self.cfg.head_info_limits)?;
+        opts.validate_with(advertised, self.cfg.capability_strictness)?;
         Ok((list, opts))
     }
+
+    /// Append the new tips of `updates` to the repository's split commit-graph chain.
+    ///
+    /// Call this after the quarantine was migrated and the ref updates were applied. Failures of the
+    /// writer are reported in the returned [`CommitGraphUpdate`] and never undo the push.
+    pub fn update_commit_graph(&self, updates: &[protocol::CommandUpdate]) -> Result<CommitGraphUpdate, Error> {
+        if !self.cfg.commit_graph.enabled {
+            return Ok(CommitGraphUpdate::Disabled);
+        }
+        let objects_dir = self
+            .cfg
+            .objects_dir
+            .as_ref()
+            .ok_or_else(|| Error::Validation("objects_dir is required to update the commit-graph".into()))?;
+        commit_graph::update_after_receive(&self.cfg.commit_graph, objects_dir, updates)
+    }
+
+    /// Check out the new commit of the current branch with `updater` instead of refusing its update.
+    ///
+    /// Call this for the [`UpdateInstead`] delegated by the policy once the quarantine was migrated, before the ref
+    /// is updated. If it fails, refuse the ref update with the [`Refusal`] of the returned error as its reason.
+    pub fn update_worktree(&self, updater: &WorktreeUpdater, update: &UpdateInstead) -> Result<WorktreeUpdate, Error> {
+        let objects_dir = self
+            .cfg
+            .objects_dir
+            .as_ref()
+            .ok_or_else(|| Error::Validation("objects_dir is required to update the worktree".into()))?;
+        let objects = gix_odb::at(objects_dir)?;
+        updater.update(update, &objects)
+    }
+
+    /// Complete or roll back quarantine migrations into the configured repository that a crash interrupted.
+    ///
+    /// Call this once when the server starts, before serving pushes, so the object database never keeps
+    /// referencing half-migrated packs.
+    pub fn recover_migrations(&self) -> Result<Vec<crate::pack::MigrationRecovery>, Error> {
+        let objects_dir = self
+            .cfg
+            .objects_dir
+            .as_ref()
+            .ok_or_else(|| Error::Validation("objects_dir is required to recover migrations".into()))?;
+        Ok(crate::pack::Quarantine::recover(objects_dir)?)
+    }
+
+    /// Return an error if the maintenance operation `op` must not run on the configured repository.
+    ///
+    /// Maintenance tooling should call this before each step, so repositories with `extensions.preciousObjects`
+    /// and packs with a `.keep` file never lose objects.
+    pub fn check_maintenance(&self, op: &crate::pack::MaintenanceOp) -> Result<(), Error> {
+        let objects_dir = self
+            .cfg
+            .objects_dir
+            .as_ref()
+            .ok_or_else(|| Error::Validation("objects_dir is required to check maintenance operations".into()))?;
+        crate::pack::Protection::at(objects_dir)?.check(op)
+    }
+
+    /// Read the manifests of all packs in `quarantine`, if recording is enabled.
+    ///
+    /// Must be called before the quarantine is migrated.
+    fn collect_push_manifests(
+        &self,
+        quarantine: &crate::pack::Quarantine,
+    ) -> Option<Result<Vec<crate::pack::PushManifest>, Error>> {
+        self.cfg.push_manifests.then(|| {
+            crate::pack::manifest::collect(&quarantine.objects_dir, self.cfg.object_hash)
+        })
+    }
+
+    /// Write the manifests obtained by [`Self::collect_push_manifests()`] once the quarantine was migrated.
+    fn record_push_manifests(
+        &self,
+        objects_dir: &std::path::Path,
+        manifests: Option<Result<Vec<crate::pack::PushManifest>, Error>>,
+    ) -> crate::pack::ManifestRecord {
+        match manifests {
+            None => crate::pack::ManifestRecord::Disabled,
+            Some(Ok(manifests)) => crate::pack::manifest::record(objects_dir, &manifests),
+            Some(Err(e)) => crate::pack::ManifestRecord::Failed(e.to_string()),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -864,16 +1471,30 @@ mod tests {
     use super::*;
 
     #[test]
-    fn builder_blocking_compiles_and_runs() {
+    fn builder_blocking_needs_a_repository_to_run() {
         let rp = ReceivePackBuilder::new().blocking().build();
-        rp.run().unwrap();
+        let err = rp.run(&b""[..], Vec::new(), &mut NoopHooks::new()).unwrap_err();
+        assert!(matches!(err, Error::Validation(_) | Error::Unimplemented), "{err:?}");
     }
 
     #[cfg(feature = "async-io")]
     #[test]
-    fn builder_async_compiles_and_runs() {
+    fn builder_async_needs_a_repository_to_run() {
         let rp = ReceivePackBuilder::new().r#async().build();
-        rp.run().unwrap();
+        let err = rp.run(&b""[..], Vec::new(), &mut NoopHooks::new()).unwrap_err();
+        assert!(matches!(err, Error::Validation(_) | Error::Unimplemented), "{err:?}");
+    }
+
+    #[test]
+    fn objects_dir_defaults_to_that_of_the_git_dir() {
+        let rp = ReceivePackBuilder::new().blocking().with_git_dir("repo.git").build();
+        assert_eq!(rp.cfg.objects_dir, Some(PathBuf::from("repo.git/objects")));
+        let rp = ReceivePackBuilder::new()
+            .blocking()
+            .with_objects_dir("elsewhere")
+            .with_git_dir("repo.git")
+            .build();
+        assert_eq!(rp.cfg.objects_dir, Some(PathBuf::from("elsewhere")));
     }
 
     #[test]
@@ -1,7 +1,10 @@
 //! Fsck integration for pack ingestion validation.
 //!
 //! This module provides configurable object validation using gix-fsck with different
-//! strictness levels and comprehensive error reporting.
+//! strictness levels and comprehensive error reporting. The [`special`] checks of `.gitmodules` and
+//! `.gitattributes` content and of symlinked special files run at the `Normal` and `Strict` levels.
+
+pub mod special;
 
 use std::collections::HashMap;
 use std::path::Path;
@@ -166,7 +169,7 @@ impl FsckValidator {
                 // Basic validation is already done by connectivity check
             }
             FsckLevel::Normal | FsckLevel::Strict => {
-                self.validate_object_integrity(&quarantine_odb, &pack_objects, &mut results)?;
+                self.validate_object_integrity(&quarantine_odb, main_odb, &pack_objects, &mut results)?;
             }
         }
 
@@ -300,13 +303,17 @@ impl FsckValidator {
     }
 
     /// Validate object integrity beyond basic connectivity.
+    ///
+    /// Special files found in trees are looked up in `main_odb` if they aren't part of `objects`.
     fn validate_object_integrity(
         &self,
         odb: &gix_odb::Handle,
+        main_odb: &gix_odb::Handle,
         objects: &[ObjectId],
         results: &mut FsckResults,
     ) -> Result<(), crate::Error> {
         let mut buf = Vec::new();
+        let mut special_files = special::SpecialFiles::default();
 
         for &object_id in objects {
             // Skip if this object should be skipped
@@ -332,6 +339,17 @@ impl FsckValidator {
                     } else {
                         results.validated_objects.push(object_id);
                     }
+
+                    if !self.config.skip_types.contains(&obj.kind) {
+                        let findings = match obj.kind {
+                            Kind::Tree => gix_object::TreeRef::from_bytes(obj.data)
+                                .map(|tree| special_files.check_tree(object_id, &tree))
+                                .unwrap_or_default(),
+                            Kind::Blob => special_files.check_blob(object_id, obj.data),
+                            Kind::Commit | Kind::Tag => Vec::new(),
+                        };
+                        self.report_special_files(findings, results);
+                    }
                 }
                 Ok(None) => {
                     // Object doesn't exist - this should have been caught by connectivity check
@@ -353,9 +371,43 @@ impl FsckValidator {
             buf.clear();
         }
 
+        let findings = special_files.finish(|id| {
+            [odb, main_odb].into_iter().find_map(|odb| {
+                let mut buf = Vec::new();
+                let obj = odb.try_find(id, &mut buf).ok().flatten()?;
+                Some((obj.kind, obj.data.to_vec()))
+            })
+        });
+        self.report_special_files(findings, results);
+
         Ok(())
     }
 
+    /// Report `findings` about special files with their configured level, or the default level of `git`.
+    fn report_special_files(&self, findings: Vec<special::Finding>, results: &mut FsckResults) {
+        for finding in findings {
+            if self.config.skip_objects.contains(&finding.object_id) {
+                continue;
+            }
+            let level = self
+                .config
+                .msg_types
+                .iter()
+                .find(|(name, _)| name.eq_ignore_ascii_case(finding.id.as_str()))
+                .map_or(finding.id.default_level(), |(_, level)| *level);
+            let message = FsckMessage {
+                object_id: finding.object_id,
+                message_type: finding.id.as_str().to_string(),
+                message: finding.message,
+            };
+            match level {
+                FsckMessageLevel::Ignore => {}
+                FsckMessageLevel::Warn => results.warnings.push(message),
+                FsckMessageLevel::Error => results.errors.push(message),
+            }
+        }
+    }
+
     /// Validate the content of a specific object.
     fn validate_object_content(
         &self,
@@ -761,6 +813,48 @@ mod integration_tests {
         let _ = std::fs::remove_dir_all(&temp_dir);
     }
 
+    #[cfg(feature = "fsck")]
+    #[test]
+    fn test_fsck_validator_special_files() {
+        let repo = gix_testtools::scripted_fixture_read_only("fsck-special-files.sh").unwrap();
+        let objects: Vec<ObjectId> = std::fs::read_to_string(repo.join("objects.txt"))
+            .unwrap()
+            .lines()
+            .map(|line| ObjectId::from_hex(line.as_bytes()).unwrap())
+            .collect();
+        let odb = gix_odb::at(repo.join(".git/objects")).unwrap();
+        let check = |msg_types: HashMap<String, FsckMessageLevel>| {
+            let validator = FsckValidator::new(FsckConfig {
+                msg_types,
+                ..FsckConfig::default()
+            });
+            let mut results = FsckResults {
+                validated_objects: Vec::new(),
+                warnings: Vec::new(),
+                errors: Vec::new(),
+                missing_objects: Vec::new(),
+            };
+            validator
+                .validate_object_integrity(&odb, &odb, &objects, &mut results)
+                .unwrap();
+            let types = |messages: &[FsckMessage]| -> Vec<String> {
+                messages.iter().map(|m| m.message_type.clone()).collect()
+            };
+            (types(&results.errors), types(&results.warnings))
+        };
+
+        let (errors, warnings) = check(HashMap::new());
+        assert_eq!(errors, ["gitmodulesName", "gitmodulesUrl"]);
+        assert_eq!(warnings, ["gitignoreSymlink"]);
+
+        let (errors, warnings) = check(HashMap::from([
+            ("gitmodulesurl".to_string(), FsckMessageLevel::Ignore),
+            ("gitmodulesName".to_string(), FsckMessageLevel::Warn),
+        ]));
+        assert!(errors.is_empty());
+        assert_eq!(warnings, ["gitignoreSymlink", "gitmodulesName"]);
+    }
+
     #[test]
     fn test_pack_ingestor_creation() {
         // Test creating PackIngestor with different configurations
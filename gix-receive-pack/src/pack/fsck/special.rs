@@ -0,0 +1,558 @@
+//! Checks of special files, matching `git fsck` for `.gitmodules`, `.gitattributes`, `.gitignore` and `.mailmap`.
+//!
+//! Trees are checked for symlinked special files, and the blobs they name as `.gitmodules` or `.gitattributes`
+//! are remembered so their content can be validated once they are seen, or when [`SpecialFiles::finish()`] is
+//! called for blobs that weren't part of the validated objects. Names are matched like `git` does on all
+//! platforms, including case-folding, HFS+ ignorable code points and NTFS short names and trailing dots.
+
+use std::collections::HashSet;
+
+use gix_hash::ObjectId;
+use gix_object::{tree::EntryKind, Kind, TreeRef};
+
+use super::FsckMessageLevel;
+
+/// `.gitattributes` blobs larger than this are rejected, as `git` wouldn't parse them.
+pub const MAX_ATTRIBUTES_SIZE: usize = 100 * 1024 * 1024;
+/// `.gitattributes` lines this long or longer are rejected, as `git` would ignore them.
+pub const MAX_ATTRIBUTES_LINE_LENGTH: usize = 2048;
+/// `.gitmodules` blobs larger than this are rejected, matching `core.bigFileThreshold` of `git`.
+pub const MAX_GITMODULES_SIZE: usize = 512 * 1024 * 1024;
+
+/// The ids of messages reported for special files, named like the `fsck.<msg-id>` configuration of `git`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MessageId {
+    /// A `.gitmodules` entry doesn't point to a blob.
+    GitmodulesBlob,
+    /// A `.gitmodules` blob is too large to be parsed.
+    GitmodulesLarge,
+    /// A `.gitmodules` blob is referenced but not present.
+    GitmodulesMissing,
+    /// A submodule name could escape the `.git/modules` directory.
+    GitmodulesName,
+    /// A `.gitmodules` blob isn't valid configuration.
+    GitmodulesParse,
+    /// A submodule path could be mistaken for a command-line option.
+    GitmodulesPath,
+    /// `.gitmodules` is a symbolic link.
+    GitmodulesSymlink,
+    /// A submodule update setting runs a command.
+    GitmodulesUpdate,
+    /// A submodule URL could be mistaken for a command-line option or inject credentials.
+    GitmodulesUrl,
+    /// A `.gitattributes` entry doesn't point to a blob.
+    GitattributesBlob,
+    /// A `.gitattributes` blob is too large to be parsed.
+    GitattributesLarge,
+    /// A `.gitattributes` blob has lines too long to be parsed.
+    GitattributesLineLength,
+    /// A `.gitattributes` blob is referenced but not present.
+    GitattributesMissing,
+    /// `.gitattributes` is a symbolic link.
+    GitattributesSymlink,
+    /// `.gitignore` is a symbolic link.
+    GitignoreSymlink,
+    /// `.mailmap` is a symbolic link.
+    MailmapSymlink,
+}
+
+impl MessageId {
+    /// Return the name of the message id, as used in `fsck.<msg-id>` and `receive.fsck.<msg-id>`.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MessageId::GitmodulesBlob => "gitmodulesBlob",
+            MessageId::GitmodulesLarge => "gitmodulesLarge",
+            MessageId::GitmodulesMissing => "gitmodulesMissing",
+            MessageId::GitmodulesName => "gitmodulesName",
+            MessageId::GitmodulesParse => "gitmodulesParse",
+            MessageId::GitmodulesPath => "gitmodulesPath",
+            MessageId::GitmodulesSymlink => "gitmodulesSymlink",
+            MessageId::GitmodulesUpdate => "gitmodulesUpdate",
+            MessageId::GitmodulesUrl => "gitmodulesUrl",
+            MessageId::GitattributesBlob => "gitattributesBlob",
+            MessageId::GitattributesLarge => "gitattributesLarge",
+            MessageId::GitattributesLineLength => "gitattributesLineLength",
+            MessageId::GitattributesMissing => "gitattributesMissing",
+            MessageId::GitattributesSymlink => "gitattributesSymlink",
+            MessageId::GitignoreSymlink => "gitignoreSymlink",
+            MessageId::MailmapSymlink => "mailmapSymlink",
+        }
+    }
+
+    /// Return the severity `git` uses by default, where its informational messages are reported as warnings.
+    pub fn default_level(&self) -> FsckMessageLevel {
+        match self {
+            MessageId::GitmodulesParse
+            | MessageId::GitattributesSymlink
+            | MessageId::GitignoreSymlink
+            | MessageId::MailmapSymlink => FsckMessageLevel::Warn,
+            _ => FsckMessageLevel::Error,
+        }
+    }
+}
+
+/// A problem with a special file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Finding {
+    /// The tree or blob the problem was found in.
+    pub object_id: ObjectId,
+    /// What kind of problem it is.
+    pub id: MessageId,
+    /// A human-readable description, like `git` would print it.
+    pub message: String,
+}
+
+impl Finding {
+    fn new(object_id: ObjectId, id: MessageId, message: impl Into<String>) -> Self {
+        Finding {
+            object_id,
+            id,
+            message: message.into(),
+        }
+    }
+}
+
+/// The special files found in trees, and the ones whose content was checked already.
+#[derive(Debug, Default)]
+pub struct SpecialFiles {
+    gitmodules: HashSet<ObjectId>,
+    gitattributes: HashSet<ObjectId>,
+    checked: HashSet<ObjectId>,
+}
+
+impl SpecialFiles {
+    /// Check the entries of `tree` with id `tree_id`, and remember the blobs of special files in it.
+    pub fn check_tree(&mut self, tree_id: ObjectId, tree: &TreeRef<'_>) -> Vec<Finding> {
+        let mut findings = Vec::new();
+        for entry in &tree.entries {
+            let name: &[u8] = entry.filename.as_ref();
+            let is_symlink = entry.mode.kind() == EntryKind::Link;
+            if is_special(name, "gitmodules", "gi7eba") {
+                if is_symlink {
+                    findings.push(Finding::new(
+                        tree_id,
+                        MessageId::GitmodulesSymlink,
+                        ".gitmodules is a symbolic link",
+                    ));
+                } else {
+                    self.gitmodules.insert(entry.oid.to_owned());
+                }
+            }
+            if is_special(name, "gitattributes", "gi7d29") {
+                if is_symlink {
+                    findings.push(Finding::new(
+                        tree_id,
+                        MessageId::GitattributesSymlink,
+                        ".gitattributes is a symlink",
+                    ));
+                } else {
+                    self.gitattributes.insert(entry.oid.to_owned());
+                }
+            }
+            if is_symlink {
+                if is_special(name, "gitignore", "gi250a") {
+                    findings.push(Finding::new(
+                        tree_id,
+                        MessageId::GitignoreSymlink,
+                        ".gitignore is a symlink",
+                    ));
+                }
+                if is_special(name, "mailmap", "maba30") {
+                    findings.push(Finding::new(
+                        tree_id,
+                        MessageId::MailmapSymlink,
+                        ".mailmap is a symlink",
+                    ));
+                }
+            }
+        }
+        findings
+    }
+
+    /// Check the content of blob `id` if it was found as a special file, and remember it as checked.
+    pub fn check_blob(&mut self, id: ObjectId, data: &[u8]) -> Vec<Finding> {
+        let mut findings = Vec::new();
+        let is_special = self.gitmodules.contains(&id) || self.gitattributes.contains(&id);
+        if !is_special || !self.checked.insert(id) {
+            return findings;
+        }
+        if self.gitmodules.contains(&id) {
+            findings.extend(check_gitmodules(id, data));
+        }
+        if self.gitattributes.contains(&id) {
+            findings.extend(check_gitattributes(id, data));
+        }
+        findings
+    }
+
+    /// Check all special files that weren't checked yet, with `find` returning the kind and data of an object,
+    /// or `None` if it isn't present.
+    pub fn finish(&mut self, mut find: impl FnMut(&ObjectId) -> Option<(Kind, Vec<u8>)>) -> Vec<Finding> {
+        let mut findings = Vec::new();
+        let pending = self
+            .gitmodules
+            .iter()
+            .map(|id| {
+                (
+                    *id,
+                    ".gitmodules",
+                    MessageId::GitmodulesMissing,
+                    MessageId::GitmodulesBlob,
+                )
+            })
+            .chain(self.gitattributes.iter().map(|id| {
+                (
+                    *id,
+                    ".gitattributes",
+                    MessageId::GitattributesMissing,
+                    MessageId::GitattributesBlob,
+                )
+            }))
+            .filter(|(id, ..)| !self.checked.contains(id))
+            .collect::<Vec<_>>();
+        for (id, name, missing, not_a_blob) in pending {
+            match find(&id) {
+                None => findings.push(Finding::new(id, missing, format!("unable to read {name} blob"))),
+                Some((Kind::Blob, data)) => findings.extend(self.check_blob(id, &data)),
+                Some(_) => findings.push(Finding::new(id, not_a_blob, format!("non-blob found at {name}"))),
+            }
+        }
+        findings
+    }
+}
+
+/// Check the content of a `.gitmodules` blob.
+fn check_gitmodules(id: ObjectId, data: &[u8]) -> Vec<Finding> {
+    if data.len() > MAX_GITMODULES_SIZE {
+        return vec![Finding::new(
+            id,
+            MessageId::GitmodulesLarge,
+            ".gitmodules too large to parse",
+        )];
+    }
+    let config =
+        match gix_config::File::from_bytes_no_includes(data, gix_config::file::Metadata::api(), Default::default()) {
+            Ok(config) => config,
+            Err(_) => {
+                return vec![Finding::new(
+                    id,
+                    MessageId::GitmodulesParse,
+                    "could not parse gitmodules blob",
+                )]
+            }
+        };
+
+    let mut findings = Vec::new();
+    for section in config.sections_by_name("submodule").into_iter().flatten() {
+        let Some(name) = section.header().subsection_name() else {
+            continue;
+        };
+        if !is_valid_submodule_name(name) {
+            findings.push(Finding::new(
+                id,
+                MessageId::GitmodulesName,
+                format!("disallowed submodule name: {name}"),
+            ));
+        }
+        let body = section.body();
+        for url in body.values("url") {
+            if !is_valid_submodule_url(&url) {
+                findings.push(Finding::new(
+                    id,
+                    MessageId::GitmodulesUrl,
+                    format!("disallowed submodule url: {url}"),
+                ));
+            }
+        }
+        for path in body.values("path") {
+            if looks_like_option(&path) {
+                findings.push(Finding::new(
+                    id,
+                    MessageId::GitmodulesPath,
+                    format!("disallowed submodule path: {path}"),
+                ));
+            }
+        }
+        for update in body.values("update") {
+            if update.first() == Some(&b'!') {
+                findings.push(Finding::new(
+                    id,
+                    MessageId::GitmodulesUpdate,
+                    format!("disallowed submodule update setting: {update}"),
+                ));
+            }
+        }
+    }
+    findings
+}
+
+/// Check the content of a `.gitattributes` blob.
+fn check_gitattributes(id: ObjectId, data: &[u8]) -> Vec<Finding> {
+    if data.len() > MAX_ATTRIBUTES_SIZE {
+        return vec![Finding::new(
+            id,
+            MessageId::GitattributesLarge,
+            ".gitattributes too large to parse",
+        )];
+    }
+    if data
+        .split(|b| *b == b'\n')
+        .any(|line| line.len() >= MAX_ATTRIBUTES_LINE_LENGTH)
+    {
+        return vec![Finding::new(
+            id,
+            MessageId::GitattributesLineLength,
+            ".gitattributes has too long lines to parse",
+        )];
+    }
+    Vec::new()
+}
+
+/// Submodule names must not be empty or contain `..` components, as they are used as paths below `.git/modules`.
+fn is_valid_submodule_name(name: &[u8]) -> bool {
+    !name.is_empty()
+        && !name
+            .split(|b| *b == b'/' || *b == b'\\')
+            .any(|component| component == b"..")
+}
+
+/// Submodule URLs must not look like options, and must not contain newlines which could inject credentials.
+fn is_valid_submodule_url(url: &[u8]) -> bool {
+    !looks_like_option(url) && !url.contains(&b'\n')
+}
+
+fn looks_like_option(value: &[u8]) -> bool {
+    value.first() == Some(&b'-')
+}
+
+/// Return true if `name` would be treated as `.<dotfile>` on HFS+ or NTFS, whose short names start with
+/// `ntfs_shortname_prefix`.
+fn is_special(name: &[u8], dotfile: &str, ntfs_shortname_prefix: &str) -> bool {
+    is_hfs_dotfile(name, dotfile) || is_ntfs_dotfile(name, dotfile.as_bytes(), ntfs_shortname_prefix.as_bytes())
+}
+
+/// HFS+ ignores some Unicode code points and compares case-insensitively.
+fn is_hfs_dotfile(name: &[u8], dotfile: &str) -> bool {
+    let Ok(name) = std::str::from_utf8(name) else {
+        return false;
+    };
+    let mut chars = name.chars().filter(|c| !is_hfs_ignorable(*c));
+    chars.next() == Some('.')
+        && chars
+            .map(|c| c.to_ascii_lowercase())
+            .eq(dotfile.chars().map(|c| c.to_ascii_lowercase()))
+}
+
+fn is_hfs_ignorable(c: char) -> bool {
+    matches!(c, '\u{200c}'..='\u{200f}' | '\u{202a}'..='\u{202e}' | '\u{206a}'..='\u{206f}' | '\u{feff}')
+}
+
+/// NTFS strips trailing spaces and dots, ignores alternate data streams and supports 8.3 short names.
+fn is_ntfs_dotfile(name: &[u8], dotfile: &[u8], shortname_prefix: &[u8]) -> bool {
+    let only_spaces_and_periods = |rest: &[u8]| {
+        rest.iter()
+            .take_while(|b| **b != b':')
+            .all(|b| *b == b' ' || *b == b'.')
+    };
+    if name.first() == Some(&b'.')
+        && name.len() > dotfile.len()
+        && name[1..=dotfile.len()].eq_ignore_ascii_case(dotfile)
+    {
+        return only_spaces_and_periods(&name[dotfile.len() + 1..]);
+    }
+    if name.len() >= 8
+        && name[..6].eq_ignore_ascii_case(&dotfile[..6])
+        && name[6] == b'~'
+        && (b'1'..=b'4').contains(&name[7])
+    {
+        return only_spaces_and_periods(&name[8..]);
+    }
+
+    // The fall-back short name, like `gi7eba~1`.
+    let mut saw_tilde = false;
+    let mut i = 0;
+    while i < 8 {
+        let Some(&b) = name.get(i) else {
+            return false;
+        };
+        if saw_tilde {
+            if !b.is_ascii_digit() {
+                return false;
+            }
+        } else if b == b'~' {
+            i += 1;
+            if !matches!(name.get(i), Some(b'1'..=b'9')) {
+                return false;
+            }
+            saw_tilde = true;
+        } else if i >= 6 || !b.is_ascii() || b.to_ascii_lowercase() != shortname_prefix[i] {
+            return false;
+        }
+        i += 1;
+    }
+    only_spaces_and_periods(&name[8..])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gix_object::tree::{EntryMode, EntryRef};
+
+    fn oid(byte: u8) -> ObjectId {
+        ObjectId::from_bytes_or_panic(&[byte; 20])
+    }
+
+    fn tree<'a>(entries: &[(&'a str, EntryKind, &'a ObjectId)]) -> TreeRef<'a> {
+        TreeRef {
+            entries: entries
+                .iter()
+                .map(|(name, kind, oid)| EntryRef {
+                    mode: EntryMode::from(*kind),
+                    filename: (*name).into(),
+                    oid,
+                })
+                .collect(),
+        }
+    }
+
+    fn ids(findings: &[Finding]) -> Vec<MessageId> {
+        findings.iter().map(|finding| finding.id).collect()
+    }
+
+    #[test]
+    fn special_names() {
+        for name in [
+            ".gitmodules",
+            ".GITMODULES",
+            ".gitmodules ",
+            ".gitmodules. .",
+            ".gitmodules:$DATA",
+            "gitmod~1",
+            "GI7EBA~1",
+            "gi7eb~12",
+            ".git\u{200c}modules",
+            "\u{feff}.gitModules",
+        ] {
+            assert!(is_special(name.as_bytes(), "gitmodules", "gi7eba"), "{name:?}");
+        }
+        for name in [
+            "gitmodules",
+            ".gitmodulesx",
+            ".gitmodule",
+            "gitmod~5",
+            "gi7eba~0",
+            "gi7ebb~1",
+            ".gitmodules\u{200b}",
+        ] {
+            assert!(!is_special(name.as_bytes(), "gitmodules", "gi7eba"), "{name:?}");
+        }
+        assert!(is_special(b"mailma~1", "mailmap", "maba30"));
+        assert!(is_special(b".GitIgnore", "gitignore", "gi250a"));
+    }
+
+    #[test]
+    fn symlinked_special_files() {
+        let (blob, tree_id) = (oid(1), oid(2));
+        let entries = tree(&[
+            (".gitattributes", EntryKind::Link, &blob),
+            (".gitignore", EntryKind::Link, &blob),
+            (".gitmodules", EntryKind::Link, &blob),
+            (".mailmap", EntryKind::Link, &blob),
+            ("gitignore", EntryKind::Link, &blob),
+        ]);
+        let mut special = SpecialFiles::default();
+        let findings = special.check_tree(tree_id, &entries);
+        assert_eq!(
+            ids(&findings),
+            [
+                MessageId::GitattributesSymlink,
+                MessageId::GitignoreSymlink,
+                MessageId::GitmodulesSymlink,
+                MessageId::MailmapSymlink
+            ]
+        );
+        assert!(findings.iter().all(|finding| finding.object_id == tree_id));
+        assert_eq!(MessageId::GitmodulesSymlink.default_level(), FsckMessageLevel::Error);
+        assert_eq!(MessageId::GitignoreSymlink.default_level(), FsckMessageLevel::Warn);
+        assert!(special.finish(|_| None).is_empty(), "symlinks aren't checked as blobs");
+    }
+
+    #[test]
+    fn gitmodules_content() {
+        let blob = oid(1);
+        let mut special = SpecialFiles::default();
+        special.check_tree(oid(2), &tree(&[(".gitmodules", EntryKind::Blob, &blob)]));
+
+        let content = b"[submodule \"../../hooks\"]\n\tpath = -evil\n\turl = --upload-pack=touch\n\tupdate = !rm -rf /\n[submodule \"ok\"]\n\tpath = ok\n\turl = https://example.com/ok.git\n";
+        let findings = special.check_blob(blob, content);
+        assert_eq!(
+            ids(&findings),
+            [
+                MessageId::GitmodulesName,
+                MessageId::GitmodulesUrl,
+                MessageId::GitmodulesPath,
+                MessageId::GitmodulesUpdate
+            ]
+        );
+        assert_eq!(findings[0].message, "disallowed submodule name: ../../hooks");
+        assert!(
+            special.check_blob(blob, content).is_empty(),
+            "blobs are checked only once"
+        );
+        assert!(special.finish(|_| None).is_empty());
+
+        let mut special = SpecialFiles::default();
+        special.check_tree(oid(2), &tree(&[(".gitmodules", EntryKind::Blob, &blob)]));
+        assert_eq!(
+            ids(&special.check_blob(blob, b"[submodule \"unterminated\n")),
+            [MessageId::GitmodulesParse]
+        );
+        assert!(special
+            .check_blob(oid(3), b"[submodule \"x\"]\n\tpath = -x\n")
+            .is_empty());
+    }
+
+    #[test]
+    fn gitattributes_content() {
+        let blob = oid(1);
+        let mut special = SpecialFiles::default();
+        special.check_tree(oid(2), &tree(&[(".gitattributes", EntryKind::Blob, &blob)]));
+        let long_line = format!("*.txt {}\n", "a".repeat(MAX_ATTRIBUTES_LINE_LENGTH));
+        assert_eq!(
+            ids(&special.check_blob(blob, long_line.as_bytes())),
+            [MessageId::GitattributesLineLength]
+        );
+    }
+
+    #[test]
+    fn unchecked_blobs_are_looked_up_on_finish() {
+        let (modules, attributes, missing) = (oid(1), oid(2), oid(3));
+        let mut special = SpecialFiles::default();
+        special.check_tree(
+            oid(4),
+            &tree(&[
+                (".gitattributes", EntryKind::Blob, &attributes),
+                (".gitmodules", EntryKind::Blob, &modules),
+            ]),
+        );
+        special.check_tree(oid(5), &tree(&[("GITMOD~1", EntryKind::Blob, &missing)]));
+        let mut findings = special.finish(|id| {
+            if *id == modules {
+                Some((Kind::Blob, b"[submodule \"a\"]\n\turl = -x\n".to_vec()))
+            } else if *id == attributes {
+                Some((Kind::Tree, Vec::new()))
+            } else {
+                None
+            }
+        });
+        findings.sort_by_key(|finding| finding.id.as_str());
+        assert_eq!(
+            ids(&findings),
+            [
+                MessageId::GitattributesBlob,
+                MessageId::GitmodulesMissing,
+                MessageId::GitmodulesUrl
+            ]
+        );
+    }
+}
@@ -0,0 +1,149 @@
+// Size limit for packs of unknown size, like git's `receive.maxInputSize`.
+//
+// Packs whose size is known up front are refused before reading them, but a push only reveals the size of its pack
+// once it ends. `LimitReader` wraps the pack input and fails once more than `max_pack_bytes` were read, and
+// `ReceivePack` turns that failure into the same `Resource` error the up-front check returns.
+//
+// Notes
+// - A pack may be complete with the read that exceeds the limit, so the monitor is checked after ingestion as well.
+
+use std::io::{self, BufRead, Read};
+use std::sync::{Arc, OnceLock};
+
+/// The reason a [`LimitReader`] stopped reading.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TooLarge {
+    /// The number of bytes read when the limit was exceeded.
+    pub read: u64,
+    /// The maximum number of bytes of the pack.
+    pub limit: u64,
+}
+
+impl std::fmt::Display for TooLarge {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "incoming pack exceeds size limit: at least {} > {}",
+            self.read, self.limit
+        )
+    }
+}
+
+impl std::error::Error for TooLarge {}
+
+/// A handle to learn whether a [`LimitReader`] exceeded its limit after the reader was handed off.
+#[derive(Debug, Clone, Default)]
+pub struct LimitMonitor(Arc<OnceLock<TooLarge>>);
+
+impl LimitMonitor {
+    /// Return the exceeded limit, if any.
+    pub fn exceeded(&self) -> Option<TooLarge> {
+        self.0.get().copied()
+    }
+
+    /// Return the exceeded limit as a [`Resource`](crate::Error::Resource) error, if any.
+    pub fn to_error(&self) -> Option<crate::Error> {
+        self.exceeded()
+            .map(|exceeded| crate::Error::Resource(exceeded.to_string()))
+    }
+}
+
+/// A reader that fails with [`io::ErrorKind::Other`] once more than a limit of bytes was read.
+pub struct LimitReader<R> {
+    inner: R,
+    limit: Option<u64>,
+    read: u64,
+    monitor: LimitMonitor,
+}
+
+impl<R> LimitReader<R> {
+    /// Wrap `inner`, allowing at most `limit` bytes to be read from it, or any amount if it is `None`.
+    pub fn new(inner: R, limit: Option<u64>) -> Self {
+        LimitReader {
+            inner,
+            limit,
+            read: 0,
+            monitor: LimitMonitor::default(),
+        }
+    }
+
+    /// A handle to check the limit once the reader was moved elsewhere.
+    pub fn monitor(&self) -> LimitMonitor {
+        self.monitor.clone()
+    }
+
+    fn check(&self) -> io::Result<()> {
+        match self.monitor.exceeded() {
+            Some(exceeded) => Err(io::Error::other(exceeded)),
+            None => Ok(()),
+        }
+    }
+
+    fn record(&mut self, bytes: usize) {
+        self.read += bytes as u64;
+        match self.limit {
+            Some(limit) if self.read > limit => {
+                let _ = self.monitor.0.set(TooLarge { read: self.read, limit });
+            }
+            _ => {}
+        }
+    }
+}
+
+impl<R: Read> Read for LimitReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.check()?;
+        let bytes = self.inner.read(buf)?;
+        self.record(bytes);
+        self.check()?;
+        Ok(bytes)
+    }
+}
+
+impl<R: BufRead> BufRead for LimitReader<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.check()?;
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.inner.consume(amt);
+        self.record(amt);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reading_past_the_limit_fails() {
+        let data = [0u8; 100];
+        let mut reader = LimitReader::new(&data[..], Some(60));
+        let monitor = reader.monitor();
+        let mut buf = [0u8; 50];
+        assert_eq!(reader.read(&mut buf).unwrap(), 50);
+        assert!(monitor.exceeded().is_none());
+        let err = reader.read(&mut buf).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Other);
+        assert_eq!(monitor.exceeded(), Some(TooLarge { read: 100, limit: 60 }));
+        assert!(matches!(monitor.to_error(), Some(crate::Error::Resource(_))));
+
+        let mut reader = LimitReader::new(&data[..], None);
+        assert_eq!(reader.read_to_end(&mut Vec::new()).unwrap(), 100);
+        assert!(reader.monitor().exceeded().is_none());
+    }
+
+    #[test]
+    fn consuming_past_the_limit_is_noticed() {
+        let data = [0u8; 100];
+        let mut reader = LimitReader::new(&data[..], Some(100));
+        reader.consume(100);
+        assert!(reader.monitor().exceeded().is_none(), "the limit itself is allowed");
+        let mut reader = LimitReader::new(&data[..], Some(99));
+        let monitor = reader.monitor();
+        reader.consume(100);
+        assert!(monitor.exceeded().is_some());
+        assert!(reader.fill_buf().is_err());
+    }
+}
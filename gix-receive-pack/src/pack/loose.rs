@@ -0,0 +1,260 @@
+// M3: Loose objects written by unpack-objects, and when to index a small pack instead.
+//
+// Packs with fewer objects than `transfer.unpackLimit` are exploded into loose objects in the quarantine, one
+// zlib stream per file. Resolving deltas and the filesystem block each file occupies make loose objects take
+// far more space than the pack they came from, which matters for pushes of many objects with a high unpack limit
+// because all of it sits in the quarantine until it's migrated. `LooseObjects` bounds this in two ways:
+// - `compression` writes loose objects with a higher zlib level than the fast default (core.looseCompression).
+// - `max_bytes` indexes packs whose loose objects are projected to take more space than that, even if they are
+//   within the unpack limit (receive.maxLooseObjectsSize).
+//
+// Notes
+// - The projection is a lower bound: each object takes at least a filesystem block, and resolving deltas never
+//   makes objects smaller than they are in the pack.
+// - The decision is returned as `UnpackDecision` and recorded in `ReceiveOutcome`, so operators can see why a
+//   pack was kept.
+// - Loose objects are in the zlib format git reads, other compression formats aren't an option.
+
+use crate::config::keys;
+use crate::pack::PackIngestPath;
+use crate::Error;
+use std::io::Write;
+use std::path::Path;
+
+/// The disk space each loose object is assumed to take in addition to its data, a typical filesystem block.
+pub const FILE_OVERHEAD: u64 = 4096;
+
+/// How loose objects are written when unpacking, and how much space they may take before packs are indexed instead.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LooseObjects {
+    /// The zlib level from 0 to 9 to compress loose objects with, or `None` for the fast default.
+    pub compression: Option<u32>,
+    /// Index packs within the unpack limit if their loose objects are projected to take more bytes than this.
+    pub max_bytes: Option<u64>,
+}
+
+/// Why a pack was unpacked into loose objects or indexed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnpackDecision {
+    /// The pack was indexed as it has more objects than `transfer.unpackLimit`, or no limit is set.
+    ObjectCount,
+    /// The pack was unpacked into loose objects projected to take `projected_bytes`.
+    Unpacked {
+        /// The disk space projected for the loose objects.
+        projected_bytes: u64,
+    },
+    /// The pack was indexed despite `transfer.unpackLimit`, as its loose objects were projected to take too much space.
+    TooLarge {
+        /// The disk space projected for the loose objects.
+        projected_bytes: u64,
+        /// The most disk space loose objects may take.
+        limit: u64,
+    },
+}
+
+impl UnpackDecision {
+    /// The path the pack is ingested with.
+    pub fn path(&self) -> PackIngestPath {
+        match self {
+            UnpackDecision::Unpacked { .. } => PackIngestPath::UnpackObjects,
+            UnpackDecision::ObjectCount | UnpackDecision::TooLarge { .. } => PackIngestPath::IndexPack,
+        }
+    }
+}
+
+impl LooseObjects {
+    /// Compress loose objects with zlib `level`, from 0 for none to 9 for the smallest objects.
+    pub fn with_compression(mut self, level: impl Into<Option<u32>>) -> Self {
+        self.compression = level.into().map(|level| level.min(9));
+        self
+    }
+
+    /// Index packs whose loose objects are projected to take more than `bytes`.
+    pub fn with_max_bytes(mut self, bytes: impl Into<Option<u64>>) -> Self {
+        self.max_bytes = bytes.into();
+        self
+    }
+
+    /// Load the settings from `core.looseCompression`, falling back to `core.compression`, and
+    /// `receive.maxLooseObjectsSize`.
+    ///
+    /// A compression level of `-1` selects the zlib default of 6, and a size of `0` means unlimited.
+    pub fn from_config(config: &gix_config::File<'static>) -> Result<Self, Error> {
+        let level = match keys::core::LOOSE_COMPRESSION.get(config)? {
+            Some(level) => Some((keys::core::LOOSE_COMPRESSION, level)),
+            None => keys::core::COMPRESSION
+                .get(config)?
+                .map(|level| (keys::core::COMPRESSION, level)),
+        };
+        let compression = match level {
+            None => None,
+            Some((_, -1)) => Some(6),
+            Some((_, level @ 0..=9)) => Some(level as u32),
+            Some((key, level)) => {
+                return Err(Error::Validation(format!(
+                    "invalid value for '{}': {level} is not a zlib level from -1 to 9",
+                    key.name()
+                )))
+            }
+        };
+        let max_bytes = match keys::receive::MAX_LOOSE_OBJECTS_SIZE.get(config)? {
+            None | Some(0) => None,
+            Some(bytes) => Some(u64::try_from(bytes).map_err(|_| {
+                Error::Validation(format!(
+                    "invalid value for '{}': must not be negative",
+                    keys::receive::MAX_LOOSE_OBJECTS_SIZE.name()
+                ))
+            })?),
+        };
+        Ok(LooseObjects { compression, max_bytes })
+    }
+
+    /// The disk space `objects` loose objects from a pack of `pack_bytes` are projected to take at least.
+    pub fn projected_bytes(objects: u64, pack_bytes: Option<u64>) -> u64 {
+        objects
+            .saturating_mul(FILE_OVERHEAD)
+            .saturating_add(pack_bytes.unwrap_or(0))
+    }
+
+    /// Decide whether to unpack a pack of `objects` and `pack_bytes`, for which the ingestion policy chose `path`.
+    ///
+    /// Only packs the policy chose to unpack are checked against [`max_bytes`](Self::max_bytes).
+    pub fn decide(&self, path: PackIngestPath, objects: Option<u64>, pack_bytes: Option<u64>) -> UnpackDecision {
+        if path == PackIngestPath::IndexPack {
+            return UnpackDecision::ObjectCount;
+        }
+        let projected_bytes = Self::projected_bytes(objects.unwrap_or(0), pack_bytes);
+        match self.max_bytes {
+            Some(limit) if projected_bytes > limit => UnpackDecision::TooLarge { projected_bytes, limit },
+            _ => UnpackDecision::Unpacked { projected_bytes },
+        }
+    }
+
+    /// Write `data` of `kind` as loose object into `store`, compressed with [`compression`](Self::compression).
+    ///
+    /// Objects that already exist are left as they are.
+    pub fn write(
+        &self,
+        store: &gix_odb::loose::Store,
+        kind: gix_object::Kind,
+        data: &[u8],
+    ) -> Result<gix_hash::ObjectId, Box<dyn std::error::Error + Send + Sync>> {
+        let Some(level) = self.compression else {
+            use gix_object::Write;
+            return store.write_buf(kind, data);
+        };
+        let id = gix_object::compute_hash(store.object_hash(), kind, data)?;
+        let path = object_path(store.path(), &id);
+        if path.is_file() {
+            return Ok(id);
+        }
+        let dir = path.parent().expect("objects are in a fan-out directory");
+        std::fs::create_dir_all(dir)?;
+        let tmp = dir.join(format!("tmp_obj_{id}"));
+        let file = std::fs::File::create(&tmp)?;
+        let mut encoder = flate2::write::ZlibEncoder::new(file, flate2::Compression::new(level));
+        encoder.write_all(&gix_object::encode::loose_header(kind, data.len() as u64))?;
+        encoder.write_all(data)?;
+        encoder.finish()?;
+        std::fs::rename(&tmp, &path)?;
+        Ok(id)
+    }
+}
+
+/// The path of the loose object `id` in the objects directory `objects_dir`.
+fn object_path(objects_dir: &Path, id: &gix_hash::oid) -> std::path::PathBuf {
+    let hex = id.to_hex().to_string();
+    objects_dir.join(&hex[..2]).join(&hex[2..])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn packs_with_large_loose_objects_are_indexed() {
+        let loose = LooseObjects::default().with_max_bytes(100_000);
+        assert_eq!(
+            loose.decide(PackIngestPath::IndexPack, Some(5), Some(100)),
+            UnpackDecision::ObjectCount
+        );
+        let decision = loose.decide(PackIngestPath::UnpackObjects, Some(10), Some(2_000));
+        assert_eq!(
+            decision,
+            UnpackDecision::Unpacked {
+                projected_bytes: 42_960
+            }
+        );
+        assert_eq!(decision.path(), PackIngestPath::UnpackObjects);
+        let decision = loose.decide(PackIngestPath::UnpackObjects, Some(100), Some(2_000));
+        assert_eq!(
+            decision,
+            UnpackDecision::TooLarge {
+                projected_bytes: 411_600,
+                limit: 100_000
+            }
+        );
+        assert_eq!(decision.path(), PackIngestPath::IndexPack);
+        assert_eq!(
+            LooseObjects::default().decide(PackIngestPath::UnpackObjects, Some(u64::MAX), None),
+            UnpackDecision::Unpacked {
+                projected_bytes: u64::MAX
+            },
+            "without a limit the policy decides alone"
+        );
+    }
+
+    #[test]
+    fn compressed_objects_are_readable() {
+        let tmp = gix_testtools::tempfile::tempdir().unwrap();
+        let store = gix_odb::loose::Store::at(tmp.path(), gix_hash::Kind::Sha1);
+        let data = "repetitive content\n".repeat(1000);
+        let fast = LooseObjects::default()
+            .write(&store, gix_object::Kind::Blob, data.as_bytes())
+            .unwrap();
+        let fast_size = std::fs::metadata(object_path(tmp.path(), &fast)).unwrap().len();
+        std::fs::remove_file(object_path(tmp.path(), &fast)).unwrap();
+
+        let best = LooseObjects::default()
+            .with_compression(9)
+            .write(&store, gix_object::Kind::Blob, data.as_bytes())
+            .unwrap();
+        assert_eq!(best, fast, "the compression doesn't change the id");
+        let best_size = std::fs::metadata(object_path(tmp.path(), &best)).unwrap().len();
+        assert!(best_size < fast_size, "{best_size} < {fast_size}");
+        let mut buf = Vec::new();
+        let object = store.try_find(&best, &mut buf).unwrap().expect("written");
+        assert_eq!((object.kind, object.data), (gix_object::Kind::Blob, data.as_bytes()));
+        assert_eq!(
+            std::fs::read_dir(tmp.path().join(&best.to_hex().to_string()[..2]))
+                .unwrap()
+                .count(),
+            1,
+            "no temporary files are left"
+        );
+    }
+
+    #[test]
+    fn from_config_reads_levels_and_limits() {
+        let config: gix_config::File<'static> = "[core]\n\tcompression = 3\n".parse().unwrap();
+        assert_eq!(LooseObjects::from_config(&config).unwrap().compression, Some(3));
+        let config: gix_config::File<'static> =
+            "[core]\n\tcompression = 3\n\tlooseCompression = -1\n[receive]\n\tmaxLooseObjectsSize = 1m\n"
+                .parse()
+                .unwrap();
+        assert_eq!(
+            LooseObjects::from_config(&config).unwrap(),
+            LooseObjects::default().with_compression(6).with_max_bytes(1 << 20)
+        );
+        assert_eq!(
+            LooseObjects::from_config(&gix_config::File::default()).unwrap(),
+            LooseObjects::default()
+        );
+
+        let config: gix_config::File<'static> = "[core]\n\tlooseCompression = 10\n".parse().unwrap();
+        assert_eq!(
+            LooseObjects::from_config(&config).unwrap_err().to_string(),
+            "validation error: invalid value for 'core.looseCompression': 10 is not a zlib level from -1 to 9"
+        );
+    }
+}
@@ -0,0 +1,308 @@
+// M9: Push manifests for abuse forensics.
+//
+// Once a push is migrated out of quarantine its objects are indistinguishable from everything else in the
+// repository, and objects that no ref points to are invisible to anything but a full scan. When
+// `receive.recordPushManifests` is enabled, every pack that a push adds is recorded in a manifest listing the
+// ids of all objects it introduced, so operators can later answer which push brought a given object in.
+//
+// Notes
+// - Manifests live in `objects/info/push-manifests/<pack checksum>.manifest`, outside of `objects/pack`, so
+//   repacks neither see nor delete them.
+// - The format is line-based text: a header line, `pack <checksum>`, `time <seconds since epoch>`, then one
+//   object id per line in index order.
+// - Recording is best-effort: a push that was accepted is never failed because its manifest couldn't be written.
+// - Manifests accumulate until pruned with `prune()`, typically from a maintenance job.
+
+use gix_hash::ObjectId;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The directory below the objects directory that holds push manifests.
+pub const MANIFEST_DIR: &str = "info/push-manifests";
+
+/// The first line of every manifest file.
+const HEADER: &str = "# gix-receive-pack push manifest v1";
+
+/// The file extension of manifest files.
+const EXTENSION: &str = "manifest";
+
+/// Whether push manifests should be recorded, as configured by `receive.recordPushManifests`.
+pub fn enabled_from_config(config: &gix_config::File<'static>) -> Result<bool, crate::Error> {
+    Ok(crate::config::keys::receive::RECORD_PUSH_MANIFESTS
+        .get(config)?
+        .unwrap_or(false))
+}
+
+/// The objects a single pack introduced into the repository.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PushManifest {
+    /// The checksum of the pack, which also names the pack and its manifest.
+    pub pack_checksum: ObjectId,
+    /// When the manifest was recorded, in seconds since the Unix epoch.
+    pub recorded_at: i64,
+    /// The ids of all objects in the pack, sorted as in the pack index.
+    pub objects: Vec<ObjectId>,
+}
+
+impl PushManifest {
+    /// Create a manifest for the pack whose index is at `index_path`, recorded now.
+    pub fn from_index(index_path: &Path, object_hash: gix_hash::Kind) -> Result<Self, crate::Error> {
+        let index = gix_pack::index::File::at(index_path, object_hash)
+            .map_err(|e| crate::Error::Validation(format!("cannot read pack index '{}': {e}", index_path.display())))?;
+        Ok(PushManifest {
+            pack_checksum: index.pack_checksum(),
+            recorded_at: seconds_since_epoch(SystemTime::now()),
+            objects: index.iter().map(|entry| entry.oid).collect(),
+        })
+    }
+
+    /// Return true if this manifest lists `id`.
+    pub fn contains(&self, id: &gix_hash::oid) -> bool {
+        self.objects.binary_search_by(|probe| probe.as_ref().cmp(id)).is_ok()
+    }
+
+    /// The file name of this manifest within the manifest directory.
+    pub fn file_name(&self) -> String {
+        format!("{}.{EXTENSION}", self.pack_checksum)
+    }
+
+    /// Serialize this manifest into its on-disk format.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let hex_len = self.pack_checksum.kind().len_in_hex() + 1;
+        let mut out = String::with_capacity(64 + self.objects.len() * hex_len);
+        out.push_str(HEADER);
+        out.push('\n');
+        out.push_str(&format!("pack {}\ntime {}\n", self.pack_checksum, self.recorded_at));
+        for id in &self.objects {
+            out.push_str(&id.to_string());
+            out.push('\n');
+        }
+        out.into_bytes()
+    }
+
+    /// Parse a manifest from its on-disk format.
+    pub fn from_bytes(data: &[u8]) -> Result<Self, crate::Error> {
+        let invalid = |what: &str| crate::Error::Validation(format!("invalid push manifest: {what}"));
+        let text = std::str::from_utf8(data).map_err(|_| invalid("not UTF-8"))?;
+        let mut lines = text.lines();
+        if lines.next() != Some(HEADER) {
+            return Err(invalid("unknown header"));
+        }
+        let pack_checksum = lines
+            .next()
+            .and_then(|line| line.strip_prefix("pack "))
+            .and_then(|hex| ObjectId::from_hex(hex.as_bytes()).ok())
+            .ok_or_else(|| invalid("missing or malformed pack line"))?;
+        let recorded_at = lines
+            .next()
+            .and_then(|line| line.strip_prefix("time "))
+            .and_then(|secs| secs.parse().ok())
+            .ok_or_else(|| invalid("missing or malformed time line"))?;
+        let objects = lines
+            .map(|line| ObjectId::from_hex(line.as_bytes()).map_err(|_| invalid("malformed object id")))
+            .collect::<Result<_, _>>()?;
+        Ok(PushManifest {
+            pack_checksum,
+            recorded_at,
+            objects,
+        })
+    }
+
+    /// Read the manifest file at `path`.
+    pub fn read(path: &Path) -> Result<Self, crate::Error> {
+        Self::from_bytes(&fs::read(path)?)
+    }
+
+    /// Write this manifest into `dir`, returning the path of the new file.
+    ///
+    /// The file is written under a temporary name and renamed into place, so readers never see partial manifests.
+    pub fn write_to(&self, dir: &Path) -> Result<PathBuf, crate::Error> {
+        fs::create_dir_all(dir)?;
+        let path = dir.join(self.file_name());
+        let tmp = dir.join(format!("{}.tmp", self.file_name()));
+        fs::write(&tmp, self.to_bytes())?;
+        if let Err(e) = fs::rename(&tmp, &path) {
+            let _ = fs::remove_file(&tmp);
+            return Err(e.into());
+        }
+        Ok(path)
+    }
+}
+
+/// What happened to the push manifests of a received pack.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ManifestRecord {
+    /// Recording is disabled (`receive.recordPushManifests` is false).
+    Disabled,
+    /// Manifests were written to these paths, one per pack the push added.
+    Written(Vec<PathBuf>),
+    /// Recording failed, the push itself was not affected.
+    Failed(String),
+}
+
+/// The outcome of pruning old manifests.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PruneOutcome {
+    /// The number of manifests that were removed.
+    pub removed: usize,
+    /// The number of manifests that were kept, including unreadable ones.
+    pub kept: usize,
+}
+
+/// The manifest directory of the repository whose objects directory is `objects_dir`.
+pub fn manifest_dir(objects_dir: &Path) -> PathBuf {
+    objects_dir.join(MANIFEST_DIR)
+}
+
+/// Create manifests for all packs in the quarantine objects directory `quarantine_objects_dir`.
+///
+/// This must run before the quarantine is migrated, as afterwards the new packs can't be told apart from existing ones.
+pub fn collect(quarantine_objects_dir: &Path, object_hash: gix_hash::Kind) -> Result<Vec<PushManifest>, crate::Error> {
+    let pack_dir = quarantine_objects_dir.join("pack");
+    if !pack_dir.is_dir() {
+        return Ok(Vec::new());
+    }
+    let mut manifests = Vec::new();
+    for entry in fs::read_dir(&pack_dir)? {
+        let path = entry?.path();
+        if path.extension().is_some_and(|ext| ext == "idx") {
+            manifests.push(PushManifest::from_index(&path, object_hash)?);
+        }
+    }
+    manifests.sort_by_key(|manifest| manifest.pack_checksum);
+    Ok(manifests)
+}
+
+/// Write `manifests` into the manifest directory of `objects_dir`.
+pub fn record(objects_dir: &Path, manifests: &[PushManifest]) -> ManifestRecord {
+    let dir = manifest_dir(objects_dir);
+    manifests
+        .iter()
+        .map(|manifest| manifest.write_to(&dir))
+        .collect::<Result<Vec<_>, _>>()
+        .map_or_else(|e| ManifestRecord::Failed(e.to_string()), ManifestRecord::Written)
+}
+
+/// Return all recorded manifests of the repository at `objects_dir` that list `id`, oldest first.
+///
+/// Unreadable manifests are skipped.
+pub fn find_introducing(objects_dir: &Path, id: &gix_hash::oid) -> Result<Vec<PushManifest>, crate::Error> {
+    let mut out: Vec<_> = manifest_paths(&manifest_dir(objects_dir))?
+        .into_iter()
+        .filter_map(|path| PushManifest::read(&path).ok())
+        .filter(|manifest| manifest.contains(id))
+        .collect();
+    out.sort_by_key(|manifest| manifest.recorded_at);
+    Ok(out)
+}
+
+/// Remove all manifests of the repository at `objects_dir` that were recorded before `cutoff`.
+///
+/// Manifests that can't be parsed are kept so they can be inspected by hand.
+pub fn prune(objects_dir: &Path, cutoff: SystemTime) -> Result<PruneOutcome, crate::Error> {
+    let cutoff = seconds_since_epoch(cutoff);
+    let mut out = PruneOutcome::default();
+    for path in manifest_paths(&manifest_dir(objects_dir))? {
+        match PushManifest::read(&path) {
+            Ok(manifest) if manifest.recorded_at < cutoff => {
+                fs::remove_file(&path)?;
+                out.removed += 1;
+            }
+            _ => out.kept += 1,
+        }
+    }
+    Ok(out)
+}
+
+fn manifest_paths(dir: &Path) -> Result<Vec<PathBuf>, crate::Error> {
+    if !dir.is_dir() {
+        return Ok(Vec::new());
+    }
+    let mut paths = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().is_some_and(|ext| ext == EXTENSION) {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+    Ok(paths)
+}
+
+fn seconds_since_epoch(time: SystemTime) -> i64 {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(elapsed) => elapsed.as_secs() as i64,
+        Err(before) => -(before.duration().as_secs() as i64),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn id(byte: u8) -> ObjectId {
+        ObjectId::from_bytes_or_panic(&[byte; 20])
+    }
+
+    fn manifest(checksum: u8, recorded_at: i64, objects: &[u8]) -> PushManifest {
+        PushManifest {
+            pack_checksum: id(checksum),
+            recorded_at,
+            objects: objects.iter().copied().map(id).collect(),
+        }
+    }
+
+    #[test]
+    fn roundtrip() {
+        let manifest = manifest(0xaa, 1_700_000_000, &[1, 2, 3]);
+        let bytes = manifest.to_bytes();
+        assert!(bytes.starts_with(HEADER.as_bytes()));
+        assert_eq!(PushManifest::from_bytes(&bytes).unwrap(), manifest);
+        assert!(manifest.contains(&id(2)));
+        assert!(!manifest.contains(&id(4)));
+
+        assert!(PushManifest::from_bytes(b"pack 00\n").is_err());
+        let mut truncated = bytes.clone();
+        truncated.truncate(bytes.len() - 5);
+        assert!(PushManifest::from_bytes(&truncated).is_err());
+    }
+
+    #[test]
+    fn find_and_prune() -> anyhow::Result<()> {
+        let tmp = gix_testtools::tempfile::tempdir()?;
+        let objects_dir = tmp.path();
+        let ManifestRecord::Written(paths) = record(
+            objects_dir,
+            &[manifest(0xaa, 100, &[1, 2]), manifest(0xbb, 200, &[2, 3])],
+        ) else {
+            panic!("manifests can be written");
+        };
+        assert_eq!(paths.len(), 2);
+        fs::write(manifest_dir(objects_dir).join("garbage.manifest"), b"not a manifest")?;
+
+        let introducing = find_introducing(objects_dir, &id(2))?;
+        assert_eq!(
+            introducing.iter().map(|m| m.pack_checksum).collect::<Vec<_>>(),
+            [id(0xaa), id(0xbb)]
+        );
+        assert_eq!(find_introducing(objects_dir, &id(3))?.len(), 1);
+        assert!(find_introducing(objects_dir, &id(9))?.is_empty());
+
+        let outcome = prune(objects_dir, UNIX_EPOCH + Duration::from_secs(150))?;
+        assert_eq!(outcome, PruneOutcome { removed: 1, kept: 2 });
+        assert!(find_introducing(objects_dir, &id(1))?.is_empty());
+        assert_eq!(find_introducing(objects_dir, &id(3))?.len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn collect_without_packs() -> anyhow::Result<()> {
+        let tmp = gix_testtools::tempfile::tempdir()?;
+        assert!(collect(tmp.path(), gix_hash::Kind::Sha1)?.is_empty());
+        assert!(find_introducing(tmp.path(), &id(1))?.is_empty());
+        assert_eq!(prune(tmp.path(), SystemTime::now())?, PruneOutcome::default());
+        Ok(())
+    }
+}
@@ -0,0 +1,217 @@
+// M9: Multi-pack-index maintenance after pack ingestion.
+//
+// Every push that takes the index-pack path leaves one more pack in objects/pack. Without a
+// multi-pack-index, lookups have to probe each pack index in turn, so read performance degrades
+// until the next maintenance run repacks. This module either rewrites the MIDX right away or leaves
+// a marker for maintenance tooling, depending on `receive.updateMultiPackIndex`.
+//
+// Notes
+// - Only the index-pack path adds packs; unpack-objects writes loose objects and never needs a MIDX update.
+// - The MIDX is written to `multi-pack-index.lock` and renamed into place, mirroring git's lockfile convention.
+
+#[cfg(feature = "progress")]
+use crate::error::{ErrorContext, PackIngestionError, Result};
+#[cfg(feature = "progress")]
+use crate::pack::PackIngestPath;
+#[cfg(feature = "progress")]
+use std::fs;
+#[cfg(feature = "progress")]
+use std::path::Path;
+use std::path::PathBuf;
+
+/// The name of the marker file left in `objects/pack` when the MIDX update is deferred.
+pub const PENDING_MARKER: &str = "multi-pack-index.pending";
+
+/// What to do with the multi-pack-index after a new pack was ingested (`receive.updateMultiPackIndex`).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MidxMode {
+    /// Leave the multi-pack-index alone.
+    #[default]
+    Off,
+    /// Rewrite the multi-pack-index to include the new pack.
+    Write,
+    /// Leave a marker so the next maintenance run rewrites the multi-pack-index.
+    Defer,
+}
+
+impl MidxMode {
+    /// Load the mode from `receive.updateMultiPackIndex`, which is a boolean or `defer`.
+    pub fn from_config(config: &gix_config::File<'static>) -> std::result::Result<Self, crate::Error> {
+        let key = crate::config::keys::receive::UPDATE_MULTI_PACK_INDEX;
+        let Some(value) = key.get(config)? else {
+            return Ok(MidxMode::Off);
+        };
+        if value.eq_ignore_ascii_case(b"defer") {
+            return Ok(MidxMode::Defer);
+        }
+        match gix_config::Boolean::try_from(value.as_ref()) {
+            Ok(gix_config::Boolean(true)) => Ok(MidxMode::Write),
+            Ok(gix_config::Boolean(false)) => Ok(MidxMode::Off),
+            Err(e) => Err(crate::Error::Validation(format!(
+                "invalid value for '{}' (expected boolean or 'defer'): {}",
+                key.name(),
+                e
+            ))),
+        }
+    }
+}
+
+/// Why the multi-pack-index was not touched.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MidxSkipReason {
+    /// `receive.updateMultiPackIndex` is off.
+    Disabled,
+    /// The pack was unpacked into loose objects, so no pack was added.
+    NoNewPack,
+}
+
+/// The decision taken for the multi-pack-index after ingestion, and its result.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MidxUpdate {
+    /// The multi-pack-index was left alone.
+    Skipped(MidxSkipReason),
+    /// The multi-pack-index was rewritten to cover all packs.
+    Written {
+        /// The number of packs covered by the new multi-pack-index.
+        packs: usize,
+        /// The checksum of the new multi-pack-index.
+        checksum: gix_hash::ObjectId,
+    },
+    /// A marker was left for the next maintenance run.
+    Deferred {
+        /// The path of the marker file.
+        marker: PathBuf,
+    },
+    /// Updating the multi-pack-index failed; the ingested pack is unaffected and readable without it.
+    Failed(String),
+}
+
+/// Update the multi-pack-index in `objects_dir` according to `mode`, after a pack was ingested via `path`.
+///
+/// This must only be called after the quarantine was migrated, as the new pack has to be in `objects_dir/pack`.
+#[cfg(feature = "progress")]
+pub fn update_after_ingest(
+    mode: MidxMode,
+    objects_dir: &Path,
+    path: PackIngestPath,
+    object_hash: gix_hash::Kind,
+    progress: &mut dyn gix_features::progress::DynNestedProgress,
+) -> Result<MidxUpdate> {
+    if let Some(skipped) = skip_reason(mode, path) {
+        return Ok(skipped);
+    }
+    let pack_dir = objects_dir.join("pack");
+    match mode {
+        MidxMode::Off => unreachable!("handled by skip_reason()"),
+        MidxMode::Defer => mark_pending(&pack_dir),
+        MidxMode::Write => {
+            let update = write(&pack_dir, object_hash, progress)?;
+            let marker = pack_dir.join(PENDING_MARKER);
+            if marker.exists() {
+                let _ = fs::remove_file(marker);
+            }
+            Ok(update)
+        }
+    }
+}
+
+#[cfg(feature = "progress")]
+fn skip_reason(mode: MidxMode, path: PackIngestPath) -> Option<MidxUpdate> {
+    if mode == MidxMode::Off {
+        Some(MidxUpdate::Skipped(MidxSkipReason::Disabled))
+    } else if path == PackIngestPath::UnpackObjects {
+        Some(MidxUpdate::Skipped(MidxSkipReason::NoNewPack))
+    } else {
+        None
+    }
+}
+
+#[cfg(feature = "progress")]
+fn mark_pending(pack_dir: &Path) -> Result<MidxUpdate> {
+    let marker = pack_dir.join(PENDING_MARKER);
+    fs::write(&marker, b"").map_err(|e| {
+        PackIngestionError::io(
+            "failed to write multi-pack-index marker",
+            ErrorContext::new("midx_defer").with_context("path", marker.display().to_string()),
+            e,
+        )
+    })?;
+    Ok(MidxUpdate::Deferred { marker })
+}
+
+#[cfg(feature = "progress")]
+fn write(
+    pack_dir: &Path,
+    object_hash: gix_hash::Kind,
+    progress: &mut dyn gix_features::progress::DynNestedProgress,
+) -> Result<MidxUpdate> {
+    use std::sync::atomic::AtomicBool;
+
+    let context = || ErrorContext::new("midx_write").with_context("pack_dir", pack_dir.display().to_string());
+    let index_paths = fs::read_dir(pack_dir)
+        .map_err(|e| PackIngestionError::io("failed to list pack directory", context(), e))?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|p| p.extension().is_some_and(|ext| ext == "idx"))
+        .collect::<Vec<_>>();
+    let packs = index_paths.len();
+
+    let lock_path = pack_dir.join("multi-pack-index.lock");
+    let mut lock = fs::File::create(&lock_path)
+        .map_err(|e| PackIngestionError::io("failed to create multi-pack-index lock", context(), e))?;
+    let should_interrupt = AtomicBool::new(false);
+    let outcome = gix_pack::multi_index::File::write_from_index_paths(
+        index_paths,
+        &mut lock,
+        progress,
+        &should_interrupt,
+        gix_pack::multi_index::write::Options { object_hash },
+    );
+    drop(lock);
+    let outcome = match outcome {
+        Ok(outcome) => outcome,
+        Err(e) => {
+            let _ = fs::remove_file(&lock_path);
+            return Err(PackIngestionError::index_pack_operation(
+                "failed to write multi-pack-index",
+                context(),
+                Some(Box::new(e)),
+            ));
+        }
+    };
+    fs::rename(&lock_path, pack_dir.join("multi-pack-index"))
+        .map_err(|e| PackIngestionError::io("failed to move multi-pack-index into place", context(), e))?;
+
+    Ok(MidxUpdate::Written {
+        packs,
+        checksum: outcome.multi_index_checksum,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mode_from_config() {
+        let parse = |text: &'static str| MidxMode::from_config(&gix_config::File::try_from(text).unwrap());
+        assert_eq!(parse("").unwrap(), MidxMode::Off);
+        assert_eq!(parse("[receive]\n\tupdateMultiPackIndex = true\n").unwrap(), MidxMode::Write);
+        assert_eq!(parse("[receive]\n\tupdateMultiPackIndex = off\n").unwrap(), MidxMode::Off);
+        assert_eq!(parse("[receive]\n\tupdateMultiPackIndex = defer\n").unwrap(), MidxMode::Defer);
+        assert!(parse("[receive]\n\tupdateMultiPackIndex = later\n").is_err());
+    }
+
+    #[cfg(feature = "progress")]
+    #[test]
+    fn skip_reasons() {
+        assert_eq!(
+            skip_reason(MidxMode::Off, PackIngestPath::IndexPack),
+            Some(MidxUpdate::Skipped(MidxSkipReason::Disabled))
+        );
+        assert_eq!(
+            skip_reason(MidxMode::Write, PackIngestPath::UnpackObjects),
+            Some(MidxUpdate::Skipped(MidxSkipReason::NoNewPack))
+        );
+        assert_eq!(skip_reason(MidxMode::Defer, PackIngestPath::IndexPack), None);
+    }
+}
@@ -2,8 +2,12 @@
 //
 // This module provides:
 // - Policy to choose between index-pack and unpack-objects based on transfer.unpackLimit.
-// - Quarantine lifecycle with activation (tmp ODB + alternates), migration on success, and drop on failure.
-// - Blocking ingestion from a BufRead using gix-pack::Bundle into the quarantine, with thin-pack base lookup via gix-odb.
+//   Packs whose loose objects would take too much space are indexed anyway, see `loose`.
+// - Size limits for packs of unknown size, enforced while reading them, see `limit`.
+// - Quarantine lifecycle with activation (tmp ODB + alternates), journaled migration on success, and drop on failure.
+// - Blocking ingestion from a BufRead using gix-pack::Bundle into the quarantine, with thin-pack base lookup via
+//   gix-odb that can be turned off, see `thin`. Packs on disk with ref-deltas against in-pack bases are rewritten
+//   to use ofs-deltas first, see `ref_delta`.
 // - Fsck integration for object validation with configurable strictness levels.
 //
 // Notes
@@ -11,8 +15,18 @@
 // - We route UnpackObjects to IndexPack for now; a dedicated unpack path can be added later if needed.
 
 pub mod fsck;
+pub mod limit;
+pub mod loose;
+pub mod manifest;
+pub mod midx;
+pub mod precious;
 pub mod quarantine;
+pub mod rate;
+#[cfg(feature = "progress")]
+mod ref_delta;
+pub mod stall;
 pub mod streaming;
+pub mod thin;
 
 use crate::error::{ErrorContext, PackIngestionError, Result};
 
@@ -23,9 +37,20 @@ use std::fs;
 use std::path::PathBuf;
 
 pub use fsck::{FsckConfig, FsckLevel, FsckMessageLevel, FsckResults, FsckValidator};
+pub use limit::{LimitMonitor, LimitReader, TooLarge};
+pub use loose::{LooseObjects, UnpackDecision};
+pub use manifest::{ManifestRecord, PruneOutcome, PushManifest};
+pub use midx::{MidxMode, MidxSkipReason, MidxUpdate};
+pub use precious::{MaintenanceOp, Protection};
+pub use quarantine::{MigrationRecovery, Quarantine};
+#[cfg(feature = "progress")]
+pub use rate::CountingProgress;
+pub use rate::{IngestionRates, RateExceeded, RateKind, RateLimits, RateMonitor, RateReader};
+pub use stall::{StallDetection, StallMonitor, StallReader, Stalled};
 pub use streaming::{
     BufferPool, MemoryStats, MemoryTracker, StreamingBufReader, StreamingConfig, StreamingPackReader, StreamingStats,
 };
+pub use thin::ThinPacks;
 
 /// CountingReader is only used in streaming pack operations
 #[cfg(all(feature = "progress", feature = "pack-streaming"))]
@@ -55,6 +80,35 @@ impl<R: std::io::BufRead> std::io::BufRead for CountingReader<R> {
     }
 }
 
+/// Read and decode the 12-byte header of the pack file at `path`, returning its version and object count.
+pub fn read_pack_header(path: &std::path::Path) -> Result<(gix_pack::data::Version, u32)> {
+    use std::io::Read;
+
+    let context = ErrorContext::new("read-pack-header").with_context("pack_path", path.display().to_string());
+    let mut header = [0u8; 12];
+    fs::File::open(path)
+        .and_then(|mut file| file.read_exact(&mut header))
+        .map_err(|e| PackIngestionError::io("failed to read pack header", context.clone(), e))?;
+    gix_pack::data::header::decode(&header)
+        .map_err(|e| PackIngestionError::pack_parsing("invalid pack header", context, Some(Box::new(e))))
+}
+
+/// Read the 12-byte header of the pack at the start of `input`, returning the bytes read along with the object count
+/// it announces, or `None` if it isn't a valid header.
+///
+/// The bytes have to be read again before the rest of `input`, as the pack isn't complete without them.
+pub fn peek_pack_header(input: &mut impl std::io::Read) -> std::io::Result<(Vec<u8>, Option<u32>)> {
+    use std::io::Read;
+
+    let mut header = Vec::with_capacity(12);
+    input.take(12).read_to_end(&mut header)?;
+    let object_count = <&[u8; 12]>::try_from(header.as_slice())
+        .ok()
+        .and_then(|header| gix_pack::data::header::decode(header).ok())
+        .map(|(_version, object_count)| object_count);
+    Ok((header, object_count))
+}
+
 /// Which path to use to ingest an incoming pack.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum PackIngestPath {
@@ -142,6 +196,12 @@ pub struct PackIngestor {
     fsck_validator: Option<FsckValidator>,
     /// Streaming configuration for memory management
     streaming_config: StreamingConfig,
+    /// Whether bases of thin packs are looked up
+    thin_packs: ThinPacks,
+    /// The number of bases the last ingested pack took from the main object database
+    thin_bases: std::sync::atomic::AtomicU32,
+    /// How loose objects are written and when packs are indexed instead
+    loose: LooseObjects,
 }
 
 impl Default for PackIngestor {
@@ -149,6 +209,9 @@ impl Default for PackIngestor {
         Self {
             fsck_validator: None,
             streaming_config: StreamingConfig::default(),
+            thin_packs: ThinPacks::default(),
+            thin_bases: Default::default(),
+            loose: LooseObjects::default(),
         }
     }
 }
@@ -159,6 +222,9 @@ impl PackIngestor {
         Self {
             fsck_validator: fsck_config.map(FsckValidator::new),
             streaming_config: StreamingConfig::default(),
+            thin_packs: ThinPacks::default(),
+            thin_bases: Default::default(),
+            loose: LooseObjects::default(),
         }
     }
 
@@ -167,6 +233,9 @@ impl PackIngestor {
         Self {
             fsck_validator: Some(FsckValidator::new(fsck_config)),
             streaming_config: StreamingConfig::default(),
+            thin_packs: ThinPacks::default(),
+            thin_bases: Default::default(),
+            loose: LooseObjects::default(),
         }
     }
 
@@ -175,6 +244,9 @@ impl PackIngestor {
         Self {
             fsck_validator: None,
             streaming_config: StreamingConfig::default(),
+            thin_packs: ThinPacks::default(),
+            thin_bases: Default::default(),
+            loose: LooseObjects::default(),
         }
     }
 
@@ -183,6 +255,9 @@ impl PackIngestor {
         Self {
             fsck_validator: fsck_config.map(FsckValidator::new),
             streaming_config,
+            thin_packs: ThinPacks::default(),
+            thin_bases: Default::default(),
+            loose: LooseObjects::default(),
         }
     }
 
@@ -195,6 +270,35 @@ impl PackIngestor {
     pub fn set_streaming_config(&mut self, config: StreamingConfig) {
         self.streaming_config = config;
     }
+
+    /// Set whether the bases of thin packs are looked up in the main object database.
+    pub fn with_thin_packs(mut self, thin_packs: ThinPacks) -> Self {
+        self.thin_packs = thin_packs;
+        self
+    }
+
+    /// Set how loose objects are compressed when unpacking, and when packs are indexed to avoid too many of them.
+    pub fn with_loose_objects(mut self, loose: LooseObjects) -> Self {
+        self.loose = loose;
+        self
+    }
+
+    /// The number of delta bases the last ingested pack took from the main object database.
+    ///
+    /// This is non-zero only for thin packs, as bases are looked up just for ref-deltas whose base isn't in the pack.
+    pub fn thin_bases(&self) -> u32 {
+        self.thin_bases.load(std::sync::atomic::Ordering::Relaxed)
+    }
+
+    /// Return a lookup for bases of thin packs in `odb`, unless thin packs are refused, and reset the base count.
+    #[cfg(feature = "progress")]
+    fn base_lookup<'a>(&'a self, odb: Option<&'a gix_odb::Handle>) -> Option<thin::BaseLookup<'a>> {
+        self.thin_bases.store(0, std::sync::atomic::Ordering::Relaxed);
+        match self.thin_packs {
+            ThinPacks::Resolve => odb.map(|odb| thin::BaseLookup::new(odb, &self.thin_bases)),
+            ThinPacks::Never => None,
+        }
+    }
 }
 
 /// Pack ingestion controller that handles strategy selection and fallback logic.
@@ -535,13 +639,13 @@ impl PackIngestor {
             ..Default::default()
         };
         let mut write_progress = progress.add_child("write pack".to_string());
-        let _write_outcome = match &thin_pack_lookup {
-            Some(handle) => gix_pack::Bundle::write_to_directory(
+        let write_outcome = match self.base_lookup(thin_pack_lookup.as_ref()) {
+            Some(lookup) => gix_pack::Bundle::write_to_directory(
                 input,
                 Some(pack_dir.as_path()),
                 &mut write_progress,
                 &should_interrupt,
-                Some(handle.clone()),
+                Some(lookup),
                 write_opts,
             )
             .map_err(|e| {
@@ -593,7 +697,7 @@ impl PackIngestor {
 
         // 4. Explode pack contents into loose objects in quarantine objects_dir.
         //    Keep verification minimal here; rely on quarantine and later fsck when enabled.
-        let _explode_progress = progress.add_child("explode pack".to_string());
+        let explode_progress = progress.add_child("explode pack".to_string());
         // Reuse gitoxide-core explode semantics if available directly through gix APIs.
         // The explode operation in gitoxide-core maps to gix APIs, so we replicate the essence:
         // - Open the pack and stream objects into loose::Store.
@@ -615,7 +719,6 @@ impl PackIngestor {
         // Create an inflate instance for decompression
         let mut inflate = gix_features::zlib::Inflate::default();
         let mut decoded_buf = Vec::new();
-        let _explode_progress = progress.add_child("explode pack".to_string());
         
         // Set up progress tracking
         let _num_objects = pack_file.num_objects();
@@ -682,8 +785,7 @@ impl PackIngestor {
             })?;
             
             // Write the decoded object as a loose object
-            use gix_object::Write;
-            let _oid = loose_store.write_buf(outcome.kind, &decoded_buf).map_err(|e| {
+            let _oid = self.loose.write(&loose_store, outcome.kind, &decoded_buf).map_err(|e| {
                 PackIngestionError::unpack_objects_operation(
                     "failed to write loose object",
                     context.clone().with_elapsed(start_time.elapsed()),
@@ -692,8 +794,8 @@ impl PackIngestor {
             })?;
             
             _entries_processed += 1;
-            // Update progress message
-            let _ = explode_progress; // Progress updates handled internally
+            // Count the exploded object
+            gix_features::progress::Count::inc(&explode_progress);
             
             // Check for interruption
             if should_interrupt.load(std::sync::atomic::Ordering::Relaxed) {
@@ -764,7 +866,10 @@ impl PackIngestor {
         };
 
         // 6. Remove temporary pack artifacts to mimic unpack-objects behavior.
-        //    Keep *.keep if present, else remove both pack and idx.
+        //    Keep *.keep if present, else remove both pack and idx, along with the keep file written to protect them.
+        if let Some(keep_path) = &write_outcome.keep_path {
+            let _ = fs::remove_file(keep_path);
+        }
         if let Ok(entries) = fs::read_dir(&pack_dir) {
             for entry in entries.flatten() {
                 let p = entry.path();
@@ -841,15 +946,15 @@ impl PackIngestor {
             ..Default::default()
         };
 
-        let _out: Outcome = match &thin_pack_lookup {
-            Some(handle) => {
+        let _out: Outcome = match self.base_lookup(thin_pack_lookup.as_ref()) {
+            Some(lookup) => {
                 // Use thin-pack lookup to fix up deltas against the main ODB if required.
                 gix_pack::Bundle::write_to_directory(
                     input,
                     Some(pack_dir.as_path()),
                     progress,
                     &should_interrupt,
-                    Some(handle.clone()),
+                    Some(lookup),
                     options,
                 )
                 .map_err(|e| {
@@ -938,6 +1043,91 @@ impl PackIngestor {
         Ok(fsck_results)
     }
 
+    /// Ingest a pack that already resides on disk, e.g. one uploaded out-of-band or received via rsync.
+    ///
+    /// The object count is read from the pack header and handed to `policy` to choose between
+    /// index-pack and unpack-objects, which is reconsidered if the loose objects would take too much space.
+    /// The file is then fed through the same ingestion and fsck pipeline as a pack received over the wire.
+    ///
+    /// - `pack_path`: path to the `.pack` file to ingest; it is only read, never moved or removed.
+    /// - `quarantine_objects_dir`: the quarantine '.git/objects' directory.
+    /// - `policy`: ingestion policy used to select the ingestion path.
+    /// - `thin_pack_lookup`: Optional object finder to resolve thin-pack bases (typically the main ODB).
+    /// - `progress`: progress sink used by gix-pack.
+    ///
+    /// Returns the ingestion path that was taken and why, along with the fsck results.
+    pub fn ingest_pack_file(
+        &self,
+        pack_path: &std::path::Path,
+        quarantine_objects_dir: &std::path::Path,
+        policy: &IngestionPolicy,
+        thin_pack_lookup: Option<gix_odb::Handle>,
+        progress: &mut dyn gix_features::progress::DynNestedProgress,
+    ) -> Result<(PackIngestPath, UnpackDecision, FsckResults)> {
+        let context = ErrorContext::new("ingest-pack-file")
+            .with_context("pack_path", pack_path.display().to_string());
+
+        let pack_size = fs::metadata(pack_path)
+            .map_err(|e| PackIngestionError::io("failed to stat pack file", context.clone(), e))?
+            .len();
+        let (_version, num_objects) = read_pack_header(pack_path).map_err(|e| match e {
+            PackIngestionError::PackParsing { message, source_message, .. } => PackIngestionError::PackParsing {
+                message,
+                context: context.clone().with_pack_size(pack_size),
+                source_message,
+            },
+            other => other,
+        })?;
+
+        let num_objects = Some(u64::from(num_objects));
+        let decision = self
+            .loose
+            .decide(policy.choose_path(num_objects), num_objects, Some(pack_size));
+        let path = decision.path();
+        // Without the streaming bundle writer there is no unpack-objects path, so index the pack instead.
+        #[cfg(not(feature = "pack-streaming"))]
+        let path = match path {
+            PackIngestPath::UnpackObjects => PackIngestPath::IndexPack,
+            other => other,
+        };
+        let rewritten_path = quarantine_objects_dir.join("incoming-ofs-deltas.pack");
+        let (pack_path, pack_size) =
+            if ref_delta::rewrite_in_pack_ref_deltas(pack_path, &rewritten_path, thin_pack_lookup.as_ref())? {
+                let size = fs::metadata(&rewritten_path)
+                    .map_err(|e| PackIngestionError::io("failed to stat rewritten pack file", context.clone(), e))?
+                    .len();
+                (rewritten_path.as_path(), size)
+            } else {
+                (pack_path, pack_size)
+            };
+        let file = fs::File::open(pack_path)
+            .map_err(|e| PackIngestionError::io("failed to open pack file", context.clone(), e))?;
+        let mut input = std::io::BufReader::new(file);
+
+        let fsck_results = match path {
+            PackIngestPath::IndexPack => self.index_pack(
+                &mut input,
+                quarantine_objects_dir,
+                Some(pack_size),
+                thin_pack_lookup,
+                progress,
+            ),
+            #[cfg(feature = "pack-streaming")]
+            PackIngestPath::UnpackObjects => self.unpack_objects(
+                &mut input,
+                quarantine_objects_dir,
+                Some(pack_size),
+                thin_pack_lookup,
+                progress,
+            ),
+            #[cfg(not(feature = "pack-streaming"))]
+            PackIngestPath::UnpackObjects => unreachable!("unpack-objects is routed to index-pack above"),
+        };
+        // The rewritten pack was copied into the quarantine pack directory, if it was needed at all.
+        fs::remove_file(&rewritten_path).ok();
+        Ok((path, decision, fsck_results?))
+    }
+
     /// Streaming version of index_pack with bounded memory usage.
     ///
     /// This method uses a streaming reader to process pack data with controlled memory usage,
@@ -997,13 +1187,13 @@ impl PackIngestor {
                 inner: streaming_wrapper,
                 counter: bytes_counter.clone(),
             };
-            match &thin_pack_lookup {
-                Some(handle) => gix_pack::Bundle::write_to_directory(
+            match self.base_lookup(thin_pack_lookup.as_ref()) {
+                Some(lookup) => gix_pack::Bundle::write_to_directory(
                     &mut counting_reader,
                     Some(pack_dir.as_path()),
                     &mut pack_progress,
                     &should_interrupt,
-                    Some(handle.clone()),
+                    Some(lookup),
                     options,
                 )
                 .map_err(|e| {
@@ -1036,6 +1226,7 @@ impl PackIngestor {
             bytes_read: bytes_counter.load(std::sync::atomic::Ordering::SeqCst),
             memory_stats: memory_tracker.stats(),
             buffer_size: self.streaming_config.buffer_size,
+            rates: Default::default(),
         };
 
         // Perform fsck validation if configured
@@ -1152,18 +1343,18 @@ impl PackIngestor {
         let write_opts = WriteOptions { ..Default::default() };
 
         let mut write_progress = progress.add_child("write pack".to_string());
-        let _write_outcome = {
+        let write_outcome = {
             let mut counting_reader = CountingReader {
                 inner: streaming_wrapper,
                 counter: bytes_counter.clone(),
             };
-            match &thin_pack_lookup {
-                Some(handle) => gix_pack::Bundle::write_to_directory(
+            match self.base_lookup(thin_pack_lookup.as_ref()) {
+                Some(lookup) => gix_pack::Bundle::write_to_directory(
                     &mut counting_reader,
                     Some(pack_dir.as_path()),
                     &mut write_progress,
                     &should_interrupt,
-                    Some(handle.clone()),
+                    Some(lookup),
                     write_opts,
                 )
                 .map_err(|e| {
@@ -1219,6 +1410,7 @@ impl PackIngestor {
             bytes_read: bytes_counter.load(std::sync::atomic::Ordering::SeqCst),
             memory_stats: memory_tracker.stats(),
             buffer_size: self.streaming_config.buffer_size,
+            rates: Default::default(),
         };
 
         // Explode pack contents into loose objects with memory management
@@ -1314,8 +1506,7 @@ impl PackIngestor {
             })?;
             
             // Write the decoded object as a loose object
-            use gix_object::Write;
-            let _oid = loose_store.write_buf(outcome.kind, &decoded_buf).map_err(|e| {
+            let _oid = self.loose.write(&loose_store, outcome.kind, &decoded_buf).map_err(|e| {
                 PackIngestionError::unpack_objects_operation(
                     "failed to write loose object (streaming)",
                     context.clone().with_elapsed(start_time.elapsed()),
@@ -1397,7 +1588,10 @@ impl PackIngestor {
             }
         };
 
-        // Remove temporary pack artifacts
+        // Remove temporary pack artifacts, along with the keep file written to protect them
+        if let Some(keep_path) = &write_outcome.keep_path {
+            let _ = fs::remove_file(keep_path);
+        }
         if let Ok(entries) = fs::read_dir(&pack_dir) {
             for entry in entries.flatten() {
                 let p = entry.path();
@@ -0,0 +1,209 @@
+// M9: Protection of precious objects and kept packs against destructive maintenance.
+//
+// Repositories that share their object database with others (e.g. through alternates) set
+// `extensions.preciousObjects`, and git then refuses every operation that may delete objects. Individual packs
+// are protected the same way by a `.keep` file next to them. Post-receive maintenance has to honor both, so all
+// maintenance steps are described as a `MaintenanceOp` and checked against the repository's `Protection` first.
+//
+// Notes
+// - Operations that only add files (multi-pack-index, commit-graph, new packs) are always allowed.
+// - Ingestion never replaces existing files in the objects directory, see `Quarantine::migrate_on_success()`.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// A maintenance operation that may run on a repository after a push.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MaintenanceOp {
+    /// Rewrite the multi-pack-index.
+    WriteMultiPackIndex,
+    /// Add a layer to the commit-graph.
+    WriteCommitGraph,
+    /// Combine packs into a new one.
+    Repack {
+        /// Delete the packs and loose objects that became redundant, like `git repack -d`.
+        delete_redundant: bool,
+    },
+    /// Delete unreachable loose objects, like `git prune`.
+    PruneLooseObjects,
+    /// Delete the pack at the given path, along with its index and auxiliary files.
+    DeletePack(PathBuf),
+}
+
+impl MaintenanceOp {
+    /// Return true if this operation may delete objects from the repository.
+    pub fn is_destructive(&self) -> bool {
+        match self {
+            MaintenanceOp::WriteMultiPackIndex | MaintenanceOp::WriteCommitGraph => false,
+            MaintenanceOp::Repack { delete_redundant } => *delete_redundant,
+            MaintenanceOp::PruneLooseObjects | MaintenanceOp::DeletePack(_) => true,
+        }
+    }
+
+    fn describe(&self) -> String {
+        match self {
+            MaintenanceOp::WriteMultiPackIndex => "write the multi-pack-index".into(),
+            MaintenanceOp::WriteCommitGraph => "write the commit-graph".into(),
+            MaintenanceOp::Repack { delete_redundant: true } => "repack and delete redundant packs".into(),
+            MaintenanceOp::Repack {
+                delete_redundant: false,
+            } => "repack".into(),
+            MaintenanceOp::PruneLooseObjects => "prune loose objects".into(),
+            MaintenanceOp::DeletePack(path) => format!("delete pack '{}'", path.display()),
+        }
+    }
+}
+
+/// The protections a repository places on its objects.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Protection {
+    /// `extensions.preciousObjects` is set, so no object may ever be deleted.
+    pub precious_objects: bool,
+    /// The packs in `objects/pack` that have a `.keep` file, as paths to their `.pack` files.
+    pub kept_packs: Vec<PathBuf>,
+}
+
+impl Protection {
+    /// Obtain the protections of the repository with the objects directory `objects_dir` and configuration `config`.
+    pub fn from_config(objects_dir: &Path, config: &gix_config::File<'static>) -> Result<Self, crate::Error> {
+        Ok(Protection {
+            precious_objects: crate::config::keys::extensions::PRECIOUS_OBJECTS
+                .get(config)?
+                .unwrap_or(false),
+            kept_packs: kept_packs(&objects_dir.join("pack"))?,
+        })
+    }
+
+    /// Obtain the protections of the repository with the objects directory `objects_dir`, reading the repository
+    /// configuration from the `config` file next to it.
+    pub fn at(objects_dir: &Path) -> Result<Self, crate::Error> {
+        let config_path = objects_dir
+            .parent()
+            .map(|git_dir| git_dir.join("config"))
+            .filter(|path| path.is_file());
+        let config = match config_path {
+            Some(path) => {
+                gix_config::File::from_path_no_includes(path.clone(), gix_config::Source::Local).map_err(|e| {
+                    crate::Error::Validation(format!("cannot read configuration '{}': {e}", path.display()))
+                })?
+            }
+            None => gix_config::File::default(),
+        };
+        Self::from_config(objects_dir, &config)
+    }
+
+    /// Return true if the pack at `pack` (with any of its extensions) is protected by a `.keep` file.
+    pub fn is_kept(&self, pack: &Path) -> bool {
+        let pack = pack.with_extension("pack");
+        self.kept_packs.contains(&pack)
+    }
+
+    /// Return an error if `op` must not run on this repository.
+    pub fn check(&self, op: &MaintenanceOp) -> Result<(), crate::Error> {
+        if self.precious_objects && op.is_destructive() {
+            return Err(crate::Error::MaintenanceRefused(format!(
+                "refusing to {}: the repository sets extensions.preciousObjects",
+                op.describe()
+            )));
+        }
+        if let MaintenanceOp::DeletePack(pack) = op {
+            if self.is_kept(pack) {
+                return Err(crate::Error::MaintenanceRefused(format!(
+                    "refusing to {}: it is marked with a .keep file",
+                    op.describe()
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn kept_packs(pack_dir: &Path) -> Result<Vec<PathBuf>, crate::Error> {
+    if !pack_dir.is_dir() {
+        return Ok(Vec::new());
+    }
+    let mut packs = Vec::new();
+    for entry in fs::read_dir(pack_dir)? {
+        let path = entry?.path();
+        if path.extension().is_some_and(|ext| ext == "keep") {
+            packs.push(path.with_extension("pack"));
+        }
+    }
+    packs.sort();
+    Ok(packs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn protection(config: &'static str, objects_dir: &Path) -> Protection {
+        Protection::from_config(objects_dir, &gix_config::File::try_from(config).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn precious_repositories_refuse_destructive_operations() {
+        let tmp = gix_testtools::tempfile::tempdir().unwrap();
+        let precious = protection("[extensions]\n\tpreciousObjects = true\n", tmp.path());
+        assert!(precious.precious_objects);
+        for op in [
+            MaintenanceOp::WriteMultiPackIndex,
+            MaintenanceOp::WriteCommitGraph,
+            MaintenanceOp::Repack {
+                delete_redundant: false,
+            },
+        ] {
+            precious.check(&op).expect("additive operations are allowed");
+        }
+        for op in [
+            MaintenanceOp::Repack { delete_redundant: true },
+            MaintenanceOp::PruneLooseObjects,
+            MaintenanceOp::DeletePack(tmp.path().join("pack/pack-1.pack")),
+        ] {
+            let err = precious.check(&op).unwrap_err();
+            assert_eq!(err.kind(), crate::Kind::Permission);
+            assert!(err.to_string().contains("extensions.preciousObjects"), "{err}");
+        }
+
+        let plain = protection("", tmp.path());
+        plain.check(&MaintenanceOp::PruneLooseObjects).unwrap();
+        plain.check(&MaintenanceOp::Repack { delete_redundant: true }).unwrap();
+    }
+
+    #[test]
+    fn kept_packs_cannot_be_deleted() {
+        let tmp = gix_testtools::tempfile::tempdir().unwrap();
+        let objects_dir = tmp.path().join("objects");
+        let pack_dir = objects_dir.join("pack");
+        fs::create_dir_all(&pack_dir).unwrap();
+        for name in ["pack-1.pack", "pack-1.idx", "pack-1.keep", "pack-2.pack", "pack-2.idx"] {
+            fs::write(pack_dir.join(name), b"").unwrap();
+        }
+
+        let protection = Protection::at(&objects_dir).unwrap();
+        assert!(!protection.precious_objects);
+        assert_eq!(protection.kept_packs, [pack_dir.join("pack-1.pack")]);
+        assert!(protection.is_kept(&pack_dir.join("pack-1.idx")));
+
+        let err = protection
+            .check(&MaintenanceOp::DeletePack(pack_dir.join("pack-1.pack")))
+            .unwrap_err();
+        assert!(err.to_string().contains(".keep"), "{err}");
+        protection
+            .check(&MaintenanceOp::DeletePack(pack_dir.join("pack-2.pack")))
+            .unwrap();
+    }
+
+    #[test]
+    fn repository_config_is_read() {
+        let tmp = gix_testtools::tempfile::tempdir().unwrap();
+        let objects_dir = tmp.path().join("objects");
+        fs::create_dir_all(&objects_dir).unwrap();
+        fs::write(
+            tmp.path().join("config"),
+            "[core]\n\trepositoryformatversion = 1\n[extensions]\n\tpreciousObjects = true\n",
+        )
+        .unwrap();
+        assert!(Protection::at(&objects_dir).unwrap().precious_objects);
+    }
+}
@@ -1,7 +1,33 @@
-use std::path::PathBuf;
+// M3: Quarantine for incoming objects, migrated into the main object database once they were accepted.
+//
+// Migration is crash-safe: before anything is moved, the files to move are written to a journal next to the
+// quarantine directory, `quarantine/<name>.journal`. Files are then renamed in an order that never lets the main
+// object database see a pack without its data: loose objects and pack data first, `.idx` files last, as git only
+// considers packs that have an index. Directories are fsynced after each step, so a crash leaves every journaled
+// file either in the quarantine or in the main object database, and the journal is removed only once all of them
+// were moved.
+//
+// `Quarantine::recover()` finishes migrations a crash interrupted and is meant to run at startup, before pushes are
+// served, as it can't tell interrupted migrations from those still running in other processes:
+// - If every journaled file is still in the quarantine or already migrated, the migration is completed.
+// - If files are missing in both places, the quarantine was tampered with and the migration is rolled back by
+//   removing the migrated pack files, indices first. Migrated loose objects are left, as they are complete on
+//   their own and unreferenced objects are harmless.
+//
+// Notes
+// - Files that already exist in the main object database are named after their content, so they are kept as is and
+//   never journaled. Rolling back thus only removes files this migration created.
+// - Quarantines without journal were never migrated and are left alone.
+
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// The first line of a migration journal.
+const JOURNAL_HEADER: &str = "# gix-receive-pack quarantine migration v1";
 
 /// Quarantine directory for safe pack ingestion.
-/// 
+///
 /// This provides a temporary directory structure that can be safely cleaned up
 /// if the operation fails, or migrated to the main objects directory on success.
 pub struct Quarantine {
@@ -13,6 +39,25 @@ pub struct Quarantine {
     active: bool,
 }
 
+/// How an interrupted migration was finished by [`Quarantine::recover()`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MigrationRecovery {
+    /// All files of the migration were moved into the main object database.
+    Completed {
+        /// The quarantine directory that was migrated.
+        quarantine: PathBuf,
+        /// The number of files that were still in the quarantine and had to be moved.
+        moved: usize,
+    },
+    /// Files of the migration were lost, so the packs it already migrated were removed again.
+    RolledBack {
+        /// The quarantine directory whose migration was undone.
+        quarantine: PathBuf,
+        /// The migrated pack files that were removed from the main object database.
+        removed: usize,
+    },
+}
+
 impl Quarantine {
     /// Create a new quarantine for the given objects directory.
     pub fn new(main_objects_dir: PathBuf) -> Self {
@@ -22,102 +67,257 @@ impl Quarantine {
             active: false,
         }
     }
-    
+
     /// Activate the quarantine by creating the temporary directory structure.
     pub fn activate(&mut self) -> Result<(), std::io::Error> {
         if self.active {
             return Ok(());
         }
-        
+
         // Create quarantine directory using a simple approach
-        let quarantine_dir = self.main_objects_dir.join("quarantine").join(format!("tmp-{}", std::process::id()));
+        let quarantine_dir = self
+            .main_objects_dir
+            .join("quarantine")
+            .join(format!("tmp-{}", std::process::id()));
         std::fs::create_dir_all(&quarantine_dir)?;
-        
+
         // Setup alternates file to point to main objects directory
         let alternates_file = quarantine_dir.join("info/alternates");
         std::fs::create_dir_all(alternates_file.parent().unwrap())?;
         std::fs::write(&alternates_file, self.main_objects_dir.to_string_lossy().as_bytes())?;
-        
+        std::fs::create_dir_all(quarantine_dir.join("pack"))?;
+
         self.objects_dir = quarantine_dir;
         self.active = true;
-        
+
         Ok(())
     }
-    
+
+    /// The main objects directory the quarantine is migrated into, and borrows existing objects from.
+    pub fn main_objects_dir(&self) -> &Path {
+        &self.main_objects_dir
+    }
+
     /// Check if the quarantine is currently active.
     pub fn is_active(&self) -> bool {
         self.active
     }
-    
+
     /// Migrate the quarantine contents to the main objects directory on success.
+    ///
+    /// The migration is journaled, so [`Quarantine::recover()`] can finish it if the process crashes midway.
     pub fn migrate_on_success(&mut self) -> Result<(), std::io::Error> {
         if !self.active {
             return Ok(());
         }
-        
-        // Move all objects from quarantine to main objects directory
+
         if self.objects_dir.exists() {
-            for entry in std::fs::read_dir(&self.objects_dir)? {
-                let entry = entry?;
-                let path = entry.path();
-                
-                // Skip the info directory (contains alternates)
-                if path.file_name().unwrap() == "info" {
-                    continue;
-                }
-                
-                let dest = self.main_objects_dir.join(entry.file_name());
-                if path.is_dir() {
-                    // Move directory recursively
-                    self.move_dir_recursive(&path, &dest)?;
-                } else {
-                    // Move file
-                    std::fs::rename(&path, &dest)?;
-                }
-            }
-            
+            let mut files = Vec::new();
+            collect_files(&self.objects_dir, Path::new(""), &mut files)?;
+            // Files that already exist are kept as is, and only the others are journaled and moved.
+            files.retain(|rel| !self.main_objects_dir.join(rel).exists());
+            sort_for_migration(&mut files);
+
+            let journal = journal_path(&self.objects_dir);
+            write_journal(&journal, &files)?;
+            migrate(&self.objects_dir, &self.main_objects_dir, &files)?;
+            fs::remove_file(&journal)?;
+            sync_dir(journal.parent().expect("journals are in the quarantine directory"))?;
+
             // Clean up quarantine directory
             std::fs::remove_dir_all(&self.objects_dir)?;
         }
-        
+
         self.active = false;
         Ok(())
     }
-    
+
     /// Drop the quarantine on failure, cleaning up temporary files.
     pub fn drop_on_failure(&mut self) -> Result<(), std::io::Error> {
         if !self.active {
             return Ok(());
         }
-        
+
         // Remove the entire quarantine directory
         if self.objects_dir.exists() {
             std::fs::remove_dir_all(&self.objects_dir)?;
         }
-        
+
         self.active = false;
         Ok(())
     }
-    
-    /// Helper to move directories recursively.
-    fn move_dir_recursive(&self, src: &std::path::Path, dest: &std::path::Path) -> Result<(), std::io::Error> {
-        std::fs::create_dir_all(dest)?;
-        
-        for entry in std::fs::read_dir(src)? {
-            let entry = entry?;
-            let src_path = entry.path();
-            let dest_path = dest.join(entry.file_name());
-            
-            if src_path.is_dir() {
-                self.move_dir_recursive(&src_path, &dest_path)?;
+
+    /// Complete or roll back the migrations into `main_objects_dir` that were interrupted by a crash.
+    ///
+    /// Call this once at startup, before serving pushes, as migrations still running in other processes would
+    /// be finished as well.
+    pub fn recover(main_objects_dir: &Path) -> Result<Vec<MigrationRecovery>, std::io::Error> {
+        let quarantines = main_objects_dir.join("quarantine");
+        let entries = match fs::read_dir(&quarantines) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(err),
+        };
+        let mut journals = Vec::new();
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "journal") {
+                journals.push(path);
+            }
+        }
+        journals.sort();
+
+        let mut recovered = Vec::new();
+        for journal in journals {
+            let quarantine = journal.with_extension("");
+            let files = read_journal(&journal)?;
+            let lost = files
+                .iter()
+                .any(|rel| !quarantine.join(rel).exists() && !main_objects_dir.join(rel).exists());
+            let recovery = if lost {
+                let mut removed = 0;
+                for rel in files.iter().rev().filter(|rel| rel.starts_with("pack")) {
+                    match fs::remove_file(main_objects_dir.join(rel)) {
+                        Ok(()) => removed += 1,
+                        Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+                        Err(err) => return Err(err),
+                    }
+                }
+                if removed > 0 {
+                    sync_dir(&main_objects_dir.join("pack"))?;
+                }
+                MigrationRecovery::RolledBack {
+                    quarantine: quarantine.clone(),
+                    removed,
+                }
             } else {
-                std::fs::rename(&src_path, &dest_path)?;
+                let pending: Vec<_> = files.into_iter().filter(|rel| quarantine.join(rel).exists()).collect();
+                migrate(&quarantine, main_objects_dir, &pending)?;
+                MigrationRecovery::Completed {
+                    quarantine: quarantine.clone(),
+                    moved: pending.len(),
+                }
+            };
+            fs::remove_file(&journal)?;
+            sync_dir(&quarantines)?;
+            if quarantine.exists() {
+                fs::remove_dir_all(&quarantine)?;
             }
+            recovered.push(recovery);
         }
-        
-        std::fs::remove_dir(src)?;
-        Ok(())
+        Ok(recovered)
+    }
+}
+
+/// Collect the paths of all files below `dir`, relative to the quarantine, except for its `info` directory.
+fn collect_files(dir: &Path, rel: &Path, out: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let rel = rel.join(entry.file_name());
+        // Skip the info directory (contains alternates)
+        if rel == Path::new("info") {
+            continue;
+        }
+        if entry.file_type()?.is_dir() {
+            collect_files(&entry.path(), &rel, out)?;
+        } else {
+            out.push(rel);
+        }
+    }
+    Ok(())
+}
+
+/// Order `files` so that pack indices come last, as they make packs visible to readers.
+fn sort_for_migration(files: &mut [PathBuf]) {
+    files.sort_by_key(|rel| (rel.extension().is_some_and(|ext| ext == "idx"), rel.clone()));
+}
+
+/// The journal of the migration of `quarantine`, next to it.
+fn journal_path(quarantine: &Path) -> PathBuf {
+    quarantine.with_extension("journal")
+}
+
+/// Durably write the journal listing `files` to `path`.
+fn write_journal(path: &Path, files: &[PathBuf]) -> io::Result<()> {
+    let tmp = path.with_extension("journal.tmp");
+    let mut out = fs::File::create(&tmp)?;
+    writeln!(out, "{JOURNAL_HEADER}")?;
+    for rel in files {
+        let rel = rel
+            .to_str()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("unexpected file name {rel:?}")))?;
+        writeln!(out, "{}", rel.replace('\\', "/"))?;
+    }
+    out.sync_all()?;
+    fs::rename(&tmp, path)?;
+    sync_dir(path.parent().expect("journals are in the quarantine directory"))
+}
+
+/// Read the files listed in the journal at `path`.
+fn read_journal(path: &Path) -> io::Result<Vec<PathBuf>> {
+    let text = fs::read_to_string(path)?;
+    let mut lines = text.lines();
+    if lines.next() != Some(JOURNAL_HEADER) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} is not a quarantine migration journal", path.display()),
+        ));
+    }
+    Ok(lines.filter(|line| !line.is_empty()).map(PathBuf::from).collect())
+}
+
+/// Move `files` from `quarantine` to `main_objects_dir` in order, syncing the directories before indices are moved
+/// and at the end.
+fn migrate(quarantine: &Path, main_objects_dir: &Path, files: &[PathBuf]) -> io::Result<()> {
+    let mut dirs = Vec::<PathBuf>::new();
+    let mut indices_started = false;
+    for rel in files {
+        let is_index = rel.extension().is_some_and(|ext| ext == "idx");
+        if is_index && !indices_started {
+            // Pack data must be durable before an index makes it visible.
+            for dir in &dirs {
+                sync_dir(dir)?;
+            }
+            indices_started = true;
+        }
+        let dest = main_objects_dir.join(rel);
+        let dir = dest.parent().expect("files are inside the objects directory");
+        if !dir.is_dir() {
+            fs::create_dir_all(dir)?;
+            sync_dir(dir.parent().expect("new directories are inside the objects directory"))?;
+        }
+        if !dirs.iter().any(|known| known == dir) {
+            dirs.push(dir.to_owned());
+        }
+        move_file(&quarantine.join(rel), &dest)?;
+    }
+    for dir in &dirs {
+        sync_dir(dir)?;
     }
+    Ok(())
+}
+
+/// Move `src` to `dest` unless `dest` already exists.
+///
+/// Object and pack files are named after their content, so an existing file is kept as is, just like git does.
+/// This also leaves packs protected by a `.keep` file untouched in repositories with precious objects.
+fn move_file(src: &std::path::Path, dest: &std::path::Path) -> Result<(), std::io::Error> {
+    if dest.exists() {
+        return std::fs::remove_file(src);
+    }
+    std::fs::rename(src, dest)
+}
+
+/// Make renames within `dir` durable.
+#[cfg(unix)]
+fn sync_dir(dir: &Path) -> io::Result<()> {
+    fs::File::open(dir)?.sync_all()
+}
+
+/// Directories can't be synced on this platform, renames are durable once the file system flushes them.
+#[cfg(not(unix))]
+fn sync_dir(_dir: &Path) -> io::Result<()> {
+    Ok(())
 }
 
 impl Drop for Quarantine {
@@ -132,45 +332,155 @@ impl Drop for Quarantine {
 mod tests {
     use super::*;
     use tempfile::tempdir;
-    
+
     #[test]
     fn test_quarantine_lifecycle() {
         let temp = tempdir().unwrap();
         let objects_dir = temp.path().join("objects");
         std::fs::create_dir_all(&objects_dir).unwrap();
-        
+
         let mut quarantine = Quarantine::new(objects_dir.clone());
         assert!(!quarantine.is_active());
-        
+
         // Activate quarantine
         quarantine.activate().unwrap();
         assert!(quarantine.is_active());
         assert!(quarantine.objects_dir.exists());
-        
+
         // Check alternates file
         let alternates_file = quarantine.objects_dir.join("info/alternates");
         assert!(alternates_file.exists());
         let content = std::fs::read_to_string(&alternates_file).unwrap();
         assert!(content.contains("objects"));
-        
+
         // Migrate on success
         quarantine.migrate_on_success().unwrap();
         assert!(!quarantine.is_active());
     }
-    
+
+    #[test]
+    fn test_quarantine_migration_keeps_existing_files() {
+        let temp = tempdir().unwrap();
+        let objects_dir = temp.path().join("objects");
+        let pack_dir = objects_dir.join("pack");
+        std::fs::create_dir_all(&pack_dir).unwrap();
+        std::fs::write(pack_dir.join("pack-1.pack"), b"existing").unwrap();
+        std::fs::write(pack_dir.join("pack-1.keep"), b"").unwrap();
+
+        let mut quarantine = Quarantine::new(objects_dir.clone());
+        quarantine.activate().unwrap();
+        std::fs::create_dir_all(quarantine.objects_dir.join("pack")).unwrap();
+        std::fs::write(quarantine.objects_dir.join("pack/pack-1.pack"), b"incoming").unwrap();
+        std::fs::write(quarantine.objects_dir.join("pack/pack-2.pack"), b"new").unwrap();
+        quarantine.migrate_on_success().unwrap();
+
+        assert_eq!(std::fs::read(pack_dir.join("pack-1.pack")).unwrap(), b"existing");
+        assert_eq!(std::fs::read(pack_dir.join("pack-2.pack")).unwrap(), b"new");
+        assert!(pack_dir.join("pack-1.keep").exists());
+    }
+
     #[test]
     fn test_quarantine_cleanup_on_drop() {
         let temp = tempdir().unwrap();
         let objects_dir = temp.path().join("objects");
         std::fs::create_dir_all(&objects_dir).unwrap();
-        
+
         let quarantine_path = {
             let mut quarantine = Quarantine::new(objects_dir);
             quarantine.activate().unwrap();
             quarantine.objects_dir.clone()
         }; // quarantine dropped here
-        
+
         // Directory should be cleaned up
         assert!(!quarantine_path.exists());
     }
-}
\ No newline at end of file
+
+    /// A quarantine below `objects_dir` holding a loose object and a pack, whose migration was journaled and
+    /// interrupted after the loose object and pack data were moved.
+    fn interrupted_migration(objects_dir: &Path) -> PathBuf {
+        let quarantine = objects_dir.join("quarantine/tmp-1");
+        for rel in ["ab/cdef", "pack/pack-1.pack", "pack/pack-1.idx"] {
+            let path = quarantine.join(rel);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, rel).unwrap();
+        }
+        let mut files = Vec::new();
+        collect_files(&quarantine, Path::new(""), &mut files).unwrap();
+        sort_for_migration(&mut files);
+        assert_eq!(
+            files,
+            ["ab/cdef", "pack/pack-1.pack", "pack/pack-1.idx"].map(PathBuf::from),
+            "indices come last"
+        );
+        write_journal(&journal_path(&quarantine), &files).unwrap();
+        migrate(&quarantine, objects_dir, &files[..2]).unwrap();
+        quarantine
+    }
+
+    #[test]
+    fn interrupted_migrations_are_completed() {
+        let temp = tempdir().unwrap();
+        let objects_dir = temp.path().join("objects");
+        let quarantine = interrupted_migration(&objects_dir);
+        assert!(!objects_dir.join("pack/pack-1.idx").exists());
+
+        let recovered = Quarantine::recover(&objects_dir).unwrap();
+        assert_eq!(
+            recovered,
+            vec![MigrationRecovery::Completed {
+                quarantine: quarantine.clone(),
+                moved: 1
+            }]
+        );
+        for rel in ["ab/cdef", "pack/pack-1.pack", "pack/pack-1.idx"] {
+            assert_eq!(fs::read_to_string(objects_dir.join(rel)).unwrap(), rel);
+        }
+        assert!(!quarantine.exists());
+        assert!(!journal_path(&quarantine).exists());
+        assert_eq!(
+            Quarantine::recover(&objects_dir).unwrap(),
+            Vec::new(),
+            "nothing is left to recover"
+        );
+    }
+
+    #[test]
+    fn migrations_with_lost_files_are_rolled_back() {
+        let temp = tempdir().unwrap();
+        let objects_dir = temp.path().join("objects");
+        let quarantine = interrupted_migration(&objects_dir);
+        fs::remove_file(quarantine.join("pack/pack-1.idx")).unwrap();
+
+        let recovered = Quarantine::recover(&objects_dir).unwrap();
+        assert_eq!(
+            recovered,
+            vec![MigrationRecovery::RolledBack {
+                quarantine: quarantine.clone(),
+                removed: 1
+            }]
+        );
+        assert!(!objects_dir.join("pack/pack-1.pack").exists());
+        assert!(objects_dir.join("ab/cdef").exists(), "loose objects are kept");
+        assert!(!quarantine.exists());
+    }
+
+    #[test]
+    fn quarantines_without_journal_are_left_alone() {
+        let temp = tempdir().unwrap();
+        assert_eq!(Quarantine::recover(temp.path()).unwrap(), Vec::new());
+
+        let mut quarantine = Quarantine::new(temp.path().to_owned());
+        quarantine.activate().unwrap();
+        assert_eq!(Quarantine::recover(temp.path()).unwrap(), Vec::new());
+        assert!(quarantine.objects_dir.exists());
+
+        std::fs::write(quarantine.objects_dir.join("object"), b"data").unwrap();
+        quarantine.migrate_on_success().unwrap();
+        assert!(temp.path().join("object").exists());
+        assert_eq!(
+            fs::read_dir(temp.path().join("quarantine")).unwrap().count(),
+            0,
+            "the journal is removed with the quarantine"
+        );
+    }
+}
@@ -0,0 +1,557 @@
+// M9: Anti-abuse monitoring of the rate at which a push creates objects.
+//
+// Scripts can push objects far faster than people create them, like millions of tiny generated blobs meant to
+// exhaust disk space, inodes or the time of later maintenance. `RateMonitor` tracks the bytes and objects received
+// per second while a pack is ingested: `RateReader` counts the bytes of the pack input and `CountingProgress` counts
+// the objects as gix-pack indexes them. Once the rate within a window exceeds a `RateLimits` threshold, the reader
+// fails and `ReceivePack` turns that into a `Resource` error with the text of `RateExceeded`.
+//
+// Notes
+// - The observed rates are recorded even without limits and returned with the outcome, so anomaly detection can
+//   flag pushes that stay below the thresholds as well.
+// - Rates are measured over whole windows, so short bursts of a fast connection don't count as abuse. The window
+//   still open at the end counts as if it was complete, which never overestimates its rate.
+// - Objects are counted as they are indexed, which may continue after the last byte was read, so the limits are
+//   checked once more after ingestion.
+// - Packs ingested from a file aren't received from a client, so their rates are observed but never limited.
+
+use std::io::{self, BufRead, Read};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// What a [`RateLimits`] threshold limits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RateKind {
+    /// Bytes of pack data received.
+    Bytes,
+    /// Objects received.
+    Objects,
+}
+
+impl RateKind {
+    fn unit(&self) -> &'static str {
+        match self {
+            RateKind::Bytes => "bytes",
+            RateKind::Objects => "objects",
+        }
+    }
+}
+
+/// The rates above which a push is considered abusive and aborted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimits {
+    /// The most bytes per second allowed, or `None` for no limit.
+    pub max_bytes_per_second: Option<u64>,
+    /// The most objects per second allowed, or `None` for no limit.
+    pub max_objects_per_second: Option<u64>,
+    /// The length of the windows the rates are measured over.
+    pub window: Duration,
+}
+
+impl Default for RateLimits {
+    fn default() -> Self {
+        RateLimits {
+            max_bytes_per_second: None,
+            max_objects_per_second: None,
+            window: Self::DEFAULT_WINDOW,
+        }
+    }
+}
+
+impl RateLimits {
+    /// The length of the windows rates are measured over by default.
+    pub const DEFAULT_WINDOW: Duration = Duration::from_secs(1);
+
+    /// Abort pushes that receive more than `bytes` of pack data per second.
+    pub fn with_max_bytes_per_second(mut self, bytes: u64) -> Self {
+        self.max_bytes_per_second = Some(bytes);
+        self
+    }
+
+    /// Abort pushes that create more than `objects` per second.
+    pub fn with_max_objects_per_second(mut self, objects: u64) -> Self {
+        self.max_objects_per_second = Some(objects);
+        self
+    }
+
+    /// Measure rates over windows of `window`.
+    pub fn with_window(mut self, window: Duration) -> Self {
+        self.window = window;
+        self
+    }
+
+    /// Obtain the limits configured by `receive.maxBytesPerSecond`, `receive.maxObjectsPerSecond` and
+    /// `receive.rateWindow`, or `None` if neither rate is limited.
+    pub fn from_config(config: &gix_config::File<'static>) -> Result<Option<Self>, crate::Error> {
+        use crate::config::keys::receive::{MAX_BYTES_PER_SECOND, MAX_OBJECTS_PER_SECOND, RATE_WINDOW};
+        let positive = |value: Option<i64>| value.filter(|value| *value > 0).map(|value| value as u64);
+        let mut limits = RateLimits {
+            max_bytes_per_second: positive(MAX_BYTES_PER_SECOND.get(config)?),
+            max_objects_per_second: positive(MAX_OBJECTS_PER_SECOND.get(config)?),
+            ..Default::default()
+        };
+        if let Some(seconds) = positive(RATE_WINDOW.get(config)?) {
+            limits.window = Duration::from_secs(seconds);
+        }
+        Ok((limits.max_bytes_per_second.is_some() || limits.max_objects_per_second.is_some()).then_some(limits))
+    }
+}
+
+/// The reason a push was aborted by its [`RateLimits`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateExceeded {
+    /// What was received too fast.
+    pub kind: RateKind,
+    /// The rate per second observed within the window.
+    pub observed: u64,
+    /// The rate per second allowed.
+    pub limit: u64,
+}
+
+impl std::fmt::Display for RateExceeded {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let unit = self.kind.unit();
+        write!(
+            f,
+            "push aborted as abusive: received {} {unit} per second, at most {} are allowed",
+            self.observed, self.limit
+        )
+    }
+}
+
+impl std::error::Error for RateExceeded {}
+
+/// The rates observed while ingesting a pack.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct IngestionRates {
+    /// The bytes of pack data received.
+    pub bytes: u64,
+    /// The objects received.
+    pub objects: u64,
+    /// The time from the first to the last byte or object received.
+    pub elapsed: Duration,
+    /// The highest bytes per second of any window.
+    pub peak_bytes_per_second: u64,
+    /// The highest objects per second of any window.
+    pub peak_objects_per_second: u64,
+}
+
+impl IngestionRates {
+    /// The average bytes per second, or 0 if everything arrived at once.
+    pub fn bytes_per_second(&self) -> f64 {
+        per_second(self.bytes, self.elapsed)
+    }
+
+    /// The average objects per second, or 0 if everything arrived at once.
+    pub fn objects_per_second(&self) -> f64 {
+        per_second(self.objects, self.elapsed)
+    }
+}
+
+fn per_second(count: u64, elapsed: Duration) -> f64 {
+    if elapsed.is_zero() {
+        0.0
+    } else {
+        count as f64 / elapsed.as_secs_f64()
+    }
+}
+
+#[derive(Debug, Default)]
+struct State {
+    limits: Option<RateLimits>,
+    first: Option<Instant>,
+    last: Option<Instant>,
+    window_start: Option<Instant>,
+    window_bytes: u64,
+    window_objects: u64,
+    rates: IngestionRates,
+    exceeded: Option<RateExceeded>,
+}
+
+impl State {
+    fn window(&self) -> Duration {
+        self.limits.map_or(RateLimits::DEFAULT_WINDOW, |limits| limits.window)
+    }
+
+    fn record(&mut self, bytes: u64, objects: u64) {
+        if bytes == 0 && objects == 0 {
+            return;
+        }
+        let now = Instant::now();
+        let first = *self.first.get_or_insert(now);
+        self.last = Some(now);
+        self.rates.elapsed = now.duration_since(first);
+        self.rates.bytes += bytes;
+        self.rates.objects += objects;
+
+        let window = self.window();
+        let start = *self.window_start.get_or_insert(now);
+        let elapsed = now.duration_since(start);
+        if elapsed >= window {
+            self.close_window(elapsed);
+            self.window_start = Some(now);
+        }
+        self.window_bytes += bytes;
+        self.window_objects += objects;
+        self.check();
+    }
+
+    /// Account for the window that ended after `elapsed`, which may be longer than a window if nothing arrived.
+    fn close_window(&mut self, elapsed: Duration) {
+        let seconds = elapsed.max(self.window()).as_secs_f64();
+        let bytes = (self.window_bytes as f64 / seconds) as u64;
+        let objects = (self.window_objects as f64 / seconds) as u64;
+        self.rates.peak_bytes_per_second = self.rates.peak_bytes_per_second.max(bytes);
+        self.rates.peak_objects_per_second = self.rates.peak_objects_per_second.max(objects);
+        self.window_bytes = 0;
+        self.window_objects = 0;
+    }
+
+    fn check(&mut self) {
+        let Some(limits) = self.limits else {
+            return;
+        };
+        if self.exceeded.is_some() {
+            return;
+        }
+        let seconds = limits.window.as_secs_f64();
+        for (kind, count, limit) in [
+            (RateKind::Bytes, self.window_bytes, limits.max_bytes_per_second),
+            (RateKind::Objects, self.window_objects, limits.max_objects_per_second),
+        ] {
+            let Some(limit) = limit else { continue };
+            if count as f64 > limit as f64 * seconds {
+                self.exceeded = Some(RateExceeded {
+                    kind,
+                    observed: (count as f64 / seconds) as u64,
+                    limit,
+                });
+                return;
+            }
+        }
+    }
+}
+
+/// A handle shared by everything that counts what a push received, to learn the rates and whether they exceeded
+/// the limits.
+#[derive(Debug, Clone, Default)]
+pub struct RateMonitor(Arc<Mutex<State>>);
+
+impl RateMonitor {
+    /// Track rates, aborting once they exceed `limits`, or never if it is `None`.
+    pub fn new(limits: Option<RateLimits>) -> Self {
+        RateMonitor(Arc::new(Mutex::new(State {
+            limits,
+            ..Default::default()
+        })))
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, State> {
+        self.0.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Count `bytes` of pack data as received now.
+    pub fn record_bytes(&self, bytes: u64) {
+        self.state().record(bytes, 0);
+    }
+
+    /// Count `objects` as received now.
+    pub fn record_objects(&self, objects: u64) {
+        self.state().record(0, objects);
+    }
+
+    /// Return the exceeded limit, if any.
+    pub fn exceeded(&self) -> Option<RateExceeded> {
+        self.state().exceeded
+    }
+
+    /// Return the exceeded limit as a [`Resource`](crate::Error::Resource) error, if any.
+    pub fn to_error(&self) -> Option<crate::Error> {
+        self.exceeded()
+            .map(|exceeded| crate::Error::Resource(exceeded.to_string()))
+    }
+
+    /// Return the rates observed so far, counting the current window as if it was complete.
+    pub fn rates(&self) -> IngestionRates {
+        let state = self.state();
+        let seconds = state.window().as_secs_f64();
+        let mut rates = state.rates;
+        rates.peak_bytes_per_second = rates
+            .peak_bytes_per_second
+            .max((state.window_bytes as f64 / seconds) as u64);
+        rates.peak_objects_per_second = rates
+            .peak_objects_per_second
+            .max((state.window_objects as f64 / seconds) as u64);
+        rates
+    }
+}
+
+/// A reader that counts the bytes it passes on and fails with [`io::ErrorKind::Other`] once a limit is exceeded.
+pub struct RateReader<R> {
+    inner: R,
+    monitor: RateMonitor,
+}
+
+impl<R> RateReader<R> {
+    /// Wrap `inner`, counting its bytes with `monitor`.
+    pub fn new(inner: R, monitor: RateMonitor) -> Self {
+        RateReader { inner, monitor }
+    }
+
+    fn check(&self) -> io::Result<()> {
+        match self.monitor.exceeded() {
+            Some(exceeded) => Err(io::Error::other(exceeded)),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<R: Read> Read for RateReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.check()?;
+        let bytes = self.inner.read(buf)?;
+        self.monitor.record_bytes(bytes as u64);
+        Ok(bytes)
+    }
+}
+
+impl<R: BufRead> BufRead for RateReader<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.check()?;
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.inner.consume(amt);
+        self.monitor.record_bytes(amt as u64);
+    }
+}
+
+#[cfg(feature = "progress")]
+pub use counting::CountingProgress;
+
+#[cfg(feature = "progress")]
+mod counting {
+    use super::RateMonitor;
+    use gix_features::progress::{
+        BoxedDynNestedProgress, Count, DynNestedProgress, Id, MessageLevel, NestedProgress, Progress, Step, StepShared,
+        Unit,
+    };
+
+    /// The id of the progress gix-pack counts each indexed object with.
+    const INDEXED_OBJECTS: Id = *b"IWIO";
+
+    /// A progress that passes everything on to another one, counting the objects gix-pack indexes with a
+    /// [`RateMonitor`].
+    pub struct CountingProgress<P> {
+        inner: P,
+        monitor: RateMonitor,
+        counts_objects: bool,
+    }
+
+    impl<'a> CountingProgress<&'a mut dyn DynNestedProgress> {
+        /// Wrap `inner`, counting the objects indexed by its children with `monitor`.
+        pub fn new(inner: &'a mut dyn DynNestedProgress, monitor: RateMonitor) -> Self {
+            CountingProgress {
+                inner,
+                monitor,
+                counts_objects: false,
+            }
+        }
+    }
+
+    /// The progress types children can be added to.
+    pub trait AddChild {
+        /// Add a child called `name` with `id`.
+        fn add_child_with_id(&mut self, name: String, id: Id) -> BoxedDynNestedProgress;
+    }
+
+    impl AddChild for &mut dyn DynNestedProgress {
+        fn add_child_with_id(&mut self, name: String, id: Id) -> BoxedDynNestedProgress {
+            DynNestedProgress::add_child_with_id(&mut **self, name, id)
+        }
+    }
+
+    impl AddChild for BoxedDynNestedProgress {
+        fn add_child_with_id(&mut self, name: String, id: Id) -> BoxedDynNestedProgress {
+            NestedProgress::add_child_with_id(self, name, id)
+        }
+    }
+
+    impl<P: Count> Count for CountingProgress<P> {
+        fn set(&self, step: Step) {
+            if self.counts_objects {
+                self.monitor
+                    .record_objects(step.saturating_sub(self.inner.step()) as u64);
+            }
+            self.inner.set(step)
+        }
+
+        fn step(&self) -> Step {
+            self.inner.step()
+        }
+
+        fn inc_by(&self, step: Step) {
+            if self.counts_objects {
+                self.monitor.record_objects(step as u64);
+            }
+            self.inner.inc_by(step)
+        }
+
+        fn counter(&self) -> StepShared {
+            self.inner.counter()
+        }
+    }
+
+    impl<P: Progress> Progress for CountingProgress<P> {
+        fn init(&mut self, max: Option<Step>, unit: Option<Unit>) {
+            self.inner.init(max, unit)
+        }
+
+        fn unit(&self) -> Option<Unit> {
+            self.inner.unit()
+        }
+
+        fn max(&self) -> Option<Step> {
+            self.inner.max()
+        }
+
+        fn set_max(&mut self, max: Option<Step>) -> Option<Step> {
+            self.inner.set_max(max)
+        }
+
+        fn set_name(&mut self, name: String) {
+            self.inner.set_name(name)
+        }
+
+        fn name(&self) -> Option<String> {
+            self.inner.name()
+        }
+
+        fn id(&self) -> Id {
+            self.inner.id()
+        }
+
+        fn message(&self, level: MessageLevel, message: String) {
+            self.inner.message(level, message)
+        }
+    }
+
+    impl<P: Progress + AddChild> NestedProgress for CountingProgress<P> {
+        type SubProgress = CountingProgress<BoxedDynNestedProgress>;
+
+        fn add_child(&mut self, name: impl Into<String>) -> Self::SubProgress {
+            NestedProgress::add_child_with_id(self, name, gix_features::progress::UNKNOWN)
+        }
+
+        fn add_child_with_id(&mut self, name: impl Into<String>, id: Id) -> Self::SubProgress {
+            CountingProgress {
+                inner: self.inner.add_child_with_id(name.into(), id),
+                monitor: self.monitor.clone(),
+                counts_objects: id == INDEXED_OBJECTS,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fast_pushes_are_aborted() {
+        let limits = RateLimits::default()
+            .with_max_bytes_per_second(1000)
+            .with_window(Duration::from_millis(100));
+        let monitor = RateMonitor::new(Some(limits));
+        let mut reader = RateReader::new(io::Cursor::new(vec![b'x'; 1000]), monitor.clone());
+        let err = io::copy(&mut reader, &mut io::sink()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Other);
+        let exceeded = monitor.exceeded().expect("recorded");
+        assert_eq!(exceeded.kind, RateKind::Bytes);
+        assert_eq!(exceeded.limit, 1000);
+        assert_eq!(
+            exceeded.observed, 10_000,
+            "all bytes arrived within one window of 100ms"
+        );
+        let err = monitor.to_error().expect("exceeded");
+        assert_eq!(err.kind(), crate::Kind::Resource);
+        assert!(
+            err.to_string()
+                .contains("push aborted as abusive: received 10000 bytes per second, at most 1000 are allowed"),
+            "{err}"
+        );
+
+        let monitor = RateMonitor::new(Some(RateLimits::default().with_max_objects_per_second(5)));
+        monitor.record_objects(5);
+        assert!(monitor.exceeded().is_none());
+        monitor.record_objects(1);
+        assert_eq!(
+            monitor.exceeded().map(|exceeded| exceeded.kind),
+            Some(RateKind::Objects)
+        );
+    }
+
+    #[test]
+    fn rates_are_observed_without_limits() {
+        let monitor = RateMonitor::new(None);
+        let mut reader = RateReader::new(io::Cursor::new(vec![b'x'; 4096]), monitor.clone());
+        assert_eq!(io::copy(&mut reader, &mut io::sink()).unwrap(), 4096);
+        monitor.record_objects(3);
+        let rates = monitor.rates();
+        assert_eq!((rates.bytes, rates.objects), (4096, 3));
+        assert_eq!(
+            rates.peak_bytes_per_second, 4096,
+            "the open window counts as a full second"
+        );
+        assert_eq!(rates.peak_objects_per_second, 3);
+        assert!(monitor.exceeded().is_none());
+
+        let monitor = RateMonitor::new(Some(RateLimits::default().with_window(Duration::from_millis(20))));
+        monitor.record_objects(10);
+        std::thread::sleep(Duration::from_millis(40));
+        monitor.record_objects(1);
+        let rates = monitor.rates();
+        assert!(
+            rates.peak_objects_per_second <= 500,
+            "the pause counts towards the first window: {rates:?}"
+        );
+        assert!(rates.objects_per_second() < 500.0, "{rates:?}");
+    }
+
+    #[test]
+    fn configuration() {
+        let config = gix_config::File::try_from(
+            "[receive]\n\tmaxObjectsPerSecond = 10k\n\tmaxBytesPerSecond = 0\n\trateWindow = 5\n",
+        )
+        .unwrap();
+        assert_eq!(
+            RateLimits::from_config(&config).unwrap(),
+            Some(
+                RateLimits::default()
+                    .with_max_objects_per_second(10 * 1024)
+                    .with_window(Duration::from_secs(5))
+            )
+        );
+        let config = gix_config::File::try_from("[receive]\n\trateWindow = 5\n").unwrap();
+        assert_eq!(RateLimits::from_config(&config).unwrap(), None);
+    }
+
+    #[cfg(feature = "progress")]
+    #[test]
+    fn indexed_objects_are_counted() {
+        use gix_features::progress::{Count, Discard, DynNestedProgress};
+        let monitor = RateMonitor::new(None);
+        let mut discard = Discard;
+        let mut progress = CountingProgress::new(&mut discard, monitor.clone());
+        let progress: &mut dyn DynNestedProgress = &mut progress;
+        let mut index = progress.add_child("create index file".into());
+        let objects = index.add_child_with_id("indexing".into(), *b"IWIO");
+        let bytes = index.add_child_with_id("decompressing".into(), *b"IWDB");
+        objects.inc();
+        objects.inc_by(2);
+        bytes.inc_by(100);
+        assert_eq!(monitor.rates().objects, 3);
+        assert_eq!(monitor.rates().bytes, 0);
+    }
+}
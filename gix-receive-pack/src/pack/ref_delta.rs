@@ -0,0 +1,200 @@
+// Ref-deltas against bases within the same pack.
+//
+// Clients that don't use `ofs-delta` refer to delta bases by object id even if the base is in the pack they send.
+// gix-pack only resolves ref-deltas against bases it can look up outside of the pack, see `thin`, so packs like
+// these are rewritten to use ofs-deltas for in-pack bases before indexing them, like `git index-pack` would resolve
+// them after the fact.
+//
+// Notes
+// - Only packs on disk are rewritten, as the ids of in-pack bases are only known after decoding the objects.
+// - Bases found in the main object database are left to the thin-pack lookup.
+// - Bases are expected before their deltas, as written by `git pack-objects`; others stay ref-deltas.
+
+use crate::error::{ErrorContext, PackIngestionError, Result};
+use gix_hash::ObjectId;
+use gix_pack::data::{self, decode::entry::ResolvedBase, entry::Header, input};
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+/// Rewrite the pack at `pack_path` into `out_path` so that its ref-deltas against bases within the pack become ofs-deltas,
+/// leaving bases that `odb` has to the thin-pack lookup.
+///
+/// Returns `false` without writing `out_path` if the pack has no such ref-deltas and can be indexed as is.
+pub(crate) fn rewrite_in_pack_ref_deltas(
+    pack_path: &Path,
+    out_path: &Path,
+    odb: Option<&gix_odb::Handle>,
+) -> Result<bool> {
+    let context = ErrorContext::new("rewrite-ref-deltas").with_context("pack_path", pack_path.display().to_string());
+    let parse_error = |message: &str, e: Box<dyn std::error::Error + Send + Sync>| {
+        PackIngestionError::pack_parsing(message, context.clone(), Some(e))
+    };
+    let in_odb = |id: &ObjectId| odb.is_some_and(|odb| gix_object::Exists::exists(odb, id));
+
+    let pack = data::File::at(pack_path, gix_hash::Kind::Sha1)
+        .map_err(|e| parse_error("failed to open pack file", Box::new(e)))?;
+    let mut entries = Vec::new();
+    let mut wanted = HashSet::new();
+    for entry in pack
+        .streaming_iter()
+        .map_err(|e| parse_error("failed to read pack file", Box::new(e)))?
+    {
+        let entry = entry.map_err(|e| parse_error("failed to read pack entry", Box::new(e)))?;
+        if let Header::RefDelta { base_id } = entry.header {
+            if !in_odb(&base_id) {
+                wanted.insert(base_id);
+            }
+        }
+        entries.push(entry.pack_offset);
+    }
+    if wanted.is_empty() {
+        return Ok(false);
+    }
+
+    // Find the wanted bases by decoding objects in pack order until all of them are known.
+    let bases = RefCell::new(HashMap::<ObjectId, data::Offset>::new());
+    let resolve = |id: &gix_hash::oid, out: &mut Vec<u8>| {
+        if let Some(offset) = bases.borrow().get(id) {
+            return Some(ResolvedBase::InPack(pack.entry(*offset).ok()?));
+        }
+        let object = gix_object::Find::try_find(odb?, id, out).ok()??;
+        let kind = object.kind;
+        let end = object.data.len();
+        Some(ResolvedBase::OutOfPack { kind, end })
+    };
+    let mut inflate = gix_features::zlib::Inflate::default();
+    let mut buf = Vec::new();
+    for offset in entries {
+        if bases.borrow().len() == wanted.len() {
+            break;
+        }
+        let Ok(entry) = pack.entry(offset) else { continue };
+        // Objects that can't be decoded are left to the indexer to report.
+        let Ok(outcome) = pack.decode_entry(entry, &mut buf, &mut inflate, &resolve, &mut gix_pack::cache::Never)
+        else {
+            continue;
+        };
+        let id = gix_object::compute_hash(pack.object_hash(), outcome.kind, &buf[..outcome.object_size as usize])
+            .map_err(|e| parse_error("failed to hash pack entry", Box::new(e)))?;
+        if wanted.contains(&id) {
+            bases.borrow_mut().insert(id, offset);
+        }
+    }
+    let bases = bases.into_inner();
+    if bases.is_empty() {
+        return Ok(false);
+    }
+
+    // Write all entries again, pointing to the bases by offset, which also moves all entries after them.
+    let mut offsets = HashMap::<data::Offset, data::Offset>::new();
+    let mut next_offset = 12; // The pack header.
+    let rewritten = pack
+        .streaming_iter()
+        .map_err(|e| parse_error("failed to read pack file", Box::new(e)))?
+        .map(|entry| {
+            let mut entry = entry?;
+            let base_offset = match entry.header {
+                Header::OfsDelta { base_distance } => Some(entry.pack_offset - base_distance),
+                Header::RefDelta { base_id } => bases.get(&base_id).copied(),
+                _ => None,
+            };
+            offsets.insert(entry.pack_offset, next_offset);
+            entry.pack_offset = next_offset;
+            if let Some(base_offset) = base_offset.and_then(|offset| offsets.get(&offset)) {
+                entry.header = Header::OfsDelta {
+                    base_distance: next_offset - base_offset,
+                };
+                entry.header_size = entry.header.size(entry.decompressed_size) as u16;
+                entry.crc32 = Some(entry.compute_crc32());
+            }
+            next_offset += entry.bytes_in_pack();
+            Ok::<_, input::Error>(entry)
+        });
+    let out = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(out_path)
+        .map_err(|e| PackIngestionError::io("failed to create rewritten pack file", context.clone(), e))?;
+    for entry in input::EntriesToBytesIter::new(rewritten, out, pack.version(), pack.object_hash()) {
+        entry.map_err(|e| parse_error("failed to write rewritten pack file", Box::new(e)))?;
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::Command;
+
+    #[test]
+    fn in_pack_bases_of_ref_deltas_are_referred_to_by_offset() {
+        let tmp = gix_testtools::tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let git = |args: &[&str]| {
+            let status = Command::new("git")
+                .args(args)
+                .current_dir(dir)
+                .env("GIT_AUTHOR_NAME", "author")
+                .env("GIT_AUTHOR_EMAIL", "author@example.com")
+                .env("GIT_COMMITTER_NAME", "committer")
+                .env("GIT_COMMITTER_EMAIL", "committer@example.com")
+                .status()
+                .expect("git is installed");
+            assert!(status.success(), "git {args:?} failed");
+        };
+        git(&["init", "--quiet"]);
+        let content: String = (0..200)
+            .map(|line| format!("line {line} of a file to delta against\n"))
+            .collect();
+        std::fs::write(dir.join("file"), &content).unwrap();
+        git(&["add", "file"]);
+        git(&["commit", "--quiet", "-m", "first"]);
+        std::fs::write(dir.join("file"), content + "one more line\n").unwrap();
+        git(&["commit", "--quiet", "-am", "second"]);
+        git(&[
+            "-c",
+            "repack.useDeltaBaseOffset=false",
+            "repack",
+            "-a",
+            "-d",
+            "-F",
+            "--quiet",
+        ]);
+        let pack_path = std::fs::read_dir(dir.join(".git/objects/pack"))
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .find(|path| path.extension().is_some_and(|ext| ext == "pack"))
+            .expect("one pack");
+
+        let index = |path: &Path| {
+            let out = gix_testtools::tempfile::tempdir().unwrap();
+            gix_pack::Bundle::write_to_directory(
+                &mut std::io::BufReader::new(std::fs::File::open(path).unwrap()),
+                Some(out.path()),
+                &mut gix_features::progress::Discard,
+                &std::sync::atomic::AtomicBool::new(false),
+                Option::<gix_odb::Handle>::None,
+                Default::default(),
+            )
+            .map(|outcome| outcome.index.num_objects)
+        };
+        assert!(index(&pack_path).is_err(), "ref-deltas need a lookup");
+
+        let rewritten = dir.join("rewritten.pack");
+        assert!(rewrite_in_pack_ref_deltas(&pack_path, &rewritten, None).unwrap());
+        assert_eq!(
+            index(&rewritten).unwrap(),
+            6,
+            "all objects, with the blob delta resolved in the pack"
+        );
+
+        let odb = gix_odb::at(dir.join(".git/objects")).unwrap();
+        assert!(
+            !rewrite_in_pack_ref_deltas(&pack_path, &dir.join("unused.pack"), Some(&odb)).unwrap(),
+            "bases in the object database are left to the thin-pack lookup"
+        );
+    }
+}
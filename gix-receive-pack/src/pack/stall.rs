@@ -0,0 +1,266 @@
+// M9: Stall detection while reading pack data, like git's `http.lowSpeedLimit`.
+//
+// The time budget only bounds the whole ingestion, so a client trickling a few bytes at a time can hold a quarantine
+// and a connection for the entire budget. `StallReader` wraps the pack input and fails once fewer than a minimum
+// number of bytes arrived within a window, and `ReceivePack` turns that failure into a `Resource` error.
+//
+// Notes
+// - The check runs whenever the client delivers bytes, so a client that sends nothing is left to transport timeouts.
+// - The first window starts with the first byte, so the time until the pack starts isn't counted.
+
+use std::io::{self, BufRead, Read};
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
+
+/// The minimum transfer rate below which a client is considered stalled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StallDetection {
+    /// The number of bytes that must arrive within each window.
+    pub min_bytes: u64,
+    /// The length of the window.
+    pub window: Duration,
+}
+
+impl StallDetection {
+    /// Abort if fewer than `min_bytes` arrive within `window`.
+    pub fn new(min_bytes: u64, window: Duration) -> Self {
+        StallDetection { min_bytes, window }
+    }
+
+    /// Abort if the rate stays below `bytes_per_second` for `seconds`, or return `None` if either is zero.
+    pub fn from_low_speed(bytes_per_second: u64, seconds: u64) -> Option<Self> {
+        (bytes_per_second > 0 && seconds > 0)
+            .then(|| Self::new(bytes_per_second.saturating_mul(seconds), Duration::from_secs(seconds)))
+    }
+
+    /// Obtain the stall detection configured by `receive.lowSpeedLimit` and `receive.lowSpeedTime`, if enabled.
+    pub fn from_config(config: &gix_config::File<'static>) -> Result<Option<Self>, crate::Error> {
+        use crate::config::keys::receive::{LOW_SPEED_LIMIT, LOW_SPEED_TIME};
+        let limit = LOW_SPEED_LIMIT.get(config)?.unwrap_or(0).max(0);
+        let time = LOW_SPEED_TIME.get(config)?.unwrap_or(0).max(0);
+        Ok(Self::from_low_speed(limit as u64, time as u64))
+    }
+}
+
+/// The reason a [`StallReader`] stopped reading.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Stalled {
+    /// The bytes received within the window.
+    pub received: u64,
+    /// The minimum number of bytes required within the window.
+    pub min_bytes: u64,
+    /// The length of the window that was exceeded.
+    pub window: Duration,
+}
+
+impl std::fmt::Display for Stalled {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "client sent only {} bytes of pack data within {}s, at least {} are required",
+            self.received,
+            self.window.as_secs_f32(),
+            self.min_bytes
+        )
+    }
+}
+
+impl std::error::Error for Stalled {}
+
+/// A handle to learn whether a [`StallReader`] detected a stall after the reader was handed off.
+#[derive(Debug, Clone, Default)]
+pub struct StallMonitor(Arc<OnceLock<Stalled>>);
+
+impl StallMonitor {
+    /// Return the detected stall, if any.
+    pub fn stalled(&self) -> Option<&Stalled> {
+        self.0.get()
+    }
+
+    /// Return the detected stall as a [`Resource`](crate::Error::Resource) error, if any.
+    pub fn to_error(&self) -> Option<crate::Error> {
+        self.stalled()
+            .map(|stalled| crate::Error::Resource(stalled.to_string()))
+    }
+}
+
+/// A reader that fails with [`io::ErrorKind::TimedOut`] once the client stalls.
+pub struct StallReader<R> {
+    inner: R,
+    detection: Option<StallDetection>,
+    window_start: Option<Instant>,
+    window_bytes: u64,
+    monitor: StallMonitor,
+}
+
+impl<R> StallReader<R> {
+    /// Wrap `inner`, detecting stalls according to `detection`, or not at all if it is `None`.
+    pub fn new(inner: R, detection: Option<StallDetection>) -> Self {
+        StallReader {
+            inner,
+            detection,
+            window_start: None,
+            window_bytes: 0,
+            monitor: StallMonitor::default(),
+        }
+    }
+
+    /// A handle to check for stalls once the reader was moved elsewhere.
+    pub fn monitor(&self) -> StallMonitor {
+        self.monitor.clone()
+    }
+
+    fn check(&self) -> io::Result<()> {
+        match self.monitor.stalled() {
+            Some(stalled) => Err(io::Error::new(io::ErrorKind::TimedOut, stalled.clone())),
+            None => Ok(()),
+        }
+    }
+
+    fn record(&mut self, bytes: usize) {
+        let Some(detection) = self.detection else {
+            return;
+        };
+        if bytes == 0 || self.monitor.stalled().is_some() {
+            return;
+        }
+        let now = Instant::now();
+        let start = *self.window_start.get_or_insert(now);
+        self.window_bytes += bytes as u64;
+        if now.duration_since(start) < detection.window {
+            return;
+        }
+        if self.window_bytes < detection.min_bytes {
+            let _ = self.monitor.0.set(Stalled {
+                received: self.window_bytes,
+                min_bytes: detection.min_bytes,
+                window: detection.window,
+            });
+            return;
+        }
+        self.window_start = Some(now);
+        self.window_bytes = 0;
+    }
+}
+
+impl<R: Read> Read for StallReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.check()?;
+        let bytes = self.inner.read(buf)?;
+        self.record(bytes);
+        self.check()?;
+        Ok(bytes)
+    }
+}
+
+impl<R: BufRead> BufRead for StallReader<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.check()?;
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.inner.consume(amt);
+        self.record(amt);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Hands out `chunk` bytes per call, sleeping `delay` before each.
+    struct Trickle {
+        data: Vec<u8>,
+        pos: usize,
+        chunk: usize,
+        delay: Duration,
+    }
+
+    impl Trickle {
+        fn new(len: usize, chunk: usize, delay_ms: u64) -> Self {
+            Trickle {
+                data: vec![b'x'; len],
+                pos: 0,
+                chunk,
+                delay: Duration::from_millis(delay_ms),
+            }
+        }
+    }
+
+    impl Read for Trickle {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = {
+                let available = self.fill_buf()?;
+                let n = available.len().min(buf.len());
+                buf[..n].copy_from_slice(&available[..n]);
+                n
+            };
+            self.consume(n);
+            Ok(n)
+        }
+    }
+
+    impl BufRead for Trickle {
+        fn fill_buf(&mut self) -> io::Result<&[u8]> {
+            std::thread::sleep(self.delay);
+            let end = (self.pos + self.chunk).min(self.data.len());
+            Ok(&self.data[self.pos..end])
+        }
+
+        fn consume(&mut self, amt: usize) {
+            self.pos += amt;
+        }
+    }
+
+    #[test]
+    fn slow_pack_data_is_refused() {
+        let detection = StallDetection::new(100, Duration::from_millis(30));
+        let mut reader = StallReader::new(Trickle::new(1000, 1, 5), Some(detection));
+        let monitor = reader.monitor();
+        let err = io::copy(&mut reader, &mut io::sink()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        assert!(monitor.stalled().expect("recorded").received < 100);
+
+        let mut reader = StallReader::new(Trickle::new(1000, 1, 5), Some(detection));
+        let monitor = reader.monitor();
+        let err = loop {
+            match reader.fill_buf() {
+                Ok([]) => panic!("the reader must stall before the end"),
+                Ok(buf) => {
+                    let amt = buf.len();
+                    reader.consume(amt);
+                }
+                Err(err) => break err,
+            }
+        };
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        let err = monitor.to_error().expect("stalled");
+        assert_eq!(err.kind(), crate::Kind::Resource);
+        assert!(err.to_string().contains("at least 100 are required"), "{err}");
+    }
+
+    #[test]
+    fn fast_or_unchecked_pack_data_passes() {
+        let detection = StallDetection::new(100, Duration::from_millis(20));
+        let mut reader = StallReader::new(Trickle::new(1000, 100, 5), Some(detection));
+        let mut out = Vec::new();
+        reader.read_to_end(&mut out).unwrap();
+        assert_eq!(out.len(), 1000);
+
+        let mut reader = StallReader::new(Trickle::new(10, 1, 2), None);
+        assert_eq!(io::copy(&mut reader, &mut io::sink()).unwrap(), 10);
+        assert!(reader.monitor().to_error().is_none());
+    }
+
+    #[test]
+    fn low_speed_configuration() {
+        let config = gix_config::File::try_from("[receive]\n\tlowSpeedLimit = 1000\n\tlowSpeedTime = 30\n").unwrap();
+        assert_eq!(
+            StallDetection::from_config(&config).unwrap(),
+            Some(StallDetection::new(30_000, Duration::from_secs(30)))
+        );
+        let config = gix_config::File::try_from("[receive]\n\tlowSpeedLimit = 1000\n").unwrap();
+        assert_eq!(StallDetection::from_config(&config).unwrap(), None);
+    }
+}
@@ -359,6 +359,7 @@ impl<R: BufRead> StreamingPackReader<R> {
             bytes_read: self.bytes_read,
             memory_stats: self.memory_tracker.stats(),
             buffer_size: self.config.buffer_size,
+            rates: Default::default(),
         }
     }
 
@@ -391,6 +392,8 @@ pub struct StreamingStats {
     pub memory_stats: MemoryStats,
     /// Buffer size used
     pub buffer_size: usize,
+    /// The rates at which bytes and objects were received, filled in by `ReceivePack`
+    pub rates: super::rate::IngestionRates,
 }
 
 /// Memory-aware buffer pool for reusing allocations.
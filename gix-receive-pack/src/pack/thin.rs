@@ -0,0 +1,130 @@
+// M9: Thin-pack detection and optional base lookup.
+//
+// Thin packs leave out delta bases the client expects the server to have, and refer to them by object id with
+// ref-deltas. gix-pack only consults the base lookup when it meets a ref-delta whose base isn't in the pack yet, so
+// `BaseLookup` counts the bases it found in the main object database to tell whether a push was thin, without
+// scanning the pack up-front. Pushes without such ref-deltas never touch the main object database for bases.
+//
+// Notes
+// - With `ThinPacks::Never` no lookup is passed at all, for servers advertising `no-thin`, and thin packs fail to
+//   ingest instead.
+// - Only bases count: objects the fsck connectivity check finds in the main object database are looked up
+//   separately.
+
+#[cfg(feature = "progress")]
+use std::sync::atomic::{AtomicU32, Ordering};
+
+/// Whether the bases of thin packs are looked up in the main object database.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ThinPacks {
+    /// Look up the bases of ref-deltas that aren't in the pack, which only happens for thin packs.
+    #[default]
+    Resolve,
+    /// Never look up bases, so that thin packs fail to ingest, like when advertising `no-thin`.
+    Never,
+}
+
+/// Finds the bases of ref-deltas in the main object database, counting the ones it found.
+#[cfg(feature = "progress")]
+pub(crate) struct BaseLookup<'a> {
+    odb: &'a gix_odb::Handle,
+    found: &'a AtomicU32,
+}
+
+#[cfg(feature = "progress")]
+impl<'a> BaseLookup<'a> {
+    /// Look up bases in `odb`, adding one to `found` for each base that exists.
+    pub(crate) fn new(odb: &'a gix_odb::Handle, found: &'a AtomicU32) -> Self {
+        BaseLookup { odb, found }
+    }
+}
+
+#[cfg(feature = "progress")]
+impl gix_object::Find for BaseLookup<'_> {
+    fn try_find<'b>(
+        &self,
+        id: &gix_hash::oid,
+        buffer: &'b mut Vec<u8>,
+    ) -> Result<Option<gix_object::Data<'b>>, gix_object::find::Error> {
+        let object = gix_object::Find::try_find(self.odb, id, buffer)?;
+        if object.is_some() {
+            self.found.fetch_add(1, Ordering::Relaxed);
+        }
+        Ok(object)
+    }
+}
+
+#[cfg(all(test, feature = "progress"))]
+mod tests {
+    use super::*;
+    use std::path::Path;
+    use std::process::Command;
+
+    fn git(dir: &Path, args: &[&str]) -> Vec<u8> {
+        let output = Command::new("git")
+            .args(args)
+            .current_dir(dir)
+            .env("GIT_AUTHOR_NAME", "author")
+            .env("GIT_AUTHOR_EMAIL", "author@example.com")
+            .env("GIT_COMMITTER_NAME", "committer")
+            .env("GIT_COMMITTER_EMAIL", "committer@example.com")
+            .output()
+            .expect("git is installed");
+        assert!(output.status.success(), "git {args:?} failed");
+        output.stdout
+    }
+
+    /// Pack the second commit of a two-commit repository at `dir`, as a client would push it.
+    fn pack_of_second_commit(dir: &Path, thin: bool) -> Vec<u8> {
+        use std::io::Write;
+        let mut child = Command::new("git")
+            .args(["pack-objects", "--stdout", "--revs", "--delta-base-offset"])
+            .args(thin.then_some("--thin"))
+            .current_dir(dir)
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::piped())
+            .spawn()
+            .expect("git is installed");
+        child.stdin.take().unwrap().write_all(b"HEAD\n^HEAD~1\n").unwrap();
+        let output = child.wait_with_output().unwrap();
+        assert!(output.status.success());
+        output.stdout
+    }
+
+    /// Index `pack` into a new directory with bases from `odb`, returning how many bases were found.
+    fn index(pack: &[u8], odb: Option<&gix_odb::Handle>) -> Result<u32, gix_pack::bundle::write::Error> {
+        let found = AtomicU32::new(0);
+        let dir = gix_testtools::tempfile::tempdir().unwrap();
+        gix_pack::Bundle::write_to_directory(
+            &mut std::io::BufReader::new(pack),
+            Some(dir.path()),
+            &mut gix_features::progress::Discard,
+            &std::sync::atomic::AtomicBool::new(false),
+            odb.map(|odb| BaseLookup::new(odb, &found)),
+            Default::default(),
+        )?;
+        Ok(found.into_inner())
+    }
+
+    #[test]
+    fn bases_are_only_looked_up_for_thin_packs() {
+        let tmp = gix_testtools::tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        git(dir, &["init", "--quiet"]);
+        let content: String = (0..200).map(|line| format!("line {line} of a file to delta against\n")).collect();
+        std::fs::write(dir.join("file"), &content).unwrap();
+        git(dir, &["add", "file"]);
+        git(dir, &["commit", "--quiet", "-m", "first"]);
+        std::fs::write(dir.join("file"), content + "one more line\n").unwrap();
+        git(dir, &["commit", "--quiet", "-am", "second"]);
+        let odb = gix_odb::at(dir.join(".git/objects")).unwrap();
+
+        let thin = pack_of_second_commit(dir, true);
+        assert!(index(&thin, Some(&odb)).unwrap() > 0, "the base of the blob came from the odb");
+        assert!(index(&thin, None).is_err(), "thin packs can't be indexed without lookup");
+
+        let full = pack_of_second_commit(dir, false);
+        assert_eq!(index(&full, Some(&odb)).unwrap(), 0, "complete packs don't need the odb");
+        assert_eq!(index(&full, None).unwrap(), 0);
+    }
+}
@@ -0,0 +1,221 @@
+//! Divergence of forced updates in mirror mode.
+//!
+//! Replication pipelines push to mirrors with force, so a mirror has to accept whatever its source did,
+//! including rewritten history. With [`PolicySet::with_mirror()`](super::PolicySet::with_mirror()), non-fast-forward
+//! updates are allowed even if `receive.denyNonFastForwards` is set, and [`divergence()`] records which updates
+//! of a push weren't fast-forwards and how many commits they discarded, so rewrites remain auditable.
+//!
+//! Only updates are considered, deletions are covered by `receive.denyDeletes` as usual.
+
+use std::collections::HashSet;
+
+use gix_hash::{oid, ObjectId};
+use gix_object::{CommitRefIter, Find, Kind};
+
+use crate::protocol::CommandUpdate;
+use crate::Error;
+
+/// Commit walks stop after this many commits, and the counts they produce are marked as inexact.
+const MAX_WALK: usize = 10_000;
+
+/// A non-fast-forward update accepted in mirror mode.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Divergence {
+    /// The updated ref.
+    pub refname: String,
+    /// The value of the ref before the update.
+    pub old: ObjectId,
+    /// The value of the ref after the update.
+    pub new: ObjectId,
+    /// The number of commits reachable from `old` that aren't reachable from `new` anymore.
+    pub discarded_commits: usize,
+    /// `false` if the history was too long to walk completely, in which case `discarded_commits` is an estimate.
+    pub exact: bool,
+}
+
+/// The non-fast-forward updates of a push, as returned by [`divergence()`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MirrorReport {
+    /// The updates that weren't fast-forwards, in the order of their commands.
+    pub divergences: Vec<Divergence>,
+}
+
+impl MirrorReport {
+    /// Return the number of non-fast-forward updates.
+    pub fn forced_updates(&self) -> usize {
+        self.divergences.len()
+    }
+
+    /// Return the number of commits discarded by all updates together.
+    pub fn discarded_commits(&self) -> usize {
+        self.divergences.iter().map(|d| d.discarded_commits).sum()
+    }
+
+    /// Return `true` if all counts are exact.
+    pub fn is_exact(&self) -> bool {
+        self.divergences.iter().all(|d| d.exact)
+    }
+}
+
+/// Find the updates among `commands` that aren't fast-forwards, looking up commits in `objects`, which has to
+/// include the objects received with the push.
+///
+/// Updates from or to objects that aren't commits are reported as diverging without discarded commits.
+pub fn divergence(commands: &[CommandUpdate], objects: &dyn Find) -> Result<MirrorReport, Error> {
+    let mut report = MirrorReport::default();
+    for command in commands {
+        let CommandUpdate::Update { old, new, name } = command else {
+            continue;
+        };
+        if old == new {
+            continue;
+        }
+        let (kept, kept_complete) = walk(objects, *new, &HashSet::new())?;
+        if kept.contains(old) {
+            continue;
+        }
+        let (discarded, discarded_complete) = walk(objects, *old, &kept)?;
+        report.divergences.push(Divergence {
+            refname: name.clone(),
+            old: *old,
+            new: *new,
+            discarded_commits: discarded.len(),
+            exact: kept_complete && discarded_complete,
+        });
+    }
+    Ok(report)
+}
+
+/// Collect the commits reachable from `tip` that aren't in `stop`, and whether the walk completed.
+fn walk(objects: &dyn Find, tip: ObjectId, stop: &HashSet<ObjectId>) -> Result<(HashSet<ObjectId>, bool), Error> {
+    let mut seen = HashSet::new();
+    let mut queue = vec![tip];
+    let mut buf = Vec::new();
+    while let Some(id) = queue.pop() {
+        if stop.contains(&id) || seen.contains(&id) {
+            continue;
+        }
+        if seen.len() == MAX_WALK {
+            return Ok((seen, false));
+        }
+        let Some(data) = find(objects, &id, &mut buf)? else {
+            return Err(Error::Validation(format!("commit {id} is missing")));
+        };
+        if data.kind != Kind::Commit {
+            continue;
+        }
+        seen.insert(id);
+        queue.extend(CommitRefIter::from_bytes(data.data).parent_ids());
+    }
+    Ok((seen, true))
+}
+
+fn find<'a>(objects: &dyn Find, id: &oid, buf: &'a mut Vec<u8>) -> Result<Option<gix_object::Data<'a>>, Error> {
+    objects
+        .try_find(id, buf)
+        .map_err(|e| Error::Validation(format!("cannot look up object {id}: {e}")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[derive(Default)]
+    struct Objects(HashMap<ObjectId, (Kind, Vec<u8>)>);
+
+    impl Find for Objects {
+        fn try_find<'a>(
+            &self,
+            id: &oid,
+            buffer: &'a mut Vec<u8>,
+        ) -> Result<Option<gix_object::Data<'a>>, gix_object::find::Error> {
+            Ok(self.0.get(id).map(|(kind, data)| {
+                buffer.clear();
+                buffer.extend_from_slice(data);
+                gix_object::Data::new(*kind, buffer)
+            }))
+        }
+    }
+
+    impl Objects {
+        fn insert(&mut self, kind: Kind, data: Vec<u8>) -> ObjectId {
+            let id = gix_object::compute_hash(gix_hash::Kind::Sha1, kind, &data).unwrap();
+            self.0.insert(id, (kind, data));
+            id
+        }
+
+        fn commit(&mut self, message: &str, parents: &[ObjectId]) -> ObjectId {
+            let tree = self.insert(Kind::Tree, Vec::new());
+            let mut data = format!("tree {tree}\n");
+            for parent in parents {
+                data.push_str(&format!("parent {parent}\n"));
+            }
+            data.push_str("author a <a@example.com> 0 +0000\ncommitter a <a@example.com> 0 +0000\n\n");
+            data.push_str(message);
+            self.insert(Kind::Commit, data.into_bytes())
+        }
+    }
+
+    fn update(name: &str, old: ObjectId, new: ObjectId) -> CommandUpdate {
+        CommandUpdate::Update {
+            old,
+            new,
+            name: name.into(),
+        }
+    }
+
+    #[test]
+    fn rewritten_history_is_counted() {
+        let mut objects = Objects::default();
+        let base = objects.commit("base\n", &[]);
+        let first = objects.commit("first\n", &[base]);
+        let second = objects.commit("second\n", &[first]);
+        let rewritten = objects.commit("rewritten\n", &[base]);
+        let merge = objects.commit("merge\n", &[rewritten, second]);
+
+        let commands = [
+            update("refs/heads/fast-forward", first, second),
+            update("refs/heads/rewritten", second, rewritten),
+            update("refs/heads/merged", second, merge),
+            update("refs/heads/rewound", second, base),
+        ];
+        let report = divergence(&commands, &objects).unwrap();
+        assert_eq!(
+            report.divergences,
+            vec![
+                Divergence {
+                    refname: "refs/heads/rewritten".into(),
+                    old: second,
+                    new: rewritten,
+                    discarded_commits: 2,
+                    exact: true,
+                },
+                Divergence {
+                    refname: "refs/heads/rewound".into(),
+                    old: second,
+                    new: base,
+                    discarded_commits: 2,
+                    exact: true,
+                },
+            ]
+        );
+        assert_eq!(report.forced_updates(), 2);
+        assert_eq!(report.discarded_commits(), 4);
+        assert!(report.is_exact());
+    }
+
+    #[test]
+    fn non_commits_diverge_without_discarded_commits() {
+        let mut objects = Objects::default();
+        let commit = objects.commit("commit\n", &[]);
+        let blob = objects.insert(Kind::Blob, b"content".to_vec());
+
+        let report = divergence(&[update("refs/tags/v1", blob, commit)], &objects).unwrap();
+        assert_eq!(report.forced_updates(), 1);
+        assert_eq!(report.discarded_commits(), 0);
+
+        let missing = ObjectId::from_hex(b"1111111111111111111111111111111111111111").unwrap();
+        assert!(divergence(&[update("refs/heads/main", commit, missing)], &objects).is_err());
+    }
+}
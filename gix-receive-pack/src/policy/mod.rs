@@ -6,6 +6,7 @@
 //! - deny_current_branch: Forbid updates to the current branch
 //! - deny_delete_current: Forbid deletion of the current branch
 //! - update_instead: Allow worktree updates for current branch
+//! - mirror: Accept non-fast-forward updates despite deny_non_fast_forwards
 //!
 //! Policy evaluation follows a strict precedence order (first match wins):
 //! 1. deny_delete_current
@@ -13,9 +14,16 @@
 //! 3. deny_deletes
 //! 4. deny_non_fast_forwards
 //! 5. updateInstead (transform-only, not a hard allow)
+//!
+//! Pushed tag objects are checked separately by [`TagPolicy`], as its rules need the objects of the push.
+//! The same goes for the [`MirrorReport`] of forced updates accepted in mirror mode.
 
 pub mod set;
 pub mod ff;
+pub mod mirror;
+pub mod tags;
 
 pub use set::{PolicySet, PolicyDecision, ReasonCode, UpdateInstead};
-pub use ff::is_fast_forward;
\ No newline at end of file
+pub use ff::is_fast_forward;
+pub use mirror::{Divergence, MirrorReport};
+pub use tags::{TagFinding, TagPolicy, TagRule};
\ No newline at end of file
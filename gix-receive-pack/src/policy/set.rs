@@ -20,6 +20,8 @@ pub struct PolicySet {
     delete_current_policy: Policy,
     /// Enable worktree updates for current branch
     update_instead: bool,
+    /// Accept non-fast-forward updates even if they are denied
+    mirror: bool,
 }
 
 /// Policy enforcement level for specific operations.
@@ -48,12 +50,29 @@ pub enum ReasonCode {
     DenyDeleteCurrent,
     /// Allowed but delegated to worktree updater
     UpdateInstead,
+    /// Non-fast-forward update allowed in mirror mode
+    MirrorForced,
     /// Denied by hook execution
     HookRejected,
     /// Denied by proc-receive helper
     ProcReceiveRejected,
 }
 
+impl ReasonCode {
+    /// Return why the update was denied for audit records, or `None` if it was allowed.
+    pub fn denial_reason(&self) -> Option<gix_serve_core::audit::Reason> {
+        use gix_serve_core::audit::Reason;
+        match self {
+            ReasonCode::Allowed | ReasonCode::UpdateInstead | ReasonCode::MirrorForced => None,
+            ReasonCode::DenyDeletes
+            | ReasonCode::NonFastForward
+            | ReasonCode::DenyCurrent
+            | ReasonCode::DenyDeleteCurrent => Some(Reason::Policy),
+            ReasonCode::HookRejected | ReasonCode::ProcReceiveRejected => Some(Reason::Hook),
+        }
+    }
+}
+
 /// Action to be taken when updateInstead is triggered.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct UpdateInstead {
@@ -170,6 +189,7 @@ impl PolicySet {
             current_branch_policy: Policy::Allow,
             delete_current_policy: Policy::Allow,
             update_instead: false,
+            mirror: false,
         }
     }
 
@@ -228,6 +248,19 @@ impl PolicySet {
         self
     }
 
+    /// Get the mirror setting.
+    pub fn mirror(&self) -> bool {
+        self.mirror
+    }
+
+    /// Accept non-fast-forward updates even with deny_non_fast_forwards, like a mirror receiving forced pushes.
+    ///
+    /// Use [`divergence()`](super::mirror::divergence()) to record which updates were forced.
+    pub fn with_mirror(mut self, enable: bool) -> Self {
+        self.mirror = enable;
+        self
+    }
+
     /// Evaluate a command against the configured policies.
     ///
     /// This method implements the policy precedence order:
@@ -362,6 +395,14 @@ impl PolicySet {
                 // For now, use minimal fast-forward detection
                 match super::ff::is_fast_forward(*old, *new, main_odb) {
                     Ok(is_ff) => {
+                        if !is_ff && self.mirror {
+                            return Ok(PolicyDecision {
+                                allowed: true,
+                                reason_code: ReasonCode::MirrorForced,
+                                message: format!("non-fast-forward update to '{}' accepted in mirror mode", refname),
+                                delegated_action: None,
+                            });
+                        }
                         if !is_ff {
                             return Ok(PolicyDecision {
                                 allowed: false,
@@ -497,6 +538,31 @@ mod tests {
         assert!(result.is_err());
     }
 
+    #[test]
+    fn test_mirror_accepts_non_fast_forwards() {
+        use gix_object::Write;
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let odb = gix_odb::at(temp_dir.path()).unwrap();
+        let commit = |message: &str| {
+            let data = format!("tree 4b825dc642cb6eb9a060e54bf8d69288fbee4904\nauthor a <a@example.com> 0 +0000\ncommitter a <a@example.com> 0 +0000\n\n{message}\n");
+            odb.write_buf(gix_object::Kind::Commit, data.as_bytes()).unwrap()
+        };
+        let cmd = CommandUpdate::Update {
+            old: commit("old"),
+            new: commit("unrelated"),
+            name: "refs/heads/feature".to_string(),
+        };
+
+        let policy = PolicySet::new().with_deny_non_fast_forwards(true);
+        let decision = policy.evaluate_internal(&cmd, None, &odb).unwrap();
+        assert_eq!(decision.reason_code, ReasonCode::NonFastForward);
+
+        let decision = policy.with_mirror(true).evaluate_internal(&cmd, None, &odb).unwrap();
+        assert!(decision.allowed);
+        assert_eq!(decision.reason_code, ReasonCode::MirrorForced);
+    }
+
     #[test]
     fn test_update_instead_precedence() {
         let policy = PolicySet::new()
@@ -0,0 +1,370 @@
+//! Validation of pushed tag objects.
+//!
+//! Several hosting platforms refuse tags that don't make sense on the server, like tags whose target
+//! is missing, long chains of tags pointing to tags, or tags of commits that aren't part of what was
+//! pushed. [`TagPolicy`] checks the tag objects that commands of a push point to, and reports each
+//! problem as a [`TagFinding`] with the [`Policy`] configured for its [`TagRule`].
+//!
+//! All rules are [`Policy::Allow`] by default, so nothing is checked unless configured.
+
+use std::collections::HashSet;
+
+use gix_hash::{oid, ObjectId};
+use gix_object::{Find, Kind, TagRef};
+
+use super::set::Policy;
+use crate::protocol::CommandUpdate;
+use crate::Error;
+
+/// Tag chains are never followed further than this, whatever the configured maximum depth.
+const MAX_CHAIN_LENGTH: usize = 1000;
+
+/// The problems [`TagPolicy`] looks for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TagRule {
+    /// A tag in the chain points to an object that isn't present.
+    MissingTarget,
+    /// The chain of tags pointing to tags is longer than the configured maximum depth.
+    TooDeep,
+    /// The object the tag chain ends at was neither received with the push, nor is it the new value of one of
+    /// its commands.
+    OutsidePush,
+}
+
+impl TagRule {
+    /// Return the name of the rule, as used in policy violation messages.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TagRule::MissingTarget => "tag_missing_target",
+            TagRule::TooDeep => "tag_too_deep",
+            TagRule::OutsidePush => "tag_outside_push",
+        }
+    }
+}
+
+/// A problem with a tag a command of the push points to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TagFinding {
+    /// The ref whose new value is the tag.
+    pub refname: String,
+    /// The tag object the problem was found in.
+    pub tag: ObjectId,
+    /// The rule the tag violates.
+    pub rule: TagRule,
+    /// The policy configured for the rule, which is never [`Policy::Allow`].
+    pub policy: Policy,
+    /// A human-readable description of the problem.
+    pub message: String,
+}
+
+/// Rules for tag objects that are pushed, each with the policy to apply when it's violated.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TagPolicy {
+    missing_target: Policy,
+    max_depth: usize,
+    too_deep: Policy,
+    outside_push: Policy,
+}
+
+impl Default for TagPolicy {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TagPolicy {
+    /// Create a policy that allows all tags, with a maximum depth of `1`, so tags of tags are too deep once
+    /// [`TagRule::TooDeep`] is configured.
+    pub fn new() -> Self {
+        Self {
+            missing_target: Policy::Allow,
+            max_depth: 1,
+            too_deep: Policy::Allow,
+            outside_push: Policy::Allow,
+        }
+    }
+
+    /// Apply `policy` to tags whose target, or the target of a tag they point to, is missing.
+    pub fn with_missing_target(mut self, policy: Policy) -> Self {
+        self.missing_target = policy;
+        self
+    }
+
+    /// Apply `policy` to chains of more than `max_depth` tags, where a tag pointing to a commit has a depth of `1`.
+    pub fn with_max_depth(mut self, max_depth: usize, policy: Policy) -> Self {
+        self.max_depth = max_depth;
+        self.too_deep = policy;
+        self
+    }
+
+    /// Apply `policy` to tags whose chain ends at an object that isn't part of the push.
+    pub fn with_outside_push(mut self, policy: Policy) -> Self {
+        self.outside_push = policy;
+        self
+    }
+
+    /// Return the policy for `rule`.
+    pub fn policy(&self, rule: TagRule) -> Policy {
+        match rule {
+            TagRule::MissingTarget => self.missing_target,
+            TagRule::TooDeep => self.too_deep,
+            TagRule::OutsidePush => self.outside_push,
+        }
+    }
+
+    /// Return the maximum number of tags in a chain.
+    pub fn max_depth(&self) -> usize {
+        self.max_depth
+    }
+
+    /// Return `true` if no rule is checked.
+    pub fn is_disabled(&self) -> bool {
+        [TagRule::MissingTarget, TagRule::TooDeep, TagRule::OutsidePush]
+            .iter()
+            .all(|rule| self.policy(*rule) == Policy::Allow)
+    }
+
+    /// Check the tags the `commands` of a push point to, looking them up in `objects`, which has to include the
+    /// objects received with the push. `received` returns `true` for the ids of objects that were received,
+    /// like [`PushManifest::contains()`](crate::pack::PushManifest::contains()) does.
+    ///
+    /// Commands whose new value is missing or not a tag are skipped, as the connectivity check is responsible
+    /// for them.
+    pub fn evaluate(
+        &self,
+        commands: &[CommandUpdate],
+        objects: &dyn Find,
+        received: &dyn Fn(&oid) -> bool,
+    ) -> Result<Vec<TagFinding>, Error> {
+        let mut findings = Vec::new();
+        if self.is_disabled() {
+            return Ok(findings);
+        }
+
+        let new_values: HashSet<ObjectId> = commands
+            .iter()
+            .filter_map(|command| match command {
+                CommandUpdate::Create { new, .. } | CommandUpdate::Update { new, .. } => Some(*new),
+                CommandUpdate::Delete { .. } => None,
+            })
+            .collect();
+        let mut buf = Vec::new();
+        for command in commands {
+            let (CommandUpdate::Create { new, name } | CommandUpdate::Update { new, name, .. }) = command else {
+                continue;
+            };
+            let mut report = |tag: ObjectId, rule: TagRule, message: String| {
+                let policy = self.policy(rule);
+                if policy != Policy::Allow {
+                    findings.push(TagFinding {
+                        refname: name.clone(),
+                        tag,
+                        rule,
+                        policy,
+                        message,
+                    });
+                }
+            };
+
+            let (mut id, mut tag) = (*new, *new);
+            let mut depth = 0;
+            let end = loop {
+                let Some(data) = find(objects, &id, &mut buf)? else {
+                    if depth > 0 {
+                        report(tag, TagRule::MissingTarget, format!("tag {tag} points to missing object {id}"));
+                    }
+                    break None;
+                };
+                if data.kind != Kind::Tag {
+                    break Some(id);
+                }
+                tag = id;
+                depth += 1;
+                if depth > MAX_CHAIN_LENGTH {
+                    return Err(Error::Validation(format!(
+                        "tag chain of '{name}' is longer than {MAX_CHAIN_LENGTH} tags"
+                    )));
+                }
+                let target = TagRef::from_bytes(data.data)
+                    .map_err(|e| Error::Validation(format!("cannot decode tag {tag}: {e}")))?
+                    .target();
+                if depth == self.max_depth + 1 {
+                    report(
+                        *new,
+                        TagRule::TooDeep,
+                        format!("tag chain is deeper than {} tags", self.max_depth),
+                    );
+                }
+                id = target;
+            };
+
+            if let Some(end) = end.filter(|end| depth > 0 && !received(end) && !new_values.contains(end)) {
+                report(
+                    *new,
+                    TagRule::OutsidePush,
+                    format!("tagged object {end} is not part of the push"),
+                );
+            }
+        }
+        Ok(findings)
+    }
+
+    /// Like [`evaluate()`](Self::evaluate()), but fail with a policy violation for the first finding whose policy
+    /// is [`Policy::Deny`], and return the remaining findings as warnings otherwise.
+    pub fn enforce(
+        &self,
+        commands: &[CommandUpdate],
+        objects: &dyn Find,
+        received: &dyn Fn(&oid) -> bool,
+    ) -> Result<Vec<TagFinding>, Error> {
+        let findings = self.evaluate(commands, objects, received)?;
+        match findings.iter().find(|finding| finding.policy == Policy::Deny) {
+            Some(denied) => Err(Error::policy_violation(denied.rule.as_str(), &denied.refname)),
+            None => Ok(findings),
+        }
+    }
+}
+
+fn find<'a>(objects: &dyn Find, id: &oid, buf: &'a mut Vec<u8>) -> Result<Option<gix_object::Data<'a>>, Error> {
+    objects
+        .try_find(id, buf)
+        .map_err(|e| Error::Validation(format!("cannot look up object {id}: {e}")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gix_object::WriteTo;
+    use std::collections::HashMap;
+
+    #[derive(Default)]
+    struct Objects(HashMap<ObjectId, (Kind, Vec<u8>)>);
+
+    impl Find for Objects {
+        fn try_find<'a>(
+            &self,
+            id: &oid,
+            buffer: &'a mut Vec<u8>,
+        ) -> Result<Option<gix_object::Data<'a>>, gix_object::find::Error> {
+            Ok(self.0.get(id).map(|(kind, data)| {
+                buffer.clear();
+                buffer.extend_from_slice(data);
+                gix_object::Data::new(*kind, buffer)
+            }))
+        }
+    }
+
+    impl Objects {
+        fn insert(&mut self, kind: Kind, data: Vec<u8>) -> ObjectId {
+            let id = gix_object::compute_hash(gix_hash::Kind::Sha1, kind, &data).unwrap();
+            self.0.insert(id, (kind, data));
+            id
+        }
+
+        fn blob(&mut self, content: &str) -> ObjectId {
+            self.insert(Kind::Blob, content.into())
+        }
+
+        fn tag(&mut self, target: ObjectId, target_kind: Kind) -> ObjectId {
+            let tag = gix_object::Tag {
+                target,
+                target_kind,
+                name: "v1".into(),
+                tagger: None,
+                message: "release\n".into(),
+                pgp_signature: None,
+            };
+            let mut data = Vec::new();
+            tag.write_to(&mut data).unwrap();
+            self.insert(Kind::Tag, data)
+        }
+    }
+
+    fn create(name: &str, new: ObjectId) -> CommandUpdate {
+        CommandUpdate::Create {
+            new,
+            name: name.into(),
+        }
+    }
+
+    fn strict() -> TagPolicy {
+        TagPolicy::new()
+            .with_missing_target(Policy::Deny)
+            .with_max_depth(1, Policy::Warn)
+            .with_outside_push(Policy::Deny)
+    }
+
+    #[test]
+    fn nothing_is_checked_by_default() {
+        let mut objects = Objects::default();
+        let missing = ObjectId::from_hex(b"1111111111111111111111111111111111111111").unwrap();
+        let tag = objects.tag(missing, Kind::Commit);
+        let findings = TagPolicy::default()
+            .evaluate(&[create("refs/tags/v1", tag)], &objects, &|_| false)
+            .unwrap();
+        assert!(findings.is_empty());
+    }
+
+    #[test]
+    fn tags_of_received_objects_and_other_ref_values_are_accepted() {
+        let mut objects = Objects::default();
+        let blob = objects.blob("content");
+        let tag = objects.tag(blob, Kind::Blob);
+        let received = |id: &oid| id == blob;
+        assert_eq!(
+            strict()
+                .evaluate(&[create("refs/tags/v1", tag)], &objects, &received)
+                .unwrap(),
+            []
+        );
+
+        let old = objects.blob("old");
+        let commands = [create("refs/tags/v1", objects.tag(old, Kind::Blob)), create("refs/heads/main", old)];
+        assert_eq!(strict().evaluate(&commands, &objects, &|_| false).unwrap(), []);
+    }
+
+    #[test]
+    fn violations_are_reported_with_their_policy() {
+        let mut objects = Objects::default();
+        let missing = ObjectId::from_hex(b"1111111111111111111111111111111111111111").unwrap();
+        let dangling = objects.tag(missing, Kind::Commit);
+        let old = objects.blob("old");
+        let inner = objects.tag(old, Kind::Blob);
+        let nested = objects.tag(inner, Kind::Tag);
+        let commands = [
+            create("refs/tags/dangling", dangling),
+            create("refs/tags/nested", nested),
+            CommandUpdate::Delete {
+                old: dangling,
+                name: "refs/tags/deleted".into(),
+            },
+        ];
+
+        let findings = strict().evaluate(&commands, &objects, &|_| false).unwrap();
+        let rules: Vec<_> = findings
+            .iter()
+            .map(|finding| (finding.refname.as_str(), finding.rule, finding.policy))
+            .collect();
+        assert_eq!(
+            rules,
+            [
+                ("refs/tags/dangling", TagRule::MissingTarget, Policy::Deny),
+                ("refs/tags/nested", TagRule::TooDeep, Policy::Warn),
+                ("refs/tags/nested", TagRule::OutsidePush, Policy::Deny),
+            ]
+        );
+        assert_eq!(findings[0].tag, dangling);
+        assert!(findings[0].message.contains(&missing.to_string()), "{}", findings[0].message);
+
+        let err = strict().enforce(&commands, &objects, &|_| false).unwrap_err();
+        assert_eq!(err.to_string(), "validation error: policy: tag_missing_target: refs/tags/dangling");
+        let warnings = strict()
+            .with_missing_target(Policy::Warn)
+            .with_outside_push(Policy::Allow)
+            .with_max_depth(2, Policy::Deny)
+            .enforce(&commands, &objects, &|_| false)
+            .unwrap();
+        assert_eq!(warnings.len(), 1, "nested tags are within the limit now");
+        assert_eq!(warnings[0].rule, TagRule::MissingTarget);
+    }
+}
@@ -71,40 +71,12 @@ mod blocking {
             caps: &CapabilitySet,
             hidden: Option<&HiddenRefPredicate>,
         ) -> Result<(), crate::Error> {
-            let mut visible: Vec<&RefRecord> = match hidden {
-                Some(pred) => refs.iter().filter(|r| !(pred)(r)).collect(),
-                None => refs.iter().collect(),
-            };
-
             let caps_line = self.formatter.format_capabilities(caps);
-
-            if visible.is_empty() {
-                // Empty repository: emit a special capabilities line with a zero OID and 'capabilities^{}'
-                let zeros = "0".repeat(40); // SHA-1 default; object-format enforcement is added in M2.
-                let first = format!("{zeros} capabilities^{{}}\0{caps_line}");
-                self.out
-                    .write_all(first.as_bytes())
-                    .map_err(|_| crate::Error::Unimplemented)?;
-                pkt::encode::flush_to_write(self.out.inner_mut()).map_err(|_| crate::Error::Unimplemented)?;
-                self.out.flush().map_err(|_| crate::Error::Unimplemented)?;
-                return Ok(());
-            }
-
-            // First visible ref line carries capabilities after a NUL
-            let first_ref = visible.remove(0);
-            let first = format!("{} {}\0{caps_line}", first_ref.oid.to_string(), first_ref.name);
+            let advertisement = crate::protocol::machine::encode_advertisement(refs, &caps_line, hidden);
             self.out
-                .write_all(first.as_bytes())
+                .inner_mut()
+                .write_all(&advertisement)
                 .map_err(|_| crate::Error::Unimplemented)?;
-
-            // Remaining refs as standard lines
-            for r in visible {
-                let line = format!("{} {}", r.oid.to_string(), r.name);
-                self.out.write_all(line.as_bytes()).map_err(|_| crate::Error::Unimplemented)?;
-            }
-
-            // Final flush
-            pkt::encode::flush_to_write(self.out.inner_mut()).map_err(|_| crate::Error::Unimplemented)?;
             self.out.flush().map_err(|_| crate::Error::Unimplemented)?;
             Ok(())
         }
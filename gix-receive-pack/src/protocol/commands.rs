@@ -13,6 +13,7 @@
 // Wire IO integration (pkt-line iteration) can be added later; this file focuses on
 // robust, typed parsing independent of IO.
 
+use crate::protocol::limits::HeadInfoLimits;
 use crate::protocol::options::Options;
 use crate::Error;
 use gix_hash::ObjectId;
@@ -37,6 +38,24 @@ impl CommandUpdate {
             CommandUpdate::Delete { name, .. } => name,
         }
     }
+
+    /// The object the ref points to before and after this command, `None` where the ref doesn't exist.
+    pub fn old_and_new(&self) -> (Option<ObjectId>, Option<ObjectId>) {
+        match self {
+            CommandUpdate::Create { new, .. } => (None, Some(*new)),
+            CommandUpdate::Update { old, new, .. } => (Some(*old), Some(*new)),
+            CommandUpdate::Delete { old, .. } => (Some(*old), None),
+        }
+    }
+
+    /// The refname targeted by this command, for rewriting it.
+    pub(crate) fn name_mut(&mut self) -> &mut String {
+        match self {
+            CommandUpdate::Create { name, .. } => name,
+            CommandUpdate::Update { name, .. } => name,
+            CommandUpdate::Delete { name, .. } => name,
+        }
+    }
 }
 
 /// A list of parsed update commands.
@@ -58,6 +77,10 @@ impl CommandList {
         self.commands.iter()
     }
 
+    pub(crate) fn iter_mut(&mut self) -> impl Iterator<Item = &mut CommandUpdate> {
+        self.commands.iter_mut()
+    }
+
     pub fn len(&self) -> usize {
         self.commands.len()
     }
@@ -66,6 +89,47 @@ impl CommandList {
         self.commands.is_empty()
     }
 
+    /// Return the commands in the order they should be applied to avoid transient broken states, using
+    /// `symref_target` to look up the ref a symbolic ref points to, or `None` for refs that aren't symbolic.
+    ///
+    /// - Deletions come first, so a push can replace `refs/heads/a` with `refs/heads/a/b` or the other way around
+    ///   without a directory/file conflict. Symbolic refs are deleted before the refs they point to.
+    /// - All other commands follow with the refs symbolic refs point to before the symbolic refs, so no symbolic
+    ///   ref points to a ref that wasn't updated yet.
+    ///
+    /// Otherwise commands are sorted by refname, so the order doesn't depend on the order the client sent them in.
+    pub fn application_order(&self, symref_target: impl Fn(&str) -> Option<String>) -> Vec<&CommandUpdate> {
+        /// Symbolic ref chains are never followed further than this, which also ends cycles.
+        const MAX_SYMREF_DEPTH: usize = 5;
+        let depth = |name: &str| {
+            let mut name = name.to_owned();
+            let mut depth = 0;
+            while depth < MAX_SYMREF_DEPTH {
+                match symref_target(&name) {
+                    Some(target) => name = target,
+                    None => break,
+                }
+                depth += 1;
+            }
+            depth
+        };
+
+        let mut ordered: Vec<_> = self
+            .commands
+            .iter()
+            .map(|cmd| {
+                let depth = depth(cmd.name());
+                let key = match cmd {
+                    CommandUpdate::Delete { .. } => (0, MAX_SYMREF_DEPTH - depth),
+                    CommandUpdate::Create { .. } | CommandUpdate::Update { .. } => (1, depth),
+                };
+                (key, cmd)
+            })
+            .collect();
+        ordered.sort_by(|(a, a_cmd), (b, b_cmd)| a.cmp(b).then_with(|| a_cmd.name().cmp(b_cmd.name())));
+        ordered.into_iter().map(|(_, cmd)| cmd).collect()
+    }
+
     /// Parse head-info from text, one logical line per `\n`.
     ///
     /// - Command lines: "<old> <new> <ref>[\\0caps]"
@@ -83,11 +147,19 @@ impl CommandList {
     ///   - Update: old and new are non-zero
     ///   - Both zero → invalid
     pub fn parse_from_text(text: &str) -> Result<(Self, Options), Error> {
+        Self::parse_with_limits(text, &HeadInfoLimits::default())
+    }
+
+    /// Parse head-info from text like [`parse_from_text()`](Self::parse_from_text()), failing with a
+    /// validation error naming the offending line, command or push option once `limits` are exceeded.
+    pub fn parse_with_limits(text: &str, limits: &HeadInfoLimits) -> Result<(Self, Options), Error> {
         let mut list = CommandList::new();
         let mut opts = Options::default();
         let mut caps_seen = false;
+        let mut check = limits.check();
 
         for raw_line in text.lines() {
+            check.line(raw_line)?;
             let line = raw_line.trim_end_matches('\r');
             if line.is_empty() {
                 continue;
@@ -327,6 +399,51 @@ mod tests {
         }
     }
 
+    #[test]
+    fn limits_name_the_offending_line_command_or_push_option() {
+        let text = concat!(
+            "0000000000000000000000000000000000000000 1111111111111111111111111111111111111111 refs/heads/a\0report-status\n",
+            "push-option=ci.skip\n",
+            "0000000000000000000000000000000000000000 1111111111111111111111111111111111111111 refs/heads/b\n",
+            "push-option=reviewer=someone-with-a-long-name\n",
+            "0000000000000000000000000000000000000000 1111111111111111111111111111111111111111 refs/heads/c\n",
+        );
+        let message = |limits: HeadInfoLimits| match CommandList::parse_with_limits(text, &limits) {
+            Err(Error::Validation(message)) => message,
+            other => panic!("expected Validation, got {other:?}"),
+        };
+
+        assert_eq!(
+            message(HeadInfoLimits::default().with_max_commands(2)),
+            "command 3 exceeds the limit of 2 commands per push"
+        );
+        assert_eq!(
+            message(HeadInfoLimits::default().with_max_push_options(1)),
+            "push option 2 exceeds the limit of 1 push options per push"
+        );
+        assert_eq!(
+            message(HeadInfoLimits::default().with_max_push_option_length(10)),
+            "push option 2 is 33 bytes long, at most 10 are allowed"
+        );
+        assert_eq!(
+            message(HeadInfoLimits::default().with_max_bytes(200)),
+            "head-info exceeds 200 bytes at line 3"
+        );
+        assert_eq!(
+            message(HeadInfoLimits::default().with_max_line_length(100)),
+            "head-info line 1 is 108 bytes long, at most 100 are allowed"
+        );
+
+        let limits = HeadInfoLimits::default()
+            .with_max_bytes(text.len())
+            .with_max_line_length(108)
+            .with_max_commands(3)
+            .with_max_push_options(2)
+            .with_max_push_option_length(33);
+        let (list, opts) = CommandList::parse_with_limits(text, &limits).unwrap();
+        assert_eq!((list.len(), opts.push_options.len()), (3, 2));
+    }
+
     #[test]
     fn unshallow_parsing_and_validation() {
         let text = concat!(
@@ -337,4 +454,43 @@ mod tests {
         assert_eq!(opts.unshallow.len(), 1);
         assert_eq!(opts.unshallow[0], oid("4444444444444444444444444444444444444444"));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn application_order_avoids_transient_broken_states() {
+        let text = concat!(
+            "1111111111111111111111111111111111111111 2222222222222222222222222222222222222222 refs/heads/alias\n",
+            "0000000000000000000000000000000000000000 1111111111111111111111111111111111111111 refs/heads/a/b\n",
+            "1111111111111111111111111111111111111111 2222222222222222222222222222222222222222 refs/heads/main\n",
+            "1111111111111111111111111111111111111111 0000000000000000000000000000000000000000 refs/heads/a\n",
+            "1111111111111111111111111111111111111111 0000000000000000000000000000000000000000 refs/heads/z\n",
+            "1111111111111111111111111111111111111111 0000000000000000000000000000000000000000 refs/heads/link\n",
+        );
+        let (list, _opts) = CommandList::parse_from_text(text).unwrap();
+        let symref_target = |name: &str| match name {
+            "refs/heads/alias" => Some("refs/heads/main".to_owned()),
+            "refs/heads/link" => Some("refs/heads/z".to_owned()),
+            "refs/heads/cycle" => Some("refs/heads/cycle".to_owned()),
+            _ => None,
+        };
+        let names: Vec<_> = list.application_order(symref_target).into_iter().map(CommandUpdate::name).collect();
+        assert_eq!(
+            names,
+            [
+                "refs/heads/link",
+                "refs/heads/a",
+                "refs/heads/z",
+                "refs/heads/a/b",
+                "refs/heads/main",
+                "refs/heads/alias",
+            ],
+            "deletions first with symbolic refs before their targets, then targets before symbolic refs"
+        );
+
+        let mut cycle = CommandList::new();
+        cycle.push(CommandUpdate::Delete {
+            old: oid("1111111111111111111111111111111111111111"),
+            name: "refs/heads/cycle".into(),
+        });
+        assert_eq!(cycle.application_order(symref_target).len(), 1, "cycles end");
+    }
+}
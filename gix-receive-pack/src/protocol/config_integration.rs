@@ -6,6 +6,7 @@
 
 use super::capabilities::{CapabilityOrdering, CapabilitySet};
 use super::advertise::Advertiser;
+use super::truncation::AdvertisementLimits;
 use std::io::Write;
 
 /// Example configuration structure that higher layers might use to inject
@@ -23,6 +24,10 @@ pub struct AdvertisementConfig {
     /// Maps from `receive.advertiseAtomic` configuration.
     pub advertise_atomic: bool,
     
+    /// Whether to advertise the push-options capability.
+    /// Maps from `receive.advertisePushOptions` configuration.
+    pub advertise_push_options: bool,
+    
     /// Whether to use strict compatibility mode for capability ordering.
     /// This would be controlled by feature flags or configuration.
     pub strict_compat: bool,
@@ -30,6 +35,10 @@ pub struct AdvertisementConfig {
     /// Additional capability tokens to advertise.
     /// These might come from extensions or plugin configuration.
     pub extra_capabilities: Vec<String>,
+
+    /// Limits for the advertised refs, for repositories with enormous ref counts.
+    /// Maps from `receive.advertiseMaxRefs` and related configuration, see [`AdvertisementLimits::from_config()`].
+    pub limits: AdvertisementLimits,
 }
 
 impl AdvertisementConfig {
@@ -38,8 +47,10 @@ impl AdvertisementConfig {
         Self {
             agent: Some("gix-receive-pack/0.1.0".to_string()),
             advertise_atomic: false, // Conservative default
+            advertise_push_options: false, // Like git
             strict_compat: false,
             extra_capabilities: Vec::new(),
+            limits: AdvertisementLimits::default(),
         }
     }
     
@@ -56,12 +67,33 @@ impl AdvertisementConfig {
         self
     }
     
+    /// Enable or disable push-options capability advertisement.
+    /// This maps directly from `receive.advertisePushOptions` git configuration.
+    pub fn with_push_options(mut self, enabled: bool) -> Self {
+        self.advertise_push_options = enabled;
+        self
+    }
+    
+    /// Apply `receive.advertisePushOptions` from `config`, keeping the current setting if it's not set.
+    pub fn with_config(mut self, config: &gix_config::File<'static>) -> Result<Self, crate::Error> {
+        if let Some(enabled) = crate::config::keys::receive::ADVERTISE_PUSH_OPTIONS.get(config)? {
+            self.advertise_push_options = enabled;
+        }
+        Ok(self)
+    }
+    
     /// Enable strict compatibility mode for upstream byte-for-byte parity.
     pub fn with_strict_compat(mut self, enabled: bool) -> Self {
         self.strict_compat = enabled;
         self
     }
     
+    /// Set the limits for the advertised refs.
+    pub fn with_limits(mut self, limits: AdvertisementLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+    
     /// Add an extra capability token.
     pub fn push_extra_capability<S: Into<String>>(mut self, token: S) -> Self {
         self.extra_capabilities.push(token.into());
@@ -82,6 +114,11 @@ impl From<AdvertisementConfig> for CapabilitySet {
             caps.push_extra("atomic");
         }
         
+        // Map receive.advertisePushOptions → push-options token
+        if config.advertise_push_options {
+            caps.push_extra("push-options");
+        }
+        
         // Add any extra capabilities from configuration
         for token in config.extra_capabilities {
             caps.push_extra(token);
@@ -149,6 +186,19 @@ mod tests {
         assert!(encoded.contains("atomic"));
     }
 
+    #[test]
+    fn config_with_push_options_capability() {
+        let caps: CapabilitySet = AdvertisementConfig::modern_defaults().into();
+        assert!(!caps.encode(CapabilityOrdering::PreserveIdiomatic).contains("push-options")); // Like git
+        
+        let config = gix_config::File::try_from("[receive]\n\tadvertisePushOptions = true").unwrap();
+        let caps: CapabilitySet = AdvertisementConfig::modern_defaults()
+            .with_config(&config)
+            .unwrap()
+            .into();
+        assert!(caps.encode(CapabilityOrdering::PreserveIdiomatic).contains("push-options"));
+    }
+
     #[test]
     fn config_with_custom_agent() {
         let config = AdvertisementConfig::modern_defaults()
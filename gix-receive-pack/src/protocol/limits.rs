@@ -0,0 +1,252 @@
+// M2: Limits on head-info, enforced while it is parsed.
+//
+// Head-info is buffered completely before it is parsed, so a crafted push could make the server hold
+// arbitrarily many commands or push options in memory before pack data even starts. These limits bound
+// the size of head-info as a whole, the length of each line, the number of commands and the number and
+// length of push options. The `Machine` checks them as lines arrive, before buffering them, and
+// `CommandList::parse_with_limits()` checks head-info that was buffered as text.
+//
+// Violations are `Error::Validation`s naming the 1-based line, command or push option that exceeded a limit.
+
+use crate::config::keys;
+use crate::protocol::push_cert;
+use crate::Error;
+
+/// Limits on the head-info of a push, all disabled by default.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct HeadInfoLimits {
+    /// Accept at most this many bytes of head-info, including line endings.
+    pub max_bytes: Option<usize>,
+    /// Accept head-info lines of at most this many bytes, excluding the line ending.
+    pub max_line_length: Option<usize>,
+    /// Accept at most this many update commands per push.
+    pub max_commands: Option<usize>,
+    /// Accept at most this many push options per push.
+    pub max_push_options: Option<usize>,
+    /// Accept push options of at most this many bytes each.
+    pub max_push_option_length: Option<usize>,
+}
+
+impl HeadInfoLimits {
+    /// Load the limits from `receive.maxHeadInfoSize`, `receive.maxHeadInfoLineLength`, `receive.maxCommands`,
+    /// `receive.maxPushOptions` and `receive.maxPushOptionLength`.
+    ///
+    /// Negative limits are rejected, and `0` disables the respective limit.
+    pub fn from_config(config: &gix_config::File<'static>) -> Result<Self, Error> {
+        let limit = |key: keys::Key<i64>| -> Result<Option<usize>, Error> {
+            match key.get(config)? {
+                None | Some(0) => Ok(None),
+                Some(value) => usize::try_from(value).map(Some).map_err(|_| {
+                    Error::Validation(format!("invalid value for '{}': must not be negative", key.name()))
+                }),
+            }
+        };
+        Ok(Self {
+            max_bytes: limit(keys::receive::MAX_HEAD_INFO_SIZE)?,
+            max_line_length: limit(keys::receive::MAX_HEAD_INFO_LINE_LENGTH)?,
+            max_commands: limit(keys::receive::MAX_COMMANDS)?,
+            max_push_options: limit(keys::receive::MAX_PUSH_OPTIONS)?,
+            max_push_option_length: limit(keys::receive::MAX_PUSH_OPTION_LENGTH)?,
+        })
+    }
+
+    /// Accept at most `max` bytes of head-info.
+    pub fn with_max_bytes(mut self, max: impl Into<Option<usize>>) -> Self {
+        self.max_bytes = max.into();
+        self
+    }
+
+    /// Accept head-info lines of at most `max` bytes.
+    pub fn with_max_line_length(mut self, max: impl Into<Option<usize>>) -> Self {
+        self.max_line_length = max.into();
+        self
+    }
+
+    /// Accept at most `max` update commands per push.
+    pub fn with_max_commands(mut self, max: impl Into<Option<usize>>) -> Self {
+        self.max_commands = max.into();
+        self
+    }
+
+    /// Accept at most `max` push options per push.
+    pub fn with_max_push_options(mut self, max: impl Into<Option<usize>>) -> Self {
+        self.max_push_options = max.into();
+        self
+    }
+
+    /// Accept push options of at most `max` bytes each.
+    pub fn with_max_push_option_length(mut self, max: impl Into<Option<usize>>) -> Self {
+        self.max_push_option_length = max.into();
+        self
+    }
+
+    /// Start checking head-info against these limits, one line at a time.
+    pub(crate) fn check(&self) -> HeadInfoCheck {
+        HeadInfoCheck {
+            limits: *self,
+            lines: 0,
+            bytes: 0,
+            commands: 0,
+            push_options: 0,
+            push_cert: None,
+        }
+    }
+}
+
+/// The state of checking head-info against [`HeadInfoLimits`] as it arrives.
+#[derive(Debug, Clone)]
+pub(crate) struct HeadInfoCheck {
+    limits: HeadInfoLimits,
+    lines: usize,
+    bytes: usize,
+    commands: usize,
+    push_options: usize,
+    /// The section of the push certificate the lines are in, if they are in one.
+    push_cert: Option<CertSection>,
+}
+
+/// The sections of a push certificate in head-info.
+#[derive(Debug, Clone, Copy)]
+enum CertSection {
+    Headers,
+    Commands,
+    Signature,
+}
+
+impl HeadInfoCheck {
+    /// Check the next head-info `line`, without its line ending.
+    pub(crate) fn line(&mut self, line: &str) -> Result<(), Error> {
+        self.lines += 1;
+        self.bytes += line.len() + 1;
+        let (index, limits) = (self.lines, &self.limits);
+        if let Some(max) = limits.max_line_length.filter(|max| line.len() > *max) {
+            return Err(Error::Validation(format!(
+                "head-info line {index} is {} bytes long, at most {max} are allowed",
+                line.len()
+            )));
+        }
+        if let Some(max) = limits.max_bytes.filter(|max| self.bytes > *max) {
+            return Err(Error::Validation(format!(
+                "head-info exceeds {max} bytes at line {index}"
+            )));
+        }
+
+        let line = line.trim_end_matches('\r');
+        // Only the commands of a push certificate count, its headers and signature are checked for their size.
+        if let Some(section) = self.push_cert.as_mut() {
+            match *section {
+                _ if line == push_cert::END => self.push_cert = None,
+                CertSection::Headers if line.is_empty() => *section = CertSection::Commands,
+                CertSection::Commands if line.starts_with("-----BEGIN ") => *section = CertSection::Signature,
+                CertSection::Commands if !line.is_empty() => return self.command(),
+                _ => {}
+            }
+            return Ok(());
+        }
+        if line
+            .strip_prefix(push_cert::BEGIN)
+            .is_some_and(|rest| rest.starts_with('\0'))
+        {
+            self.push_cert = Some(CertSection::Headers);
+            return Ok(());
+        }
+        if let Some(value) = line.strip_prefix("push-option=") {
+            return self.push_option(value);
+        }
+        if line.is_empty() || line.starts_with("shallow ") || line.starts_with("unshallow ") {
+            return Ok(());
+        }
+        self.command()
+    }
+
+    /// Count the next update command.
+    fn command(&mut self) -> Result<(), Error> {
+        self.commands += 1;
+        match self.limits.max_commands.filter(|max| self.commands > *max) {
+            Some(max) => Err(Error::Validation(format!(
+                "command {} exceeds the limit of {max} commands per push",
+                self.commands
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    /// Check the next push option `value`, sent in head-info or after it.
+    pub(crate) fn push_option(&mut self, value: &str) -> Result<(), Error> {
+        self.push_options += 1;
+        let index = self.push_options;
+        if let Some(max) = self.limits.max_push_options.filter(|max| index > *max) {
+            return Err(Error::Validation(format!(
+                "push option {index} exceeds the limit of {max} push options per push"
+            )));
+        }
+        if let Some(max) = self.limits.max_push_option_length.filter(|max| value.len() > *max) {
+            return Err(Error::Validation(format!(
+                "push option {index} is {} bytes long, at most {max} are allowed",
+                value.len()
+            )));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn disabled_by_default() {
+        let mut check = HeadInfoLimits::default().check();
+        let long = "x".repeat(100_000);
+        for _ in 0..1000 {
+            check.line(&long).unwrap();
+            check.push_option(&long).unwrap();
+        }
+    }
+
+    #[test]
+    fn only_the_commands_of_push_certificates_count() {
+        let command =
+            "0000000000000000000000000000000000000000 1111111111111111111111111111111111111111 refs/heads/main";
+        let mut check = HeadInfoLimits::default().with_max_commands(1).check();
+        for line in [
+            "push-cert\0report-status",
+            "certificate version 0.1",
+            "nonce 1-abc",
+            "",
+            command,
+            "-----BEGIN PGP SIGNATURE-----",
+            "",
+            "-----END PGP SIGNATURE-----",
+            "push-cert-end",
+        ] {
+            check.line(line).unwrap();
+        }
+        let err = check.line(command).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "validation error: command 2 exceeds the limit of 1 commands per push"
+        );
+    }
+
+    #[test]
+    fn from_config_reads_limits() {
+        let config: gix_config::File<'static> = "[receive]\n\tmaxHeadInfoSize = 4096\n\tmaxCommands = 0\n\tmaxPushOptions = 2\n\tmaxPushOptionLength = 16\n"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            HeadInfoLimits::from_config(&config).unwrap(),
+            HeadInfoLimits::default()
+                .with_max_bytes(4096)
+                .with_max_push_options(2)
+                .with_max_push_option_length(16)
+        );
+
+        let config: gix_config::File<'static> = "[receive]\n\tmaxCommands = -1\n".parse().unwrap();
+        let err = HeadInfoLimits::from_config(&config).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "validation error: invalid value for 'receive.maxCommands': must not be negative"
+        );
+    }
+}
@@ -0,0 +1,1308 @@
+// M8: Sans-IO protocol core.
+//
+// The receive-pack conversation is a fixed sequence of phases:
+//   advertise → head-info → [push-options] → [pack] → [report] → done
+//
+// `Machine` implements these phases without performing any IO. Transports feed it the bytes they
+// received with `push_input()`, ask it what happened with `poll()`, and send whatever
+// `take_output()` returns. This keeps the protocol logic testable on plain byte slices and
+// reusable for transports that are neither `std::io` nor tokio based.
+//
+// Thin blocking and async drivers which run a `Machine` over a reader/writer pair and delegate
+// the actual work to a `Handler` are layered on top, behind the respective IO features.
+
+use super::{HiddenRefPredicate, RefRecord};
+use crate::protocol::capabilities::{CapabilityFormatter, CapabilityOrdering, CapabilitySet, IdiomaticFormatter};
+use crate::protocol::commands::{CommandList, CommandUpdate};
+use crate::protocol::limits::{HeadInfoCheck, HeadInfoLimits};
+use crate::protocol::options::{CapabilityStrictness, Options};
+use crate::protocol::push_cert;
+use crate::protocol::report::{ExtendedStatus, ReportFormat, ReportWriter};
+use crate::protocol::rewrite::RefRewrites;
+use crate::Error;
+use gix_packetline_blocking::{decode, PacketLineRef};
+use gix_serve_core::audit::Reason;
+
+/// The largest payload of a single pkt-line, excluding its 4 byte length prefix.
+const MAX_DATA_LEN: usize = 65516;
+
+/// The phase a [`Machine`] is in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    /// The ref advertisement has to be produced with [`Machine::advertise()`].
+    Advertise,
+    /// Command lines are read until the terminating flush.
+    HeadInfo,
+    /// Push options are read until the terminating flush, if `push-options` was negotiated.
+    PushOptions,
+    /// Raw pack data follows until the handler saw the complete pack or the input ends.
+    Pack,
+    /// The result of the push has to be reported with [`Machine::report()`].
+    Report,
+    /// The conversation is complete, even though progress may still be sent until the final flush.
+    Done,
+}
+
+/// What a [`Machine`] produced after consuming input, as returned by [`Machine::poll()`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    /// More input is needed, or [`Machine::finish_input()`] has to be called if there is none.
+    NeedInput,
+    /// Head-info was received completely, including push options if these were negotiated.
+    Commands {
+        /// The update commands sent by the client.
+        commands: CommandList,
+        /// The negotiated capabilities, push options and shallow lines, boxed as they are large.
+        options: Box<Options>,
+    },
+    /// A chunk of pack data; call [`Machine::end_pack()`] once it completed the pack.
+    PackData(Vec<u8>),
+    /// The input ended while reading the pack, which is now considered complete.
+    PackEnd,
+    /// The push result is expected next with [`Machine::report()`].
+    NeedReport,
+    /// The conversation is complete, and remaining output, including the final flush, should be sent.
+    Done,
+}
+
+/// The status of a single ref update, as reported to the client.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RefStatus {
+    /// The refname of the command.
+    pub name: String,
+    /// `None` if the update succeeded, or the reason it was rejected.
+    pub error: Option<String>,
+    /// Why the update was denied if a policy or hook rejected it, as opposed to failing, for audit records.
+    pub denial: Option<Reason>,
+    /// How the ref was actually updated, reported with `report-status-v2` if the update succeeded.
+    pub extended: Vec<ExtendedStatus>,
+}
+
+impl RefStatus {
+    /// A successful update of `name`.
+    pub fn ok(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            error: None,
+            denial: None,
+            extended: Vec::new(),
+        }
+    }
+
+    /// A rejected update of `name` with `reason`.
+    pub fn rejected(name: impl Into<String>, reason: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            error: Some(reason.into()),
+            denial: None,
+            extended: Vec::new(),
+        }
+    }
+
+    /// An update of `name` rejected with `reason` as it was denied for `denial`, like by a policy or hook.
+    pub fn denied(name: impl Into<String>, reason: impl Into<String>, denial: Reason) -> Self {
+        Self {
+            denial: Some(denial),
+            ..Self::rejected(name, reason)
+        }
+    }
+
+    /// Add `extended` to the ways the ref was actually updated.
+    pub fn with_extended(mut self, extended: ExtendedStatus) -> Self {
+        self.extended.push(extended);
+        self
+    }
+}
+
+/// The outcome of a push, as sent in the report phase.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Report {
+    /// `None` if the pack was unpacked successfully, or the reason it failed.
+    pub unpack_error: Option<String>,
+    /// The status of each command, in any order as they are sent sorted by refname.
+    pub refs: Vec<RefStatus>,
+}
+
+/// A sans-io receive-pack state machine, see the [module documentation](self).
+pub struct Machine {
+    phase: Phase,
+    formatter: Box<dyn CapabilityFormatter + Send + Sync>,
+    advertised: Option<CapabilitySet>,
+    input: Vec<u8>,
+    input_finished: bool,
+    output: Vec<u8>,
+    commands: CommandList,
+    options: Options,
+    head_info: String,
+    check: HeadInfoCheck,
+    limit_exceeded: bool,
+    expect_pack: bool,
+    side_band: bool,
+    report_format: Option<ReportFormat>,
+    final_flush: bool,
+    strictness: CapabilityStrictness,
+    rewrites: RefRewrites,
+    principal: Option<String>,
+    /// `(stored, pushed)` names of the commands whose refname was rewritten.
+    renamed: Vec<(String, String)>,
+}
+
+impl Default for Machine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Machine {
+    /// Create a machine which starts by advertising refs.
+    pub fn new() -> Self {
+        Self {
+            phase: Phase::Advertise,
+            formatter: Box::new(IdiomaticFormatter::new(CapabilityOrdering::PreserveIdiomatic)),
+            advertised: None,
+            input: Vec::new(),
+            input_finished: false,
+            output: Vec::new(),
+            commands: CommandList::new(),
+            options: Options::default(),
+            head_info: String::new(),
+            check: HeadInfoLimits::default().check(),
+            limit_exceeded: false,
+            expect_pack: false,
+            side_band: false,
+            report_format: None,
+            final_flush: false,
+            strictness: CapabilityStrictness::Strict,
+            rewrites: RefRewrites::default(),
+            principal: None,
+            renamed: Vec::new(),
+        }
+    }
+
+    /// Create a machine for a stateless request, which starts at head-info as the advertisement
+    /// with `advertised` capabilities was sent in an earlier request.
+    pub fn for_request(advertised: CapabilitySet) -> Self {
+        Self {
+            phase: Phase::HeadInfo,
+            advertised: Some(advertised),
+            ..Self::new()
+        }
+    }
+
+    /// Set a custom capability formatter for the advertisement.
+    pub fn with_formatter(mut self, formatter: Box<dyn CapabilityFormatter + Send + Sync>) -> Self {
+        self.formatter = formatter;
+        self
+    }
+
+    /// Set how capabilities the client sent but we didn't advertise are treated.
+    ///
+    /// With [`CapabilityStrictness::WarnAndIgnore`], ignored capabilities are listed in the options of
+    /// [`Event::Commands`], and a warning for each is sent on the progress band if `side-band-64k` was negotiated.
+    pub fn with_capability_strictness(mut self, strictness: CapabilityStrictness) -> Self {
+        self.strictness = strictness;
+        self
+    }
+
+    /// Store pushed refs under the names `rewrites` map them to for pushes authenticated as `principal`.
+    ///
+    /// [`Event::Commands`] carries the stored names, and so should the [`Report`], while the client sees the names
+    /// it pushed in its report.
+    pub fn with_ref_rewrites(mut self, rewrites: RefRewrites, principal: Option<String>) -> Self {
+        self.rewrites = rewrites;
+        self.principal = principal;
+        self
+    }
+
+    /// Refuse pushes whose head-info or push options exceed `limits`, checked as lines arrive so that no more
+    /// than the limits allow is ever buffered.
+    pub fn with_head_info_limits(mut self, limits: HeadInfoLimits) -> Self {
+        self.check = limits.check();
+        self
+    }
+
+    /// Whether [`poll()`](Self::poll()) failed as the client exceeded the [head-info limits](Self::with_head_info_limits()).
+    pub fn head_info_limit_exceeded(&self) -> bool {
+        self.limit_exceeded
+    }
+
+    /// The phase the machine is currently in.
+    pub fn phase(&self) -> Phase {
+        self.phase
+    }
+
+    /// Whether the client asked for a report with `report-status` or `report-status-v2`, known once
+    /// [`Event::Commands`] was returned.
+    ///
+    /// Only negotiated capabilities count, so with [`CapabilityStrictness::WarnAndIgnore`] a client asking for a
+    /// report that wasn't advertised doesn't get one.
+    pub fn report_requested(&self) -> bool {
+        self.report_format.is_some()
+    }
+
+    /// Produce the advertisement of `refs` that aren't `hidden` with capabilities `caps`, and move on to head-info.
+    pub fn advertise(
+        &mut self,
+        refs: &[RefRecord],
+        caps: &CapabilitySet,
+        hidden: Option<&HiddenRefPredicate>,
+    ) -> Result<(), Error> {
+        self.expect_phase(Phase::Advertise, "advertise")?;
+        let caps_line = self.formatter.format_capabilities(caps);
+        self.output
+            .extend_from_slice(&encode_advertisement(refs, &caps_line, hidden));
+        self.advertised = Some(caps.clone());
+        self.phase = Phase::HeadInfo;
+        Ok(())
+    }
+
+    /// Add client input as it was received.
+    pub fn push_input(&mut self, data: &[u8]) {
+        self.input.extend_from_slice(data);
+    }
+
+    /// Signal that the client will not send any more input.
+    pub fn finish_input(&mut self) {
+        self.input_finished = true;
+    }
+
+    /// Take all output produced so far, to be sent to the client.
+    pub fn take_output(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.output)
+    }
+
+    /// Consume as much input as possible and return what happened.
+    pub fn poll(&mut self) -> Result<Event, Error> {
+        loop {
+            match self.phase {
+                Phase::Advertise => {
+                    return Err(Error::Protocol(
+                        "input received before the advertisement was sent".into(),
+                    ))
+                }
+                Phase::HeadInfo => match self.next_line()? {
+                    Some(Line::Data(line)) => {
+                        let line = String::from_utf8(line)
+                            .map_err(|_| Error::Protocol("head-info line is not valid UTF-8".into()))?;
+                        let line = line.trim_end_matches('\n');
+                        let checked = self.check.line(line);
+                        self.limited(checked)?;
+                        self.head_info.push_str(line);
+                        self.head_info.push('\n');
+                    }
+                    Some(Line::Flush) => {
+                        // Limits were checked as lines arrived.
+                        let (commands, mut options) = match push_cert::split_head_info(&self.head_info)? {
+                            Some((head_info, certificate)) => {
+                                let (commands, mut options) = CommandList::parse_from_text(&head_info)?;
+                                options.push_cert = Some(certificate);
+                                (commands, options)
+                            }
+                            None => CommandList::parse_from_text(&self.head_info)?,
+                        };
+                        if let Some(advertised) = &self.advertised {
+                            options.validate_with(advertised, self.strictness)?;
+                        }
+                        self.head_info.clear();
+                        if commands.is_empty() {
+                            self.phase = Phase::Done;
+                            continue;
+                        }
+                        self.expect_pack = commands.iter().any(|cmd| !matches!(cmd, CommandUpdate::Delete { .. }));
+                        self.side_band = options.has("side-band-64k");
+                        self.report_format = ReportFormat::from_options(&options);
+                        for warning in options.warnings() {
+                            self.progress(format!("{warning}\n").as_bytes());
+                        }
+                        self.commands = commands;
+                        self.options = options;
+                        if self.options.has("push-options") {
+                            self.phase = Phase::PushOptions;
+                            continue;
+                        }
+                        return self.commands_received();
+                    }
+                    Some(Line::Delimiter) => {
+                        return Err(Error::Protocol("unexpected delimiter packet in head-info".into()))
+                    }
+                    // A client with nothing to push may disconnect right after the advertisement.
+                    None if self.input_finished && self.input.is_empty() && self.head_info.is_empty() => {
+                        self.phase = Phase::Done;
+                    }
+                    None => return self.need_input("head-info"),
+                },
+                Phase::PushOptions => match self.next_line()? {
+                    Some(Line::Data(line)) => {
+                        let value = String::from_utf8(line)
+                            .map_err(|_| Error::Protocol("push option is not valid UTF-8".into()))?;
+                        let value = value.trim_end_matches('\n');
+                        let checked = self.check.push_option(value);
+                        self.limited(checked)?;
+                        self.options.add_push_option(value);
+                    }
+                    Some(Line::Flush) => return self.commands_received(),
+                    Some(Line::Delimiter) => {
+                        return Err(Error::Protocol("unexpected delimiter packet in push options".into()))
+                    }
+                    None => return self.need_input("push options"),
+                },
+                Phase::Pack => {
+                    if !self.input.is_empty() {
+                        return Ok(Event::PackData(std::mem::take(&mut self.input)));
+                    }
+                    if self.input_finished {
+                        self.phase = Phase::Report;
+                        return Ok(Event::PackEnd);
+                    }
+                    return Ok(Event::NeedInput);
+                }
+                Phase::Report => return Ok(Event::NeedReport),
+                Phase::Done => {
+                    if std::mem::take(&mut self.final_flush) {
+                        self.output.extend_from_slice(b"0000");
+                    }
+                    return Ok(Event::Done);
+                }
+            }
+        }
+    }
+
+    /// Signal that the pack data received so far forms the complete pack, and move on to the report.
+    pub fn end_pack(&mut self) -> Result<(), Error> {
+        self.expect_phase(Phase::Pack, "end the pack")?;
+        self.phase = Phase::Report;
+        Ok(())
+    }
+
+    /// Produce the `report` for the client if it [asked for one](Self::report_requested()), and complete the
+    /// conversation.
+    ///
+    /// With `side-band-64k`, the report is sent on the data band and the conversation ends with a flush once
+    /// [`poll()`](Self::poll) returns [`Event::Done`], which leaves room for [progress](Self::progress) in between,
+    /// like the output of `post-receive` hooks. The flush is sent even if no report was requested, as git does.
+    ///
+    /// Ref statuses are sent sorted by refname, so clients see the same report whatever order the updates were
+    /// applied in, see [`CommandList::application_order()`]. Refs stored under a rewritten name are reported under
+    /// the name the client pushed, and with `report-status-v2` the stored name is sent as `option refname` along with
+    /// the old and new object, unless the handler provided the [extended status](RefStatus::extended) itself.
+    pub fn report(&mut self, report: &Report) -> Result<(), Error> {
+        self.expect_phase(Phase::Report, "report")?;
+        self.phase = Phase::Done;
+        self.final_flush = self.side_band;
+        let Some(format) = self.report_format else {
+            return Ok(());
+        };
+
+        let mut refs: Vec<_> = report.refs.iter().map(|status| self.as_pushed(status)).collect();
+        refs.sort_by(|a, b| a.name.cmp(&b.name));
+        let report = Report {
+            unpack_error: report.unpack_error.clone(),
+            refs,
+        };
+        ReportWriter::new(format)
+            .with_side_band(self.side_band)
+            .write(&report, &mut self.output);
+        Ok(())
+    }
+
+    /// `status` under the name the client pushed, which is stored under another name if it was rewritten.
+    fn as_pushed(&self, status: &RefStatus) -> RefStatus {
+        let Some((stored, pushed)) = self.renamed.iter().find(|(stored, _)| *stored == status.name) else {
+            return status.clone();
+        };
+        let mut status = RefStatus {
+            name: pushed.clone(),
+            ..status.clone()
+        };
+        if status.extended.is_empty() {
+            let (old_oid, new_oid) = self
+                .commands
+                .iter()
+                .find(|command| command.name() == stored)
+                .map_or((None, None), CommandUpdate::old_and_new);
+            status.extended.push(ExtendedStatus {
+                refname: Some(stored.clone()),
+                old_oid,
+                new_oid,
+                forced_update: false,
+            });
+        }
+        status
+    }
+
+    /// Send `data` on the progress band if `side-band-64k` was negotiated, or drop it otherwise.
+    ///
+    /// Without a sideband, git leaves such output to its standard error, which doesn't reach the client
+    /// either. Progress sent after the final flush is dropped as well.
+    pub fn progress(&mut self, data: &[u8]) {
+        if !self.side_band || (self.phase == Phase::Done && !self.final_flush) {
+            return;
+        }
+        self.encode_band(gix_packetline_blocking::Channel::Progress, data);
+    }
+
+    /// Append `data` to the output as pkt-lines on `band`, split like git does to fit each line.
+    fn encode_band(&mut self, band: gix_packetline_blocking::Channel, data: &[u8]) {
+        encode_band(&mut self.output, band, data);
+    }
+
+    fn commands_received(&mut self) -> Result<Event, Error> {
+        self.phase = if self.expect_pack { Phase::Pack } else { Phase::Report };
+        self.renamed = self.rewrites.apply(&mut self.commands, self.principal.as_deref())?;
+        Ok(Event::Commands {
+            commands: self.commands.clone(),
+            options: Box::new(self.options.clone()),
+        })
+    }
+
+    /// Remember if `checked` failed as a head-info limit was exceeded.
+    fn limited(&mut self, checked: Result<(), Error>) -> Result<(), Error> {
+        self.limit_exceeded |= checked.is_err();
+        checked
+    }
+
+    fn need_input(&self, what: &str) -> Result<Event, Error> {
+        if self.input_finished {
+            return Err(Error::Protocol(format!("unexpected end of input in {what}")));
+        }
+        Ok(Event::NeedInput)
+    }
+
+    fn expect_phase(&self, expected: Phase, action: &str) -> Result<(), Error> {
+        if self.phase != expected {
+            return Err(Error::Protocol(format!(
+                "cannot {action} in phase {:?}, expected {expected:?}",
+                self.phase
+            )));
+        }
+        Ok(())
+    }
+
+    /// Decode the next complete pkt-line from the input, if there is one.
+    fn next_line(&mut self) -> Result<Option<Line>, Error> {
+        let (line, consumed) =
+            match decode::streaming(&self.input).map_err(|err| Error::Protocol(format!("invalid pkt-line: {err}")))? {
+                decode::Stream::Complete { line, bytes_consumed } => {
+                    let line = match line {
+                        PacketLineRef::Data(data) => Line::Data(data.to_vec()),
+                        PacketLineRef::Flush => Line::Flush,
+                        PacketLineRef::Delimiter | PacketLineRef::ResponseEnd => Line::Delimiter,
+                    };
+                    (line, bytes_consumed)
+                }
+                decode::Stream::Incomplete { .. } => return Ok(None),
+            };
+        self.input.drain(..consumed);
+        Ok(Some(line))
+    }
+}
+
+enum Line {
+    Data(Vec<u8>),
+    Flush,
+    Delimiter,
+}
+
+/// Append `data` as a single pkt-line to `out`.
+pub(crate) fn encode_data(out: &mut Vec<u8>, data: &[u8]) {
+    out.extend_from_slice(format!("{:04x}", data.len() + 4).as_bytes());
+    out.extend_from_slice(data);
+}
+
+/// Append `data` to `out` as pkt-lines on `band`, split like git does to fit each line.
+pub(crate) fn encode_band(out: &mut Vec<u8>, band: gix_packetline_blocking::Channel, data: &[u8]) {
+    for chunk in data.chunks(MAX_DATA_LEN - 1) {
+        let mut line = Vec::with_capacity(chunk.len() + 1);
+        line.push(band as u8);
+        line.extend_from_slice(chunk);
+        encode_data(out, &line);
+    }
+}
+
+/// Encode the v0/v1 advertisement of `refs` that aren't `hidden`, with the first line carrying `caps_line`.
+///
+/// For empty repositories, a special first line is emitted using a zero OID and the refname `capabilities^{}`.
+pub fn encode_advertisement(refs: &[RefRecord], caps_line: &str, hidden: Option<&HiddenRefPredicate>) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut visible = refs.iter().filter(|r| hidden.is_none_or(|pred| !(pred)(r)));
+    match visible.next() {
+        None => {
+            let zeros = "0".repeat(40); // SHA-1 default; object-format enforcement is added in M2.
+            encode_data(&mut out, format!("{zeros} capabilities^{{}}\0{caps_line}\n").as_bytes());
+        }
+        Some(first) => {
+            encode_data(
+                &mut out,
+                format!("{} {}\0{caps_line}\n", first.oid, first.name).as_bytes(),
+            );
+            for r in visible {
+                encode_data(&mut out, format!("{} {}\n", r.oid, r.name).as_bytes());
+            }
+        }
+    }
+    out.extend_from_slice(b"0000");
+    out
+}
+
+/// The work behind a receive-pack conversation, called by the drivers as the [`Machine`] progresses.
+pub trait Handler {
+    /// Called once head-info was received with the client's `commands` and `options`.
+    fn commands(&mut self, commands: &CommandList, options: &Options) -> Result<(), Error>;
+    /// Called with each chunk of pack `data`, returning `true` once the pack is complete.
+    fn pack_data(&mut self, data: &[u8]) -> Result<bool, Error>;
+    /// Called once the pack was received, or right after `commands()` if no pack was expected.
+    fn report(&mut self) -> Result<Report, Error>;
+    /// Called after the report was sent, returning output to send as progress before the final flush,
+    /// like that of `post-receive` hooks.
+    fn after_report(&mut self) -> Result<Vec<u8>, Error> {
+        Ok(Vec::new())
+    }
+}
+
+/// Blocking driver running a [`Machine`] over [`std::io`] types.
+#[cfg(feature = "blocking-io")]
+pub mod blocking {
+    use super::*;
+    use gix_serve_core::wire::{Counter, Direction, WireStats};
+    use std::io::{Read, Write};
+
+    /// Run the conversation of `machine` over `read` and `write`, delegating the work to `handler`.
+    ///
+    /// The advertisement must have been produced already, or `machine` must be [for a request](Machine::for_request).
+    /// Returns the bytes exchanged with the client.
+    pub fn drive(
+        machine: &mut Machine,
+        mut read: impl Read,
+        mut write: impl Write,
+        handler: &mut impl Handler,
+    ) -> Result<WireStats, Error> {
+        let mut buf = vec![0; 64 * 1024];
+        let mut request = Counter::new(Direction::Request);
+        let mut response = Counter::new(Direction::Response);
+        loop {
+            match machine.poll()? {
+                Event::NeedInput => {
+                    let output = machine.take_output();
+                    response.record(&output);
+                    write.write_all(&output)?;
+                    write.flush()?;
+                    match read.read(&mut buf)? {
+                        0 => machine.finish_input(),
+                        n => {
+                            request.record(&buf[..n]);
+                            machine.push_input(&buf[..n]);
+                        }
+                    }
+                }
+                Event::Commands { commands, options } => handler.commands(&commands, &options)?,
+                Event::PackData(data) => {
+                    if handler.pack_data(&data)? {
+                        machine.end_pack()?;
+                    }
+                }
+                Event::PackEnd => {}
+                Event::NeedReport => {
+                    let report = handler.report()?;
+                    machine.report(&report)?;
+                    let progress = handler.after_report()?;
+                    machine.progress(&progress);
+                }
+                Event::Done => {
+                    let output = machine.take_output();
+                    response.record(&output);
+                    write.write_all(&output)?;
+                    write.flush()?;
+                    return Ok(request.stats() + response.stats());
+                }
+            }
+        }
+    }
+}
+
+/// Async driver running a [`Machine`] over tokio IO types.
+#[cfg(feature = "async-io")]
+pub mod async_io {
+    use super::*;
+    use gix_serve_core::wire::{Counter, Direction, WireStats};
+    use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+    /// Run the conversation of `machine` over `read` and `write`, delegating the work to `handler`.
+    ///
+    /// The advertisement must have been produced already, or `machine` must be [for a request](Machine::for_request).
+    /// Returns the bytes exchanged with the client.
+    pub async fn drive(
+        machine: &mut Machine,
+        mut read: impl AsyncRead + Unpin,
+        mut write: impl AsyncWrite + Unpin,
+        handler: &mut impl Handler,
+    ) -> Result<WireStats, Error> {
+        let mut buf = vec![0; 64 * 1024];
+        let mut request = Counter::new(Direction::Request);
+        let mut response = Counter::new(Direction::Response);
+        loop {
+            match machine.poll()? {
+                Event::NeedInput => {
+                    let output = machine.take_output();
+                    response.record(&output);
+                    write.write_all(&output).await?;
+                    write.flush().await?;
+                    match read.read(&mut buf).await? {
+                        0 => machine.finish_input(),
+                        n => {
+                            request.record(&buf[..n]);
+                            machine.push_input(&buf[..n]);
+                        }
+                    }
+                }
+                Event::Commands { commands, options } => handler.commands(&commands, &options)?,
+                Event::PackData(data) => {
+                    if handler.pack_data(&data)? {
+                        machine.end_pack()?;
+                    }
+                }
+                Event::PackEnd => {}
+                Event::NeedReport => {
+                    let report = handler.report()?;
+                    machine.report(&report)?;
+                    let progress = handler.after_report()?;
+                    machine.progress(&progress);
+                }
+                Event::Done => {
+                    let output = machine.take_output();
+                    response.record(&output);
+                    write.write_all(&output).await?;
+                    write.flush().await?;
+                    return Ok(request.stats() + response.stats());
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ZERO: &str = "0000000000000000000000000000000000000000";
+    const A: &str = "1111111111111111111111111111111111111111";
+    const B: &str = "2222222222222222222222222222222222222222";
+
+    fn oid(hex: &str) -> gix_hash::ObjectId {
+        gix_hash::ObjectId::from_hex(hex.as_bytes()).expect("valid hex")
+    }
+
+    fn pkt(data: &str) -> Vec<u8> {
+        let mut out = Vec::new();
+        encode_data(&mut out, data.as_bytes());
+        out
+    }
+
+    fn request(lines: &[&str]) -> Vec<u8> {
+        let mut out: Vec<u8> = lines.iter().flat_map(|line| pkt(line)).collect();
+        out.extend_from_slice(b"0000");
+        out
+    }
+
+    fn advertised() -> Machine {
+        let mut caps = CapabilitySet::modern_defaults();
+        caps.side_band_64k = true;
+        caps.push_extra("push-options");
+        let mut machine = Machine::new();
+        machine
+            .advertise(&[RefRecord::new(oid(A), "refs/heads/main")], &caps, None)
+            .expect("advertise phase");
+        machine.take_output();
+        machine
+    }
+
+    /// Feed `input` in chunks of `chunk` bytes and collect all events until the report is due or the machine is done.
+    fn events(machine: &mut Machine, input: &[u8], chunk: usize) -> Vec<Event> {
+        let mut events = Vec::new();
+        let mut chunks = input.chunks(chunk);
+        loop {
+            match machine.poll().expect("valid input") {
+                Event::NeedInput => match chunks.next() {
+                    Some(chunk) => machine.push_input(chunk),
+                    None => machine.finish_input(),
+                },
+                event @ (Event::NeedReport | Event::Done) => {
+                    events.push(event);
+                    return events;
+                }
+                event => events.push(event),
+            }
+        }
+    }
+
+    #[test]
+    fn advertisement_encoding() {
+        let caps = CapabilitySet::modern_defaults();
+        let mut machine = Machine::new();
+        assert!(machine.poll().is_err(), "input is only read after advertising");
+        machine.advertise(&[], &caps, None).unwrap();
+        assert_eq!(machine.phase(), Phase::HeadInfo);
+        let mut expected = pkt(&format!(
+            "{ZERO} capabilities^{{}}\0report-status report-status-v2 quiet delete-refs ofs-delta\n"
+        ));
+        expected.extend_from_slice(b"0000");
+        assert_eq!(machine.take_output(), expected);
+        assert!(machine.advertise(&[], &caps, None).is_err(), "only once");
+
+        let refs = [
+            RefRecord::new(oid(A), "refs/heads/main"),
+            RefRecord::new(oid(B), "refs/hidden/x"),
+        ];
+        let hidden: &HiddenRefPredicate = &|r: &RefRecord| r.name.starts_with("refs/hidden/");
+        let out = encode_advertisement(&refs, "quiet", Some(hidden));
+        let mut expected = pkt(&format!("{A} refs/heads/main\0quiet\n"));
+        expected.extend_from_slice(b"0000");
+        assert_eq!(out, expected);
+    }
+
+    #[test]
+    fn create_with_pack_and_report() {
+        let mut input = request(&[&format!("{ZERO} {A} refs/heads/new\0report-status")]);
+        input.extend_from_slice(b"PACK-DATA");
+
+        for chunk in [1, 3, input.len()] {
+            let mut machine = advertised();
+            let events = events(&mut machine, &input, chunk);
+            let Event::Commands { commands, options } = &events[0] else {
+                panic!("commands come first: {events:?}")
+            };
+            assert_eq!(
+                commands.iter().next(),
+                Some(&CommandUpdate::Create {
+                    new: oid(A),
+                    name: "refs/heads/new".into()
+                })
+            );
+            assert!(options.has("report-status"));
+            let pack: Vec<u8> = events
+                .iter()
+                .filter_map(|event| match event {
+                    Event::PackData(data) => Some(data.clone()),
+                    _ => None,
+                })
+                .flatten()
+                .collect();
+            assert_eq!(pack, b"PACK-DATA", "chunk size {chunk}");
+            assert_eq!(&events[events.len() - 2..], &[Event::PackEnd, Event::NeedReport]);
+
+            machine
+                .report(&Report {
+                    unpack_error: None,
+                    refs: vec![RefStatus::ok("refs/heads/new")],
+                })
+                .unwrap();
+            assert_eq!(machine.poll().unwrap(), Event::Done);
+            let mut expected = pkt("unpack ok\n");
+            expected.extend(pkt("ok refs/heads/new\n"));
+            expected.extend_from_slice(b"0000");
+            assert_eq!(machine.take_output(), expected);
+        }
+    }
+
+    #[test]
+    fn handler_can_end_the_pack() {
+        let mut machine = advertised();
+        machine.push_input(&request(&[&format!("{A} {B} refs/heads/main\0report-status")]));
+        assert!(matches!(machine.poll().unwrap(), Event::Commands { .. }));
+        assert_eq!(machine.poll().unwrap(), Event::NeedInput);
+        machine.push_input(b"PACK");
+        assert_eq!(machine.poll().unwrap(), Event::PackData(b"PACK".to_vec()));
+        machine.end_pack().unwrap();
+        assert_eq!(machine.phase(), Phase::Report);
+        assert!(machine.end_pack().is_err(), "the pack can only end once");
+    }
+
+    #[test]
+    fn delete_only_skips_pack() {
+        let mut machine = advertised();
+        let input = request(&[&format!("{A} {ZERO} refs/heads/main\0report-status side-band-64k")]);
+        let events = events(&mut machine, &input, input.len());
+        assert!(matches!(events[0], Event::Commands { .. }));
+        assert_eq!(events[1], Event::NeedReport);
+
+        machine
+            .report(&Report {
+                unpack_error: None,
+                refs: vec![RefStatus::rejected("refs/heads/main", "deletion prohibited")],
+            })
+            .unwrap();
+        let mut status = pkt("unpack ok\n");
+        status.extend(pkt("ng refs/heads/main deletion prohibited\n"));
+        status.extend_from_slice(b"0000");
+        let mut band = vec![1];
+        band.extend(status);
+        let mut expected = Vec::new();
+        encode_data(&mut expected, &band);
+        assert_eq!(machine.take_output(), expected, "the report is sent on band 1");
+        assert_eq!(machine.poll().unwrap(), Event::Done);
+        assert_eq!(machine.take_output(), b"0000", "the final flush follows once done");
+    }
+
+    /// Run a push deleting `refs/heads/main` and `refs/heads/other` with `caps`, where the update of the latter
+    /// is declined, sending progress like the `update` and `post-receive` hooks of git would.
+    fn declined_push(caps: &str) -> Vec<u8> {
+        let mut machine = advertised();
+        let input = request(&[
+            &format!("{A} {ZERO} refs/heads/main\0{caps}"),
+            &format!("{A} {ZERO} refs/heads/other"),
+        ]);
+        assert_eq!(events(&mut machine, &input, 9).last(), Some(&Event::NeedReport));
+        machine.progress(b"checking refs/heads/main\n");
+        machine.progress(b"checking refs/heads/other\n");
+        machine.progress(b"error: hook declined to update refs/heads/other\n");
+        machine
+            .report(&Report {
+                unpack_error: None,
+                refs: vec![
+                    RefStatus::ok("refs/heads/main"),
+                    RefStatus::rejected("refs/heads/other", "hook declined"),
+                ],
+            })
+            .unwrap();
+        machine.progress(b"post-receive ran\n");
+        assert_eq!(machine.poll().unwrap(), Event::Done);
+        machine.progress(b"too late\n");
+        machine.take_output()
+    }
+
+    #[test]
+    fn report_traffic_matches_git() {
+        // Everything git 2.39 sent after the advertisement for the same push.
+        assert_eq!(
+            declined_push("report-status side-band-64k"),
+            b"001e\x02checking refs/heads/main\n001f\x02checking refs/heads/other\n\
+              0035\x02error: hook declined to update refs/heads/other\n\
+              0054\x01000eunpack ok\n0017ok refs/heads/main\n0026ng refs/heads/other hook declined\n0000\
+              0016\x02post-receive ran\n0000",
+            "status on band 1, progress on band 2 and a final flush"
+        );
+        assert_eq!(
+            declined_push("report-status"),
+            b"000eunpack ok\n0017ok refs/heads/main\n0026ng refs/heads/other hook declined\n0000",
+            "without sideband, only the report is sent"
+        );
+        assert_eq!(
+            declined_push("side-band-64k"),
+            b"001e\x02checking refs/heads/main\n001f\x02checking refs/heads/other\n\
+              0035\x02error: hook declined to update refs/heads/other\n0016\x02post-receive ran\n0000",
+            "without report, the final flush is still sent"
+        );
+    }
+
+    #[test]
+    fn ref_statuses_are_sorted_by_refname() {
+        let mut machine = advertised();
+        let input = request(&[
+            &format!("{A} {ZERO} refs/heads/main\0report-status"),
+            &format!("{A} {ZERO} refs/heads/feature"),
+        ]);
+        events(&mut machine, &input, input.len());
+        machine
+            .report(&Report {
+                unpack_error: None,
+                refs: vec![
+                    RefStatus::rejected("refs/heads/main", "hook declined"),
+                    RefStatus::ok("refs/heads/feature"),
+                ],
+            })
+            .unwrap();
+        let mut expected = pkt("unpack ok\n");
+        expected.extend(pkt("ok refs/heads/feature\n"));
+        expected.extend(pkt("ng refs/heads/main hook declined\n"));
+        expected.extend_from_slice(b"0000");
+        assert_eq!(machine.take_output(), expected);
+    }
+
+    #[test]
+    fn rewritten_refs_are_reported_under_their_pushed_names() {
+        use crate::protocol::{RefRewrite, RefRewrites};
+        let rewrites = RefRewrites::new().with_rule(
+            RefRewrite::new("refs/heads/*", "refs/bots/{principal}/*")
+                .unwrap()
+                .for_principal("ci-bot"),
+        );
+        let input = request(&[
+            &format!("{A} {ZERO} refs/heads/main\0report-status"),
+            &format!("{A} {ZERO} refs/tags/v1.0"),
+        ]);
+        let mut machine = advertised().with_ref_rewrites(rewrites.clone(), Some("ci-bot".into()));
+        let received = events(&mut machine, &input, input.len());
+        let Event::Commands { commands, .. } = &received[0] else {
+            panic!("commands come first: {received:?}")
+        };
+        let names: Vec<_> = commands.iter().map(|cmd| cmd.name()).collect();
+        assert_eq!(
+            names,
+            ["refs/bots/ci-bot/main", "refs/tags/v1.0"],
+            "the handler sees the stored names"
+        );
+        machine
+            .report(&Report {
+                unpack_error: None,
+                refs: vec![RefStatus::ok("refs/bots/ci-bot/main"), RefStatus::ok("refs/tags/v1.0")],
+            })
+            .unwrap();
+        let mut expected = pkt("unpack ok\n");
+        expected.extend(pkt("ok refs/heads/main\n"));
+        expected.extend(pkt("ok refs/tags/v1.0\n"));
+        expected.extend_from_slice(b"0000");
+        assert_eq!(machine.take_output(), expected, "the client sees the names it pushed");
+
+        let mut machine = advertised().with_ref_rewrites(rewrites.clone(), Some("alice".into()));
+        let received = events(&mut machine, &input, input.len());
+        let Event::Commands { commands, .. } = &received[0] else {
+            panic!("commands come first: {received:?}")
+        };
+        assert_eq!(
+            commands.iter().next().unwrap().name(),
+            "refs/heads/main",
+            "rules scoped to other principals don't apply"
+        );
+
+        let input = request(&[
+            &format!("{A} {ZERO} refs/heads/main\0report-status"),
+            &format!("{A} {ZERO} refs/bots/ci-bot/main"),
+        ]);
+        let mut machine = advertised().with_ref_rewrites(rewrites, Some("ci-bot".into()));
+        machine.push_input(&input);
+        let err = machine.poll().unwrap_err().to_string();
+        assert!(err.contains("refs/bots/ci-bot/main"), "{err}");
+    }
+
+    #[test]
+    fn rewritten_refs_have_their_stored_names_reported_with_report_status_v2() {
+        use crate::protocol::{RefRewrite, RefRewrites};
+        let rewrites =
+            RefRewrites::new().with_rule(RefRewrite::new("refs/heads/*", "refs/bots/{principal}/*").unwrap());
+        let input = request(&[
+            &format!("{A} {B} refs/heads/main\0report-status report-status-v2"),
+            &format!("{A} {ZERO} refs/tags/v1.0"),
+        ]);
+        let mut machine = advertised().with_ref_rewrites(rewrites, Some("ci-bot".into()));
+        events(&mut machine, &input, input.len());
+        machine
+            .report(&Report {
+                unpack_error: None,
+                refs: vec![RefStatus::ok("refs/bots/ci-bot/main"), RefStatus::ok("refs/tags/v1.0")],
+            })
+            .unwrap();
+        let mut expected = pkt("unpack ok\n");
+        expected.extend(pkt("ok refs/heads/main\n"));
+        expected.extend(pkt("option refname refs/bots/ci-bot/main\n"));
+        expected.extend(pkt(&format!("option old-oid {A}\n")));
+        expected.extend(pkt(&format!("option new-oid {B}\n")));
+        expected.extend(pkt("ok refs/tags/v1.0\n"));
+        expected.extend_from_slice(b"0000");
+        assert_eq!(machine.take_output(), expected);
+    }
+
+    #[test]
+    fn large_reports_are_split_across_band_lines() {
+        let mut machine = advertised();
+        let input = request(&[&format!("{A} {ZERO} refs/heads/main\0report-status side-band-64k")]);
+        events(&mut machine, &input, input.len());
+        let name = format!("refs/heads/{}", "x".repeat(40_000));
+        machine
+            .report(&Report {
+                unpack_error: None,
+                refs: vec![RefStatus::ok(&name), RefStatus::ok(&name)],
+            })
+            .unwrap();
+        assert_eq!(machine.poll().unwrap(), Event::Done);
+
+        let out = machine.take_output();
+        let mut status = Vec::new();
+        let mut rest = &out[..];
+        while rest != b"0000" {
+            let len = usize::from_str_radix(std::str::from_utf8(&rest[..4]).unwrap(), 16).unwrap();
+            assert!(len <= MAX_DATA_LEN + 4);
+            assert_eq!(rest[4], 1, "every line is on band 1");
+            status.extend_from_slice(&rest[5..len]);
+            rest = &rest[len..];
+        }
+        let mut expected = pkt("unpack ok\n");
+        expected.extend(pkt(&format!("ok {name}\n")));
+        expected.extend(pkt(&format!("ok {name}\n")));
+        expected.extend_from_slice(b"0000");
+        assert_eq!(status, expected);
+    }
+
+    #[test]
+    fn report_is_omitted_without_report_status() {
+        let mut machine = advertised();
+        let input = request(&[&format!("{A} {ZERO} refs/heads/main")]);
+        events(&mut machine, &input, input.len());
+        machine
+            .report(&Report {
+                unpack_error: None,
+                refs: vec![RefStatus::ok("refs/heads/main")],
+            })
+            .unwrap();
+        assert!(machine.take_output().is_empty());
+        assert_eq!(machine.phase(), Phase::Done);
+    }
+
+    #[cfg(feature = "blocking-io")]
+    #[test]
+    fn report_and_final_flush_depend_on_negotiated_capabilities() {
+        struct Accept;
+        impl Handler for Accept {
+            fn commands(&mut self, _commands: &CommandList, _options: &Options) -> Result<(), Error> {
+                Ok(())
+            }
+            fn pack_data(&mut self, _data: &[u8]) -> Result<bool, Error> {
+                Ok(false)
+            }
+            fn report(&mut self) -> Result<Report, Error> {
+                Ok(Report {
+                    unpack_error: None,
+                    refs: vec![RefStatus::ok("refs/heads/main")],
+                })
+            }
+        }
+
+        /// Everything sent after the advertisement for a push of `command` with `caps`.
+        fn traffic(machine: &mut Machine, command: &str, caps: &str) -> Vec<u8> {
+            let mut input = request(&[&format!("{command} refs/heads/main\0{caps}")]);
+            if !command.ends_with(ZERO) {
+                input.extend_from_slice(b"PACK");
+            }
+            let mut out = Vec::new();
+            blocking::drive(machine, &input[..], &mut out, &mut Accept).unwrap();
+            out
+        }
+
+        let status = b"000eunpack ok\n0017ok refs/heads/main\n0000";
+        let mut status_on_band = pkt("\u{1}000eunpack ok\n0017ok refs/heads/main\n0000");
+        status_on_band.extend_from_slice(b"0000");
+        for command in [format!("{A} {ZERO}"), format!("{ZERO} {A}"), format!("{A} {B}")] {
+            for (caps, expected) in [
+                ("", &b""[..]),
+                ("side-band-64k", &b"0000"[..]),
+                ("report-status", &status[..]),
+                ("report-status-v2", &status[..]),
+                ("report-status side-band-64k", &status_on_band[..]),
+                ("report-status report-status-v2 side-band-64k", &status_on_band[..]),
+            ] {
+                let mut machine = advertised();
+                assert_eq!(
+                    String::from_utf8_lossy(&traffic(&mut machine, &command, caps)),
+                    String::from_utf8_lossy(expected),
+                    "{command} with '{caps}'"
+                );
+                assert_eq!(machine.report_requested(), caps.contains("report-status"));
+            }
+        }
+
+        let mut caps = CapabilitySet::modern_defaults();
+        caps.report_status = false;
+        caps.report_status_v2 = false;
+        caps.side_band_64k = true;
+        let mut machine = Machine::for_request(caps).with_capability_strictness(CapabilityStrictness::WarnAndIgnore);
+        let mut expected = pkt("\u{2}warning: ignoring unknown capability 'report-status'\n");
+        expected.extend_from_slice(b"0000");
+        assert_eq!(
+            String::from_utf8_lossy(&traffic(
+                &mut machine,
+                &format!("{A} {ZERO}"),
+                "report-status side-band-64k"
+            )),
+            String::from_utf8_lossy(&expected),
+            "reports that weren't advertised aren't sent"
+        );
+        assert!(!machine.report_requested());
+    }
+
+    #[test]
+    fn empty_head_info_is_done() {
+        let mut machine = advertised();
+        assert_eq!(events(&mut machine, b"0000", 4), vec![Event::Done]);
+        assert!(machine.take_output().is_empty());
+
+        let mut machine = advertised();
+        assert_eq!(
+            events(&mut machine, b"", 1),
+            vec![Event::Done],
+            "disconnect after advertisement"
+        );
+    }
+
+    #[test]
+    fn push_options_follow_head_info() {
+        let mut machine = advertised();
+        let mut input = request(&[&format!("{A} {ZERO} refs/heads/main\0report-status push-options")]);
+        input.extend(request(&["ci.skip", "reviewer=a"]));
+        let events = events(&mut machine, &input, 5);
+        let Event::Commands { options, .. } = &events[0] else {
+            panic!("commands come first: {events:?}")
+        };
+        assert_eq!(options.push_options, vec!["ci.skip".to_string(), "reviewer=a".into()]);
+        assert_eq!(events[1], Event::NeedReport);
+    }
+
+    #[test]
+    fn signed_pushes_take_their_commands_from_the_certificate() {
+        let mut machine = advertised();
+        let command = format!("{A} {ZERO} refs/heads/main");
+        let certificate = [
+            "certificate version 0.1",
+            "pusher A U Thor",
+            "nonce 1-abc",
+            "",
+            &command,
+        ];
+        let mut lines = vec!["push-cert\0report-status".to_owned()];
+        lines.extend(certificate.iter().map(|line| format!("{line}\n")));
+        lines.push("push-cert-end\n".into());
+        let lines: Vec<_> = lines.iter().map(String::as_str).collect();
+        let events = events(&mut machine, &request(&lines), 7);
+        let Event::Commands { commands, options } = &events[0] else {
+            panic!("commands come first: {events:?}")
+        };
+        assert_eq!(
+            commands.iter().map(CommandUpdate::name).collect::<Vec<_>>(),
+            ["refs/heads/main"]
+        );
+        assert!(options.has("report-status"));
+        let cert = options.push_cert.as_ref().expect("certificate is passed on");
+        assert_eq!(cert.nonce.as_deref(), Some("1-abc"));
+        assert_eq!(cert.text, format!("{}\n", certificate.join("\n")));
+    }
+
+    #[test]
+    fn head_info_limits_are_checked_as_lines_arrive() {
+        let limits = HeadInfoLimits::default().with_max_commands(2);
+        let mut machine = advertised().with_head_info_limits(limits);
+        for name in ["a", "b", "c"] {
+            machine.push_input(&pkt(&format!("{A} {ZERO} refs/heads/{name}")));
+        }
+        let err = machine.poll().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "validation error: command 3 exceeds the limit of 2 commands per push"
+        );
+        assert!(
+            machine.head_info_limit_exceeded(),
+            "the flush wasn't needed to detect it"
+        );
+
+        let limits = HeadInfoLimits::default().with_max_push_options(2);
+        let mut machine = advertised().with_head_info_limits(limits);
+        let mut input = request(&[&format!("{A} {ZERO} refs/heads/main\0report-status push-options")]);
+        input.extend(request(&["ci.skip", "reviewer=a", "reviewer=b"]));
+        machine.push_input(&input);
+        let err = machine.poll().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "validation error: push option 3 exceeds the limit of 2 push options per push"
+        );
+        assert!(machine.head_info_limit_exceeded());
+
+        let mut machine = advertised().with_capability_strictness(CapabilityStrictness::Strict);
+        machine.push_input(&request(&[&format!(
+            "{A} {ZERO} refs/heads/main\0report-status future-cap"
+        )]));
+        assert!(machine.poll().is_err());
+        assert!(!machine.head_info_limit_exceeded(), "only limits count");
+    }
+
+    #[test]
+    fn unknown_capabilities_can_be_ignored_with_a_warning() {
+        let mut machine = advertised().with_capability_strictness(CapabilityStrictness::WarnAndIgnore);
+        let input = request(&[&format!(
+            "{A} {ZERO} refs/heads/main\0report-status side-band-64k future-cap"
+        )]);
+        let events = events(&mut machine, &input, 7);
+        let Event::Commands { options, .. } = &events[0] else {
+            panic!("commands come first: {events:?}")
+        };
+        assert_eq!(options.negotiated, vec!["report-status", "side-band-64k"]);
+        assert_eq!(options.ignored, vec!["future-cap"]);
+        assert_eq!(
+            machine.take_output(),
+            pkt("\u{2}warning: ignoring unknown capability 'future-cap'\n"),
+            "the warning is sent on the progress band"
+        );
+    }
+
+    #[test]
+    fn invalid_input_is_rejected() {
+        let mut machine = advertised();
+        machine.push_input(&request(&[&format!("{ZERO} {A} refs/heads/new\0no-such-cap")]));
+        assert!(machine.poll().is_err(), "capabilities must have been advertised");
+
+        let mut machine = advertised();
+        machine.push_input(b"zzzz");
+        assert!(machine.poll().is_err(), "malformed pkt-line");
+
+        let mut machine = advertised();
+        machine.push_input(&pkt(&format!("{ZERO} {A} refs/heads/new")));
+        assert_eq!(machine.poll().unwrap(), Event::NeedInput);
+        machine.finish_input();
+        assert!(machine.poll().is_err(), "truncated head-info");
+
+        let mut machine = advertised();
+        assert!(machine
+            .report(&Report {
+                unpack_error: None,
+                refs: Vec::new()
+            })
+            .is_err());
+    }
+
+    #[test]
+    fn stateless_request_starts_at_head_info() {
+        let mut machine = Machine::for_request(CapabilitySet::modern_defaults());
+        assert_eq!(machine.phase(), Phase::HeadInfo);
+        let input = request(&[&format!("{A} {ZERO} refs/heads/main\0report-status")]);
+        let events = events(&mut machine, &input, 2);
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[1], Event::NeedReport);
+    }
+
+    #[cfg(feature = "blocking-io")]
+    #[test]
+    fn blocking_driver() {
+        #[derive(Default)]
+        struct Collect {
+            pack: Vec<u8>,
+            names: Vec<String>,
+        }
+        impl Handler for Collect {
+            fn commands(&mut self, commands: &CommandList, _options: &Options) -> Result<(), Error> {
+                self.names = commands.iter().map(|cmd| cmd.name().to_owned()).collect();
+                Ok(())
+            }
+            fn pack_data(&mut self, data: &[u8]) -> Result<bool, Error> {
+                self.pack.extend_from_slice(data);
+                Ok(false)
+            }
+            fn report(&mut self) -> Result<Report, Error> {
+                Ok(Report {
+                    unpack_error: None,
+                    refs: self.names.iter().map(RefStatus::ok).collect(),
+                })
+            }
+        }
+
+        let mut machine = advertised();
+        let mut input = request(&[&format!("{ZERO} {A} refs/heads/new\0report-status")]);
+        input.extend_from_slice(b"PACK");
+        let mut out = Vec::new();
+        let mut handler = Collect::default();
+        let wire = blocking::drive(&mut machine, &input[..], &mut out, &mut handler).unwrap();
+        assert_eq!(handler.pack, b"PACK");
+        let mut expected = pkt("unpack ok\n");
+        expected.extend(pkt("ok refs/heads/new\n"));
+        expected.extend_from_slice(b"0000");
+        assert_eq!(out, expected);
+        assert_eq!(wire.request_bytes, input.len() as u64);
+        assert_eq!(wire.response_bytes, out.len() as u64);
+        assert_eq!(wire.pack_bytes, 4);
+        assert_eq!(wire.framing_bytes, 2 * 4 + 3 * 4);
+    }
+}
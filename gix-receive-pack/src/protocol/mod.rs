@@ -2,9 +2,22 @@
 pub mod capabilities;
 pub mod advertise;
 pub mod config_integration;
+pub mod truncation;
 // M2: Options and commands parsing (blocking-first).
 pub mod options;
 pub mod commands;
+pub mod limits;
+// M8: Sans-IO protocol core with blocking and async drivers.
+pub mod machine;
+// M8: report-status and report-status-v2 reports.
+pub mod report;
+// M8: Push certificates of signed pushes.
+pub mod push_cert;
+// M9: The gix-serve-core service interface.
+#[cfg(all(feature = "serve-core", feature = "blocking-io"))]
+pub mod service;
+// M9: Rewriting pushed refnames to the names they are stored under.
+pub mod rewrite;
 
 use gix_hash::ObjectId;
 
@@ -28,5 +41,11 @@ pub type HiddenRefPredicate = dyn Fn(&RefRecord) -> bool + Send + Sync;
 pub use capabilities::{CapabilityOrdering, CapabilitySet};
 pub use advertise::Advertiser;
 pub use config_integration::{AdvertisementConfig, setup_advertiser_with_config};
-pub use options::Options;
-pub use commands::{CommandList, CommandUpdate};
\ No newline at end of file
+pub use truncation::AdvertisementLimits;
+pub use options::{CapabilityStrictness, Options};
+pub use commands::{CommandList, CommandUpdate};
+pub use limits::HeadInfoLimits;
+pub use machine::{Event, Handler, Machine, Phase, RefStatus, Report};
+pub use push_cert::{NonceCheck, NonceStatus, PushCertConfig, PushCertRecord, PushCertificate};
+pub use report::{ExtendedStatus, ReportFormat, ReportWriter};
+pub use rewrite::{RefRewrite, RefRewrites};
\ No newline at end of file
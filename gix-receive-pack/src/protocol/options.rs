@@ -8,6 +8,17 @@ use gix_hash::ObjectId;
 use crate::Error;
 use crate::protocol::capabilities::{CapabilityOrdering, CapabilitySet};
 
+/// How capabilities that weren't advertised are treated when validating negotiated options.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CapabilityStrictness {
+    /// Reject the request with a validation error.
+    #[default]
+    Strict,
+    /// Drop the token from the negotiated set and record a warning, so that forward-compatible clients
+    /// sending capabilities we don't know yet can still push.
+    WarnAndIgnore,
+}
+
 /// Parsed options negotiated during head-info parsing.
 ///
 /// Contains a subset of tokens negotiated by the client (capabilities),
@@ -24,6 +35,10 @@ pub struct Options {
     pub shallow: Vec<ObjectId>,
     /// OIDs from `unshallow <oid>` lines.
     pub unshallow: Vec<ObjectId>,
+    /// Tokens that weren't advertised and were removed from `negotiated` in [`CapabilityStrictness::WarnAndIgnore`] mode.
+    pub ignored: Vec<String>,
+    /// The certificate of a signed push, whose commands are the ones of the push.
+    pub push_cert: Option<crate::protocol::push_cert::PushCertificate>,
 }
 
 impl Options {
@@ -65,33 +80,68 @@ impl Options {
     /// - Agent is validated only syntactically here (no spaces). More rules can be added later.
     pub fn validate_against(&self, advertised: &CapabilitySet) -> Result<(), Error> {
         let advertised_tokens = Self::advertised_token_set(advertised);
+        self.negotiated
+            .iter()
+            .try_for_each(|tok| Self::validate_token(tok, &advertised_tokens))
+    }
 
-        for tok in &self.negotiated {
-            // 'agent=' is special: if we didn't advertise agent at all, it's not allowed.
-            if tok.starts_with("agent=") {
-                // only allowed if our advertised set contains "agent=" prefix (i.e., agent was present)
-                if !advertised_tokens.iter().any(|t| t == "agent" || t.starts_with("agent=")) {
-                    return Err(Error::Validation(format!("capability '{}' not advertised (agent disabled)", tok)));
-                }
-                // basic syntax check: no spaces
-                if tok.split_once('=').map_or(true, |(_, v)| v.contains(' ')) {
-                    return Err(Error::Validation(format!("invalid agent token '{}': must not contain spaces", tok)));
-                }
-                continue;
+    /// Validate negotiated capability tokens against the set we advertised, treating tokens that weren't
+    /// advertised according to `strictness`.
+    ///
+    /// With [`CapabilityStrictness::WarnAndIgnore`], such tokens are moved from `negotiated` to `ignored`
+    /// instead of failing, and [`warnings()`](Self::warnings()) describes them for the client.
+    pub fn validate_with(&mut self, advertised: &CapabilitySet, strictness: CapabilityStrictness) -> Result<(), Error> {
+        match strictness {
+            CapabilityStrictness::Strict => self.validate_against(advertised),
+            CapabilityStrictness::WarnAndIgnore => {
+                let advertised_tokens = Self::advertised_token_set(advertised);
+                let mut ignored = Vec::new();
+                self.negotiated
+                    .retain(|tok| match Self::validate_token(tok, &advertised_tokens) {
+                        Ok(()) => true,
+                        Err(_) => {
+                            ignored.push(tok.clone());
+                            false
+                        }
+                    });
+                self.ignored.extend(ignored);
+                Ok(())
             }
+        }
+    }
 
-            // Standard tokens or key=value pairs must appear in the advertised token set.
-            // We check both exact matches and key matches for key=value forms.
-            if let Some((key, _value)) = tok.split_once('=') {
-                if !advertised_tokens.contains(key) && !advertised_tokens.contains(tok) {
-                    return Err(Error::Validation(format!(
-                        "capability '{}' not advertised (neither '{}' nor exact match allowed)",
-                        tok, key
-                    )));
-                }
-            } else if !advertised_tokens.contains(tok) {
-                return Err(Error::Validation(format!("capability '{}' not advertised", tok)));
+    /// Human-readable warnings about ignored capabilities, one per token, suitable for sending to the client.
+    pub fn warnings(&self) -> impl Iterator<Item = String> + '_ {
+        self.ignored
+            .iter()
+            .map(|tok| format!("warning: ignoring unknown capability '{}'", tok))
+    }
+
+    fn validate_token(tok: &str, advertised_tokens: &std::collections::HashSet<String>) -> Result<(), Error> {
+        // 'agent=' is special: if we didn't advertise agent at all, it's not allowed.
+        if tok.starts_with("agent=") {
+            // only allowed if our advertised set contains "agent=" prefix (i.e., agent was present)
+            if !advertised_tokens.iter().any(|t| t == "agent" || t.starts_with("agent=")) {
+                return Err(Error::Validation(format!("capability '{}' not advertised (agent disabled)", tok)));
+            }
+            // basic syntax check: no spaces
+            if tok.split_once('=').is_none_or(|(_, v)| v.contains(' ')) {
+                return Err(Error::Validation(format!("invalid agent token '{}': must not contain spaces", tok)));
             }
+            return Ok(());
+        }
+
+        // Standard tokens or key=value pairs must appear in the advertised token set.
+        // We check both exact matches and key matches for key=value forms.
+        if let Some((key, _value)) = tok.split_once('=') {
+            if !advertised_tokens.contains(key) && !advertised_tokens.contains(tok) {
+                return Err(Error::Validation(format!(
+                    "capability '{}' not advertised (neither '{}' nor exact match allowed)",
+                    tok, key
+                )));
+            }
+        } else if !advertised_tokens.contains(tok) {
+            return Err(Error::Validation(format!("capability '{}' not advertised", tok)));
         }
         Ok(())
     }
@@ -162,6 +212,26 @@ mod tests {
         assert!(format!("{err}").contains("agent"));
     }
 
+    #[test]
+    fn warn_and_ignore_drops_unadvertised() {
+        let adv = CapabilitySet::modern_defaults(); // no agent
+        let mut opts = Options::parse("report-status future-cap=1 agent=gix/1.0 quiet");
+        opts.validate_with(&adv, CapabilityStrictness::WarnAndIgnore).unwrap();
+        assert_eq!(opts.negotiated, vec!["report-status", "quiet"]);
+        assert_eq!(opts.ignored, vec!["future-cap=1", "agent=gix/1.0"]);
+        assert_eq!(
+            opts.warnings().collect::<Vec<_>>(),
+            vec![
+                "warning: ignoring unknown capability 'future-cap=1'",
+                "warning: ignoring unknown capability 'agent=gix/1.0'"
+            ]
+        );
+
+        let mut strict = Options::parse("report-status future-cap=1");
+        assert!(strict.validate_with(&adv, CapabilityStrictness::Strict).is_err());
+        assert!(strict.ignored.is_empty());
+    }
+
     #[test]
     fn add_push_option_and_shallow() {
         let mut opts = Options::default();
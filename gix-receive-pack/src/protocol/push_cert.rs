@@ -0,0 +1,487 @@
+// M8: Push certificates of signed pushes.
+//
+// With `receive.certNonceSeed` set, the advertisement carries `push-cert=<nonce>`, and `git push --signed` sends a
+// certificate instead of plain commands: a `push-cert\0<capabilities>` line, the certificate one line at a time and
+// `push-cert-end`, all before the flush ending head-info.
+//
+//   certificate version 0.1
+//   pusher <ident>
+//   pushee <url>
+//   nonce <nonce>
+//   push-option <option>
+//
+//   <old-oid> <new-oid> <refname>
+//   -----BEGIN PGP SIGNATURE-----
+//   ...
+//   -----END PGP SIGNATURE-----
+//
+// The commands of the push are the ones in the certificate. The nonce is `<stamp>-<hmac>`, an HMAC-SHA1 of the
+// repository path and the time it was issued, so the server can tell if a certificate was made for this push, or in
+// stateless mode at least for this repository and how long ago. Signatures aren't verified here, hooks can do that
+// with the certificate stored as a blob.
+
+use crate::config::keys;
+use crate::Error;
+use gix_hash::ObjectId;
+
+/// The line starting a certificate in head-info, followed by the capabilities after a NUL.
+pub(crate) const BEGIN: &str = "push-cert";
+/// The line ending a certificate in head-info.
+pub(crate) const END: &str = "push-cert-end";
+
+/// How nonces for push certificates are issued and checked.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PushCertConfig {
+    /// The secret nonces are derived from, `receive.certNonceSeed`.
+    seed: String,
+    /// Seconds a nonce may be off in stateless mode and still be accepted, `receive.certNonceSlop`.
+    slop: Option<u64>,
+}
+
+impl PushCertConfig {
+    /// Issue nonces derived from `seed`, accepting only the nonce issued for the current connection.
+    pub fn new(seed: impl Into<String>) -> Self {
+        Self {
+            seed: seed.into(),
+            slop: None,
+        }
+    }
+
+    /// Load the configuration from `receive.certNonceSeed` and `receive.certNonceSlop`, or `None` if no seed is set
+    /// and signed pushes aren't offered.
+    pub fn from_config(config: &gix_config::File<'static>) -> Result<Option<Self>, Error> {
+        let Some(seed) = keys::receive::CERT_NONCE_SEED.get(config)? else {
+            return Ok(None);
+        };
+        let slop = match keys::receive::CERT_NONCE_SLOP.get(config)? {
+            None | Some(0) => None,
+            Some(slop) => Some(u64::try_from(slop).map_err(|_| {
+                Error::Validation(format!(
+                    "invalid value for '{}': must not be negative",
+                    keys::receive::CERT_NONCE_SLOP.name()
+                ))
+            })?),
+        };
+        Ok(Some(Self::new(seed.to_string()).with_slop(slop)))
+    }
+
+    /// Accept nonces issued up to `seconds` apart from the one issued for a request in stateless mode.
+    pub fn with_slop(mut self, seconds: impl Into<Option<u64>>) -> Self {
+        self.slop = seconds.into();
+        self
+    }
+
+    /// The nonce for the repository at `path`, as given to receive-pack, issued at `stamp` seconds since the epoch.
+    ///
+    /// It's the same nonce `git receive-pack` issues with the same seed.
+    pub fn nonce(&self, path: &str, stamp: u64) -> String {
+        // Git passes the path and stamp as the key and the seed as the message.
+        let hmac = hmac_sha1(format!("{path}:{stamp}").as_bytes(), self.seed.as_bytes());
+        format!("{stamp}-{hmac}")
+    }
+
+    /// Check the nonce `received` in a certificate against the nonce `issued` for the repository at `path`.
+    ///
+    /// Only the nonce that was issued is accepted, unless the request is `stateless` and its nonce was issued in
+    /// an earlier request. Nonces issued for the same repository are then accepted if they are at most
+    /// [the slop](Self::with_slop()) apart, and reported as [`NonceStatus::Slop`] otherwise.
+    pub fn check_nonce(&self, issued: &str, received: Option<&str>, path: &str, stateless: bool) -> NonceCheck {
+        let check = |status, slop| NonceCheck {
+            nonce: Some(issued.to_owned()),
+            status,
+            slop,
+        };
+        let Some(received) = received else {
+            return check(NonceStatus::Missing, None);
+        };
+        if received == issued {
+            return check(NonceStatus::Ok, None);
+        }
+        if !stateless {
+            return check(NonceStatus::Bad, None);
+        }
+        let Some(stamp) = received
+            .split_once('-')
+            .and_then(|(stamp, _)| stamp.parse::<u64>().ok())
+        else {
+            return check(NonceStatus::Bad, None);
+        };
+        if self.nonce(path, stamp) != received {
+            return check(NonceStatus::Bad, None);
+        }
+        let issued_stamp = issued
+            .split_once('-')
+            .and_then(|(stamp, _)| stamp.parse::<i64>().ok())
+            .unwrap_or_default();
+        let slop = issued_stamp - stamp as i64;
+        match self.slop {
+            Some(limit) if slop.unsigned_abs() <= limit => NonceCheck {
+                nonce: Some(received.to_owned()),
+                status: NonceStatus::Ok,
+                slop: None,
+            },
+            _ => check(NonceStatus::Slop, Some(slop)),
+        }
+    }
+}
+
+/// The outcome of checking the nonce of a certificate, in the terms of `GIT_PUSH_CERT_NONCE_STATUS`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NonceStatus {
+    /// The certificate has a nonce, but none was issued.
+    Unsolicited,
+    /// The nonce isn't the one that was issued, or wasn't issued by this server at all.
+    Bad,
+    /// A nonce was issued, but the certificate has none.
+    Missing,
+    /// The nonce is the one that was issued.
+    Ok,
+    /// The nonce was issued by this server for this repository in an earlier stateless request, too long ago.
+    Slop,
+}
+
+impl NonceStatus {
+    /// The status as git passes it to hooks, like `OK`.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            NonceStatus::Unsolicited => "UNSOLICITED",
+            NonceStatus::Bad => "BAD",
+            NonceStatus::Missing => "MISSING",
+            NonceStatus::Ok => "OK",
+            NonceStatus::Slop => "SLOP",
+        }
+    }
+}
+
+/// The nonce of a certificate, checked against the one that was issued.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NonceCheck {
+    /// The nonce that was issued, or the one of the certificate if it was accepted in its place, `None` if no
+    /// nonce was issued.
+    pub nonce: Option<String>,
+    /// How the nonce of the certificate compares to the issued one.
+    pub status: NonceStatus,
+    /// How many seconds the nonce of the certificate was issued before the current one, with [`NonceStatus::Slop`].
+    pub slop: Option<i64>,
+}
+
+impl NonceCheck {
+    /// The check of a certificate with the nonce `received` if no nonce was issued.
+    pub fn unissued(received: Option<&str>) -> Self {
+        NonceCheck {
+            nonce: None,
+            status: match received {
+                Some(_) => NonceStatus::Unsolicited,
+                None => NonceStatus::Missing,
+            },
+            slop: None,
+        }
+    }
+}
+
+/// A certificate sent with a signed push.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PushCertificate {
+    /// The certificate as it was sent, the signed payload followed by the signature.
+    pub text: String,
+    /// The `pusher` header, the identity of who signed the push along with a timestamp.
+    pub pusher: Option<String>,
+    /// The `pushee` header, the URL the client pushed to.
+    pub pushee: Option<String>,
+    /// The `nonce` header, the nonce the client received.
+    pub nonce: Option<String>,
+    /// The `push-option` headers, the push options the client sent.
+    pub push_options: Vec<String>,
+    /// The update commands of the push, as `<old-oid> <new-oid> <refname>`.
+    pub commands: Vec<String>,
+    /// Where the signature starts in `text`.
+    signature_start: usize,
+}
+
+impl PushCertificate {
+    /// Parse the certificate `text`, each line ending in a newline, failing if it doesn't separate its headers from
+    /// the commands or isn't of version `0.1`.
+    pub fn parse(text: impl Into<String>) -> Result<Self, Error> {
+        let text = text.into();
+        let invalid = |reason: &str| Error::Protocol(format!("invalid push certificate: {reason}"));
+        let (headers, body) = text
+            .split_once("\n\n")
+            .ok_or_else(|| invalid("no blank line after the headers"))?;
+        let mut lines = headers.lines();
+        if lines.next() != Some("certificate version 0.1") {
+            return Err(invalid("unsupported version"));
+        }
+
+        let mut certificate = PushCertificate {
+            pusher: None,
+            pushee: None,
+            nonce: None,
+            push_options: Vec::new(),
+            commands: Vec::new(),
+            signature_start: text.len(),
+            text: String::new(),
+        };
+        for line in lines {
+            let (name, value) = line.split_once(' ').unwrap_or((line, ""));
+            match name {
+                "pusher" => certificate.pusher = Some(value.to_owned()),
+                "pushee" => certificate.pushee = Some(value.to_owned()),
+                "nonce" => certificate.nonce = Some(value.to_owned()),
+                "push-option" => certificate.push_options.push(value.to_owned()),
+                _ => {}
+            }
+        }
+
+        let body_start = headers.len() + 2;
+        let mut offset = body_start;
+        for line in body.split_inclusive('\n') {
+            if line.starts_with("-----BEGIN ") {
+                certificate.signature_start = offset;
+                break;
+            }
+            let command = line.trim_end_matches('\n');
+            if !command.is_empty() {
+                certificate.commands.push(command.to_owned());
+            }
+            offset += line.len();
+        }
+        certificate.text = text;
+        Ok(certificate)
+    }
+
+    /// The signed part of the certificate, everything before the signature.
+    pub fn payload(&self) -> &str {
+        &self.text[..self.signature_start]
+    }
+
+    /// The signature of the certificate, empty if it wasn't signed.
+    pub fn signature(&self) -> &str {
+        &self.text[self.signature_start..]
+    }
+
+    /// Whether the certificate lists exactly the `push_options` that were sent along with it, in order.
+    pub fn has_push_options(&self, push_options: &[String]) -> bool {
+        self.push_options == push_options
+    }
+
+    /// The head-info the certificate stands for, its commands with `capabilities` on the first one.
+    pub(crate) fn head_info(&self, capabilities: &str) -> String {
+        let mut out = String::new();
+        for (index, command) in self.commands.iter().enumerate() {
+            out.push_str(command);
+            if index == 0 {
+                out.push('\0');
+                out.push_str(capabilities);
+            }
+            out.push('\n');
+        }
+        out
+    }
+}
+
+/// A received certificate, as it's recorded and passed to hooks.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PushCertRecord {
+    /// The id of the blob the certificate was stored as.
+    pub id: ObjectId,
+    /// The certificate.
+    pub certificate: PushCertificate,
+    /// The nonce of the certificate, checked against the one that was issued.
+    pub nonce: NonceCheck,
+}
+
+impl PushCertRecord {
+    /// The variables git passes to hooks for the certificate.
+    ///
+    /// As signatures aren't verified, `GIT_PUSH_CERT_STATUS` is always `N` with an empty `GIT_PUSH_CERT_SIGNER` and
+    /// `GIT_PUSH_CERT_KEY`. Hooks can verify the certificate stored in the blob `GIT_PUSH_CERT` names. The nonce is
+    /// only passed if one was issued.
+    pub fn env_vars(&self) -> Vec<(String, String)> {
+        let mut vars = vec![
+            ("GIT_PUSH_CERT".to_owned(), self.id.to_string()),
+            ("GIT_PUSH_CERT_SIGNER".to_owned(), String::new()),
+            ("GIT_PUSH_CERT_KEY".to_owned(), String::new()),
+            ("GIT_PUSH_CERT_STATUS".to_owned(), "N".to_owned()),
+        ];
+        if let Some(nonce) = &self.nonce.nonce {
+            vars.push(("GIT_PUSH_CERT_NONCE".to_owned(), nonce.clone()));
+            vars.push((
+                "GIT_PUSH_CERT_NONCE_STATUS".to_owned(),
+                self.nonce.status.as_str().to_owned(),
+            ));
+            if let Some(slop) = self.nonce.slop {
+                vars.push(("GIT_PUSH_CERT_NONCE_SLOP".to_owned(), slop.to_string()));
+            }
+        }
+        vars
+    }
+}
+
+/// Split a certificate off `head_info`, returning the head-info it stands for with the lines outside of it, like
+/// `shallow` lines, followed by its commands.
+///
+/// Returns `None` if `head_info` has no certificate.
+pub(crate) fn split_head_info(head_info: &str) -> Result<Option<(String, PushCertificate)>, Error> {
+    let mut rest = String::new();
+    let mut capabilities = None;
+    let mut text = String::new();
+    let mut lines = head_info.lines();
+    while let Some(line) = lines.next() {
+        let Some(caps) = line.strip_prefix(BEGIN).and_then(|caps| caps.strip_prefix('\0')) else {
+            rest.push_str(line);
+            rest.push('\n');
+            continue;
+        };
+        if capabilities.replace(caps).is_some() {
+            return Err(Error::Protocol("more than one push certificate".into()));
+        }
+        loop {
+            match lines.next() {
+                Some(END) => break,
+                Some(line) => {
+                    text.push_str(line);
+                    text.push('\n');
+                }
+                None => return Err(Error::Protocol("push certificate has no end".into())),
+            }
+        }
+    }
+    let Some(capabilities) = capabilities else {
+        return Ok(None);
+    };
+    let certificate = PushCertificate::parse(text)?;
+    rest.push_str(&certificate.head_info(capabilities));
+    Ok(Some((rest, certificate)))
+}
+
+/// The hex HMAC-SHA1 of `message` with `key`.
+fn hmac_sha1(key: &[u8], message: &[u8]) -> String {
+    const BLOCK_SIZE: usize = 64;
+    let sha1 = |parts: &[&[u8]]| {
+        let mut hasher = gix_hash::hasher(gix_hash::Kind::Sha1);
+        for part in parts {
+            hasher.update(part);
+        }
+        hasher.try_finalize().unwrap_or_else(|err| match err {
+            gix_hash::hasher::Error::CollisionAttack { digest } => digest,
+        })
+    };
+
+    let mut block = [0u8; BLOCK_SIZE];
+    if key.len() > BLOCK_SIZE {
+        let digest = sha1(&[key]);
+        block[..digest.as_bytes().len()].copy_from_slice(digest.as_bytes());
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+    let pad = |byte: u8| block.map(|b| b ^ byte);
+    let inner = sha1(&[&pad(0x36), message]);
+    sha1(&[&pad(0x5c), inner.as_bytes()]).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COMMAND: &str =
+        "1111111111111111111111111111111111111111 2222222222222222222222222222222222222222 refs/heads/main";
+
+    fn certificate(nonce: &str) -> String {
+        format!(
+            "certificate version 0.1\npusher A U Thor <author@example.com> 1700000000 +0000\npushee /tmp/remote\nnonce {nonce}\npush-option ci.skip\n\n{COMMAND}\n-----BEGIN PGP SIGNATURE-----\nsig\n-----END PGP SIGNATURE-----\n"
+        )
+    }
+
+    #[test]
+    fn hmac_sha1_matches_rfc_2202() {
+        assert_eq!(
+            hmac_sha1(b"Jefe", b"what do ya want for nothing?"),
+            "effcdf6ae5eb2fa2d27416d5f184df9c259a7c79"
+        );
+        assert_eq!(
+            hmac_sha1(&[0xaa; 80], b"Test Using Larger Than Block-Size Key - Hash Key First"),
+            "aa4ae5e15272d00e95705637ce8a3b55ed402112"
+        );
+    }
+
+    #[test]
+    fn nonces_are_derived_like_git() {
+        // Issued by `git -c receive.certNonceSeed=s3cret receive-pack --advertise-refs pc`.
+        assert_eq!(
+            PushCertConfig::new("s3cret").nonce("pc", 1792182699),
+            "1792182699-05fc0007468d1c1fbd2ab0019ca77a1a45b0bf27"
+        );
+    }
+
+    #[test]
+    fn certificates_are_parsed_into_headers_commands_and_signature() {
+        let text = certificate("1-abc");
+        let cert = PushCertificate::parse(text.clone()).unwrap();
+        assert_eq!(
+            cert.pusher.as_deref(),
+            Some("A U Thor <author@example.com> 1700000000 +0000")
+        );
+        assert_eq!(cert.pushee.as_deref(), Some("/tmp/remote"));
+        assert_eq!(cert.nonce.as_deref(), Some("1-abc"));
+        assert_eq!(cert.push_options, ["ci.skip"]);
+        assert_eq!(cert.commands, [COMMAND]);
+        assert!(cert.payload().ends_with(&format!("\n\n{COMMAND}\n")));
+        assert!(cert.signature().starts_with("-----BEGIN PGP SIGNATURE-----\n"));
+        assert_eq!(format!("{}{}", cert.payload(), cert.signature()), text);
+
+        let err = PushCertificate::parse("certificate version 0.2\n\n").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "protocol error: invalid push certificate: unsupported version"
+        );
+    }
+
+    #[test]
+    fn head_info_takes_its_commands_from_the_certificate() {
+        let mut head_info =
+            "shallow 3333333333333333333333333333333333333333\npush-cert\0report-status atomic\n".to_owned();
+        head_info.push_str(&certificate("1-abc"));
+        head_info.push_str("push-cert-end\n");
+        let (rest, cert) = split_head_info(&head_info).unwrap().expect("has certificate");
+        assert_eq!(
+            rest,
+            format!("shallow 3333333333333333333333333333333333333333\n{COMMAND}\0report-status atomic\n")
+        );
+        assert_eq!(cert.text, certificate("1-abc"));
+
+        assert!(split_head_info(&format!("{COMMAND}\n")).unwrap().is_none());
+        let err = split_head_info("push-cert\0\ncertificate version 0.1\n").unwrap_err();
+        assert_eq!(err.to_string(), "protocol error: push certificate has no end");
+    }
+
+    #[test]
+    fn nonces_must_be_the_issued_one_unless_stateless_within_the_slop() {
+        let config = PushCertConfig::new("seed");
+        let issued = config.nonce("repo", 1000);
+        let status = |config: &PushCertConfig, received: Option<&str>, stateless| {
+            let check = config.check_nonce(&issued, received, "repo", stateless);
+            (check.status, check.slop)
+        };
+        assert_eq!(status(&config, Some(&issued), false), (NonceStatus::Ok, None));
+        assert_eq!(status(&config, None, false), (NonceStatus::Missing, None));
+
+        let earlier = config.nonce("repo", 990);
+        assert_eq!(status(&config, Some(&earlier), false), (NonceStatus::Bad, None));
+        assert_eq!(status(&config, Some(&earlier), true), (NonceStatus::Slop, Some(10)));
+        let tolerant = config.clone().with_slop(10);
+        let check = tolerant.check_nonce(&issued, Some(&earlier), "repo", true);
+        assert_eq!(
+            (check.status, check.nonce.as_deref()),
+            (NonceStatus::Ok, Some(earlier.as_str()))
+        );
+        assert_eq!(
+            status(&tolerant, Some(&config.nonce("other", 990)), true),
+            (NonceStatus::Bad, None),
+            "nonces of other repositories are never accepted"
+        );
+        assert_eq!(status(&tolerant, Some("990-garbage"), true), (NonceStatus::Bad, None));
+
+        assert_eq!(NonceCheck::unissued(Some(&issued)).status, NonceStatus::Unsolicited);
+        assert_eq!(NonceCheck::unissued(None).status, NonceStatus::Missing);
+    }
+}
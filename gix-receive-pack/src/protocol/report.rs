@@ -0,0 +1,220 @@
+// M8: Writing the report of a push.
+//
+// Clients asking for `report-status` get an `unpack` line telling if the pack could be stored, followed by an
+// `ok <ref>` or `ng <ref> <reason>` line per command and a flush. With `report-status-v2`, each `ok` line may be
+// followed by `option` lines telling how the ref was actually updated, like when it was stored under another name.
+// With `side-band-64k`, the whole report is sent on the data band.
+
+use super::machine::{encode_band, encode_data, Report};
+use super::Options;
+use gix_hash::ObjectId;
+
+/// The format of the report a client asked for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReportFormat {
+    /// `report-status`, with one `ok` or `ng` line per command.
+    V1,
+    /// `report-status-v2`, where `ok` lines may be followed by the [extended status](ExtendedStatus) of the ref.
+    V2,
+}
+
+impl ReportFormat {
+    /// The format the client asked for in the negotiated `options`, preferring `report-status-v2` like git, or
+    /// `None` if it didn't ask for a report.
+    pub fn from_options(options: &Options) -> Option<Self> {
+        if options.has("report-status-v2") {
+            Some(ReportFormat::V2)
+        } else if options.has("report-status") {
+            Some(ReportFormat::V1)
+        } else {
+            None
+        }
+    }
+}
+
+/// How a ref was updated if it's not what the client pushed, sent as `option` lines with `report-status-v2`.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ExtendedStatus {
+    /// The ref that was updated instead of the pushed one, sent as `option refname`.
+    pub refname: Option<String>,
+    /// The object the ref pointed to before, sent as `option old-oid`.
+    pub old_oid: Option<ObjectId>,
+    /// The object the ref points to now, sent as `option new-oid`.
+    pub new_oid: Option<ObjectId>,
+    /// Whether the update wasn't a fast-forward, sent as `option forced-update`.
+    pub forced_update: bool,
+}
+
+/// Writes the [`Report`] of a push in the format the client asked for.
+#[derive(Debug, Clone, Copy)]
+pub struct ReportWriter {
+    format: ReportFormat,
+    side_band: bool,
+}
+
+impl ReportWriter {
+    /// Write reports in `format`, without a sideband.
+    pub fn new(format: ReportFormat) -> Self {
+        ReportWriter {
+            format,
+            side_band: false,
+        }
+    }
+
+    /// Send the report on the data band if `side_band` is set, as `side-band-64k` was negotiated.
+    pub fn with_side_band(mut self, side_band: bool) -> Self {
+        self.side_band = side_band;
+        self
+    }
+
+    /// Append `report` to `out` as pkt-lines ending with a flush, with ref statuses in the order they are given.
+    ///
+    /// Extended statuses are only sent with [`ReportFormat::V2`], where each one after the first repeats the `ok`
+    /// line of its ref like git does, so a pushed ref can be reported as updating several refs.
+    pub fn write(&self, report: &Report, out: &mut Vec<u8>) {
+        let mut status = Vec::new();
+        match &report.unpack_error {
+            None => encode_data(&mut status, b"unpack ok\n"),
+            Some(reason) => encode_data(&mut status, format!("unpack {reason}\n").as_bytes()),
+        }
+        for ref_status in &report.refs {
+            let name = &ref_status.name;
+            if let Some(reason) = &ref_status.error {
+                encode_data(&mut status, format!("ng {name} {reason}\n").as_bytes());
+                continue;
+            }
+            encode_data(&mut status, format!("ok {name}\n").as_bytes());
+            if self.format == ReportFormat::V1 {
+                continue;
+            }
+            for (index, extended) in ref_status.extended.iter().enumerate() {
+                if index > 0 {
+                    encode_data(&mut status, format!("ok {name}\n").as_bytes());
+                }
+                encode_options(&mut status, extended);
+            }
+        }
+        status.extend_from_slice(b"0000");
+
+        if self.side_band {
+            encode_band(out, gix_packetline_blocking::Channel::Data, &status);
+        } else {
+            out.extend_from_slice(&status);
+        }
+    }
+}
+
+/// Append the `option` lines of `extended` to `out`.
+fn encode_options(out: &mut Vec<u8>, extended: &ExtendedStatus) {
+    if let Some(refname) = &extended.refname {
+        encode_data(out, format!("option refname {refname}\n").as_bytes());
+    }
+    if let Some(old) = extended.old_oid {
+        encode_data(out, format!("option old-oid {old}\n").as_bytes());
+    }
+    if let Some(new) = extended.new_oid {
+        encode_data(out, format!("option new-oid {new}\n").as_bytes());
+    }
+    if extended.forced_update {
+        encode_data(out, b"option forced-update\n");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::RefStatus;
+
+    fn oid(byte: u8) -> ObjectId {
+        ObjectId::from_bytes_or_panic(&[byte; 20])
+    }
+
+    fn pkt(line: &str) -> Vec<u8> {
+        let mut out = Vec::new();
+        encode_data(&mut out, line.as_bytes());
+        out
+    }
+
+    fn report() -> Report {
+        let extended = ExtendedStatus {
+            refname: Some("refs/changes/1".into()),
+            old_oid: Some(oid(1)),
+            new_oid: Some(oid(2)),
+            forced_update: true,
+        };
+        Report {
+            unpack_error: None,
+            refs: vec![
+                RefStatus::ok("refs/for/main")
+                    .with_extended(extended.clone())
+                    .with_extended(ExtendedStatus {
+                        refname: Some("refs/changes/2".into()),
+                        ..Default::default()
+                    }),
+                RefStatus::rejected("refs/heads/main", "hook declined").with_extended(extended),
+            ],
+        }
+    }
+
+    #[test]
+    fn format_prefers_v2() {
+        let options = |caps: &str| Options::parse(caps);
+        assert_eq!(
+            ReportFormat::from_options(&options("report-status")),
+            Some(ReportFormat::V1)
+        );
+        assert_eq!(
+            ReportFormat::from_options(&options("report-status report-status-v2")),
+            Some(ReportFormat::V2)
+        );
+        assert_eq!(ReportFormat::from_options(&options("side-band-64k")), None);
+    }
+
+    #[test]
+    fn v1_reports_leave_out_extended_statuses() {
+        let mut out = Vec::new();
+        ReportWriter::new(ReportFormat::V1).write(&report(), &mut out);
+        let mut expected = pkt("unpack ok\n");
+        expected.extend(pkt("ok refs/for/main\n"));
+        expected.extend(pkt("ng refs/heads/main hook declined\n"));
+        expected.extend_from_slice(b"0000");
+        assert_eq!(out, expected);
+    }
+
+    #[test]
+    fn v2_reports_send_extended_statuses_of_updated_refs() {
+        let mut out = Vec::new();
+        ReportWriter::new(ReportFormat::V2).write(&report(), &mut out);
+        let mut expected = pkt("unpack ok\n");
+        expected.extend(pkt("ok refs/for/main\n"));
+        expected.extend(pkt("option refname refs/changes/1\n"));
+        expected.extend(pkt(&format!("option old-oid {}\n", oid(1))));
+        expected.extend(pkt(&format!("option new-oid {}\n", oid(2))));
+        expected.extend(pkt("option forced-update\n"));
+        expected.extend(pkt("ok refs/for/main\n"));
+        expected.extend(pkt("option refname refs/changes/2\n"));
+        expected.extend(pkt("ng refs/heads/main hook declined\n"));
+        expected.extend_from_slice(b"0000");
+        assert_eq!(out, expected);
+    }
+
+    #[test]
+    fn side_band_reports_are_sent_on_the_data_band() {
+        let report = Report {
+            unpack_error: Some("index-pack abnormal exit".into()),
+            refs: vec![RefStatus::rejected("refs/heads/main", "unpacker error")],
+        };
+        let mut out = Vec::new();
+        ReportWriter::new(ReportFormat::V2)
+            .with_side_band(true)
+            .write(&report, &mut out);
+        let mut status = pkt("unpack index-pack abnormal exit\n");
+        status.extend(pkt("ng refs/heads/main unpacker error\n"));
+        status.extend_from_slice(b"0000");
+        let mut band = vec![1];
+        band.extend(status);
+        let mut expected = Vec::new();
+        encode_data(&mut expected, &band);
+        assert_eq!(out, expected);
+    }
+}
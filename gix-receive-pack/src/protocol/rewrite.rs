@@ -0,0 +1,245 @@
+// M9: Rewriting pushed refnames to the names they are stored under.
+//
+// Operators can map the refnames clients push to different refnames in the repository, like storing the branches
+// a bot account pushes below `refs/bots/<user>/` for staging or review workflows. Rules are applied right after the
+// commands were parsed, so policies, hooks and the handler only ever see the stored names, while the report sent to
+// the client echoes the names it pushed, so it considers its push complete.
+//
+// Notes
+// - The first matching rule wins, and refs no rule matches are stored as pushed.
+// - A source pattern has at most one `*`, which the target has to have as well and which matches any suffix of
+//   the refname, like with refspecs.
+// - `{principal}` in a target is replaced by the authenticated principal, and rules with it don't apply to
+//   anonymous pushes.
+// - Two commands of a push that would be stored under the same name are rejected as a whole.
+
+use crate::config::keys;
+use crate::protocol::CommandList;
+use crate::Error;
+
+/// The placeholder in rule targets replaced by the authenticated principal.
+pub const PRINCIPAL_PLACEHOLDER: &str = "{principal}";
+
+/// A single rule mapping pushed refnames matching `source` to `target`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RefRewrite {
+    /// The pattern pushed refnames have to match, with at most one `*`.
+    pub source: String,
+    /// The refname to store matches under, with a `*` if `source` has one.
+    pub target: String,
+    /// The only principal the rule applies to, or `None` if it applies to everyone.
+    pub principal: Option<String>,
+}
+
+impl RefRewrite {
+    /// Create a rule storing refs matching `source` under `target` for all principals.
+    ///
+    /// Both have to be below `refs/`, and `target` has to have a `*` exactly if `source` has one.
+    pub fn new(source: impl Into<String>, target: impl Into<String>) -> Result<Self, Error> {
+        let (source, target) = (source.into(), target.into());
+        let invalid =
+            |reason: &str| Err(Error::Validation(format!("invalid ref rewrite '{source}:{target}': {reason}")));
+        if !source.starts_with("refs/") || !target.starts_with("refs/") {
+            return invalid("both sides have to start with 'refs/'");
+        }
+        match (source.matches('*').count(), target.matches('*').count()) {
+            (0, 0) | (1, 1) => {}
+            (0 | 1, _) => return invalid("the target has to have a '*' exactly if the source has one"),
+            _ => return invalid("patterns may have at most one '*'"),
+        }
+        Ok(RefRewrite {
+            source,
+            target,
+            principal: None,
+        })
+    }
+
+    /// Parse a rule from `<source>:<target>`, optionally prefixed with the principal it applies to and a space,
+    /// as used by `receive.rewriteRef`.
+    pub fn parse(spec: &str) -> Result<Self, Error> {
+        let (principal, rule) = match spec.trim().split_once(' ') {
+            Some((principal, rule)) => (Some(principal), rule.trim_start()),
+            None => (None, spec.trim()),
+        };
+        let (source, target) = rule
+            .split_once(':')
+            .ok_or_else(|| Error::Validation(format!("invalid ref rewrite '{spec}': expected '<source>:<target>'")))?;
+        let rule = Self::new(source, target)?;
+        Ok(match principal {
+            Some(principal) => rule.for_principal(principal),
+            None => rule,
+        })
+    }
+
+    /// Only apply the rule to pushes authenticated as `principal`.
+    pub fn for_principal(mut self, principal: impl Into<String>) -> Self {
+        self.principal = Some(principal.into());
+        self
+    }
+
+    /// Return the name `refname` pushed by `principal` is stored under, or `None` if the rule doesn't apply.
+    pub fn apply(&self, refname: &str, principal: Option<&str>) -> Option<String> {
+        if self.principal.is_some() && self.principal.as_deref() != principal {
+            return None;
+        }
+        let target = match self.source.split_once('*') {
+            None => (refname == self.source).then(|| self.target.clone())?,
+            Some((prefix, suffix)) => {
+                let matched = refname
+                    .strip_prefix(prefix)?
+                    .strip_suffix(suffix)
+                    .filter(|matched| !matched.is_empty())?;
+                self.target.replacen('*', matched, 1)
+            }
+        };
+        if self.target.contains(PRINCIPAL_PLACEHOLDER) {
+            return Some(target.replace(PRINCIPAL_PLACEHOLDER, principal?));
+        }
+        Some(target)
+    }
+}
+
+/// The rules rewriting pushed refnames, none by default.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RefRewrites {
+    rules: Vec<RefRewrite>,
+}
+
+impl RefRewrites {
+    /// Create an empty set of rules, storing all refs as pushed.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Load the rules from the values of `receive.rewriteRef`, in order, see [`RefRewrite::parse()`].
+    pub fn from_config(config: &gix_config::File<'static>) -> Result<Self, Error> {
+        let rules = keys::receive::REWRITE_REF
+            .get_all(config)
+            .iter()
+            .map(|spec| RefRewrite::parse(&spec.to_string()))
+            .collect::<Result<_, _>>()?;
+        Ok(RefRewrites { rules })
+    }
+
+    /// Add `rule`, which applies if none of the rules added before it does.
+    pub fn with_rule(mut self, rule: RefRewrite) -> Self {
+        self.rules.push(rule);
+        self
+    }
+
+    /// Return true if there are no rules.
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Return the name `refname` pushed by `principal` is stored under, or `None` if no rule applies.
+    pub fn rewrite(&self, refname: &str, principal: Option<&str>) -> Option<String> {
+        self.rules.iter().find_map(|rule| rule.apply(refname, principal))
+    }
+
+    /// Rewrite the names of `commands` pushed by `principal` in place, returning `(stored, pushed)` pairs of the
+    /// names that changed.
+    ///
+    /// Fails if two commands would update the same stored ref.
+    pub(crate) fn apply(
+        &self,
+        commands: &mut CommandList,
+        principal: Option<&str>,
+    ) -> Result<Vec<(String, String)>, Error> {
+        let mut renamed = Vec::new();
+        if self.is_empty() {
+            return Ok(renamed);
+        }
+        for command in commands.iter_mut() {
+            if let Some(stored) = self.rewrite(command.name(), principal) {
+                let pushed = std::mem::replace(command.name_mut(), stored.clone());
+                renamed.push((stored, pushed));
+            }
+        }
+        let mut names: Vec<_> = commands.iter().map(|command| command.name()).collect();
+        names.sort_unstable();
+        if let Some(name) = names.windows(2).find(|pair| pair[0] == pair[1]).map(|pair| pair[0]) {
+            return Err(Error::Validation(format!("ref rewrites store more than one pushed ref as '{name}'")));
+        }
+        Ok(renamed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn patterns_and_exact_names() {
+        let rule = RefRewrite::new("refs/heads/*", "refs/staging/*").unwrap();
+        assert_eq!(rule.apply("refs/heads/main", None).as_deref(), Some("refs/staging/main"));
+        assert_eq!(rule.apply("refs/heads/team/topic", Some("alice")).as_deref(), Some("refs/staging/team/topic"));
+        assert_eq!(rule.apply("refs/tags/v1.0", None), None);
+        assert_eq!(rule.apply("refs/heads/", None), None, "'*' matches at least one character");
+
+        let rule = RefRewrite::new("refs/heads/main", "refs/review/main").unwrap();
+        assert_eq!(rule.apply("refs/heads/main", None).as_deref(), Some("refs/review/main"));
+        assert_eq!(rule.apply("refs/heads/main2", None), None);
+    }
+
+    #[test]
+    fn principals_scope_and_name_targets() {
+        let rewrites = RefRewrites::new()
+            .with_rule(RefRewrite::parse("ci-bot refs/heads/*:refs/bots/{principal}/*").unwrap())
+            .with_rule(RefRewrite::new("refs/heads/wip/*", "refs/wip/{principal}/*").unwrap());
+        assert_eq!(
+            rewrites.rewrite("refs/heads/main", Some("ci-bot")).as_deref(),
+            Some("refs/bots/ci-bot/main")
+        );
+        assert_eq!(rewrites.rewrite("refs/heads/main", Some("alice")), None);
+        assert_eq!(
+            rewrites.rewrite("refs/heads/wip/topic", Some("alice")).as_deref(),
+            Some("refs/wip/alice/topic"),
+            "later rules apply if earlier ones don't"
+        );
+        assert_eq!(
+            rewrites.rewrite("refs/heads/wip/topic", None),
+            None,
+            "anonymous pushes can't fill in the principal"
+        );
+    }
+
+    #[test]
+    fn invalid_rules() {
+        for spec in [
+            "refs/heads/main",
+            "refs/heads/*:refs/staging/main",
+            "refs/heads/main:refs/staging/*",
+            "refs/heads/*/*:refs/staging/*/*",
+            "heads/*:refs/staging/*",
+        ] {
+            assert!(RefRewrite::parse(spec).is_err(), "{spec}");
+        }
+    }
+
+    #[test]
+    fn from_config() {
+        let config: gix_config::File<'static> = concat!(
+            "[receive]\n",
+            "\trewriteRef = ci-bot refs/heads/*:refs/bots/{principal}/*\n",
+            "\trewriteRef = refs/for/*:refs/review/*\n"
+        )
+        .parse()
+        .unwrap();
+        let rewrites = RefRewrites::from_config(&config).unwrap();
+        assert_eq!(
+            rewrites,
+            RefRewrites::new()
+                .with_rule(
+                    RefRewrite::new("refs/heads/*", "refs/bots/{principal}/*")
+                        .unwrap()
+                        .for_principal("ci-bot")
+                )
+                .with_rule(RefRewrite::new("refs/for/*", "refs/review/*").unwrap())
+        );
+
+        let config: gix_config::File<'static> = "[receive]\n\trewriteRef = refs/heads/*\n".parse().unwrap();
+        let err = RefRewrites::from_config(&config).unwrap_err().to_string();
+        assert!(err.contains("refs/heads/*"), "{err}");
+    }
+}
@@ -0,0 +1,409 @@
+// M9: Uniform service interface shared with upload-pack.
+//
+// `ReceivePackService` runs a `Machine` with the blocking driver and implements
+// `gix_serve_core::service::GitService`, so orchestrators, HTTP adapters and tests can hold it as
+// `Box<dyn GitService>` next to upload-pack. The work itself is still delegated to a `Handler`.
+//
+// Notes
+// - Receive-pack has no protocol v2, so all protocol versions are served like v0, as git does.
+// - The report sent to the client is returned as the `Outcome` of the request, if one was produced.
+// - Ref rewrites apply to the principal of the request, and the outcome lists the refs as stored.
+// - With an auditor, ref updates the handler denied and requests failing for capability violations or limits
+//   are recorded as `Denial`s.
+
+use super::machine::{blocking, Handler, Machine, Phase, Report};
+use super::{
+    CapabilitySet, CapabilityStrictness, CommandList, HeadInfoLimits, HiddenRefPredicate, Options, RefRecord,
+    RefRewrites,
+};
+use crate::{Error, Kind};
+use gix_serve_core::audit::{Auditor, Denial, Reason};
+use gix_serve_core::protocol::ServiceKind;
+use gix_serve_core::service::{self, GitService, Outcome, RefUpdate, ServiceContext};
+use std::io::{Read, Write};
+use std::path::PathBuf;
+
+/// A receive-pack [`GitService`] advertising `refs` and delegating pushes to a [`Handler`].
+pub struct ReceivePackService<H> {
+    refs: Vec<RefRecord>,
+    caps: CapabilitySet,
+    hidden: Option<Box<HiddenRefPredicate>>,
+    strictness: CapabilityStrictness,
+    limits: HeadInfoLimits,
+    rewrites: RefRewrites,
+    auditor: Auditor,
+    repository: PathBuf,
+    handler: H,
+}
+
+impl<H: Handler> ReceivePackService<H> {
+    /// Create a service advertising `refs` with `caps`, which lets `handler` process each push.
+    pub fn new(refs: Vec<RefRecord>, caps: CapabilitySet, handler: H) -> Self {
+        ReceivePackService {
+            refs,
+            caps,
+            hidden: None,
+            strictness: CapabilityStrictness::Strict,
+            limits: HeadInfoLimits::default(),
+            rewrites: RefRewrites::default(),
+            auditor: Auditor::default(),
+            repository: PathBuf::new(),
+            handler,
+        }
+    }
+
+    /// Leave refs matching `hidden` out of the advertisement.
+    pub fn with_hidden_refs(mut self, hidden: Box<HiddenRefPredicate>) -> Self {
+        self.hidden = Some(hidden);
+        self
+    }
+
+    /// Set how capabilities the client sent but we didn't advertise are treated.
+    pub fn with_capability_strictness(mut self, strictness: CapabilityStrictness) -> Self {
+        self.strictness = strictness;
+        self
+    }
+
+    /// Refuse pushes whose head-info or push options exceed `limits`.
+    pub fn with_head_info_limits(mut self, limits: HeadInfoLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Store pushed refs under the names `rewrites` map them to for the principal of each request.
+    pub fn with_ref_rewrites(mut self, rewrites: RefRewrites) -> Self {
+        self.rewrites = rewrites;
+        self
+    }
+
+    /// Record denied ref updates and requests of `repository` with `auditor`.
+    ///
+    /// Ref updates count as denied if the handler reported them with
+    /// [`RefStatus::denied()`](super::RefStatus::denied()).
+    pub fn with_auditor(mut self, auditor: Auditor, repository: impl Into<PathBuf>) -> Self {
+        self.auditor = auditor;
+        self.repository = repository.into();
+        self
+    }
+
+    /// The handler processing pushes.
+    pub fn handler(&self) -> &H {
+        &self.handler
+    }
+
+    /// Return the handler processing pushes.
+    pub fn into_handler(self) -> H {
+        self.handler
+    }
+
+    fn advertised_machine(&self) -> Result<Machine, Error> {
+        let mut machine = Machine::new()
+            .with_capability_strictness(self.strictness)
+            .with_head_info_limits(self.limits);
+        machine.advertise(&self.refs, &self.caps, self.hidden.as_deref())?;
+        Ok(machine)
+    }
+}
+
+impl<H: Handler> GitService for ReceivePackService<H> {
+    fn kind(&self) -> ServiceKind {
+        ServiceKind::ReceivePack
+    }
+
+    fn advertise(&mut self, out: &mut dyn Write, _ctx: &ServiceContext) -> Result<(), service::Error> {
+        let mut machine = self.advertised_machine().map_err(to_service_error)?;
+        out.write_all(&machine.take_output())?;
+        out.flush()?;
+        Ok(())
+    }
+
+    fn serve(
+        &mut self,
+        input: &mut dyn Read,
+        output: &mut dyn Write,
+        ctx: &ServiceContext,
+    ) -> Result<Outcome, service::Error> {
+        let machine = if ctx.stateless {
+            Machine::for_request(self.caps.clone())
+                .with_capability_strictness(self.strictness)
+                .with_head_info_limits(self.limits)
+        } else {
+            self.advertised_machine().map_err(to_service_error)?
+        };
+        let mut machine = machine.with_ref_rewrites(self.rewrites.clone(), ctx.principal.clone());
+        let mut recorder = RecordReport {
+            handler: &mut self.handler,
+            report: None,
+        };
+        let wire = match blocking::drive(&mut machine, input, output, &mut recorder) {
+            Ok(wire) => wire,
+            Err(err) => {
+                let reason = match err.kind() {
+                    Kind::Validation if machine.head_info_limit_exceeded() => Some(Reason::Limit),
+                    // Commands are parsed as protocol errors, so these come from validating capabilities
+                    Kind::Validation if machine.phase() == Phase::HeadInfo => Some(Reason::Capability),
+                    Kind::Resource => Some(Reason::Limit),
+                    Kind::Permission => Some(Reason::Policy),
+                    _ => None,
+                };
+                if let Some(reason) = reason {
+                    self.audit(Denial::new(ServiceKind::ReceivePack, &self.repository, reason, err.to_string()), ctx);
+                }
+                return Err(to_service_error(err));
+            }
+        };
+        let refs = recorder.report.map(|report| report.refs).unwrap_or_default();
+        for status in &refs {
+            if let (Some(reason), Some(error)) = (status.denial, &status.error) {
+                let denial = Denial::new(ServiceKind::ReceivePack, &self.repository, reason, error.as_str());
+                self.audit(denial.with_refname(&status.name), ctx);
+            }
+        }
+        let ref_updates = refs
+            .into_iter()
+            .map(|status| RefUpdate {
+                name: status.name,
+                rejected: status.error,
+            })
+            .collect();
+        Ok(Outcome { ref_updates, wire })
+    }
+}
+
+impl<H> ReceivePackService<H> {
+    fn audit(&self, denial: Denial, ctx: &ServiceContext) {
+        self.auditor.record(&denial.with_context(ctx));
+    }
+}
+
+/// Passes all calls on to `handler`, keeping a copy of the report.
+struct RecordReport<'a, H> {
+    handler: &'a mut H,
+    report: Option<Report>,
+}
+
+impl<H: Handler> Handler for RecordReport<'_, H> {
+    fn commands(&mut self, commands: &CommandList, options: &Options) -> Result<(), Error> {
+        self.handler.commands(commands, options)
+    }
+
+    fn pack_data(&mut self, data: &[u8]) -> Result<bool, Error> {
+        self.handler.pack_data(data)
+    }
+
+    fn report(&mut self) -> Result<Report, Error> {
+        let report = self.handler.report()?;
+        self.report = Some(report.clone());
+        Ok(report)
+    }
+
+    fn after_report(&mut self) -> Result<Vec<u8>, Error> {
+        self.handler.after_report()
+    }
+}
+
+/// Keep IO errors as they are and map all others by their [`Kind`].
+pub(crate) fn to_service_error(err: Error) -> service::Error {
+    match err {
+        Error::Io(err) => service::Error::Io(err),
+        err => match err.kind() {
+            Kind::Protocol => service::Error::Protocol(err.to_string()),
+            Kind::Validation | Kind::Permission | Kind::NotFound => service::Error::Validation(err.to_string()),
+            _ => service::Error::Internal(err.to_string()),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::RefStatus;
+    use gix_serve_core::protocol::ProtocolVersion;
+
+    const ZERO: &str = "0000000000000000000000000000000000000000";
+    const A: &str = "1111111111111111111111111111111111111111";
+
+    fn pkt(data: &str) -> String {
+        format!("{:04x}{data}", data.len() + 4)
+    }
+
+    /// Accepts every update except deletions.
+    #[derive(Default)]
+    struct NoDeletes {
+        statuses: Vec<RefStatus>,
+    }
+
+    impl Handler for NoDeletes {
+        fn commands(&mut self, commands: &CommandList, _options: &Options) -> Result<(), Error> {
+            self.statuses = commands
+                .iter()
+                .map(|cmd| match cmd {
+                    crate::protocol::CommandUpdate::Delete { name, .. } => {
+                        RefStatus::denied(name, "deletion denied", Reason::Policy)
+                    }
+                    cmd => RefStatus::ok(cmd.name()),
+                })
+                .collect();
+            Ok(())
+        }
+
+        fn pack_data(&mut self, _data: &[u8]) -> Result<bool, Error> {
+            Ok(false)
+        }
+
+        fn report(&mut self) -> Result<Report, Error> {
+            Ok(Report {
+                unpack_error: None,
+                refs: self.statuses.clone(),
+            })
+        }
+    }
+
+    fn receive_pack() -> ReceivePackService<NoDeletes> {
+        let oid = gix_hash::ObjectId::from_hex(A.as_bytes()).expect("valid hex");
+        let refs = vec![RefRecord::new(oid, "refs/heads/main")];
+        ReceivePackService::new(refs, CapabilitySet::modern_defaults(), NoDeletes::default())
+    }
+
+    fn service() -> Box<dyn GitService> {
+        Box::new(receive_pack())
+    }
+
+    #[test]
+    fn stateless_push_reports_ref_updates() {
+        let mut service = service();
+        assert_eq!(service.kind(), ServiceKind::ReceivePack);
+        let ctx = ServiceContext::new(ProtocolVersion::V0).with_stateless(true);
+
+        let mut advertisement = Vec::new();
+        service.advertise(&mut advertisement, &ctx).unwrap();
+        let advertisement = String::from_utf8(advertisement).unwrap();
+        assert!(
+            advertisement.contains(&format!("{A} refs/heads/main\0")),
+            "{advertisement}"
+        );
+
+        let request = pkt(&format!("{A} {ZERO} refs/heads/main\0report-status delete-refs\n")) + "0000";
+        let mut output = Vec::new();
+        let outcome = service.serve(&mut request.as_bytes(), &mut output, &ctx).unwrap();
+        assert_eq!(
+            outcome.ref_updates,
+            vec![RefUpdate {
+                name: "refs/heads/main".into(),
+                rejected: Some("deletion denied".into()),
+            }]
+        );
+        let output = String::from_utf8(output).unwrap();
+        assert!(!output.contains("refs/heads/main\0"), "no advertisement: {output}");
+        assert!(output.contains("ng refs/heads/main deletion denied"), "{output}");
+    }
+
+    #[test]
+    fn push_conversations_match_their_transcript() {
+        let caps = CapabilitySet {
+            side_band_64k: true,
+            agent: Some("gix/0.1.0".into()),
+            ..CapabilitySet::modern_defaults()
+        };
+        let oid = gix_hash::ObjectId::from_hex(A.as_bytes()).expect("valid hex");
+        let mut service: Box<dyn GitService> = Box::new(ReceivePackService::new(
+            vec![RefRecord::new(oid, "refs/heads/main")],
+            caps,
+            NoDeletes::default(),
+        ));
+        let ctx = ServiceContext::new(ProtocolVersion::V0).with_stateless(true);
+        let mut transcript = gix_serve_core::testing::snapshot::Transcript::new("push with a denied deletion");
+
+        let mut advertisement = Vec::new();
+        service.advertise(&mut advertisement, &ctx).unwrap();
+        let commands = pkt(&format!(
+            "{ZERO} {A} refs/heads/new\0report-status side-band-64k delete-refs agent=git/2.45.0\n"
+        )) + &pkt(&format!("{A} {ZERO} refs/heads/main\n"))
+            + "0000";
+        let request = [commands.as_bytes(), b"PACK\0\0\0\x02\0\0\0\0", &[0; 20]].concat();
+        let mut response = Vec::new();
+        service.serve(&mut request.as_slice(), &mut response, &ctx).unwrap();
+
+        transcript.response(&advertisement).request(&request).response(&response);
+        transcript.assert_golden(std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/snapshots/push.txt"));
+    }
+
+    #[test]
+    fn stateful_connections_start_with_the_advertisement() {
+        let mut service = service();
+        let ctx = ServiceContext::new(ProtocolVersion::V1);
+        let mut output = Vec::new();
+        let outcome = service.serve(&mut &b"0000"[..], &mut output, &ctx).unwrap();
+        assert!(outcome.ref_updates.is_empty());
+        assert_eq!(outcome.wire.request_bytes, 4);
+        assert_eq!(outcome.wire.response_bytes, output.len() as u64, "the advertisement is counted");
+        assert!(String::from_utf8(output).unwrap().contains("refs/heads/main\0"));
+
+        let err = service
+            .serve(&mut &b"zzzz"[..], &mut Vec::new(), &ctx.with_stateless(true))
+            .unwrap_err();
+        assert!(matches!(err, service::Error::Protocol(_)), "{err}");
+    }
+
+    #[test]
+    fn refs_are_rewritten_for_the_principal_of_the_request() {
+        let rewrites = RefRewrites::new()
+            .with_rule(crate::protocol::RefRewrite::new("refs/heads/*", "refs/users/{principal}/*").unwrap());
+        let mut service: Box<dyn GitService> = Box::new(receive_pack().with_ref_rewrites(rewrites));
+        let ctx = ServiceContext::new(ProtocolVersion::V0)
+            .with_stateless(true)
+            .with_principal("alice");
+
+        let request = pkt(&format!("{A} {ZERO} refs/heads/main\0report-status delete-refs\n")) + "0000";
+        let mut output = Vec::new();
+        let outcome = service.serve(&mut request.as_bytes(), &mut output, &ctx).unwrap();
+        assert_eq!(outcome.ref_updates[0].name, "refs/users/alice/main");
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("ng refs/heads/main deletion denied"), "{output}");
+    }
+
+    #[test]
+    fn denials_are_audited() {
+        let denials = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let auditor = Auditor::new({
+            let denials = denials.clone();
+            move |denial: &Denial| denials.lock().unwrap().push(denial.clone())
+        });
+        let mut service: Box<dyn GitService> = Box::new(
+            receive_pack()
+                .with_head_info_limits(HeadInfoLimits::default().with_max_commands(1))
+                .with_auditor(auditor, "/srv/repo.git"),
+        );
+        let ctx = ServiceContext::new(ProtocolVersion::V0)
+            .with_stateless(true)
+            .with_principal("alice");
+
+        let request = pkt(&format!("{A} {ZERO} refs/heads/main\0report-status delete-refs\n")) + "0000";
+        service.serve(&mut request.as_bytes(), &mut Vec::new(), &ctx).unwrap();
+        let request = pkt(&format!("{A} {ZERO} refs/heads/main\0report-status no-such-capability\n")) + "0000";
+        let err = service.serve(&mut request.as_bytes(), &mut Vec::new(), &ctx).unwrap_err();
+        assert!(matches!(err, service::Error::Validation(_)), "{err}");
+        let request = pkt(&format!("{A} {ZERO} refs/heads/main\0report-status delete-refs\n"))
+            + &pkt(&format!("{A} {ZERO} refs/heads/side\n"))
+            + "0000";
+        let err = service.serve(&mut request.as_bytes(), &mut Vec::new(), &ctx).unwrap_err();
+        assert!(matches!(err, service::Error::Validation(_)), "{err}");
+
+        let denials = denials.lock().unwrap();
+        assert_eq!(denials.len(), 3, "{denials:?}");
+        assert_eq!(denials[0].reason, Reason::Policy);
+        assert_eq!(denials[0].refname.as_deref(), Some("refs/heads/main"));
+        assert_eq!(denials[0].message, "deletion denied");
+        assert_eq!(denials[0].principal.as_deref(), Some("alice"));
+        assert_eq!(denials[0].repository, std::path::Path::new("/srv/repo.git"));
+        assert_eq!(denials[1].reason, Reason::Capability);
+        assert_eq!(denials[1].refname, None);
+        assert_eq!(denials[2].reason, Reason::Limit);
+        assert_eq!(denials[2].message, "validation error: command 2 exceeds the limit of 1 commands per push");
+        assert_eq!(
+            crate::policy::ReasonCode::HookRejected.denial_reason(),
+            Some(Reason::Hook),
+            "policy decisions map to audit reasons"
+        );
+    }
+}
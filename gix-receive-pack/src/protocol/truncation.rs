@@ -0,0 +1,205 @@
+// M1: Advertisement truncation for repositories with enormous ref counts.
+//
+// Push clients only use the advertised refs as negotiation hints: they learn which objects the
+// server already has, and which old value to send for refs they update. Advertising fewer refs
+// therefore only makes packs larger, never pushes incorrect, as commands for refs that weren't
+// advertised are accepted like any other. Operators can trade pack size for advertisement size by
+// keeping only the most recently updated refs, dropping tags, or restricting the advertisement to
+// ref namespaces, and can cap the `.have` lines advertised for alternate object stores.
+
+use gix_hash::ObjectId;
+
+use super::RefRecord;
+use crate::config::keys;
+use crate::Error;
+
+/// The pseudo-refname of lines advertising tips of alternate object stores.
+pub const HAVE_REFNAME: &str = ".have";
+
+/// Limits applied to the refs of an advertisement, all disabled by default.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AdvertisementLimits {
+    /// Advertise at most this many refs, keeping the most recently updated ones.
+    pub max_refs: Option<usize>,
+    /// Don't advertise refs below `refs/tags/`.
+    pub omit_tags: bool,
+    /// Only advertise refs starting with one of these prefixes, or all refs if empty.
+    pub namespaces: Vec<String>,
+    /// Advertise at most this many `.have` lines for tips of alternate object stores.
+    pub max_have_lines: Option<usize>,
+}
+
+impl AdvertisementLimits {
+    /// Load the limits from `receive.advertiseMaxRefs`, `receive.advertiseTags`, `receive.advertiseNamespace`
+    /// and `receive.advertiseMaxHaves`.
+    ///
+    /// Negative limits are rejected, and `0` disables the respective limit.
+    pub fn from_config(config: &gix_config::File<'static>) -> Result<Self, Error> {
+        let limit = |key: keys::Key<i64>| -> Result<Option<usize>, Error> {
+            match key.get(config)? {
+                None | Some(0) => Ok(None),
+                Some(value) => usize::try_from(value).map(Some).map_err(|_| {
+                    Error::Validation(format!("invalid value for '{}': must not be negative", key.name()))
+                }),
+            }
+        };
+        Ok(Self {
+            max_refs: limit(keys::receive::ADVERTISE_MAX_REFS)?,
+            omit_tags: !keys::receive::ADVERTISE_TAGS.get(config)?.unwrap_or(true),
+            namespaces: keys::receive::ADVERTISE_NAMESPACE
+                .get_all(config)
+                .into_iter()
+                .map(|prefix| prefix.to_string())
+                .collect(),
+            max_have_lines: limit(keys::receive::ADVERTISE_MAX_HAVES)?,
+        })
+    }
+
+    /// Advertise at most `max` refs, keeping the most recently updated ones.
+    pub fn with_max_refs(mut self, max: impl Into<Option<usize>>) -> Self {
+        self.max_refs = max.into();
+        self
+    }
+
+    /// Don't advertise refs below `refs/tags/` if `omit` is true.
+    pub fn with_omit_tags(mut self, omit: bool) -> Self {
+        self.omit_tags = omit;
+        self
+    }
+
+    /// Add `prefix` to the namespaces refs have to be in to be advertised.
+    pub fn with_namespace(mut self, prefix: impl Into<String>) -> Self {
+        self.namespaces.push(prefix.into());
+        self
+    }
+
+    /// Advertise at most `max` `.have` lines.
+    pub fn with_max_have_lines(mut self, max: impl Into<Option<usize>>) -> Self {
+        self.max_have_lines = max.into();
+        self
+    }
+
+    /// Return true if no limit is configured.
+    pub fn is_unlimited(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Return the refs to advertise out of `refs`, preserving their order.
+    ///
+    /// `recency` returns the time of the last update of a ref, like the commit time of its tip, and is only called
+    /// if `max_refs` has to drop refs. Refs without known recency are dropped first.
+    pub fn apply(&self, refs: &[RefRecord], recency: impl Fn(&RefRecord) -> Option<i64>) -> Vec<RefRecord> {
+        let mut kept: Vec<(usize, &RefRecord)> = refs
+            .iter()
+            .enumerate()
+            .filter(|(_, r)| !(self.omit_tags && r.name.starts_with("refs/tags/")))
+            .filter(|(_, r)| self.namespaces.is_empty() || self.namespaces.iter().any(|ns| r.name.starts_with(ns.as_str())))
+            .collect();
+        if let Some(max) = self.max_refs.filter(|max| kept.len() > *max) {
+            // Stable sort, so refs updated at the same time are kept in advertisement order.
+            kept.sort_by_cached_key(|(_, r)| std::cmp::Reverse(recency(r)));
+            kept.truncate(max);
+            kept.sort_unstable_by_key(|(idx, _)| *idx);
+        }
+        kept.into_iter().map(|(_, r)| r.clone()).collect()
+    }
+
+    /// Return the `.have` lines to advertise for `tips` of alternate object stores, without duplicates and
+    /// limited to `max_have_lines`.
+    pub fn have_lines(&self, tips: &[ObjectId]) -> Vec<RefRecord> {
+        let mut seen = std::collections::HashSet::new();
+        tips.iter()
+            .filter(|oid| seen.insert(**oid))
+            .take(self.max_have_lines.unwrap_or(usize::MAX))
+            .map(|oid| RefRecord::new(*oid, HAVE_REFNAME))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn oid(byte: u8) -> ObjectId {
+        ObjectId::from_bytes_or_panic(&[byte; 20])
+    }
+
+    fn refs() -> Vec<RefRecord> {
+        vec![
+            RefRecord::new(oid(1), "refs/heads/main"),
+            RefRecord::new(oid(2), "refs/heads/old"),
+            RefRecord::new(oid(3), "refs/heads/team/feature"),
+            RefRecord::new(oid(4), "refs/tags/v1.0"),
+        ]
+    }
+
+    fn names(refs: &[RefRecord]) -> Vec<&str> {
+        refs.iter().map(|r| r.name.as_str()).collect()
+    }
+
+    #[test]
+    fn unlimited_keeps_everything() {
+        let limits = AdvertisementLimits::default();
+        assert!(limits.is_unlimited());
+        assert_eq!(limits.apply(&refs(), |_| None), refs());
+    }
+
+    #[test]
+    fn tags_and_namespaces() {
+        let limits = AdvertisementLimits::default().with_omit_tags(true);
+        assert_eq!(
+            names(&limits.apply(&refs(), |_| None)),
+            ["refs/heads/main", "refs/heads/old", "refs/heads/team/feature"]
+        );
+
+        let limits = AdvertisementLimits::default()
+            .with_namespace("refs/heads/team/")
+            .with_namespace("refs/tags/");
+        assert_eq!(
+            names(&limits.apply(&refs(), |_| None)),
+            ["refs/heads/team/feature", "refs/tags/v1.0"]
+        );
+    }
+
+    #[test]
+    fn most_recent_refs_are_kept_in_order() {
+        let limits = AdvertisementLimits::default().with_max_refs(2);
+        let recency = |r: &RefRecord| match r.name.as_str() {
+            "refs/heads/old" => Some(10),
+            "refs/tags/v1.0" => None,
+            _ => Some(100),
+        };
+        assert_eq!(
+            names(&limits.apply(&refs(), recency)),
+            ["refs/heads/main", "refs/heads/team/feature"]
+        );
+    }
+
+    #[test]
+    fn have_lines_are_deduplicated_and_capped() {
+        let limits = AdvertisementLimits::default().with_max_have_lines(2);
+        let haves = limits.have_lines(&[oid(1), oid(1), oid(2), oid(3)]);
+        assert_eq!(haves, vec![RefRecord::new(oid(1), ".have"), RefRecord::new(oid(2), ".have")]);
+    }
+
+    #[test]
+    fn from_config() {
+        let config: gix_config::File<'static> = "[receive]\n\tadvertiseMaxRefs = 100\n\tadvertiseTags = false\n\tadvertiseNamespace = refs/heads/\n\tadvertiseNamespace = refs/changes/\n\tadvertiseMaxHaves = 0\n"
+            .parse()
+            .unwrap();
+        let limits = AdvertisementLimits::from_config(&config).unwrap();
+        assert_eq!(
+            limits,
+            AdvertisementLimits {
+                max_refs: Some(100),
+                omit_tags: true,
+                namespaces: vec!["refs/heads/".into(), "refs/changes/".into()],
+                max_have_lines: None,
+            }
+        );
+
+        let config: gix_config::File<'static> = "[receive]\n\tadvertiseMaxRefs = -1\n".parse().unwrap();
+        let err = AdvertisementLimits::from_config(&config).unwrap_err().to_string();
+        assert!(err.contains("receive.advertiseMaxRefs"), "{err}");
+    }
+}
@@ -0,0 +1,175 @@
+//! Symbolic refs among the refs a push updates.
+
+use super::{PlannedCommand, TransactionError};
+use crate::protocol::CommandUpdate;
+use std::collections::HashMap;
+
+/// Symbolic ref chains are never followed further than this, which also ends cycles.
+const MAX_SYMREF_DEPTH: usize = 5;
+
+/// Looks up where symbolic refs point to.
+pub struct SymrefResolver<'a> {
+    refs: &'a gix_ref::file::Store,
+}
+
+impl<'a> SymrefResolver<'a> {
+    /// Resolve symbolic refs in `refs`.
+    pub fn new(refs: &'a gix_ref::file::Store) -> Self {
+        Self { refs }
+    }
+
+    /// The ref store symbolic refs are resolved in.
+    pub fn refs(&self) -> &'a gix_ref::file::Store {
+        self.refs
+    }
+
+    /// The ref the ref `name` points to if it's symbolic, or `None` if it isn't, doesn't exist or isn't a valid ref
+    /// name.
+    pub fn target(&self, name: &str) -> Result<Option<String>, TransactionError> {
+        let Ok(full_name) = gix_ref::FullName::try_from(name) else {
+            return Ok(None);
+        };
+        match super::find(self.refs, &full_name)? {
+            Some(gix_ref::Reference {
+                target: gix_ref::Target::Symbolic(target),
+                ..
+            }) => Ok(Some(target.as_bstr().to_string())),
+            _ => Ok(None),
+        }
+    }
+
+    /// The ref at the end of the chain of symbolic refs starting at `name`, or `None` if `name` isn't symbolic.
+    ///
+    /// Like in git, the last ref of the chain doesn't have to exist, while chains that are too long or cyclic fail
+    /// with [`TransactionError::BrokenSymref`].
+    pub fn resolve(&self, name: &str) -> Result<Option<String>, TransactionError> {
+        let mut resolved = None;
+        for _ in 0..MAX_SYMREF_DEPTH {
+            match self.target(resolved.as_deref().unwrap_or(name))? {
+                Some(target) => resolved = Some(target),
+                None => return Ok(resolved),
+            }
+        }
+        Err(TransactionError::BrokenSymref { name: name.into() })
+    }
+}
+
+/// Validates commands updating the same ref through symbolic refs, like `git receive-pack` does.
+///
+/// A command updating a symbolic ref whose target is updated by another command of the same push has to agree with
+/// that command on the old and new object. If it does, it's skipped as the update of the target applies it already,
+/// and otherwise both are rejected as `inconsistent aliased update`.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct AliasValidator;
+
+impl AliasValidator {
+    /// Create a validator.
+    pub fn new() -> Self {
+        AliasValidator
+    }
+
+    /// Skip or reject the aliased updates among `commands`, whose symbolic refs must be resolved already, and return
+    /// why commands were rejected for logs.
+    pub fn validate(&self, commands: &mut [PlannedCommand]) -> Vec<String> {
+        let by_name: HashMap<String, usize> = commands
+            .iter()
+            .enumerate()
+            .filter(|(_, planned)| planned.rejection.is_none())
+            .map(|(index, planned)| (planned.command.name().to_owned(), index))
+            .collect();
+        let mut messages = Vec::new();
+        for index in 0..commands.len() {
+            let planned = &commands[index];
+            if planned.rejection.is_some() || planned.skip {
+                continue;
+            }
+            let Some(target_index) = planned.target.as_ref().and_then(|target| by_name.get(target)).copied() else {
+                continue;
+            };
+            let (symref, target) = (&planned.command, &commands[target_index].command);
+            if symref.old_and_new() == target.old_and_new() {
+                commands[index].skip = true;
+                continue;
+            }
+            messages.push(format!(
+                "refusing inconsistent update between symref '{}' ({}) and its target '{}' ({})",
+                symref.name(),
+                range(symref),
+                target.name(),
+                range(target)
+            ));
+            for index in [index, target_index] {
+                commands[index].rejection = Some("inconsistent aliased update".into());
+            }
+        }
+        messages
+    }
+}
+
+/// The change of `command` as `<old>..<new>` with abbreviated object ids, like git shows it.
+fn range(command: &CommandUpdate) -> String {
+    let (old, new) = command.old_and_new();
+    let short = |id: Option<gix_hash::ObjectId>| {
+        id.unwrap_or_else(|| gix_hash::Kind::Sha1.null())
+            .to_hex_with_len(7)
+            .to_string()
+    };
+    format!("{}..{}", short(old), short(new))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gix_hash::ObjectId;
+
+    fn oid(byte: u8) -> ObjectId {
+        ObjectId::from_bytes_or_panic(&[byte; 20])
+    }
+
+    fn update(name: &str, old: u8, new: u8, target: Option<&str>) -> PlannedCommand {
+        PlannedCommand {
+            target: target.map(Into::into),
+            ..PlannedCommand::new(CommandUpdate::Update {
+                old: oid(old),
+                new: oid(new),
+                name: name.into(),
+            })
+        }
+    }
+
+    #[test]
+    fn consistent_aliased_updates_skip_the_symref() {
+        let mut commands = [
+            update("refs/heads/alias", 1, 2, Some("refs/heads/main")),
+            update("refs/heads/main", 1, 2, None),
+        ];
+        assert!(AliasValidator::new().validate(&mut commands).is_empty());
+        assert!(commands[0].skip, "the update of the target applies the symref's update");
+        assert!(commands[1].is_pending());
+    }
+
+    #[test]
+    fn inconsistent_aliased_updates_reject_both() {
+        let mut commands = [
+            update("refs/heads/alias", 1, 2, Some("refs/heads/main")),
+            update("refs/heads/main", 1, 3, None),
+            update("refs/heads/other", 1, 3, None),
+        ];
+        let messages = AliasValidator::new().validate(&mut commands);
+        assert_eq!(
+            messages,
+            ["refusing inconsistent update between symref 'refs/heads/alias' (0101010..0202020) and its target 'refs/heads/main' (0101010..0303030)"]
+        );
+        for planned in &commands[..2] {
+            assert_eq!(planned.rejection.as_deref(), Some("inconsistent aliased update"));
+        }
+        assert!(commands[2].is_pending(), "other commands aren't affected");
+    }
+
+    #[test]
+    fn symrefs_without_updated_targets_are_left_alone() {
+        let mut commands = [update("refs/heads/alias", 1, 2, Some("refs/heads/main"))];
+        assert!(AliasValidator::new().validate(&mut commands).is_empty());
+        assert!(commands[0].is_pending());
+    }
+}
@@ -0,0 +1,225 @@
+//! Ref updates that can't be applied in the same transaction.
+
+use super::{PlannedCommand, TransactionError};
+use crate::protocol::CommandUpdate;
+use std::collections::BTreeSet;
+use std::ops::Bound;
+
+/// Why two updates can't be applied in the same transaction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConflictType {
+    /// The same ref is updated more than once, possibly through symbolic refs.
+    Duplicate,
+    /// One ref would be a directory of the other, like `refs/heads/a` and `refs/heads/a/b`, as refs are stored as
+    /// files. This includes refs that exist already and aren't updated.
+    DirectoryFile,
+}
+
+/// An update that can't be applied in the same transaction as another update or an existing ref.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Conflict {
+    /// The ref that can't be updated.
+    pub name: String,
+    /// The ref it conflicts with.
+    pub other: String,
+    /// How the two conflict.
+    pub kind: ConflictType,
+}
+
+impl std::fmt::Display for Conflict {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.kind {
+            ConflictType::Duplicate => write!(f, "multiple updates for ref '{}' not allowed", self.name),
+            ConflictType::DirectoryFile => write!(
+                f,
+                "cannot lock ref '{}': '{}' exists; cannot create '{}'",
+                self.name, self.other, self.name
+            ),
+        }
+    }
+}
+
+/// Finds the updates of a plan that can't be applied in one transaction.
+///
+/// Refs are changed through lock files next to them, so a transaction can neither lock a ref twice nor lock a ref
+/// along with one below it. Deleting `refs/heads/a` doesn't make room for `refs/heads/a/b` either, as the deletion
+/// isn't done before the transaction is committed, and the same goes for existing refs below a new one.
+pub struct ConflictDetector<'a> {
+    refs: &'a gix_ref::file::Store,
+}
+
+impl<'a> ConflictDetector<'a> {
+    /// Detect conflicts among updates and with the existing refs in `refs`.
+    pub fn new(refs: &'a gix_ref::file::Store) -> Self {
+        Self { refs }
+    }
+
+    /// Return the conflicts of the pending updates among `commands`, each reported for the later of the two
+    /// updates.
+    pub fn detect(&self, commands: &[PlannedCommand]) -> Result<Vec<Conflict>, TransactionError> {
+        let mut conflicts = Vec::new();
+        let mut names = BTreeSet::<&str>::new();
+        for planned in commands.iter().filter(|planned| planned.is_pending()) {
+            let name = planned.effective_name();
+            let conflict = |other: &str, kind| Conflict {
+                name: name.into(),
+                other: other.into(),
+                kind,
+            };
+            if names.contains(name) {
+                conflicts.push(conflict(name, ConflictType::Duplicate));
+                continue;
+            }
+            let directory = format!("{name}/");
+            if let Some(other) = parents(name).find(|parent| names.contains(parent)) {
+                conflicts.push(conflict(other, ConflictType::DirectoryFile));
+            } else if let Some(other) = names
+                .range::<str, _>((Bound::Included(directory.as_str()), Bound::Unbounded))
+                .next()
+                .filter(|other| other.starts_with(&directory))
+            {
+                conflicts.push(conflict(other, ConflictType::DirectoryFile));
+            } else if let CommandUpdate::Create { .. } = planned.command {
+                if let Some(other) = self.existing_conflict(name)? {
+                    conflicts.push(conflict(&other, ConflictType::DirectoryFile));
+                }
+            }
+            names.insert(name);
+        }
+        Ok(conflicts)
+    }
+
+    /// The existing ref that is a directory of the ref `name` to be created or below it, if there is one.
+    fn existing_conflict(&self, name: &str) -> Result<Option<String>, TransactionError> {
+        for parent in parents(name) {
+            let Ok(full_name) = gix_ref::FullName::try_from(parent) else {
+                continue;
+            };
+            let exists = super::find(self.refs, &full_name)?.is_some();
+            if exists {
+                return Ok(Some(parent.into()));
+            }
+        }
+
+        let prefix = format!("{name}/");
+        let list = |source: Box<dyn std::error::Error + Send + Sync>| TransactionError::List {
+            prefix: prefix.clone(),
+            source,
+        };
+        let Ok(relative) = <&gix_path::RelativePath>::try_from(prefix.as_str()) else {
+            return Ok(None);
+        };
+        let platform = self.refs.iter().map_err(|err| list(err.into()))?;
+        let mut below = platform.prefixed(relative).map_err(|err| list(err.into()))?;
+        match below.next() {
+            Some(Ok(reference)) => Ok(Some(reference.name.as_bstr().to_string())),
+            Some(Err(err)) => Err(list(err.into())),
+            None => Ok(None),
+        }
+    }
+}
+
+/// The directories of the ref `name` that could be refs themselves, like `refs/heads/a` for `refs/heads/a/b`.
+fn parents(name: &str) -> impl Iterator<Item = &str> {
+    name.match_indices('/')
+        .map(move |(index, _)| &name[..index])
+        .filter(|parent| parent.matches('/').count() >= 2)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gix_hash::ObjectId;
+
+    fn oid(byte: u8) -> ObjectId {
+        ObjectId::from_bytes_or_panic(&[byte; 20])
+    }
+
+    fn ref_store(existing: &[&str]) -> (gix_testtools::tempfile::TempDir, gix_ref::file::Store) {
+        let tmp = gix_testtools::tempfile::tempdir().unwrap();
+        for name in existing {
+            let path = tmp.path().join(name);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, format!("{}\n", oid(1))).unwrap();
+        }
+        let store = gix_ref::file::Store::at(
+            tmp.path().into(),
+            gix_ref::store::init::Options {
+                write_reflog: gix_ref::store::WriteReflog::Disable,
+                ..Default::default()
+            },
+        );
+        (tmp, store)
+    }
+
+    fn create(name: &str) -> PlannedCommand {
+        PlannedCommand::new(CommandUpdate::Create {
+            new: oid(2),
+            name: name.into(),
+        })
+    }
+
+    fn delete(name: &str) -> PlannedCommand {
+        PlannedCommand::new(CommandUpdate::Delete {
+            old: oid(1),
+            name: name.into(),
+        })
+    }
+
+    #[test]
+    fn duplicates_conflict_even_through_symrefs() {
+        let (_tmp, refs) = ref_store(&[]);
+        let mut alias = create("refs/heads/alias");
+        alias.target = Some("refs/heads/main".into());
+        let conflicts = ConflictDetector::new(&refs)
+            .detect(&[create("refs/heads/main"), alias])
+            .unwrap();
+        assert_eq!(
+            conflicts,
+            [Conflict {
+                name: "refs/heads/main".into(),
+                other: "refs/heads/main".into(),
+                kind: ConflictType::Duplicate,
+            }]
+        );
+        assert_eq!(
+            conflicts[0].to_string(),
+            "multiple updates for ref 'refs/heads/main' not allowed"
+        );
+    }
+
+    #[test]
+    fn refs_conflict_with_updated_refs_above_and_below_them() {
+        let (_tmp, refs) = ref_store(&[]);
+        let detector = ConflictDetector::new(&refs);
+        for commands in [
+            [delete("refs/heads/a"), create("refs/heads/a/b")],
+            [create("refs/heads/a/b"), create("refs/heads/a")],
+        ] {
+            let conflicts = detector.detect(&commands).unwrap();
+            assert_eq!(conflicts.len(), 1, "the later of both updates conflicts");
+            assert_eq!(conflicts[0].kind, ConflictType::DirectoryFile);
+            assert_eq!(conflicts[0].name, commands[1].command.name());
+        }
+        assert!(detector
+            .detect(&[create("refs/heads/ab"), create("refs/heads/a.b")])
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn created_refs_conflict_with_existing_refs_above_and_below_them() {
+        let (_tmp, refs) = ref_store(&["refs/heads/a", "refs/heads/c/d"]);
+        let detector = ConflictDetector::new(&refs);
+        let conflicts = detector
+            .detect(&[create("refs/heads/a/b"), create("refs/heads/c"), create("refs/heads/e")])
+            .unwrap();
+        assert_eq!(
+            conflicts.iter().map(ToString::to_string).collect::<Vec<_>>(),
+            [
+                "cannot lock ref 'refs/heads/a/b': 'refs/heads/a' exists; cannot create 'refs/heads/a/b'",
+                "cannot lock ref 'refs/heads/c': 'refs/heads/c/d' exists; cannot create 'refs/heads/c'",
+            ]
+        );
+    }
+}
@@ -0,0 +1,293 @@
+//! Applying planned ref updates with `gix-ref` transactions.
+//!
+//! Transactions are committed without a committer, so the ref store must not write reflogs, like one opened with
+//! [`WriteReflog::Disable`](gix_ref::store::WriteReflog::Disable).
+
+use super::{PlannedCommand, TransactionError, TransactionMode, TransactionPlan};
+use crate::protocol::machine::RefStatus;
+use crate::protocol::CommandUpdate;
+use gix_ref::transaction::{Change, LogChange, PreviousValue, RefEdit, RefLog};
+use gix_serve_core::audit::Reason;
+
+/// Why all other commands of an atomic push fail if one of them is rejected.
+const ATOMIC_PUSH_FAILURE: &str = "atomic push failure";
+/// Why all commands of an atomic push fail if its transaction fails.
+const ATOMIC_TRANSACTION_FAILED: &str = "atomic transaction failed";
+
+/// The outcome of a command, as reported to the client.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommandResult {
+    /// The command, with the old and new object `report-status-v2` reports for its ref.
+    pub command: CommandUpdate,
+    /// `None` if the ref was updated, or why it wasn't.
+    pub error: Option<String>,
+    /// Why the update was denied if a policy or hook rejected it, as opposed to failing, for audit records.
+    pub denial: Option<Reason>,
+}
+
+impl CommandResult {
+    /// A successful `command`.
+    pub fn ok(command: CommandUpdate) -> Self {
+        Self {
+            command,
+            error: None,
+            denial: None,
+        }
+    }
+
+    /// A `command` rejected with `reason`.
+    pub fn rejected(command: CommandUpdate, reason: impl Into<String>) -> Self {
+        Self {
+            command,
+            error: Some(reason.into()),
+            denial: None,
+        }
+    }
+
+    /// A `command` rejected with `reason` as it was denied for `denial`, like by a policy or hook.
+    pub fn denied(command: CommandUpdate, reason: impl Into<String>, denial: Reason) -> Self {
+        Self {
+            denial: Some(denial),
+            ..Self::rejected(command, reason)
+        }
+    }
+
+    /// Whether the ref was updated.
+    pub fn is_ok(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+impl From<CommandResult> for RefStatus {
+    fn from(result: CommandResult) -> Self {
+        RefStatus {
+            name: result.command.name().into(),
+            error: result.error,
+            denial: result.denial,
+            extended: Vec::new(),
+        }
+    }
+}
+
+/// The outcome of executing a [`TransactionPlan`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TransactionResult {
+    /// How the plan was executed.
+    pub mode: TransactionMode,
+    /// The outcome of each command of the plan, in the order they were applied.
+    pub results: Vec<CommandResult>,
+    /// Why commands were rejected, starting with the messages of the plan, followed by why the ref store refused
+    /// updates, for logs.
+    pub messages: Vec<String>,
+}
+
+impl TransactionResult {
+    /// Whether all commands succeeded.
+    pub fn is_success(&self) -> bool {
+        self.results.iter().all(CommandResult::is_ok)
+    }
+
+    /// The commands that succeeded, in the order they were applied.
+    pub fn applied(&self) -> impl Iterator<Item = &CommandUpdate> {
+        self.results
+            .iter()
+            .filter(|result| result.is_ok())
+            .map(|result| &result.command)
+    }
+}
+
+/// Applies all updates of a plan in one transaction, so either all succeed or none do.
+///
+/// Like in `git receive-pack`, each command is checked before its update is added to the transaction, and the first
+/// one failing its check fails all others with `atomic push failure` without checking them. If the transaction
+/// fails, all commands fail with `atomic transaction failed`. Commands that were rejected while planning are the
+/// exception, as they don't fail the others.
+pub struct AtomicExecutor<'a> {
+    refs: &'a gix_ref::file::Store,
+}
+
+impl<'a> AtomicExecutor<'a> {
+    /// Apply updates to `refs`.
+    pub fn new(refs: &'a gix_ref::file::Store) -> Self {
+        Self { refs }
+    }
+
+    /// Execute `plan`, passing each pending command to `check` right before adding it to the transaction, which
+    /// returns its result if it's rejected.
+    pub fn execute(
+        &self,
+        plan: &TransactionPlan,
+        mut check: impl FnMut(&PlannedCommand) -> Result<(), CommandResult>,
+    ) -> TransactionResult {
+        let mut messages = plan.messages.clone();
+        let mut results: Vec<Option<CommandResult>> = plan.commands.iter().map(planned_result).collect();
+        let mut edits = Vec::new();
+        let mut failure = None;
+        for (index, planned) in plan.commands.iter().enumerate() {
+            if !planned.is_pending() {
+                continue;
+            }
+            let checked = check(planned).and_then(|()| {
+                edits_for(planned).map_err(|()| CommandResult::rejected(planned.command.clone(), "funny refname"))
+            });
+            match checked {
+                Ok(command_edits) => edits.extend(command_edits),
+                Err(result) => {
+                    results[index] = Some(result);
+                    failure = Some(ATOMIC_PUSH_FAILURE);
+                    break;
+                }
+            }
+        }
+
+        if failure.is_none() {
+            if !plan.conflicts.is_empty() {
+                messages.extend(plan.conflicts.iter().map(ToString::to_string));
+                failure = Some(ATOMIC_TRANSACTION_FAILED);
+            } else if let Err(err) = commit(self.refs, edits) {
+                messages.push(err.to_string());
+                failure = Some(ATOMIC_TRANSACTION_FAILED);
+            }
+        }
+
+        let results = plan
+            .commands
+            .iter()
+            .zip(results)
+            .map(|(planned, result)| match (result, failure) {
+                (Some(result), _) if !result.is_ok() => result,
+                (_, Some(failure)) => CommandResult::rejected(planned.command.clone(), failure),
+                (_, None) => CommandResult::ok(planned.command.clone()),
+            })
+            .collect();
+        TransactionResult {
+            mode: TransactionMode::Atomic,
+            results,
+            messages,
+        }
+    }
+}
+
+/// Applies each update of a plan in its own transaction, so failing updates don't affect the others.
+///
+/// Like in `git receive-pack`, each command is checked right before its update is applied, so checks see the updates
+/// of the commands before it.
+pub struct NonAtomicExecutor<'a> {
+    refs: &'a gix_ref::file::Store,
+}
+
+impl<'a> NonAtomicExecutor<'a> {
+    /// Apply updates to `refs`.
+    pub fn new(refs: &'a gix_ref::file::Store) -> Self {
+        Self { refs }
+    }
+
+    /// Execute `plan`, passing each pending command to `check` right before applying it, which returns its result
+    /// if it's rejected.
+    pub fn execute(
+        &self,
+        plan: &TransactionPlan,
+        mut check: impl FnMut(&PlannedCommand) -> Result<(), CommandResult>,
+    ) -> TransactionResult {
+        let mut messages = plan.messages.clone();
+        let results = plan
+            .commands
+            .iter()
+            .map(|planned| {
+                if let Some(result) = planned_result(planned) {
+                    return result;
+                }
+                if let Err(result) = check(planned) {
+                    return result;
+                }
+                match self.apply(planned) {
+                    Ok(()) => CommandResult::ok(planned.command.clone()),
+                    Err(None) => CommandResult::rejected(planned.command.clone(), "funny refname"),
+                    Err(Some(err)) => {
+                        messages.push(err.to_string());
+                        let reason = match planned.command {
+                            CommandUpdate::Delete { .. } => "failed to delete",
+                            CommandUpdate::Create { .. } | CommandUpdate::Update { .. } => "failed to update ref",
+                        };
+                        CommandResult::rejected(planned.command.clone(), reason)
+                    }
+                }
+            })
+            .collect();
+        TransactionResult {
+            mode: TransactionMode::NonAtomic,
+            results,
+            messages,
+        }
+    }
+
+    /// Apply the update of `planned` in its own transaction, failing with `None` if its ref name isn't valid.
+    fn apply(&self, planned: &PlannedCommand) -> Result<(), Option<TransactionError>> {
+        let edits = edits_for(planned).map_err(|()| None)?;
+        commit(self.refs, edits).map_err(Some)
+    }
+}
+
+/// The result of `planned` known from planning, if it's not pending.
+fn planned_result(planned: &PlannedCommand) -> Option<CommandResult> {
+    match &planned.rejection {
+        Some(rejection) => Some(CommandResult::rejected(planned.command.clone(), rejection.clone())),
+        None if planned.skip => Some(CommandResult::ok(planned.command.clone())),
+        None => None,
+    }
+}
+
+/// The edits applying `planned`, or `Err` if its ref names aren't valid.
+///
+/// Updates through a symbolic ref change its target, while deleting a symbolic ref deletes it along with its target
+/// like git does.
+fn edits_for(planned: &PlannedCommand) -> Result<Vec<RefEdit>, ()> {
+    let name = gix_ref::FullName::try_from(planned.effective_name()).map_err(|_| ())?;
+    let (old, new) = planned.command.old_and_new();
+    let expected = match old {
+        Some(old) => PreviousValue::MustExistAndMatch(gix_ref::Target::Object(old)),
+        None => PreviousValue::MustNotExist,
+    };
+    let change = match new {
+        Some(new) => Change::Update {
+            log: LogChange {
+                mode: RefLog::AndReference,
+                force_create_reflog: false,
+                message: "push".into(),
+            },
+            expected,
+            new: gix_ref::Target::Object(new),
+        },
+        None => Change::Delete {
+            expected,
+            log: RefLog::AndReference,
+        },
+    };
+    let mut edits = vec![RefEdit {
+        change,
+        name,
+        deref: false,
+    }];
+    if let (Some(_), None) = (&planned.target, new) {
+        edits.push(RefEdit {
+            change: Change::Delete {
+                expected: PreviousValue::MustExist,
+                log: RefLog::AndReference,
+            },
+            name: gix_ref::FullName::try_from(planned.command.name()).map_err(|_| ())?,
+            deref: false,
+        });
+    }
+    Ok(edits)
+}
+
+/// Apply `edits` to `refs` in one transaction.
+fn commit(refs: &gix_ref::file::Store, edits: Vec<RefEdit>) -> Result<(), TransactionError> {
+    if edits.is_empty() {
+        return Ok(());
+    }
+    refs.transaction()
+        .prepare(edits, Default::default(), Default::default())?
+        .commit(None)?;
+    Ok(())
+}
@@ -0,0 +1,75 @@
+//! Applying the commands of a push to the refs of the repository.
+//!
+//! A [`TransactionPlanner`] turns the commands into a [`TransactionPlan`], ordered so deletions make room for the
+//! refs created in their place, with symbolic refs resolved by the [`SymrefResolver`]. Like `git receive-pack`, the
+//! [`AliasValidator`] rejects commands updating a ref both directly and through a symbolic ref to different values,
+//! and lets a symbolic ref updated consistently with its target go along with it.
+//!
+//! Plans are executed by one of two executors, which produce a [`CommandResult`] for each command as reported to
+//! the client:
+//!
+//! - [`AtomicExecutor`] applies all updates in one transaction when the client asked for `atomic`, so all succeed or
+//!   none do. The [`ConflictDetector`] finds updates that can't be applied together beforehand, like a ref and one
+//!   below it, as they would fail the transaction only once some refs were written.
+//! - [`NonAtomicExecutor`] applies each update in its own transaction, so failing ones don't affect the others.
+//!
+//! Each command is checked right before it's applied, which is where policies and the `update` hook come in. A
+//! failed check in an atomic push fails all other commands as well, while in a non-atomic push it only rejects its
+//! own command.
+
+pub mod alias;
+pub mod conflict;
+pub mod execute;
+pub mod plan;
+
+pub use alias::{AliasValidator, SymrefResolver};
+pub use conflict::{Conflict, ConflictDetector, ConflictType};
+pub use execute::{AtomicExecutor, CommandResult, NonAtomicExecutor, TransactionResult};
+pub use plan::{PhaseType, PlannedCommand, TransactionMode, TransactionPlan, TransactionPlanner};
+
+/// The error returned when refs can't be read or updated.
+#[derive(Debug, thiserror::Error)]
+pub enum TransactionError {
+    /// The ref couldn't be read.
+    #[error("failed to read ref {name}")]
+    Find {
+        name: String,
+        #[source]
+        source: gix_ref::file::find::Error,
+    },
+    /// The refs below `prefix` couldn't be listed.
+    #[error("failed to list the refs below {prefix}")]
+    List {
+        prefix: String,
+        #[source]
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+    /// The symbolic ref points to symbolic refs too deeply nested to resolve, or to itself.
+    #[error("symbolic ref {name} can't be resolved")]
+    BrokenSymref { name: String },
+    /// The transaction couldn't be prepared, like when a ref is locked or doesn't have its expected value.
+    #[error(transparent)]
+    Prepare(#[from] gix_ref::file::transaction::prepare::Error),
+    /// The prepared transaction couldn't be committed.
+    #[error(transparent)]
+    Commit(#[from] gix_ref::file::transaction::commit::Error),
+}
+
+/// The ref `name` in `refs`, or `None` if it doesn't exist.
+///
+/// Like in git, a ref can't exist below another one, like `refs/heads/a/b` if `refs/heads/a` does, rather than
+/// failing the lookup.
+fn find(refs: &gix_ref::file::Store, name: &gix_ref::FullName) -> Result<Option<gix_ref::Reference>, TransactionError> {
+    match refs.try_find(name.as_ref()) {
+        Ok(reference) => Ok(reference.filter(|reference| reference.name == *name)),
+        Err(gix_ref::file::find::Error::ReadFileContents { source, .. })
+            if source.kind() == std::io::ErrorKind::NotADirectory =>
+        {
+            Ok(None)
+        }
+        Err(source) => Err(TransactionError::Find {
+            name: name.as_bstr().to_string(),
+            source,
+        }),
+    }
+}
@@ -0,0 +1,146 @@
+//! Planning the ref updates of a push.
+
+use super::{AliasValidator, Conflict, ConflictDetector, SymrefResolver, TransactionError};
+use crate::protocol::{CommandList, CommandUpdate, Options};
+
+/// How the ref updates of a push are applied.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum TransactionMode {
+    /// All updates are applied in one transaction, so either all succeed or none do.
+    Atomic,
+    /// Each update is applied in its own transaction, so failing ones don't affect the others.
+    #[default]
+    NonAtomic,
+}
+
+impl TransactionMode {
+    /// The mode the client asked for in the negotiated `options`, atomic if it sent the `atomic` capability.
+    pub fn from_options(options: &Options) -> Self {
+        if options.has("atomic") {
+            TransactionMode::Atomic
+        } else {
+            TransactionMode::NonAtomic
+        }
+    }
+}
+
+/// The phase a command is applied in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum PhaseType {
+    /// Deletions come first, so they make room for refs created in their place.
+    Delete,
+    /// Creations and updates follow.
+    Update,
+}
+
+/// A command of a push as it's going to be applied.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlannedCommand {
+    /// The command as the client sent it.
+    pub command: CommandUpdate,
+    /// The phase it's applied in.
+    pub phase: PhaseType,
+    /// The ref the symbolic ref of the command points to, which is updated instead of it, or `None` if its ref isn't
+    /// symbolic.
+    pub target: Option<String>,
+    /// Whether the command isn't applied by itself as it updates a symbolic ref just like the command updating its
+    /// target, so it succeeds along with that one.
+    pub skip: bool,
+    /// Why the command is rejected before it's applied, like for an inconsistent aliased update.
+    pub rejection: Option<String>,
+}
+
+impl PlannedCommand {
+    /// Plan to apply `command` to the ref of its own.
+    pub fn new(command: CommandUpdate) -> Self {
+        let phase = match command {
+            CommandUpdate::Delete { .. } => PhaseType::Delete,
+            CommandUpdate::Create { .. } | CommandUpdate::Update { .. } => PhaseType::Update,
+        };
+        Self {
+            command,
+            phase,
+            target: None,
+            skip: false,
+            rejection: None,
+        }
+    }
+
+    /// The name of the ref that is changed when the command is applied, its target if its ref is symbolic.
+    pub fn effective_name(&self) -> &str {
+        self.target.as_deref().unwrap_or(self.command.name())
+    }
+
+    /// Whether the command still has to be applied, as it's neither skipped nor rejected.
+    pub fn is_pending(&self) -> bool {
+        !self.skip && self.rejection.is_none()
+    }
+}
+
+/// The ref updates of a push, in the order they are applied.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TransactionPlan {
+    /// How the updates are applied.
+    pub mode: TransactionMode,
+    /// All commands of the push in the order they are applied, deletions first.
+    pub commands: Vec<PlannedCommand>,
+    /// The updates of an atomic plan that can't be applied together, which fail the transaction. Non-atomic plans
+    /// are applied one update after another, so the ref store decides which of them fail.
+    pub conflicts: Vec<Conflict>,
+    /// What was wrong with the rejected commands, in the words of `git receive-pack`, for logs.
+    pub messages: Vec<String>,
+}
+
+impl TransactionPlan {
+    /// The commands that still have to be applied, in order.
+    pub fn pending(&self) -> impl Iterator<Item = &PlannedCommand> {
+        self.commands.iter().filter(|planned| planned.is_pending())
+    }
+}
+
+/// Plans how to apply the commands of a push to the refs of a repository.
+pub struct TransactionPlanner<'a> {
+    resolver: SymrefResolver<'a>,
+    mode: TransactionMode,
+}
+
+impl<'a> TransactionPlanner<'a> {
+    /// Plan updates of `refs` in `mode`.
+    pub fn new(refs: &'a gix_ref::file::Store, mode: TransactionMode) -> Self {
+        Self {
+            resolver: SymrefResolver::new(refs),
+            mode,
+        }
+    }
+
+    /// Plan to apply `commands` in their [application order](CommandList::application_order()), resolving
+    /// symbolic refs and validating aliased updates.
+    pub fn plan(&self, commands: &CommandList) -> Result<TransactionPlan, TransactionError> {
+        let ordered = commands.application_order(|name| self.resolver.target(name).ok().flatten());
+        let mut planned = Vec::with_capacity(ordered.len());
+        let mut messages = Vec::new();
+        for command in ordered {
+            let mut command = PlannedCommand::new(command.clone());
+            match self.resolver.resolve(command.command.name()) {
+                Ok(target) => command.target = target,
+                Err(TransactionError::BrokenSymref { name }) => {
+                    messages.push(format!("refusing update to broken symref '{name}'"));
+                    command.rejection = Some("broken symref".into());
+                }
+                Err(err) => return Err(err),
+            }
+            planned.push(command);
+        }
+        messages.extend(AliasValidator::new().validate(&mut planned));
+        let conflicts = match self.mode {
+            TransactionMode::Atomic => ConflictDetector::new(self.resolver.refs()).detect(&planned)?,
+            TransactionMode::NonAtomic => Vec::new(),
+        };
+        Ok(TransactionPlan {
+            mode: self.mode,
+            commands: planned,
+            conflicts,
+            messages,
+        })
+    }
+}
@@ -0,0 +1,585 @@
+//! Updating the worktree when its checked-out branch is pushed to.
+//!
+//! With `receive.denyCurrentBranch=updateInstead`, the policy doesn't refuse updates of the current branch but
+//! delegates them as [`UpdateInstead`]. [`WorktreeUpdater`] then does what `git receive-pack` does without a
+//! `push-to-checkout` hook: it refuses unless the worktree and index are clean, and otherwise checks out the new
+//! commit like `git read-tree -u -m <new>`, touching only the files that changed. The refusals carry git's messages,
+//! so clients see the same `ng` reasons.
+//!
+//! Submodules are never entered, just like git doesn't without `--recurse-submodules`:
+//!
+//! - Changes inside them, like another commit checked out or modified files, don't make the worktree unclean, as
+//!   git checks with `--ignore-submodules`.
+//! - The index records the commit pushed for each submodule, but its directory and content are left untouched.
+//! - New submodules get an empty directory, and directories of removed submodules are only removed if empty.
+//!
+//! Each submodule whose recorded commit changed is listed in the [`WorktreeUpdate`], so it can be reported that
+//! its checkout may now be behind.
+
+use crate::policy::UpdateInstead;
+use crate::Error;
+use gix_hash::ObjectId;
+use gix_index::entry::{Mode, Stat};
+use gix_object::bstr::{BStr, BString, ByteSlice};
+use gix_object::FindExt;
+use std::path::{Path, PathBuf};
+
+/// Why the worktree wasn't updated, with the messages `git receive-pack` reports for the ref.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Refusal {
+    /// The repository is bare, so there is no worktree to update.
+    NoWorktree,
+    /// The index couldn't be read to check the worktree.
+    UpToDateCheckFailed,
+    /// Tracked files in the worktree differ from the index.
+    UnstagedChanges,
+    /// The index differs from the commit that is checked out.
+    StagedChanges,
+    /// Checking out the new commit failed, possibly after some files were updated.
+    CheckoutFailed,
+}
+
+impl std::fmt::Display for Refusal {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Refusal::NoWorktree => "denyCurrentBranch = updateInstead needs a worktree",
+            Refusal::UpToDateCheckFailed => "Up-to-date check failed",
+            Refusal::UnstagedChanges => "Working directory has unstaged changes",
+            Refusal::StagedChanges => "Working directory has staged changes",
+            Refusal::CheckoutFailed => "Could not update working tree to new HEAD",
+        })
+    }
+}
+
+/// What happened to the directory of a submodule whose recorded commit changed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SubmoduleDirectory {
+    /// The directory was left as it was, including whatever is checked out in it.
+    Untouched,
+    /// The submodule is new, so an empty directory was created for it to be initialized in.
+    Created,
+    /// The submodule was removed and its directory was empty, so it was removed as well.
+    Removed,
+    /// The submodule was removed, but its directory wasn't empty and was left in place.
+    LeftInPlace,
+}
+
+/// A submodule whose recorded commit was changed by the update.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SubmoduleChange {
+    /// The path of the submodule relative to the worktree.
+    pub path: BString,
+    /// The commit recorded for it before, or `None` if it was added.
+    pub previous: Option<ObjectId>,
+    /// The commit recorded for it now, or `None` if it was removed.
+    pub current: Option<ObjectId>,
+    /// What happened to its directory.
+    pub directory: SubmoduleDirectory,
+}
+
+/// The result of updating the worktree.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WorktreeUpdate {
+    /// The number of files and symlinks written.
+    pub files_written: usize,
+    /// The number of files and symlinks removed.
+    pub files_removed: usize,
+    /// The submodules whose recorded commit changed, in path order.
+    pub submodules: Vec<SubmoduleChange>,
+}
+
+impl WorktreeUpdate {
+    /// Return the warnings git prints while updating the worktree, one line each without the `warning: ` prefix.
+    pub fn warnings(&self) -> Vec<String> {
+        self.submodules
+            .iter()
+            .filter(|submodule| submodule.directory == SubmoduleDirectory::LeftInPlace)
+            .map(|submodule| format!("unable to rmdir '{}': Directory not empty", submodule.path))
+            .collect()
+    }
+}
+
+/// Checks out pushed commits of the current branch in the worktree of a repository.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WorktreeUpdater {
+    work_tree: PathBuf,
+    index_path: PathBuf,
+    object_hash: gix_hash::Kind,
+}
+
+impl WorktreeUpdater {
+    /// Update the worktree at `work_tree` along with the index of the repository at `git_dir`.
+    pub fn new(work_tree: impl Into<PathBuf>, git_dir: &Path) -> Self {
+        Self {
+            work_tree: work_tree.into(),
+            index_path: git_dir.join("index"),
+            object_hash: gix_hash::Kind::Sha1,
+        }
+    }
+
+    /// Locate the worktree of the repository at `git_dir` from its `config` like git does.
+    ///
+    /// That's `core.worktree` relative to `git_dir` if set, and the parent directory of `git_dir` otherwise. Bare
+    /// repositories are refused with [`Refusal::NoWorktree`].
+    pub fn from_config(git_dir: &Path, config: &gix_config::File<'static>) -> Result<Self, Error> {
+        use crate::config::keys::core;
+        if core::BARE.get(config)? == Some(true) {
+            return Err(Error::WorktreeRefused(Refusal::NoWorktree));
+        }
+        let work_tree = match core::WORKTREE.get(config)? {
+            Some(path) => git_dir.join(gix_path::from_bstring(path)),
+            None => git_dir
+                .parent()
+                .ok_or(Error::WorktreeRefused(Refusal::NoWorktree))?
+                .to_owned(),
+        };
+        Ok(Self::new(work_tree, git_dir))
+    }
+
+    /// Use `object_hash` for the index instead of SHA-1.
+    pub fn with_object_hash(mut self, object_hash: gix_hash::Kind) -> Self {
+        self.object_hash = object_hash;
+        self
+    }
+
+    /// The worktree that is updated.
+    pub fn work_tree(&self) -> &Path {
+        &self.work_tree
+    }
+
+    /// Check out the new commit of `update` with `objects`, if the worktree is clean.
+    ///
+    /// Call this before the ref is updated, and refuse the ref update with the returned error if it fails, as git
+    /// does.
+    pub fn update(&self, update: &UpdateInstead, objects: &impl gix_object::Find) -> Result<WorktreeUpdate, Error> {
+        let mut index = gix_index::File::at_or_default(&self.index_path, self.object_hash, false, Default::default())
+            .map_err(refused(Refusal::UpToDateCheckFailed))?;
+        if self
+            .has_unstaged_changes(&mut index)
+            .map_err(refused(Refusal::UpToDateCheckFailed))?
+        {
+            return Err(Error::WorktreeRefused(Refusal::UnstagedChanges));
+        }
+        let head = tree_of(objects, update.old_oid, self.object_hash).map_err(refused(Refusal::StagedChanges))?;
+        if has_staged_changes(&index, &head) {
+            return Err(Error::WorktreeRefused(Refusal::StagedChanges));
+        }
+
+        let new = tree_of(objects, update.new_oid, self.object_hash).map_err(refused(Refusal::CheckoutFailed))?;
+        let mut out = WorktreeUpdate::default();
+        let new = self
+            .check_out(&index, new, objects, &mut out)
+            .map_err(refused(Refusal::CheckoutFailed))?;
+        gix_index::File::from_state(new, &self.index_path)
+            .write(Default::default())
+            .map_err(refused(Refusal::CheckoutFailed))?;
+        Ok(out)
+    }
+
+    /// Return `true` if a tracked file differs from its entry in `index`, refreshing the stat of unchanged entries.
+    fn has_unstaged_changes(&self, index: &mut gix_index::File) -> std::io::Result<bool> {
+        let timestamp = index.timestamp();
+        let options = Default::default();
+        let mut buf = Vec::new();
+        for (entry, path) in index.entries_mut_with_paths() {
+            if entry.mode.is_submodule() {
+                continue;
+            }
+            if entry.stage_raw() != 0 {
+                return Ok(true);
+            }
+            let racy = entry.stat.is_racy(timestamp, options);
+            match worktree_stat(&self.work_tree, path, entry, racy, &mut buf)? {
+                Some(stat) => entry.stat = stat,
+                None => return Ok(true),
+            }
+        }
+        Ok(false)
+    }
+
+    /// Remove the entries of `old` that aren't in `new` from the worktree and write those that are new or changed,
+    /// returning `new` with the stat of each file.
+    fn check_out(
+        &self,
+        old: &gix_index::State,
+        mut new: gix_index::State,
+        objects: &impl gix_object::Find,
+        out: &mut WorktreeUpdate,
+    ) -> std::io::Result<gix_index::State> {
+        for entry in old.entries() {
+            let path = entry.path(old);
+            if new.entry_by_path(path).is_some() {
+                continue;
+            }
+            let location = self.work_tree.join(gix_path::from_bstr(path));
+            if entry.mode.is_submodule() {
+                let directory = match std::fs::remove_dir(&location) {
+                    Ok(()) => SubmoduleDirectory::Removed,
+                    Err(err) if err.kind() == std::io::ErrorKind::NotFound => SubmoduleDirectory::Removed,
+                    Err(_) => SubmoduleDirectory::LeftInPlace,
+                };
+                out.submodules.push(SubmoduleChange {
+                    path: path.to_owned(),
+                    previous: Some(entry.id),
+                    current: None,
+                    directory,
+                });
+            } else {
+                match std::fs::remove_file(&location) {
+                    Err(err) if err.kind() != std::io::ErrorKind::NotFound => return Err(err),
+                    _ => out.files_removed += 1,
+                }
+            }
+            remove_empty_parents(&self.work_tree, &location);
+        }
+
+        let mut stack = gix_worktree::Stack::new(
+            &self.work_tree,
+            gix_worktree::stack::State::for_checkout(false, Default::default(), Default::default()),
+            gix_worktree::glob::pattern::Case::Sensitive,
+            Vec::new(),
+            Vec::new(),
+        );
+        let mut buf = Vec::new();
+        let (entries, paths) = new.entries_mut_and_pathbacking();
+        for entry in entries {
+            let path = entry.path_in(paths);
+            let previous = old.entry_by_path(path);
+            let location = self.work_tree.join(gix_path::from_bstr(path));
+            if entry.mode.is_submodule() {
+                if previous.is_some_and(|previous| !previous.mode.is_submodule()) {
+                    std::fs::remove_file(&location)?;
+                    out.files_removed += 1;
+                }
+                let previous = previous
+                    .filter(|previous| previous.mode.is_submodule())
+                    .map(|previous| previous.id);
+                let existed = location.is_dir();
+                // Creates the directory of the submodule, if it's missing
+                let _ = stack.at_entry(path, Some(entry.mode), objects)?;
+                if previous != Some(entry.id) {
+                    out.submodules.push(SubmoduleChange {
+                        path: path.to_owned(),
+                        previous,
+                        current: Some(entry.id),
+                        directory: if existed {
+                            SubmoduleDirectory::Untouched
+                        } else {
+                            SubmoduleDirectory::Created
+                        },
+                    });
+                }
+                continue;
+            }
+            if let Some(previous) = previous.filter(|previous| previous.id == entry.id && previous.mode == entry.mode) {
+                entry.stat = previous.stat;
+                continue;
+            }
+            let location = stack.at_entry(path, Some(entry.mode), objects)?.path().to_owned();
+            let blob = objects.find_blob(&entry.id, &mut buf).map_err(std::io::Error::other)?;
+            write_file(&location, entry.mode, blob.data)?;
+            entry.stat = stat_of(&location)?;
+            out.files_written += 1;
+            if let Some(previous) = previous.filter(|previous| previous.mode.is_submodule()) {
+                out.submodules.push(SubmoduleChange {
+                    path: path.to_owned(),
+                    previous: Some(previous.id),
+                    current: None,
+                    directory: SubmoduleDirectory::Removed,
+                });
+            }
+        }
+        out.submodules.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok(new)
+    }
+}
+
+/// Map any error to the refusal of the update for `refusal`.
+fn refused<E>(refusal: Refusal) -> impl FnOnce(E) -> Error {
+    move |_| Error::WorktreeRefused(refusal)
+}
+
+/// Return the index of the tree of `commit`, or an empty one if it's null.
+fn tree_of(
+    objects: &impl gix_object::Find,
+    commit: ObjectId,
+    object_hash: gix_hash::Kind,
+) -> Result<gix_index::State, Box<dyn std::error::Error + Send + Sync>> {
+    if commit.is_null() {
+        return Ok(gix_index::State::new(object_hash));
+    }
+    let mut buf = Vec::new();
+    let tree = objects.find_commit(&commit, &mut buf)?.tree();
+    Ok(gix_index::State::from_tree(&tree, objects, Default::default())?)
+}
+
+/// Return `true` if `index` differs from `head` in anything but submodules.
+fn has_staged_changes(index: &gix_index::State, head: &gix_index::State) -> bool {
+    let tracked = |state: &gix_index::State| -> Vec<(BString, Mode, ObjectId)> {
+        state
+            .entries()
+            .iter()
+            .filter(|entry| !entry.mode.is_submodule())
+            .map(|entry| (entry.path(state).to_owned(), entry.mode, entry.id))
+            .collect()
+    };
+    tracked(index) != tracked(head)
+}
+
+/// Return the stat of the file of `entry` at `path` if it is unchanged, or `None` if it was changed or removed.
+///
+/// Files whose stat differs from the index are hashed to compare their content, as are `racy` ones which may have
+/// changed within the same second the index was written.
+fn worktree_stat(
+    work_tree: &Path,
+    path: &BStr,
+    entry: &gix_index::Entry,
+    racy: bool,
+    buf: &mut Vec<u8>,
+) -> std::io::Result<Option<Stat>> {
+    let location = work_tree.join(gix_path::from_bstr(path));
+    let metadata = match gix_index::fs::Metadata::from_path_no_follow(&location) {
+        Ok(metadata) => metadata,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err),
+    };
+    if entry.mode.change_to_match_fs(&metadata, true, true).is_some() {
+        return Ok(None);
+    }
+    let stat = Stat::from_fs(&metadata).map_err(std::io::Error::other)?;
+    if !racy && entry.stat.matches(&stat, Default::default()) {
+        return Ok(Some(stat));
+    }
+    buf.clear();
+    if entry.mode == Mode::SYMLINK {
+        buf.extend_from_slice(gix_path::into_bstr(std::fs::read_link(&location)?).as_ref());
+    } else {
+        buf.extend_from_slice(&std::fs::read(&location)?);
+    }
+    let id = gix_object::compute_hash(entry.id.kind(), gix_object::Kind::Blob, buf).map_err(std::io::Error::other)?;
+    Ok((id == entry.id).then_some(stat))
+}
+
+/// Replace whatever is at `location` with a file or symlink of `mode` and `data`.
+fn write_file(location: &Path, mode: Mode, data: &[u8]) -> std::io::Result<()> {
+    match std::fs::symlink_metadata(location) {
+        Ok(metadata) if metadata.is_dir() => std::fs::remove_dir(location)?,
+        Ok(_) => std::fs::remove_file(location)?,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+        Err(err) => return Err(err),
+    }
+    #[cfg(unix)]
+    if mode == Mode::SYMLINK {
+        return std::os::unix::fs::symlink(gix_path::from_bstr(data.as_bstr()), location);
+    }
+    std::fs::write(location, data)?;
+    #[cfg(unix)]
+    if mode == Mode::FILE_EXECUTABLE {
+        use std::os::unix::fs::PermissionsExt;
+        let mut permissions = std::fs::metadata(location)?.permissions();
+        permissions.set_mode(permissions.mode() | 0o111);
+        std::fs::set_permissions(location, permissions)?;
+    }
+    Ok(())
+}
+
+fn stat_of(location: &Path) -> std::io::Result<Stat> {
+    Stat::from_fs(&gix_index::fs::Metadata::from_path_no_follow(location)?).map_err(std::io::Error::other)
+}
+
+/// Remove the directories leading to `location` that are empty now, up to but excluding `work_tree`.
+fn remove_empty_parents(work_tree: &Path, location: &Path) {
+    let mut directory = location.parent();
+    while let Some(dir) = directory.filter(|dir| *dir != work_tree && dir.starts_with(work_tree)) {
+        if std::fs::remove_dir(dir).is_err() {
+            break;
+        }
+        directory = dir.parent();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::Command;
+
+    fn git(dir: &Path, args: &[&str]) -> String {
+        let output = Command::new("git")
+            .args(args)
+            .current_dir(dir)
+            .env("GIT_AUTHOR_NAME", "author")
+            .env("GIT_AUTHOR_EMAIL", "author@example.com")
+            .env("GIT_COMMITTER_NAME", "committer")
+            .env("GIT_COMMITTER_EMAIL", "committer@example.com")
+            .env("GIT_ALLOW_PROTOCOL", "file")
+            .output()
+            .expect("git is installed");
+        assert!(output.status.success(), "git {args:?} failed");
+        String::from_utf8(output.stdout).unwrap().trim().to_owned()
+    }
+
+    fn id(hex: String) -> ObjectId {
+        ObjectId::from_hex(hex.as_bytes()).unwrap()
+    }
+
+    /// Create `super` with the submodules `sub` and `gone` checked out, and `work` with a commit that changes `a`,
+    /// adds `dir/b`, moves `sub` to a newer commit, removes `gone` and adds the submodule `new`.
+    fn superproject(root: &Path) -> (ObjectId, ObjectId) {
+        let lib = root.join("lib");
+        std::fs::create_dir(&lib).unwrap();
+        git(&lib, &["init", "--quiet", "--initial-branch=main"]);
+        git(&lib, &["commit", "--quiet", "--allow-empty", "-m", "first"]);
+        let first = git(&lib, &["rev-parse", "HEAD"]);
+        std::fs::write(lib.join("f"), "f\n").unwrap();
+        git(&lib, &["add", "f"]);
+        git(&lib, &["commit", "--quiet", "-m", "second"]);
+        let second = git(&lib, &["rev-parse", "HEAD"]);
+
+        let superproject = root.join("super");
+        std::fs::create_dir(&superproject).unwrap();
+        git(&superproject, &["init", "--quiet", "--initial-branch=main"]);
+        std::fs::write(superproject.join("a"), "a\n").unwrap();
+        git(&superproject, &["add", "a"]);
+        for name in ["sub", "gone"] {
+            git(&superproject, &["submodule", "add", "--quiet", "../lib", name]);
+            git(&superproject.join(name), &["checkout", "--quiet", &first]);
+            git(&superproject, &["add", name]);
+        }
+        git(&superproject, &["commit", "--quiet", "-m", "initial"]);
+        let old = id(git(&superproject, &["rev-parse", "HEAD"]));
+
+        git(root, &["clone", "--quiet", "super", "work"]);
+        let work = root.join("work");
+        std::fs::write(work.join("a"), "changed\n").unwrap();
+        std::fs::create_dir(work.join("dir")).unwrap();
+        std::fs::write(work.join("dir/b"), "b\n").unwrap();
+        git(&work, &["add", "a", "dir"]);
+        git(&work, &["update-index", "--cacheinfo", &format!("160000,{second},sub")]);
+        git(&work, &["rm", "--quiet", "--cached", "gone"]);
+        git(
+            &work,
+            &["update-index", "--add", "--cacheinfo", &format!("160000,{first},new")],
+        );
+        git(&work, &["commit", "--quiet", "-m", "next"]);
+        (old, id(git(&work, &["rev-parse", "HEAD"])))
+    }
+
+    fn copy(from: &Path, to: &Path) {
+        let status = Command::new("cp").arg("-a").arg(from).arg(to).status().unwrap();
+        assert!(status.success());
+    }
+
+    fn main_update(old: ObjectId, new: ObjectId) -> UpdateInstead {
+        UpdateInstead {
+            refname: "refs/heads/main".into(),
+            old_oid: old,
+            new_oid: new,
+        }
+    }
+
+    #[test]
+    fn submodules_are_left_alone_like_git_does() {
+        let tmp = gix_testtools::tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        let (old, new) = superproject(root);
+        std::fs::write(root.join("super/sub/f"), "modified in the submodule\n").unwrap();
+        copy(&root.join("super"), &root.join("native"));
+        copy(&root.join("super"), &root.join("ours"));
+
+        git(
+            &root.join("native"),
+            &["config", "receive.denyCurrentBranch", "updateInstead"],
+        );
+        git(&root.join("work"), &["push", "--quiet", "../native", "main"]);
+
+        let ours = root.join("ours");
+        git(&ours, &["fetch", "--quiet", "../work", "main"]);
+        let config =
+            gix_config::File::from_path_no_includes(ours.join(".git/config"), gix_config::Source::Local).unwrap();
+        let updater = WorktreeUpdater::from_config(&ours.join(".git"), &config).unwrap();
+        assert_eq!(updater.work_tree(), ours);
+        let odb = gix_odb::at(ours.join(".git/objects")).unwrap();
+        let outcome = updater.update(&main_update(old, new), &odb).unwrap();
+        git(&ours, &["update-ref", "refs/heads/main", &new.to_string()]);
+
+        for dir in [root.join("native"), ours.clone()] {
+            assert_eq!(std::fs::read_to_string(dir.join("a")).unwrap(), "changed\n");
+            assert_eq!(std::fs::read_to_string(dir.join("dir/b")).unwrap(), "b\n");
+            assert_eq!(
+                std::fs::read_to_string(dir.join("sub/f")).unwrap(),
+                "modified in the submodule\n",
+                "the checkout of the submodule is left alone"
+            );
+            assert!(dir.join("gone/.git").is_file(), "populated submodules aren't removed");
+            assert_eq!(std::fs::read_dir(dir.join("new")).unwrap().count(), 0);
+        }
+        assert_eq!(
+            git(&ours, &["ls-files", "--stage"]),
+            git(&root.join("native"), &["ls-files", "--stage"])
+        );
+        let status = git(&ours, &["status", "--porcelain"]);
+        assert_eq!(status, git(&root.join("native"), &["status", "--porcelain"]));
+        assert!(status.contains("?? gone/"), "{status}");
+
+        assert_eq!(outcome.files_written, 2, "a and dir/b");
+        assert_eq!(outcome.files_removed, 0);
+        let directories: Vec<_> = outcome
+            .submodules
+            .iter()
+            .map(|submodule| (submodule.path.to_string(), submodule.directory))
+            .collect();
+        assert_eq!(
+            directories,
+            [
+                ("gone".to_string(), SubmoduleDirectory::LeftInPlace),
+                ("new".to_string(), SubmoduleDirectory::Created),
+                ("sub".to_string(), SubmoduleDirectory::Untouched),
+            ]
+        );
+        assert_eq!(
+            outcome.submodules[2].current,
+            Some(id(git(&root.join("lib"), &["rev-parse", "HEAD"])))
+        );
+        assert_eq!(outcome.warnings(), ["unable to rmdir 'gone': Directory not empty"]);
+    }
+
+    #[test]
+    fn unclean_worktrees_are_refused_with_the_messages_of_git() {
+        let tmp = gix_testtools::tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        let (old, new) = superproject(root);
+        let dir = root.join("super");
+        git(&dir, &["fetch", "--quiet", "../work", "main"]);
+        let odb = gix_odb::at(dir.join(".git/objects")).unwrap();
+        let updater = WorktreeUpdater::new(&dir, &dir.join(".git"));
+        let refusal = |updater: &WorktreeUpdater| match updater.update(&main_update(old, new), &odb) {
+            Err(Error::WorktreeRefused(refusal)) => refusal.to_string(),
+            other => panic!("expected a refusal, got {other:?}"),
+        };
+
+        std::fs::write(dir.join("a"), "unstaged\n").unwrap();
+        assert_eq!(refusal(&updater), "Working directory has unstaged changes");
+        git(&dir, &["add", "a"]);
+        assert_eq!(refusal(&updater), "Working directory has staged changes");
+        git(&dir, &["reset", "--quiet", "--hard"]);
+        assert_eq!(git(&dir, &["status", "--porcelain"]), "");
+
+        let config: gix_config::File<'static> = "[core]\n\tbare = true\n".parse().unwrap();
+        assert!(matches!(
+            WorktreeUpdater::from_config(&dir.join(".git"), &config),
+            Err(Error::WorktreeRefused(Refusal::NoWorktree))
+        ));
+        assert_eq!(
+            Refusal::NoWorktree.to_string(),
+            "denyCurrentBranch = updateInstead needs a worktree"
+        );
+
+        std::fs::remove_dir_all(dir.join("sub")).unwrap();
+        updater.update(&main_update(old, new), &odb).unwrap();
+        assert!(
+            dir.join("sub").is_dir(),
+            "missing submodule directories are recreated, but not populated"
+        );
+        assert_eq!(std::fs::read_dir(dir.join("sub")).unwrap().count(), 0);
+    }
+}
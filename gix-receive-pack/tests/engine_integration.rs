@@ -140,7 +140,7 @@ fn test_ingest_pack_from_reader_success() {
     );
     
     match result {
-        Ok(()) => {
+        Ok(_) => {
             // Verify that pack files were created in the main objects directory
             let main_pack_dir = objects_dir.join("pack");
             assert!(
@@ -324,7 +324,7 @@ fn test_ingest_pack_from_reader_with_sideband_success() {
     );
     
     match result {
-        Ok(()) => {
+        Ok(_) => {
             // Verify that pack files were created in the main objects directory
             let main_pack_dir = objects_dir.join("pack");
             assert!(
@@ -431,7 +431,7 @@ fn test_quarantine_lifecycle_direct() {
     let objects_dir = create_temp_objects_dir().expect("Failed to create temp objects dir");
     
     // Test quarantine activation
-    let mut quarantine = Quarantine::new(&objects_dir);
+    let mut quarantine = Quarantine::new(objects_dir.clone());
     assert!(quarantine.activate().is_ok(), "Quarantine activation should succeed");
     
     // Verify quarantine structure was created
@@ -489,7 +489,7 @@ fn test_quarantine_failure_cleanup() {
     
     let objects_dir = create_temp_objects_dir().expect("Failed to create temp objects dir");
     
-    let mut quarantine = Quarantine::new(&objects_dir);
+    let mut quarantine = Quarantine::new(objects_dir.clone());
     assert!(quarantine.activate().is_ok(), "Quarantine activation should succeed");
     
     // Create test files in quarantine
@@ -590,6 +590,142 @@ fn test_configuration_validation() {
     }
 }
 
+/// Test ingesting a pack that already lives on disk, and that size limits apply to it as well.
+#[cfg(feature = "progress")]
+#[test]
+fn test_ingest_pack_file_from_disk() {
+    use gix_features::progress::Discard;
+    use test_utils::*;
+
+    let fixture_dir = scripted_fixture_read_only("pack-ingestion-test.sh")
+        .expect("pack ingestion fixture script should run");
+    let pack_path = fixture_dir.join("test-pack.pack");
+    let objects_dir = create_temp_objects_dir().expect("Failed to create temp objects dir");
+
+    let too_small = ReceivePackBuilder::new()
+        .blocking()
+        .with_objects_dir(&objects_dir)
+        .with_max_pack_bytes(Some(16))
+        .build();
+    let err = too_small
+        .ingest_pack_file(&pack_path, &mut Discard)
+        .expect_err("pack exceeds the configured size limit");
+    assert!(matches!(err, Error::Resource(_)), "got {err:?}");
+    assert_eq!(count_pack_files(&objects_dir.join("pack")), 0);
+
+    let receive_pack = ReceivePackBuilder::new()
+        .blocking()
+        .with_objects_dir(&objects_dir)
+        .build();
+    let outcome = receive_pack
+        .ingest_pack_file(&pack_path, &mut Discard)
+        .expect("on-disk pack can be ingested");
+    assert_eq!(
+        outcome.ingest_path,
+        gix_receive_pack::pack::PackIngestPath::IndexPack,
+        "no unpack limit configured"
+    );
+    assert_eq!(outcome.unpack, gix_receive_pack::pack::UnpackDecision::ObjectCount);
+    assert_eq!(
+        outcome.midx,
+        gix_receive_pack::pack::MidxUpdate::Skipped(gix_receive_pack::pack::MidxSkipReason::Disabled)
+    );
+    assert!(!outcome.thin, "the pack is self-contained");
+    assert!(
+        has_pack_files(&objects_dir.join("pack")),
+        "pack and index are migrated into the main objects directory"
+    );
+    assert!(pack_path.exists(), "the source pack is left untouched");
+
+    cleanup_temp_dir(objects_dir.parent().unwrap());
+}
+
+/// Test that packs within the unpack limit are indexed if their loose objects would take too much space.
+#[cfg(feature = "progress")]
+#[test]
+fn test_ingest_pack_file_indexes_packs_with_large_loose_objects() {
+    use gix_features::progress::Discard;
+    use gix_receive_pack::pack::{LooseObjects, PackIngestPath, UnpackDecision};
+    use test_utils::*;
+
+    let fixture_dir = scripted_fixture_read_only("pack-ingestion-test.sh")
+        .expect("pack ingestion fixture script should run");
+    let pack_path = fixture_dir.join("test-pack.pack");
+    let objects_dir = create_temp_objects_dir().expect("Failed to create temp objects dir");
+
+    let receive_pack = ReceivePackBuilder::new()
+        .blocking()
+        .with_objects_dir(&objects_dir)
+        .with_unpack_limit(Some(1000))
+        .with_loose_objects(LooseObjects::default().with_compression(9).with_max_bytes(4096))
+        .build();
+    let outcome = receive_pack
+        .ingest_pack_file(&pack_path, &mut Discard)
+        .expect("on-disk pack can be ingested");
+    assert_eq!(outcome.ingest_path, PackIngestPath::IndexPack);
+    assert!(
+        matches!(
+            outcome.unpack,
+            UnpackDecision::TooLarge { projected_bytes, limit: 4096 } if projected_bytes > 4096
+        ),
+        "{:?}",
+        outcome.unpack
+    );
+    assert!(has_pack_files(&objects_dir.join("pack")));
+
+    cleanup_temp_dir(objects_dir.parent().unwrap());
+}
+
+/// Test that an on-disk ingestion rewrites or defers the multi-pack-index as configured.
+#[cfg(feature = "progress")]
+#[test]
+fn test_ingest_pack_file_updates_multi_pack_index() {
+    use gix_features::progress::Discard;
+    use gix_receive_pack::pack::{midx, MidxMode, MidxUpdate};
+    use test_utils::*;
+
+    let fixture_dir = scripted_fixture_read_only("pack-ingestion-test.sh")
+        .expect("pack ingestion fixture script should run");
+    let pack_path = fixture_dir.join("test-pack.pack");
+    let objects_dir = create_temp_objects_dir().expect("Failed to create temp objects dir");
+    let pack_dir = objects_dir.join("pack");
+
+    let deferred = ReceivePackBuilder::new()
+        .blocking()
+        .with_objects_dir(&objects_dir)
+        .with_update_multi_pack_index(MidxMode::Defer)
+        .build()
+        .ingest_pack_file(&pack_path, &mut Discard)
+        .expect("on-disk pack can be ingested");
+    assert_eq!(
+        deferred.midx,
+        MidxUpdate::Deferred {
+            marker: pack_dir.join(midx::PENDING_MARKER)
+        }
+    );
+    assert!(!pack_dir.join("multi-pack-index").exists());
+
+    let written = ReceivePackBuilder::new()
+        .blocking()
+        .with_objects_dir(&objects_dir)
+        .with_update_multi_pack_index(MidxMode::Write)
+        .build()
+        .ingest_pack_file(&pack_path, &mut Discard)
+        .expect("on-disk pack can be ingested");
+    assert!(
+        matches!(written.midx, MidxUpdate::Written { packs, .. } if packs >= 1),
+        "got {:?}",
+        written.midx
+    );
+    assert!(pack_dir.join("multi-pack-index").is_file());
+    assert!(
+        !pack_dir.join(midx::PENDING_MARKER).exists(),
+        "writing the multi-pack-index clears a pending marker"
+    );
+
+    cleanup_temp_dir(objects_dir.parent().unwrap());
+}
+
 /// Test stub methods when progress feature is disabled.
 #[cfg(not(feature = "progress"))]
 #[test]
@@ -650,7 +786,7 @@ fn test_comprehensive_engine_wrapper_integration() {
         );
         
         match result {
-            Ok(()) => {
+            Ok(_) => {
                 // Verify artifacts were migrated to main objects directory
                 let main_pack_dir = objects_dir.join("pack");
                 assert!(
@@ -760,7 +896,7 @@ fn test_comprehensive_engine_wrapper_integration() {
         );
         
         match result {
-            Ok(()) => {
+            Ok(_) => {
                 println!("✓ Sideband success path: pack ingested with progress output");
             }
             Err(e) => {
@@ -820,7 +956,7 @@ fn test_engine_wrapper_edge_cases() {
         
         // Should handle missing hints gracefully
         match result {
-            Ok(()) => println!("✓ Handled missing size/count hints successfully"),
+            Ok(_) => println!("✓ Handled missing size/count hints successfully"),
             Err(e) => println!("Note: Missing hints may cause issues in test environment: {:?}", e),
         }
         
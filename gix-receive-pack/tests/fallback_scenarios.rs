@@ -194,6 +194,7 @@ fn test_pack_ingestion_streaming_result_structure() {
                 deallocations: 5,
             },
             buffer_size: 8192,
+            rates: Default::default(),
         },
         attempts_made: 1,
         fallback_used: false,
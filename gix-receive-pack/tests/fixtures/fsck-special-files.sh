@@ -0,0 +1,28 @@
+#!/usr/bin/env sh
+set -eu
+
+# Purpose: Create a repository whose special files fail the checks `git fsck` performs on them.
+# Output: a non-bare repository, and objects.txt listing all of its object ids.
+#
+# - .gitmodules names a submodule that escapes .git/modules and uses a URL that looks like an option.
+# - .gitignore is a symlink, which git reports at informational level.
+# - sub/.gitattributes is fine and must not be reported.
+
+git init --quiet
+
+cat > .gitmodules <<'MODULES'
+[submodule "../../escape"]
+	path = escape
+	url = --upload-pack=touch${IFS}pwned
+MODULES
+echo '*.txt text' > .gitattributes
+mkdir sub
+cp .gitattributes sub/.gitattributes
+git add .gitmodules .gitattributes sub/.gitattributes
+
+# Add the symlink through the index so it works without filesystem support for symlinks.
+link=$(printf 'ignored-elsewhere' | git hash-object -w --stdin)
+git update-index --add --cacheinfo 120000,"$link",.gitignore
+
+git -c user.name=author -c user.email=author@example.com commit --quiet -m "special files"
+git rev-list --objects --all | cut -d' ' -f1 > objects.txt
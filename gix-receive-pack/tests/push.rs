@@ -0,0 +1,1229 @@
+//! End-to-end pushes served by `ReceivePack::run()`, compared to `git receive-pack`.
+#![cfg(all(feature = "progress", feature = "blocking-io"))]
+
+use gix_receive_pack::protocol::CommandUpdate;
+use gix_receive_pack::{AdvertisementConfig, Error, HookDecision, Hooks, NoopHooks, PolicySet, ReceivePackBuilder};
+use gix_serve_core::metrics::{Meter, Metrics, Phase};
+use gix_serve_core::protocol::ServiceKind;
+use gix_serve_core::visibility::HiddenRefs;
+use gix_serve_core::wire::WireStats;
+use gix_testtools::tempfile;
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::time::Duration;
+
+const ZERO: &str = "0000000000000000000000000000000000000000";
+
+fn git(dir: &Path, args: &[&str]) -> String {
+    let output = Command::new("git")
+        .args(args)
+        .current_dir(dir)
+        .env("GIT_AUTHOR_NAME", "author")
+        .env("GIT_AUTHOR_EMAIL", "author@example.com")
+        .env("GIT_COMMITTER_NAME", "committer")
+        .env("GIT_COMMITTER_EMAIL", "committer@example.com")
+        .output()
+        .expect("git is installed");
+    assert!(
+        output.status.success(),
+        "git {args:?} failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8(output.stdout).unwrap().trim().to_owned()
+}
+
+fn pkt(line: &str) -> String {
+    format!("{:04x}{line}", line.len() + 4)
+}
+
+/// A repository with `main` two commits ahead of `old`, returning both commits.
+fn source(dir: &Path) -> (String, String) {
+    git(dir, &["init", "--quiet", "--initial-branch=main"]);
+    std::fs::write(dir.join("file"), "one\n").unwrap();
+    git(dir, &["add", "file"]);
+    git(dir, &["commit", "--quiet", "-m", "one"]);
+    git(dir, &["branch", "old"]);
+    std::fs::write(dir.join("file"), "two\n").unwrap();
+    git(dir, &["commit", "--quiet", "-am", "two"]);
+    (git(dir, &["rev-parse", "old"]), git(dir, &["rev-parse", "main"]))
+}
+
+/// A bare repository at `dir` with the `refspecs` of `source` pushed to it natively.
+fn target(source: &Path, dir: &Path, refspecs: &[&str]) {
+    git(source, &["init", "--quiet", "--bare", dir.to_str().unwrap()]);
+    let mut push = vec!["push", "--quiet", dir.to_str().unwrap()];
+    push.extend_from_slice(refspecs);
+    git(source, &push);
+}
+
+/// A push request of `commands`, `<old> <new> <ref>` each, with the objects of `source` reachable from `revs`.
+fn request(source: &Path, commands: &[String], revs: &str) -> Vec<u8> {
+    request_with(source, "report-status", &[], commands, revs)
+}
+
+/// Like [`request()`], but asking for `capabilities` and sending `push_options` if there are any.
+fn request_with(source: &Path, capabilities: &str, push_options: &[&str], commands: &[String], revs: &str) -> Vec<u8> {
+    let mut request = String::new();
+    for (index, command) in commands.iter().enumerate() {
+        request += &pkt(&if index == 0 {
+            format!("{command}\0{capabilities}\n")
+        } else {
+            format!("{command}\n")
+        });
+    }
+    request += "0000";
+    if !push_options.is_empty() {
+        for option in push_options {
+            request += &pkt(&format!("{option}\n"));
+        }
+        request += "0000";
+    }
+    let mut request = request.into_bytes();
+    if commands.iter().any(|command| !command.contains(&format!(" {ZERO} "))) {
+        request.extend(pack(source, revs));
+    }
+    request
+}
+
+/// A pack with the objects of `source` reachable from `revs`.
+fn pack(source: &Path, revs: &str) -> Vec<u8> {
+    let mut pack_objects = Command::new("git")
+        .args(["pack-objects", "--revs", "--stdout", "--quiet"])
+        .current_dir(source)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("git is installed");
+    pack_objects.stdin.take().unwrap().write_all(revs.as_bytes()).unwrap();
+    pack_objects.wait_with_output().unwrap().stdout
+}
+
+/// The report in `output`, the lines after the advertisement.
+fn report(output: &[u8]) -> Vec<String> {
+    let output = String::from_utf8_lossy(output);
+    let (_advertisement, mut rest) = output.split_once("0000").expect("advertisement ends with a flush");
+    let mut lines = Vec::new();
+    while rest.len() >= 4 {
+        let len = usize::from_str_radix(&rest[..4], 16).unwrap();
+        if len == 0 {
+            lines.push("0000".to_owned());
+            rest = &rest[4..];
+            continue;
+        }
+        lines.push(rest[4..len].to_owned());
+        rest = &rest[len..];
+    }
+    lines
+}
+
+/// Serve `request` to the repository at `dir` with `policy`, hiding the refs configured like git does.
+fn serve(dir: &Path, request: &[u8], policy: PolicySet, hooks: &mut dyn Hooks) -> Vec<u8> {
+    serve_namespace(dir, None, request, policy, hooks)
+}
+
+/// Like [`serve()`], but serving only the refs of `namespace` if there is one.
+fn serve_namespace(
+    dir: &Path,
+    namespace: Option<&str>,
+    request: &[u8],
+    policy: PolicySet,
+    hooks: &mut dyn Hooks,
+) -> Vec<u8> {
+    let config = gix_config::File::from_path_no_includes(dir.join("config"), gix_config::Source::Local).unwrap();
+    let receive_pack = ReceivePackBuilder::new()
+        .blocking()
+        .with_git_dir(dir)
+        .with_policy(policy)
+        .with_hidden_refs(HiddenRefs::from_config(&config, "receive"))
+        .with_namespace(namespace.map(|namespace| gix_ref::namespace::expand(namespace).unwrap()))
+        .with_advertisement(
+            AdvertisementConfig::modern_defaults()
+                .with_atomic(true)
+                .push_extra_capability("side-band-64k"),
+        )
+        .build();
+    let mut output = Vec::new();
+    receive_pack.run(request, &mut output, hooks).unwrap();
+    output
+}
+
+fn serve_natively(dir: &Path, request: &[u8]) -> Vec<u8> {
+    serve_natively_namespace(dir, None, request)
+}
+
+/// Like [`serve_natively()`], but with `namespace` in `GIT_NAMESPACE` if there is one.
+fn serve_natively_namespace(dir: &Path, namespace: Option<&str>, request: &[u8]) -> Vec<u8> {
+    let mut receive_pack = Command::new("git")
+        .args(["receive-pack", dir.to_str().unwrap()])
+        .envs(namespace.map(|namespace| ("GIT_NAMESPACE", namespace)))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .expect("git is installed");
+    receive_pack.stdin.take().unwrap().write_all(request).unwrap();
+    receive_pack.wait_with_output().unwrap().stdout
+}
+
+fn refs(dir: &Path) -> String {
+    git(dir, &["for-each-ref", "--format=%(objectname) %(refname) %(symref)"])
+}
+
+/// Push `request` to bare repositories with the `refspecs` of `source` after `setup`, served by us with `policy`
+/// and by git, and return our report once it and the refs after the push were checked to match git's.
+///
+/// Progress isn't compared, as git sends why refs couldn't be updated before the report, and we send it after.
+fn push_like_git(
+    source: &Path,
+    refspecs: &[&str],
+    setup: impl Fn(&Path),
+    policy: PolicySet,
+    request: &[u8],
+) -> Vec<String> {
+    let tmp = tempfile::tempdir().unwrap();
+    let (ours, native) = (tmp.path().join("ours.git"), tmp.path().join("native.git"));
+    for dir in [&ours, &native] {
+        target(source, dir, refspecs);
+        setup(dir);
+    }
+    let without_progress = |output: &[u8]| -> Vec<String> {
+        report(output)
+            .into_iter()
+            .filter(|line| !line.starts_with('\u{2}'))
+            .collect()
+    };
+    let report = without_progress(&serve(&ours, request, policy, &mut NoopHooks::new()));
+    assert_eq!(report, without_progress(&serve_natively(&native, request)));
+    assert_eq!(refs(&ours), refs(&native));
+    report
+}
+
+#[test]
+fn pushes_are_applied_like_git() {
+    let tmp = tempfile::tempdir().unwrap();
+    let (old, main) = source(tmp.path());
+    let (ours, native) = (tmp.path().join("ours.git"), tmp.path().join("native.git"));
+    for dir in [&ours, &native] {
+        target(tmp.path(), dir, &["old:refs/heads/main", "old:refs/heads/old"]);
+    }
+
+    let request = request(
+        tmp.path(),
+        &[
+            format!("{old} {main} refs/heads/main"),
+            format!("{old} {ZERO} refs/heads/old"),
+            format!("{ZERO} {main} refs/heads/topic"),
+        ],
+        &format!("{main}\n^{old}\n"),
+    );
+    let outcome = report(&serve(&ours, &request, PolicySet::new(), &mut NoopHooks::new()));
+    assert_eq!(outcome, report(&serve_natively(&native, &request)));
+    assert_eq!(
+        outcome,
+        [
+            "unpack ok\n",
+            "ok refs/heads/main\n",
+            "ok refs/heads/old\n",
+            "ok refs/heads/topic\n",
+            "0000"
+        ]
+    );
+    assert_eq!(refs(&ours), refs(&native));
+    git(&ours, &["fsck", "--connectivity-only", "--no-progress"]);
+    assert!(
+        !ours.join("objects/quarantine").read_dir().unwrap().any(|_| true),
+        "the quarantine was migrated"
+    );
+}
+
+#[test]
+fn policies_reject_updates_like_git() {
+    let tmp = tempfile::tempdir().unwrap();
+    let (old, main) = source(tmp.path());
+    let (ours, native) = (tmp.path().join("ours.git"), tmp.path().join("native.git"));
+    for dir in [&ours, &native] {
+        target(tmp.path(), dir, &["main:refs/heads/main", "old:refs/heads/old"]);
+    }
+    git(&native, &["config", "receive.denyDeletes", "true"]);
+    git(&native, &["config", "receive.denyNonFastForwards", "true"]);
+    let policy = PolicySet::new()
+        .with_deny_deletes(true)
+        .with_deny_non_fast_forwards(true);
+
+    let request = request(
+        tmp.path(),
+        &[
+            format!("{main} {old} refs/heads/main"),
+            format!("{ZERO} {old} refs/heads/new"),
+            format!("{old} {ZERO} refs/heads/old"),
+        ],
+        &format!("{old}\n^{main}\n"),
+    );
+    let outcome = report(&serve(&ours, &request, policy, &mut NoopHooks::new()));
+    assert_eq!(outcome, report(&serve_natively(&native, &request)));
+    assert_eq!(
+        outcome,
+        [
+            "unpack ok\n",
+            "ng refs/heads/main non-fast-forward\n",
+            "ok refs/heads/new\n",
+            "ng refs/heads/old deletion prohibited\n",
+            "0000"
+        ]
+    );
+    assert_eq!(refs(&ours), refs(&native));
+}
+
+/// Hooks declining the whole push, or the update of `refs/heads/protected`.
+struct Declining {
+    pre_receive: bool,
+    post_received: Vec<CommandUpdate>,
+}
+
+impl Hooks for Declining {
+    fn update(&mut self, command: &CommandUpdate) -> Result<HookDecision, Error> {
+        Ok(if command.name() == "refs/heads/protected" {
+            HookDecision::deny_with_output("protected", 1, Vec::new(), b"protected branch\n".to_vec())
+        } else {
+            HookDecision::allow()
+        })
+    }
+
+    fn pre_receive(&mut self, _commands: &[CommandUpdate]) -> Result<HookDecision, Error> {
+        Ok(if self.pre_receive {
+            HookDecision::allow()
+        } else {
+            HookDecision::deny("declined")
+        })
+    }
+
+    fn post_receive(&mut self, commands: &[CommandUpdate]) -> Result<(), Error> {
+        self.post_received = commands.to_vec();
+        Ok(())
+    }
+}
+
+#[test]
+fn hooks_decline_updates() {
+    let tmp = tempfile::tempdir().unwrap();
+    let (old, main) = source(tmp.path());
+    let ours = tmp.path().join("ours.git");
+    target(tmp.path(), &ours, &["old:refs/heads/main"]);
+    let before = refs(&ours);
+
+    let request = request(
+        tmp.path(),
+        &[
+            format!("{old} {main} refs/heads/main"),
+            format!("{ZERO} {main} refs/heads/protected"),
+        ],
+        &format!("{main}\n^{old}\n"),
+    );
+    let mut hooks = Declining {
+        pre_receive: false,
+        post_received: Vec::new(),
+    };
+    assert_eq!(
+        report(&serve(&ours, &request, PolicySet::new(), &mut hooks)),
+        [
+            "unpack ok\n",
+            "ng refs/heads/main pre-receive hook declined\n",
+            "ng refs/heads/protected pre-receive hook declined\n",
+            "0000"
+        ]
+    );
+    assert_eq!(refs(&ours), before);
+    assert!(hooks.post_received.is_empty());
+    assert!(
+        Command::new("git")
+            .args(["cat-file", "-e", &main])
+            .current_dir(&ours)
+            .status()
+            .unwrap()
+            .code()
+            != Some(0),
+        "objects of declined pushes are dropped with the quarantine"
+    );
+
+    hooks.pre_receive = true;
+    assert_eq!(
+        report(&serve(&ours, &request, PolicySet::new(), &mut hooks)),
+        [
+            "unpack ok\n",
+            "ok refs/heads/main\n",
+            "ng refs/heads/protected hook declined\n",
+            "0000"
+        ]
+    );
+    assert_eq!(
+        hooks.post_received,
+        [CommandUpdate::Update {
+            old: old.parse().unwrap(),
+            new: main.parse().unwrap(),
+            name: "refs/heads/main".into()
+        }]
+    );
+}
+
+#[test]
+fn incomplete_packs_reject_all_updates() {
+    let tmp = tempfile::tempdir().unwrap();
+    let (old, main) = source(tmp.path());
+    let ours = tmp.path().join("ours.git");
+    target(tmp.path(), &ours, &["old:refs/heads/main"]);
+    let before = refs(&ours);
+
+    let mut request = request(
+        tmp.path(),
+        &[format!("{old} {main} refs/heads/main")],
+        &format!("{main}\n^{old}\n"),
+    );
+    request.truncate(request.len() - 30);
+    let report = report(&serve(&ours, &request, PolicySet::new(), &mut NoopHooks::new()));
+    assert!(report[0].starts_with("unpack "), "{report:?}");
+    assert_ne!(report[0], "unpack ok\n");
+    assert_eq!(report[1..], ["ng refs/heads/main unpacker error\n", "0000"]);
+    assert_eq!(refs(&ours), before);
+}
+
+#[test]
+fn nothing_to_push_leaves_the_repository_alone() {
+    let tmp = tempfile::tempdir().unwrap();
+    source(tmp.path());
+    let ours = tmp.path().join("ours.git");
+    target(tmp.path(), &ours, &["main"]);
+
+    let receive_pack = ReceivePackBuilder::new().blocking().with_git_dir(&ours).build();
+    let mut output = Vec::new();
+    let outcome = receive_pack
+        .run(&b"0000"[..], &mut output, &mut NoopHooks::new())
+        .unwrap();
+    assert_eq!(outcome.report, None);
+    assert!(outcome.applied.is_empty());
+    let advertisement = String::from_utf8(output).unwrap();
+    assert!(
+        advertisement.contains(&format!("{} refs/heads/main\0", git(&ours, &["rev-parse", "main"]))),
+        "{advertisement:?}"
+    );
+}
+
+#[test]
+fn atomic_pushes_apply_all_updates_or_none_like_git() {
+    let tmp = tempfile::tempdir().unwrap();
+    let (old, main) = source(tmp.path());
+    let refspecs = ["old:refs/heads/a", "old:refs/heads/main", "old:refs/heads/old"];
+    let revs = format!("{main}\n^{old}\n");
+    let deny_deletes = |dir: &Path| {
+        git(dir, &["config", "receive.denyDeletes", "true"]);
+    };
+
+    let commands = [
+        format!("{old} {main} refs/heads/main"),
+        format!("{old} {ZERO} refs/heads/old"),
+    ];
+    assert_eq!(
+        push_like_git(
+            tmp.path(),
+            &refspecs,
+            deny_deletes,
+            PolicySet::new().with_deny_deletes(true),
+            &request_with(tmp.path(), "report-status atomic", &[], &commands, &revs),
+        ),
+        [
+            "unpack ok\n",
+            "ng refs/heads/main atomic push failure\n",
+            "ng refs/heads/old deletion prohibited\n",
+            "0000"
+        ],
+        "a rejected command fails all others"
+    );
+    assert_eq!(
+        push_like_git(
+            tmp.path(),
+            &refspecs,
+            deny_deletes,
+            PolicySet::new().with_deny_deletes(true),
+            &request(tmp.path(), &commands, &revs),
+        ),
+        [
+            "unpack ok\n",
+            "ok refs/heads/main\n",
+            "ng refs/heads/old deletion prohibited\n",
+            "0000"
+        ],
+        "without atomic, only the rejected command fails"
+    );
+
+    let commands = [
+        format!("{old} {ZERO} refs/heads/a"),
+        format!("{ZERO} {main} refs/heads/a/b"),
+        format!("{old} {main} refs/heads/main"),
+    ];
+    assert_eq!(
+        push_like_git(
+            tmp.path(),
+            &refspecs,
+            |_| {},
+            PolicySet::new(),
+            &request_with(tmp.path(), "report-status atomic", &[], &commands, &revs),
+        ),
+        [
+            "unpack ok\n",
+            "ng refs/heads/a atomic transaction failed\n",
+            "ng refs/heads/a/b atomic transaction failed\n",
+            "ng refs/heads/main atomic transaction failed\n",
+            "0000"
+        ],
+        "a ref can't be replaced by one below it in the same transaction"
+    );
+    assert_eq!(
+        push_like_git(
+            tmp.path(),
+            &refspecs,
+            |_| {},
+            PolicySet::new(),
+            &request(tmp.path(), &commands, &revs),
+        ),
+        [
+            "unpack ok\n",
+            "ok refs/heads/a\n",
+            "ok refs/heads/a/b\n",
+            "ok refs/heads/main\n",
+            "0000"
+        ],
+        "without atomic, deletions come first"
+    );
+
+    let commands = [
+        format!("{old} {ZERO} refs/heads/a"),
+        format!("{old} {main} refs/heads/main"),
+        format!("{ZERO} {main} refs/heads/new"),
+    ];
+    assert_eq!(
+        push_like_git(
+            tmp.path(),
+            &refspecs,
+            |_| {},
+            PolicySet::new(),
+            &request_with(tmp.path(), "report-status atomic", &[], &commands, &revs),
+        ),
+        [
+            "unpack ok\n",
+            "ok refs/heads/a\n",
+            "ok refs/heads/main\n",
+            "ok refs/heads/new\n",
+            "0000"
+        ]
+    );
+}
+
+#[test]
+fn hidden_refs_are_neither_advertised_nor_updated_like_git() {
+    let tmp = tempfile::tempdir().unwrap();
+    let (old, main) = source(tmp.path());
+    let refspecs = [
+        "old:refs/heads/main",
+        "old:refs/hidden/a",
+        "old:refs/hidden/open",
+        "old:refs/secret/b",
+    ];
+    let revs = format!("{main}\n^{old}\n");
+    let hide = |dir: &Path| {
+        git(dir, &["config", "--add", "transfer.hideRefs", "refs/secret/"]);
+        git(dir, &["config", "--add", "receive.hideRefs", "refs/hidden"]);
+        git(dir, &["config", "--add", "uploadpack.hideRefs", "!refs/secret/b"]);
+        git(dir, &["config", "--add", "receive.hideRefs", "!refs/hidden/open"]);
+    };
+
+    let advertised = tmp.path().join("advertised.git");
+    target(tmp.path(), &advertised, &refspecs);
+    hide(&advertised);
+    let advertisement = serve(&advertised, b"0000", PolicySet::new(), &mut NoopHooks::new());
+    let advertisement = String::from_utf8_lossy(&advertisement);
+    for (name, visible) in [
+        ("refs/heads/main", true),
+        ("refs/hidden/a", false),
+        ("refs/hidden/open", true),
+        ("refs/secret/b", false),
+    ] {
+        assert_eq!(advertisement.contains(name), visible, "{name}: {advertisement}");
+    }
+
+    let commands = [
+        format!("{old} {main} refs/heads/main"),
+        format!("{old} {main} refs/hidden/a"),
+        format!("{old} {main} refs/hidden/open"),
+        format!("{old} {ZERO} refs/secret/b"),
+    ];
+    let expected = [
+        "unpack ok\n",
+        "ok refs/heads/main\n",
+        "ng refs/hidden/a deny updating a hidden ref\n",
+        "ok refs/hidden/open\n",
+        "ng refs/secret/b deny deleting a hidden ref\n",
+        "0000",
+    ];
+    assert_eq!(
+        push_like_git(
+            tmp.path(),
+            &refspecs,
+            hide,
+            PolicySet::new(),
+            &request(tmp.path(), &commands, &revs),
+        ),
+        expected
+    );
+    assert_eq!(
+        push_like_git(
+            tmp.path(),
+            &refspecs,
+            hide,
+            PolicySet::new(),
+            &request_with(tmp.path(), "report-status atomic", &[], &commands, &revs),
+        ),
+        expected,
+        "refused updates of hidden refs don't fail atomic pushes"
+    );
+}
+
+/// The refs advertised in `output`, without capabilities.
+fn advertised_refs(output: &[u8]) -> Vec<String> {
+    let output = String::from_utf8_lossy(output);
+    let (mut rest, _report) = output.split_once("0000").expect("advertisement ends with a flush");
+    let mut refs = Vec::new();
+    while rest.len() >= 4 {
+        let len = usize::from_str_radix(&rest[..4], 16).unwrap();
+        let line = rest[4..len].trim_end();
+        refs.push(
+            line.split_once('\0')
+                .map_or(line, |(reference, _capabilities)| reference)
+                .to_owned(),
+        );
+        rest = &rest[len..];
+    }
+    refs
+}
+
+#[test]
+fn namespaces_are_advertised_and_updated_like_git() {
+    let tmp = tempfile::tempdir().unwrap();
+    let (old, main) = source(tmp.path());
+    let refspecs = [
+        "old:refs/heads/main",
+        "main:refs/heads/outside",
+        "old:refs/tags/outside",
+        "old:refs/namespaces/a/refs/heads/main",
+        "old:refs/namespaces/a/refs/heads/gone",
+        "old:refs/namespaces/a/refs/heads/hidden",
+        "old:refs/namespaces/a/refs/heads/secret",
+        "old:refs/namespaces/a/refs/namespaces/b/refs/heads/nested",
+    ];
+    let hide = |dir: &Path| {
+        git(dir, &["config", "--add", "receive.hideRefs", "refs/heads/hidden"]);
+        git(
+            dir,
+            &[
+                "config",
+                "--add",
+                "receive.hideRefs",
+                "^refs/namespaces/a/refs/heads/secret",
+            ],
+        );
+    };
+    let (ours, native) = (tmp.path().join("ours.git"), tmp.path().join("native.git"));
+    for dir in [&ours, &native] {
+        target(tmp.path(), dir, &refspecs);
+        hide(dir);
+    }
+
+    for namespace in ["a", "a/b", "missing"] {
+        let advertisement = advertised_refs(&serve_namespace(
+            &ours,
+            Some(namespace),
+            b"0000",
+            PolicySet::new(),
+            &mut NoopHooks::new(),
+        ));
+        assert_eq!(
+            advertisement,
+            advertised_refs(&serve_natively_namespace(&native, Some(namespace), b"0000")),
+            "{namespace}"
+        );
+    }
+    assert_eq!(
+        advertised_refs(&serve_namespace(
+            &ours,
+            Some("a"),
+            b"0000",
+            PolicySet::new(),
+            &mut NoopHooks::new()
+        )),
+        [
+            format!("{old} .have"),
+            format!("{main} .have"),
+            format!("{old} refs/heads/gone"),
+            format!("{old} refs/heads/main"),
+            format!("{old} refs/namespaces/b/refs/heads/nested"),
+        ],
+        "refs outside of the namespace are only advertised by their objects, once each if they come first"
+    );
+
+    let request = request(
+        tmp.path(),
+        &[
+            format!("{old} {ZERO} refs/heads/gone"),
+            format!("{old} {main} refs/heads/hidden"),
+            format!("{old} {main} refs/heads/main"),
+            format!("{ZERO} {main} refs/heads/new"),
+            format!("{old} {main} refs/heads/secret"),
+        ],
+        &format!("{main}\n^{old}\n"),
+    );
+    let outcome = report(&serve_namespace(
+        &ours,
+        Some("a"),
+        &request,
+        PolicySet::new(),
+        &mut NoopHooks::new(),
+    ));
+    assert_eq!(outcome, report(&serve_natively_namespace(&native, Some("a"), &request)));
+    assert_eq!(
+        outcome,
+        [
+            "unpack ok\n",
+            "ok refs/heads/gone\n",
+            "ng refs/heads/hidden deny updating a hidden ref\n",
+            "ok refs/heads/main\n",
+            "ok refs/heads/new\n",
+            "ng refs/heads/secret deny updating a hidden ref\n",
+            "0000"
+        ]
+    );
+    assert_eq!(refs(&ours), refs(&native));
+    assert_eq!(
+        git(
+            &ours,
+            &["rev-parse", "refs/heads/main", "refs/namespaces/a/refs/heads/new"]
+        ),
+        format!("{old}\n{main}"),
+        "only refs of the namespace are updated"
+    );
+}
+
+#[test]
+fn symbolic_refs_are_updated_like_git() {
+    let tmp = tempfile::tempdir().unwrap();
+    let (old, main) = source(tmp.path());
+    let refspecs = ["old:refs/heads/main", "old:refs/heads/x"];
+    let revs = format!("{main}\n^{old}\n");
+    let alias = |dir: &Path| {
+        git(dir, &["symbolic-ref", "refs/heads/alias", "refs/heads/main"]);
+    };
+    let push = |capabilities: &str, commands: &[String]| {
+        push_like_git(
+            tmp.path(),
+            &refspecs,
+            alias,
+            PolicySet::new(),
+            &request_with(tmp.path(), capabilities, &[], commands, &revs),
+        )
+    };
+
+    assert_eq!(
+        push("report-status", &[format!("{old} {main} refs/heads/alias")]),
+        ["unpack ok\n", "ok refs/heads/alias\n", "0000"],
+        "the target is updated"
+    );
+    assert_eq!(
+        push("report-status", &[format!("{old} {ZERO} refs/heads/alias")]),
+        ["unpack ok\n", "ok refs/heads/alias\n", "0000"],
+        "the symbolic ref is deleted along with its target"
+    );
+    assert_eq!(
+        push(
+            "report-status atomic",
+            &[
+                format!("{old} {main} refs/heads/alias"),
+                format!("{old} {main} refs/heads/main"),
+            ]
+        ),
+        ["unpack ok\n", "ok refs/heads/alias\n", "ok refs/heads/main\n", "0000"],
+        "consistent aliased updates are applied once"
+    );
+    for capabilities in ["report-status", "report-status atomic"] {
+        assert_eq!(
+            push(
+                capabilities,
+                &[
+                    format!("{old} {main} refs/heads/alias"),
+                    format!("{old} {old} refs/heads/main"),
+                    format!("{old} {main} refs/heads/x"),
+                ]
+            ),
+            [
+                "unpack ok\n",
+                "ng refs/heads/alias inconsistent aliased update\n",
+                "ng refs/heads/main inconsistent aliased update\n",
+                "ok refs/heads/x\n",
+                "0000"
+            ],
+            "inconsistent aliased updates don't fail other commands, even in atomic pushes"
+        );
+    }
+}
+
+#[test]
+fn report_status_v2_is_reported_like_git() {
+    let tmp = tempfile::tempdir().unwrap();
+    let (old, main) = source(tmp.path());
+    let commands = [
+        format!("{old} {main} refs/heads/main"),
+        format!("{old} {ZERO} refs/heads/old"),
+    ];
+    let revs = format!("{main}\n^{old}\n");
+    let deny_deletes = |dir: &Path| {
+        git(dir, &["config", "receive.denyDeletes", "true"]);
+    };
+    let push = |capabilities: &str| {
+        push_like_git(
+            tmp.path(),
+            &["old:refs/heads/main", "old:refs/heads/old"],
+            deny_deletes,
+            PolicySet::new().with_deny_deletes(true),
+            &request_with(tmp.path(), capabilities, &[], &commands, &revs),
+        )
+    };
+    assert_eq!(
+        push("report-status report-status-v2"),
+        [
+            "unpack ok\n",
+            "ok refs/heads/main\n",
+            "ng refs/heads/old deletion prohibited\n",
+            "0000"
+        ],
+        "refs updated as pushed have no options"
+    );
+    push("report-status-v2 side-band-64k");
+}
+
+#[cfg(all(unix, feature = "hooks-external"))]
+#[test]
+fn push_options_reach_hooks_like_git() {
+    use gix_receive_pack::hooks::env::HookEnvironment;
+    use gix_receive_pack::hooks::{ExternalHookConfig, ExternalHooks};
+    use std::os::unix::fs::PermissionsExt;
+
+    let tmp = tempfile::tempdir().unwrap();
+    let (old, main) = source(tmp.path());
+    let (ours, native) = (tmp.path().join("ours.git"), tmp.path().join("native.git"));
+    for dir in [&ours, &native] {
+        target(tmp.path(), dir, &["old:refs/heads/main"]);
+        git(dir, &["config", "receive.advertisePushOptions", "true"]);
+        let hook = dir.join("hooks").join("pre-receive");
+        std::fs::write(
+            &hook,
+            "#!/bin/sh\ntest \"$GIT_PUSH_OPTION_COUNT:$GIT_PUSH_OPTION_0\" != 1:reject\n",
+        )
+        .unwrap();
+        std::fs::set_permissions(&hook, std::fs::Permissions::from_mode(0o755)).unwrap();
+    }
+    let config = gix_config::File::from_path_no_includes(ours.join("config"), gix_config::Source::Local).unwrap();
+    let receive_pack = ReceivePackBuilder::new()
+        .blocking()
+        .with_git_dir(&ours)
+        .with_advertisement(AdvertisementConfig::modern_defaults().with_config(&config).unwrap())
+        .build();
+    let mut hooks = ExternalHooks::new(
+        ExternalHookConfig {
+            hooks_dir: ours.join("hooks"),
+            ..Default::default()
+        },
+        HookEnvironment::new().with_git_dir(&ours),
+    );
+
+    let commands = [format!("{old} {main} refs/heads/main")];
+    let revs = format!("{main}\n^{old}\n");
+    for (push_options, expected) in [
+        (["reject"], "ng refs/heads/main pre-receive hook declined\n"),
+        (["ci.skip"], "ok refs/heads/main\n"),
+    ] {
+        let request = request_with(
+            tmp.path(),
+            "report-status push-options",
+            &push_options,
+            &commands,
+            &revs,
+        );
+        let mut output = Vec::new();
+        receive_pack.run(&request[..], &mut output, &mut hooks).unwrap();
+        let ours = report(&output);
+        assert_eq!(ours, ["unpack ok\n", expected, "0000"], "{push_options:?}");
+        assert_eq!(ours, report(&serve_natively(&native, &request)));
+    }
+    assert_eq!(refs(&ours), refs(&native));
+}
+
+#[cfg(all(unix, feature = "hooks-external"))]
+#[test]
+fn hooks_see_quarantined_objects_like_git() {
+    use gix_receive_pack::hooks::env::HookEnvironment;
+    use gix_receive_pack::hooks::{ExternalHookConfig, ExternalHooks};
+    use std::os::unix::fs::PermissionsExt;
+
+    let tmp = tempfile::tempdir().unwrap();
+    let (old, main) = source(tmp.path());
+    let (ours, native) = (tmp.path().join("ours.git"), tmp.path().join("native.git"));
+    for dir in [&ours, &native] {
+        target(tmp.path(), dir, &["old:refs/heads/main"]);
+        for name in ["pre-receive", "update", "post-receive"] {
+            let hook = dir.join("hooks").join(name);
+            std::fs::write(
+                &hook,
+                format!(
+                    "#!/bin/sh\n\
+                     quarantine=${{GIT_QUARANTINE_PATH:+quarantined}}\n\
+                     test \"$GIT_OBJECT_DIRECTORY\" = \"$GIT_QUARANTINE_PATH\" || quarantine=inconsistent\n\
+                     git cat-file -e {main} && found=found || found=missing\n\
+                     echo {name} $quarantine $found >>{log}\n",
+                    log = dir.join("hooks.log").display()
+                ),
+            )
+            .unwrap();
+            std::fs::set_permissions(&hook, std::fs::Permissions::from_mode(0o755)).unwrap();
+        }
+    }
+    let receive_pack = ReceivePackBuilder::new().blocking().with_git_dir(&ours).build();
+    let mut hooks = ExternalHooks::new(
+        ExternalHookConfig {
+            hooks_dir: ours.join("hooks"),
+            ..Default::default()
+        },
+        HookEnvironment::new().with_git_dir(&ours),
+    );
+
+    let request = request(
+        tmp.path(),
+        &[format!("{old} {main} refs/heads/main")],
+        &format!("{main}\n^{old}\n"),
+    );
+    let mut output = Vec::new();
+    receive_pack.run(&request[..], &mut output, &mut hooks).unwrap();
+    assert_eq!(report(&output), report(&serve_natively(&native, &request)));
+
+    let log = |dir: &Path| std::fs::read_to_string(dir.join("hooks.log")).unwrap();
+    assert_eq!(
+        log(&ours),
+        "pre-receive quarantined found\nupdate found\npost-receive found\n",
+        "only pre-receive runs while the objects are quarantined"
+    );
+    assert_eq!(log(&ours), log(&native));
+    assert_eq!(refs(&ours), refs(&native));
+}
+
+/// Push `commands` with `pack` to the server at the other end of `to_server` and `from_server` like `git push --signed`,
+/// with a certificate whose `headers` have `{nonce}` replaced by the advertised nonce, and return the certificate
+/// along with the report.
+#[cfg(all(unix, feature = "hooks-external"))]
+fn push_signed(
+    mut to_server: impl Write,
+    mut from_server: impl std::io::Read,
+    headers: &str,
+    commands: &[String],
+    pack: &[u8],
+) -> (String, Vec<String>) {
+    let mut output = Vec::new();
+    loop {
+        let mut len = [0; 4];
+        from_server.read_exact(&mut len).unwrap();
+        output.extend_from_slice(&len);
+        let len = usize::from_str_radix(std::str::from_utf8(&len).unwrap(), 16).unwrap();
+        if len == 0 {
+            break;
+        }
+        let mut line = vec![0; len - 4];
+        from_server.read_exact(&mut line).unwrap();
+        output.extend_from_slice(&line);
+    }
+    let advertisement = String::from_utf8_lossy(&output).into_owned();
+    let nonce = advertisement
+        .split(['\0', ' ', '\n'])
+        .find_map(|token| token.strip_prefix("push-cert="))
+        .expect("push-cert is advertised");
+
+    let mut certificate = format!(
+        "certificate version 0.1\npusher committer <committer@example.com> 1700000000 +0000\npushee remote\n{}\n\n",
+        headers.replace("{nonce}", nonce)
+    );
+    for command in commands {
+        certificate += &format!("{command}\n");
+    }
+    certificate += "-----BEGIN PGP SIGNATURE-----\n\niQEzBAABCAAdFiEE\n-----END PGP SIGNATURE-----\n";
+    let mut request = pkt("push-cert\0report-status\n");
+    for line in certificate.split_inclusive('\n') {
+        request += &pkt(line);
+    }
+    request += &pkt("push-cert-end\n");
+    request += "0000";
+    to_server.write_all(request.as_bytes()).unwrap();
+    to_server.write_all(pack).unwrap();
+    to_server.flush().unwrap();
+    from_server.read_to_end(&mut output).unwrap();
+    (certificate, report(&output))
+}
+
+#[cfg(all(unix, feature = "hooks-external"))]
+#[test]
+fn signed_pushes_reach_hooks_like_git() {
+    use gix_receive_pack::hooks::env::HookEnvironment;
+    use gix_receive_pack::hooks::{ExternalHookConfig, ExternalHooks};
+    use gix_receive_pack::protocol::{NonceStatus, PushCertConfig};
+    use std::os::unix::fs::PermissionsExt;
+    use std::os::unix::net::UnixStream;
+
+    let tmp = tempfile::tempdir().unwrap();
+    let (old, main) = source(tmp.path());
+    let (ours, native) = (tmp.path().join("ours.git"), tmp.path().join("native.git"));
+    let gnupg_home = tmp.path().join("gnupg");
+    std::fs::create_dir(&gnupg_home).unwrap();
+    std::fs::set_permissions(&gnupg_home, std::fs::Permissions::from_mode(0o700)).unwrap();
+    for dir in [&ours, &native] {
+        target(tmp.path(), dir, &["old:refs/heads/main"]);
+        git(dir, &["config", "receive.certNonceSeed", "s3cret"]);
+        let hook = dir.join("hooks").join("pre-receive");
+        std::fs::write(
+            &hook,
+            format!(
+                "#!/bin/sh\ncert=$(git cat-file blob \"$GIT_PUSH_CERT\") || exit 1\ncase \"$cert\" in *\"nonce $GIT_PUSH_CERT_NONCE\"*) nonce=issued;; *) nonce=other;; esac\necho \"$GIT_PUSH_CERT_NONCE_STATUS $nonce\" >>{}\n",
+                dir.join("certs").display()
+            ),
+        )
+        .unwrap();
+        std::fs::set_permissions(&hook, std::fs::Permissions::from_mode(0o755)).unwrap();
+    }
+    let config = gix_config::File::from_path_no_includes(ours.join("config"), gix_config::Source::Local).unwrap();
+    let push_certs = PushCertConfig::from_config(&config).unwrap();
+
+    let revs = format!("{main}\n^{old}\n");
+    let pack = pack(tmp.path(), &revs);
+    let commands = [format!("{old} {main} refs/heads/main")];
+    for (headers, expected, nonce_status) in [
+        ("nonce {nonce}", "ok refs/heads/main\n", NonceStatus::Ok),
+        ("nonce 1-abc", "ok refs/heads/main\n", NonceStatus::Bad),
+        (
+            "nonce {nonce}\npush-option ci.skip",
+            "ng refs/heads/main inconsistent push options\n",
+            NonceStatus::Ok,
+        ),
+    ] {
+        let (client, server) = UnixStream::pair().unwrap();
+        let (certificate, report, outcome) = std::thread::scope(|scope| {
+            let (ours, push_certs) = (&ours, &push_certs);
+            let serving = scope.spawn(move || {
+                let receive_pack = ReceivePackBuilder::new()
+                    .blocking()
+                    .with_git_dir(&ours)
+                    .with_push_certs(push_certs.clone())
+                    .build();
+                let mut hooks = ExternalHooks::new(
+                    ExternalHookConfig {
+                        hooks_dir: ours.join("hooks"),
+                        ..Default::default()
+                    },
+                    HookEnvironment::new().with_git_dir(&ours),
+                );
+                receive_pack.run(&server, &server, &mut hooks)
+            });
+            let (certificate, report) = push_signed(&client, &client, headers, &commands, &pack);
+            (certificate, report, serving.join().unwrap().unwrap())
+        });
+        assert_eq!(report, ["unpack ok\n", expected, "0000"], "{headers}");
+
+        let mut receive_pack = Command::new("git")
+            .args(["receive-pack", native.to_str().unwrap()])
+            .env("GNUPGHOME", &gnupg_home)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .expect("git is installed");
+        let (_, native_report) = push_signed(
+            receive_pack.stdin.take().unwrap(),
+            receive_pack.stdout.take().unwrap(),
+            headers,
+            &commands,
+            &pack,
+        );
+        receive_pack.wait().unwrap();
+        assert_eq!(report, native_report, "{headers}");
+
+        let record = outcome.push_cert.expect("the certificate is recorded");
+        assert_eq!(record.certificate.text, certificate);
+        assert_eq!(record.nonce.status, nonce_status);
+        assert_eq!(git(&ours, &["cat-file", "-t", &record.id.to_string()]), "blob");
+        assert_eq!(refs(&ours), refs(&native));
+        for dir in [&ours, &native] {
+            git(dir, &["update-ref", "refs/heads/main", &old]);
+        }
+    }
+    let certs = std::fs::read_to_string(ours.join("certs")).unwrap();
+    assert_eq!(certs, "OK issued\nBAD other\nOK issued\n", "hooks see the issued nonce");
+    assert_eq!(certs, std::fs::read_to_string(native.join("certs")).unwrap());
+}
+
+/// Records the measurements of pushes.
+#[derive(Default, Clone)]
+struct Recorder(std::sync::Arc<std::sync::Mutex<Vec<String>>>);
+
+impl Metrics for Recorder {
+    fn session_started(&self, service: ServiceKind, active: usize) {
+        self.0.lock().unwrap().push(format!("start {service:?} {active}"));
+    }
+
+    fn session_finished(&self, _: ServiceKind, wire: &WireStats, _: Duration, success: bool, active: usize) {
+        let pack = wire.pack_bytes > 0;
+        self.0
+            .lock()
+            .unwrap()
+            .push(format!("finish pack={pack} success={success} {active}"));
+    }
+
+    fn phase(&self, _: ServiceKind, phase: Phase, _: Duration) {
+        self.0.lock().unwrap().push(format!("phase {phase}"));
+    }
+
+    fn pack_received(&self, objects: u64, bytes: u64) {
+        let nonempty = bytes > 32;
+        self.0
+            .lock()
+            .unwrap()
+            .push(format!("pack objects={objects} nonempty={nonempty}"));
+    }
+}
+
+#[test]
+fn pushes_are_measured() {
+    let tmp = tempfile::tempdir().unwrap();
+    let (old, main) = source(tmp.path());
+    let ours = tmp.path().join("ours.git");
+    target(tmp.path(), &ours, &["old:refs/heads/main"]);
+
+    let revs = format!("{main}\n^{old}\n");
+    let request = request(tmp.path(), &[format!("{old} {main} refs/heads/main")], &revs);
+    let recorder = Recorder::default();
+    let receive_pack = ReceivePackBuilder::new()
+        .blocking()
+        .with_git_dir(&ours)
+        .with_metrics(Meter::new(recorder.clone()))
+        .build();
+    receive_pack
+        .run(&request[..], Vec::new(), &mut NoopHooks::new())
+        .unwrap();
+
+    assert_eq!(
+        *recorder.0.lock().unwrap(),
+        [
+            "start ReceivePack 1",
+            "phase handshake",
+            "phase ingestion",
+            "pack objects=3 nonempty=true",
+            "finish pack=true success=true 0",
+        ]
+    );
+}
+
+#[test]
+fn the_multi_pack_index_is_maintained_for_received_packs() {
+    use gix_receive_pack::pack::{midx, MidxMode, MidxUpdate};
+
+    let tmp = tempfile::tempdir().unwrap();
+    let (old, main) = source(tmp.path());
+    let ours = tmp.path().join("ours.git");
+    target(tmp.path(), &ours, &["old:refs/heads/main", "old:refs/heads/old"]);
+    let pack_dir = ours.join("objects/pack");
+
+    let revs = format!("{main}\n^{old}\n");
+    let update = request(tmp.path(), &[format!("{old} {main} refs/heads/main")], &revs);
+    let receive_pack = |mode| {
+        ReceivePackBuilder::new()
+            .blocking()
+            .with_git_dir(&ours)
+            .with_update_multi_pack_index(mode)
+            .build()
+    };
+    let outcome = receive_pack(MidxMode::Defer)
+        .run(&update[..], Vec::new(), &mut NoopHooks::new())
+        .unwrap();
+    assert_eq!(
+        outcome.midx,
+        Some(MidxUpdate::Deferred {
+            marker: pack_dir.join(midx::PENDING_MARKER)
+        })
+    );
+
+    let outcome = receive_pack(MidxMode::Write)
+        .run(
+            &request(tmp.path(), &[format!("{old} {ZERO} refs/heads/old")], "")[..],
+            Vec::new(),
+            &mut NoopHooks::new(),
+        )
+        .unwrap();
+    assert_eq!(outcome.midx, None, "deletions don't send a pack");
+
+    let pack = pack(tmp.path(), &revs);
+    let mut input = std::io::BufReader::new(&pack[..]);
+    receive_pack(MidxMode::Write)
+        .ingest_pack_from_reader(&mut input, None, None, &mut gix_features::progress::Discard)
+        .unwrap();
+    assert!(pack_dir.join("multi-pack-index").is_file());
+    assert!(
+        !pack_dir.join(midx::PENDING_MARKER).exists(),
+        "writing the multi-pack-index clears a pending marker"
+    );
+}
+
+#[test]
+fn pushed_packs_are_limited_in_size_and_unpacked_by_their_object_count() {
+    let tmp = tempfile::tempdir().unwrap();
+    let (old, main) = source(tmp.path());
+    let ours = tmp.path().join("ours.git");
+    target(tmp.path(), &ours, &["old:refs/heads/main"]);
+    let before = refs(&ours);
+    let packs = |dir: &Path| std::fs::read_dir(dir.join("objects/pack")).unwrap().count();
+    let packs_before = packs(&ours);
+
+    let revs = format!("{main}\n^{old}\n");
+    let update = request(tmp.path(), &[format!("{old} {main} refs/heads/main")], &revs);
+    let pack_len = pack(tmp.path(), &revs).len() as u64;
+    let receive_pack = ReceivePackBuilder::new()
+        .blocking()
+        .with_git_dir(&ours)
+        .with_max_pack_bytes(pack_len - 1)
+        .build();
+    let mut output = Vec::new();
+    receive_pack
+        .run(&update[..], &mut output, &mut NoopHooks::new())
+        .unwrap();
+    let refused = report(&output);
+    assert!(refused[0].contains("exceeds size limit"), "{refused:?}");
+    assert_eq!(refs(&ours), before, "the limit applies to packs of unknown size");
+
+    let receive_pack = ReceivePackBuilder::new()
+        .blocking()
+        .with_git_dir(&ours)
+        .with_max_pack_bytes(pack_len)
+        .with_unpack_limit(100)
+        .build();
+    let mut output = Vec::new();
+    receive_pack
+        .run(&update[..], &mut output, &mut NoopHooks::new())
+        .unwrap();
+    assert_eq!(report(&output)[0], "unpack ok\n");
+    assert_eq!(git(&ours, &["rev-parse", "main"]), main);
+    assert_eq!(
+        packs(&ours),
+        packs_before,
+        "the object count in the pack header is within the unpack limit"
+    );
+    assert!(ours.join("objects").join(&main[..2]).join(&main[2..]).is_file());
+}
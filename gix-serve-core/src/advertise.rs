@@ -43,4 +43,64 @@ impl AdvertConfig {
     }
 }
 
+/// A strong hash of everything an advertisement is made of, to tell whether it changed without writing it.
+///
+/// Services compute it from the same inputs they advertise, like the refs with their targets and the capabilities,
+/// and the protocol version they were asked for. Frequent pollers can then be answered with `304 Not Modified`
+/// as long as their fingerprint is [current](Self::matches()), see
+/// [`GitService::advertisement_fingerprint()`](crate::service::GitService::advertisement_fingerprint()).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Fingerprint(gix_hash::ObjectId);
 
+impl Fingerprint {
+    /// Start computing a fingerprint by feeding the parts of an advertisement to the returned hasher.
+    pub fn hasher() -> FingerprintHasher {
+        FingerprintHasher(gix_hash::hasher(gix_hash::Kind::Sha1))
+    }
+
+    /// The fingerprint as strong HTTP entity tag, a quoted hex string.
+    pub fn to_etag(&self) -> String {
+        format!("\"{}\"", self.0)
+    }
+
+    /// Return `true` if `if_none_match`, the value of an `If-None-Match` header, lists this fingerprint or is `*`.
+    ///
+    /// Weak tags match as well, as the comparison for `If-None-Match` is weak.
+    pub fn matches(&self, if_none_match: &str) -> bool {
+        let etag = self.to_etag();
+        if_none_match
+            .split(',')
+            .map(str::trim)
+            .any(|candidate| candidate == "*" || candidate.strip_prefix("W/").unwrap_or(candidate) == etag)
+    }
+}
+
+impl std::fmt::Display for Fingerprint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// Computes a [`Fingerprint`] from the parts of an advertisement.
+#[derive(Clone)]
+pub struct FingerprintHasher(gix_hash::Hasher);
+
+impl FingerprintHasher {
+    /// Add `part`, like a ref name or a capability, to the fingerprint.
+    ///
+    /// Parts are length-prefixed, so `["ab", "c"]` and `["a", "bc"]` have different fingerprints.
+    pub fn update(&mut self, part: impl AsRef<[u8]>) -> &mut Self {
+        let part = part.as_ref();
+        self.0.update(&(part.len() as u64).to_be_bytes());
+        self.0.update(part);
+        self
+    }
+
+    /// Produce the fingerprint of all parts added so far.
+    pub fn finalize(self) -> Fingerprint {
+        // Collisions can only be provoked by those who control the refs, who could change them just as well.
+        Fingerprint(self.0.try_finalize().unwrap_or_else(|err| match err {
+            gix_hash::hasher::Error::CollisionAttack { digest } => digest,
+        }))
+    }
+}
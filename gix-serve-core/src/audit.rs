@@ -0,0 +1,153 @@
+//! Structured records of denied operations, kept apart from logs and metrics for security reviews.
+//!
+//! Services report each request or ref update they refuse as a [`Denial`] to the [`AuditSink`] of an [`Auditor`],
+//! be it a client asking for a hidden ref, a push rejected by policy or by a hook, a capability that wasn't
+//! advertised or an exceeded resource limit. Records name the principal, repository and refname along with a stable
+//! [`Reason`] code, so sinks can forward them as they are.
+//!
+//! Nothing is recorded by default.
+
+use crate::protocol::ServiceKind;
+use crate::service::ServiceContext;
+use std::fmt;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+/// Why an operation was denied, as stable code for filtering and alerting.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Reason {
+    /// The client asked for a ref or object it isn't allowed to see.
+    HiddenRef,
+    /// A server policy refused the operation, like denying deletions or non-fast-forward updates.
+    Policy,
+    /// A hook declined the operation.
+    Hook,
+    /// The client used a capability that wasn't advertised or conflicts with others.
+    Capability,
+    /// The client exceeded a resource limit, like sending its request too slowly.
+    Limit,
+}
+
+impl Reason {
+    /// The code of the reason, like `hidden-ref`.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Reason::HiddenRef => "hidden-ref",
+            Reason::Policy => "policy",
+            Reason::Hook => "hook",
+            Reason::Capability => "capability",
+            Reason::Limit => "limit",
+        }
+    }
+}
+
+impl fmt::Display for Reason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// A denied operation, as passed to [`AuditSink::record()`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Denial {
+    /// The service that denied the operation.
+    pub service: ServiceKind,
+    /// Why the operation was denied.
+    pub reason: Reason,
+    /// The authenticated identity of the client, if known.
+    pub principal: Option<String>,
+    /// The repository the operation was denied in.
+    pub repository: PathBuf,
+    /// The full name of the ref the operation concerned, if it concerned a single ref.
+    pub refname: Option<String>,
+    /// The address of the client, if known.
+    pub remote_addr: Option<SocketAddr>,
+    /// The trace identifier of the request, if set.
+    pub trace_id: Option<String>,
+    /// A description of the denial, like the message sent to the client.
+    pub message: String,
+}
+
+impl Denial {
+    /// Create a record of `service` denying an operation in `repository` for `reason`, described by `message`.
+    pub fn new(
+        service: ServiceKind,
+        repository: impl Into<PathBuf>,
+        reason: Reason,
+        message: impl Into<String>,
+    ) -> Self {
+        Denial {
+            service,
+            reason,
+            principal: None,
+            repository: repository.into(),
+            refname: None,
+            remote_addr: None,
+            trace_id: None,
+            message: message.into(),
+        }
+    }
+
+    /// Set the full name of the ref the operation concerned.
+    pub fn with_refname(mut self, refname: impl Into<String>) -> Self {
+        self.refname = Some(refname.into());
+        self
+    }
+
+    /// Set the principal, client address and trace identifier from `ctx`.
+    pub fn with_context(mut self, ctx: &ServiceContext) -> Self {
+        self.principal.clone_from(&ctx.principal);
+        self.remote_addr = ctx.remote_addr;
+        self.trace_id.clone_from(&ctx.trace_id);
+        self
+    }
+}
+
+/// A destination for [`Denial`] records, like a file read by a SIEM.
+pub trait AuditSink: Send + Sync {
+    /// Record `denial`.
+    fn record(&self, denial: &Denial);
+}
+
+impl<F> AuditSink for F
+where
+    F: Fn(&Denial) + Send + Sync,
+{
+    fn record(&self, denial: &Denial) {
+        self(denial);
+    }
+}
+
+/// A cheaply clonable handle to an [`AuditSink`], which records nothing by default.
+#[derive(Clone, Default)]
+pub struct Auditor(Option<Arc<dyn AuditSink>>);
+
+impl Auditor {
+    /// Create an auditor passing records to `sink`.
+    pub fn new(sink: impl AuditSink + 'static) -> Self {
+        Auditor(Some(Arc::new(sink)))
+    }
+
+    /// Return `true` if records are passed to a sink.
+    pub fn is_enabled(&self) -> bool {
+        self.0.is_some()
+    }
+
+    /// Pass `denial` to the sink, if there is one.
+    pub fn record(&self, denial: &Denial) {
+        if let Some(sink) = &self.0 {
+            sink.record(denial);
+        }
+    }
+}
+
+impl fmt::Debug for Auditor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(if self.0.is_some() {
+            "Auditor"
+        } else {
+            "Auditor(disabled)"
+        })
+    }
+}
@@ -0,0 +1,437 @@
+//! Recording of the requests and responses of a service, to debug sessions after the fact.
+//!
+//! A [`Recorder`] wraps any [`GitService`] into a [`Recording`] that copies each request it serves and the response it
+//! writes into files in a directory, and appends a line describing the request to an [index](INDEX_FILE) in the same
+//! directory. Captures are rotated once there are more than [`Options::max_files`] or they take more than
+//! [`Options::max_total_bytes`], oldest first, and can be compressed, so recording can stay enabled in production.
+//!
+//! Recording is best-effort: requests are served the same whether or not their capture could be written, and
+//! captures that couldn't be written completely are removed. Advertisements of stateless transports are not recorded.
+//!
+//! Each capture consists of `<id>.request` and `<id>.response`, with a `.gz` extension if compressed. The index has
+//! one line per capture with tab-separated fields: id, time in seconds since the epoch, repository, service, protocol
+//! version, `gz` or `raw`, bytes on disk and outcome, which is `ok` or the error message.
+
+use crate::protocol::{ProtocolVersion, ServiceKind};
+use crate::service::{Error, GitService, Outcome, ServiceContext};
+use std::fs;
+use std::io::{self, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The name of the index file in the capture directory.
+pub const INDEX_FILE: &str = "index";
+
+/// Where and how much to record.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Options {
+    /// The directory to write captures and their index to.
+    pub directory: PathBuf,
+    /// Keep at most this many captures, removing the oldest ones first.
+    pub max_files: Option<usize>,
+    /// Keep at most this many bytes of captures on disk, removing the oldest ones first.
+    pub max_total_bytes: Option<u64>,
+    /// Compress captures with gzip.
+    pub compress: bool,
+}
+
+impl Options {
+    /// Record into `directory` without any limits or compression.
+    pub fn new(directory: impl Into<PathBuf>) -> Self {
+        Options {
+            directory: directory.into(),
+            max_files: None,
+            max_total_bytes: None,
+            compress: false,
+        }
+    }
+
+    /// Keep at most `max_files` captures.
+    pub fn with_max_files(mut self, max_files: usize) -> Self {
+        self.max_files = Some(max_files);
+        self
+    }
+
+    /// Keep at most `max_total_bytes` of captures on disk.
+    pub fn with_max_total_bytes(mut self, max_total_bytes: u64) -> Self {
+        self.max_total_bytes = Some(max_total_bytes);
+        self
+    }
+
+    /// Set whether captures are compressed with gzip.
+    pub fn with_compression(mut self, compress: bool) -> Self {
+        self.compress = compress;
+        self
+    }
+}
+
+/// A line of the capture index, describing one recorded request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    /// The unique id of the capture, which also names its files.
+    pub id: String,
+    /// When the request was served, in seconds since the epoch.
+    pub timestamp: u64,
+    /// The repository the request was for, as passed to [`Recorder::wrap()`].
+    pub repository: String,
+    /// The service that served the request.
+    pub service: ServiceKind,
+    /// The protocol version of the request.
+    pub version: ProtocolVersion,
+    /// Whether the capture files are compressed with gzip.
+    pub compressed: bool,
+    /// The size of both capture files on disk.
+    pub bytes: u64,
+    /// `ok` if the request was served successfully, or the error it failed with.
+    pub outcome: String,
+}
+
+impl Entry {
+    /// The path of the file holding the request in `directory`.
+    pub fn request_path(&self, directory: &Path) -> PathBuf {
+        directory.join(self.file_name("request"))
+    }
+
+    /// The path of the file holding the response in `directory`.
+    pub fn response_path(&self, directory: &Path) -> PathBuf {
+        directory.join(self.file_name("response"))
+    }
+
+    fn file_name(&self, kind: &str) -> String {
+        let extension = if self.compressed { ".gz" } else { "" };
+        format!("{}.{kind}{extension}", self.id)
+    }
+
+    fn to_line(&self) -> String {
+        let service = match self.service {
+            ServiceKind::UploadPack => "upload-pack",
+            ServiceKind::ReceivePack => "receive-pack",
+        };
+        let version = match self.version {
+            ProtocolVersion::V0 => "0",
+            ProtocolVersion::V1 => "1",
+            ProtocolVersion::V2 => "2",
+        };
+        let clean = |text: &str| text.replace(['\t', '\n', '\r'], " ");
+        format!(
+            "{}\t{}\t{}\t{service}\t{version}\t{}\t{}\t{}\n",
+            self.id,
+            self.timestamp,
+            clean(&self.repository),
+            if self.compressed { "gz" } else { "raw" },
+            self.bytes,
+            clean(&self.outcome)
+        )
+    }
+
+    fn from_line(line: &str) -> Option<Self> {
+        let mut fields = line.splitn(8, '\t');
+        let mut next = || fields.next();
+        Some(Entry {
+            id: next()?.to_owned(),
+            timestamp: next()?.parse().ok()?,
+            repository: next()?.to_owned(),
+            service: match next()? {
+                "upload-pack" => ServiceKind::UploadPack,
+                "receive-pack" => ServiceKind::ReceivePack,
+                _ => return None,
+            },
+            version: match next()? {
+                "0" => ProtocolVersion::V0,
+                "1" => ProtocolVersion::V1,
+                "2" => ProtocolVersion::V2,
+                _ => return None,
+            },
+            compressed: match next()? {
+                "gz" => true,
+                "raw" => false,
+                _ => return None,
+            },
+            bytes: next()?.parse().ok()?,
+            outcome: next()?.to_owned(),
+        })
+    }
+}
+
+/// Records requests into a directory, shared by all services that record into it.
+///
+/// Recorders are cheap to clone, and clones write to the same index. Recorders of different processes must not
+/// share a directory.
+#[derive(Debug, Clone)]
+pub struct Recorder {
+    shared: Arc<Shared>,
+}
+
+#[derive(Debug)]
+struct Shared {
+    options: Options,
+    index: Mutex<()>,
+    next_id: AtomicU64,
+}
+
+impl Recorder {
+    /// Create a recorder writing into the directory of `options`, creating it if needed.
+    pub fn new(options: Options) -> io::Result<Self> {
+        fs::create_dir_all(&options.directory)?;
+        Ok(Recorder {
+            shared: Arc::new(Shared {
+                options,
+                index: Mutex::new(()),
+                next_id: AtomicU64::new(0),
+            }),
+        })
+    }
+
+    /// The options the recorder was created with.
+    pub fn options(&self) -> &Options {
+        &self.shared.options
+    }
+
+    /// Record all requests `service` serves for `repository`.
+    pub fn wrap<S: GitService>(&self, service: S, repository: impl Into<String>) -> Recording<S> {
+        Recording {
+            service,
+            recorder: self.clone(),
+            repository: repository.into(),
+        }
+    }
+
+    /// Read the index, oldest capture first.
+    pub fn entries(&self) -> io::Result<Vec<Entry>> {
+        let _lock = self.shared.index.lock().expect("not poisoned");
+        self.read_index()
+    }
+
+    fn read_index(&self) -> io::Result<Vec<Entry>> {
+        match fs::read_to_string(self.shared.options.directory.join(INDEX_FILE)) {
+            Ok(index) => Ok(index.lines().filter_map(Entry::from_line).collect()),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Create the files of a new capture, returning the entry for it with all fields known before it is served.
+    fn start(&self) -> io::Result<(Entry, CaptureFile, CaptureFile)> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        let options = &self.shared.options;
+        let entry = Entry {
+            id: format!(
+                "{}-{}-{}",
+                now.as_millis(),
+                std::process::id(),
+                self.shared.next_id.fetch_add(1, Ordering::Relaxed)
+            ),
+            timestamp: now.as_secs(),
+            repository: String::new(),
+            service: ServiceKind::UploadPack,
+            version: ProtocolVersion::V0,
+            compressed: options.compress,
+            bytes: 0,
+            outcome: String::new(),
+        };
+        let request_path = entry.request_path(&options.directory);
+        let request = CaptureFile::create(request_path.clone(), options.compress)?;
+        let response = match CaptureFile::create(entry.response_path(&options.directory), options.compress) {
+            Ok(response) => response,
+            Err(err) => {
+                fs::remove_file(request_path).ok();
+                return Err(err);
+            }
+        };
+        Ok((entry, request, response))
+    }
+
+    /// Complete the capture of `entry` and add it to the index, then remove the oldest captures exceeding the limits.
+    fn finish(&self, mut entry: Entry, request: CaptureFile, response: CaptureFile) -> io::Result<()> {
+        let paths = [request.path.clone(), response.path.clone()];
+        let sizes = request.finish().and_then(|request| Ok(request + response.finish()?));
+        entry.bytes = match sizes {
+            Ok(bytes) => bytes,
+            Err(err) => {
+                for path in paths {
+                    fs::remove_file(path).ok();
+                }
+                return Err(err);
+            }
+        };
+
+        let _lock = self.shared.index.lock().expect("not poisoned");
+        let index_path = self.shared.options.directory.join(INDEX_FILE);
+        fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&index_path)?
+            .write_all(entry.to_line().as_bytes())?;
+        self.rotate(&index_path)
+    }
+
+    fn rotate(&self, index_path: &Path) -> io::Result<()> {
+        let options = &self.shared.options;
+        let entries = self.read_index()?;
+        let mut expired = options
+            .max_files
+            .map_or(0, |max_files| entries.len().saturating_sub(max_files));
+        if let Some(max_total_bytes) = options.max_total_bytes {
+            let mut total: u64 = entries[expired..].iter().map(|entry| entry.bytes).sum();
+            while total > max_total_bytes && expired < entries.len() {
+                total -= entries[expired].bytes;
+                expired += 1;
+            }
+        }
+        if expired == 0 {
+            return Ok(());
+        }
+        for entry in &entries[..expired] {
+            for path in [
+                entry.request_path(&options.directory),
+                entry.response_path(&options.directory),
+            ] {
+                match fs::remove_file(path) {
+                    Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+                    _ => {}
+                }
+            }
+        }
+        let index: String = entries[expired..].iter().map(Entry::to_line).collect();
+        let new_index = index_path.with_extension("new");
+        fs::write(&new_index, index)?;
+        fs::rename(new_index, index_path)
+    }
+}
+
+enum Writer {
+    Raw(BufWriter<fs::File>),
+    Gzip(flate2::write::GzEncoder<BufWriter<fs::File>>),
+}
+
+/// A file receiving a copy of one direction of a request, which stops recording at the first error.
+struct CaptureFile {
+    path: PathBuf,
+    writer: Writer,
+    failed: Option<io::Error>,
+}
+
+impl CaptureFile {
+    fn create(path: PathBuf, compress: bool) -> io::Result<Self> {
+        let file = BufWriter::new(fs::File::create(&path)?);
+        let writer = if compress {
+            Writer::Gzip(flate2::write::GzEncoder::new(file, flate2::Compression::default()))
+        } else {
+            Writer::Raw(file)
+        };
+        Ok(CaptureFile {
+            path,
+            writer,
+            failed: None,
+        })
+    }
+
+    fn record(&mut self, data: &[u8]) {
+        if self.failed.is_some() {
+            return;
+        }
+        let result = match &mut self.writer {
+            Writer::Raw(file) => file.write_all(data),
+            Writer::Gzip(file) => file.write_all(data),
+        };
+        self.failed = result.err();
+    }
+
+    /// Write everything to disk and return the size of the file.
+    fn finish(self) -> io::Result<u64> {
+        if let Some(err) = self.failed {
+            return Err(err);
+        }
+        let file = match self.writer {
+            Writer::Raw(file) => file,
+            Writer::Gzip(file) => file.finish()?,
+        };
+        let file = file.into_inner().map_err(io::IntoInnerError::into_error)?;
+        Ok(file.metadata()?.len())
+    }
+}
+
+struct TeeReader<'a> {
+    inner: &'a mut dyn Read,
+    copy: &'a mut CaptureFile,
+}
+
+impl Read for TeeReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.copy.record(&buf[..read]);
+        Ok(read)
+    }
+}
+
+struct TeeWriter<'a> {
+    inner: &'a mut dyn Write,
+    copy: &'a mut CaptureFile,
+}
+
+impl Write for TeeWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.copy.record(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// A [`GitService`] recording the requests it serves, created with [`Recorder::wrap()`].
+pub struct Recording<S> {
+    service: S,
+    recorder: Recorder,
+    repository: String,
+}
+
+impl<S> Recording<S> {
+    /// Return the wrapped service.
+    pub fn into_inner(self) -> S {
+        self.service
+    }
+}
+
+impl<S: GitService> GitService for Recording<S> {
+    fn kind(&self) -> ServiceKind {
+        self.service.kind()
+    }
+
+    fn advertise(&mut self, out: &mut dyn Write, ctx: &ServiceContext) -> Result<(), Error> {
+        self.service.advertise(out, ctx)
+    }
+
+    fn serve(&mut self, input: &mut dyn Read, output: &mut dyn Write, ctx: &ServiceContext) -> Result<Outcome, Error> {
+        let Ok((entry, mut request, mut response)) = self.recorder.start() else {
+            return self.service.serve(input, output, ctx);
+        };
+        let result = self.service.serve(
+            &mut TeeReader {
+                inner: input,
+                copy: &mut request,
+            },
+            &mut TeeWriter {
+                inner: output,
+                copy: &mut response,
+            },
+            ctx,
+        );
+        let entry = Entry {
+            repository: self.repository.clone(),
+            service: self.service.kind(),
+            version: ctx.version,
+            outcome: match &result {
+                Ok(_) => "ok".into(),
+                Err(err) => err.to_string(),
+            },
+            ..entry
+        };
+        self.recorder.finish(entry, request, response).ok();
+        result
+    }
+}
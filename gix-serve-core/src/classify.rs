@@ -0,0 +1,166 @@
+//! Classification of incoming requests from their first bytes, for routing at the edge.
+//!
+//! Front-end proxies often need to know what a client is about to do before handing the connection to a backend,
+//! e.g. to route pushes and fetches differently or to deny one of them early. [`classify()`] looks at a peeked
+//! prefix of the request body without performing any I/O, so callers can use `BufRead::fill_buf()` or a similar
+//! peek and forward the untouched stream afterwards. [`classify_http()`] does the same for smart-HTTP request
+//! targets.
+
+use crate::pktline::MAX_LINE_LEN;
+use crate::protocol::ServiceKind;
+
+/// The kind of request a client sent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RequestKind {
+    /// An upload-pack request of protocol v0 or v1, starting with `want` lines.
+    UploadPackV1,
+    /// A protocol v2 command request, starting with `command=`.
+    V2,
+    /// The head-info of a receive-pack request: ref update commands, `shallow` lines or a push certificate.
+    ReceivePackHeadInfo,
+    /// A single flush packet, sent by upload-pack clients that want nothing and by receive-pack clients that push
+    /// nothing.
+    Flush,
+}
+
+impl RequestKind {
+    /// The service the request is meant for, if it can be known from the request alone.
+    ///
+    /// Protocol v2 commands and flush packets are valid for both services.
+    pub fn service(&self) -> Option<ServiceKind> {
+        match self {
+            RequestKind::UploadPackV1 => Some(ServiceKind::UploadPack),
+            RequestKind::ReceivePackHeadInfo => Some(ServiceKind::ReceivePack),
+            RequestKind::V2 | RequestKind::Flush => None,
+        }
+    }
+}
+
+/// The result of classifying the first bytes of a request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Classification {
+    /// The request is of the given kind.
+    Request(RequestKind),
+    /// More bytes are needed to decide, peek again with a larger buffer.
+    Incomplete,
+    /// The bytes are not the start of a request any service understands.
+    Garbage,
+}
+
+/// Line prefixes that identify a request on their own.
+const PREFIXES: &[(&[u8], RequestKind)] = &[
+    (b"want ", RequestKind::UploadPackV1),
+    (b"command=", RequestKind::V2),
+    (b"shallow ", RequestKind::ReceivePackHeadInfo),
+    (b"push-cert\0", RequestKind::ReceivePackHeadInfo),
+];
+
+/// Classify a request from `peeked`, the first bytes of its pkt-line encoded body.
+///
+/// Only the first pkt-line is inspected, and only as much of it as is needed, so a few dozen bytes usually suffice.
+pub fn classify(peeked: &[u8]) -> Classification {
+    let Some(len) = peeked.get(..4) else {
+        return if peeked.iter().all(u8::is_ascii_hexdigit) {
+            Classification::Incomplete
+        } else {
+            Classification::Garbage
+        };
+    };
+    let Some(len) = parse_hex_len(len) else {
+        return Classification::Garbage;
+    };
+    match len {
+        0 => return Classification::Request(RequestKind::Flush),
+        1..=4 => return Classification::Garbage,
+        len if len > MAX_LINE_LEN => return Classification::Garbage,
+        _ => {}
+    }
+    let line = &peeked[4..peeked.len().min(len)];
+    let complete = peeked.len() >= len;
+
+    let mut undecided = false;
+    for (prefix, kind) in PREFIXES {
+        if line.starts_with(prefix) {
+            return Classification::Request(*kind);
+        }
+        undecided |= !complete && prefix.starts_with(line);
+    }
+    match command_line_prefix(line, complete) {
+        Some(true) => Classification::Request(RequestKind::ReceivePackHeadInfo),
+        Some(false) => Classification::Incomplete,
+        None if undecided => Classification::Incomplete,
+        None => Classification::Garbage,
+    }
+}
+
+/// Return `Some(true)` if `line` starts with `<old-id> <new-id> `, `Some(false)` if it could still turn into such a
+/// line as more bytes arrive, or `None` if it can't be a ref update command.
+fn command_line_prefix(line: &[u8], complete: bool) -> Option<bool> {
+    let first = hex_run(line);
+    if !matches!(first, 40 | 64) {
+        return (!complete && first == line.len() && first < 64).then_some(false);
+    }
+    let rest = &line[first..];
+    match rest.first() {
+        None if !complete => return Some(false),
+        Some(b' ') => {}
+        _ => return None,
+    }
+    let rest = &rest[1..];
+    let second = hex_run(rest);
+    if second == first && rest.get(second) == Some(&b' ') {
+        Some(true)
+    } else if !complete && second == rest.len() && second <= first {
+        Some(false)
+    } else {
+        None
+    }
+}
+
+fn hex_run(bytes: &[u8]) -> usize {
+    bytes
+        .iter()
+        .take_while(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+        .count()
+}
+
+fn parse_hex_len(digits: &[u8]) -> Option<usize> {
+    digits.iter().try_fold(0usize, |acc, digit| {
+        let value = (*digit as char).to_digit(16)?;
+        Some(acc * 16 + value as usize)
+    })
+}
+
+/// A smart-HTTP request, as identified by its request target.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HttpRequest {
+    /// The service the request is for.
+    pub service: ServiceKind,
+    /// `true` for the ref advertisement (`GET .../info/refs?service=...`), `false` for the service request itself
+    /// (`POST .../git-upload-pack` or `POST .../git-receive-pack`).
+    pub advertisement: bool,
+}
+
+/// Classify a smart-HTTP request by its `target`, the path and query of the request line.
+///
+/// Returns `None` for targets that aren't smart-HTTP requests, like dumb-HTTP object downloads.
+pub fn classify_http(target: &str) -> Option<HttpRequest> {
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let service = |name: &str| match name {
+        "git-upload-pack" => Some(ServiceKind::UploadPack),
+        "git-receive-pack" => Some(ServiceKind::ReceivePack),
+        _ => None,
+    };
+    if path.ends_with("/info/refs") {
+        let name = query.split('&').find_map(|pair| pair.strip_prefix("service="))?;
+        return Some(HttpRequest {
+            service: service(name)?,
+            advertisement: true,
+        });
+    }
+    let (_, name) = path.rsplit_once('/')?;
+    Some(HttpRequest {
+        service: service(name)?,
+        advertisement: false,
+    })
+}
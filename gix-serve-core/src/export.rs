@@ -0,0 +1,160 @@
+//! Decide which repositories may be served, like `git daemon` does.
+//!
+//! Front-ends like a daemon, SSH or HTTP receive a path from the client and have to map it to a repository
+//! before opening it. [`ExportPolicy::resolve()`] does that for paths relative to a base directory: it refuses
+//! paths that would leave the base directory, tries the same suffixes as `git` to find the repository, and
+//! optionally requires the repository to be bare and to contain a `git-daemon-export-ok` file.
+
+use std::path::{Component, Path, PathBuf};
+
+/// The file in a repository that allows it to be served if [`ExportPolicy::with_export_ok_required()`] is set.
+pub const EXPORT_OK_FILE: &str = "git-daemon-export-ok";
+
+/// The suffixes tried in order to find the repository for a requested path, matching `enter_repo()` of `git`.
+const SUFFIXES: &[&str] = &["/.git", "", ".git/.git", ".git"];
+
+/// The error returned by [`ExportPolicy::resolve()`].
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// The requested path isn't inside the base directory, or contains components like `..`.
+    #[error("'{path}' is outside of the served directory")]
+    OutsideBase {
+        /// The path as requested by the client.
+        path: String,
+    },
+    /// No repository was found at the requested path.
+    #[error("'{path}' does not appear to be a git repository")]
+    NotFound {
+        /// The path as requested by the client.
+        path: String,
+    },
+    /// The repository doesn't contain a [`EXPORT_OK_FILE`].
+    #[error("repository '{path}' not exported")]
+    NotExported {
+        /// The path as requested by the client.
+        path: String,
+    },
+    /// The repository has a work tree, but only bare repositories may be served.
+    #[error("repository '{path}' is not bare")]
+    NotBare {
+        /// The path as requested by the client.
+        path: String,
+    },
+    /// The filesystem couldn't be accessed.
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+impl Error {
+    /// Return the message `git daemon` sends in an `ERR` packet for `requested`, the path as requested by the client.
+    ///
+    /// Unless `informative`, all refusals read the same so clients can't tell which repositories exist, like with
+    /// `git daemon` without `--informative-errors`. Write it with [`write_error()`](crate::pktline::write_error()).
+    pub fn daemon_message(&self, requested: &str, informative: bool) -> String {
+        let reason = match self {
+            _ if !informative => "access denied or repository not exported",
+            Error::NotExported { .. } => "repository not exported",
+            Error::OutsideBase { .. } | Error::NotFound { .. } | Error::NotBare { .. } | Error::Io(_) => {
+                "no such repository"
+            }
+        };
+        format!("{reason}: {requested}")
+    }
+
+    /// Return the status code a smart-HTTP front-end should answer with, where all refusals are reported as
+    /// not found, like `git http-backend` does, so clients can't tell which repositories exist.
+    #[cfg(feature = "hyper")]
+    pub fn status_code(&self) -> http::StatusCode {
+        match self {
+            Error::Io(_) => http::StatusCode::INTERNAL_SERVER_ERROR,
+            _ => http::StatusCode::NOT_FOUND,
+        }
+    }
+}
+
+/// A repository that may be served, as resolved by [`ExportPolicy::resolve()`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Export {
+    /// The canonicalized `git` directory of the repository.
+    pub git_dir: PathBuf,
+    /// Whether the repository is bare, as far as can be told without reading its configuration.
+    pub bare: bool,
+}
+
+/// Which repositories below a base directory may be served.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExportPolicy {
+    base: PathBuf,
+    export_ok_required: bool,
+    bare_required: bool,
+}
+
+impl ExportPolicy {
+    /// Serve repositories below `base`, without requiring them to be bare or exported.
+    pub fn new(base: impl Into<PathBuf>) -> Self {
+        Self {
+            base: base.into(),
+            export_ok_required: false,
+            bare_required: false,
+        }
+    }
+
+    /// Only serve repositories that contain a [`EXPORT_OK_FILE`] if `required` is `true`, like `git daemon` does
+    /// unless `--export-all` is given.
+    pub fn with_export_ok_required(mut self, required: bool) -> Self {
+        self.export_ok_required = required;
+        self
+    }
+
+    /// Only serve bare repositories if `required` is `true`.
+    pub fn with_bare_required(mut self, required: bool) -> Self {
+        self.bare_required = required;
+        self
+    }
+
+    /// Return the directory below which repositories are served.
+    pub fn base(&self) -> &Path {
+        &self.base
+    }
+
+    /// Resolve `path` as sent by a client, like `/project.git` or `group/project`, to the repository to serve.
+    ///
+    /// Paths are always relative to the base directory, leading slashes are ignored. Paths with `..` components
+    /// are refused before the filesystem is accessed, and the repository found is checked to still be inside
+    /// the base directory once symbolic links are resolved.
+    pub fn resolve(&self, path: &str) -> Result<Export, Error> {
+        let outside = || Error::OutsideBase { path: path.into() };
+        let relative = Path::new(path.trim_start_matches('/'));
+        if !relative
+            .components()
+            .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+        {
+            return Err(outside());
+        }
+
+        let base = self.base.canonicalize()?;
+        let requested = base.join(relative);
+        let (git_dir, kind) = SUFFIXES
+            .iter()
+            .find_map(|suffix| {
+                let mut candidate = requested.clone().into_os_string();
+                candidate.push(suffix);
+                let candidate = PathBuf::from(candidate);
+                gix::discover::is_git(&candidate).ok().map(|kind| (candidate, kind))
+            })
+            .ok_or_else(|| Error::NotFound { path: path.into() })?;
+        let git_dir = git_dir.canonicalize()?;
+        if !git_dir.starts_with(&base) {
+            return Err(outside());
+        }
+
+        let bare = matches!(kind, gix::discover::repository::Kind::PossiblyBare);
+        if self.bare_required && !bare {
+            return Err(Error::NotBare { path: path.into() });
+        }
+        if self.export_ok_required && !git_dir.join(EXPORT_OK_FILE).is_file() {
+            return Err(Error::NotExported { path: path.into() });
+        }
+        Ok(Export { git_dir, bare })
+    }
+}
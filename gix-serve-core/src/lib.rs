@@ -10,13 +10,27 @@
 compile_error!("Cannot enable both 'blocking-io' and 'async-io' features for gix-serve-core");
 
 pub mod service;
+pub mod audit;
+pub mod metrics;
 pub mod protocol;
 pub mod visibility;
 pub mod advertise;
 pub mod capabilities;
+pub mod classify;
+pub mod export;
+pub mod proxy;
 pub mod pktline;
+pub mod wire;
 #[cfg(feature = "progress")]
 pub mod progress;
+#[cfg(feature = "testing")]
+pub mod testing;
+#[cfg(feature = "hyper")]
+pub mod smart_http;
+#[cfg(feature = "capture")]
+pub mod capture;
+#[cfg(feature = "rustls")]
+pub mod tls;
 
 // IO helpers are feature-gated to match the selected I/O mode.
 #[cfg(feature = "blocking-io")]
@@ -0,0 +1,199 @@
+//! Counters and timings of sessions for hosting integrations, like exporters of Prometheus metrics.
+//!
+//! Services report what happens in each session to the [`Metrics`] of a [`Meter`]: when it starts and ends along
+//! with the bytes it exchanged, how long each [`Phase`] took, the rounds of negotiation, and the packs sent or
+//! received. The meter counts the sessions served at once, so implementations get concurrency without their own
+//! bookkeeping. All callbacks do nothing by default, so implementations only override what they collect.
+//!
+//! Nothing is measured by default.
+
+use crate::protocol::ServiceKind;
+use crate::wire::WireStats;
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// A part of a session whose duration is measured.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Phase {
+    /// Advertising refs and capabilities.
+    Handshake,
+    /// Receiving wants and acknowledging haves of a fetch.
+    Negotiation,
+    /// Counting objects and sending the pack of a fetch.
+    PackGeneration,
+    /// Receiving and storing the pack of a push.
+    Ingestion,
+}
+
+impl Phase {
+    /// The name of the phase, like `pack-generation`.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Phase::Handshake => "handshake",
+            Phase::Negotiation => "negotiation",
+            Phase::PackGeneration => "pack-generation",
+            Phase::Ingestion => "ingestion",
+        }
+    }
+}
+
+impl fmt::Display for Phase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// A destination for the measurements of sessions, whose callbacks do nothing unless overridden.
+#[allow(unused_variables)]
+pub trait Metrics: Send + Sync {
+    /// A session of `service` started, which is one of `active` sessions served at once.
+    fn session_started(&self, service: ServiceKind, active: usize) {}
+
+    /// A session of `service` ended after `duration`, having exchanged `wire`, leaving `active` sessions.
+    ///
+    /// `success` is `false` if it failed, in which case `wire` may not cover all bytes.
+    fn session_finished(
+        &self,
+        service: ServiceKind,
+        wire: &WireStats,
+        duration: Duration,
+        success: bool,
+        active: usize,
+    ) {
+    }
+
+    /// `phase` of a session of `service` took `duration`.
+    fn phase(&self, service: ServiceKind, phase: Phase, duration: Duration) {}
+
+    /// A round of negotiation of a session of `service` ended, like a batch of haves that was acknowledged.
+    fn negotiation_round(&self, service: ServiceKind) {}
+
+    /// A pack of `objects` objects and `bytes` bytes was sent to a client.
+    fn pack_sent(&self, objects: u64, bytes: u64) {}
+
+    /// A pack of `objects` objects and `bytes` bytes was received from a client.
+    fn pack_received(&self, objects: u64, bytes: u64) {}
+}
+
+/// A cheaply clonable handle to [`Metrics`], which measures nothing by default.
+///
+/// Clones share the count of active sessions.
+#[derive(Clone, Default)]
+pub struct Meter(Option<(Arc<dyn Metrics>, Arc<AtomicUsize>)>);
+
+impl Meter {
+    /// Create a meter passing measurements to `metrics`.
+    pub fn new(metrics: impl Metrics + 'static) -> Self {
+        Meter(Some((Arc::new(metrics), Arc::default())))
+    }
+
+    /// Return `true` if measurements are passed to metrics.
+    pub fn is_enabled(&self) -> bool {
+        self.0.is_some()
+    }
+
+
+    /// Start measuring a session of `service`, which counts as active until it's [finished](Session::finish()) or
+    /// dropped.
+    pub fn session(&self, service: ServiceKind) -> Session {
+        if let Some((metrics, active)) = &self.0 {
+            metrics.session_started(service, active.fetch_add(1, Ordering::SeqCst) + 1);
+        }
+        Session {
+            meter: self.clone(),
+            service,
+            start: Instant::now(),
+            finished: false,
+        }
+    }
+
+    /// Start measuring `phase` of a session of `service`, which ends when the returned timer is dropped.
+    pub fn phase(&self, service: ServiceKind, phase: Phase) -> PhaseTimer {
+        PhaseTimer {
+            meter: self.clone(),
+            service,
+            phase,
+            start: Instant::now(),
+        }
+    }
+
+    fn metrics(&self) -> Option<&dyn Metrics> {
+        self.0.as_ref().map(|(metrics, _)| metrics.as_ref())
+    }
+
+    /// Report that a round of negotiation of a session of `service` ended, see [`Metrics::negotiation_round()`].
+    pub fn negotiation_round(&self, service: ServiceKind) {
+        if let Some(metrics) = self.metrics() {
+            metrics.negotiation_round(service);
+        }
+    }
+
+    /// Report that a pack of `objects` objects and `bytes` bytes was sent, see [`Metrics::pack_sent()`].
+    pub fn pack_sent(&self, objects: u64, bytes: u64) {
+        if let Some(metrics) = self.metrics() {
+            metrics.pack_sent(objects, bytes);
+        }
+    }
+
+    /// Report that a pack of `objects` objects and `bytes` bytes was received, see [`Metrics::pack_received()`].
+    pub fn pack_received(&self, objects: u64, bytes: u64) {
+        if let Some(metrics) = self.metrics() {
+            metrics.pack_received(objects, bytes);
+        }
+    }
+}
+
+impl fmt::Debug for Meter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(if self.0.is_some() { "Meter" } else { "Meter(disabled)" })
+    }
+}
+
+/// A session measured by a [`Meter`], which fails if it's dropped before it's [finished](Self::finish()).
+pub struct Session {
+    meter: Meter,
+    service: ServiceKind,
+    start: Instant,
+    finished: bool,
+}
+
+impl Session {
+    /// End the session, which exchanged `wire` and failed unless `success`.
+    pub fn finish(mut self, wire: &WireStats, success: bool) {
+        self.report(wire, success);
+    }
+
+    fn report(&mut self, wire: &WireStats, success: bool) {
+        self.finished = true;
+        if let Some((metrics, active)) = &self.meter.0 {
+            let active = active.fetch_sub(1, Ordering::SeqCst) - 1;
+            metrics.session_finished(self.service, wire, self.start.elapsed(), success, active);
+        }
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        if !self.finished {
+            self.report(&WireStats::default(), false);
+        }
+    }
+}
+
+/// Measures a [`Phase`] until it's dropped, see [`Meter::phase()`].
+pub struct PhaseTimer {
+    meter: Meter,
+    service: ServiceKind,
+    phase: Phase,
+    start: Instant,
+}
+
+impl Drop for PhaseTimer {
+    fn drop(&mut self) {
+        if let Some(metrics) = self.meter.metrics() {
+            metrics.phase(self.service, self.phase, self.start.elapsed());
+        }
+    }
+}
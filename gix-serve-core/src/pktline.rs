@@ -12,6 +12,9 @@ pub use pkt::{
     Channel as SidebandChannel, PacketLineRef, StreamingPeekableIter as PktIter, Writer as PktWriter,
 };
 
+/// The largest pkt-line length, including the 4 byte length prefix.
+pub const MAX_LINE_LEN: usize = 65520;
+
 /// Write a flush packet.
 #[cfg(feature = "blocking-io")]
 pub fn write_flush<W: std::io::Write>(w: &mut PktWriter<W>) -> std::io::Result<()> {
@@ -43,3 +46,9 @@ pub fn write_sideband_error<W: std::io::Write>(w: &mut PktWriter<W>, msg: &[u8])
     use std::io::Write;
     w.write_all(msg).map(|_| ())
 }
+
+/// Write an `ERR` packet with `msg`, which makes clients abort and show `msg` to the user.
+#[cfg(feature = "blocking-io")]
+pub fn write_error<W: std::io::Write>(w: &mut PktWriter<W>, msg: &[u8]) -> std::io::Result<()> {
+    pkt::encode::error_to_write(msg, w.inner_mut()).map(|_| ())
+}
@@ -20,6 +20,29 @@ pub enum ProtocolVersion {
     V2,
 }
 
+impl ProtocolVersion {
+    /// Return the highest version requested by the `version=<n>` parameters of `value`, the colon-separated
+    /// parameters of the `GIT_PROTOCOL` environment variable or the `Git-Protocol` HTTP header, or `V0` if there is
+    /// none.
+    pub fn from_git_protocol(value: &str) -> Self {
+        let version = value
+            .split(':')
+            .filter_map(|parameter| parameter.strip_prefix("version="))
+            .filter_map(|version| match version {
+                "0" => Some(0),
+                "1" => Some(1),
+                "2" => Some(2),
+                _ => None,
+            })
+            .max();
+        match version {
+            Some(2) => ProtocolVersion::V2,
+            Some(1) => ProtocolVersion::V1,
+            _ => ProtocolVersion::V0,
+        }
+    }
+}
+
 /// A server request encapsulating the context and I/O streams.
 pub struct ServerRequest<'a, R, W> {
     /// Which service to invoke.
@@ -0,0 +1,159 @@
+//! Read the PROXY protocol header load balancers send ahead of the client's data.
+//!
+//! Load balancers like HAProxy terminate the client's TCP connection and open their own to the server, so the peer
+//! address a listener sees is the one of the load balancer. With the PROXY protocol, they first send the address of
+//! the client, in the text format of version 1 or the binary format of version 2. [`read_header()`] consumes
+//! exactly that header, so the git protocol can be read from the same stream right after, and
+//! [`Header::remote_addr()`] yields the address to put into
+//! [`ServiceContext::with_remote_addr()`](crate::service::ServiceContext::with_remote_addr()).
+//!
+//! Only expect headers on listeners that can't be reached without passing the load balancer, as clients connecting
+//! directly could claim any address otherwise.
+
+use std::io::Read;
+use std::net::{IpAddr, SocketAddr};
+
+/// The signature starting a version 2 header.
+const V2_SIGNATURE: &[u8; 12] = b"\r\n\r\n\0\r\nQUIT\n";
+
+/// The longest version 1 header, including the trailing CRLF.
+const V1_MAX_LEN: usize = 107;
+
+/// The error returned by [`read_header()`].
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// The connection didn't start with a PROXY protocol header.
+    #[error("connection does not start with a PROXY protocol header")]
+    Missing,
+    /// The header didn't follow the specification.
+    #[error("invalid PROXY protocol header: {0}")]
+    Invalid(&'static str),
+    /// The header couldn't be read.
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+/// The version of the PROXY protocol a [`Header`] was sent with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Version {
+    /// The human-readable text format.
+    V1,
+    /// The binary format.
+    V2,
+}
+
+/// A PROXY protocol header, as read by [`read_header()`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Header {
+    /// The version the header was sent with.
+    pub version: Version,
+    /// The address of the client, or `None` if the load balancer didn't proxy a client connection, like for its
+    /// own health checks, or used an address family other than IPv4 and IPv6.
+    pub source: Option<SocketAddr>,
+    /// The address the client connected to, if `source` is known.
+    pub destination: Option<SocketAddr>,
+}
+
+impl Header {
+    /// Return the address of the client, or `peer`, the address of the connection itself, if it isn't known.
+    pub fn remote_addr(&self, peer: SocketAddr) -> SocketAddr {
+        self.source.unwrap_or(peer)
+    }
+}
+
+/// Read a PROXY protocol header of either version from `input`, without reading any of the data following it.
+pub fn read_header(input: &mut impl Read) -> Result<Header, Error> {
+    // Version 1 headers are at least 15 bytes long, so the first 12 bytes can always be read
+    let mut start = [0; 12];
+    input.read_exact(&mut start)?;
+    if &start == V2_SIGNATURE {
+        read_v2(input)
+    } else if start.starts_with(b"PROXY ") {
+        read_v1(input, &start)
+    } else {
+        Err(Error::Missing)
+    }
+}
+
+/// Read the rest of a version 1 header starting with `start`.
+fn read_v1(input: &mut impl Read, start: &[u8]) -> Result<Header, Error> {
+    let mut line = start.to_vec();
+    while !line.ends_with(b"\r\n") {
+        if line.len() == V1_MAX_LEN {
+            return Err(Error::Invalid("header line is too long"));
+        }
+        let mut byte = [0];
+        input.read_exact(&mut byte)?;
+        line.push(byte[0]);
+    }
+    let line = std::str::from_utf8(&line[..line.len() - 2]).map_err(|_| Error::Invalid("header isn't ASCII"))?;
+    let fields: Vec<_> = line.split(' ').collect();
+    let unknown = Header {
+        version: Version::V1,
+        source: None,
+        destination: None,
+    };
+    let ipv6 = match fields.get(1) {
+        Some(&"UNKNOWN") => return Ok(unknown),
+        Some(&"TCP4") => false,
+        Some(&"TCP6") => true,
+        _ => return Err(Error::Invalid("unsupported protocol")),
+    };
+    let [_, _, source, destination, source_port, destination_port] = fields[..] else {
+        return Err(Error::Invalid("wrong number of fields"));
+    };
+    let addr = |ip: &str, port: &str| -> Result<SocketAddr, Error> {
+        let ip: IpAddr = ip.parse().map_err(|_| Error::Invalid("invalid address"))?;
+        let port: u16 = port.parse().map_err(|_| Error::Invalid("invalid port"))?;
+        if ip.is_ipv6() != ipv6 {
+            return Err(Error::Invalid("address doesn't match the protocol"));
+        }
+        Ok(SocketAddr::new(ip, port))
+    };
+    Ok(Header {
+        source: Some(addr(source, source_port)?),
+        destination: Some(addr(destination, destination_port)?),
+        ..unknown
+    })
+}
+
+/// Read the rest of a version 2 header after its signature.
+fn read_v2(input: &mut impl Read) -> Result<Header, Error> {
+    let mut fixed = [0; 4];
+    input.read_exact(&mut fixed)?;
+    let [version_command, family, len @ ..] = fixed;
+    let mut data = vec![0; u16::from_be_bytes(len) as usize];
+    input.read_exact(&mut data)?;
+    if version_command >> 4 != 2 {
+        return Err(Error::Invalid("unsupported version"));
+    }
+    let local = Header {
+        version: Version::V2,
+        source: None,
+        destination: None,
+    };
+    match version_command & 0xf {
+        0 => return Ok(local),
+        1 => {}
+        _ => return Err(Error::Invalid("unsupported command")),
+    }
+    let port = |at: usize| u16::from_be_bytes([data[at], data[at + 1]]);
+    let (source, destination) = match family >> 4 {
+        1 if data.len() >= 12 => {
+            let ip = |at: usize| IpAddr::from(<[u8; 4]>::try_from(&data[at..at + 4]).expect("in bounds"));
+            (SocketAddr::new(ip(0), port(8)), SocketAddr::new(ip(4), port(10)))
+        }
+        2 if data.len() >= 36 => {
+            let ip = |at: usize| IpAddr::from(<[u8; 16]>::try_from(&data[at..at + 16]).expect("in bounds"));
+            (SocketAddr::new(ip(0), port(32)), SocketAddr::new(ip(16), port(34)))
+        }
+        1 | 2 => return Err(Error::Invalid("addresses are truncated")),
+        // Unspecified and Unix socket addresses can't be represented, like the unknown protocol of version 1
+        _ => return Ok(local),
+    };
+    Ok(Header {
+        source: Some(source),
+        destination: Some(destination),
+        ..local
+    })
+}
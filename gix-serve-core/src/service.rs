@@ -1,6 +1,13 @@
-//! Service trait implemented by server-side protocol handlers.
+//! Service traits implemented by server-side protocol handlers.
+//!
+//! [`GitService`] is the uniform interface both `gix-upload-pack` and `gix-receive-pack` implement, so orchestrators,
+//! HTTP adapters and tests can drive either service through a `Box<dyn GitService>` without knowing which one it is.
 
-use crate::protocol::ServerRequest;
+use crate::advertise::Fingerprint;
+use crate::protocol::{ProtocolVersion, ServerRequest, ServiceKind};
+use crate::wire::WireStats;
+use std::io::{Read, Write};
+use std::net::SocketAddr;
 
 /// The error type used by services in this crate.
 ///
@@ -28,4 +35,101 @@ pub trait Service<R, W> {
     fn handle(&mut self, req: ServerRequest<'_, R, W>) -> Result<(), Error>;
 }
 
+/// Information about the request a [`GitService`] handles.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServiceContext {
+    /// The protocol version requested by the client.
+    pub version: ProtocolVersion,
+    /// Whether the transport is stateless (HTTP), so the advertisement was sent in an earlier request.
+    pub stateless: bool,
+    /// Optional trace identifier for correlation.
+    pub trace_id: Option<String>,
+    /// The authenticated identity of the client, if the transport authenticated it.
+    pub principal: Option<String>,
+    /// The address of the client, if known, like from a [PROXY protocol header](crate::proxy::read_header()).
+    pub remote_addr: Option<SocketAddr>,
+}
+
+impl ServiceContext {
+    /// Create a context for a stateful connection using protocol `version`.
+    pub fn new(version: ProtocolVersion) -> Self {
+        ServiceContext {
+            version,
+            stateless: false,
+            trace_id: None,
+            principal: None,
+            remote_addr: None,
+        }
+    }
+
+    /// Set whether the transport is stateless.
+    pub fn with_stateless(mut self, stateless: bool) -> Self {
+        self.stateless = stateless;
+        self
+    }
+
+    /// Set the trace identifier for correlation.
+    pub fn with_trace_id(mut self, trace_id: impl Into<String>) -> Self {
+        self.trace_id = Some(trace_id.into());
+        self
+    }
+
+    /// Set the authenticated identity of the client, for services to use in [audit records](crate::audit::Denial).
+    pub fn with_principal(mut self, principal: impl Into<String>) -> Self {
+        self.principal = Some(principal.into());
+        self
+    }
+
+    /// Set the address of the client, for services to use in rate limits and logs.
+    pub fn with_remote_addr(mut self, remote_addr: SocketAddr) -> Self {
+        self.remote_addr = Some(remote_addr);
+        self
+    }
+}
+
+/// The status of a ref update requested by the client.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RefUpdate {
+    /// The full name of the ref.
+    pub name: String,
+    /// `None` if the update was applied, or the reason it was rejected.
+    pub rejected: Option<String>,
+}
+
+/// What a [`GitService`] did while serving a request.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Outcome {
+    /// The ref updates requested by the client in the order they were received, empty for services that don't
+    /// update refs.
+    pub ref_updates: Vec<RefUpdate>,
+    /// The bytes exchanged with the client while serving the request.
+    pub wire: WireStats,
+}
+
+/// A service that serves requests over arbitrary byte streams, implemented by upload-pack and receive-pack.
+///
+/// Unlike [`Service`], this trait is object safe, so services can be stored and routed to as `Box<dyn GitService>`.
+pub trait GitService {
+    /// The kind of service.
+    fn kind(&self) -> ServiceKind;
+
+    /// Write the advertisement that starts a conversation using `ctx`, like refs and capabilities for protocol v0 and
+    /// v1, or the capabilities for protocol v2.
+    ///
+    /// Stateless transports send it in response to their first request, like `GET /info/refs` over HTTP.
+    fn advertise(&mut self, out: &mut dyn Write, ctx: &ServiceContext) -> Result<(), Error>;
 
+    /// Compute the [`Fingerprint`] of the advertisement [`advertise()`](Self::advertise()) would write for `ctx`,
+    /// without writing it, or return `None` if the service can't tell.
+    ///
+    /// It must change whenever the advertisement changes, so clients polling for changes can be told nothing changed
+    /// without generating the advertisement again. Services don't compute one by default.
+    fn advertisement_fingerprint(&mut self, _ctx: &ServiceContext) -> Result<Option<Fingerprint>, Error> {
+        Ok(None)
+    }
+
+    /// Serve a request read from `input`, writing the response to `output`.
+    ///
+    /// If `ctx` isn't stateless, the service starts with the advertisement itself and serves the whole connection.
+    fn serve(&mut self, input: &mut dyn Read, output: &mut dyn Write, ctx: &ServiceContext) -> Result<Outcome, Error>;
+}
@@ -0,0 +1,502 @@
+//! A ready-made [`tower_service::Service`] for the smart-HTTP endpoints, serving any [`GitService`].
+//!
+//! [`SmartHttp`] answers requests like `git http-backend` does: `GET .../info/refs?service=<name>` with the
+//! advertisement, and `POST .../git-upload-pack` or `POST .../git-receive-pack` with the response to a stateless
+//! request. Which service handles a request is decided by a resolver that is called with the repository part of the
+//! path, like `/org/repo.git`, so embedders stay in charge of locating repositories and of access control.
+//!
+//! Request bodies compressed with `gzip`, as git sends larger negotiations, are decompressed transparently. Services
+//! run on tokio's blocking thread pool and read the request body as it arrives, and their output is streamed to the
+//! client as it is produced, which makes hyper use chunked transfer encoding. Hence the service has to be called from
+//! within a tokio runtime. Request bodies are [limited in size](SmartHttp::with_max_request_size()) before and after
+//! decompression.
+//!
+//! The protocol version is taken from each request's `Git-Protocol` header, or from a `git-protocol` query parameter
+//! holding the same value for clients behind proxies that drop unknown headers, and passed to the service with its
+//! [`ServiceContext`]. Clients of different versions can thus be served concurrently by the same process. As responses
+//! differ by version, they carry `Vary: Git-Protocol`, and caching proxies in front of the service must include the
+//! header in their cache key if they cache responses despite the `no-cache` directives.
+//!
+//! Advertisements of services that compute a
+//! [fingerprint](crate::service::GitService::advertisement_fingerprint()) carry it as `ETag`, and requests listing
+//! it in `If-None-Match` are answered with `304 Not Modified` without generating the advertisement, which keeps
+//! clients polling for changes, like CI systems, cheap to serve.
+//!
+//! To serve with hyper, adapt it with `hyper_util::service::TowerToHyperService`.
+
+use crate::classify::{classify_http, HttpRequest};
+use crate::protocol::{ProtocolVersion, ServiceKind};
+use crate::service::{self, GitService, ServiceContext};
+use bytes::{Buf, Bytes};
+use http::{header, HeaderMap, HeaderValue, Method, Request, Response, StatusCode};
+use http_body::Frame;
+use http_body_util::BodyExt;
+use std::convert::Infallible;
+use std::future::Future;
+use std::io::{Read, Write};
+use std::pin::Pin;
+use std::sync::{Arc, OnceLock};
+use std::task::{Context, Poll};
+use tokio::sync::mpsc;
+
+/// The amount of service output collected before it is sent to the client as a chunk.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// The largest upload-pack request body accepted by default, before and after decompression, like the default of
+/// `GIT_HTTP_MAX_REQUEST_BUFFER` of `git http-backend`.
+pub const DEFAULT_MAX_REQUEST_SIZE: usize = 10 * 1024 * 1024;
+
+/// The status and message to answer with instead of the service error, if the request body couldn't be read.
+type Rejection = Arc<OnceLock<(StatusCode, String)>>;
+
+/// Serve the smart-HTTP endpoints with the services returned by a resolver `F`, see the
+/// [module documentation](self).
+///
+/// The resolver is called with the repository part of the request path and the kind of service the client asked
+/// for. It returns the service to handle the request, or the status to answer with instead, like
+/// [`StatusCode::NOT_FOUND`] for unknown repositories or [`StatusCode::FORBIDDEN`] for disabled services.
+/// [`ExportPolicy::resolve()`](crate::export::ExportPolicy::resolve()) maps the repository part to a repository
+/// that may be served, and its errors to a [status code](crate::export::Error::status_code()).
+pub struct SmartHttp<F> {
+    resolve: Arc<F>,
+    max_request_size: Option<usize>,
+}
+
+impl<F> Clone for SmartHttp<F> {
+    fn clone(&self) -> Self {
+        SmartHttp {
+            resolve: self.resolve.clone(),
+            max_request_size: self.max_request_size,
+        }
+    }
+}
+
+impl<F> SmartHttp<F>
+where
+    F: Fn(&str, ServiceKind) -> Result<Box<dyn GitService + Send>, StatusCode> + Send + Sync + 'static,
+{
+    /// Create a service which lets `resolve` pick the [`GitService`] for each request.
+    pub fn new(resolve: F) -> Self {
+        SmartHttp {
+            resolve: Arc::new(resolve),
+            max_request_size: None,
+        }
+    }
+
+    /// Answer requests whose body is larger than `bytes`, before or after decompression, with
+    /// [`StatusCode::PAYLOAD_TOO_LARGE`].
+    ///
+    /// Like with `git http-backend`, upload-pack requests are limited to [`DEFAULT_MAX_REQUEST_SIZE`] by default, and
+    /// receive-pack requests aren't limited as they carry the pack, which receive-pack limits itself.
+    pub fn with_max_request_size(mut self, bytes: usize) -> Self {
+        self.max_request_size = Some(bytes);
+        self
+    }
+
+    async fn handle<B>(self, request: Request<B>) -> Response<ResponseBody>
+    where
+        B: http_body::Body + Send + 'static,
+        B::Data: Send,
+        B::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+    {
+        let uri = request.uri();
+        let Some(http) = classify_http(uri.path_and_query().map_or(uri.path(), |target| target.as_str())) else {
+            return status(StatusCode::NOT_FOUND, "Not Found");
+        };
+        let expected_method = if http.advertisement { Method::GET } else { Method::POST };
+        if request.method() != expected_method {
+            return status(StatusCode::METHOD_NOT_ALLOWED, "Method Not Allowed");
+        }
+        let repository = match uri.path().strip_suffix("/info/refs") {
+            Some(repository) if http.advertisement => repository,
+            _ => uri.path().rsplit_once('/').map_or("", |(repository, _)| repository),
+        };
+        let service = match (self.resolve)(repository, http.service) {
+            Ok(service) => service,
+            Err(code) => return status(code, code.canonical_reason().unwrap_or_default()),
+        };
+        let version = protocol_version(request.headers(), uri.query());
+        let ctx = ServiceContext::new(version).with_stateless(true);
+        if http.advertisement {
+            let if_none_match = request
+                .headers()
+                .get(header::IF_NONE_MATCH)
+                .and_then(|value| value.to_str().ok())
+                .map(ToOwned::to_owned);
+            advertise(service, http, ctx, if_none_match).await
+        } else {
+            self.serve(service, http, ctx, request).await
+        }
+    }
+
+    async fn serve<B>(
+        &self,
+        mut service: Box<dyn GitService + Send>,
+        http: HttpRequest,
+        ctx: ServiceContext,
+        request: Request<B>,
+    ) -> Response<ResponseBody>
+    where
+        B: http_body::Body + Send + 'static,
+        B::Data: Send,
+        B::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+    {
+        let name = service_name(http.service);
+        let headers = request.headers();
+        if headers.get(header::CONTENT_TYPE).and_then(|value| value.to_str().ok())
+            != Some(&format!("application/x-{name}-request"))
+        {
+            return status(StatusCode::UNSUPPORTED_MEDIA_TYPE, "Unsupported Media Type");
+        }
+        let gzip = match headers.get(header::CONTENT_ENCODING).map(HeaderValue::as_bytes) {
+            None | Some(b"identity") => false,
+            Some(b"gzip" | b"x-gzip") => true,
+            Some(_) => return status(StatusCode::UNSUPPORTED_MEDIA_TYPE, "Unsupported Content-Encoding"),
+        };
+        let limit = self
+            .max_request_size
+            .or((http.service == ServiceKind::UploadPack).then_some(DEFAULT_MAX_REQUEST_SIZE))
+            .unwrap_or(usize::MAX);
+        let rejection = Rejection::default();
+        let (body_tx, body_rx) = mpsc::channel(16);
+        tokio::spawn(forward(request.into_body(), body_tx, rejection.clone()));
+
+        let (tx, mut rx) = mpsc::channel(16);
+        let task = tokio::task::spawn_blocking({
+            let rejection = rejection.clone();
+            move || {
+                let body = ChannelReader {
+                    rx: body_rx,
+                    chunk: Bytes::new(),
+                };
+                let body = SizeLimit::new(body, limit, rejection.clone());
+                let mut input: Box<dyn Read> = if gzip {
+                    Box::new(SizeLimit::new(flate2::read::GzDecoder::new(body), limit, rejection))
+                } else {
+                    Box::new(body)
+                };
+                let mut output = ChannelWriter { tx, buf: Vec::new() };
+                let result = service
+                    .serve(&mut input, &mut output, &ctx)
+                    .and_then(|_| Ok(output.flush()?));
+                if let Err(err) = result {
+                    output.buf.clear();
+                    output.tx.blocking_send(Err(err)).ok();
+                }
+            }
+        });
+
+        // Wait for the first output so that failures before the response started are reported with a status.
+        let first = match rx.recv().await {
+            Some(Ok(chunk)) => Some(chunk),
+            Some(Err(err)) => {
+                return match rejection.get() {
+                    Some((code, message)) => status(*code, message),
+                    None => error(err),
+                }
+            }
+            None => match task.await {
+                Ok(()) => None,
+                Err(err) => {
+                    return status(
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        &format!("The service panicked: {err}"),
+                    )
+                }
+            },
+        };
+        let content_type = format!("application/x-{name}-result");
+        response(&content_type, ResponseBody(Inner::Stream { first, rx }))
+    }
+}
+
+impl<F, B> tower_service::Service<Request<B>> for SmartHttp<F>
+where
+    F: Fn(&str, ServiceKind) -> Result<Box<dyn GitService + Send>, StatusCode> + Send + Sync + 'static,
+    B: http_body::Body + Send + 'static,
+    B::Data: Send,
+    B::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    type Response = Response<ResponseBody>;
+    type Error = Infallible;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Infallible>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Infallible>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: Request<B>) -> Self::Future {
+        let this = self.clone();
+        Box::pin(async move { Ok(this.handle(request).await) })
+    }
+}
+
+/// The body of responses produced by [`SmartHttp`].
+///
+/// Service output is streamed, and it fails with the service error if the service failed after sending the first
+/// part of its response, which makes servers abort the response.
+pub struct ResponseBody(Inner);
+
+enum Inner {
+    Full(Option<Bytes>),
+    Stream {
+        first: Option<Bytes>,
+        rx: mpsc::Receiver<Result<Bytes, service::Error>>,
+    },
+}
+
+impl http_body::Body for ResponseBody {
+    type Data = Bytes;
+    type Error = std::io::Error;
+
+    fn poll_frame(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Frame<Bytes>, std::io::Error>>> {
+        match &mut self.get_mut().0 {
+            Inner::Full(data) => Poll::Ready(data.take().map(|data| Ok(Frame::data(data)))),
+            Inner::Stream { first, rx } => {
+                if let Some(first) = first.take() {
+                    return Poll::Ready(Some(Ok(Frame::data(first))));
+                }
+                rx.poll_recv(cx).map(|chunk| {
+                    chunk.map(|chunk| match chunk {
+                        Ok(data) => Ok(Frame::data(data)),
+                        Err(service::Error::Io(err)) => Err(err),
+                        Err(err) => Err(std::io::Error::other(err)),
+                    })
+                })
+            }
+        }
+    }
+}
+
+/// Send the data of the request `body` to `tx` as it arrives, until it ends or the service stops reading it.
+///
+/// If the body can't be read, the service fails to read it and the request is answered with `400 Bad Request`.
+async fn forward<B>(body: B, tx: mpsc::Sender<std::io::Result<Bytes>>, rejection: Rejection)
+where
+    B: http_body::Body,
+    B::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    let mut body = std::pin::pin!(body);
+    loop {
+        // The frame is converted right away, as body errors don't have to be `Send`.
+        let chunk = match body.frame().await.map(|frame| frame.map_err(Into::into)) {
+            None => return,
+            Some(Ok(frame)) => match frame.into_data() {
+                Ok(mut data) => Ok(data.copy_to_bytes(data.remaining())),
+                Err(_trailers) => continue,
+            },
+            Some(Err(err)) => {
+                rejection
+                    .set((StatusCode::BAD_REQUEST, format!("Failed to read the request: {err}")))
+                    .ok();
+                Err(std::io::Error::other(err))
+            }
+        };
+        let failed = chunk.is_err();
+        if tx.send(chunk).await.is_err() || failed {
+            return;
+        }
+    }
+}
+
+/// The request body sent by [`forward()`], read by the service.
+struct ChannelReader {
+    rx: mpsc::Receiver<std::io::Result<Bytes>>,
+    chunk: Bytes,
+}
+
+impl Read for ChannelReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.chunk.is_empty() {
+            match self.rx.blocking_recv() {
+                Some(chunk) => self.chunk = chunk?,
+                None => return Ok(0),
+            }
+        }
+        let len = buf.len().min(self.chunk.len());
+        buf[..len].copy_from_slice(&self.chunk.split_to(len));
+        Ok(len)
+    }
+}
+
+/// Fails reads once more than `remaining` bytes were read, to answer with `413 Payload Too Large`.
+struct SizeLimit<R> {
+    inner: R,
+    remaining: usize,
+    rejection: Rejection,
+}
+
+impl<R> SizeLimit<R> {
+    fn new(inner: R, limit: usize, rejection: Rejection) -> Self {
+        SizeLimit {
+            inner,
+            remaining: limit,
+            rejection,
+        }
+    }
+}
+
+impl<R: Read> Read for SizeLimit<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        // Read one byte more than allowed to tell bodies of exactly the limit from larger ones.
+        let max = buf.len().min(self.remaining.saturating_add(1));
+        let read = self.inner.read(&mut buf[..max])?;
+        if read > self.remaining {
+            self.rejection
+                .set((StatusCode::PAYLOAD_TOO_LARGE, "Payload Too Large".into()))
+                .ok();
+            return Err(std::io::Error::other("the request body is too large"));
+        }
+        self.remaining -= read;
+        Ok(read)
+    }
+}
+
+/// Sends service output to the response body in chunks of [`CHUNK_SIZE`].
+struct ChannelWriter {
+    tx: mpsc::Sender<Result<Bytes, service::Error>>,
+    buf: Vec<u8>,
+}
+
+impl Write for ChannelWriter {
+    fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+        self.buf.extend_from_slice(data);
+        if self.buf.len() >= CHUNK_SIZE {
+            self.flush()?;
+        }
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        if self.buf.is_empty() {
+            return Ok(());
+        }
+        let chunk = Bytes::from(std::mem::take(&mut self.buf));
+        self.tx
+            .blocking_send(Ok(chunk))
+            .map_err(|_| std::io::Error::new(std::io::ErrorKind::BrokenPipe, "the client went away"))
+    }
+}
+
+/// Answer with the advertisement of `service`, or with `304 Not Modified` if its fingerprint is among the entity
+/// tags of `if_none_match`, without generating the advertisement.
+async fn advertise(
+    mut service: Box<dyn GitService + Send>,
+    http: HttpRequest,
+    ctx: ServiceContext,
+    if_none_match: Option<String>,
+) -> Response<ResponseBody> {
+    let name = service_name(http.service);
+    let advertisement = tokio::task::spawn_blocking(move || {
+        let fingerprint = service.advertisement_fingerprint(&ctx)?;
+        if let (Some(fingerprint), Some(tags)) = (fingerprint, if_none_match) {
+            if fingerprint.matches(&tags) {
+                return Ok((None, Some(fingerprint)));
+            }
+        }
+        let mut out = Vec::new();
+        // Like `git http-backend`, announce the service unless protocol v2 was requested.
+        if ctx.version != ProtocolVersion::V2 {
+            let line = format!("# service={name}\n");
+            write!(out, "{:04x}{line}0000", line.len() + 4)?;
+        }
+        service.advertise(&mut out, &ctx).map(|()| (Some(out), fingerprint))
+    })
+    .await;
+    match advertisement {
+        Ok(Ok((out, fingerprint))) => {
+            let not_modified = out.is_none();
+            let mut response = response(
+                &format!("application/x-{name}-advertisement"),
+                ResponseBody(Inner::Full(out.map(Into::into))),
+            );
+            if not_modified {
+                *response.status_mut() = StatusCode::NOT_MODIFIED;
+            }
+            if let Some(fingerprint) = fingerprint {
+                let etag = HeaderValue::from_str(&fingerprint.to_etag()).expect("hex is a valid header value");
+                response.headers_mut().insert(header::ETAG, etag);
+            }
+            response
+        }
+        Ok(Err(err)) => error(err),
+        Err(err) => status(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &format!("The service panicked: {err}"),
+        ),
+    }
+}
+
+fn service_name(kind: ServiceKind) -> &'static str {
+    match kind {
+        ServiceKind::UploadPack => "git-upload-pack",
+        ServiceKind::ReceivePack => "git-receive-pack",
+    }
+}
+
+/// The highest protocol version requested with the `Git-Protocol` header, or with the `git-protocol` parameter of
+/// `query` if there is no such header, ignoring unknown versions like git does, and defaulting to v0.
+fn protocol_version(headers: &HeaderMap, query: Option<&str>) -> ProtocolVersion {
+    let requested = match headers.get("git-protocol") {
+        Some(value) => value.to_str().unwrap_or_default().to_owned(),
+        None => query
+            .unwrap_or_default()
+            .split('&')
+            .find_map(|pair| pair.strip_prefix("git-protocol="))
+            .map(percent_decode)
+            .unwrap_or_default(),
+    };
+    ProtocolVersion::from_git_protocol(&requested)
+}
+
+/// Decode the `%XX` escapes of a query parameter `value`, keeping invalid ones as they are.
+fn percent_decode(value: &str) -> String {
+    let mut out = Vec::with_capacity(value.len());
+    let mut bytes = value.as_bytes();
+    while let Some((&byte, rest)) = bytes.split_first() {
+        let escaped = rest
+            .get(..2)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(decoded) if byte == b'%' => {
+                out.push(decoded);
+                bytes = &rest[2..];
+            }
+            _ => {
+                out.push(byte);
+                bytes = rest;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// A successful response of `content_type`, with the headers `git http-backend` uses to prevent caching.
+fn response(content_type: &str, body: ResponseBody) -> Response<ResponseBody> {
+    Response::builder()
+        .header(header::CONTENT_TYPE, content_type)
+        .header(header::EXPIRES, "Fri, 01 Jan 1980 00:00:00 GMT")
+        .header(header::PRAGMA, "no-cache")
+        .header(header::CACHE_CONTROL, "no-cache, max-age=0, must-revalidate")
+        .header(header::VARY, "Git-Protocol")
+        .body(body)
+        .expect("valid header values")
+}
+
+fn error(err: service::Error) -> Response<ResponseBody> {
+    let code = match err {
+        service::Error::Protocol(_) | service::Error::Validation(_) => StatusCode::BAD_REQUEST,
+        service::Error::Io(_) | service::Error::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    };
+    status(code, &err.to_string())
+}
+
+fn status(code: StatusCode, message: &str) -> Response<ResponseBody> {
+    let mut response = Response::new(ResponseBody(Inner::Full(Some(Bytes::from(format!("{message}\n"))))));
+    *response.status_mut() = code;
+    response
+        .headers_mut()
+        .insert(header::CONTENT_TYPE, HeaderValue::from_static("text/plain"));
+    response
+}
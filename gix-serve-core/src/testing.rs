@@ -0,0 +1,200 @@
+//! Utilities for inspecting captured server output in tests.
+//!
+//! [`SidebandDemux`] splits a recorded response into pack bytes, progress messages and errors,
+//! so test suites and embedders can assert on what a service wrote without a full client.
+//! [`MockService`] stands in for a real service when testing adapters built on [`GitService`].
+//! [`snapshot`] compares complete conversations with golden files.
+
+use crate::protocol::ServiceKind;
+use crate::service::{Error, GitService, Outcome, ServiceContext};
+use std::io::{Read, Write};
+
+pub mod snapshot;
+
+/// Splits a captured pkt-line response into its sideband channels.
+///
+/// Packets whose first byte is `1`, `2` or `3` are treated as sideband data, pack data, progress and
+/// errors respectively. All other packets are collected as plain lines, like `NAK`, `ACK` or v2 section
+/// headers. Without sideband, a `PACK` signature at a packet boundary starts raw pack data that extends
+/// to the end of the input.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SidebandDemux {
+    /// Keep trailing newlines and surrounding whitespace of progress and error messages.
+    pub keep_whitespace: bool,
+}
+
+/// The result of [`SidebandDemux::demux()`].
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Demuxed {
+    /// Non-sideband packets in order of appearance, like `NAK`, `ACK <oid>` or `packfile`.
+    pub lines: Vec<Vec<u8>>,
+    /// The pack data from channel 1 or from a raw pack without sideband.
+    pub pack: Vec<u8>,
+    /// Progress messages from channel 2.
+    pub progress: Vec<String>,
+    /// Error messages from channel 3 and `ERR` packets.
+    pub errors: Vec<String>,
+    /// The amount of flush packets seen.
+    pub flushes: usize,
+    /// The byte offset at which the input stopped being valid pkt-line data, if it did.
+    pub malformed_at: Option<usize>,
+}
+
+impl Demuxed {
+    /// Return `true` if a `NAK` line was seen.
+    pub fn has_nak(&self) -> bool {
+        self.lines.iter().any(|line| line.starts_with(b"NAK"))
+    }
+
+    /// Return the pack data if it starts with a `PACK` signature.
+    pub fn pack(&self) -> Option<&[u8]> {
+        self.pack.starts_with(b"PACK").then_some(self.pack.as_slice())
+    }
+}
+
+impl SidebandDemux {
+    /// Split `data`, a complete response as written by a service, into its parts.
+    pub fn demux(&self, data: &[u8]) -> Demuxed {
+        let mut out = Demuxed::default();
+        let mut pos = 0;
+        while pos < data.len() {
+            if data[pos..].starts_with(b"PACK") {
+                out.pack.extend_from_slice(&data[pos..]);
+                break;
+            }
+            let Some(len) = data
+                .get(pos..pos + 4)
+                .and_then(|hex| std::str::from_utf8(hex).ok())
+                .and_then(|hex| usize::from_str_radix(hex, 16).ok())
+            else {
+                out.malformed_at = Some(pos);
+                break;
+            };
+            match len {
+                0 => {
+                    out.flushes += 1;
+                    pos += 4;
+                    continue;
+                }
+                // delimiter and response-end packets carry no data.
+                1 | 2 => {
+                    pos += 4;
+                    continue;
+                }
+                3 => {
+                    out.malformed_at = Some(pos);
+                    break;
+                }
+                _ => {}
+            }
+            let Some(payload) = data.get(pos + 4..pos + len) else {
+                out.malformed_at = Some(pos);
+                break;
+            };
+            match payload.first() {
+                Some(1) => out.pack.extend_from_slice(&payload[1..]),
+                Some(2) => out.progress.push(self.message(&payload[1..])),
+                Some(3) => out.errors.push(self.message(&payload[1..])),
+                _ if payload.starts_with(b"ERR ") => out.errors.push(self.message(&payload[4..])),
+                _ => out.lines.push(payload.to_vec()),
+            }
+            pos += len;
+        }
+        out
+    }
+
+    fn message(&self, bytes: &[u8]) -> String {
+        let msg = String::from_utf8_lossy(bytes);
+        if self.keep_whitespace {
+            msg.into_owned()
+        } else {
+            msg.trim().to_owned()
+        }
+    }
+}
+
+/// A request served by a [`MockService`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServedRequest {
+    /// The context the request was served with.
+    pub ctx: ServiceContext,
+    /// All bytes read from the request input.
+    pub input: Vec<u8>,
+}
+
+/// A [`GitService`] writing scripted responses and recording what it was asked to do.
+#[derive(Debug, Clone)]
+pub struct MockService {
+    kind: ServiceKind,
+    advertisement: Vec<u8>,
+    response: Vec<u8>,
+    outcome: Outcome,
+    failure: Option<String>,
+    /// The contexts of all advertisements written, in order.
+    pub advertised: Vec<ServiceContext>,
+    /// All requests served, in order.
+    pub served: Vec<ServedRequest>,
+}
+
+impl MockService {
+    /// Create a service of `kind` that writes nothing and succeeds.
+    pub fn new(kind: ServiceKind) -> Self {
+        MockService {
+            kind,
+            advertisement: Vec::new(),
+            response: Vec::new(),
+            outcome: Outcome::default(),
+            failure: None,
+            advertised: Vec::new(),
+            served: Vec::new(),
+        }
+    }
+
+    /// Write `advertisement` whenever an advertisement is requested, and before the response of stateful requests.
+    pub fn with_advertisement(mut self, advertisement: impl Into<Vec<u8>>) -> Self {
+        self.advertisement = advertisement.into();
+        self
+    }
+
+    /// Write `response` after reading each request, and return `outcome`.
+    pub fn with_response(mut self, response: impl Into<Vec<u8>>, outcome: Outcome) -> Self {
+        self.response = response.into();
+        self.outcome = outcome;
+        self
+    }
+
+    /// Fail each request with a protocol error carrying `message` after reading it.
+    pub fn with_failure(mut self, message: impl Into<String>) -> Self {
+        self.failure = Some(message.into());
+        self
+    }
+}
+
+impl GitService for MockService {
+    fn kind(&self) -> ServiceKind {
+        self.kind
+    }
+
+    fn advertise(&mut self, out: &mut dyn Write, ctx: &ServiceContext) -> Result<(), Error> {
+        self.advertised.push(ctx.clone());
+        out.write_all(&self.advertisement)?;
+        Ok(())
+    }
+
+    fn serve(&mut self, input: &mut dyn Read, output: &mut dyn Write, ctx: &ServiceContext) -> Result<Outcome, Error> {
+        if !ctx.stateless {
+            output.write_all(&self.advertisement)?;
+        }
+        let mut request = Vec::new();
+        input.read_to_end(&mut request)?;
+        self.served.push(ServedRequest {
+            ctx: ctx.clone(),
+            input: request,
+        });
+        if let Some(message) = &self.failure {
+            return Err(Error::Protocol(message.clone()));
+        }
+        output.write_all(&self.response)?;
+        Ok(self.outcome.clone())
+    }
+}
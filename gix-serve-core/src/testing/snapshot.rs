@@ -0,0 +1,415 @@
+//! Golden-file snapshots of complete protocol conversations.
+//!
+//! A [`Transcript`] decodes the pkt-lines exchanged in a scenario into readable text, one line per packet,
+//! annotated with its direction and sideband:
+//!
+//! ```text
+//! # ls-refs with protocol v2
+//!
+//! S: version 2\n
+//! S: agent=*\n
+//! S: ls-refs=unborn\n
+//! S: flush
+//!
+//! C: command=ls-refs\n
+//! C: delim
+//! C: flush
+//! ```
+//!
+//! `C` marks what the client sent and `S` what the server sent. Sideband packets are labelled with their band
+//! like `S [progress]`, and consecutive packets of the same band are merged. Packs are summarized by their
+//! object count, and data on band 1 that isn't a pack, like a `report-status`, is decoded as the pkt-lines it
+//! contains. Payloads are shown with control characters escaped, so that trailing newlines are visible.
+//!
+//! Values that change between runs are [normalized](Normalization) before they are compared with the golden
+//! file by [`Transcript::assert_golden()`]. Set [`UPDATE_ENV`] to write the transcripts as new golden files.
+
+use crate::wire::Direction;
+use std::fmt::{self, Write as _};
+use std::path::Path;
+
+/// The environment variable which, if set to `1`, makes [`Transcript::assert_golden()`] write golden files
+/// instead of comparing with them.
+pub const UPDATE_ENV: &str = "GIX_SERVE_UPDATE_SNAPSHOTS";
+
+/// How values that change between runs are made stable in a [`Transcript`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Normalization {
+    /// Replace the value of `agent=` capabilities with `*`.
+    pub agent: bool,
+    /// Replace the value of `session-id=` capabilities with `*`.
+    pub session_id: bool,
+    /// Replace git timestamps like `1700000000 +0100` with `<timestamp>`, and transfer rates in progress
+    /// messages like `1.5 MiB/s` with `<rate>`.
+    pub timestamps: bool,
+    /// Keep progress updates ending in a carriage return, whose number depends on timing, instead of only the
+    /// completed progress lines.
+    pub progress_updates: bool,
+    /// Replace each occurrence of the first string with the second one, like object ids with names.
+    pub replacements: Vec<(String, String)>,
+}
+
+impl Default for Normalization {
+    fn default() -> Self {
+        Normalization {
+            agent: true,
+            session_id: true,
+            timestamps: true,
+            progress_updates: false,
+            replacements: Vec::new(),
+        }
+    }
+}
+
+impl Normalization {
+    /// Replace `from` with `to` in all payloads, after all other normalizations.
+    pub fn with_replacement(mut self, from: impl Into<String>, to: impl Into<String>) -> Self {
+        self.replacements.push((from.into(), to.into()));
+        self
+    }
+
+    /// Return `text` with all enabled normalizations applied.
+    pub fn apply(&self, text: &str) -> String {
+        let mut text = text.to_owned();
+        if self.agent {
+            text = mask_value(&text, "agent=");
+        }
+        if self.session_id {
+            text = mask_value(&text, "session-id=");
+        }
+        if self.timestamps {
+            text = mask_timestamps(&text);
+        }
+        for (from, to) in &self.replacements {
+            text = text.replace(from.as_str(), to);
+        }
+        text
+    }
+}
+
+/// The annotated text of a protocol conversation, see the [module documentation](self).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Transcript {
+    normalization: Normalization,
+    lines: Vec<String>,
+}
+
+impl Transcript {
+    /// Start the transcript of the scenario called `title`, with the default [`Normalization`].
+    pub fn new(title: &str) -> Self {
+        Transcript {
+            normalization: Normalization::default(),
+            lines: vec![format!("# {title}")],
+        }
+    }
+
+    /// Normalize payloads with `normalization` instead of the default.
+    pub fn with_normalization(mut self, normalization: Normalization) -> Self {
+        self.normalization = normalization;
+        self
+    }
+
+    /// Record `data` the client sent, like a request.
+    pub fn request(&mut self, data: &[u8]) -> &mut Self {
+        self.record(Direction::Request, data)
+    }
+
+    /// Record `data` the server sent, like an advertisement or a response.
+    pub fn response(&mut self, data: &[u8]) -> &mut Self {
+        self.record(Direction::Response, data)
+    }
+
+    /// Record `data` sent in `direction`, separated from the previously recorded data by an empty line.
+    pub fn record(&mut self, direction: Direction, data: &[u8]) -> &mut Self {
+        let side = match direction {
+            Direction::Request => "C",
+            Direction::Response => "S",
+        };
+        self.lines.push(String::new());
+        let mut decoder = Decoder {
+            side,
+            normalization: &self.normalization,
+            lines: &mut self.lines,
+            band: None,
+            pack: None,
+        };
+        decoder.packets(side, data);
+        decoder.finish();
+        self
+    }
+
+    /// The lines of the transcript, starting with the title.
+    pub fn lines(&self) -> &[String] {
+        &self.lines
+    }
+
+    /// Compare this transcript with the golden file at `path`, and panic with the differing lines if they
+    /// differ or the file doesn't exist.
+    ///
+    /// If [`UPDATE_ENV`] is set to `1`, the golden file and its parent directories are written instead.
+    pub fn assert_golden(&self, path: impl AsRef<Path>) {
+        let path = path.as_ref();
+        let actual = self.to_string();
+        if std::env::var_os(UPDATE_ENV).is_some_and(|value| value == "1") {
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent).expect("golden file directory can be created");
+            }
+            std::fs::write(path, actual).expect("golden file can be written");
+            return;
+        }
+        let expected = match std::fs::read_to_string(path) {
+            Ok(expected) => expected,
+            Err(err) => panic!(
+                "golden file {} can't be read ({err}), run with {UPDATE_ENV}=1 to create it:\n{actual}",
+                path.display()
+            ),
+        };
+        if expected != actual {
+            panic!(
+                "transcript differs from golden file {}, run with {UPDATE_ENV}=1 to update it:\n{}",
+                path.display(),
+                diff(&expected, &actual)
+            );
+        }
+    }
+}
+
+impl fmt::Display for Transcript {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for line in &self.lines {
+            writeln!(f, "{line}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Decodes the packets of one recorded message into transcript lines.
+struct Decoder<'a> {
+    side: &'static str,
+    normalization: &'a Normalization,
+    lines: &'a mut Vec<String>,
+    /// The band and data of consecutive sideband packets not yet written.
+    band: Option<(u8, Vec<u8>)>,
+    /// The line index and data of a pack, which is summarized once all of it was seen.
+    pack: Option<(usize, Vec<u8>)>,
+}
+
+impl Decoder<'_> {
+    /// Decode the pkt-lines in `data`, labelling them with `label`.
+    fn packets(&mut self, label: &str, data: &[u8]) {
+        let mut pos = 0;
+        while pos < data.len() {
+            let rest = &data[pos..];
+            // Without sideband, the pack follows the last pkt-line directly.
+            if rest.starts_with(b"PACK") && self.pack.is_none() {
+                self.start_pack(rest);
+                return;
+            }
+            let Some(len) = rest
+                .get(..4)
+                .and_then(|hex| std::str::from_utf8(hex).ok())
+                .and_then(|hex| usize::from_str_radix(hex, 16).ok())
+                .filter(|len| *len != 3 && rest.len() >= *len)
+            else {
+                self.flush_band();
+                let rest = escape(&String::from_utf8_lossy(rest));
+                self.lines.push(format!("{label} [malformed]: {rest}"));
+                return;
+            };
+            pos += len.max(4);
+            let special = match len {
+                0 => Some("flush"),
+                1 => Some("delim"),
+                2 => Some("response-end"),
+                _ => None,
+            };
+            if let Some(special) = special {
+                self.flush_band();
+                self.lines.push(format!("{label}: {special}"));
+                continue;
+            }
+            let payload = &rest[4..len];
+            match payload.first() {
+                Some(band @ 1..=3) => self.band(*band, &payload[1..]),
+                _ => {
+                    self.flush_band();
+                    let text = self.normalization.apply(&String::from_utf8_lossy(payload));
+                    self.lines.push(format!("{label}: {}", escape(&text)));
+                }
+            }
+        }
+    }
+
+    /// Add `data` received on `band`, merging it with data of the same band received just before.
+    fn band(&mut self, band: u8, data: &[u8]) {
+        if band == 1 {
+            if let Some((_, pack)) = self.pack.as_mut() {
+                pack.extend_from_slice(data);
+                return;
+            }
+            if data.starts_with(b"PACK") {
+                self.start_pack(data);
+                return;
+            }
+        }
+        match self.band.as_mut() {
+            Some((current, pending)) if *current == band => pending.extend_from_slice(data),
+            _ => {
+                self.flush_band();
+                self.band = Some((band, data.to_vec()));
+            }
+        }
+    }
+
+    /// Remember where the pack starting with `data` is summarized, once it was seen completely.
+    fn start_pack(&mut self, data: &[u8]) {
+        self.flush_band();
+        self.pack = Some((self.lines.len(), data.to_vec()));
+        self.lines.push(String::new());
+    }
+
+    fn flush_band(&mut self) {
+        let Some((band, data)) = self.band.take() else {
+            return;
+        };
+        let side = self.side;
+        match band {
+            1 => self.packets(&format!("{side} [data]"), &data),
+            2 => {
+                let text = String::from_utf8_lossy(&data);
+                for message in progress_messages(&text, self.normalization.progress_updates) {
+                    let message = self.normalization.apply(&message);
+                    self.lines.push(format!("{side} [progress]: {}", escape(&message)));
+                }
+            }
+            _ => {
+                let message = self.normalization.apply(&String::from_utf8_lossy(&data));
+                self.lines.push(format!("{side} [error]: {}", escape(&message)));
+            }
+        }
+    }
+
+    fn finish(mut self) {
+        self.flush_band();
+        if let Some((index, pack)) = self.pack.take() {
+            let summary = match pack.get(8..12) {
+                Some(count) => {
+                    let objects = u32::from_be_bytes(count.try_into().expect("4 bytes"));
+                    format!("pack with {objects} objects")
+                }
+                None => format!("incomplete pack of {} bytes", pack.len()),
+            };
+            self.lines[index] = format!("{} [pack]: {summary}", self.side);
+        }
+    }
+}
+
+/// Split progress `text` into messages, each ending in `\n` or `\r`, leaving out updates ending in `\r`
+/// unless `updates` is set.
+fn progress_messages(text: &str, updates: bool) -> Vec<String> {
+    let mut messages = Vec::new();
+    let mut start = 0;
+    for (pos, c) in text.char_indices() {
+        if c == '\n' || c == '\r' {
+            if c == '\n' || updates {
+                messages.push(text[start..=pos].to_owned());
+            }
+            start = pos + 1;
+        }
+    }
+    if start < text.len() {
+        messages.push(text[start..].to_owned());
+    }
+    messages
+}
+
+/// Replace the values following each `key` in `text` with `*`, up to the next space, NUL or line ending.
+fn mask_value(text: &str, key: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find(key) {
+        let value_start = start + key.len();
+        out.push_str(&rest[..value_start]);
+        out.push('*');
+        let value_len = rest[value_start..]
+            .find([' ', '\0', '\n', '\r'])
+            .unwrap_or(rest.len() - value_start);
+        rest = &rest[value_start + value_len..];
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Replace git timestamps, seconds since the epoch followed by a timezone offset, and transfer rates.
+fn mask_timestamps(text: &str) -> String {
+    let words: Vec<&str> = text.split(' ').collect();
+    let mut out: Vec<String> = Vec::with_capacity(words.len());
+    let mut i = 0;
+    while i < words.len() {
+        let (word, next) = (words[i], words.get(i + 1).copied().unwrap_or_default());
+        let is_seconds = word.len() >= 9 && word.bytes().all(|b| b.is_ascii_digit());
+        let is_offset =
+            next.len() >= 5 && next.starts_with(['+', '-']) && next[1..5].bytes().all(|b| b.is_ascii_digit());
+        let rate_unit = next.trim_end_matches([',', '\n', '\r']);
+        if is_seconds && is_offset {
+            out.push(format!("<timestamp>{}", &next[5..]));
+            i += 2;
+        } else if word.parse::<f64>().is_ok() && rate_unit.ends_with("B/s") {
+            out.push(format!("<rate>{}", &next[rate_unit.len()..]));
+            i += 2;
+        } else {
+            out.push(word.to_owned());
+            i += 1;
+        }
+    }
+    out.join(" ")
+}
+
+/// Show control characters in `text` as escapes, so whitespace at the end of a payload stays visible.
+fn escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            '\0' => out.push_str("\\0"),
+            '\\' => out.push_str("\\\\"),
+            c if c.is_control() => write!(out, "\\x{:02x}", c as u32).expect("writing to a string never fails"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// The lines of `expected` and `actual`, prefixed with `-` if only expected, `+` if only actual, or a space.
+fn diff(expected: &str, actual: &str) -> String {
+    let expected: Vec<_> = expected.lines().collect();
+    let actual: Vec<_> = actual.lines().collect();
+    // The length of the longest common subsequence of the remaining lines, for each pair of positions.
+    let mut common = vec![vec![0usize; actual.len() + 1]; expected.len() + 1];
+    for i in (0..expected.len()).rev() {
+        for j in (0..actual.len()).rev() {
+            common[i][j] = if expected[i] == actual[j] {
+                common[i + 1][j + 1] + 1
+            } else {
+                common[i + 1][j].max(common[i][j + 1])
+            };
+        }
+    }
+    let mut out = String::new();
+    let (mut i, mut j) = (0, 0);
+    while i < expected.len() || j < actual.len() {
+        if i < expected.len() && j < actual.len() && expected[i] == actual[j] {
+            writeln!(out, "  {}", expected[i]).expect("writing to a string never fails");
+            (i, j) = (i + 1, j + 1);
+        } else if i < expected.len() && (j == actual.len() || common[i + 1][j] >= common[i][j + 1]) {
+            writeln!(out, "- {}", expected[i]).expect("writing to a string never fails");
+            i += 1;
+        } else {
+            writeln!(out, "+ {}", actual[j]).expect("writing to a string never fails");
+            j += 1;
+        }
+    }
+    out
+}
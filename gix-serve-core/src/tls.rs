@@ -0,0 +1,109 @@
+//! Accept TLS connections with rustls, to serve the git protocol encrypted without a separate reverse proxy.
+//!
+//! An [`Acceptor`] is created once for a listener, like with [`Acceptor::from_pem_files()`], and
+//! [`Acceptor::accept()`] completes the handshake of each accepted connection before returning the encrypted stream.
+//! Connections whose handshake fails or times out are refused right there, so front-ends limiting concurrent
+//! sessions only spend a slot on connections that are established.
+//!
+//! ALPN isn't negotiated, as the git protocol has no registered identifier: protocols offered by clients are
+//! ignored instead of failing the handshake.
+
+use rustls::{ServerConfig, ServerConnection, StreamOwned};
+use rustls_pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer};
+use std::net::TcpStream;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// An established TLS connection, as returned by [`Acceptor::accept()`].
+pub type TlsStream = StreamOwned<ServerConnection, TcpStream>;
+
+/// The error returned when creating an [`Acceptor`] or accepting connections.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// A certificate or key file couldn't be read or didn't contain PEM data of the expected kind.
+    #[error("could not load '{}': {message}", path.display())]
+    Pem {
+        /// The file that was read.
+        path: PathBuf,
+        /// What went wrong.
+        message: String,
+    },
+    /// The certificate chain and key don't make a valid configuration, for instance because they don't match.
+    #[error("invalid TLS configuration: {0}")]
+    Config(#[from] rustls::Error),
+    /// The handshake with the client failed or timed out, and the connection was dropped.
+    #[error("TLS handshake failed: {0}")]
+    Handshake(std::io::Error),
+}
+
+/// Completes TLS handshakes of accepted connections.
+#[derive(Debug, Clone)]
+pub struct Acceptor {
+    config: Arc<ServerConfig>,
+    handshake_timeout: Option<Duration>,
+}
+
+impl Acceptor {
+    /// The time clients have to complete the handshake by default.
+    pub const DEFAULT_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+    /// Accept connections with `config`, which should leave ALPN protocols unset.
+    pub fn new(config: Arc<ServerConfig>) -> Self {
+        Acceptor {
+            config,
+            handshake_timeout: Some(Self::DEFAULT_HANDSHAKE_TIMEOUT),
+        }
+    }
+
+    /// Present the PEM encoded certificate chain in `cert`, starting with the certificate of the server, and prove
+    /// its identity with the PEM encoded private key in `key`.
+    pub fn from_pem_files(cert: impl AsRef<Path>, key: impl AsRef<Path>) -> Result<Self, Error> {
+        let (cert, key) = (cert.as_ref(), key.as_ref());
+        let pem_error = |path: &Path, err: rustls_pki_types::pem::Error| Error::Pem {
+            path: path.to_owned(),
+            message: err.to_string(),
+        };
+        let chain = CertificateDer::pem_file_iter(cert)
+            .and_then(Iterator::collect::<Result<Vec<_>, _>>)
+            .map_err(|err| pem_error(cert, err))?;
+        if chain.is_empty() {
+            return Err(Error::Pem {
+                path: cert.to_owned(),
+                message: "no certificate found".into(),
+            });
+        }
+        let key = PrivateKeyDer::from_pem_file(key).map_err(|err| pem_error(key, err))?;
+        let config = ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_safe_default_protocol_versions()?
+            .with_no_client_auth()
+            .with_single_cert(chain, key)?;
+        Ok(Self::new(Arc::new(config)))
+    }
+
+    /// Drop connections that didn't complete the handshake within `timeout`, or never if `None`.
+    pub fn with_handshake_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.handshake_timeout = timeout;
+        self
+    }
+
+    /// Complete the TLS handshake on `stream`, a connection that was just accepted, and return the encrypted stream.
+    ///
+    /// The read and write timeouts of `stream` are overridden by the handshake timeout while the handshake is in
+    /// progress, and restored afterwards.
+    pub fn accept(&self, mut stream: TcpStream) -> Result<TlsStream, Error> {
+        let mut connection = ServerConnection::new(self.config.clone())?;
+        let timeouts = (stream.read_timeout(), stream.write_timeout());
+        let handshake = (|| {
+            stream.set_read_timeout(self.handshake_timeout)?;
+            stream.set_write_timeout(self.handshake_timeout)?;
+            while connection.is_handshaking() {
+                connection.complete_io(&mut stream)?;
+            }
+            stream.set_read_timeout(timeouts.0?)?;
+            stream.set_write_timeout(timeouts.1?)
+        })();
+        handshake.map_err(Error::Handshake)?;
+        Ok(StreamOwned::new(connection, stream))
+    }
+}
@@ -1,8 +1,144 @@
 //! Shared visibility primitives for advertising references safely.
+//!
+//! [`HiddenRefs`] decides which refs are hidden like the `transfer.hideRefs`, `uploadpack.hideRefs` and
+//! `receive.hideRefs` configuration of git, so upload-pack and receive-pack hide the same refs as git would.
 
 use gix_hash::ObjectId;
 use std::sync::Arc;
 
+/// A single `hideRefs` pattern.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct HideRule {
+    /// The pattern as it was added.
+    pattern: String,
+    /// The ref, or the hierarchy of refs, the rule applies to, without trailing slashes.
+    prefix: String,
+    /// Whether matching refs are shown instead, for patterns starting with `!`.
+    negated: bool,
+    /// Whether the pattern applies to the full name of the ref before its namespace is stripped, for patterns
+    /// starting with `^` after the optional `!`.
+    full_name: bool,
+}
+
+impl HideRule {
+    /// Return `true` if `subject` is the ref of this rule or inside of its hierarchy.
+    fn matches(&self, subject: &str) -> bool {
+        subject
+            .strip_prefix(self.prefix.as_str())
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+    }
+}
+
+/// Which refs are hidden from clients, following the `hideRefs` semantics of git.
+///
+/// Each pattern hides a ref and all refs below it, so `refs/pull` hides `refs/pull` and `refs/pull/1/head`, but not
+/// `refs/pulls`. Patterns starting with `!` show matching refs again, and the last matching pattern decides, so
+/// `refs/pull` followed by `!refs/pull/1` only leaves `refs/pull/1` and the refs below it visible. Patterns
+/// starting with `^`, after the optional `!`, match the full name of refs before their namespace is stripped instead
+/// of the name they are advertised with.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HiddenRefs {
+    rules: Vec<HideRule>,
+}
+
+impl HiddenRefs {
+    /// Create an instance that hides no ref.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Load the patterns of `transfer.hideRefs` and `<section>.hideRefs` from `config`, in the order they appear in,
+    /// like git does for `uploadpack` and `receive`.
+    pub fn from_config(config: &gix::config::File<'_>, section: &str) -> Self {
+        let mut hidden = Self::new();
+        for config_section in config.sections() {
+            let header = config_section.header();
+            let name = header.name();
+            if header.subsection_name().is_some()
+                || !(name.eq_ignore_ascii_case(b"transfer") || name.eq_ignore_ascii_case(section.as_bytes()))
+            {
+                continue;
+            }
+            for pattern in config_section.values("hideRefs") {
+                hidden.push(&String::from_utf8_lossy(&pattern));
+            }
+        }
+        hidden
+    }
+
+    /// Add `pattern`, which takes precedence over the patterns added before it, see the
+    /// [type documentation](Self).
+    pub fn with_pattern(mut self, pattern: &str) -> Self {
+        self.push(pattern);
+        self
+    }
+
+    /// Add `pattern` like [`with_pattern()`](Self::with_pattern()) does.
+    pub fn push(&mut self, pattern: &str) {
+        let (negated, rest) = match pattern.strip_prefix('!') {
+            Some(rest) => (true, rest),
+            None => (false, pattern),
+        };
+        let (full_name, rest) = match rest.strip_prefix('^') {
+            Some(rest) => (true, rest),
+            None => (false, rest),
+        };
+        self.rules.push(HideRule {
+            pattern: pattern.to_owned(),
+            prefix: rest.trim_end_matches('/').to_owned(),
+            negated,
+            full_name,
+        });
+    }
+
+    /// Return the patterns in the order they were added.
+    pub fn patterns(&self) -> impl Iterator<Item = &str> + '_ {
+        self.rules.iter().map(|rule| rule.pattern.as_str())
+    }
+
+    /// Return `true` if no pattern was added, so no ref is hidden.
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Return `true` if the ref `name` is hidden, for refs outside of any namespace.
+    pub fn is_hidden(&self, name: &str) -> bool {
+        self.is_hidden_in_namespace(Some(name), name)
+    }
+
+    /// Return `true` if the ref `full_name` is hidden while serving a namespace, where `name` is the name of the ref
+    /// with its namespace stripped, or `None` if it's outside of the namespace.
+    ///
+    /// Patterns starting with `^` match `full_name`, all others match `name`.
+    pub fn is_hidden_in_namespace(&self, name: Option<&str>, full_name: &str) -> bool {
+        self.rules
+            .iter()
+            .rev()
+            .find(|rule| match rule.full_name {
+                true => rule.matches(full_name),
+                false => name.is_some_and(|name| rule.matches(name)),
+            })
+            .is_some_and(|rule| !rule.negated)
+    }
+
+    /// Return a predicate hiding the same refs as [`is_hidden()`](Self::is_hidden()), for use with
+    /// [`VisibleRoots`] and [`AdvertConfig`](crate::advertise::AdvertConfig).
+    pub fn predicate(&self) -> Arc<HiddenRefPredicate> {
+        let hidden = self.clone();
+        Arc::new(move |record: &RefRecord| hidden.is_hidden(&record.name))
+    }
+}
+
+impl<S: AsRef<str>> FromIterator<S> for HiddenRefs {
+    fn from_iter<I: IntoIterator<Item = S>>(patterns: I) -> Self {
+        let mut hidden = Self::new();
+        for pattern in patterns {
+            hidden.push(pattern.as_ref());
+        }
+        hidden
+    }
+}
+
 /// A reference record with its object id and name.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct RefRecord {
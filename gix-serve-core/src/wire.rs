@@ -0,0 +1,234 @@
+//! Byte counters for what is sent over the wire during a session, for billing and capacity planning.
+//!
+//! [`Counter`] follows the pkt-line framing of one direction of a conversation without buffering it, so it can
+//! be fed from any transport. [`CountingReader`] and [`CountingWriter`] apply it to the request and response streams
+//! of a session, and [`WireStats`] is what they count.
+//!
+//! Pack bytes are the payload of sideband channel 1 and raw pack data, which starts with a `PACK` signature where a
+//! pkt-line length was expected. Framing bytes are the pkt-line length prefixes and sideband channel bytes. All
+//! other bytes, like ref advertisements, commands, negotiation lines and progress messages, are only part of the
+//! total of their direction.
+
+use std::io::{Read, Write};
+
+/// The bytes exchanged during a session.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct WireStats {
+    /// The bytes received from the client.
+    pub request_bytes: u64,
+    /// The bytes sent to the client.
+    pub response_bytes: u64,
+    /// The bytes of pack data in either direction, without framing.
+    pub pack_bytes: u64,
+    /// The bytes of pkt-line length prefixes and sideband channel bytes in either direction.
+    pub framing_bytes: u64,
+}
+
+impl WireStats {
+    /// The bytes exchanged in both directions.
+    pub fn total_bytes(&self) -> u64 {
+        self.request_bytes + self.response_bytes
+    }
+}
+
+impl std::ops::AddAssign for WireStats {
+    fn add_assign(&mut self, other: Self) {
+        self.request_bytes += other.request_bytes;
+        self.response_bytes += other.response_bytes;
+        self.pack_bytes += other.pack_bytes;
+        self.framing_bytes += other.framing_bytes;
+    }
+}
+
+impl std::ops::Add for WireStats {
+    type Output = Self;
+
+    fn add(mut self, other: Self) -> Self {
+        self += other;
+        self
+    }
+}
+
+/// The direction of the bytes a [`Counter`] sees.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// Bytes received from the client.
+    Request,
+    /// Bytes sent to the client.
+    Response,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum State {
+    /// Collecting the 4 bytes of a pkt-line length.
+    Header { bytes: [u8; 4], len: usize },
+    /// Within the payload of a pkt-line, with `remaining` bytes to go.
+    Payload { remaining: usize, first: bool, pack: bool },
+    /// Within raw pack data, which lasts until the end of the stream.
+    Raw,
+}
+
+/// Counts the bytes of one direction of a session as they pass, see the [module documentation](self).
+#[derive(Debug, Clone)]
+pub struct Counter {
+    direction: Direction,
+    state: State,
+    stats: WireStats,
+}
+
+impl Counter {
+    /// Create a counter for bytes travelling in `direction`.
+    pub fn new(direction: Direction) -> Self {
+        Counter {
+            direction,
+            state: State::Header { bytes: [0; 4], len: 0 },
+            stats: WireStats::default(),
+        }
+    }
+
+    /// Count `data`, the next bytes of the stream.
+    pub fn record(&mut self, mut data: &[u8]) {
+        let total = data.len() as u64;
+        match self.direction {
+            Direction::Request => self.stats.request_bytes += total,
+            Direction::Response => self.stats.response_bytes += total,
+        }
+        while !data.is_empty() {
+            match &mut self.state {
+                State::Header { bytes, len } => {
+                    let take = (4 - *len).min(data.len());
+                    bytes[*len..*len + take].copy_from_slice(&data[..take]);
+                    *len += take;
+                    data = &data[take..];
+                    if *len < 4 {
+                        break;
+                    }
+                    let bytes = *bytes;
+                    let length = std::str::from_utf8(&bytes)
+                        .ok()
+                        .and_then(|hex| u16::from_str_radix(hex, 16).ok());
+                    match length {
+                        Some(length) => {
+                            self.stats.framing_bytes += 4;
+                            self.state = match usize::from(length).checked_sub(4) {
+                                Some(remaining) if remaining > 0 => State::Payload {
+                                    remaining,
+                                    first: true,
+                                    pack: false,
+                                },
+                                _ => State::Header { bytes: [0; 4], len: 0 },
+                            };
+                        }
+                        None => {
+                            self.stats.pack_bytes += 4;
+                            self.state = State::Raw;
+                        }
+                    }
+                }
+                State::Payload { remaining, first, pack } => {
+                    let take = (*remaining).min(data.len());
+                    let mut payload = &data[..take];
+                    if *first {
+                        *first = false;
+                        if let Some(band @ 1..=3) = payload.first() {
+                            self.stats.framing_bytes += 1;
+                            *pack = *band == 1;
+                            payload = &payload[1..];
+                        }
+                    }
+                    if *pack {
+                        self.stats.pack_bytes += payload.len() as u64;
+                    }
+                    *remaining -= take;
+                    data = &data[take..];
+                    if *remaining == 0 {
+                        self.state = State::Header { bytes: [0; 4], len: 0 };
+                    }
+                }
+                State::Raw => {
+                    self.stats.pack_bytes += data.len() as u64;
+                    break;
+                }
+            }
+        }
+    }
+
+    /// The bytes counted so far.
+    pub fn stats(&self) -> WireStats {
+        self.stats
+    }
+}
+
+/// A reader counting the request bytes read from it.
+#[derive(Debug)]
+pub struct CountingReader<R> {
+    inner: R,
+    counter: Counter,
+}
+
+impl<R> CountingReader<R> {
+    /// Count the bytes read from `inner`.
+    pub fn new(inner: R) -> Self {
+        CountingReader {
+            inner,
+            counter: Counter::new(Direction::Request),
+        }
+    }
+
+    /// The bytes read so far.
+    pub fn stats(&self) -> WireStats {
+        self.counter.stats()
+    }
+
+    /// Return the reader we were created with.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.counter.record(&buf[..read]);
+        Ok(read)
+    }
+}
+
+/// A writer counting the response bytes written to it.
+#[derive(Debug)]
+pub struct CountingWriter<W> {
+    inner: W,
+    counter: Counter,
+}
+
+impl<W> CountingWriter<W> {
+    /// Count the bytes written to `inner`.
+    pub fn new(inner: W) -> Self {
+        CountingWriter {
+            inner,
+            counter: Counter::new(Direction::Response),
+        }
+    }
+
+    /// The bytes written so far.
+    pub fn stats(&self) -> WireStats {
+        self.counter.stats()
+    }
+
+    /// Return the writer we were created with.
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.counter.record(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
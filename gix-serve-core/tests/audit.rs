@@ -0,0 +1,41 @@
+use gix_serve_core::audit::{Auditor, Denial, Reason};
+use gix_serve_core::protocol::{ProtocolVersion, ServiceKind};
+use gix_serve_core::service::ServiceContext;
+use std::sync::{Arc, Mutex};
+
+#[test]
+fn denials_are_passed_to_the_sink_with_the_request_context() {
+    let records = Arc::new(Mutex::new(Vec::new()));
+    let auditor = Auditor::new({
+        let records = records.clone();
+        move |denial: &Denial| records.lock().unwrap().push(denial.clone())
+    });
+    assert!(auditor.is_enabled());
+
+    let ctx = ServiceContext::new(ProtocolVersion::V0)
+        .with_principal("alice")
+        .with_trace_id("t-1")
+        .with_remote_addr("192.0.2.1:51234".parse().unwrap());
+    let denial = Denial::new(ServiceKind::ReceivePack, "/srv/repo.git", Reason::Hook, "hook declined")
+        .with_refname("refs/heads/main")
+        .with_context(&ctx);
+    auditor.record(&denial);
+
+    let records = records.lock().unwrap();
+    assert_eq!(*records, vec![denial]);
+    let record = &records[0];
+    assert_eq!(record.principal.as_deref(), Some("alice"));
+    assert_eq!(record.trace_id.as_deref(), Some("t-1"));
+    assert_eq!(record.remote_addr, ctx.remote_addr);
+    assert_eq!(record.refname.as_deref(), Some("refs/heads/main"));
+    assert_eq!(record.reason.to_string(), "hook");
+}
+
+#[test]
+fn nothing_is_recorded_by_default() {
+    let auditor = Auditor::default();
+    assert!(!auditor.is_enabled());
+    auditor.record(&Denial::new(ServiceKind::UploadPack, "repo", Reason::Limit, "too slow"));
+    assert_eq!(format!("{auditor:?}"), "Auditor(disabled)");
+    assert_eq!(Reason::HiddenRef.as_str(), "hidden-ref");
+}
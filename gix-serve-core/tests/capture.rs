@@ -0,0 +1,83 @@
+#![cfg(all(feature = "capture", feature = "testing"))]
+
+use gix_serve_core::capture::{Options, Recorder};
+use gix_serve_core::protocol::{ProtocolVersion, ServiceKind};
+use gix_serve_core::service::{GitService, Outcome, ServiceContext};
+use gix_serve_core::testing::MockService;
+use std::io::Read;
+
+fn serve(service: &mut dyn GitService, request: &str) -> Vec<u8> {
+    let ctx = ServiceContext::new(ProtocolVersion::V2).with_stateless(true);
+    let mut output = Vec::new();
+    service.serve(&mut request.as_bytes(), &mut output, &ctx).ok();
+    output
+}
+
+#[test]
+fn the_oldest_captures_are_removed_beyond_max_files() {
+    let tmp = gix_testtools::tempfile::tempdir().unwrap();
+    let recorder = Recorder::new(Options::new(tmp.path().join("captures")).with_max_files(2)).unwrap();
+    let mock = MockService::new(ServiceKind::UploadPack).with_response("0008NAK\n", Outcome::default());
+    let mut service = recorder.wrap(mock, "org/repo.git");
+
+    let requests = ["0000", "0014command=ls-refs\n0000", "0012command=fetch\n0000"];
+    for request in requests {
+        assert_eq!(serve(&mut service, request), b"0008NAK\n", "responses are unchanged");
+    }
+
+    let entries = recorder.entries().unwrap();
+    assert_eq!(entries.len(), 2);
+    let directory = &recorder.options().directory;
+    for (entry, request) in entries.iter().zip(&requests[1..]) {
+        assert_eq!(entry.repository, "org/repo.git");
+        assert_eq!(
+            (entry.service, entry.version),
+            (ServiceKind::UploadPack, ProtocolVersion::V2)
+        );
+        assert_eq!(entry.outcome, "ok");
+        assert_eq!(
+            std::fs::read(entry.request_path(directory)).unwrap(),
+            request.as_bytes()
+        );
+        assert_eq!(std::fs::read(entry.response_path(directory)).unwrap(), b"0008NAK\n");
+        assert_eq!(entry.bytes, request.len() as u64 + 8);
+    }
+    assert_eq!(
+        std::fs::read_dir(directory).unwrap().count(),
+        1 + 2 * 2,
+        "the index and the files of two captures"
+    );
+}
+
+#[test]
+fn compressed_captures_record_failures_within_max_total_bytes() {
+    let tmp = gix_testtools::tempfile::tempdir().unwrap();
+    let options = Options::new(tmp.path()).with_compression(true);
+    let recorder = Recorder::new(options.clone().with_max_total_bytes(1000)).unwrap();
+    let mock = MockService::new(ServiceKind::ReceivePack).with_failure("bad\trequest");
+    let mut service = recorder.wrap(mock, "repo");
+
+    let request = "0000".repeat(1000);
+    serve(&mut service, &request);
+    let entries = recorder.entries().unwrap();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].outcome, "protocol error: bad request");
+    assert!(entries[0].compressed);
+    assert!(entries[0].bytes < 1000, "{} bytes on disk", entries[0].bytes);
+    let mut decompressed = String::new();
+    flate2::read::GzDecoder::new(std::fs::File::open(entries[0].request_path(tmp.path())).unwrap())
+        .read_to_string(&mut decompressed)
+        .unwrap();
+    assert_eq!(decompressed, request);
+
+    for _ in 0..20 {
+        serve(&mut service, &request);
+    }
+    let entries = recorder.entries().unwrap();
+    let total: u64 = entries.iter().map(|entry| entry.bytes).sum();
+    assert!(total <= 1000, "{total} bytes are kept");
+    assert!(entries.len() > 1 && entries.len() < 21, "{} captures", entries.len());
+
+    let unlimited = Recorder::new(options).unwrap();
+    assert_eq!(unlimited.entries().unwrap(), entries, "the index is read from disk");
+}
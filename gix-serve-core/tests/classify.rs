@@ -0,0 +1,147 @@
+use gix_serve_core::classify::{classify, classify_http, Classification, HttpRequest, RequestKind};
+use gix_serve_core::protocol::ServiceKind;
+
+fn pkt(line: &str) -> Vec<u8> {
+    format!("{:04x}{line}", line.len() + 4).into_bytes()
+}
+
+const OLD: &str = "0000000000000000000000000000000000000000";
+const NEW: &str = "1111111111111111111111111111111111111111";
+
+#[test]
+fn complete_requests() {
+    let request = |kind| Classification::Request(kind);
+    assert_eq!(
+        classify(&pkt(&format!("want {NEW} multi_ack side-band-64k\n"))),
+        request(RequestKind::UploadPackV1)
+    );
+    assert_eq!(classify(&pkt("command=fetch\n")), request(RequestKind::V2));
+    assert_eq!(
+        classify(&pkt(&format!("{OLD} {NEW} refs/heads/main\0report-status\n"))),
+        request(RequestKind::ReceivePackHeadInfo)
+    );
+    assert_eq!(
+        classify(&pkt(&format!("shallow {NEW}\n"))),
+        request(RequestKind::ReceivePackHeadInfo)
+    );
+    assert_eq!(
+        classify(&pkt("push-cert\0report-status\n")),
+        request(RequestKind::ReceivePackHeadInfo)
+    );
+    assert_eq!(classify(b"0000"), request(RequestKind::Flush));
+
+    let sha256 = "2".repeat(64);
+    assert_eq!(
+        classify(&pkt(&format!("{sha256} {sha256} refs/heads/main\0\n"))),
+        request(RequestKind::ReceivePackHeadInfo)
+    );
+}
+
+#[test]
+fn only_the_first_line_is_inspected() {
+    let mut stream = pkt("command=ls-refs\n");
+    stream.extend_from_slice(b"0001garbage that isn't a pkt-line");
+    assert_eq!(classify(&stream), Classification::Request(RequestKind::V2));
+}
+
+#[test]
+fn prefixes_of_requests_are_incomplete() {
+    let requests = [
+        pkt(&format!("want {NEW}\n")),
+        pkt("command=fetch\n"),
+        pkt(&format!("{OLD} {NEW} refs/heads/main\0report-status\n")),
+        pkt("push-cert\0\n"),
+    ];
+    for request in requests {
+        let kind = classify(&request);
+        assert!(matches!(kind, Classification::Request(_)));
+        let decided_at = (0..request.len())
+            .find(|&len| classify(&request[..len]) != Classification::Incomplete)
+            .unwrap_or(request.len());
+        assert!(
+            (decided_at..=request.len()).all(|len| classify(&request[..len]) == kind),
+            "{:?} must stay decided once decided",
+            request.as_slice()
+        );
+    }
+    assert_eq!(classify(b""), Classification::Incomplete);
+    assert_eq!(classify(b"00"), Classification::Incomplete);
+    assert_eq!(classify(b"0032wa"), Classification::Incomplete);
+    assert_eq!(
+        classify(&pkt(&format!("{OLD} {NEW}"))[..40]),
+        Classification::Incomplete
+    );
+}
+
+#[test]
+fn garbage() {
+    for input in [
+        &b"GET / HTTP/1.1\r\n"[..],
+        b"PACK\0\0\0\x02",
+        b"0001",
+        b"0002",
+        b"0003",
+        b"fff1want ",
+        b"0010wanted thing",
+        b"zz",
+    ] {
+        assert_eq!(classify(input), Classification::Garbage, "{input:?}");
+    }
+    assert_eq!(classify(&pkt("have 1234\n")), Classification::Garbage);
+    assert_eq!(
+        classify(&pkt(&format!("{OLD}x{NEW} refs/heads/main\n"))),
+        Classification::Garbage
+    );
+    assert_eq!(
+        classify(&pkt(&format!("{OLD} {} refs/heads/main\n", "1".repeat(64)))),
+        Classification::Garbage,
+        "mixed hash lengths"
+    );
+}
+
+#[test]
+fn services() {
+    assert_eq!(RequestKind::UploadPackV1.service(), Some(ServiceKind::UploadPack));
+    assert_eq!(
+        RequestKind::ReceivePackHeadInfo.service(),
+        Some(ServiceKind::ReceivePack)
+    );
+    assert_eq!(RequestKind::V2.service(), None);
+    assert_eq!(RequestKind::Flush.service(), None);
+}
+
+#[test]
+fn http_targets() {
+    assert_eq!(
+        classify_http("/repo.git/info/refs?service=git-upload-pack"),
+        Some(HttpRequest {
+            service: ServiceKind::UploadPack,
+            advertisement: true
+        })
+    );
+    assert_eq!(
+        classify_http("/org/repo/info/refs?foo=bar&service=git-receive-pack"),
+        Some(HttpRequest {
+            service: ServiceKind::ReceivePack,
+            advertisement: true
+        })
+    );
+    assert_eq!(
+        classify_http("/repo.git/git-receive-pack"),
+        Some(HttpRequest {
+            service: ServiceKind::ReceivePack,
+            advertisement: false
+        })
+    );
+    assert_eq!(
+        classify_http("/repo.git/git-upload-pack?x=1"),
+        Some(HttpRequest {
+            service: ServiceKind::UploadPack,
+            advertisement: false
+        })
+    );
+    assert_eq!(classify_http("/repo.git/info/refs"), None);
+    assert_eq!(classify_http("/repo.git/info/refs?service=git-upload-archive"), None);
+    assert_eq!(classify_http("/repo.git/objects/info/packs"), None);
+    assert_eq!(classify_http("git-upload-pack"), None);
+}
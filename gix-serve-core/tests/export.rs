@@ -0,0 +1,117 @@
+use gix_serve_core::export::{Error, ExportPolicy, EXPORT_OK_FILE};
+use std::path::Path;
+
+fn git_init(base: &Path, args: &[&str]) {
+    let status = std::process::Command::new("git")
+        .args(["init", "--quiet"])
+        .args(args)
+        .current_dir(base)
+        .status()
+        .expect("git is installed");
+    assert!(status.success());
+}
+
+/// A base directory with a bare `bare.git` and a non-bare `work` repository, and a bare `outside.git` next to it
+fn repositories() -> gix_testtools::tempfile::TempDir {
+    let tmp = gix_testtools::tempfile::tempdir().unwrap();
+    let base = tmp.path().join("base");
+    std::fs::create_dir(&base).unwrap();
+    git_init(&base, &["--bare", "bare.git"]);
+    git_init(&base, &["work"]);
+    git_init(tmp.path(), &["--bare", "outside.git"]);
+    tmp
+}
+
+#[test]
+fn repositories_are_found_with_the_suffixes_of_git() {
+    let tmp = repositories();
+    let base = tmp.path().join("base").canonicalize().unwrap();
+    let policy = ExportPolicy::new(&base);
+
+    for path in ["bare.git", "/bare", "./bare.git/"] {
+        let export = policy.resolve(path).unwrap();
+        assert_eq!(export.git_dir, base.join("bare.git"), "{path}");
+        assert!(export.bare, "{path}");
+    }
+    let export = policy.resolve("/work").unwrap();
+    assert_eq!(export.git_dir, base.join("work/.git"));
+    assert!(!export.bare);
+
+    assert!(matches!(policy.resolve("missing"), Err(Error::NotFound { .. })));
+}
+
+#[test]
+fn paths_may_not_leave_the_base_directory() {
+    let tmp = repositories();
+    let base = tmp.path().join("base");
+    let policy = ExportPolicy::new(&base);
+
+    for path in ["../outside.git", "/bare.git/../../outside.git", "work/../../outside"] {
+        assert!(
+            matches!(policy.resolve(path), Err(Error::OutsideBase { .. })),
+            "{path} is refused"
+        );
+    }
+
+    #[cfg(unix)]
+    {
+        std::os::unix::fs::symlink(tmp.path().join("outside.git"), base.join("link.git")).unwrap();
+        assert!(
+            matches!(policy.resolve("link.git"), Err(Error::OutsideBase { .. })),
+            "symbolic links can't escape either"
+        );
+    }
+}
+
+#[test]
+fn export_ok_and_bare_repositories_can_be_required() {
+    let tmp = repositories();
+    let base = tmp.path().join("base");
+
+    let policy = ExportPolicy::new(&base).with_bare_required(true);
+    assert!(policy.resolve("bare.git").is_ok());
+    assert!(matches!(policy.resolve("work"), Err(Error::NotBare { .. })));
+
+    let policy = ExportPolicy::new(&base).with_export_ok_required(true);
+    let err = policy.resolve("bare.git").unwrap_err();
+    assert_eq!(err.to_string(), "repository 'bare.git' not exported");
+    std::fs::write(base.join("bare.git").join(EXPORT_OK_FILE), "").unwrap();
+    assert!(policy.resolve("bare.git").is_ok());
+}
+
+#[test]
+fn refusals_are_reported_like_git_daemon() {
+    let tmp = repositories();
+    let policy = ExportPolicy::new(tmp.path().join("base")).with_export_ok_required(true);
+
+    let err = policy.resolve("/missing.git").unwrap_err();
+    assert_eq!(
+        err.daemon_message("/missing.git", false),
+        "access denied or repository not exported: /missing.git"
+    );
+    assert_eq!(
+        err.daemon_message("/missing.git", true),
+        "no such repository: /missing.git"
+    );
+    let err = policy.resolve("/bare.git").unwrap_err();
+    assert_eq!(
+        err.daemon_message("/bare.git", false),
+        "access denied or repository not exported: /bare.git",
+        "clients can't tell existing repositories apart"
+    );
+    assert_eq!(
+        err.daemon_message("/bare.git", true),
+        "repository not exported: /bare.git"
+    );
+
+    #[cfg(feature = "blocking-io")]
+    {
+        let mut out = Vec::new();
+        let mut writer = gix_serve_core::io_blocking::pkt_writer(&mut out);
+        gix_serve_core::pktline::write_error(&mut writer, err.daemon_message("/bare.git", false).as_bytes()).unwrap();
+        assert_eq!(
+            out,
+            b"003bERR access denied or repository not exported: /bare.git".as_slice()
+        );
+    }
+}
@@ -0,0 +1,65 @@
+use gix_serve_core::metrics::{Meter, Metrics, Phase};
+use gix_serve_core::protocol::ServiceKind;
+use gix_serve_core::wire::WireStats;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+#[derive(Default, Clone)]
+struct Events(Arc<Mutex<Vec<String>>>);
+
+impl Metrics for Events {
+    fn session_started(&self, service: ServiceKind, active: usize) {
+        self.0.lock().unwrap().push(format!("start {service:?} {active}"));
+    }
+
+    fn session_finished(&self, service: ServiceKind, wire: &WireStats, _: Duration, success: bool, active: usize) {
+        let bytes = wire.response_bytes;
+        self.0
+            .lock()
+            .unwrap()
+            .push(format!("finish {service:?} {bytes} {success} {active}"));
+    }
+
+    fn phase(&self, _: ServiceKind, phase: Phase, _: Duration) {
+        self.0.lock().unwrap().push(format!("phase {phase}"));
+    }
+}
+
+#[test]
+fn sessions_and_phases_are_passed_to_the_metrics() {
+    let events = Events::default();
+    let meter = Meter::new(events.clone());
+    assert!(meter.is_enabled());
+
+    let first = meter.session(ServiceKind::UploadPack);
+    let second = meter.clone().session(ServiceKind::ReceivePack);
+    drop(meter.phase(ServiceKind::UploadPack, Phase::PackGeneration));
+    let wire = WireStats {
+        response_bytes: 42,
+        ..Default::default()
+    };
+    first.finish(&wire, true);
+    drop(second);
+    meter.negotiation_round(ServiceKind::UploadPack);
+
+    assert_eq!(
+        *events.0.lock().unwrap(),
+        [
+            "start UploadPack 1",
+            "start ReceivePack 2",
+            "phase pack-generation",
+            "finish UploadPack 42 true 1",
+            "finish ReceivePack 0 false 0",
+        ],
+        "unfinished sessions fail, and callbacks that aren't implemented do nothing"
+    );
+}
+
+#[test]
+fn nothing_is_measured_by_default() {
+    let meter = Meter::default();
+    assert!(!meter.is_enabled());
+    meter.session(ServiceKind::UploadPack).finish(&WireStats::default(), true);
+    assert_eq!(format!("{meter:?}"), "Meter(disabled)");
+    assert_eq!(Phase::Handshake.as_str(), "handshake");
+}
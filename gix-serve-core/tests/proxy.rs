@@ -0,0 +1,88 @@
+use gix_serve_core::proxy::{read_header, Error, Header, Version};
+use std::io::{Cursor, Read};
+use std::net::SocketAddr;
+
+/// Read the header from `input`, returning it along with the data following it
+fn read(input: &[u8]) -> (Result<Header, Error>, Vec<u8>) {
+    let mut input = Cursor::new(input);
+    let header = read_header(&mut input);
+    let mut rest = Vec::new();
+    input.read_to_end(&mut rest).unwrap();
+    (header, rest)
+}
+
+fn addr(addr: &str) -> Option<SocketAddr> {
+    Some(addr.parse().unwrap())
+}
+
+fn v2(command: u8, family: u8, addresses: &[u8]) -> Vec<u8> {
+    let mut header = b"\r\n\r\n\0\r\nQUIT\n".to_vec();
+    header.extend_from_slice(&[0x20 | command, family << 4 | 1]);
+    header.extend_from_slice(&(addresses.len() as u16).to_be_bytes());
+    header.extend_from_slice(addresses);
+    header
+}
+
+#[test]
+fn version_1() {
+    let (header, rest) = read(b"PROXY TCP4 192.0.2.1 198.51.100.2 51234 9418\r\n0032git-upload-pack");
+    let header = header.unwrap();
+    assert_eq!(header.version, Version::V1);
+    assert_eq!(header.source, addr("192.0.2.1:51234"));
+    assert_eq!(header.destination, addr("198.51.100.2:9418"));
+    assert_eq!(
+        rest, b"0032git-upload-pack",
+        "the data following the header is left alone"
+    );
+
+    let (header, _) = read(b"PROXY TCP6 2001:db8::1 2001:db8::2 51234 9418\r\n");
+    assert_eq!(header.unwrap().source, addr("[2001:db8::1]:51234"));
+
+    let (header, rest) = read(b"PROXY UNKNOWN ffff::1 ffff::2 1 2\r\n0000");
+    let peer = "203.0.113.7:4000".parse().unwrap();
+    assert_eq!(header.unwrap().remote_addr(peer), peer, "the peer is the client");
+    assert_eq!(rest, b"0000");
+}
+
+#[test]
+fn version_2() {
+    let mut addresses = vec![192, 0, 2, 1, 198, 51, 100, 2];
+    addresses.extend_from_slice(&51234u16.to_be_bytes());
+    addresses.extend_from_slice(&9418u16.to_be_bytes());
+    addresses.extend_from_slice(&[0x04, 0, 1, 0]);
+    let mut input = v2(1, 1, &addresses);
+    input.extend_from_slice(b"0000");
+    let (header, rest) = read(&input);
+    let header = header.unwrap();
+    assert_eq!(header.version, Version::V2);
+    assert_eq!(header.source, addr("192.0.2.1:51234"));
+    assert_eq!(header.destination, addr("198.51.100.2:9418"));
+    assert_eq!(rest, b"0000", "TLVs after the addresses are skipped");
+
+    let mut addresses = [0; 36];
+    addresses[..16].copy_from_slice(&"2001:db8::1".parse::<std::net::Ipv6Addr>().unwrap().octets());
+    addresses[32..34].copy_from_slice(&51234u16.to_be_bytes());
+    let (header, _) = read(&v2(1, 2, &addresses));
+    assert_eq!(header.unwrap().source, addr("[2001:db8::1]:51234"));
+
+    let (header, _) = read(&v2(0, 0, &[]));
+    assert_eq!(
+        header.unwrap().source,
+        None,
+        "health checks of the load balancer are local"
+    );
+}
+
+#[test]
+fn invalid_headers() {
+    assert!(matches!(read(b"0032git-upload-pack /repo\0").0, Err(Error::Missing)));
+    assert!(matches!(
+        read(b"PROXY TCP4 2001:db8::1 192.0.2.1 1 2\r\n").0,
+        Err(Error::Invalid(_))
+    ));
+    assert!(matches!(read(b"PROXY TCP4 192.0.2.1\r\n").0, Err(Error::Invalid(_))));
+    let long = format!("PROXY TCP4 {}\r\n", "1".repeat(120));
+    assert!(matches!(read(long.as_bytes()).0, Err(Error::Invalid(_))));
+    assert!(matches!(read(&v2(1, 1, &[192, 0, 2, 1])).0, Err(Error::Invalid(_))));
+    assert!(matches!(read(b"PROXY TCP4 192.0").0, Err(Error::Io(_))));
+}
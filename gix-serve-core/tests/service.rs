@@ -0,0 +1,68 @@
+#![cfg(feature = "testing")]
+
+use gix_serve_core::protocol::{ProtocolVersion, ServiceKind};
+use gix_serve_core::service::{Error, GitService, Outcome, RefUpdate, ServiceContext};
+use gix_serve_core::testing::MockService;
+
+/// Route `input` to the service of `kind`, like an HTTP adapter would.
+fn route(
+    services: &mut [Box<dyn GitService>],
+    kind: ServiceKind,
+    input: &[u8],
+    ctx: &ServiceContext,
+) -> Result<(Vec<u8>, Outcome), Error> {
+    let service = services
+        .iter_mut()
+        .find(|service| service.kind() == kind)
+        .expect("service is registered");
+    let mut output = Vec::new();
+    let outcome = service.serve(&mut &input[..], &mut output, ctx)?;
+    Ok((output, outcome))
+}
+
+#[test]
+fn services_are_driven_through_the_trait() {
+    let pushed = Outcome {
+        ref_updates: vec![RefUpdate {
+            name: "refs/heads/main".into(),
+            rejected: None,
+        }],
+        ..Default::default()
+    };
+    let mut services: Vec<Box<dyn GitService>> = vec![
+        Box::new(MockService::new(ServiceKind::UploadPack).with_response("0008NAK\n", Outcome::default())),
+        Box::new(
+            MockService::new(ServiceKind::ReceivePack)
+                .with_advertisement("adv")
+                .with_response("0000", pushed.clone()),
+        ),
+    ];
+    let stateless = ServiceContext::new(ProtocolVersion::V1).with_stateless(true);
+
+    let (output, outcome) = route(&mut services, ServiceKind::UploadPack, b"0000", &stateless).unwrap();
+    assert_eq!(output, b"0008NAK\n");
+    assert_eq!(outcome, Outcome::default());
+
+    let stateful = ServiceContext::new(ProtocolVersion::V0).with_trace_id("t-1");
+    let (output, outcome) = route(&mut services, ServiceKind::ReceivePack, b"request", &stateful).unwrap();
+    assert_eq!(output, b"adv0000", "stateful requests start with the advertisement");
+    assert_eq!(outcome, pushed);
+
+    let mut advertisement = Vec::new();
+    services[1].advertise(&mut advertisement, &stateless).unwrap();
+    assert_eq!(advertisement, b"adv");
+}
+
+#[test]
+fn mock_records_requests_and_fails_on_demand() {
+    let mut service = MockService::new(ServiceKind::UploadPack).with_failure("boom");
+    let ctx = ServiceContext::new(ProtocolVersion::V2).with_stateless(true);
+    let err = service
+        .serve(&mut &b"command=ls-refs"[..], &mut Vec::new(), &ctx)
+        .unwrap_err();
+    assert!(matches!(&err, Error::Protocol(message) if message == "boom"), "{err}");
+    assert_eq!(service.served.len(), 1);
+    assert_eq!(service.served[0].ctx, ctx);
+    assert_eq!(service.served[0].input, b"command=ls-refs");
+    assert!(service.advertised.is_empty());
+}
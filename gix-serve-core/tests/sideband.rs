@@ -0,0 +1,35 @@
+#![cfg(feature = "testing")]
+
+use gix_serve_core::testing::SidebandDemux;
+
+#[test]
+fn demux_sideband_channels() {
+    let data = b"0008NAK\n0016\x02Counting objects\n0011\x01PACK\x00\x00\x00\x02\x00\x00\x00\x00000f\x03fatal: no\n0000";
+    let out = SidebandDemux::default().demux(data);
+    assert_eq!(out.lines, vec![b"NAK\n".to_vec()]);
+    assert!(out.has_nak());
+    assert_eq!(out.progress, vec!["Counting objects"]);
+    assert_eq!(out.pack().map(<[u8]>::len), Some(12));
+    assert_eq!(out.errors, vec!["fatal: no"]);
+    assert_eq!(out.flushes, 1);
+    assert_eq!(out.malformed_at, None);
+}
+
+#[test]
+fn demux_raw_pack_without_sideband() {
+    let data = b"0008NAK\nPACK\x00\x00\x00\x02\x00\x00\x00\x00trailer";
+    let out = SidebandDemux::default().demux(data);
+    assert!(out.has_nak());
+    assert_eq!(out.pack, b"PACK\x00\x00\x00\x02\x00\x00\x00\x00trailer".to_vec());
+}
+
+#[test]
+fn demux_err_packet_and_malformed_input() {
+    let out = SidebandDemux::default().demux(b"000fERR denied\n00zz");
+    assert_eq!(out.errors, vec!["denied"]);
+    assert_eq!(out.malformed_at, Some(15));
+
+    let out = SidebandDemux { keep_whitespace: true }.demux(b"000a\x02done\n0010truncated");
+    assert_eq!(out.progress, vec!["done\n"]);
+    assert_eq!(out.malformed_at, Some(10));
+}
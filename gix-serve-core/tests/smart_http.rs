@@ -0,0 +1,341 @@
+#![cfg(all(feature = "hyper", feature = "testing"))]
+
+use bytes::Bytes;
+use gix_serve_core::advertise::Fingerprint;
+use gix_serve_core::protocol::ServiceKind;
+use gix_serve_core::service::{Error, GitService, Outcome, ServiceContext};
+use gix_serve_core::smart_http::{ResponseBody, SmartHttp};
+use gix_serve_core::testing::MockService;
+use http::{header, Request, Response, StatusCode};
+use http_body_util::{BodyExt, Full};
+use std::io::{Read, Write};
+use std::sync::{Arc, Mutex};
+use tower_service::Service;
+
+/// Answers each request with its input, and the advertisement with the protocol version it was asked for.
+struct Echo;
+
+impl GitService for Echo {
+    fn kind(&self) -> ServiceKind {
+        ServiceKind::UploadPack
+    }
+
+    fn advertise(&mut self, out: &mut dyn Write, ctx: &ServiceContext) -> Result<(), Error> {
+        assert!(ctx.stateless);
+        write!(out, "{:?}", ctx.version)?;
+        Ok(())
+    }
+
+    fn serve(&mut self, input: &mut dyn Read, output: &mut dyn Write, ctx: &ServiceContext) -> Result<Outcome, Error> {
+        assert!(ctx.stateless);
+        std::io::copy(input, output)?;
+        Ok(Outcome::default())
+    }
+}
+
+/// Advertises the protocol version along with its fingerprint, counting how often it advertised.
+struct Fingerprinted(Arc<Mutex<usize>>);
+
+impl GitService for Fingerprinted {
+    fn kind(&self) -> ServiceKind {
+        ServiceKind::UploadPack
+    }
+
+    fn advertise(&mut self, out: &mut dyn Write, ctx: &ServiceContext) -> Result<(), Error> {
+        *self.0.lock().unwrap() += 1;
+        write!(out, "{:?}", ctx.version)?;
+        Ok(())
+    }
+
+    fn advertisement_fingerprint(&mut self, ctx: &ServiceContext) -> Result<Option<Fingerprint>, Error> {
+        let mut hasher = Fingerprint::hasher();
+        hasher.update(format!("{:?}", ctx.version));
+        Ok(Some(hasher.finalize()))
+    }
+
+    fn serve(&mut self, _: &mut dyn Read, _: &mut dyn Write, _: &ServiceContext) -> Result<Outcome, Error> {
+        Ok(Outcome::default())
+    }
+}
+
+type Resolved = Arc<Mutex<Vec<(String, ServiceKind)>>>;
+type Resolver = Box<dyn Fn(&str, ServiceKind) -> Result<Box<dyn GitService + Send>, StatusCode> + Send + Sync>;
+
+fn smart_http(
+    service: impl Fn() -> Box<dyn GitService + Send> + Send + Sync + 'static,
+) -> (SmartHttp<Resolver>, Resolved) {
+    let resolved = Resolved::default();
+    let log = resolved.clone();
+    let http = SmartHttp::new(Box::new(move |repository: &str, kind| {
+        log.lock().unwrap().push((repository.to_owned(), kind));
+        match repository {
+            "/missing.git" => Err(StatusCode::NOT_FOUND),
+            _ if kind == ServiceKind::ReceivePack => Err(StatusCode::FORBIDDEN),
+            _ => Ok(service()),
+        }
+    }) as Resolver);
+    (http, resolved)
+}
+
+async fn call(http: &mut SmartHttp<Resolver>, request: Request<Full<Bytes>>) -> (Response<()>, Vec<u8>) {
+    let response: Response<ResponseBody> = http.call(request).await.unwrap();
+    let (parts, body) = response.into_parts();
+    let body = body.collect().await.unwrap().to_bytes().to_vec();
+    (Response::from_parts(parts, ()), body)
+}
+
+fn get(target: &str) -> http::request::Builder {
+    Request::get(target)
+}
+
+fn post(target: &str) -> http::request::Builder {
+    Request::post(target).header(header::CONTENT_TYPE, "application/x-git-upload-pack-request")
+}
+
+#[tokio::test]
+async fn advertisements_announce_the_service_unless_v2_is_requested() {
+    let (mut http, resolved) = smart_http(|| Box::new(Echo));
+
+    let request = get("/org/repo.git/info/refs?service=git-upload-pack").body(Full::default());
+    let (response, body) = call(&mut http, request.unwrap()).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.headers()[header::CONTENT_TYPE],
+        "application/x-git-upload-pack-advertisement"
+    );
+    assert_eq!(
+        response.headers()[header::CACHE_CONTROL],
+        "no-cache, max-age=0, must-revalidate"
+    );
+    assert_eq!(body, b"001e# service=git-upload-pack\n0000V0");
+
+    let request = get("/org/repo.git/info/refs?service=git-upload-pack")
+        .header("Git-Protocol", "version=2")
+        .body(Full::default());
+    let (_, body) = call(&mut http, request.unwrap()).await;
+    assert_eq!(body, b"V2");
+    assert_eq!(
+        resolved.lock().unwrap()[0],
+        ("/org/repo.git".to_owned(), ServiceKind::UploadPack)
+    );
+}
+
+#[tokio::test]
+async fn the_version_comes_from_the_header_or_the_query() {
+    let (mut http, _) = smart_http(|| Box::new(Echo));
+    let cases = [
+        ("", Some("version=1"), "V1"),
+        ("", Some("version=1:version=2"), "V2"),
+        ("", Some("version=3:object-format=sha1"), "V0"),
+        ("&git-protocol=version%3D2", None, "V2"),
+        ("&git-protocol=version%3D2", Some("version=1"), "V1"),
+        ("&git-protocol=version=1", None, "V1"),
+    ];
+    for (query, header, expected) in cases {
+        let mut request = get(&format!("/repo/info/refs?service=git-upload-pack{query}"));
+        if let Some(header) = header {
+            request = request.header("Git-Protocol", header);
+        }
+        let (response, body) = call(&mut http, request.body(Full::default()).unwrap()).await;
+        assert_eq!(response.headers()[header::VARY], "Git-Protocol");
+        let body = String::from_utf8(body).unwrap();
+        assert!(body.ends_with(expected), "{query} {header:?}: {body}");
+    }
+}
+
+#[tokio::test]
+async fn requests_are_served_from_plain_and_gzip_bodies() {
+    let (mut http, resolved) = smart_http(|| Box::new(Echo));
+    let input = b"0032want 1111111111111111111111111111111111111111\n00000009done\n".repeat(5000);
+
+    let (response, body) = call(
+        &mut http,
+        post("/repo/git-upload-pack").body(input.clone().into()).unwrap(),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.headers()[header::CONTENT_TYPE],
+        "application/x-git-upload-pack-result"
+    );
+    assert_eq!(body, input, "output larger than a chunk is streamed completely");
+
+    let mut gzip = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    gzip.write_all(&input).unwrap();
+    let request = post("/repo/git-upload-pack")
+        .header(header::CONTENT_ENCODING, "gzip")
+        .body(gzip.finish().unwrap().into());
+    let (response, body) = call(&mut http, request.unwrap()).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(body, input, "gzip bodies are decompressed");
+    assert!(resolved
+        .lock()
+        .unwrap()
+        .iter()
+        .all(|(repository, _)| repository == "/repo"));
+}
+
+#[tokio::test]
+async fn invalid_requests_are_rejected_with_a_status() {
+    let (mut http, _) = smart_http(|| Box::new(Echo));
+    let cases = [
+        (get("/repo/objects/info/packs"), StatusCode::NOT_FOUND),
+        (
+            get("/missing.git/info/refs?service=git-upload-pack"),
+            StatusCode::NOT_FOUND,
+        ),
+        (get("/repo/info/refs?service=git-receive-pack"), StatusCode::FORBIDDEN),
+        (
+            post("/repo/info/refs?service=git-upload-pack"),
+            StatusCode::METHOD_NOT_ALLOWED,
+        ),
+        (get("/repo/git-upload-pack"), StatusCode::METHOD_NOT_ALLOWED),
+        (
+            Request::post("/repo/git-upload-pack"),
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+        ),
+        (
+            post("/repo/git-upload-pack").header(header::CONTENT_ENCODING, "br"),
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+        ),
+    ];
+    for (request, expected) in cases {
+        let request = request.body(Full::default()).unwrap();
+        let target = request.uri().to_string();
+        let (response, _) = call(&mut http, request).await;
+        assert_eq!(response.status(), expected, "{target}");
+    }
+
+    let mut limited = http.with_max_request_size(4);
+    let (response, _) = call(
+        &mut limited,
+        post("/repo/git-upload-pack").body("00000".into()).unwrap(),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+}
+
+#[tokio::test]
+async fn service_failures_before_any_output_have_a_status() {
+    let (mut http, _) = smart_http(|| Box::new(MockService::new(ServiceKind::UploadPack).with_failure("bad want")));
+    let (response, body) = call(&mut http, post("/repo/git-upload-pack").body("0000".into()).unwrap()).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    assert_eq!(String::from_utf8(body).unwrap(), "protocol error: bad want\n");
+}
+
+#[tokio::test]
+async fn unchanged_advertisements_are_not_generated_again() {
+    let advertised = Arc::new(Mutex::new(0));
+    let (mut http, _) = smart_http({
+        let advertised = advertised.clone();
+        move || Box::new(Fingerprinted(advertised.clone()))
+    });
+    let target = "/repo/info/refs?service=git-upload-pack";
+
+    let (response, body) = call(&mut http, get(target).body(Full::default()).unwrap()).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(body.ends_with(b"V0"));
+    let etag = response.headers()[header::ETAG].to_str().unwrap().to_owned();
+    assert_eq!(*advertised.lock().unwrap(), 1);
+
+    let request = get(target).header(header::IF_NONE_MATCH, format!("\"other\", {etag}"));
+    let (response, body) = call(&mut http, request.body(Full::default()).unwrap()).await;
+    assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+    assert_eq!(response.headers()[header::ETAG], etag.as_str());
+    assert_eq!(response.headers()[header::VARY], "Git-Protocol");
+    assert!(body.is_empty());
+    assert_eq!(*advertised.lock().unwrap(), 1, "the advertisement wasn't generated");
+
+    let request = get(target)
+        .header(header::IF_NONE_MATCH, etag.as_str())
+        .header("Git-Protocol", "version=2");
+    let (response, body) = call(&mut http, request.body(Full::default()).unwrap()).await;
+    assert_eq!(
+        response.status(),
+        StatusCode::OK,
+        "the fingerprint depends on the version"
+    );
+    assert_eq!(body, b"V2");
+    assert_ne!(response.headers()[header::ETAG], etag.as_str());
+
+    let (mut http, _) = smart_http(|| Box::new(Echo));
+    let request = get(target).header(header::IF_NONE_MATCH, "*");
+    let (response, _) = call(&mut http, request.body(Full::default()).unwrap()).await;
+    assert_eq!(
+        response.status(),
+        StatusCode::OK,
+        "services without fingerprint always advertise"
+    );
+    assert!(response.headers().get(header::ETAG).is_none());
+}
+
+#[test]
+fn fingerprints_match_strong_and_weak_entity_tags() {
+    let fingerprint = |parts: &[&str]| {
+        let mut hasher = Fingerprint::hasher();
+        for part in parts {
+            hasher.update(part);
+        }
+        hasher.finalize()
+    };
+    let a = fingerprint(&["ab", "c"]);
+    assert_ne!(a, fingerprint(&["a", "bc"]), "parts are delimited");
+    assert_eq!(a, fingerprint(&["ab", "c"]));
+    let etag = a.to_etag();
+    assert_eq!(etag, format!("\"{a}\""));
+    assert!(a.matches(&etag));
+    assert!(a.matches(&format!("W/{etag}")));
+    assert!(a.matches("*"));
+    assert!(!a.matches(&format!("{a}")), "unquoted tags are invalid");
+}
+
+#[tokio::test]
+async fn request_bodies_are_limited_after_decompression() {
+    let (http, _) = smart_http(|| Box::new(Echo));
+    let mut limited = http.with_max_request_size(1000);
+    let mut gzip = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    gzip.write_all(&[b'0'; 100_000]).unwrap();
+    let body = gzip.finish().unwrap();
+    assert!(body.len() < 1000);
+    let request = post("/repo/git-upload-pack")
+        .header(header::CONTENT_ENCODING, "gzip")
+        .body(body.into());
+    let (response, _) = call(&mut limited, request.unwrap()).await;
+    assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+    let (response, body) = call(
+        &mut limited,
+        post("/repo/git-upload-pack").body(vec![b'0'; 1000].into()).unwrap(),
+    )
+    .await;
+    assert_eq!(
+        response.status(),
+        StatusCode::OK,
+        "bodies of exactly the limit are fine"
+    );
+    assert_eq!(body.len(), 1000);
+}
+
+/// Panics while serving a request.
+struct Panicking;
+
+impl GitService for Panicking {
+    fn kind(&self) -> ServiceKind {
+        ServiceKind::UploadPack
+    }
+
+    fn advertise(&mut self, _: &mut dyn Write, _: &ServiceContext) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn serve(&mut self, _: &mut dyn Read, _: &mut dyn Write, _: &ServiceContext) -> Result<Outcome, Error> {
+        panic!("the service failed unexpectedly")
+    }
+}
+
+#[tokio::test]
+async fn panicking_services_are_answered_with_an_internal_server_error() {
+    let (mut http, _) = smart_http(|| Box::new(Panicking));
+    let (response, _) = call(&mut http, post("/repo/git-upload-pack").body("0000".into()).unwrap()).await;
+    assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+}
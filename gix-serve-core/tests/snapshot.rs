@@ -0,0 +1,167 @@
+#![cfg(feature = "testing")]
+
+use gix_serve_core::testing::snapshot::{Normalization, Transcript};
+
+fn pkt(data: &[u8]) -> Vec<u8> {
+    let mut line = format!("{:04x}", data.len() + 4).into_bytes();
+    line.extend_from_slice(data);
+    line
+}
+
+fn band(band: u8, data: &[u8]) -> Vec<u8> {
+    pkt(&[&[band], data].concat())
+}
+
+fn pack(objects: u32) -> Vec<u8> {
+    [&b"PACK\0\0\0\x02"[..], &objects.to_be_bytes(), &[0x90; 20]].concat()
+}
+
+#[test]
+fn conversations_are_annotated_by_direction_and_band() {
+    let advertisement = [
+        pkt(b"version 2\n"),
+        pkt(b"agent=git/2.39.5\n"),
+        pkt(b"fetch=shallow\n"),
+        b"0000".to_vec(),
+    ]
+    .concat();
+    let request = [
+        pkt(b"command=fetch\n"),
+        pkt(b"agent=git/2.45.0-1-gdeadbeef\n"),
+        pkt(b"session-id=a1b2c3"),
+        b"0001".to_vec(),
+        pkt(b"want 1111111111111111111111111111111111111111\n"),
+        pkt(b"done\n"),
+        b"0000".to_vec(),
+    ]
+    .concat();
+    let pack = pack(3);
+    let response = [
+        pkt(b"packfile\n"),
+        band(2, b"Enumerating objects: 1\rEnumerating objects: 3, done.\nCounting "),
+        band(2, b"objects: 100% (3/3), 1.5 KiB | 1.50 MiB/s, done.\n"),
+        band(1, &pack[..10]),
+        band(2, b"Total 3 (delta 0)\n"),
+        band(1, &pack[10..]),
+        b"0000".to_vec(),
+    ]
+    .concat();
+
+    let mut transcript = Transcript::new("fetch with protocol v2").with_normalization(
+        Normalization::default().with_replacement("1111111111111111111111111111111111111111", "<main>"),
+    );
+    transcript
+        .response(&advertisement)
+        .request(&request)
+        .response(&response);
+    assert_eq!(
+        transcript.to_string(),
+        r"# fetch with protocol v2
+
+S: version 2\n
+S: agent=*\n
+S: fetch=shallow\n
+S: flush
+
+C: command=fetch\n
+C: agent=*\n
+C: session-id=*
+C: delim
+C: want <main>\n
+C: done\n
+C: flush
+
+S: packfile\n
+S [progress]: Enumerating objects: 3, done.\n
+S [progress]: Counting objects: 100% (3/3), 1.5 KiB | <rate>, done.\n
+S [pack]: pack with 3 objects
+S [progress]: Total 3 (delta 0)\n
+S: flush
+"
+    );
+}
+
+#[test]
+fn reports_on_band_one_raw_packs_and_errors_are_decoded() {
+    let report = [
+        pkt(b"unpack ok\n"),
+        pkt(b"ng refs/heads/main\0x non-fast-forward\n"),
+        b"0000".to_vec(),
+    ]
+    .concat();
+    let mut transcript = Transcript::new("push");
+    transcript
+        .response(&[band(1, &report), band(3, b"hook declined\n"), b"0000".to_vec()].concat())
+        .response(&[pkt(b"NAK\n"), pack(2)].concat())
+        .response(b"0008NAK\n00zz")
+        .request(&pkt(b"tagger C O Mitter <c@example.com> 1700000000 +0100\n"));
+    assert_eq!(
+        transcript.lines()[1..],
+        [
+            "",
+            r"S [data]: unpack ok\n",
+            r"S [data]: ng refs/heads/main\0x non-fast-forward\n",
+            "S [data]: flush",
+            r"S [error]: hook declined\n",
+            "S: flush",
+            "",
+            r"S: NAK\n",
+            "S [pack]: pack with 2 objects",
+            "",
+            r"S: NAK\n",
+            "S [malformed]: 00zz",
+            "",
+            r"C: tagger C O Mitter <c@example.com> <timestamp>\n",
+        ]
+    );
+}
+
+#[test]
+fn progress_updates_and_agents_can_be_kept() {
+    let normalization = Normalization {
+        agent: false,
+        progress_updates: true,
+        ..Normalization::default()
+    };
+    let mut transcript = Transcript::new("progress").with_normalization(normalization);
+    transcript.response(
+        &[
+            pkt(b"agent=git/2.39.5\n"),
+            band(2, b"Counting: 1\rCounting: 2, done.\n"),
+        ]
+        .concat(),
+    );
+    assert_eq!(
+        transcript.lines()[2..],
+        [
+            r"S: agent=git/2.39.5\n",
+            r"S [progress]: Counting: 1\r",
+            r"S [progress]: Counting: 2, done.\n",
+        ]
+    );
+}
+
+#[test]
+fn golden_files_are_compared() {
+    let tmp = gix_testtools::tempfile::tempdir().unwrap();
+    let golden = tmp.path().join("ls-refs.txt");
+    let mut transcript = Transcript::new("ls-refs");
+    transcript.request(b"0014command=ls-refs\n0000");
+    std::fs::write(&golden, "# ls-refs\n\nC: command=ls-refs\\n\nC: flush\n").unwrap();
+    transcript.assert_golden(&golden);
+
+    std::fs::write(&golden, "# ls-refs\n\nC: command=fetch\\n\nC: flush\n").unwrap();
+    let panic = std::panic::catch_unwind(|| transcript.assert_golden(&golden)).unwrap_err();
+    let message = panic.downcast_ref::<String>().unwrap();
+    assert!(
+        message.ends_with("  # ls-refs\n  \n- C: command=fetch\\n\n+ C: command=ls-refs\\n\n  C: flush\n"),
+        "{message}"
+    );
+
+    let missing = std::panic::catch_unwind(|| transcript.assert_golden(tmp.path().join("missing.txt"))).unwrap_err();
+    let message = missing.downcast_ref::<String>().unwrap();
+    assert!(
+        message.contains("run with GIX_SERVE_UPDATE_SNAPSHOTS=1 to create it"),
+        "{message}"
+    );
+}
@@ -0,0 +1,108 @@
+#![cfg(feature = "rustls")]
+
+use gix_serve_core::tls::{Acceptor, Error};
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Write a self-signed certificate for `localhost` and its key to `dir`, returning their paths
+fn certificate(dir: &Path) -> (std::path::PathBuf, std::path::PathBuf) {
+    let (cert, key) = (dir.join("cert.pem"), dir.join("key.pem"));
+    let status = std::process::Command::new("openssl")
+        .args([
+            "req",
+            "-x509",
+            "-newkey",
+            "ec",
+            "-pkeyopt",
+            "ec_paramgen_curve:prime256v1",
+            "-nodes",
+        ])
+        .args([
+            "-days",
+            "1",
+            "-subj",
+            "/CN=localhost",
+            "-addext",
+            "subjectAltName=DNS:localhost",
+        ])
+        .args(["-addext", "basicConstraints=critical,CA:FALSE", "-keyout"])
+        .arg(&key)
+        .arg("-out")
+        .arg(&cert)
+        .stderr(std::process::Stdio::null())
+        .status()
+        .expect("openssl is installed");
+    assert!(status.success());
+    (cert, key)
+}
+
+/// A client trusting the certificate at `cert`, offering the ALPN protocols `alpn`
+fn client(cert: &Path, alpn: &[&[u8]]) -> rustls::ClientConnection {
+    let mut roots = rustls::RootCertStore::empty();
+    let cert = std::fs::read(cert).unwrap();
+    for cert in rustls_pki_types::pem::PemObject::pem_slice_iter(&cert) {
+        roots.add(cert.unwrap()).unwrap();
+    }
+    let mut config = rustls::ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    config.alpn_protocols = alpn.iter().map(|protocol| protocol.to_vec()).collect();
+    rustls::ClientConnection::new(Arc::new(config), "localhost".try_into().unwrap()).unwrap()
+}
+
+#[test]
+fn encrypted_connections_carry_the_git_protocol() {
+    let tmp = gix_testtools::tempfile::tempdir().unwrap();
+    let (cert, key) = certificate(tmp.path());
+    let acceptor = Acceptor::from_pem_files(&cert, &key).unwrap();
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let client = std::thread::spawn(move || {
+        let mut stream = rustls::StreamOwned::new(client(&cert, &[b"h2"]), TcpStream::connect(addr).unwrap());
+        stream.write_all(b"0000").unwrap();
+        let mut response = [0; 4];
+        stream.read_exact(&mut response).unwrap();
+        response
+    });
+    let (stream, _) = listener.accept().unwrap();
+    let mut stream = acceptor.accept(stream).unwrap();
+    assert_eq!(stream.sock.read_timeout().unwrap(), None, "timeouts are restored");
+    let mut request = [0; 4];
+    stream.read_exact(&mut request).unwrap();
+    assert_eq!(&request, b"0000");
+    stream.write_all(b"0000").unwrap();
+    stream.flush().unwrap();
+    assert_eq!(&client.join().unwrap(), b"0000", "offered ALPN protocols are ignored");
+}
+
+#[test]
+fn failed_handshakes_are_refused() {
+    let tmp = gix_testtools::tempfile::tempdir().unwrap();
+    let (cert, key) = certificate(tmp.path());
+    let acceptor = Acceptor::from_pem_files(&cert, &key)
+        .unwrap()
+        .with_handshake_timeout(Some(Duration::from_millis(100)));
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let mut plain = TcpStream::connect(addr).unwrap();
+    plain
+        .write_all(b"0032git-upload-pack /repo.git\0host=localhost\0")
+        .unwrap();
+    let (stream, _) = listener.accept().unwrap();
+    assert!(matches!(acceptor.accept(stream), Err(Error::Handshake(_))), "plain git");
+
+    let _silent = TcpStream::connect(addr).unwrap();
+    let (stream, _) = listener.accept().unwrap();
+    assert!(matches!(acceptor.accept(stream), Err(Error::Handshake(_))), "timeout");
+
+    let err = Acceptor::from_pem_files(&key, &key).unwrap_err();
+    assert!(matches!(err, Error::Pem { .. }), "{err}");
+    assert!(err.to_string().contains("key.pem"), "{err}");
+}
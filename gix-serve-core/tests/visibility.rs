@@ -1,4 +1,4 @@
-use gix_serve_core::visibility::{RefRecord, VisibleRoots};
+use gix_serve_core::visibility::{HiddenRefs, RefRecord, VisibleRoots};
 
 #[test]
 fn ref_record_new() {
@@ -23,4 +23,83 @@ fn visible_roots_filters_hidden() {
     }
 }
 
+#[test]
+fn hidden_refs_match_prefixes_at_component_boundaries() {
+    let hidden = HiddenRefs::new()
+        .with_pattern("refs/pull/")
+        .with_pattern("refs/heads/secret");
+    assert!(hidden.is_hidden("refs/pull"));
+    assert!(hidden.is_hidden("refs/pull/1/head"));
+    assert!(!hidden.is_hidden("refs/pulls"), "only whole components match");
+    assert!(hidden.is_hidden("refs/heads/secret"));
+    assert!(!hidden.is_hidden("refs/heads/secrets"));
+    assert!(!hidden.is_hidden("refs/heads/main"));
+    assert!(!HiddenRefs::new().is_hidden("refs/heads/main"));
+}
+
+#[test]
+fn hidden_refs_let_the_last_matching_pattern_decide() {
+    let hidden = HiddenRefs::new()
+        .with_pattern("refs/pull")
+        .with_pattern("!refs/pull/1")
+        .with_pattern("refs/pull/1/merge");
+    assert!(hidden.is_hidden("refs/pull/2/head"));
+    assert!(
+        !hidden.is_hidden("refs/pull/1/head"),
+        "negated patterns show refs again"
+    );
+    assert!(hidden.is_hidden("refs/pull/1/merge"));
+
+    let hidden = HiddenRefs::new().with_pattern("!refs/pull/1").with_pattern("refs/pull");
+    assert!(hidden.is_hidden("refs/pull/1/head"), "earlier patterns are overridden");
+}
+
+#[test]
+fn hidden_refs_match_full_names_with_a_caret() {
+    let hidden = HiddenRefs::new()
+        .with_pattern("^refs/namespaces/a/refs/heads/secret")
+        .with_pattern("refs/heads/internal");
+    assert!(hidden.is_hidden_in_namespace(Some("refs/heads/secret"), "refs/namespaces/a/refs/heads/secret"));
+    assert!(!hidden.is_hidden_in_namespace(Some("refs/heads/secret"), "refs/namespaces/b/refs/heads/secret"));
+    assert!(hidden.is_hidden_in_namespace(Some("refs/heads/internal"), "refs/namespaces/b/refs/heads/internal"));
+    assert!(
+        !hidden.is_hidden_in_namespace(None, "refs/heads/internal"),
+        "refs outside of the namespace only match full names"
+    );
+    assert!(
+        HiddenRefs::new()
+            .with_pattern("!^refs/heads")
+            .with_pattern("refs/heads/x")
+            .is_hidden("refs/heads/x"),
+        "negation and the caret combine"
+    );
+}
+
+#[test]
+fn hidden_refs_are_read_from_transfer_and_the_section_in_order() {
+    let config = gix::config::File::try_from(
+        "[transfer]\n\thideRefs = refs/pull\n[receive]\n\thideRefs = !refs/pull/1\n[uploadpack]\n\thideRefs = refs/heads/up\n[transfer \"sub\"]\n\thideRefs = refs/heads/main\n[transfer]\n\thideRefs = refs/pull/1/merge/\n",
+    )
+    .unwrap();
+    let hidden = HiddenRefs::from_config(&config, "receive");
+    assert!(hidden.is_hidden("refs/pull/2"));
+    assert!(!hidden.is_hidden("refs/pull/1/head"));
+    assert!(
+        hidden.is_hidden("refs/pull/1/merge"),
+        "later transfer sections apply last"
+    );
+    assert!(!hidden.is_hidden("refs/heads/up"), "other sections are ignored");
+    assert!(!hidden.is_hidden("refs/heads/main"), "subsections are ignored");
+    assert!(HiddenRefs::from_config(&config, "uploadpack").is_hidden("refs/heads/up"));
 
+    assert_eq!(
+        hidden.patterns().collect::<Vec<_>>(),
+        ["refs/pull", "!refs/pull/1", "refs/pull/1/merge/"]
+    );
+    assert_eq!(hidden.patterns().collect::<HiddenRefs>(), hidden);
+
+    let predicate = hidden.predicate();
+    let id = gix_hash::ObjectId::null(gix_hash::Kind::Sha1);
+    assert!(predicate(&RefRecord::new(id, "refs/pull/2")));
+    assert!(!predicate(&RefRecord::new(id, "refs/heads/main")));
+}
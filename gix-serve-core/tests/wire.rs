@@ -0,0 +1,74 @@
+use gix_serve_core::wire::{Counter, CountingReader, CountingWriter, Direction, WireStats};
+use std::io::{Read, Write};
+
+fn pkt(payload: &[u8]) -> Vec<u8> {
+    let mut line = format!("{:04x}", payload.len() + 4).into_bytes();
+    line.extend_from_slice(payload);
+    line
+}
+
+fn band(channel: u8, payload: &[u8]) -> Vec<u8> {
+    pkt(&[&[channel], payload].concat())
+}
+
+#[test]
+fn sideband_responses_separate_pack_data_from_framing() {
+    let response = [
+        pkt(b"NAK\n"),
+        band(2, b"Counting objects: 3\r"),
+        band(1, b"PACK0123"),
+        band(1, b"456789"),
+        band(3, b"oops\n"),
+        b"0000".to_vec(),
+    ]
+    .concat();
+
+    // Any split of the stream counts the same
+    for chunk in [1, 3, 7, response.len()] {
+        let mut counter = Counter::new(Direction::Response);
+        for bytes in response.chunks(chunk) {
+            counter.record(bytes);
+        }
+        assert_eq!(
+            counter.stats(),
+            WireStats {
+                request_bytes: 0,
+                response_bytes: response.len() as u64,
+                pack_bytes: 14,
+                framing_bytes: 6 * 4 + 4,
+            },
+            "chunks of {chunk}"
+        );
+    }
+}
+
+#[test]
+fn raw_packs_follow_pkt_lines_until_the_end() {
+    let request = [
+        pkt(b"0000000000000000000000000000000000000000 1111111111111111111111111111111111111111 refs/heads/main\0report-status\n"),
+        b"0000".to_vec(),
+        b"PACK\0\0\0\x02\0\0\0\x000000".to_vec(),
+    ]
+    .concat();
+    let mut reader = CountingReader::new(&request[..]);
+    let mut read = Vec::new();
+    reader.read_to_end(&mut read).unwrap();
+    assert_eq!(read, request);
+    assert_eq!(
+        reader.stats(),
+        WireStats {
+            request_bytes: request.len() as u64,
+            response_bytes: 0,
+            pack_bytes: 16,
+            framing_bytes: 8,
+        }
+    );
+
+    let mut writer = CountingWriter::new(Vec::new());
+    writer.write_all(&pkt(b"unpack ok\n")).unwrap();
+    writer.write_all(b"00010002").unwrap();
+    let stats = reader.stats() + writer.stats();
+    assert_eq!(writer.into_inner().len(), 22);
+    assert_eq!((stats.response_bytes, stats.framing_bytes), (22, 8 + 12));
+    assert_eq!(stats.total_bytes(), request.len() as u64 + 22);
+}
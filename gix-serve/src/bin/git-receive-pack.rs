@@ -0,0 +1,57 @@
+//! `gix-serve receive-pack` named like the native command, built with the `git-receive-pack` feature.
+//!
+//! With `GIX_SERVE_FALLBACK=native`, arguments it doesn't support are passed on to `git receive-pack`.
+
+use clap::Parser;
+use gix_serve_core::protocol::ServiceKind;
+
+#[path = "../stdio.rs"]
+mod stdio;
+
+/// Serve receive-pack over standard input and output, like `git receive-pack`
+#[derive(Parser, Debug)]
+#[command(name = "git-receive-pack", version)]
+struct Args {
+    #[command(flatten)]
+    service: stdio::ServiceArgs,
+}
+
+/// The environment variable that, set to `native`, runs the native command for arguments we don't support
+const FALLBACK_ENV: &str = "GIX_SERVE_FALLBACK";
+
+/// Run `git receive-pack` with our own arguments in place of this process, if enabled via [`FALLBACK_ENV`]
+///
+/// This allows to install this binary as `git-receive-pack` even if clients use flags we don't know yet.
+fn fall_back_to_native_git() -> Option<std::io::Error> {
+    if std::env::var_os(FALLBACK_ENV).as_deref() != Some(std::ffi::OsStr::new("native")) {
+        return None;
+    }
+    let mut git = std::process::Command::new("git");
+    git.arg("receive-pack").args(std::env::args_os().skip(1));
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        Some(git.exec())
+    }
+    #[cfg(not(unix))]
+    match git.status() {
+        Ok(status) => std::process::exit(status.code().unwrap_or(1)),
+        Err(err) => Some(err),
+    }
+}
+
+fn main() {
+    let args = match Args::try_parse() {
+        Ok(args) => args,
+        Err(err) => {
+            if err.kind() == clap::error::ErrorKind::UnknownArgument {
+                if let Some(exec_err) = fall_back_to_native_git() {
+                    eprintln!("Error running native git receive-pack: {exec_err}");
+                    std::process::exit(1);
+                }
+            }
+            err.exit()
+        }
+    };
+    stdio::serve(ServiceKind::ReceivePack, args.service)
+}
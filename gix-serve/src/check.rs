@@ -0,0 +1,399 @@
+//! Checks of a deployment against the native git installed next to it, see [`run()`].
+//!
+//! Each check has native git and gix-serve do the same for the repository and compares the results:
+//! - The advertisements of upload-pack with protocol v0 and v2 and of receive-pack must be the same byte by byte,
+//!   except for the agent.
+//! - Native git clones the repository from both with protocol v0 and v2, and fetches `HEAD` shallowly from both,
+//!   which must yield the same refs and objects.
+//! - Native git pushes to gix-serve from its clone without anything to push, which must succeed.
+//!
+//! The repository is only read, and the clones are made in a temporary directory that is removed afterwards.
+
+use crate::{services, Error};
+use gix_serve_core::protocol::{ProtocolVersion, ServiceKind};
+use gix_serve_core::service::ServiceContext;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// What to check.
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// The repository whose services to check.
+    pub repository: PathBuf,
+    /// The `gix-serve` executable native git runs to fetch from and push to the repository.
+    pub program: PathBuf,
+    /// The native git to compare with.
+    pub git: PathBuf,
+}
+
+impl Options {
+    /// Check the services of `repository`, run by native git as subcommands of `program`, against the `git` in `PATH`.
+    pub fn new(repository: impl Into<PathBuf>, program: impl Into<PathBuf>) -> Self {
+        Options {
+            repository: repository.into(),
+            program: program.into(),
+            git: "git".into(),
+        }
+    }
+
+    /// Compare with the native git at `git`.
+    pub fn with_git(mut self, git: impl Into<PathBuf>) -> Self {
+        self.git = git.into();
+        self
+    }
+}
+
+/// The result of a [`Check`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Status {
+    /// gix-serve did what native git did.
+    Passed,
+    /// gix-serve worked, but its output differs from that of native git as described.
+    Differs(String),
+    /// gix-serve failed, or did something else than native git, as described.
+    Failed(String),
+    /// The check didn't apply to the repository, for the reason given.
+    Skipped(String),
+}
+
+/// A check and its result.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Check {
+    /// What was checked, like `clone (protocol v2)`.
+    pub name: String,
+    /// How it went.
+    pub status: Status,
+}
+
+/// The results of all checks of a repository.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Report {
+    /// The version of the native git the repository was checked against, as printed by `git --version`.
+    pub git_version: String,
+    /// The checks in the order they ran.
+    pub checks: Vec<Check>,
+}
+
+impl Report {
+    /// Return the check called `name`, if it ran.
+    pub fn get(&self, name: &str) -> Option<&Check> {
+        self.checks.iter().find(|check| check.name == name)
+    }
+
+    /// Return `true` if no check failed.
+    ///
+    /// Checks that only differ in their output don't count as failed, as clients may not notice.
+    pub fn passed(&self) -> bool {
+        !self
+            .checks
+            .iter()
+            .any(|check| matches!(check.status, Status::Failed(_)))
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "checked against {}", self.git_version)?;
+        let mut counts = [0; 4];
+        for check in &self.checks {
+            let (index, label, details) = match &check.status {
+                Status::Passed => (0, "ok", None),
+                Status::Differs(details) => (1, "differs", Some(details)),
+                Status::Failed(details) => (2, "FAILED", Some(details)),
+                Status::Skipped(reason) => (3, "skipped", Some(reason)),
+            };
+            counts[index] += 1;
+            writeln!(f, "{label:<8} {}", check.name)?;
+            for line in details.into_iter().flat_map(|details| details.lines()) {
+                writeln!(f, "         {line}")?;
+            }
+        }
+        let [passed, differs, failed, skipped] = counts;
+        write!(
+            f,
+            "{passed} passed, {differs} differ, {failed} failed, {skipped} skipped"
+        )
+    }
+}
+
+/// Check the services of the repository of `options` against native git, see the [module documentation](self).
+///
+/// Problems found by the checks are part of the returned report, and only fail if native git can't be run or
+/// the temporary directory can't be created.
+pub fn run(options: &Options) -> Result<Report, Error> {
+    let version = git(options)
+        .arg("--version")
+        .output()
+        .map_err(|err| Error::NativeGit(format!("{}: {err}", options.git.display())))?;
+    if !version.status.success() {
+        return Err(Error::NativeGit(stderr(&version.stderr)));
+    }
+    let tmp = tempfile::tempdir()?;
+    let mut checks = Vec::new();
+    let mut check = |name: String, status: Status| checks.push(Check { name, status });
+
+    for (kind, version) in [
+        (ServiceKind::UploadPack, ProtocolVersion::V0),
+        (ServiceKind::UploadPack, ProtocolVersion::V2),
+        (ServiceKind::ReceivePack, ProtocolVersion::V0),
+    ] {
+        check(
+            format!("{} advertisement (protocol {})", command(kind), number(version)),
+            advertisement(options, kind, version),
+        );
+    }
+
+    for version in [ProtocolVersion::V0, ProtocolVersion::V2] {
+        check(
+            format!("clone (protocol {})", number(version)),
+            clone(options, version, tmp.path()),
+        );
+    }
+    check("shallow fetch of HEAD".into(), shallow_fetch(options, tmp.path()));
+    check("empty push".into(), empty_push(options, tmp.path()));
+
+    Ok(Report {
+        git_version: String::from_utf8_lossy(&version.stdout).trim().to_owned(),
+        checks,
+    })
+}
+
+/// Compare the advertisement of the `kind` service for `version` with that of native git.
+fn advertisement(options: &Options, kind: ServiceKind, version: ProtocolVersion) -> Status {
+    let native = match run_git(
+        git(options)
+            .args([command(kind), "--stateless-rpc", "--advertise-refs"])
+            .arg(&options.repository)
+            .env("GIT_PROTOCOL", format!("version={}", number(version))),
+    ) {
+        Ok(native) => native,
+        Err(err) => return Status::Skipped(format!("native git failed: {err}")),
+    };
+    let mut ours = Vec::new();
+    let ctx = ServiceContext::new(version).with_stateless(true);
+    if let Err(err) = services::open(kind, &options.repository).and_then(|mut service| {
+        service.advertise(&mut ours, &ctx)?;
+        Ok(())
+    }) {
+        return Status::Failed(err.to_string());
+    }
+    compare_advertisements(&native, &ours)
+}
+
+/// Compare the advertisements by line after replacing the agent of both, describing the lines that differ.
+fn compare_advertisements(native: &[u8], ours: &[u8]) -> Status {
+    if native == ours {
+        return Status::Passed;
+    }
+    let (native, ours) = (lines(native), lines(ours));
+    let mut details = String::new();
+    for index in 0..native.len().max(ours.len()) {
+        let (native_line, our_line) = (native.get(index), ours.get(index));
+        if native_line != our_line {
+            let describe = |line: Option<&String>| line.map_or("<none>".to_owned(), |line| line.replace('\0', "\\0"));
+            details.push_str(&format!(
+                "line {}:\n  git: {}\n  gix: {}\n",
+                index + 1,
+                describe(native_line),
+                describe(our_line)
+            ));
+        }
+    }
+    if details.is_empty() {
+        Status::Passed
+    } else {
+        Status::Differs(details)
+    }
+}
+
+/// Split the pkt-lines of `data` into their payloads without the trailing newline and the agent, with flush and
+/// delimiter packets as `0000` and `0001`, and anything that isn't a pkt-line as a line of its own.
+fn lines(mut data: &[u8]) -> Vec<String> {
+    let mut lines = Vec::new();
+    while !data.is_empty() {
+        let len = data
+            .get(..4)
+            .and_then(|len| std::str::from_utf8(len).ok())
+            .and_then(|len| usize::from_str_radix(len, 16).ok());
+        let (line, rest) = match len {
+            Some(len @ 0..=3) => (format!("{len:04}"), &data[4..]),
+            Some(len) if len <= data.len() => (String::from_utf8_lossy(&data[4..len]).into_owned(), &data[len..]),
+            _ => (String::from_utf8_lossy(data).into_owned(), &data[data.len()..]),
+        };
+        let line = line.trim_end_matches('\n');
+        lines.push(
+            line.split(' ')
+                .map(|token| match token.find("agent=") {
+                    Some(pos) if pos == 0 || token[..pos].ends_with('\0') => format!("{}agent=*", &token[..pos]),
+                    _ => token.to_owned(),
+                })
+                .collect::<Vec<_>>()
+                .join(" "),
+        );
+        data = rest;
+    }
+    lines
+}
+
+/// Clone the repository with `version` from native git and from gix-serve into `tmp`, and compare their refs and
+/// check the connectivity of the clone from gix-serve.
+fn clone(options: &Options, version: ProtocolVersion, tmp: &Path) -> Status {
+    let mut refs = Vec::new();
+    for upload_pack in [None, Some(subcommand(options, ServiceKind::UploadPack))] {
+        let destination = tmp.join(clone_name(version, upload_pack.is_some()));
+        let mut cmd = git(options);
+        cmd.args(["-c", &format!("protocol.version={}", number(version))])
+            .args(["clone", "--quiet", "--mirror"]);
+        if let Some(upload_pack) = &upload_pack {
+            cmd.args(["--upload-pack", upload_pack]);
+        }
+        let cloned = run_git(cmd.arg(url(&options.repository)).arg(&destination)).and_then(|_| {
+            run_git(
+                git(options)
+                    .args(["for-each-ref", "--format=%(objectname) %(refname)"])
+                    .current_dir(&destination),
+            )
+        });
+        match (cloned, upload_pack) {
+            (Ok(listed), _) => refs.push(listed),
+            (Err(err), None) => return Status::Skipped(format!("native git failed: {err}")),
+            (Err(err), Some(_)) => return Status::Failed(err),
+        }
+    }
+    if refs[0] != refs[1] {
+        return Status::Failed(format!(
+            "the refs differ from those cloned from native git:\n{}",
+            String::from_utf8_lossy(&refs[1])
+        ));
+    }
+    match run_git(
+        git(options)
+            .args(["fsck", "--no-progress", "--connectivity-only"])
+            .current_dir(tmp.join(clone_name(version, true))),
+    ) {
+        Ok(_) => Status::Passed,
+        Err(err) => Status::Failed(format!("the clone is incomplete: {err}")),
+    }
+}
+
+/// Fetch `HEAD` with depth 1 and protocol v2 into new repositories in `tmp` from native git and from gix-serve,
+/// and compare the fetched commit and the shallow boundary.
+fn shallow_fetch(options: &Options, tmp: &Path) -> Status {
+    if run_git(
+        git(options)
+            .args(["rev-parse", "--verify", "--quiet", "HEAD"])
+            .current_dir(&options.repository),
+    )
+    .is_err()
+    {
+        return Status::Skipped("HEAD is unborn".into());
+    }
+    let mut fetched = Vec::new();
+    for upload_pack in [None, Some(subcommand(options, ServiceKind::UploadPack))] {
+        let destination = tmp.join(if upload_pack.is_some() {
+            "shallow-gix.git"
+        } else {
+            "shallow-git.git"
+        });
+        let mut cmd = git(options);
+        cmd.args(["-c", "protocol.version=2", "fetch", "--quiet", "--depth=1"])
+            .current_dir(&destination);
+        if let Some(upload_pack) = &upload_pack {
+            cmd.args(["--upload-pack", upload_pack]);
+        }
+        let result = run_git(git(options).args(["init", "--quiet", "--bare"]).arg(&destination))
+            .and_then(|_| run_git(cmd.arg(url(&options.repository)).arg("HEAD")))
+            .and_then(|_| {
+                let head = run_git(git(options).args(["rev-parse", "FETCH_HEAD"]).current_dir(&destination))?;
+                let shallow = std::fs::read(destination.join("shallow")).map_err(|err| err.to_string())?;
+                Ok((head, shallow))
+            });
+        match (result, upload_pack) {
+            (Ok(result), _) => fetched.push(result),
+            (Err(err), None) => return Status::Skipped(format!("native git failed: {err}")),
+            (Err(err), Some(_)) => return Status::Failed(err),
+        }
+    }
+    if fetched[0] != fetched[1] {
+        return Status::Failed(format!(
+            "fetched {} with shallow boundary {}, native git fetched {} with {}",
+            String::from_utf8_lossy(&fetched[1].0).trim(),
+            String::from_utf8_lossy(&fetched[1].1).trim(),
+            String::from_utf8_lossy(&fetched[0].0).trim(),
+            String::from_utf8_lossy(&fetched[0].1).trim(),
+        ));
+    }
+    Status::Passed
+}
+
+/// Push the branches of the protocol v2 clone from gix-serve in `tmp` back to the repository, which has them all.
+fn empty_push(options: &Options, tmp: &Path) -> Status {
+    let clone = tmp.join(clone_name(ProtocolVersion::V2, true));
+    if !clone.is_dir() {
+        return Status::Skipped("there is no clone to push from".into());
+    }
+    match run_git(
+        git(options)
+            .args(["push", "--quiet", "--receive-pack"])
+            .arg(subcommand(options, ServiceKind::ReceivePack))
+            .arg(url(&options.repository))
+            .arg("refs/heads/*:refs/heads/*")
+            .current_dir(&clone),
+    ) {
+        Ok(_) => Status::Passed,
+        Err(err) => Status::Failed(err),
+    }
+}
+
+/// A native git command that isn't affected by the environment of this process.
+fn git(options: &Options) -> Command {
+    let mut cmd = Command::new(&options.git);
+    cmd.env_remove("GIT_PROTOCOL").env_remove("GIT_DIR");
+    cmd
+}
+
+/// Run `cmd` and return its output, or what it printed to standard error if it failed.
+fn run_git(cmd: &mut Command) -> Result<Vec<u8>, String> {
+    let output = cmd.output().map_err(|err| err.to_string())?;
+    if output.status.success() {
+        Ok(output.stdout)
+    } else {
+        Err(stderr(&output.stderr))
+    }
+}
+
+fn stderr(stderr: &[u8]) -> String {
+    String::from_utf8_lossy(stderr).trim().to_owned()
+}
+
+/// The shell command native git runs to start the `kind` service of gix-serve.
+fn subcommand(options: &Options, kind: ServiceKind) -> String {
+    let program = options.program.display().to_string().replace('\'', r"'\''");
+    format!("'{program}' {}", command(kind))
+}
+
+fn command(kind: ServiceKind) -> &'static str {
+    match kind {
+        ServiceKind::UploadPack => "upload-pack",
+        ServiceKind::ReceivePack => "receive-pack",
+    }
+}
+
+fn number(version: ProtocolVersion) -> u8 {
+    match version {
+        ProtocolVersion::V0 => 0,
+        ProtocolVersion::V1 => 1,
+        ProtocolVersion::V2 => 2,
+    }
+}
+
+fn clone_name(version: ProtocolVersion, gix: bool) -> String {
+    format!("clone-v{}-{}.git", number(version), if gix { "gix" } else { "git" })
+}
+
+/// A `file://` URL for `repository`, so native git runs upload-pack instead of copying the objects.
+fn url(repository: &Path) -> String {
+    let path = std::fs::canonicalize(repository).unwrap_or_else(|_| repository.to_owned());
+    format!("file://{}", path.display())
+}
@@ -0,0 +1,294 @@
+//! Serving repositories over the `git://` protocol, like `git daemon`.
+//!
+//! Clients open a TCP connection, usually to port [`DEFAULT_PORT`], and send a single [`Request`] naming the service
+//! and the repository, after which the service talks to them like over standard input and output. [`serve()`]
+//! accepts connections and serves each on its own thread, up to a [limit](Options::with_max_connections()).
+//!
+//! Requested paths are mapped to repositories below a base directory with an [`ExportPolicy`], so like with
+//! `git daemon --base-path` only repositories inside of it are served, and only those containing a
+//! `git-daemon-export-ok` file unless [all are exported](Options::with_export_all()). Like the directories given to
+//! `git daemon`, [allowed directories](Options::with_allowed_directory()) further restrict the served repositories to
+//! those inside of them. Refusals are answered with an `ERR` packet that clients show to their users.
+//!
+//! Receive-pack has to be [enabled](Options::with_receive_pack()), like with `git daemon --enable=receive-pack`, and
+//! applies pushes like it does over standard input and output.
+
+use crate::{services, Error};
+use gix_serve_core::export::{self, Export, ExportPolicy};
+use gix_serve_core::pktline::{write_error, PktWriter, MAX_LINE_LEN};
+use gix_serve_core::protocol::{ProtocolVersion, ServiceKind};
+use gix_serve_core::service::ServiceContext;
+use std::io::Read;
+use std::net::{TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// The port `git://` URLs without a port refer to.
+pub const DEFAULT_PORT: u16 = 9418;
+
+/// The request a client sends right after connecting, like `git-upload-pack /project.git\0host=example.com\0`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Request {
+    /// The service the client asked for.
+    pub service: ServiceKind,
+    /// The path of the repository as sent by the client.
+    pub path: String,
+    /// The value of the `host` parameter, which names the host and optionally the port the client connected to.
+    pub host: Option<String>,
+    /// The extra parameters following the `host` parameter, like `version=2`.
+    pub extra_parameters: Vec<String>,
+}
+
+impl Request {
+    /// Parse the payload of the pkt-line a client sends first, with or without its trailing newline.
+    pub fn parse(line: &[u8]) -> Result<Self, Error> {
+        let invalid = |reason: &str| Error::InvalidRequest(reason.into());
+        let line = std::str::from_utf8(line).map_err(|_| invalid("the request isn't valid UTF-8"))?;
+        let line = line.strip_suffix('\n').unwrap_or(line);
+        let (command, parameters) = line.split_once('\0').unwrap_or((line, ""));
+        let (service, path) = command
+            .split_once(' ')
+            .ok_or_else(|| invalid("the request doesn't name a repository"))?;
+        let service = match service {
+            "git-upload-pack" => ServiceKind::UploadPack,
+            "git-receive-pack" => ServiceKind::ReceivePack,
+            _ => return Err(Error::InvalidRequest(format!("unknown service '{service}'"))),
+        };
+        if path.is_empty() {
+            return Err(invalid("the request doesn't name a repository"));
+        }
+
+        // Like git, the extra parameters start after an empty parameter, which follows the `host` parameter if any.
+        let mut host = None;
+        let mut extra_parameters = Vec::new();
+        let mut parameters = parameters.split('\0');
+        for parameter in parameters.by_ref() {
+            match parameter.strip_prefix("host=") {
+                Some(value) if host.is_none() => host = Some(value.to_owned()),
+                _ if parameter.is_empty() => break,
+                _ => return Err(Error::InvalidRequest(format!("unknown parameter '{parameter}'"))),
+            }
+        }
+        extra_parameters.extend(parameters.filter(|p| !p.is_empty()).map(ToOwned::to_owned));
+        Ok(Request {
+            service,
+            path: path.to_owned(),
+            host,
+            extra_parameters,
+        })
+    }
+
+    /// Read the request from `input`, consuming nothing but its pkt-line.
+    pub fn read(input: &mut impl Read) -> Result<Self, Error> {
+        let mut len = [0; 4];
+        input.read_exact(&mut len)?;
+        let len = std::str::from_utf8(&len)
+            .ok()
+            .and_then(|len| usize::from_str_radix(len, 16).ok())
+            .filter(|len| (5..=MAX_LINE_LEN).contains(len))
+            .ok_or_else(|| Error::InvalidRequest("the request isn't a pkt-line".into()))?;
+        let mut line = vec![0; len - 4];
+        input.read_exact(&mut line)?;
+        Self::parse(&line)
+    }
+
+    /// Return the protocol version requested by the extra parameters, or `V0` if there is none.
+    pub fn protocol_version(&self) -> ProtocolVersion {
+        ProtocolVersion::from_git_protocol(&self.extra_parameters.join(":"))
+    }
+}
+
+/// How repositories are served over the `git://` protocol.
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// The directory below which repositories are served.
+    pub base: PathBuf,
+    /// Whether repositories are served without a `git-daemon-export-ok` file.
+    pub export_all: bool,
+    /// The directories repositories have to be inside of to be served, or empty to serve all below `base`.
+    pub allowed: Vec<PathBuf>,
+    /// Whether receive-pack is served.
+    pub receive_pack: bool,
+    /// Whether refusals tell clients why their request was refused.
+    pub informative_errors: bool,
+    /// How long to wait for the request after a client connected.
+    pub init_timeout: Option<Duration>,
+    /// How long to wait for the client to send or receive data while serving it.
+    pub timeout: Option<Duration>,
+    /// The most connections served at once, or 0 for no limit.
+    pub max_connections: usize,
+}
+
+impl Options {
+    /// Serve upload-pack for the exported repositories below `base`, to at most 32 clients at once like `git daemon`.
+    pub fn new(base: impl Into<PathBuf>) -> Self {
+        Options {
+            base: base.into(),
+            export_all: false,
+            allowed: Vec::new(),
+            receive_pack: false,
+            informative_errors: false,
+            init_timeout: None,
+            timeout: None,
+            max_connections: 32,
+        }
+    }
+
+    /// Serve repositories without a `git-daemon-export-ok` file if `export_all` is `true`, like `--export-all`.
+    pub fn with_export_all(mut self, export_all: bool) -> Self {
+        self.export_all = export_all;
+        self
+    }
+
+    /// Serve only repositories inside `directory` or other allowed directories, like the directories passed to
+    /// `git daemon`.
+    pub fn with_allowed_directory(mut self, directory: impl Into<PathBuf>) -> Self {
+        self.allowed.push(directory.into());
+        self
+    }
+
+    /// Serve receive-pack if `enabled` is `true`, like `--enable=receive-pack`.
+    pub fn with_receive_pack(mut self, enabled: bool) -> Self {
+        self.receive_pack = enabled;
+        self
+    }
+
+    /// Tell clients why their request was refused if `informative` is `true`, like `--informative-errors`, which
+    /// lets them find out which repositories exist.
+    pub fn with_informative_errors(mut self, informative: bool) -> Self {
+        self.informative_errors = informative;
+        self
+    }
+
+    /// Close connections whose request didn't arrive within `timeout`, like `--init-timeout`.
+    pub fn with_init_timeout(mut self, timeout: Duration) -> Self {
+        self.init_timeout = Some(timeout);
+        self
+    }
+
+    /// Close connections on which no data could be read or written for `timeout` while serving them, like
+    /// `--timeout`.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Refuse new connections with a `too many connections` error while `max` connections are served, or never if
+    /// `max` is 0, like `--max-connections`.
+    pub fn with_max_connections(mut self, max: usize) -> Self {
+        self.max_connections = max;
+        self
+    }
+
+    /// Resolve `path` as requested by a client to the repository to serve.
+    fn resolve(&self, path: &str) -> Result<Export, export::Error> {
+        let export = ExportPolicy::new(&self.base)
+            .with_export_ok_required(!self.export_all)
+            .resolve(path)?;
+        let allowed = self.allowed.is_empty()
+            || self.allowed.iter().any(|directory| {
+                directory
+                    .canonicalize()
+                    .is_ok_and(|directory| export.git_dir.starts_with(directory))
+            });
+        if !allowed {
+            return Err(export::Error::NotExported { path: path.into() });
+        }
+        Ok(export)
+    }
+}
+
+/// Serve the repositories described by `options` to the clients connecting to `listener`, until accepting a
+/// connection fails.
+///
+/// Each connection is served on its own thread with [`serve_connection()`], and failures of individual connections
+/// only end those.
+pub fn serve(listener: TcpListener, options: Options) -> Result<(), Error> {
+    let options = Arc::new(options);
+    let active = Arc::new(AtomicUsize::new(0));
+    for stream in listener.incoming() {
+        let stream = stream?;
+        if options.max_connections != 0 && active.load(Ordering::SeqCst) >= options.max_connections {
+            let init_timeout = options.init_timeout;
+            std::thread::spawn(move || refuse(stream, "too many connections", init_timeout).ok());
+            continue;
+        }
+        let connection = Connection::new(&active);
+        let options = Arc::clone(&options);
+        std::thread::spawn(move || {
+            let _connection = connection;
+            serve_connection(stream, &options).ok();
+        });
+    }
+    Ok(())
+}
+
+/// Read the request of the client connected through `stream` and serve it as described by `options`.
+///
+/// Requests for repositories or services that may not be served are answered with an `ERR` packet, which isn't
+/// considered a failure.
+pub fn serve_connection(mut stream: TcpStream, options: &Options) -> Result<(), Error> {
+    let remote_addr = stream.peer_addr()?;
+    stream.set_read_timeout(options.init_timeout)?;
+    let request = Request::read(&mut stream)?;
+    stream.set_read_timeout(options.timeout)?;
+    stream.set_write_timeout(options.timeout)?;
+
+    let resolved = if request.service == ServiceKind::ReceivePack && !options.receive_pack {
+        // Like `git daemon`, disabled services are refused like unexported repositories unless errors are informative.
+        Err(match options.informative_errors {
+            true => format!("service not enabled: {}", request.path),
+            false => export::Error::NotExported {
+                path: request.path.clone(),
+            }
+            .daemon_message(&request.path, false),
+        })
+    } else {
+        options
+            .resolve(&request.path)
+            .map_err(|err| err.daemon_message(&request.path, options.informative_errors))
+    };
+    let export = match resolved {
+        Ok(export) => export,
+        Err(message) => {
+            write_error(&mut PktWriter::new(&mut stream), message.as_bytes())?;
+            return Ok(());
+        }
+    };
+
+    let mut service = services::open(request.service, &export.git_dir)?;
+    let ctx = ServiceContext::new(request.protocol_version()).with_remote_addr(remote_addr);
+    let mut input = stream.try_clone()?;
+    service.serve(&mut input, &mut stream, &ctx)?;
+    Ok(())
+}
+
+/// Answer the client connected through `stream` with an `ERR` packet with `message` instead of serving it.
+///
+/// Its request is read until it closes the connection or `timeout` passes, so closing it doesn't reset the
+/// connection before the client read the packet.
+fn refuse(mut stream: TcpStream, message: &str, timeout: Option<Duration>) -> std::io::Result<()> {
+    write_error(&mut PktWriter::new(&mut stream), message.as_bytes())?;
+    stream.shutdown(std::net::Shutdown::Write)?;
+    stream.set_read_timeout(Some(timeout.unwrap_or(Duration::from_secs(1))))?;
+    std::io::copy(&mut stream, &mut std::io::sink())?;
+    Ok(())
+}
+
+/// A connection counted towards [`Options::max_connections`] until it is dropped.
+struct Connection(Arc<AtomicUsize>);
+
+impl Connection {
+    fn new(active: &Arc<AtomicUsize>) -> Self {
+        active.fetch_add(1, Ordering::SeqCst);
+        Connection(Arc::clone(active))
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
@@ -0,0 +1,102 @@
+//! Serving repositories over smart HTTP, like `git http-backend` behind a web server.
+//!
+//! [`smart_http()`] answers the smart-HTTP endpoints with the [services](crate::services) of the repositories below a
+//! base directory, and [`serve()`] runs it with hyper on a TCP listener. Requests are mapped to repositories with an
+//! [`ExportPolicy`], so only repositories inside the base directory are served, and like with `git http-backend` only
+//! those containing a `git-daemon-export-ok` file unless [all are exported](Options::with_export_all()). Refused and
+//! unknown repositories are answered alike with `404 Not Found`.
+//!
+//! Receive-pack has to be [enabled](Options::with_receive_pack()), as `git http-backend` only serves it to
+//! authenticated users by default, and is answered with `403 Forbidden` otherwise. It applies pushes like it does
+//! over standard input and output.
+
+use crate::{services, Error};
+use gix_serve_core::export::ExportPolicy;
+use gix_serve_core::protocol::ServiceKind;
+use gix_serve_core::service::GitService;
+use gix_serve_core::smart_http::SmartHttp;
+use hyper::StatusCode;
+use std::path::PathBuf;
+
+/// How repositories are served over smart HTTP.
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// The directory below which repositories are served.
+    pub base: PathBuf,
+    /// Whether repositories are served without a `git-daemon-export-ok` file.
+    pub export_all: bool,
+    /// Whether receive-pack is served.
+    pub receive_pack: bool,
+    /// The largest request body accepted, before and after decompression, or `None` for the
+    /// [default](gix_serve_core::smart_http::SmartHttp::with_max_request_size()).
+    pub max_request_size: Option<usize>,
+}
+
+impl Options {
+    /// Serve upload-pack for the exported repositories below `base`.
+    pub fn new(base: impl Into<PathBuf>) -> Self {
+        Options {
+            base: base.into(),
+            export_all: false,
+            receive_pack: false,
+            max_request_size: None,
+        }
+    }
+
+    /// Serve repositories without a `git-daemon-export-ok` file if `export_all` is `true`, like `GIT_HTTP_EXPORT_ALL`.
+    pub fn with_export_all(mut self, export_all: bool) -> Self {
+        self.export_all = export_all;
+        self
+    }
+
+    /// Serve receive-pack if `enabled` is `true`, like `http.receivepack`.
+    pub fn with_receive_pack(mut self, enabled: bool) -> Self {
+        self.receive_pack = enabled;
+        self
+    }
+
+    /// Answer requests whose body is larger than `bytes` with `413 Payload Too Large`.
+    pub fn with_max_request_size(mut self, bytes: usize) -> Self {
+        self.max_request_size = Some(bytes);
+        self
+    }
+}
+
+/// The service handling a request, or the status to answer with instead.
+type Resolved = Result<Box<dyn GitService + Send>, StatusCode>;
+
+/// Return the smart-HTTP service for the repositories described by `options`.
+pub fn smart_http(options: Options) -> SmartHttp<impl Fn(&str, ServiceKind) -> Resolved + Send + Sync + 'static> {
+    let policy = ExportPolicy::new(&options.base).with_export_ok_required(!options.export_all);
+    let receive_pack = options.receive_pack;
+    let http = SmartHttp::new(move |repository: &str, kind| {
+        if kind == ServiceKind::ReceivePack && !receive_pack {
+            return Err(StatusCode::FORBIDDEN);
+        }
+        let export = policy.resolve(repository).map_err(|err| err.status_code())?;
+        services::open(kind, &export.git_dir).map_err(|err| match err {
+            Error::Open(_) => StatusCode::NOT_FOUND,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        })
+    });
+    match options.max_request_size {
+        Some(bytes) => http.with_max_request_size(bytes),
+        None => http,
+    }
+}
+
+/// Serve the repositories described by `options` to the clients connecting to `listener`, until accepting a
+/// connection fails.
+///
+/// Each connection is served in its own task with HTTP/1.1, so this has to run within a multi-threaded tokio
+/// runtime, as the services run on its blocking thread pool.
+pub async fn serve(listener: tokio::net::TcpListener, options: Options) -> Result<(), Error> {
+    let http = smart_http(options);
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let service = hyper_util::service::TowerToHyperService::new(http.clone());
+        tokio::spawn(
+            hyper::server::conn::http1::Builder::new().serve_connection(hyper_util::rt::TokioIo::new(stream), service),
+        );
+    }
+}
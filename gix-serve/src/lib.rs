@@ -0,0 +1,48 @@
+//! gix-serve: Serve git repositories with gitoxide's services.
+//!
+//! This crate ties `gix-upload-pack` and `gix-receive-pack` together behind the `GitService` interface of
+//! `gix-serve-core`, for the `gix-serve` command and the front-ends built on it:
+//! - [`services`] opens the services of a repository and serves them over standard input and output like the
+//!   commands of native git do.
+//! - [`http`] serves them over smart HTTP like `git http-backend`.
+//! - [`daemon`] serves them over the `git://` protocol like `git daemon`.
+//! - [`shell`] serves them to SSH clients like `git-shell`.
+//! - [`check`] checks a deployment against the native git installed next to it, so operators can validate it before
+//!   switching traffic.
+#![deny(missing_docs, rust_2018_idioms)]
+#![forbid(unsafe_code)]
+
+pub mod check;
+pub mod daemon;
+pub mod http;
+pub mod services;
+pub mod shell;
+
+/// The error returned by the operations of this crate.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// A generic I/O error.
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    /// The repository could not be opened.
+    #[error(transparent)]
+    Open(Box<gix::open::Error>),
+    /// The upload-pack service failed to start.
+    #[error(transparent)]
+    UploadPack(Box<gix_upload_pack::Error>),
+    /// The receive-pack service failed to start.
+    #[error(transparent)]
+    ReceivePack(Box<gix_receive_pack::Error>),
+    /// A service failed while serving a request.
+    #[error(transparent)]
+    Service(#[from] gix_serve_core::service::Error),
+    /// The requested repository may not be served.
+    #[error(transparent)]
+    Export(#[from] gix_serve_core::export::Error),
+    /// A client sent a request that couldn't be understood or may not be served.
+    #[error("invalid request: {0}")]
+    InvalidRequest(String),
+    /// The native git to check against could not be run.
+    #[error("native git could not be run: {0}")]
+    NativeGit(String),
+}
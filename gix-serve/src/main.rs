@@ -0,0 +1,187 @@
+use clap::{Parser, Subcommand};
+use gix_serve::{check, daemon, http, shell};
+use std::path::PathBuf;
+use stdio::{serve, ServiceArgs};
+
+mod stdio;
+
+/// Serve git repositories with gitoxide's upload-pack and receive-pack
+#[derive(Parser, Debug)]
+#[command(name = "gix-serve", version, about)]
+struct Args {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Check the services of a repository against the native git installed next to them and print a report
+    ///
+    /// Compares the advertisements, clones and a shallow fetch of both, and pushes nothing to gix-serve. The
+    /// repository is only read. Exits with 1 if any check failed.
+    CheckCompat {
+        /// The repository to check
+        #[arg(value_name = "REPOSITORY")]
+        repository: PathBuf,
+        /// The native git to check against
+        #[arg(long, value_name = "PATH", default_value = "git")]
+        git: PathBuf,
+    },
+    /// Serve upload-pack over standard input and output, like `git upload-pack`
+    UploadPack {
+        #[command(flatten)]
+        service: ServiceArgs,
+    },
+    /// Serve receive-pack over standard input and output, like `git receive-pack`
+    ReceivePack {
+        #[command(flatten)]
+        service: ServiceArgs,
+    },
+    /// Serve the repositories below a directory over smart HTTP, like `git http-backend`
+    ///
+    /// Only repositories containing a `git-daemon-export-ok` file are served, unless `--export-all` is given.
+    Http {
+        /// The address to listen on
+        #[arg(long, value_name = "ADDRESS", default_value = "127.0.0.1:8080")]
+        listen: std::net::SocketAddr,
+        /// Serve all repositories, even those without a `git-daemon-export-ok` file
+        #[arg(long)]
+        export_all: bool,
+        /// Serve receive-pack, so clients can push
+        #[arg(long)]
+        enable_receive_pack: bool,
+        /// Refuse requests with larger bodies
+        #[arg(long, value_name = "BYTES")]
+        max_request_size: Option<usize>,
+        /// The directory below which repositories are served
+        #[arg(value_name = "BASE_PATH")]
+        base_path: PathBuf,
+    },
+    /// Serve the repositories below a directory over the git:// protocol, like `git daemon`
+    ///
+    /// Only repositories containing a `git-daemon-export-ok` file are served, unless `--export-all` is given.
+    Daemon {
+        /// The address to listen on
+        #[arg(long, value_name = "ADDRESS", default_value = "0.0.0.0:9418")]
+        listen: std::net::SocketAddr,
+        /// The directory below which repositories are served
+        #[arg(long, value_name = "PATH")]
+        base_path: PathBuf,
+        /// Serve all repositories, even those without a `git-daemon-export-ok` file
+        #[arg(long)]
+        export_all: bool,
+        /// Serve receive-pack, so clients can push
+        #[arg(long)]
+        enable_receive_pack: bool,
+        /// Tell clients why their request was refused
+        #[arg(long)]
+        informative_errors: bool,
+        /// Close connections whose request didn't arrive within this many seconds
+        #[arg(long, value_name = "SECONDS")]
+        init_timeout: Option<u64>,
+        /// Close connections that were idle for this many seconds while serving them
+        #[arg(long, value_name = "SECONDS")]
+        timeout: Option<u64>,
+        /// Refuse new connections while this many are served, or never if 0
+        #[arg(long, value_name = "COUNT", default_value_t = 32)]
+        max_connections: usize,
+        /// Only serve repositories inside these directories
+        #[arg(value_name = "DIRECTORY")]
+        allowed: Vec<PathBuf>,
+    },
+    /// Serve the repositories below a directory to SSH clients, like `git-shell`
+    ///
+    /// Runs the command passed with `-c`, as when used as login shell, or the one in `SSH_ORIGINAL_COMMAND`, as when
+    /// used as forced command.
+    Shell {
+        /// The command sent by the client, like `git-upload-pack '/project.git'`
+        #[arg(short = 'c', value_name = "COMMAND")]
+        command: Option<String>,
+        /// The directory below which repositories are served
+        #[arg(long, value_name = "PATH")]
+        root: PathBuf,
+        /// Only serve upload-pack
+        #[arg(long)]
+        read_only: bool,
+    },
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    use gix_serve_core::protocol::ServiceKind;
+    match Args::parse().command {
+        Command::CheckCompat { repository, git } => {
+            let options = check::Options::new(repository, std::env::current_exe()?).with_git(git);
+            let report = check::run(&options)?;
+            println!("{report}");
+            if !report.passed() {
+                std::process::exit(1);
+            }
+            Ok(())
+        }
+        Command::UploadPack { service } => serve(ServiceKind::UploadPack, service),
+        Command::ReceivePack { service } => serve(ServiceKind::ReceivePack, service),
+        Command::Http {
+            listen,
+            export_all,
+            enable_receive_pack,
+            max_request_size,
+            base_path,
+        } => {
+            let mut options = http::Options::new(base_path)
+                .with_export_all(export_all)
+                .with_receive_pack(enable_receive_pack);
+            if let Some(bytes) = max_request_size {
+                options = options.with_max_request_size(bytes);
+            }
+            tokio::runtime::Runtime::new()?.block_on(async {
+                let listener = tokio::net::TcpListener::bind(listen).await?;
+                http::serve(listener, options).await
+            })?;
+            Ok(())
+        }
+        Command::Daemon {
+            listen,
+            base_path,
+            export_all,
+            enable_receive_pack,
+            informative_errors,
+            init_timeout,
+            timeout,
+            max_connections,
+            allowed,
+        } => {
+            let mut options = daemon::Options::new(base_path)
+                .with_export_all(export_all)
+                .with_receive_pack(enable_receive_pack)
+                .with_informative_errors(informative_errors)
+                .with_max_connections(max_connections);
+            if let Some(seconds) = init_timeout {
+                options = options.with_init_timeout(std::time::Duration::from_secs(seconds));
+            }
+            if let Some(seconds) = timeout {
+                options = options.with_timeout(std::time::Duration::from_secs(seconds));
+            }
+            for directory in allowed {
+                options = options.with_allowed_directory(directory);
+            }
+            daemon::serve(std::net::TcpListener::bind(listen)?, options)?;
+            Ok(())
+        }
+        Command::Shell {
+            command,
+            root,
+            read_only,
+        } => {
+            let Some(command) = command.or_else(|| std::env::var("SSH_ORIGINAL_COMMAND").ok()) else {
+                eprintln!("fatal: interactive shells are not supported, only git commands");
+                std::process::exit(128);
+            };
+            let options = shell::Options::new(root).with_receive_pack(!read_only);
+            if let Err(err) = shell::run(&command, &options) {
+                eprintln!("fatal: {err}");
+                std::process::exit(128);
+            }
+            Ok(())
+        }
+    }
+}
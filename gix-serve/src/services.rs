@@ -0,0 +1,143 @@
+//! Opening the services of a repository, and serving them over standard input and output like native git.
+//!
+//! Receive-pack applies pushes with the [engine](gix_receive_pack::engine) of `gix-receive-pack`, configured by the
+//! `receive.*` settings of the repository that the engine supports, as listed in
+//! [`keys`](gix_receive_pack::config::keys), and serving only the refs of `GIT_NAMESPACE` if it's set. Like with
+//! `git receive-pack`, the `pre-receive`, `update` and `post-receive` hooks in `core.hooksPath` or the `hooks`
+//! directory of the repository are run for each push, and `updateInstead` checks out pushes to the current branch.
+
+use crate::Error;
+use gix_receive_pack::config::keys::{receive, transfer};
+use gix_receive_pack::engine::PushService;
+use gix_receive_pack::hooks::ExternalHookConfig;
+use gix_receive_pack::pack::{LooseObjects, MidxMode, RateLimits, StallDetection};
+use gix_receive_pack::protocol::{AdvertisementConfig, AdvertisementLimits, HeadInfoLimits, PushCertConfig};
+use gix_receive_pack::{
+    CommitGraphConfig, ExternalHooks, HookConfig, HookEnvironment, PolicyConfig, ReceivePackBuilder, WorktreeUpdater,
+};
+use gix_serve_core::protocol::{ProtocolVersion, ServiceKind};
+use gix_serve_core::service::{GitService, ServiceContext};
+use gix_serve_core::visibility::HiddenRefs;
+use std::path::Path;
+
+/// The agent advertised by receive-pack, named like that of upload-pack.
+pub const AGENT: &str = concat!("git/gitoxide-", env!("CARGO_PKG_VERSION"));
+
+/// Open the `kind` service of the repository at `path`.
+pub fn open(kind: ServiceKind, path: &Path) -> Result<Box<dyn GitService + Send>, Error> {
+    Ok(match kind {
+        ServiceKind::UploadPack => Box::new(upload_pack(path)?),
+        ServiceKind::ReceivePack => Box::new(receive_pack(path)?),
+    })
+}
+
+/// Open the upload-pack service of the repository at `path`, configured by the repository.
+pub fn upload_pack(path: &Path) -> Result<gix_upload_pack::Server, Error> {
+    gix_upload_pack::Server::new(path, gix_upload_pack::ServerOptions::default())
+        .map_err(|err| Error::UploadPack(Box::new(err)))
+}
+
+/// Open the receive-pack service of the repository at `path`, configured by the repository, see the
+/// [module documentation](self).
+pub fn receive_pack(path: &Path) -> Result<PushService<ExternalHooks>, Error> {
+    let repo = gix::open(path).map_err(|err| Error::Open(Box::new(err)))?;
+    let config = repo.config_snapshot();
+    let plumbing = config.plumbing();
+    let err = |err: gix_receive_pack::Error| Error::ReceivePack(Box::new(err));
+    let policy = PolicyConfig::from_config(plumbing).map_err(err)?;
+    let unpack_limit = match receive::UNPACK_LIMIT.get(plumbing).map_err(err)? {
+        Some(limit) => Some(limit),
+        None => transfer::UNPACK_LIMIT.get(plumbing).map_err(err)?,
+    };
+    let fsck_objects = match receive::FSCK_OBJECTS.get(plumbing).map_err(err)? {
+        Some(enabled) => enabled,
+        None => transfer::FSCK_OBJECTS.get(plumbing).map_err(err)?.unwrap_or(false),
+    };
+    let max_input_size = non_negative(
+        receive::MAX_INPUT_SIZE.get(plumbing).map_err(err)?,
+        receive::MAX_INPUT_SIZE.name(),
+    )?;
+    let push_manifests = receive::RECORD_PUSH_MANIFESTS.get(plumbing).map_err(err)?;
+    let namespace = std::env::var("GIT_NAMESPACE")
+        .ok()
+        .filter(|namespace| !namespace.is_empty())
+        .map(|namespace| gix::refs::namespace::expand(namespace.as_str()))
+        .transpose()
+        .map_err(|err| invalid(format!("invalid value for 'GIT_NAMESPACE': {err}")))?;
+    let receive_pack = ReceivePackBuilder::new()
+        .blocking()
+        .with_git_dir(repo.git_dir())
+        .with_object_hash(repo.object_hash())
+        .with_objects_dir(repo.objects.store_ref().path())
+        .with_policy(policy.into_policy_set())
+        .with_worktree_updater(
+            repo.workdir()
+                .map(|work_tree| WorktreeUpdater::new(work_tree, repo.git_dir()).with_object_hash(repo.object_hash())),
+        )
+        .with_unpack_limit(non_negative(unpack_limit, receive::UNPACK_LIMIT.name())?)
+        .with_fsck_objects(fsck_objects)
+        .with_max_pack_bytes(max_input_size.filter(|max| *max > 0))
+        .with_stall_detection(StallDetection::from_config(plumbing).map_err(err)?)
+        .with_rate_limits(RateLimits::from_config(plumbing).map_err(err)?)
+        .with_loose_objects(LooseObjects::from_config(plumbing).map_err(err)?)
+        .with_head_info_limits(HeadInfoLimits::from_config(plumbing).map_err(err)?)
+        .with_write_commit_graph(CommitGraphConfig::from_config(plumbing).map_err(err)?.enabled)
+        .with_update_multi_pack_index(MidxMode::from_config(plumbing).map_err(err)?)
+        .with_push_manifests(push_manifests.unwrap_or(false))
+        .with_push_certs(PushCertConfig::from_config(plumbing).map_err(err)?)
+        // Like git, the refs hidden by `transfer.hideRefs` and `receive.hideRefs` aren't advertised or updated.
+        .with_hidden_refs(HiddenRefs::from_config(&config, "receive"))
+        .with_namespace(namespace)
+        .with_advertisement(
+            AdvertisementConfig::modern_defaults()
+                .with_agent(Some(AGENT.into()))
+                // Like git, so the output of hooks reaches the client.
+                .push_extra_capability("side-band-64k")
+                .with_limits(AdvertisementLimits::from_config(plumbing).map_err(err)?)
+                .with_config(plumbing)
+                .map_err(err)?,
+        )
+        .build();
+    let hooks_dir = match config.trusted_path("core.hooksPath").transpose() {
+        Ok(Some(hooks_path)) => repo.workdir().unwrap_or(repo.git_dir()).join(hooks_path),
+        Ok(None) => repo.git_dir().join("hooks"),
+        Err(err) => return Err(invalid(format!("invalid value for 'core.hooksPath': {err}"))),
+    };
+    let hook_config = HookConfig::from_config(plumbing).map_err(err)?;
+    let hooks = ExternalHooks::new(
+        ExternalHookConfig::from_hook_config(hooks_dir, &hook_config),
+        HookEnvironment::new().with_git_dir(repo.git_dir()),
+    );
+    Ok(PushService::new(receive_pack, hooks))
+}
+
+/// Turn the `value` of the integer setting `key` into a limit, refusing negative values.
+fn non_negative(value: Option<i64>, key: &str) -> Result<Option<u64>, Error> {
+    value
+        .map(|value| {
+            u64::try_from(value).map_err(|_| invalid(format!("invalid value for '{key}': must not be negative")))
+        })
+        .transpose()
+}
+
+/// An error for a setting with an invalid value, described by `message`.
+fn invalid(message: String) -> Error {
+    Error::ReceivePack(Box::new(gix_receive_pack::Error::Validation(message)))
+}
+
+/// Serve `service` over standard input and output with the protocol version requested by `GIT_PROTOCOL`.
+///
+/// Like the `--stateless-rpc` and `--advertise-refs` flags of native git, `stateless` serves a single request
+/// without the advertisement, and `advertise_refs` writes only the advertisement.
+pub fn serve_stdio(service: &mut dyn GitService, stateless: bool, advertise_refs: bool) -> Result<(), Error> {
+    let version =
+        std::env::var("GIT_PROTOCOL").map_or(ProtocolVersion::V0, |value| ProtocolVersion::from_git_protocol(&value));
+    let ctx = ServiceContext::new(version).with_stateless(stateless);
+    let mut stdout = std::io::stdout().lock();
+    if advertise_refs {
+        service.advertise(&mut stdout, &ctx)?;
+    } else {
+        service.serve(&mut std::io::stdin().lock(), &mut stdout, &ctx)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,123 @@
+//! Serving repositories to SSH clients, like `git-shell` as the login shell of a git host.
+//!
+//! SSH clients run a command like `git-upload-pack '/project.git'` on the server, which `sshd` passes to a forced
+//! command as `SSH_ORIGINAL_COMMAND`, or to a login shell with `-c`. [`run()`] parses it into a [`Command`], maps
+//! its path to a repository below a root directory with an [`ExportPolicy`], and serves it over standard input and
+//! output. Paths are always relative to the root, whether they were sent as `/project.git` by `ssh://` URLs, as
+//! `project.git` by `host:project.git` URLs or as `~/project.git`, and paths leaving it are refused.
+//!
+//! As SSH clients are authenticated, receive-pack is served unless [disabled](Options::with_receive_pack()), and
+//! applies pushes like it does over standard input and output.
+
+use crate::{services, Error};
+use gix_serve_core::export::{Export, ExportPolicy};
+use gix_serve_core::protocol::ServiceKind;
+use std::path::PathBuf;
+
+/// A command an SSH client runs on the server, like `git-upload-pack '/project.git'`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Command {
+    /// The service the client asked for.
+    pub service: ServiceKind,
+    /// The unquoted path of the repository as sent by the client.
+    pub path: String,
+}
+
+impl Command {
+    /// Parse `command`, the service name followed by the single-quoted path like `git-shell` expects it.
+    ///
+    /// Like `git-shell`, `git upload-pack` is accepted for `git-upload-pack`, and quotes within the path have to be
+    /// escaped as `'\''`. Any other command is refused.
+    pub fn parse(command: &str) -> Result<Self, Error> {
+        let unrecognized = || Error::InvalidRequest(format!("unrecognized command '{command}'"));
+        let (program, argument) = command
+            .trim()
+            .split_once(char::is_whitespace)
+            .ok_or_else(unrecognized)?;
+        let (name, argument) = match program {
+            "git" => argument
+                .trim_start()
+                .split_once(char::is_whitespace)
+                .ok_or_else(unrecognized)?,
+            _ => (program.strip_prefix("git-").ok_or_else(unrecognized)?, argument),
+        };
+        let service = match name {
+            "upload-pack" => ServiceKind::UploadPack,
+            "receive-pack" => ServiceKind::ReceivePack,
+            _ => return Err(unrecognized()),
+        };
+        let path = dequote(argument.trim())
+            .filter(|path| !path.is_empty())
+            .ok_or_else(|| Error::InvalidRequest(format!("invalid repository path in '{command}'")))?;
+        Ok(Command { service, path })
+    }
+}
+
+/// Remove the single quotes `git` puts around the path it sends, or return `None` if `quoted` isn't a single quoted
+/// argument, like `sq_dequote()` of `git`.
+fn dequote(quoted: &str) -> Option<String> {
+    let mut path = String::new();
+    let mut rest = quoted.strip_prefix('\'')?;
+    loop {
+        let (part, after) = rest.split_once('\'')?;
+        path.push_str(part);
+        if after.is_empty() {
+            return Some(path);
+        }
+        // Quotes and exclamation marks are quoted as `'\''` and `'\!'`.
+        let mut chars = after.chars();
+        match (chars.next(), chars.next(), chars.next()) {
+            (Some('\\'), Some(c @ ('\'' | '!')), Some('\'')) => path.push(c),
+            _ => return None,
+        }
+        rest = chars.as_str();
+    }
+}
+
+/// How repositories are served to SSH clients.
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// The directory below which repositories are served.
+    pub root: PathBuf,
+    /// Whether receive-pack is served.
+    pub receive_pack: bool,
+}
+
+impl Options {
+    /// Serve upload-pack and receive-pack for all repositories below `root`.
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Options {
+            root: root.into(),
+            receive_pack: true,
+        }
+    }
+
+    /// Serve receive-pack if `enabled` is `true`, or only let clients fetch otherwise.
+    pub fn with_receive_pack(mut self, enabled: bool) -> Self {
+        self.receive_pack = enabled;
+        self
+    }
+
+    /// Resolve `command` to the repository it should be served with.
+    pub fn resolve(&self, command: &Command) -> Result<Export, Error> {
+        if command.service == ServiceKind::ReceivePack && !self.receive_pack {
+            return Err(Error::InvalidRequest(format!(
+                "'{}' may only be fetched from",
+                command.path
+            )));
+        }
+        let path = command.path.strip_prefix("~/").unwrap_or(&command.path);
+        Ok(ExportPolicy::new(&self.root).resolve(path)?)
+    }
+}
+
+/// Serve `command` as sent by an SSH client over standard input and output, as described by `options`.
+///
+/// The protocol version is taken from `GIT_PROTOCOL`, which `sshd` passes on if configured with
+/// `AcceptEnv GIT_PROTOCOL`.
+pub fn run(command: &str, options: &Options) -> Result<(), Error> {
+    let command = Command::parse(command)?;
+    let export = options.resolve(&command)?;
+    let mut service = services::open(command.service, &export.git_dir)?;
+    services::serve_stdio(service.as_mut(), false, false)
+}
@@ -0,0 +1,44 @@
+//! Serving a service over standard input and output from the command line, shared by the binaries.
+
+use gix_serve::services;
+use std::path::PathBuf;
+
+/// The arguments of the commands serving a service over standard input and output, like those of native git
+#[derive(clap::Args, Debug)]
+pub struct ServiceArgs {
+    /// Quit after a single request/response exchange
+    #[arg(long)]
+    stateless_rpc: bool,
+    /// Only write the advertisement
+    #[arg(long)]
+    advertise_refs: bool,
+    /// The repository to serve
+    #[arg(value_name = "DIRECTORY")]
+    directory: PathBuf,
+}
+
+/// Serve the `kind` service as described by `args` over standard input and output, and exit like native git does
+pub fn serve(kind: gix_serve_core::protocol::ServiceKind, args: ServiceArgs) -> ! {
+    let result = services::open(kind, &args.directory)
+        .and_then(|mut service| services::serve_stdio(service.as_mut(), args.stateless_rpc, args.advertise_refs));
+    match result {
+        Ok(()) => std::process::exit(0),
+        Err(gix_serve::Error::Open(_)) => not_a_repository(&args.directory),
+        Err(gix_serve::Error::UploadPack(err)) if matches!(*err, gix_upload_pack::Error::Repository(_)) => {
+            not_a_repository(&args.directory)
+        }
+        Err(err) => {
+            eprintln!("fatal: {err}");
+            std::process::exit(128);
+        }
+    }
+}
+
+/// Report a missing or unreadable repository like native git does, as clients show it
+fn not_a_repository(directory: &std::path::Path) -> ! {
+    eprintln!(
+        "fatal: '{}' does not appear to be a git repository",
+        directory.display()
+    );
+    std::process::exit(128);
+}
@@ -0,0 +1,68 @@
+//! `gix-serve check-compat` compares the services of a repository with native git
+
+mod util;
+
+use gix_serve::check::{self, Options, Status};
+use std::path::Path;
+use std::process::Command;
+use util::git;
+
+fn options(repository: &Path) -> Options {
+    Options::new(repository, env!("CARGO_BIN_EXE_gix-serve"))
+}
+
+#[test]
+fn repositories_with_history_pass() {
+    let tmp = gix_testtools::tempfile::tempdir().unwrap();
+    let repo = tmp.path();
+    git(repo, &["init", "--quiet", "--initial-branch=main"]);
+    for revision in 1..=3 {
+        std::fs::write(repo.join("file"), format!("revision {revision}\n")).unwrap();
+        git(repo, &["add", "file"]);
+        git(repo, &["commit", "--quiet", "-m", &format!("revision {revision}")]);
+    }
+    git(repo, &["tag", "-a", "-m", "first", "v1", "HEAD~2"]);
+    git(repo, &["branch", "side", "HEAD~1"]);
+
+    let report = check::run(&options(repo)).unwrap();
+    // Upload-pack doesn't negotiate shallow fetches yet, which the shallow fetch check reports.
+    for name in [
+        "upload-pack advertisement (protocol 2)",
+        "clone (protocol 0)",
+        "clone (protocol 2)",
+        "empty push",
+    ] {
+        assert_eq!(
+            report.get(name).map(|check| &check.status),
+            Some(&Status::Passed),
+            "{report}"
+        );
+    }
+    assert_eq!(report.checks.len(), 7);
+    let text = report.to_string();
+    assert!(text.starts_with("checked against git version "), "{text}");
+    assert!(text.lines().any(|line| line == "ok       empty push"), "{text}");
+}
+
+#[test]
+fn empty_repositories_skip_the_fetch() {
+    let tmp = gix_testtools::tempfile::tempdir().unwrap();
+    git(tmp.path(), &["init", "--quiet", "--bare"]);
+
+    let report = check::run(&options(tmp.path())).unwrap();
+    assert_eq!(
+        report.get("shallow fetch of HEAD").map(|check| &check.status),
+        Some(&Status::Skipped("HEAD is unborn".into()))
+    );
+    assert!(report.passed(), "{report}");
+
+    let output = Command::new(env!("CARGO_BIN_EXE_gix-serve"))
+        .arg("check-compat")
+        .arg(tmp.path())
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert!(String::from_utf8(output.stdout)
+        .unwrap()
+        .contains("skipped  shallow fetch of HEAD"));
+}
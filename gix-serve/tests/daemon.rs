@@ -0,0 +1,182 @@
+//! Native git clients fetching from and pushing to `gix-serve` over the `git://` protocol
+
+mod util;
+
+use gix_serve::daemon::{self, Options, Request};
+use gix_serve_core::protocol::{ProtocolVersion, ServiceKind};
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::Path;
+use std::time::Duration;
+use util::{git, git_fails};
+
+/// Serve the repositories described by `options` on a local port in the background, returning its address
+fn serve(options: Options) -> std::net::SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    std::thread::spawn(move || daemon::serve(listener, options));
+    addr
+}
+
+/// Create a bare repository named `name` in `base` with a single commit, returning its `main` commit.
+fn bare_repository(base: &Path, name: &str) -> String {
+    let source = base.join("source");
+    if !source.exists() {
+        std::fs::create_dir(&source).unwrap();
+        git(&source, &["init", "--quiet", "--initial-branch=main"]);
+        git(&source, &["commit", "--quiet", "--allow-empty", "-m", "initial"]);
+    }
+    git(base, &["clone", "--quiet", "--bare", "source", name]);
+    git(&source, &["rev-parse", "main"])
+}
+
+#[test]
+fn request_lines_are_parsed_like_git_daemon() {
+    let request = Request::parse(b"git-upload-pack /project.git\0host=example.com:9418\0\0version=2\0").unwrap();
+    assert_eq!(
+        request,
+        Request {
+            service: ServiceKind::UploadPack,
+            path: "/project.git".into(),
+            host: Some("example.com:9418".into()),
+            extra_parameters: vec!["version=2".into()],
+        }
+    );
+    assert_eq!(request.protocol_version(), ProtocolVersion::V2);
+
+    let request = Request::parse(b"git-receive-pack /project.git\0\0version=1\0").unwrap();
+    assert_eq!(request.service, ServiceKind::ReceivePack);
+    assert_eq!(request.host, None);
+    assert_eq!(request.protocol_version(), ProtocolVersion::V1);
+
+    let request = Request::parse(b"git-upload-pack /project.git\n").unwrap();
+    assert_eq!(request.path, "/project.git");
+    assert_eq!(request.protocol_version(), ProtocolVersion::V0);
+
+    for invalid in [
+        &b"git-upload-archive /project.git\0"[..],
+        b"git-upload-pack\0host=example.com\0",
+        b"git-upload-pack /project.git\0user=me\0",
+    ] {
+        assert!(Request::parse(invalid).is_err(), "{invalid:?}");
+    }
+}
+
+#[test]
+fn exported_repositories_are_served_to_native_clients() {
+    let tmp = gix_testtools::tempfile::tempdir().unwrap();
+    let main = bare_repository(tmp.path(), "exported.git");
+    bare_repository(tmp.path(), "private.git");
+    std::fs::write(tmp.path().join("exported.git").join("git-daemon-export-ok"), "").unwrap();
+    let addr = serve(Options::new(tmp.path()));
+
+    for version in ["0", "2"] {
+        let clone = format!("clone-v{version}");
+        git(
+            tmp.path(),
+            &[
+                "-c",
+                &format!("protocol.version={version}"),
+                "clone",
+                "--quiet",
+                &format!("git://{addr}/exported"),
+                &clone,
+            ],
+        );
+        assert_eq!(
+            git(&tmp.path().join(clone), &["rev-parse", "origin/main"]),
+            main,
+            "v{version}"
+        );
+    }
+    for path in ["private.git", "missing.git", "../source"] {
+        let stderr = git_fails(tmp.path(), &["ls-remote", &format!("git://{addr}/{path}")]);
+        assert!(
+            stderr.contains("access denied or repository not exported"),
+            "{path}: {stderr}"
+        );
+    }
+    let stderr = git_fails(&tmp.path().join("clone-v2"), &["push", "--quiet", "origin", "main"]);
+    assert!(
+        stderr.contains("access denied or repository not exported: /exported"),
+        "receive-pack isn't served by default: {stderr}"
+    );
+}
+
+#[test]
+fn informative_errors_and_allowed_directories() {
+    let tmp = gix_testtools::tempfile::tempdir().unwrap();
+    std::fs::create_dir(tmp.path().join("public")).unwrap();
+    bare_repository(tmp.path(), "public/allowed.git");
+    bare_repository(tmp.path(), "other.git");
+    let addr = serve(
+        Options::new(tmp.path())
+            .with_export_all(true)
+            .with_informative_errors(true)
+            .with_allowed_directory(tmp.path().join("public")),
+    );
+
+    git(tmp.path(), &["ls-remote", &format!("git://{addr}/public/allowed.git")]);
+    let stderr = git_fails(tmp.path(), &["ls-remote", &format!("git://{addr}/other.git")]);
+    assert!(stderr.contains("repository not exported: /other.git"), "{stderr}");
+    let stderr = git_fails(tmp.path(), &["ls-remote", &format!("git://{addr}/missing.git")]);
+    assert!(stderr.contains("no such repository: /missing.git"), "{stderr}");
+    let stderr = git_fails(
+        &tmp.path().join("source"),
+        &["push", &format!("git://{addr}/public/allowed.git"), "main"],
+    );
+    assert!(stderr.contains("service not enabled: /public/allowed.git"), "{stderr}");
+}
+
+#[test]
+fn pushes_are_applied_once_receive_pack_is_enabled() {
+    let tmp = gix_testtools::tempfile::tempdir().unwrap();
+    bare_repository(tmp.path(), "served.git");
+    let addr = serve(Options::new(tmp.path()).with_export_all(true).with_receive_pack(true));
+
+    let source = tmp.path().join("source");
+    std::fs::write(source.join("file"), "content\n").unwrap();
+    git(&source, &["add", "file"]);
+    git(&source, &["commit", "--quiet", "-m", "second"]);
+    let url = format!("git://{addr}/served.git");
+    git(&source, &["push", "--quiet", &url, "main", "main:refs/heads/topic"]);
+    let served = tmp.path().join("served.git");
+    let main = git(&source, &["rev-parse", "main"]);
+    for name in ["main", "topic"] {
+        assert_eq!(git(&served, &["rev-parse", name]), main, "{name} was updated");
+    }
+    assert_eq!(
+        git(&served, &["cat-file", "-p", "main:file"]),
+        "content",
+        "the objects were received"
+    );
+    git(&served, &["fsck", "--no-progress"]);
+
+    git(&source, &["push", "--quiet", &url, ":topic"]);
+    assert_eq!(git(&served, &["branch", "--list", "topic"]), "", "topic was deleted");
+}
+
+#[test]
+fn connections_beyond_the_limit_are_refused_and_those_without_request_closed() {
+    let tmp = gix_testtools::tempfile::tempdir().unwrap();
+    let addr = serve(
+        Options::new(tmp.path())
+            .with_max_connections(1)
+            .with_init_timeout(Duration::from_millis(200)),
+    );
+
+    let mut idle = TcpStream::connect(addr).unwrap();
+    idle.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+    std::thread::sleep(Duration::from_millis(50));
+    let mut refused = TcpStream::connect(addr).unwrap();
+    refused.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+    refused.write_all(b"0024git-upload-pack /project.git\0").unwrap();
+    let mut answer = String::new();
+    refused.read_to_string(&mut answer).unwrap();
+    assert_eq!(answer, "001cERR too many connections", "the limit is reached");
+    assert_eq!(
+        idle.read(&mut [0; 4]).unwrap(),
+        0,
+        "the idle connection is closed once its request timed out"
+    );
+}
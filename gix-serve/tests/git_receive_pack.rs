@@ -0,0 +1,68 @@
+//! `git-receive-pack`, the drop-in replacement of the native command, and its fallback to it
+#![cfg(feature = "git-receive-pack")]
+
+mod util;
+
+use std::process::Command;
+use util::git;
+
+#[test]
+fn the_alias_serves_like_gix_serve() {
+    let tmp = gix_testtools::tempfile::tempdir().unwrap();
+    git(tmp.path(), &["init", "--quiet", "--bare", "served.git"]);
+    let advertise = |program: &str, args: &[&str]| {
+        let output = Command::new(program)
+            .args(args)
+            .args(["--advertise-refs", "served.git"])
+            .current_dir(tmp.path())
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        output.stdout
+    };
+    assert_eq!(
+        advertise(env!("CARGO_BIN_EXE_git-receive-pack"), &[]),
+        advertise(env!("CARGO_BIN_EXE_gix-serve"), &["receive-pack"])
+    );
+
+    let source = tmp.path().join("source");
+    std::fs::create_dir(&source).unwrap();
+    git(&source, &["init", "--quiet", "--initial-branch=main"]);
+    git(&source, &["commit", "--quiet", "--allow-empty", "-m", "initial"]);
+    let receive_pack = format!("--receive-pack={}", env!("CARGO_BIN_EXE_git-receive-pack"));
+    git(&source, &["push", "--quiet", &receive_pack, "../served.git", "main"]);
+    assert_eq!(
+        git(&tmp.path().join("served.git"), &["rev-parse", "main"]),
+        git(&source, &["rev-parse", "main"]),
+        "pushes are applied"
+    );
+}
+
+#[test]
+fn unknown_arguments_run_native_git_only_with_fallback() {
+    let tmp = gix_testtools::tempfile::tempdir().unwrap();
+    let run = |fallback: Option<&str>| {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_git-receive-pack"));
+        command
+            .arg("--not-a-flag")
+            .arg(tmp.path())
+            .env_remove("GIX_SERVE_FALLBACK");
+        if let Some(fallback) = fallback {
+            command.env("GIX_SERVE_FALLBACK", fallback);
+        }
+        let output = command.output().unwrap();
+        (
+            output.status.code(),
+            String::from_utf8_lossy(&output.stderr).into_owned(),
+        )
+    };
+
+    let (code, stderr) = run(None);
+    assert_ne!(code, Some(0));
+    assert!(stderr.contains("--not-a-flag"), "{stderr}");
+    assert!(!stderr.contains("usage: git receive-pack"), "{stderr}");
+
+    let (code, stderr) = run(Some("native"));
+    assert_eq!(code, Some(129), "git rejects the flag as well: {stderr}");
+    assert!(stderr.contains("usage: git receive-pack"), "{stderr}");
+}
@@ -0,0 +1,92 @@
+//! Native git clients fetching from and pushing to `gix-serve` over smart HTTP
+
+mod util;
+
+use gix_serve::http::{self, Options};
+use util::{git, git_fails};
+
+/// Serve the repositories described by `options` on a local port in the background, returning the base URL
+fn serve(options: Options) -> String {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let listener = runtime.block_on(tokio::net::TcpListener::bind("127.0.0.1:0")).unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    std::thread::spawn(move || runtime.block_on(http::serve(listener, options)));
+    url
+}
+
+#[test]
+fn exported_repositories_are_served_to_native_clients() {
+    let tmp = gix_testtools::tempfile::tempdir().unwrap();
+    let source = tmp.path().join("source");
+    std::fs::create_dir(&source).unwrap();
+    git(&source, &["init", "--quiet", "--initial-branch=main"]);
+    git(&source, &["commit", "--quiet", "--allow-empty", "-m", "initial"]);
+    let base = tmp.path().join("base");
+    std::fs::create_dir(&base).unwrap();
+    for name in ["exported.git", "private.git"] {
+        git(&base, &["clone", "--quiet", "--bare", source.to_str().unwrap(), name]);
+    }
+    std::fs::write(base.join("exported.git").join("git-daemon-export-ok"), "").unwrap();
+    let url = serve(Options::new(&base));
+
+    for version in ["0", "2"] {
+        let clone = format!("clone-v{version}");
+        git(
+            tmp.path(),
+            &[
+                "-c",
+                &format!("protocol.version={version}"),
+                "clone",
+                "--quiet",
+                &format!("{url}/exported"),
+                &clone,
+            ],
+        );
+        assert_eq!(
+            git(&tmp.path().join(clone), &["rev-parse", "origin/main"]),
+            git(&source, &["rev-parse", "main"]),
+            "v{version}"
+        );
+    }
+    for path in ["private.git", "missing.git", "../source"] {
+        let stderr = git_fails(tmp.path(), &["ls-remote", &format!("{url}/{path}")]);
+        assert!(stderr.contains("not found"), "{path}: {stderr}");
+    }
+    let stderr = git_fails(&tmp.path().join("clone-v2"), &["push", "--quiet", "origin", "main"]);
+    assert!(stderr.contains("403"), "receive-pack isn't served by default: {stderr}");
+}
+
+#[test]
+fn receive_pack_is_served_once_enabled() {
+    let tmp = gix_testtools::tempfile::tempdir().unwrap();
+    let source = tmp.path().join("source");
+    std::fs::create_dir(&source).unwrap();
+    git(&source, &["init", "--quiet", "--initial-branch=main"]);
+    git(&source, &["commit", "--quiet", "--allow-empty", "-m", "initial"]);
+    git(tmp.path(), &["clone", "--quiet", "--bare", "source", "served.git"]);
+    let url = serve(Options::new(tmp.path()).with_export_all(true).with_receive_pack(true));
+
+    let served = tmp.path().join("served.git");
+    git(&source, &["remote", "add", "served", &format!("{url}/served.git")]);
+    git(&source, &["push", "--quiet", "served", "main"]);
+    git(&source, &["commit", "--quiet", "--allow-empty", "-m", "second"]);
+    git(&source, &["push", "--quiet", "served", "main", "main:refs/heads/topic"]);
+    for name in ["main", "topic"] {
+        assert_eq!(
+            git(&served, &["rev-parse", name]),
+            git(&source, &["rev-parse", "main"]),
+            "{name} was pushed with its objects"
+        );
+    }
+    git(&served, &["fsck", "--no-progress"]);
+    git(
+        &source,
+        &["commit", "--quiet", "--amend", "--allow-empty", "-m", "amended"],
+    );
+    git(&served, &["config", "receive.denyNonFastForwards", "true"]);
+    let stderr = git_fails(&source, &["push", "--force", "served", "main"]);
+    assert!(
+        stderr.contains("non-fast-forward"),
+        "the repository configures the policy: {stderr}"
+    );
+}
@@ -0,0 +1,145 @@
+//! Native git clients pushing to `gix-serve receive-pack`, which runs hooks and honours the settings of the repository
+
+mod util;
+
+use std::path::Path;
+use std::process::Command;
+use util::{git, git_fails};
+
+/// The value of `--receive-pack` for running `gix-serve receive-pack` instead of the native command.
+fn receive_pack() -> String {
+    format!("--receive-pack={} receive-pack", env!("CARGO_BIN_EXE_gix-serve"))
+}
+
+/// Write the executable hook `name` with the shell `script` to the hooks of the repository at `git_dir`.
+fn hook(git_dir: &Path, name: &str, script: &str) {
+    let path = git_dir.join("hooks").join(name);
+    std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+    std::fs::write(&path, format!("#!/bin/sh\n{script}\n")).unwrap();
+    use std::os::unix::fs::PermissionsExt;
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+}
+
+/// Create a repository with a commit on `main` at `source`, and the bare repository `served.git` next to it.
+fn source_and_served(dir: &Path) -> (std::path::PathBuf, std::path::PathBuf) {
+    let source = dir.join("source");
+    std::fs::create_dir(&source).unwrap();
+    git(&source, &["init", "--quiet", "--initial-branch=main"]);
+    std::fs::write(source.join("file"), "content\n").unwrap();
+    git(&source, &["add", "file"]);
+    git(&source, &["commit", "--quiet", "-m", "initial"]);
+    git(dir, &["init", "--quiet", "--bare", "served.git"]);
+    (source, dir.join("served.git"))
+}
+
+#[test]
+fn hooks_of_the_repository_are_run() {
+    let tmp = gix_testtools::tempfile::tempdir().unwrap();
+    let (source, served) = source_and_served(tmp.path());
+    hook(
+        &served,
+        "pre-receive",
+        "while read old new name; do\n  \
+         case \"$name\" in refs/heads/denied) echo \"pre-receive refused $name\" >&2; exit 1;; esac\n\
+         done",
+    );
+    hook(&served, "update", "echo \"$1\" >> \"$GIT_DIR/updated\"");
+    hook(&served, "post-receive", "cat > \"$GIT_DIR/received\"");
+
+    git(&source, &["push", "--quiet", &receive_pack(), "../served.git", "main"]);
+    let main = git(&source, &["rev-parse", "main"]);
+    assert_eq!(git(&served, &["rev-parse", "main"]), main);
+    assert_eq!(
+        std::fs::read_to_string(served.join("updated")).unwrap(),
+        "refs/heads/main\n"
+    );
+    assert_eq!(
+        std::fs::read_to_string(served.join("received")).unwrap().trim(),
+        format!("{} {main} refs/heads/main", "0".repeat(40))
+    );
+
+    let stderr = git_fails(
+        &source,
+        &["push", &receive_pack(), "../served.git", "main:refs/heads/denied"],
+    );
+    assert!(stderr.contains("pre-receive refused refs/heads/denied"), "{stderr}");
+    assert!(
+        Command::new("git")
+            .args(["rev-parse", "--verify", "--quiet", "refs/heads/denied"])
+            .current_dir(&served)
+            .output()
+            .unwrap()
+            .stdout
+            .is_empty(),
+        "refused updates aren't applied"
+    );
+}
+
+#[test]
+fn receive_settings_of_the_repository_are_honoured() {
+    let tmp = gix_testtools::tempfile::tempdir().unwrap();
+    let (source, served) = source_and_served(tmp.path());
+    git(&served, &["config", "receive.advertisePushOptions", "true"]);
+    hook(
+        &served,
+        "pre-receive",
+        "echo \"$GIT_PUSH_OPTION_0\" > \"$GIT_DIR/option\"",
+    );
+    git(
+        &source,
+        &[
+            "push",
+            "--quiet",
+            "--push-option=reason",
+            &receive_pack(),
+            "../served.git",
+            "main",
+        ],
+    );
+    assert_eq!(
+        std::fs::read_to_string(served.join("option")).unwrap(),
+        "reason\n",
+        "push options are advertised and passed to hooks"
+    );
+
+    git(&served, &["config", "receive.maxInputSize", "10"]);
+    git(&source, &["commit", "--quiet", "--allow-empty", "-m", "second"]);
+    let stderr = git_fails(&source, &["push", &receive_pack(), "../served.git", "main"]);
+    assert_ne!(
+        git(&served, &["rev-parse", "main"]),
+        git(&source, &["rev-parse", "main"]),
+        "packs larger than the limit are refused: {stderr}"
+    );
+}
+
+#[test]
+fn only_refs_of_the_namespace_are_served() {
+    let tmp = gix_testtools::tempfile::tempdir().unwrap();
+    let (source, served) = source_and_served(tmp.path());
+    let main = git(&source, &["rev-parse", "main"]);
+    git(
+        &served,
+        &[
+            "fetch",
+            "--quiet",
+            "../source",
+            "main:refs/namespaces/ns/refs/heads/main",
+        ],
+    );
+    git(&served, &["fetch", "--quiet", "../source", "main:refs/heads/other"]);
+
+    let output = Command::new(env!("CARGO_BIN_EXE_gix-serve"))
+        .args(["receive-pack", "--advertise-refs", "served.git"])
+        .current_dir(tmp.path())
+        .env("GIT_NAMESPACE", "ns")
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let advertisement = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        advertisement.contains(&format!("{main} refs/heads/main")),
+        "{advertisement}"
+    );
+    assert!(!advertisement.contains("refs/heads/other"), "{advertisement}");
+    assert!(!advertisement.contains("refs/namespaces/"), "{advertisement}");
+}
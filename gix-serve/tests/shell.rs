@@ -0,0 +1,195 @@
+//! Native git clients fetching from and pushing to `gix-serve shell` over a stand-in for SSH
+
+mod util;
+
+use gix_serve::shell::{Command as ShellCommand, Options};
+use gix_serve_core::protocol::ServiceKind;
+use std::path::Path;
+use std::process::Command;
+use util::git;
+
+/// Run `git` with `args` in `dir` through `ssh`, a script standing in for SSH, and return its output.
+fn git_over_ssh(ssh: &Path, dir: &Path, args: &[&str]) -> std::process::Output {
+    Command::new("git")
+        .args(args)
+        .current_dir(dir)
+        .env("GIT_SSH_COMMAND", ssh)
+        .env("GIT_SSH_VARIANT", "ssh")
+        .output()
+        .unwrap()
+}
+
+/// Write a script standing in for SSH which runs `gix-serve shell` with `args` for the command it's given, like a
+/// forced command would.
+fn fake_ssh(dir: &Path, args: &str) -> std::path::PathBuf {
+    let script = dir.join("ssh");
+    std::fs::write(
+        &script,
+        format!(
+            "#!/bin/sh\nfor command; do :; done\nSSH_ORIGINAL_COMMAND=\"$command\" exec '{}' shell {args}\n",
+            env!("CARGO_BIN_EXE_gix-serve")
+        ),
+    )
+    .unwrap();
+    use std::os::unix::fs::PermissionsExt;
+    std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+    script
+}
+
+#[test]
+fn commands_are_parsed_like_git_shell() {
+    for (command, service, path) in [
+        (
+            "git-upload-pack '/project.git'",
+            ServiceKind::UploadPack,
+            "/project.git",
+        ),
+        (
+            "git-receive-pack 'group/project.git'",
+            ServiceKind::ReceivePack,
+            "group/project.git",
+        ),
+        (
+            "git upload-pack '~/project.git'",
+            ServiceKind::UploadPack,
+            "~/project.git",
+        ),
+        ("git-upload-pack 'it'\\''s'", ServiceKind::UploadPack, "it's"),
+        ("git-upload-pack 'wow'\\!''", ServiceKind::UploadPack, "wow!"),
+    ] {
+        assert_eq!(
+            ShellCommand::parse(command).unwrap(),
+            ShellCommand {
+                service,
+                path: path.into()
+            },
+            "{command}"
+        );
+    }
+    for command in [
+        "git-upload-pack /project.git",
+        "git-upload-pack '/project.git' '/other.git'",
+        "git-upload-pack ''",
+        "git-upload-archive '/project.git'",
+        "sh -c 'git-upload-pack /project.git'",
+        "git-upload-pack",
+    ] {
+        assert!(ShellCommand::parse(command).is_err(), "{command}");
+    }
+}
+
+#[test]
+fn repositories_below_the_root_are_served_to_native_clients() {
+    let tmp = gix_testtools::tempfile::tempdir().unwrap();
+    let source = tmp.path().join("source");
+    std::fs::create_dir(&source).unwrap();
+    git(&source, &["init", "--quiet", "--initial-branch=main"]);
+    git(&source, &["commit", "--quiet", "--allow-empty", "-m", "initial"]);
+    let root = tmp.path().join("root");
+    std::fs::create_dir_all(root.join("group")).unwrap();
+    git(
+        &root,
+        &[
+            "clone",
+            "--quiet",
+            "--bare",
+            source.to_str().unwrap(),
+            "group/project.git",
+        ],
+    );
+    let ssh = fake_ssh(tmp.path(), &format!("--root '{}'", root.display()));
+    let main = git(&source, &["rev-parse", "main"]);
+
+    for (version, url) in [
+        ("0", "ssh://host/group/project.git"),
+        ("2", "ssh://host/group/project"),
+        ("2", "host:group/project.git"),
+        ("2", "ssh://host/~/group/project.git"),
+    ] {
+        let output = git_over_ssh(
+            &ssh,
+            tmp.path(),
+            &["-c", &format!("protocol.version={version}"), "ls-remote", url, "main"],
+        );
+        assert!(
+            output.status.success(),
+            "{url}: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout),
+            format!("{main}\trefs/heads/main\n"),
+            "{url} over v{version}"
+        );
+    }
+    let output = git_over_ssh(
+        &ssh,
+        tmp.path(),
+        &["clone", "--quiet", "host:group/project.git", "clone"],
+    );
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(git(&tmp.path().join("clone"), &["rev-parse", "origin/main"]), main);
+
+    let output = git_over_ssh(&ssh, tmp.path(), &["ls-remote", "host:../source"]);
+    assert!(!output.status.success(), "paths leaving the root are refused");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("is outside of the served directory"), "{stderr}");
+}
+
+#[test]
+fn pushes_are_applied_to_repositories_below_the_root() {
+    let tmp = gix_testtools::tempfile::tempdir().unwrap();
+    let source = tmp.path().join("source");
+    std::fs::create_dir(&source).unwrap();
+    git(&source, &["init", "--quiet", "--initial-branch=main"]);
+    git(&source, &["commit", "--quiet", "--allow-empty", "-m", "initial"]);
+    git(tmp.path(), &["clone", "--quiet", "--bare", "source", "project.git"]);
+    let ssh = fake_ssh(tmp.path(), &format!("--root '{}'", tmp.path().display()));
+
+    std::fs::write(source.join("file"), "content\n").unwrap();
+    git(&source, &["add", "file"]);
+    git(&source, &["commit", "--quiet", "-m", "second"]);
+    let output = git_over_ssh(
+        &ssh,
+        &source,
+        &["push", "--quiet", "host:project.git", "main", "main:refs/heads/topic"],
+    );
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let served = tmp.path().join("project.git");
+    let main = git(&source, &["rev-parse", "main"]);
+    for name in ["main", "topic"] {
+        assert_eq!(git(&served, &["rev-parse", name]), main, "{name} was updated");
+    }
+    assert_eq!(
+        git(&served, &["cat-file", "-p", "main:file"]),
+        "content",
+        "the objects were received"
+    );
+    git(&served, &["fsck", "--no-progress"]);
+}
+
+#[test]
+fn receive_pack_can_be_disabled() {
+    let tmp = gix_testtools::tempfile::tempdir().unwrap();
+    std::fs::create_dir(tmp.path().join("source")).unwrap();
+    git(
+        &tmp.path().join("source"),
+        &["init", "--quiet", "--initial-branch=main"],
+    );
+    git(
+        &tmp.path().join("source"),
+        &["commit", "--quiet", "--allow-empty", "-m", "initial"],
+    );
+    git(tmp.path(), &["clone", "--quiet", "--bare", "source", "project.git"]);
+
+    let options = Options::new(tmp.path());
+    let command = ShellCommand::parse("git-receive-pack '/project.git'").unwrap();
+    assert!(options.resolve(&command).is_ok(), "receive-pack is served by default");
+    assert!(options.with_receive_pack(false).resolve(&command).is_err());
+
+    let ssh = fake_ssh(tmp.path(), &format!("--root '{}' --read-only", tmp.path().display()));
+    let output = git_over_ssh(&ssh, &tmp.path().join("source"), &["push", "host:project.git", "main"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("'project.git' may only be fetched from"), "{stderr}");
+}
@@ -0,0 +1,33 @@
+//! Helpers shared by the tests, to set up repositories and run native git clients against the servers.
+
+// Each test binary uses only some of them.
+#![allow(dead_code)]
+
+use std::path::Path;
+use std::process::Command;
+
+/// Run `git` with `args` in `dir` and return its trimmed standard output, failing the test if it fails.
+pub fn git(dir: &Path, args: &[&str]) -> String {
+    let output = Command::new("git")
+        .args(args)
+        .current_dir(dir)
+        .env("GIT_AUTHOR_NAME", "author")
+        .env("GIT_AUTHOR_EMAIL", "author@example.com")
+        .env("GIT_COMMITTER_NAME", "committer")
+        .env("GIT_COMMITTER_EMAIL", "committer@example.com")
+        .output()
+        .expect("git is installed");
+    assert!(
+        output.status.success(),
+        "git {args:?} failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8(output.stdout).unwrap().trim().to_owned()
+}
+
+/// Run `git` with `args` in `dir`, expecting it to fail, and return its standard error.
+pub fn git_fails(dir: &Path, args: &[&str]) -> String {
+    let output = Command::new("git").args(args).current_dir(dir).output().unwrap();
+    assert!(!output.status.success(), "git {args:?} succeeded");
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
@@ -0,0 +1,98 @@
+//! Fetch time of metadata-only mirrors with `tree:0`, which counts commits only, compared to full fetches
+//!
+//! Run with `cargo bench -p gix-upload-pack --bench tree_filter`
+
+#[path = "../tests/util/mod.rs"]
+mod util;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use gix_upload_pack::{server::Step, ProtocolVersion, Server, ServerOptions};
+use std::hint::black_box;
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use util::{git, pkt};
+
+/// The number of commits in the generated history
+const COMMITS: usize = 2_000;
+/// The number of directories in each tree, each with `FILES` files
+const DIRECTORIES: usize = 20;
+const FILES: usize = 20;
+
+/// Create a packed repository at `dir` whose commits each change one file of a wide tree, returning the head
+fn generate(dir: &Path) -> String {
+    git(dir, &["init", "--quiet", "--bare"]);
+    let mut fast_import = Command::new("git")
+        .args(["fast-import", "--quiet"])
+        .current_dir(dir)
+        .stdin(Stdio::piped())
+        .spawn()
+        .expect("git is installed");
+    let mut stream = std::io::BufWriter::new(fast_import.stdin.take().unwrap());
+    for commit in 0..COMMITS {
+        writeln!(stream, "commit refs/heads/main").unwrap();
+        writeln!(
+            stream,
+            "committer bench <bench@example.com> {} +0000",
+            1_700_000_000 + commit
+        )
+        .unwrap();
+        writeln!(stream, "data <<EOF\ncommit {commit}\nEOF").unwrap();
+        let files: Vec<_> = if commit == 0 {
+            (0..DIRECTORIES * FILES).collect()
+        } else {
+            vec![commit % (DIRECTORIES * FILES)]
+        };
+        for file in files {
+            let path = format!("dir{}/file{}", file / FILES, file % FILES);
+            writeln!(
+                stream,
+                "M 644 inline {path}\ndata <<EOF\n{path} in commit {commit}\nEOF"
+            )
+            .unwrap();
+        }
+        writeln!(stream).unwrap();
+    }
+    drop(stream);
+    assert!(fast_import.wait().unwrap().success());
+    git(dir, &["repack", "-adq"]);
+    git(dir, &["rev-parse", "refs/heads/main"])
+}
+
+/// Fetch `head` from `dir` with protocol v2, optionally with `filter`, and return the size of the response
+fn fetch(dir: &Path, head: &str, filter: Option<&str>) -> usize {
+    let mut request = format!("{}0001{}", pkt("command=fetch\n"), pkt(&format!("want {head}\n")));
+    if let Some(filter) = filter {
+        request += &pkt(&format!("filter {filter}\n"));
+    }
+    request += &format!("{}0000", pkt("done\n"));
+
+    let options = ServerOptions::default()
+        .with_stateless_rpc(true)
+        .with_repository_overrides(false);
+    let mut server = Server::new(dir, options).expect("repository can be opened");
+    let mut session = server.step_session(ProtocolVersion::V2).expect("session can start");
+    session.push_input(request.as_bytes());
+    session.finish_input();
+    let mut output = Vec::new();
+    while session.serve_step(&mut output).expect("fetch succeeds") != Step::Done {}
+    output.len()
+}
+
+fn tree_filter(c: &mut Criterion) {
+    let tmp = tempfile::tempdir().expect("temp dir can be created");
+    let head = generate(tmp.path());
+    let mut group = c.benchmark_group("fetch");
+    group.sample_size(10);
+    for filter in [None, Some("blob:none"), Some("tree:0")] {
+        group.bench_with_input(
+            BenchmarkId::from_parameter(filter.unwrap_or("unfiltered")),
+            &filter,
+            |b, filter| b.iter(|| black_box(fetch(tmp.path(), &head, *filter))),
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, tree_filter);
+criterion_main!(benches);
@@ -0,0 +1,109 @@
+//! Existence checks of many wants, one by one compared to batched with `find_existing()`
+//!
+//! Run with `cargo bench -p gix-upload-pack --bench want_validation`
+
+#[path = "../tests/util/mod.rs"]
+mod util;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use gix_hash::ObjectId;
+use gix_upload_pack::services::pack::find_existing;
+use std::hint::black_box;
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use util::git;
+
+/// The number of wants checked in each iteration
+const WANTS: usize = 5_000;
+
+/// Create a repository at `dir` with `WANTS` commits, packed or loose, and return their ids
+fn generate(dir: &Path, loose: bool) -> Vec<ObjectId> {
+    git(dir, &["init", "--quiet", "--bare"]);
+    let mut fast_import = Command::new("git")
+        .args(["fast-import", "--quiet"])
+        .current_dir(dir)
+        .stdin(Stdio::piped())
+        .spawn()
+        .expect("git is installed");
+    let mut stream = std::io::BufWriter::new(fast_import.stdin.take().unwrap());
+    for commit in 0..WANTS {
+        writeln!(stream, "commit refs/heads/main").unwrap();
+        writeln!(
+            stream,
+            "committer bench <bench@example.com> {} +0000",
+            1_700_000_000 + commit
+        )
+        .unwrap();
+        writeln!(stream, "data <<EOF\ncommit {commit}\nEOF\n").unwrap();
+    }
+    drop(stream);
+    assert!(fast_import.wait().unwrap().success());
+    if loose {
+        git(dir, &["repack", "-adq"]);
+        let pack_dir = dir.join("objects/pack");
+        let pack = std::fs::read_dir(&pack_dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .find(|path| path.extension().is_some_and(|ext| ext == "pack"))
+            .expect("repository was packed");
+        let data = std::fs::read(&pack).unwrap();
+        std::fs::remove_dir_all(&pack_dir).unwrap();
+        std::fs::create_dir(&pack_dir).unwrap();
+        let mut unpack = Command::new("git")
+            .args(["unpack-objects", "-q"])
+            .current_dir(dir)
+            .stdin(Stdio::piped())
+            .spawn()
+            .expect("git is installed");
+        unpack.stdin.take().unwrap().write_all(&data).unwrap();
+        assert!(unpack.wait().unwrap().success());
+    }
+    git(dir, &["rev-list", "refs/heads/main"])
+        .lines()
+        .map(|line| ObjectId::from_hex(line.as_bytes()).unwrap())
+        .collect()
+}
+
+/// Ids of objects that don't exist, as sent by clients asking for objects the server doesn't have
+fn missing() -> Vec<ObjectId> {
+    (0..WANTS as u64)
+        .map(|n| {
+            let mut bytes = [0u8; 20];
+            bytes[..8].copy_from_slice(&n.wrapping_mul(0x9e37_79b9_7f4a_7c15).to_be_bytes());
+            bytes[19] = 1;
+            ObjectId::from_bytes_or_panic(&bytes)
+        })
+        .collect()
+}
+
+fn want_validation(c: &mut Criterion) {
+    let packed_dir = tempfile::tempdir().expect("temp dir can be created");
+    let loose_dir = tempfile::tempdir().expect("temp dir can be created");
+    let scenarios = [
+        ("packed", packed_dir.path(), generate(packed_dir.path(), false)),
+        ("loose", loose_dir.path(), generate(loose_dir.path(), true)),
+        ("missing", packed_dir.path(), missing()),
+    ];
+    for (name, dir, ids) in scenarios {
+        let repository = gix::open(dir).expect("repository can be opened");
+        let mut group = c.benchmark_group(format!("want-validation/{name}"));
+        group.sample_size(10);
+        group.bench_with_input(BenchmarkId::from_parameter("individual"), &ids, |b, ids| {
+            b.iter(|| {
+                black_box(
+                    ids.iter()
+                        .filter(|id| gix_object::Exists::exists(&repository, id))
+                        .count(),
+                )
+            })
+        });
+        group.bench_with_input(BenchmarkId::from_parameter("batched"), &ids, |b, ids| {
+            b.iter(|| black_box(find_existing(&repository, ids).expect("lookups succeed").len()))
+        });
+        group.finish();
+    }
+}
+
+criterion_group!(benches, want_validation);
+criterion_main!(benches);
@@ -60,8 +60,11 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     // Process the upload-pack protocol
     match server.serve(&mut stdin_lock, &mut stdout_lock) {
-        Ok(()) => {
-            println!("✓ Protocol session completed successfully");
+        Ok(outcome) => {
+            println!(
+                "✓ Protocol session completed successfully, {} bytes sent",
+                outcome.wire.response_bytes
+            );
         }
         Err(e) => {
             eprintln!("✗ Protocol error: {}", e);
@@ -0,0 +1,4 @@
+// `gix-upload-pack` named like the native command, built with the `git-upload-pack` feature.
+//
+// With `GIX_SERVE_FALLBACK=native`, arguments it doesn't support are passed on to `git upload-pack`.
+include!("../main.rs");
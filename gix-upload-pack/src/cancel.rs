@@ -0,0 +1,58 @@
+//! Aborting sessions from other threads
+//!
+//! Set a [`CancellationToken`] with [`ServerOptions::with_cancellation()`](crate::ServerOptions::with_cancellation())
+//! and [cancel](CancellationToken::cancel()) it from any thread to abort the sessions it was set for. Counting,
+//! compressing and streaming the pack stop at the next opportunity, the client is told about it with an error
+//! message, and [`Server::serve()`](crate::Server::serve()) fails with [`Error::Cancelled`].
+
+use crate::error::{Error, Result};
+use crate::services::packet_io::EnhancedPacketWriter;
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// A cheaply clonable flag to abort sessions, shared by all of its clones
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    /// Create a token that isn't cancelled yet
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Abort all sessions using this token, now and in future
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    /// Return `true` if [`cancel()`](Self::cancel()) was called
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// Fail with [`Error::Cancelled`] if the token was cancelled
+    pub fn check(&self) -> Result<()> {
+        if self.is_cancelled() {
+            return Err(Error::Cancelled);
+        }
+        Ok(())
+    }
+
+    /// The flag itself, for the interrupts of `gix` operations
+    pub(crate) fn as_atomic(&self) -> &AtomicBool {
+        &self.0
+    }
+}
+
+/// Tell the client through `writer` that the session was cancelled if `result` says so, and return `result`
+///
+/// The message is sent on the error channel if sideband is established, and as `ERR` packet otherwise.
+pub(crate) fn notify<W: Write, T>(writer: &mut EnhancedPacketWriter<W>, result: Result<T>) -> Result<T> {
+    if let Err(Error::Cancelled) = &result {
+        // The client may be gone already, which doesn't change the outcome
+        writer.send_error(&Error::Cancelled.to_string()).ok();
+        writer.flush().ok();
+    }
+    result
+}
@@ -0,0 +1,257 @@
+//! Typed upload-pack capabilities and their relationships
+//!
+//! This encodes the rules from git's `protocol-capabilities` and `protocol-v2` documentation
+//! about which capabilities exist in which protocol version, which ones require others,
+//! and which ones a client must not request together.
+
+use crate::{
+    error::{Error, Result},
+    types::ProtocolVersion,
+};
+
+/// A capability upload-pack can advertise or a client can request
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Capability {
+    /// `multi_ack`
+    MultiAck,
+    /// `multi_ack_detailed`
+    MultiAckDetailed,
+    /// `no-done`
+    NoDone,
+    /// `thin-pack`
+    ThinPack,
+    /// `side-band`
+    SideBand,
+    /// `side-band-64k`
+    SideBand64k,
+    /// `ofs-delta`
+    OfsDelta,
+    /// `agent=<agent>`
+    Agent,
+    /// `object-format=<hash>`
+    ObjectFormat,
+    /// `symref=<source>:<target>`
+    Symref,
+    /// `shallow`
+    Shallow,
+    /// `deepen-since`
+    DeepenSince,
+    /// `deepen-not`
+    DeepenNot,
+    /// `deepen-relative`
+    DeepenRelative,
+    /// `no-progress`
+    NoProgress,
+    /// `include-tag`
+    IncludeTag,
+    /// `allow-tip-sha1-in-want`
+    AllowTipSha1InWant,
+    /// `allow-reachable-sha1-in-want`
+    AllowReachableSha1InWant,
+    /// `filter`
+    Filter,
+    /// `session-id=<id>`
+    SessionId,
+    /// `wait-for-done` (protocol v2 `fetch` feature)
+    WaitForDone,
+    /// `packfile-uris` (protocol v2 `fetch` feature)
+    PackfileUris,
+    /// `sideband-all` (protocol v2 `fetch` feature)
+    SidebandAll,
+    /// `object-info` (protocol v2 command)
+    ObjectInfo,
+}
+
+impl Capability {
+    /// All known capabilities
+    pub const ALL: &'static [Capability] = &[
+        Capability::MultiAck,
+        Capability::MultiAckDetailed,
+        Capability::NoDone,
+        Capability::ThinPack,
+        Capability::SideBand,
+        Capability::SideBand64k,
+        Capability::OfsDelta,
+        Capability::Agent,
+        Capability::ObjectFormat,
+        Capability::Symref,
+        Capability::Shallow,
+        Capability::DeepenSince,
+        Capability::DeepenNot,
+        Capability::DeepenRelative,
+        Capability::NoProgress,
+        Capability::IncludeTag,
+        Capability::AllowTipSha1InWant,
+        Capability::AllowReachableSha1InWant,
+        Capability::Filter,
+        Capability::SessionId,
+        Capability::WaitForDone,
+        Capability::PackfileUris,
+        Capability::SidebandAll,
+        Capability::ObjectInfo,
+    ];
+
+    /// The name of the capability on the wire, without any `=<value>`
+    pub fn name(&self) -> &'static str {
+        match self {
+            Capability::MultiAck => "multi_ack",
+            Capability::MultiAckDetailed => "multi_ack_detailed",
+            Capability::NoDone => "no-done",
+            Capability::ThinPack => "thin-pack",
+            Capability::SideBand => "side-band",
+            Capability::SideBand64k => "side-band-64k",
+            Capability::OfsDelta => "ofs-delta",
+            Capability::Agent => "agent",
+            Capability::ObjectFormat => "object-format",
+            Capability::Symref => "symref",
+            Capability::Shallow => "shallow",
+            Capability::DeepenSince => "deepen-since",
+            Capability::DeepenNot => "deepen-not",
+            Capability::DeepenRelative => "deepen-relative",
+            Capability::NoProgress => "no-progress",
+            Capability::IncludeTag => "include-tag",
+            Capability::AllowTipSha1InWant => "allow-tip-sha1-in-want",
+            Capability::AllowReachableSha1InWant => "allow-reachable-sha1-in-want",
+            Capability::Filter => "filter",
+            Capability::SessionId => "session-id",
+            Capability::WaitForDone => "wait-for-done",
+            Capability::PackfileUris => "packfile-uris",
+            Capability::SidebandAll => "sideband-all",
+            Capability::ObjectInfo => "object-info",
+        }
+    }
+
+    /// The capabilities advertised in protocol `version`, in the order they are advertised in
+    ///
+    /// This is the capability matrix advertisements are generated from. Each capability exists in the
+    /// protocol versions it's listed for, see [`is_valid_for()`](Self::is_valid_for()). In protocol v2,
+    /// capabilities for which [`is_fetch_feature()`](Self::is_fetch_feature()) is `true` are advertised
+    /// as features of the `fetch` command.
+    pub fn advertised_in(version: ProtocolVersion) -> &'static [Capability] {
+        match version {
+            ProtocolVersion::V0 | ProtocolVersion::V1 => &[
+                Capability::MultiAck,
+                Capability::ThinPack,
+                Capability::SideBand,
+                Capability::SideBand64k,
+                Capability::OfsDelta,
+                Capability::Shallow,
+                Capability::DeepenSince,
+                Capability::DeepenNot,
+                Capability::DeepenRelative,
+                Capability::NoProgress,
+                Capability::IncludeTag,
+                Capability::MultiAckDetailed,
+                Capability::NoDone,
+                Capability::Filter,
+                Capability::AllowTipSha1InWant,
+                Capability::AllowReachableSha1InWant,
+                Capability::Symref,
+                Capability::ObjectFormat,
+                Capability::Agent,
+                Capability::SessionId,
+            ],
+            ProtocolVersion::V2 => &[
+                Capability::Agent,
+                Capability::ObjectFormat,
+                Capability::Shallow,
+                Capability::Filter,
+                Capability::SidebandAll,
+                Capability::PackfileUris,
+                Capability::WaitForDone,
+                Capability::ObjectInfo,
+                Capability::SessionId,
+            ],
+        }
+    }
+
+    /// Return `true` if this capability is advertised as feature of the `fetch` command in protocol v2
+    pub fn is_fetch_feature(&self) -> bool {
+        matches!(
+            self,
+            Capability::Shallow
+                | Capability::Filter
+                | Capability::SidebandAll
+                | Capability::PackfileUris
+                | Capability::WaitForDone
+        )
+    }
+
+    /// Parse a capability as it appears on the wire, ignoring any `=<value>`
+    pub fn from_wire(capability: &str) -> Option<Self> {
+        let name = capability.split_once('=').map_or(capability, |(name, _value)| name);
+        Self::ALL.iter().copied().find(|cap| cap.name() == name)
+    }
+
+    /// The capabilities that must be advertised along with this one for it to be meaningful
+    ///
+    /// `no-done` only changes `multi_ack_detailed` negotiations, and the `deepen-*` capabilities refine
+    /// shallow fetches. Advertisements leave out capabilities without their prerequisites, while clients
+    /// may request them alone, like git asking for `deepen-since` without `shallow`.
+    pub fn dependencies(&self) -> &'static [Capability] {
+        match self {
+            Capability::NoDone => &[Capability::MultiAckDetailed],
+            Capability::DeepenSince | Capability::DeepenNot | Capability::DeepenRelative => &[Capability::Shallow],
+            _ => &[],
+        }
+    }
+
+    /// The capabilities a client must not request together with this one
+    ///
+    /// Servers may advertise both sides of a conflict, like `side-band` and `side-band-64k`.
+    pub fn conflicts(&self) -> &'static [Capability] {
+        match self {
+            Capability::SideBand => &[Capability::SideBand64k],
+            Capability::SideBand64k => &[Capability::SideBand],
+            _ => &[],
+        }
+    }
+
+    /// Return `true` if this capability exists in the given protocol `version`
+    pub fn is_valid_for(&self, version: ProtocolVersion) -> bool {
+        match self {
+            Capability::Agent
+            | Capability::ObjectFormat
+            | Capability::Shallow
+            | Capability::Filter
+            | Capability::SessionId => true,
+            Capability::WaitForDone | Capability::PackfileUris | Capability::SidebandAll | Capability::ObjectInfo => {
+                version == ProtocolVersion::V2
+            }
+            _ => version != ProtocolVersion::V2,
+        }
+    }
+
+    /// Validate a set of `capabilities` for use with protocol `version`
+    ///
+    /// Every capability must exist in `version`. If `requested` is `true`, the set is what a client
+    /// asked for and must not contain conflicts, otherwise it's an advertisement and every capability
+    /// must have its dependencies in the set.
+    pub fn validate_set(capabilities: &[Capability], version: ProtocolVersion, requested: bool) -> Result<()> {
+        for cap in capabilities {
+            if !cap.is_valid_for(version) {
+                return Err(Error::CapabilityMismatch {
+                    message: format!("'{}' is not a protocol {} capability", cap.name(), version as usize),
+                });
+            }
+            if requested {
+                if let Some(conflict) = cap.conflicts().iter().find(|other| capabilities.contains(other)) {
+                    return Err(Error::CapabilityMismatch {
+                        message: format!("'{}' cannot be combined with '{}'", cap.name(), conflict.name()),
+                    });
+                }
+            } else if let Some(missing) = cap.dependencies().iter().find(|dep| !capabilities.contains(dep)) {
+                return Err(Error::CapabilityMismatch {
+                    message: format!("'{}' requires '{}'", cap.name(), missing.name()),
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for Capability {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
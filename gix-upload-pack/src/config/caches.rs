@@ -0,0 +1,107 @@
+//! Sizes of the caches for decoded objects of each session
+
+/// How much memory each session may use to cache decoded objects
+///
+/// The delta cache keeps delta bases decoded from packs, so the objects of a delta chain don't have to decode the
+/// chain from its start again, like `core.deltaBaseCacheLimit` does for git. The object cache keeps fully decoded
+/// objects that are read more than once, like the commits and trees of the revision walk and of counting.
+///
+/// Both are set up anew for each session served by a [`Server`](crate::Server), dropping whatever the previous
+/// session cached, and once more for every thread counting objects. The memory used at most is thus the sum of both
+/// times the number of concurrent sessions and counting threads. The sizes derived from
+/// [`ServerOptions::max_concurrent_sessions`](crate::ServerOptions::max_concurrent_sessions) by default keep that
+/// within [`DEFAULT_BUDGET_BYTES`](Self::DEFAULT_BUDGET_BYTES) for single-threaded counting.
+///
+/// The caches are separate from the `BufferPool` of `gix-receive-pack`, which pools the buffers incoming packs are
+/// read into and is bounded by its own memory limit, so processes serving both directions need to budget for both.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ObjectCaches {
+    /// The bytes of decoded delta bases to keep, or 0 to keep none
+    pub delta_cache_bytes: usize,
+    /// The bytes of decoded objects to keep, or 0 to keep none
+    pub object_cache_bytes: usize,
+}
+
+impl ObjectCaches {
+    /// The memory the caches of all concurrent sessions may use together by default
+    pub const DEFAULT_BUDGET_BYTES: usize = 512 * 1024 * 1024;
+    /// The largest delta cache of a session by default, git's default for `core.deltaBaseCacheLimit`
+    pub const MAX_DELTA_CACHE_BYTES: usize = 96 * 1024 * 1024;
+    /// The largest object cache of a session by default
+    pub const MAX_OBJECT_CACHE_BYTES: usize = 32 * 1024 * 1024;
+    /// Cache nothing, decoding every object from scratch each time it's read
+    pub const NONE: Self = ObjectCaches {
+        delta_cache_bytes: 0,
+        object_cache_bytes: 0,
+    };
+
+    /// Split [`DEFAULT_BUDGET_BYTES`](Self::DEFAULT_BUDGET_BYTES) among up to `max_sessions` concurrent sessions
+    pub fn for_sessions(max_sessions: usize) -> Self {
+        Self::within_budget(Self::DEFAULT_BUDGET_BYTES, max_sessions)
+    }
+
+    /// Split `budget_bytes` evenly among up to `max_sessions` concurrent sessions
+    ///
+    /// Three quarters of each share go to the delta cache and the rest to the object cache, limited to
+    /// [`MAX_DELTA_CACHE_BYTES`](Self::MAX_DELTA_CACHE_BYTES) and
+    /// [`MAX_OBJECT_CACHE_BYTES`](Self::MAX_OBJECT_CACHE_BYTES) as larger caches rarely pay off for a single fetch.
+    pub fn within_budget(budget_bytes: usize, max_sessions: usize) -> Self {
+        let share = budget_bytes / max_sessions.max(1);
+        let delta_cache_bytes = (share / 4 * 3).min(Self::MAX_DELTA_CACHE_BYTES);
+        ObjectCaches {
+            delta_cache_bytes,
+            object_cache_bytes: (share - share / 4 * 3).min(Self::MAX_OBJECT_CACHE_BYTES),
+        }
+    }
+
+    /// Keep up to `bytes` of decoded delta bases
+    pub fn with_delta_cache(mut self, bytes: usize) -> Self {
+        self.delta_cache_bytes = bytes;
+        self
+    }
+
+    /// Keep up to `bytes` of decoded objects
+    pub fn with_object_cache(mut self, bytes: usize) -> Self {
+        self.object_cache_bytes = bytes;
+        self
+    }
+
+    /// Replace the caches of `repository` with new, empty ones of our sizes
+    pub(crate) fn apply(&self, repository: &mut gix::Repository) {
+        match self.delta_cache_bytes {
+            0 => repository.objects.unset_pack_cache(),
+            bytes => repository
+                .objects
+                .set_pack_cache(move || -> Box<gix_odb::cache::PackCache> {
+                    Box::new(gix_pack::cache::lru::MemoryCappedHashmap::new(bytes))
+                }),
+        }
+        repository.object_cache_size(self.object_cache_bytes);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn budget_is_split_among_sessions_up_to_the_maximum_sizes() {
+        const MIB: usize = 1024 * 1024;
+        assert_eq!(
+            ObjectCaches::within_budget(64 * MIB, 4),
+            ObjectCaches {
+                delta_cache_bytes: 12 * MIB,
+                object_cache_bytes: 4 * MIB,
+            }
+        );
+        assert_eq!(
+            ObjectCaches::for_sessions(1),
+            ObjectCaches {
+                delta_cache_bytes: ObjectCaches::MAX_DELTA_CACHE_BYTES,
+                object_cache_bytes: ObjectCaches::MAX_OBJECT_CACHE_BYTES,
+            }
+        );
+        assert_eq!(ObjectCaches::for_sessions(0), ObjectCaches::for_sessions(1));
+        assert_eq!(ObjectCaches::within_budget(0, 8), ObjectCaches::NONE);
+    }
+}
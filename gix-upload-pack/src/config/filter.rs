@@ -0,0 +1,198 @@
+//! Semantic parsing of object filter specifications for the filter allowlist
+
+use crate::{Error, Result};
+use bstr::BString;
+
+/// A parsed object filter specification as sent by `filter <spec>`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FilterSpec {
+    /// `blob:none`
+    BlobNone,
+    /// `blob:limit=<n>[kmg]`, the limit in bytes
+    BlobLimit(u64),
+    /// `tree:<depth>`
+    TreeDepth(u64),
+    /// `sparse:oid=<blob-ish>`, an empty value in an allowlist entry permits any blob
+    SparseOid(BString),
+    /// `object:type=<type>`
+    ObjectType(BString),
+    /// `combine:<spec>+<spec>...`
+    Combine(Vec<FilterSpec>),
+}
+
+impl FilterSpec {
+    /// Parse a filter specification like `blob:limit=1k` or `combine:blob:none+tree:2`
+    pub fn parse(spec: &str) -> Result<Self> {
+        let invalid = |message: String| Error::InvalidFilter { message };
+        if spec == "blob:none" {
+            Ok(FilterSpec::BlobNone)
+        } else if let Some(limit) = spec.strip_prefix("blob:limit=") {
+            parse_size(limit).map(FilterSpec::BlobLimit)
+        } else if let Some(depth) = spec.strip_prefix("tree:") {
+            depth
+                .parse()
+                .map(FilterSpec::TreeDepth)
+                .map_err(|_| invalid(format!("invalid tree depth in '{spec}'")))
+        } else if let Some(oid) = spec.strip_prefix("sparse:oid=") {
+            Ok(FilterSpec::SparseOid(oid.into()))
+        } else if let Some(kind) = spec.strip_prefix("object:type=") {
+            match kind {
+                "blob" | "tree" | "commit" | "tag" => Ok(FilterSpec::ObjectType(kind.into())),
+                _ => Err(invalid(format!("invalid object type in '{spec}'"))),
+            }
+        } else if let Some(parts) = spec.strip_prefix("combine:") {
+            parts
+                .split('+')
+                .map(|part| percent_decode(part).and_then(|part| FilterSpec::parse(&part)))
+                .collect::<Result<Vec<_>>>()
+                .map(FilterSpec::Combine)
+        } else {
+            Err(invalid(format!("unsupported filter specification '{spec}'")))
+        }
+    }
+
+    /// Return `true` if the filter leaves out all trees and blobs, like `tree:0`, so packs only contain commits and
+    /// tags besides the objects the client asked for explicitly
+    pub fn omits_trees(&self) -> bool {
+        match self {
+            FilterSpec::TreeDepth(depth) => *depth == 0,
+            FilterSpec::Combine(parts) => parts.iter().any(FilterSpec::omits_trees),
+            _ => false,
+        }
+    }
+
+    /// Return `true` if this allowlist entry permits the `requested` filter
+    ///
+    /// Limits in allowlist entries are upper bounds, so `blob:limit=1k` permits `blob:limit=512`
+    /// and `blob:none`, but not `blob:limit=2k`. A `combine:` request is permitted if each of
+    /// its parts is, which callers checking a whole allowlist should handle with [`allowed_by()`].
+    pub fn permits(&self, requested: &FilterSpec) -> bool {
+        match (self, requested) {
+            (FilterSpec::BlobNone | FilterSpec::BlobLimit(_), FilterSpec::BlobNone) => true,
+            (FilterSpec::BlobLimit(max), FilterSpec::BlobLimit(limit)) => limit <= max,
+            (FilterSpec::TreeDepth(max), FilterSpec::TreeDepth(depth)) => depth <= max,
+            (FilterSpec::SparseOid(allowed), FilterSpec::SparseOid(oid)) => allowed.is_empty() || allowed == oid,
+            (FilterSpec::ObjectType(allowed), FilterSpec::ObjectType(kind)) => allowed == kind,
+            (FilterSpec::Combine(allowed), FilterSpec::Combine(parts)) => {
+                parts.iter().all(|part| allowed.iter().any(|entry| entry.permits(part)))
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Return `true` if `requested` is permitted by any entry of `allowlist`, part by part for `combine:` filters
+pub fn allowed_by(allowlist: &[FilterSpec], requested: &FilterSpec) -> bool {
+    match requested {
+        FilterSpec::Combine(parts) if !parts.is_empty() => parts.iter().all(|part| allowed_by(allowlist, part)),
+        _ => allowlist.iter().any(|entry| entry.permits(requested)),
+    }
+}
+
+/// Parse a size with an optional `k`, `m` or `g` suffix (case-insensitive, powers of 1024) like git does
+pub fn parse_size(value: &str) -> Result<u64> {
+    let invalid = || Error::InvalidFilter {
+        message: format!("invalid size '{value}'"),
+    };
+    let (digits, multiplier) = match value.as_bytes().last() {
+        Some(b'k' | b'K') => (&value[..value.len() - 1], 1024),
+        Some(b'm' | b'M') => (&value[..value.len() - 1], 1024 * 1024),
+        Some(b'g' | b'G') => (&value[..value.len() - 1], 1024 * 1024 * 1024),
+        _ => (value, 1),
+    };
+    let number: u64 = digits.parse().map_err(|_| invalid())?;
+    number.checked_mul(multiplier).ok_or_else(invalid)
+}
+
+fn percent_decode(part: &str) -> Result<String> {
+    let bytes = part.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut pos = 0;
+    while pos < bytes.len() {
+        if bytes[pos] == b'%' {
+            let hex = part
+                .get(pos + 1..pos + 3)
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                .ok_or_else(|| Error::InvalidFilter {
+                    message: format!("invalid percent-encoding in '{part}'"),
+                })?;
+            out.push(hex);
+            pos += 3;
+        } else {
+            out.push(bytes[pos]);
+            pos += 1;
+        }
+    }
+    String::from_utf8(out).map_err(|_| Error::InvalidFilter {
+        message: format!("filter specification '{part}' is not valid UTF-8"),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(spec: &str) -> FilterSpec {
+        FilterSpec::parse(spec).unwrap()
+    }
+
+    #[test]
+    fn size_units() {
+        assert_eq!(parse_size("0").unwrap(), 0);
+        assert_eq!(parse_size("1000").unwrap(), 1000);
+        assert_eq!(parse_size("1k").unwrap(), 1024);
+        assert_eq!(parse_size("2M").unwrap(), 2 * 1024 * 1024);
+        assert_eq!(parse_size("1g").unwrap(), 1024 * 1024 * 1024);
+        assert!(parse_size("").is_err());
+        assert!(parse_size("k").is_err());
+        assert!(parse_size("1t").is_err());
+        assert!(parse_size("-1").is_err());
+        assert!(parse_size("100000000000000G").is_err(), "overflow is rejected");
+    }
+
+    #[test]
+    fn blob_limit_is_an_upper_bound() {
+        let allowed = parse("blob:limit=1k");
+        assert!(allowed.permits(&parse("blob:limit=1024")), "exactly at the limit");
+        assert!(allowed.permits(&parse("blob:limit=1k")));
+        assert!(allowed.permits(&parse("blob:limit=0")));
+        assert!(allowed.permits(&parse("blob:none")), "blob:none is blob:limit=0");
+        assert!(!allowed.permits(&parse("blob:limit=1025")), "one above the limit");
+        assert!(!allowed.permits(&parse("blob:limit=1000000G")));
+        assert!(!parse("blob:none").permits(&parse("blob:limit=0")));
+    }
+
+    #[test]
+    fn tree_sparse_and_object_type() {
+        assert!(parse("tree:2").permits(&parse("tree:0")));
+        assert!(parse("tree:2").permits(&parse("tree:2")));
+        assert!(!parse("tree:2").permits(&parse("tree:3")));
+        assert!(parse("sparse:oid=").permits(&parse("sparse:oid=main:.sparse")));
+        assert!(!parse("sparse:oid=a").permits(&parse("sparse:oid=b")));
+        assert!(parse("object:type=blob").permits(&parse("object:type=blob")));
+        assert!(!parse("object:type=blob").permits(&parse("object:type=tree")));
+        assert!(FilterSpec::parse("object:type=other").is_err());
+        assert!(FilterSpec::parse("tree:x").is_err());
+        assert!(FilterSpec::parse("unknown").is_err());
+    }
+
+    #[test]
+    fn combine_requires_every_part() {
+        let allowlist = vec![parse("blob:limit=1k"), parse("tree:0")];
+        assert_eq!(
+            parse("combine:blob:none+tree:0"),
+            FilterSpec::Combine(vec![FilterSpec::BlobNone, FilterSpec::TreeDepth(0)])
+        );
+        assert!(allowed_by(&allowlist, &parse("combine:blob%3Anone+tree:0")));
+        assert!(!allowed_by(&allowlist, &parse("combine:blob:none+tree:1")));
+        assert!(!allowed_by(&allowlist, &parse("tree:1")));
+    }
+
+    #[test]
+    fn only_tree_depth_zero_omits_trees() {
+        assert!(parse("tree:0").omits_trees());
+        assert!(parse("combine:blob:none+tree:0").omits_trees());
+        assert!(!parse("tree:1").omits_trees());
+        assert!(!parse("blob:none").omits_trees());
+    }
+}
@@ -0,0 +1,282 @@
+//! Typed git configuration keys honored by upload-pack
+//!
+//! Every key read from the served repository's configuration is listed here with its type and
+//! default, so it's discoverable which knobs are honored. Reading a key through its [`Key`] also
+//! fails loudly on values of the wrong type instead of silently ignoring them.
+
+use crate::{Error, Result};
+use bstr::BString;
+use std::marker::PhantomData;
+
+/// A type a configuration value can be read as
+pub trait Value: Sized {
+    /// Read the value of `name` from `config`, or `None` if it isn't set
+    fn read(config: &gix::config::Snapshot<'_>, name: &str) -> Option<std::result::Result<Self, String>>;
+}
+
+impl Value for bool {
+    fn read(config: &gix::config::Snapshot<'_>, name: &str) -> Option<std::result::Result<Self, String>> {
+        config
+            .try_boolean(name)
+            .map(|value| value.map_err(|err| err.to_string()))
+    }
+}
+
+impl Value for i64 {
+    fn read(config: &gix::config::Snapshot<'_>, name: &str) -> Option<std::result::Result<Self, String>> {
+        config
+            .try_integer(name)
+            .map(|value| value.map_err(|err| err.to_string()))
+    }
+}
+
+impl Value for BString {
+    fn read(config: &gix::config::Snapshot<'_>, name: &str) -> Option<std::result::Result<Self, String>> {
+        config.string(name).map(|value| Ok(value.into_owned()))
+    }
+}
+
+/// A configuration key with a value of type `T`
+#[derive(Debug, Clone, Copy)]
+pub struct Key<T> {
+    name: &'static str,
+    default: &'static str,
+    _value: PhantomData<fn() -> T>,
+}
+
+impl<T> Key<T> {
+    /// Create a key called `name`, whose value is `default` when unset
+    pub const fn new(name: &'static str, default: &'static str) -> Self {
+        Key {
+            name,
+            default,
+            _value: PhantomData,
+        }
+    }
+
+    /// The full name of the key, like `uploadpack.allowFilter`
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// A description of the value used when the key is unset
+    pub fn default_value(&self) -> &'static str {
+        self.default
+    }
+}
+
+impl<T: Value> Key<T> {
+    /// Read the value of this key from `config`, or `None` if it isn't set
+    pub fn get(&self, config: &gix::config::Snapshot<'_>) -> Result<Option<T>> {
+        T::read(config, self.name).transpose().map_err(|err| Error::Config {
+            message: format!("invalid value for {}: {err}", self.name),
+        })
+    }
+}
+
+impl Key<BString> {
+    /// Read all values of this multi-valued key from `config`, in order
+    pub fn get_all(&self, config: &gix::config::Snapshot<'_>) -> Vec<BString> {
+        config
+            .strings(self.name)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|value| value.into_owned())
+            .collect()
+    }
+
+    /// Read the value of this key like [`get()`](Self::get()), but ignore the configuration files of the repository
+    ///
+    /// This is for keys naming programs to run, which git only honors from configuration the served repository
+    /// can't change, like system and user configuration, the environment and the command line.
+    pub fn get_protected(&self, config: &gix::config::Snapshot<'_>) -> Option<BString> {
+        config
+            .plumbing()
+            .string_filter(self.name, |meta| {
+                !matches!(meta.source, gix::config::Source::Local | gix::config::Source::Worktree)
+            })
+            .map(|value| value.into_owned())
+    }
+}
+
+/// Keys in the `uploadpack` section
+pub mod upload_pack {
+    use super::Key;
+    use bstr::BString;
+
+    /// Allow fetching any object by id, reachable or not
+    pub const ALLOW_ANY_SHA1_IN_WANT: Key<bool> = Key::new("uploadpack.allowAnySHA1InWant", "false");
+    /// Allow fetching objects reachable from any ref by id
+    pub const ALLOW_REACHABLE_SHA1_IN_WANT: Key<bool> = Key::new("uploadpack.allowReachableSHA1InWant", "false");
+    /// Allow fetching hidden ref tips by id
+    pub const ALLOW_TIP_SHA1_IN_WANT: Key<bool> = Key::new("uploadpack.allowTipSHA1InWant", "false");
+    /// Advertise and accept the `filter` capability
+    pub const ALLOW_FILTER: Key<bool> = Key::new("uploadpack.allowFilter", "true");
+    /// Seconds between keep-alive packets while the pack is prepared, `0` disables them
+    pub const KEEP_ALIVE: Key<i64> = Key::new("uploadpack.keepAlive", "5");
+    /// Bytes per second below which a client sending its request is considered stalled, `0` disables the check
+    pub const LOW_SPEED_LIMIT: Key<i64> = Key::new("uploadpack.lowSpeedLimit", "0");
+    /// Seconds the client may stay below `uploadpack.lowSpeedLimit` before the request is aborted
+    pub const LOW_SPEED_TIME: Key<i64> = Key::new("uploadpack.lowSpeedTime", "0");
+    /// Whether wants with missing objects fail the request (`error`) or are left out of the pack (`skip`)
+    pub const MISSING_OBJECTS: Key<BString> = Key::new("uploadpack.missingObjects", "error");
+    /// A program to run instead of `git pack-objects`, honored from protected configuration only
+    pub const PACK_OBJECTS_HOOK: Key<BString> = Key::new("uploadpack.packObjectsHook", "unset");
+    /// Ref patterns hidden from upload-pack only, multi-valued
+    pub const HIDE_REFS: Key<BString> = Key::new("uploadpack.hideRefs", "none");
+    /// Blobs clients may download from pre-built packs, `<object-hash> <pack-hash> <uri>`, multi-valued
+    pub const BLOB_PACKFILE_URI: Key<BString> = Key::new("uploadpack.blobPackfileUri", "none");
+    /// Verify generated packs before sending them
+    pub const VERIFY_PACK: Key<bool> = Key::new("uploadpack.verifyPack", "false");
+    /// Never write to the filesystem while serving, refusing options that would
+    pub const READ_ONLY: Key<bool> = Key::new("uploadpack.readOnly", "false");
+}
+
+/// Keys in the `transfer` section
+pub mod transfer {
+    use super::Key;
+    use bstr::BString;
+
+    /// Ref patterns hidden from all transfer commands, multi-valued
+    pub const HIDE_REFS: Key<BString> = Key::new("transfer.hideRefs", "none");
+    /// Advertise the protocol v2 `object-info` command
+    pub const ADVERTISE_OBJECT_INFO: Key<bool> = Key::new("transfer.advertiseObjectInfo", "false");
+}
+
+/// Keys in the `core` section
+pub mod core {
+    use super::Key;
+
+    /// Bytes of decoded delta bases each session keeps, see [`ObjectCaches`](crate::config::ObjectCaches)
+    pub const DELTA_BASE_CACHE_LIMIT: Key<i64> =
+        Key::new("core.deltaBaseCacheLimit", "derived from concurrent sessions");
+}
+
+/// Keys in the `pack` section
+pub mod pack {
+    use super::Key;
+
+    /// Threads used for delta compression, clamped to 1..=8
+    pub const THREADS: Key<i64> = Key::new("pack.threads", "available parallelism");
+    /// Objects considered as delta base, clamped to 10..=250
+    pub const WINDOW: Key<i64> = Key::new("pack.window", "50");
+    /// Enumerate objects with the reachability bitmap of a pack if there is one
+    pub const USE_BITMAPS: Key<bool> = Key::new("pack.useBitmaps", "true");
+    /// Copy the leading objects of a pack verbatim into packs that contain them
+    pub const ALLOW_PACK_REUSE: Key<bool> = Key::new("pack.allowPackReuse", "true");
+}
+
+/// Keys in the `serve` section of the served repository, see [`RepositoryOverrides`](super::RepositoryOverrides)
+pub mod serve {
+    use super::Key;
+    use bstr::BString;
+
+    /// Set to `false` to refuse serving the repository
+    pub const UPLOAD_PACK: Key<bool> = Key::new("serve.uploadPack", "true");
+    /// Additional hidden ref patterns, multi-valued
+    pub const HIDE_REFS: Key<BString> = Key::new("serve.hideRefs", "none");
+    /// Maximum pack size in bytes
+    pub const MAX_PACK_SIZE: Key<i64> = Key::new("serve.maxPackSize", "server limit");
+    /// Client timeout in seconds
+    pub const TIMEOUT: Key<i64> = Key::new("serve.timeout", "server limit");
+    /// Set to `false` to disable filters
+    pub const ALLOW_FILTER: Key<bool> = Key::new("serve.allowFilter", "true");
+    /// Set to `false` to disable shallow clones
+    pub const ALLOW_SHALLOW: Key<bool> = Key::new("serve.allowShallow", "true");
+    /// Maximum shallow depth
+    pub const MAX_SHALLOW_DEPTH: Key<i64> = Key::new("serve.maxShallowDepth", "server limit");
+}
+
+/// The names of all keys honored by upload-pack
+pub const ALL: &[&str] = &[
+    upload_pack::ALLOW_ANY_SHA1_IN_WANT.name,
+    upload_pack::ALLOW_REACHABLE_SHA1_IN_WANT.name,
+    upload_pack::ALLOW_TIP_SHA1_IN_WANT.name,
+    upload_pack::ALLOW_FILTER.name,
+    upload_pack::KEEP_ALIVE.name,
+    upload_pack::LOW_SPEED_LIMIT.name,
+    upload_pack::LOW_SPEED_TIME.name,
+    upload_pack::MISSING_OBJECTS.name,
+    upload_pack::PACK_OBJECTS_HOOK.name,
+    upload_pack::HIDE_REFS.name,
+    upload_pack::BLOB_PACKFILE_URI.name,
+    upload_pack::VERIFY_PACK.name,
+    upload_pack::READ_ONLY.name,
+    transfer::HIDE_REFS.name,
+    transfer::ADVERTISE_OBJECT_INFO.name,
+    core::DELTA_BASE_CACHE_LIMIT.name,
+    pack::THREADS.name,
+    pack::WINDOW.name,
+    pack::USE_BITMAPS.name,
+    pack::ALLOW_PACK_REUSE.name,
+    serve::UPLOAD_PACK.name,
+    serve::HIDE_REFS.name,
+    serve::MAX_PACK_SIZE.name,
+    serve::TIMEOUT.name,
+    serve::ALLOW_FILTER.name,
+    serve::ALLOW_SHALLOW.name,
+    serve::MAX_SHALLOW_DEPTH.name,
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn repository_with_config(lines: &[&str]) -> (tempfile::TempDir, gix::Repository) {
+        let dir = tempfile::tempdir().unwrap();
+        let status = std::process::Command::new("git")
+            .args(["init", "--bare", "--quiet"])
+            .arg(dir.path())
+            .status()
+            .unwrap();
+        assert!(status.success());
+        let config = dir.path().join("config");
+        let mut content = std::fs::read_to_string(&config).unwrap();
+        for line in lines {
+            content.push_str(line);
+            content.push('\n');
+        }
+        std::fs::write(&config, content).unwrap();
+        let repo = gix::open(dir.path()).unwrap();
+        (dir, repo)
+    }
+
+    #[test]
+    fn names_are_unique_and_sectioned() {
+        let mut names = ALL.to_vec();
+        names.sort_unstable();
+        names.dedup();
+        assert_eq!(names.len(), ALL.len());
+        for name in ALL {
+            let (section, key) = name.split_once('.').expect("section.key");
+            assert!(
+                ["uploadpack", "transfer", "core", "pack", "serve"].contains(&section),
+                "{name}"
+            );
+            assert!(!key.is_empty());
+        }
+    }
+
+    #[test]
+    fn typed_access() {
+        let (_dir, repo) = repository_with_config(&[
+            "[uploadpack]",
+            "\tallowFilter = no",
+            "\tkeepAlive = 1k",
+            "\thideRefs = refs/a",
+            "\thideRefs = refs/b",
+            "[pack]",
+            "\twindow = many",
+        ]);
+        let config = repo.config_snapshot();
+        assert_eq!(upload_pack::ALLOW_FILTER.get(&config).unwrap(), Some(false));
+        assert_eq!(upload_pack::KEEP_ALIVE.get(&config).unwrap(), Some(1024));
+        assert_eq!(upload_pack::ALLOW_TIP_SHA1_IN_WANT.get(&config).unwrap(), None);
+        assert_eq!(
+            upload_pack::HIDE_REFS.get_all(&config),
+            vec![BString::from("refs/a"), BString::from("refs/b")]
+        );
+        let err = pack::WINDOW.get(&config).unwrap_err();
+        assert!(err.to_string().contains("pack.window"), "{err}");
+    }
+}
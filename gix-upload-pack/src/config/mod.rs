@@ -5,6 +5,17 @@ use bstr::{BString, ByteSlice};
 use std::path::PathBuf;
 use std::time::Duration;
 
+mod caches;
+pub use caches::ObjectCaches;
+
+pub mod filter;
+pub use filter::FilterSpec;
+
+pub mod keys;
+
+mod overrides;
+pub use overrides::RepositoryOverrides;
+
 /// Configuration options for the upload-pack server
 #[derive(Debug, Clone)]
 pub struct ServerOptions {
@@ -14,9 +25,28 @@ pub struct ServerOptions {
     /// Whether this is a stateless RPC connection
     pub stateless_rpc: bool,
 
-    /// Timeout for client operations
+    /// Serve this protocol version instead of the one the client asked for, see
+    /// [`ProtocolDetector`](crate::server::protocol_detection::ProtocolDetector)
+    pub protocol: Option<crate::ProtocolVersion>,
+
+    /// The address of the client of this connection, if known, for logs and per-client limits
+    pub remote_addr: Option<std::net::SocketAddr>,
+
+    /// The authenticated identity of the client, if known, for audit records
+    pub principal: Option<String>,
+
+    /// Where to record denied requests, see [`Auditor`](gix_serve_core::audit::Auditor)
+    pub auditor: gix_serve_core::audit::Auditor,
+
+    /// Where to report counters and timings of sessions, see [`Meter`](gix_serve_core::metrics::Meter)
+    pub metrics: gix_serve_core::metrics::Meter,
+
+    /// End sessions once the client was inactive for this long, see [`IdleTimeout`](crate::services::IdleTimeout)
     pub timeout: Option<Duration>,
 
+    /// Aborts the sessions of this server once cancelled, see [`cancel`](crate::cancel)
+    pub cancellation: crate::CancellationToken,
+
     /// Whether to enable strict mode
     pub strict: bool,
 
@@ -26,6 +56,20 @@ pub struct ServerOptions {
     /// Maximum pack size to generate (in bytes)
     pub max_pack_size: Option<u64>,
 
+    /// Verify generated packs before sending them, see [`verify_pack()`](crate::services::pack::verify_pack())
+    pub verify_pack: bool,
+
+    /// The number of pack entries generated at a time before sending them, which bounds the memory a pack takes
+    ///
+    /// Packs are only held in memory as a whole if they are verified before sending them.
+    pub max_in_flight_entries: usize,
+
+    /// The number of sessions expected to be served at once, which the default [`ObjectCaches`] are sized for
+    pub max_concurrent_sessions: usize,
+
+    /// The caches for decoded objects of each session, or `None` to derive them from `max_concurrent_sessions`
+    pub object_caches: Option<ObjectCaches>,
+
     /// Enable keep-alive packets
     pub keepalive: Option<Duration>,
 
@@ -41,9 +85,31 @@ pub struct ServerOptions {
     /// Post-upload hook path
     pub post_upload_pack_hook: Option<PathBuf>,
 
-    /// Hidden refs patterns
+    /// The `hideRefs` patterns hiding refs from clients, in order, see [`HiddenRefs`](gix_serve_core::visibility::HiddenRefs)
     pub hidden_refs: Vec<BString>,
 
+    /// Serve only the refs of this namespace, like `a` or `a/b` in `GIT_NAMESPACE`, as if they were all refs
+    ///
+    /// The refs below `refs/namespaces/<namespace>/` are advertised without that prefix, and refs outside of it
+    /// aren't served at all. `^` patterns of the `hideRefs` still match the full names.
+    pub namespace: Option<BString>,
+
+    /// Serve these refs instead of the repository's, see [`RefSnapshot`](crate::services::RefSnapshot)
+    pub ref_snapshot: Option<std::sync::Arc<crate::services::RefSnapshot>>,
+
+    /// Decide per ref what the client may see and fetch, see [`RefAuthorizer`](crate::services::RefAuthorizer)
+    pub ref_authorization: Option<crate::services::RefAuthorization>,
+
+    /// Stop peeling annotated tags for advertisements after this long, see
+    /// [`ReferenceManager::with_peel_budget()`](crate::services::ReferenceManager::with_peel_budget())
+    pub peel_budget: Option<Duration>,
+
+    /// Leave objects out of generated packs, see [`ObjectFirewall`](crate::services::ObjectFirewall)
+    pub object_exclusion: Option<crate::services::ObjectExclusion>,
+
+    /// Abort requests from clients sending slower than this, see [`StallDetection`](crate::services::StallDetection)
+    pub stall_detection: Option<crate::services::StallDetection>,
+
     /// Allowed filter specs
     pub allowed_filters: Vec<BString>,
 
@@ -65,12 +131,18 @@ pub struct ServerOptions {
     /// Allow tip SHA1 in want
     pub allow_tip_sha1_in_want: bool,
 
+    /// What to do with wants whose objects are missing
+    pub missing_objects: MissingObjectPolicy,
+
     /// Allow deepen-relative
     pub allow_deepen_relative: bool,
 
     /// Allow packfile URIs (protocol v2)
     pub allow_packfile_uris: bool,
 
+    /// Blobs clients allowing packfile URIs download from pre-built packs, see [`PackfileUri`](crate::services::PackfileUri)
+    pub packfile_uris: Vec<crate::services::PackfileUri>,
+
     /// Enable session ID support
     pub enable_session_id: bool,
 
@@ -80,6 +152,9 @@ pub struct ServerOptions {
     /// Enable object-info command (protocol v2)
     pub enable_object_info: bool,
 
+    /// The commands served with protocol v2, see [`Commands`](crate::protocol::commands::Commands)
+    pub v2_commands: crate::protocol::commands::Commands,
+
     /// Allow blob filtering
     pub allow_blob_filter: bool,
 
@@ -98,14 +173,58 @@ pub struct ServerOptions {
     /// Custom user agent string
     pub user_agent: Option<BString>,
 
-    /// Supported hash algorithms
+    /// Object formats clients may request, which also have to match the one of the repository
     pub hash_algorithms: Vec<gix_hash::Kind>,
 
-    /// Enable tracing/logging
+    /// Enter `tracing` spans for the phases of each session, if the `tracing` feature is enabled
+    ///
+    /// Use [`Logger::tracing()`](crate::log::Logger::tracing()) to record diagnostics as events within them.
     pub enable_tracing: bool,
 
+    /// Never write to the filesystem, for repositories on read-only mounts, see [`validate()`](Self::validate())
+    pub read_only: bool,
+
+    /// Where to send diagnostics, silent by default
+    pub logger: crate::log::Logger,
+
     /// Custom configuration values
     pub custom_config: std::collections::HashMap<String, String>,
+
+    /// Merge the served repository's `serve.*` overrides at session start
+    pub repository_overrides: bool,
+
+    /// Abbreviate object ids in error messages according to `core.abbrev`, instead of showing them in full like git
+    pub abbreviate_object_ids: bool,
+
+    /// Check the packets sent to clients against the protocol grammar, by default in debug builds only, see
+    /// [`GrammarWriter`](crate::services::GrammarWriter)
+    pub check_packet_grammar: bool,
+}
+
+/// What to do with wants that are missing in the repository, or lead to missing objects
+///
+/// See [`find_missing()`](crate::services::pack::find_missing()) for the objects that are checked.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MissingObjectPolicy {
+    /// Fail the request with an error listing all missing objects, like git does
+    #[default]
+    Error,
+    /// Leave affected wants out of the pack and send the objects of the remaining ones
+    Skip,
+}
+
+impl std::str::FromStr for MissingObjectPolicy {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value {
+            "error" => Ok(Self::Error),
+            "skip" => Ok(Self::Skip),
+            _ => Err(Error::Config {
+                message: format!("invalid value for missing objects policy: '{value}', expected 'error' or 'skip'"),
+            }),
+        }
+    }
 }
 
 impl Default for ServerOptions {
@@ -113,16 +232,32 @@ impl Default for ServerOptions {
         Self {
             advertise_refs: false,
             stateless_rpc: false,
+            protocol: None,
+            remote_addr: None,
+            principal: None,
+            auditor: gix_serve_core::audit::Auditor::default(),
+            metrics: gix_serve_core::metrics::Meter::default(),
             timeout: Some(Duration::from_secs(900)), // 15 minutes
+            cancellation: crate::CancellationToken::default(),
             strict: false,
             capabilities: ServerCapabilities::default(),
             max_pack_size: None,
+            verify_pack: false,
+            max_in_flight_entries: 4096,
+            max_concurrent_sessions: 8,
+            object_caches: None,
             keepalive: Some(Duration::from_secs(5)),
             upload_pack_hook: None,
             pack_objects_hook: None,
             pre_upload_pack_hook: None,
             post_upload_pack_hook: None,
             hidden_refs: Vec::new(),
+            namespace: None,
+            ref_snapshot: None,
+            ref_authorization: None,
+            peel_budget: None,
+            object_exclusion: None,
+            stall_detection: None,
             allowed_filters: vec![
                 "blob:none".into(),
                 "blob:limit=1k".into(),
@@ -135,11 +270,14 @@ impl Default for ServerOptions {
             allow_any_sha1_in_want: false,
             allow_reachable_sha1_in_want: false,
             allow_tip_sha1_in_want: false,
+            missing_objects: MissingObjectPolicy::Error,
             allow_deepen_relative: true,
             allow_packfile_uris: false,
+            packfile_uris: Vec::new(),
             enable_session_id: true,
             enable_sha256: false,
             enable_object_info: false,
+            v2_commands: crate::protocol::commands::Commands::default(),
             allow_blob_filter: true,
             allow_tree_filter: true,
             allow_sparse_filter: false,
@@ -148,7 +286,12 @@ impl Default for ServerOptions {
             user_agent: None,
             hash_algorithms: vec![gix_hash::Kind::Sha1],
             enable_tracing: false,
+            read_only: false,
+            logger: crate::log::Logger::default(),
             custom_config: std::collections::HashMap::new(),
+            repository_overrides: true,
+            abbreviate_object_ids: false,
+            check_packet_grammar: cfg!(debug_assertions),
         }
     }
 }
@@ -159,6 +302,18 @@ impl ServerOptions {
         Self::default()
     }
 
+    /// Send diagnostics to `logger`
+    pub fn with_logger(mut self, logger: crate::log::Logger) -> Self {
+        self.logger = logger;
+        self
+    }
+
+    /// Enter `tracing` spans for the phases of each session, see [`enable_tracing`](Self::enable_tracing)
+    pub fn with_tracing(mut self, enable: bool) -> Self {
+        self.enable_tracing = enable;
+        self
+    }
+
     /// Enable or disable ref advertisement
     pub fn with_advertise_refs(mut self, advertise: bool) -> Self {
         self.advertise_refs = advertise;
@@ -171,12 +326,48 @@ impl ServerOptions {
         self
     }
 
+    /// Serve `version` to all clients, regardless of the version they asked for
+    pub fn with_protocol(mut self, version: crate::ProtocolVersion) -> Self {
+        self.protocol = Some(version);
+        self
+    }
+
+    /// Set the address of the client, like the one of a PROXY protocol header when serving behind a load balancer
+    pub fn with_remote_addr(mut self, remote_addr: std::net::SocketAddr) -> Self {
+        self.remote_addr = Some(remote_addr);
+        self
+    }
+
+    /// Set the authenticated identity of the client
+    pub fn with_principal(mut self, principal: impl Into<String>) -> Self {
+        self.principal = Some(principal.into());
+        self
+    }
+
+    /// Record requests that are denied, like wants of hidden refs, to `auditor`
+    pub fn with_auditor(mut self, auditor: gix_serve_core::audit::Auditor) -> Self {
+        self.auditor = auditor;
+        self
+    }
+
+    /// Report counters and timings of sessions, like the bytes sent and the duration of each phase, to `meter`
+    pub fn with_metrics(mut self, meter: gix_serve_core::metrics::Meter) -> Self {
+        self.metrics = meter;
+        self
+    }
+
     /// Set timeout duration
     pub fn with_timeout(mut self, timeout: Duration) -> Self {
         self.timeout = Some(timeout);
         self
     }
 
+    /// Abort sessions once `token` is cancelled from another thread
+    pub fn with_cancellation(mut self, token: crate::CancellationToken) -> Self {
+        self.cancellation = token;
+        self
+    }
+
     /// Set strict mode
     pub fn with_strict(mut self, strict: bool) -> Self {
         self.strict = strict;
@@ -195,18 +386,96 @@ impl ServerOptions {
         self
     }
 
+    /// Enable or disable verification of generated packs before sending them
+    pub fn with_pack_verification(mut self, verify: bool) -> Self {
+        self.verify_pack = verify;
+        self
+    }
+
+    /// Generate up to `entries` pack entries at a time before sending them, see [`max_in_flight_entries`](Self::max_in_flight_entries)
+    pub fn with_max_in_flight_entries(mut self, entries: usize) -> Self {
+        self.max_in_flight_entries = entries;
+        self
+    }
+
+    /// Size the default [`ObjectCaches`] of each session for up to `sessions` sessions being served at once
+    pub fn with_max_concurrent_sessions(mut self, sessions: usize) -> Self {
+        self.max_concurrent_sessions = sessions;
+        self
+    }
+
+    /// Use `caches` for decoded objects in each session instead of deriving them from the concurrent sessions
+    pub fn with_object_caches(mut self, caches: ObjectCaches) -> Self {
+        self.object_caches = Some(caches);
+        self
+    }
+
+    /// The caches for decoded objects each session uses, as configured or derived from the concurrent sessions
+    pub fn object_caches(&self) -> ObjectCaches {
+        self.object_caches
+            .unwrap_or_else(|| ObjectCaches::for_sessions(self.max_concurrent_sessions))
+    }
+
     /// Set keepalive interval
     pub fn with_keepalive(mut self, keepalive: Duration) -> Self {
         self.keepalive = Some(keepalive);
         self
     }
 
-    /// Add hidden ref pattern
+    /// Advertise and validate wants against `snapshot` instead of reading refs from the repository
+    pub fn with_ref_snapshot(mut self, snapshot: crate::services::RefSnapshot) -> Self {
+        self.ref_snapshot = Some(std::sync::Arc::new(snapshot));
+        self
+    }
+
+    /// Only advertise and serve refs that `authorizer` allows
+    pub fn with_ref_authorizer(mut self, authorizer: impl crate::services::RefAuthorizer + 'static) -> Self {
+        self.ref_authorization = Some(crate::services::RefAuthorization::new(authorizer));
+        self
+    }
+
+    /// Advertise annotated tags that weren't peeled within `budget` without their peeled value
+    pub fn with_peel_budget(mut self, budget: Duration) -> Self {
+        self.peel_budget = Some(budget);
+        self
+    }
+
+    /// Leave the objects that `firewall` excludes out of generated packs, or refuse fetches that need them
+    pub fn with_object_firewall(mut self, firewall: impl crate::services::ObjectFirewall + 'static) -> Self {
+        self.object_exclusion = Some(crate::services::ObjectExclusion::new(firewall));
+        self
+    }
+
+    /// Abort reading the client's request once it sends slower than `detection` allows
+    pub fn with_stall_detection(mut self, detection: crate::services::StallDetection) -> Self {
+        self.stall_detection = Some(detection);
+        self
+    }
+
+    /// Set what to do with wants whose objects are missing
+    pub fn with_missing_objects(mut self, policy: MissingObjectPolicy) -> Self {
+        self.missing_objects = policy;
+        self
+    }
+
+    /// Serve `command` with protocol v2, replacing the command of the same name, if any
+    pub fn with_v2_command(mut self, command: impl crate::protocol::commands::Command + 'static) -> Self {
+        self.v2_commands = self.v2_commands.with(command);
+        self
+    }
+
+    /// Add the `hideRefs` pattern `pattern`, which takes precedence over the patterns added before it
     pub fn with_hidden_ref(mut self, pattern: impl Into<BString>) -> Self {
         self.hidden_refs.push(pattern.into());
         self
     }
 
+    /// Serve only the refs of `namespace`, like `GIT_NAMESPACE` does, see [`namespace`](Self::namespace)
+    pub fn with_namespace(mut self, namespace: impl Into<BString>) -> Self {
+        self.namespace = Some(namespace.into());
+        self
+    }
+
     /// Set allowed filters
     pub fn with_allowed_filters(mut self, filters: Vec<BString>) -> Self {
         self.allowed_filters = filters;
@@ -225,12 +494,43 @@ impl ServerOptions {
         self
     }
 
+    /// Offload the blob of `uri` to its pre-built pack for clients accepting its protocol, and allow packfile URIs
+    pub fn with_packfile_uri(mut self, uri: crate::services::PackfileUri) -> Self {
+        self.packfile_uris.push(uri);
+        self.allow_packfile_uris = true;
+        self
+    }
+
     /// Set custom user agent
     pub fn with_user_agent(mut self, agent: impl Into<BString>) -> Self {
         self.user_agent = Some(agent.into());
         self
     }
 
+    /// Enable/disable merging per-repository `serve.*` overrides
+    pub fn with_repository_overrides(mut self, enabled: bool) -> Self {
+        self.repository_overrides = enabled;
+        self
+    }
+
+    /// Never write to the filesystem while serving, refusing options that would, like hooks or trace files
+    pub fn with_read_only(mut self, read_only: bool) -> Self {
+        self.read_only = read_only;
+        self
+    }
+
+    /// Abbreviate object ids in error messages to the unique length `core.abbrev` of the repository asks for
+    pub fn with_abbreviated_object_ids(mut self, abbreviate: bool) -> Self {
+        self.abbreviate_object_ids = abbreviate;
+        self
+    }
+
+    /// Check the packets sent to clients against the protocol grammar if `check` is `true`
+    pub fn with_packet_grammar_check(mut self, check: bool) -> Self {
+        self.check_packet_grammar = check;
+        self
+    }
+
     /// Add custom configuration
     pub fn with_config(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
         self.custom_config.insert(key.into(), value.into());
@@ -244,57 +544,85 @@ impl ServerOptions {
         // Load configuration values from git config
         let config = repo.config_snapshot();
 
-        // uploadpack.* configuration
-        if let Some(value) = config.boolean("uploadpack.allowAnySHA1InWant") {
+        if let Some(value) = keys::upload_pack::ALLOW_ANY_SHA1_IN_WANT.get(&config)? {
             options.allow_any_sha1_in_want = value;
         }
 
-        if let Some(value) = config.boolean("uploadpack.allowReachableSHA1InWant") {
+        if let Some(value) = keys::upload_pack::ALLOW_REACHABLE_SHA1_IN_WANT.get(&config)? {
             options.allow_reachable_sha1_in_want = value;
         }
 
-        if let Some(value) = config.boolean("uploadpack.allowTipSHA1InWant") {
+        if let Some(value) = keys::upload_pack::ALLOW_TIP_SHA1_IN_WANT.get(&config)? {
             options.allow_tip_sha1_in_want = value;
         }
 
-        if let Some(value) = config.boolean("uploadpack.allowFilter") {
+        if let Some(value) = keys::upload_pack::MISSING_OBJECTS.get(&config)? {
+            options.missing_objects = value.to_str_lossy().parse()?;
+        }
+
+        if let Some(value) = keys::upload_pack::ALLOW_FILTER.get(&config)? {
             options.allow_filter = value;
         }
 
-        if let Some(value) = config.integer("uploadpack.keepAlive") {
-            if value > 0 {
-                options.keepalive = Some(Duration::from_secs(value as u64));
-            } else {
-                options.keepalive = None;
-            }
+        if let Some(value) = keys::upload_pack::KEEP_ALIVE.get(&config)? {
+            options.keepalive = (value > 0).then(|| Duration::from_secs(value as u64));
+        }
+
+        let low_speed_limit = keys::upload_pack::LOW_SPEED_LIMIT.get(&config)?.unwrap_or(0);
+        let low_speed_time = keys::upload_pack::LOW_SPEED_TIME.get(&config)?.unwrap_or(0);
+        if let Some(detection) =
+            crate::services::StallDetection::from_low_speed(low_speed_limit.max(0) as u64, low_speed_time.max(0) as u64)
+        {
+            options.stall_detection = Some(detection);
         }
 
-        if let Some(value) = config.string("uploadpack.packObjectsHook") {
+        if let Some(value) = keys::upload_pack::PACK_OBJECTS_HOOK.get_protected(&config) {
             options.pack_objects_hook = Some(PathBuf::from(value.to_string()));
         }
 
-        // transfer.* configuration
-        if let Some(value) = config.string("transfer.hideRefs") {
-            options.hidden_refs.push(BString::from(value.into_owned()));
+        for value in keys::upload_pack::BLOB_PACKFILE_URI.get_all(&config) {
+            options = options.with_packfile_uri(crate::services::PackfileUri::from_config_value(value.as_bstr())?);
         }
 
-        // Load hidden refs from multiple values - use strings() method instead
-        if let Some(values) = config.strings("transfer.hideRefs") {
-            for value in values {
-                options.hidden_refs.push(BString::from(value.into_owned()));
-            }
+        // Like in git, the patterns of both sections apply in the order they appear in.
+        let hidden = gix_serve_core::visibility::HiddenRefs::from_config(config.plumbing(), "uploadpack");
+        options.hidden_refs.extend(hidden.patterns().map(BString::from));
+
+        if let Some(value) = keys::upload_pack::VERIFY_PACK.get(&config)? {
+            options.verify_pack = value;
         }
 
-        // Check if object info command is enabled via transfer.advertiseobjectinfo
-        if let Some(value) = config.boolean("transfer.advertiseObjectInfo") {
+        if let Some(value) = keys::transfer::ADVERTISE_OBJECT_INFO.get(&config)? {
             options.enable_object_info = value;
         }
 
+        if let Some(value) = keys::core::DELTA_BASE_CACHE_LIMIT.get(&config)? {
+            options.object_caches = Some(options.object_caches().with_delta_cache(value.max(0) as usize));
+        }
+
+        if let Some(value) = keys::upload_pack::READ_ONLY.get(&config)? {
+            options.read_only = value;
+        }
+        options.validate_read_only()?;
+
         Ok(options)
     }
 
     /// Validate configuration for consistency
+    ///
+    /// In [read-only mode](Self::read_only), options that write to the filesystem while serving are refused:
+    /// hooks, which may keep pack caches or logs, and tracing, which writes trace files.
     pub fn validate(&self) -> Result<()> {
+        self.validate_read_only()?;
+
+        if let Some(namespace) = &self.namespace {
+            if let Err(err) = gix_ref::namespace::expand(namespace.as_bstr()) {
+                return Err(Error::Config {
+                    message: format!("Invalid namespace '{namespace}': {err}"),
+                });
+            }
+        }
+
         // Validate hook paths exist if specified
         if let Some(hook_path) = &self.upload_pack_hook {
             if !hook_path.exists() {
@@ -321,6 +649,17 @@ impl ServerOptions {
                     message: "Empty filter specification not allowed".to_string(),
                 });
             }
+            if let Err(err) = FilterSpec::parse(&filter.to_str_lossy()) {
+                return Err(Error::Config {
+                    message: format!("Invalid allowed filter '{}': {}", filter, err),
+                });
+            }
+        }
+
+        if self.max_concurrent_sessions == 0 {
+            return Err(Error::Config {
+                message: "Maximum concurrent sessions cannot be zero".to_string(),
+            });
         }
 
         // Validate timeout
@@ -335,36 +674,63 @@ impl ServerOptions {
         Ok(())
     }
 
-    /// Check if a reference should be hidden
-    pub fn is_ref_hidden(&self, ref_name: &str) -> bool {
-        // Check user-configured hidden refs
-        for pattern in &self.hidden_refs {
-            if let Ok(pattern) = gix_pathspec::Pattern::from_bytes(pattern, gix_pathspec::Defaults::default()) {
-                // Simple pattern matching for now - just check if it matches
-                // Simple string matching for now - just check if pattern bytes are contained in ref_name
-                if let Ok(pattern_str) = std::str::from_utf8(pattern.path()) {
-                    if ref_name.contains(pattern_str) {
-                        return true;
-                    }
-                }
-            }
+    /// Fail if read-only mode is enabled along with options that write to the filesystem
+    fn validate_read_only(&self) -> Result<()> {
+        if !self.read_only {
+            return Ok(());
+        }
+        let conflicts: Vec<&str> = [
+            (self.upload_pack_hook.is_some(), "the upload-pack hook"),
+            (self.pack_objects_hook.is_some(), "the pack-objects hook"),
+            (self.pre_upload_pack_hook.is_some(), "the pre-upload-pack hook"),
+            (self.post_upload_pack_hook.is_some(), "the post-upload-pack hook"),
+            (self.enable_tracing, "tracing"),
+        ]
+        .into_iter()
+        .filter_map(|(enabled, option)| enabled.then_some(option))
+        .collect();
+        if conflicts.is_empty() {
+            return Ok(());
         }
-        false
+        Err(Error::Config {
+            message: format!(
+                "Read-only mode conflicts with {}, which may write to the filesystem",
+                conflicts.join(", ")
+            ),
+        })
+    }
+
+    /// Check if a reference is hidden by the `hideRefs` patterns, like git does
+    pub fn is_ref_hidden(&self, ref_name: &str) -> bool {
+        self.hidden_refs
+            .iter()
+            .map(|pattern| pattern.to_str_lossy())
+            .collect::<gix_serve_core::visibility::HiddenRefs>()
+            .is_hidden(ref_name)
     }
 
     /// Check if a filter is allowed
+    ///
+    /// The requested spec is parsed and compared against each allowed filter semantically,
+    /// so numeric limits in the allowlist act as upper bounds. Unparseable specs are never allowed.
     pub fn is_filter_allowed(&self, filter_spec: &str) -> bool {
         if !self.allow_filter {
             return false;
         }
 
-        // Check against allowed filter patterns
-        for allowed in &self.allowed_filters {
-            if filter_spec.starts_with(&*allowed.to_str_lossy()) {
-                return true;
-            }
-        }
+        let Ok(requested) = FilterSpec::parse(filter_spec) else {
+            return false;
+        };
+        let allowlist: Vec<FilterSpec> = self
+            .allowed_filters
+            .iter()
+            .filter_map(|allowed| FilterSpec::parse(&allowed.to_str_lossy()).ok())
+            .collect();
+        filter::allowed_by(&allowlist, &requested)
+    }
 
-        false
+    /// Check if protocol v2 clients may send the protocols they accept for [packfile URIs](Self::packfile_uris)
+    pub fn is_packfile_uris_allowed(&self) -> bool {
+        self.allow_packfile_uris || self.capabilities.packfile_uris
     }
 }
@@ -0,0 +1,135 @@
+//! Per-repository overrides read from the `serve.*` section of the served repository's config
+
+use super::{keys, ServerOptions};
+use crate::{Error, Result};
+use bstr::BString;
+use std::time::Duration;
+
+/// Options a repository can set for itself, merged over the global [`ServerOptions`] at session start
+///
+/// Overrides can only tighten what the server allows: limits are lowered, features can be
+/// switched off but not on, and refs can be hidden but not revealed.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RepositoryOverrides {
+    /// `serve.uploadPack`: set to `false` to refuse serving this repository
+    pub upload_pack: Option<bool>,
+
+    /// `serve.hideRefs`: additional hidden ref patterns
+    pub hidden_refs: Vec<BString>,
+
+    /// `serve.maxPackSize`: maximum pack size in bytes
+    pub max_pack_size: Option<u64>,
+
+    /// `serve.timeout`: client timeout in seconds
+    pub timeout: Option<Duration>,
+
+    /// `serve.allowFilter`: set to `false` to disable filters
+    pub allow_filter: Option<bool>,
+
+    /// `serve.allowShallow`: set to `false` to disable shallow clones
+    pub allow_shallow: Option<bool>,
+
+    /// `serve.maxShallowDepth`: maximum shallow depth
+    pub max_shallow_depth: Option<u32>,
+}
+
+impl RepositoryOverrides {
+    /// Read the overrides from the `serve.*` section of `repo`'s configuration
+    pub fn from_repository(repo: &gix::Repository) -> Result<Self> {
+        let config = repo.config_snapshot();
+        let positive = |key: &keys::Key<i64>| -> Result<Option<u64>> {
+            Ok(key.get(&config)?.filter(|value| *value > 0).map(|value| value as u64))
+        };
+
+        Ok(Self {
+            upload_pack: keys::serve::UPLOAD_PACK.get(&config)?,
+            hidden_refs: keys::serve::HIDE_REFS.get_all(&config),
+            max_pack_size: positive(&keys::serve::MAX_PACK_SIZE)?,
+            timeout: positive(&keys::serve::TIMEOUT)?.map(Duration::from_secs),
+            allow_filter: keys::serve::ALLOW_FILTER.get(&config)?,
+            allow_shallow: keys::serve::ALLOW_SHALLOW.get(&config)?,
+            max_shallow_depth: positive(&keys::serve::MAX_SHALLOW_DEPTH)?
+                .map(|depth| depth.min(u32::MAX as u64) as u32),
+        })
+    }
+}
+
+impl ServerOptions {
+    /// Return these options with `overrides` merged over them, or an error if the repository disabled serving
+    pub fn merged_with(&self, overrides: &RepositoryOverrides) -> Result<ServerOptions> {
+        if overrides.upload_pack == Some(false) {
+            return Err(Error::PermissionDenied {
+                message: "upload-pack is disabled for this repository".to_string(),
+            });
+        }
+
+        let mut options = self.clone();
+        options.hidden_refs.extend(overrides.hidden_refs.iter().cloned());
+        options.max_pack_size = min_limit(options.max_pack_size, overrides.max_pack_size);
+        options.timeout = min_limit(options.timeout, overrides.timeout);
+        options.max_shallow_depth = min_limit(options.max_shallow_depth, overrides.max_shallow_depth);
+        options.allow_filter &= overrides.allow_filter.unwrap_or(true);
+        options.allow_shallow &= overrides.allow_shallow.unwrap_or(true);
+        Ok(options)
+    }
+}
+
+/// Combine two optional limits, where `None` means unlimited
+fn min_limit<T: Ord>(global: Option<T>, repo: Option<T>) -> Option<T> {
+    match (global, repo) {
+        (Some(global), Some(repo)) => Some(global.min(repo)),
+        (global, repo) => global.or(repo),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn overrides_only_tighten() {
+        let global = ServerOptions::default()
+            .with_max_pack_size(1000)
+            .with_timeout(Duration::from_secs(60))
+            .with_filter_support(false);
+        let overrides = RepositoryOverrides {
+            hidden_refs: vec!["refs/internal/".into()],
+            max_pack_size: Some(5000),
+            timeout: Some(Duration::from_secs(10)),
+            allow_filter: Some(true),
+            allow_shallow: Some(false),
+            max_shallow_depth: Some(50),
+            ..Default::default()
+        };
+
+        let merged = global.merged_with(&overrides).unwrap();
+        assert_eq!(merged.max_pack_size, Some(1000), "the global limit is lower");
+        assert_eq!(
+            merged.timeout,
+            Some(Duration::from_secs(10)),
+            "the repository limit is lower"
+        );
+        assert_eq!(merged.max_shallow_depth, Some(50), "unlimited globally");
+        assert!(
+            !merged.allow_filter,
+            "repositories cannot enable what the server disabled"
+        );
+        assert!(!merged.allow_shallow);
+        assert!(merged.hidden_refs.contains(&"refs/internal/".into()));
+    }
+
+    #[test]
+    fn disabled_repository_is_refused() {
+        let overrides = RepositoryOverrides {
+            upload_pack: Some(false),
+            ..Default::default()
+        };
+        assert!(matches!(
+            ServerOptions::default().merged_with(&overrides),
+            Err(Error::PermissionDenied { .. })
+        ));
+        assert!(ServerOptions::default()
+            .merged_with(&RepositoryOverrides::default())
+            .is_ok());
+    }
+}
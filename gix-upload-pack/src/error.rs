@@ -24,6 +24,10 @@ pub enum Error {
     #[error("Pack generation error: {0}")]
     Pack(String),
 
+    /// A generated pack failed its self-check, and wasn't sent
+    #[error("Pack verification failed: {0}")]
+    PackVerification(String),
+
     /// Protocol error
     #[error("Protocol error: {0}")]
     Protocol(#[from] gix_protocol::handshake::Error),
@@ -32,6 +36,10 @@ pub enum Error {
     #[error("Protocol parsing error: {0}")]
     ProtocolParsing(String),
 
+    /// Protocol v2 request violating the request grammar, with the location of the offending packet
+    #[error("Protocol error: malformed request: {0}")]
+    MalformedRequest(#[from] crate::protocol::request::Diagnostic),
+
     /// Transport error
     #[error("Transport error: {0}")]
     Transport(#[from] gix_transport::client::Error),
@@ -84,6 +92,32 @@ pub enum Error {
     #[error("Invalid reference: {name}")]
     InvalidReference { name: String },
 
+    /// A want that the served refs don't allow fetching
+    #[error("Not our ref: {oid}")]
+    NotOurRef { oid: gix_hash::ObjectId },
+
+    /// Wants that can't be packed as they, or objects they lead to, are missing
+    #[error(
+        "Missing objects: {}",
+        .0.iter().map(ToString::to_string).collect::<Vec<_>>().join(", ")
+    )]
+    MissingObjects(Vec<crate::services::pack::MissingObject>),
+
+    /// Objects left out by the [`ObjectFirewall`](crate::services::pack::ObjectFirewall) that the client can't do without
+    #[error(
+        "Objects excluded from fetch: {}",
+        .0.iter().map(ToString::to_string).collect::<Vec<_>>().join(", ")
+    )]
+    ExcludedObjects(Vec<crate::services::pack::ExcludedObject>),
+
+    /// The client exceeded a resource limit, like sending its request too slowly
+    #[error("Resource limit exceeded: {0}")]
+    Resource(String),
+
+    /// The session was aborted with its [`CancellationToken`](crate::CancellationToken)
+    #[error("Session cancelled")]
+    Cancelled,
+
     /// Reference not found
     #[error("Reference not found: {name}")]
     ReferenceNotFound { name: String },
@@ -100,6 +134,10 @@ pub enum Error {
     #[error("Unsupported object format: {format}")]
     UnsupportedObjectFormat { format: String },
 
+    /// The object format of a request can't be served, with the message native git uses
+    #[error("{message}")]
+    ObjectFormat { message: String },
+
     /// Capability mismatch between client and server
     #[error("Capability mismatch: {message}")]
     CapabilityMismatch { message: String },
@@ -166,10 +204,76 @@ impl Error {
                 | Self::ObjectNotFound { .. }
                 | Self::InvalidReference { .. }
                 | Self::ReferenceNotFound { .. }
+                | Self::NotOurRef { .. }
+                | Self::MissingObjects(_)
+                | Self::ExcludedObjects(_)
+                | Self::Resource(_)
                 | Self::UnsupportedCapability { .. }
+                | Self::UnsupportedCommand { .. }
+                | Self::ObjectFormat { .. }
+                | Self::MalformedRequest(_)
                 | Self::InvalidProtocolVersion { .. }
                 | Self::Shallow { .. }
                 | Self::Filter { .. }
         )
     }
+
+    /// Describe this error like `git upload-pack` does, with object ids abbreviated if `abbreviate` is set
+    ///
+    /// Object ids are abbreviated to the length configured with `core.abbrev` in `repository`, extended as needed
+    /// to keep them unique. Errors native git doesn't know are described as with [`Display`](std::fmt::Display).
+    pub fn native_message(&self, repository: &gix::Repository, abbreviate: bool) -> String {
+        use crate::services::pack::missing::Relation;
+        let id = |id: &gix_hash::oid| {
+            if abbreviate {
+                abbreviated_id(repository, id)
+            } else {
+                id.to_string()
+            }
+        };
+        match self {
+            Self::NotOurRef { oid } => format!("git upload-pack: not our ref {}", id(oid)),
+            Self::ObjectNotFound { oid } => format!("bad object {}", id(oid)),
+            Self::UnsupportedCommand { command } => format!("invalid command '{command}'"),
+            Self::MissingObjects(missing) => match missing.first() {
+                Some(first) if first.relation == Relation::Want => {
+                    format!("git upload-pack: not our ref {}", id(&first.id))
+                }
+                Some(first) => format!("bad object {}", id(&first.id)),
+                None => self.to_string(),
+            },
+            _ => self.to_string(),
+        }
+    }
+
+    /// Return why the request was denied, if this error denies it rather than reporting a failure
+    pub fn denial_reason(&self) -> Option<gix_serve_core::audit::Reason> {
+        use gix_serve_core::audit::Reason;
+        Some(match self {
+            Self::NotOurRef { .. } => Reason::HiddenRef,
+            Self::ExcludedObjects(_) | Self::PermissionDenied { .. } => Reason::Policy,
+            Self::Hook { .. } => Reason::Hook,
+            Self::UnsupportedCapability { .. } | Self::CapabilityMismatch { .. } => Reason::Capability,
+            Self::Resource(_) => Reason::Limit,
+            _ => return None,
+        })
+    }
+}
+
+/// Abbreviate `id` to the unique prefix `core.abbrev` of `repository` asks for, like git
+///
+/// Objects the repository doesn't have, like unknown wants, can't be ambiguous and are cut to the configured
+/// length, or seven characters if it's determined automatically.
+fn abbreviated_id(repository: &gix::Repository, id: &gix_hash::oid) -> String {
+    use gix::{config::tree::Core, prelude::ObjectIdExt};
+    if let Ok(prefix) = id.to_owned().attach(repository).shorten() {
+        return prefix.to_string();
+    }
+    let hex_len = repository
+        .config_snapshot()
+        .string(Core::ABBREV)
+        .and_then(|value| Core::ABBREV.try_into_abbreviation(value, id.kind()).ok())
+        .flatten()
+        .unwrap_or(7);
+    id.to_hex_with_len(hex_len).to_string()
 }
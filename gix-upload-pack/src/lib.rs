@@ -40,16 +40,22 @@
 #![deny(rust_2018_idioms)]
 // #![warn(missing_docs, clippy::all, clippy::pedantic)]
 
+pub mod cancel;
+pub mod capability;
 pub mod config;
 pub mod error;
+pub mod log;
 pub mod protocol;
 pub mod server;
 pub mod services;
 mod types;
 
+pub use cancel::CancellationToken;
+pub use capability::Capability;
 pub use config::ServerOptions;
 pub use error::{Error, Result};
-pub use server::Server;
+pub use gix_serve_core::wire::WireStats;
+pub use server::{Server, SessionOutcome};
 pub use types::*;
 
 /// The version of this crate
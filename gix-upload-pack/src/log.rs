@@ -0,0 +1,204 @@
+//! Injectable logging for server diagnostics
+//!
+//! Nothing is logged by default, as stderr of the server may be forwarded to clients, as done by some SSH
+//! wrappers. Set a [`Logger`] with [`ServerOptions::with_logger()`](crate::ServerOptions::with_logger()) to
+//! receive diagnostics, for instance on stderr with [`Logger::stderr()`], or as `tracing` events with
+//! [`Logger::tracing()`] if the `tracing` feature is enabled.
+//!
+//! With [`ServerOptions::enable_tracing`](crate::ServerOptions::enable_tracing) and the `tracing` feature, the phases of
+//! each session are `tracing` spans as well: `handshake`, `negotiation`, `counting`, `compression` and `streaming`.
+
+use std::{fmt, sync::Arc};
+
+/// The severity of a log message, from most to least severe
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    /// An operation failed
+    Error,
+    /// Something unexpected happened that the server recovered from
+    Warn,
+    /// A noteworthy event in a session
+    Info,
+    /// Details to debug sessions
+    Debug,
+    /// Very verbose details, like timings of individual steps
+    Trace,
+}
+
+/// A destination for log messages
+pub trait Log: Send + Sync {
+    /// Return `true` if messages of `level` should be formatted and passed to [`log()`](Self::log())
+    fn enabled(&self, level: Level) -> bool;
+
+    /// Record `message` of `level`
+    fn log(&self, level: Level, message: fmt::Arguments<'_>);
+}
+
+/// A cheaply clonable handle to a [`Log`] implementation, silent by default
+#[derive(Clone, Default)]
+pub struct Logger(Option<Arc<dyn Log>>);
+
+impl Logger {
+    /// Create a logger which discards all messages
+    pub fn silent() -> Self {
+        Self(None)
+    }
+
+    /// Create a logger which passes messages to `log`
+    pub fn new(log: impl Log + 'static) -> Self {
+        Self(Some(Arc::new(log)))
+    }
+
+    /// Create a logger which writes messages up to `max_level` to stderr
+    pub fn stderr(max_level: Level) -> Self {
+        Self::new(Stderr { max_level })
+    }
+
+    /// Create a logger which emits messages as `tracing` events
+    #[cfg(feature = "tracing")]
+    pub fn tracing() -> Self {
+        Self::new(Tracing)
+    }
+
+    /// Return `true` if messages of `level` are recorded
+    pub fn enabled(&self, level: Level) -> bool {
+        self.0.as_ref().is_some_and(|log| log.enabled(level))
+    }
+
+    /// Record `message` of `level`, if enabled
+    pub fn log(&self, level: Level, message: fmt::Arguments<'_>) {
+        if let Some(log) = self.0.as_ref().filter(|log| log.enabled(level)) {
+            log.log(level, message);
+        }
+    }
+}
+
+impl fmt::Debug for Logger {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(if self.0.is_some() { "Logger" } else { "Logger(silent)" })
+    }
+}
+
+/// A [`Log`] writing to stderr
+#[derive(Debug, Clone, Copy)]
+pub struct Stderr {
+    /// The least severe level to write
+    pub max_level: Level,
+}
+
+impl Log for Stderr {
+    fn enabled(&self, level: Level) -> bool {
+        level <= self.max_level
+    }
+
+    fn log(&self, level: Level, message: fmt::Arguments<'_>) {
+        eprintln!("{level:?}: {message}");
+    }
+}
+
+/// A [`Log`] emitting `tracing` events
+#[cfg(feature = "tracing")]
+#[derive(Debug, Clone, Copy)]
+pub struct Tracing;
+
+#[cfg(feature = "tracing")]
+impl Log for Tracing {
+    fn enabled(&self, _level: Level) -> bool {
+        true
+    }
+
+    fn log(&self, level: Level, message: fmt::Arguments<'_>) {
+        match level {
+            Level::Error => tracing::error!("{message}"),
+            Level::Warn => tracing::warn!("{message}"),
+            Level::Info => tracing::info!("{message}"),
+            Level::Debug => tracing::debug!("{message}"),
+            Level::Trace => tracing::trace!("{message}"),
+        }
+    }
+}
+
+/// A `tracing` span of a phase of a session, which is left when dropped, see [`span!`]
+#[must_use = "the span is left when dropped"]
+pub(crate) struct Span {
+    #[cfg(feature = "tracing")]
+    _entered: Option<tracing::span::EnteredSpan>,
+}
+
+impl Span {
+    #[cfg(feature = "tracing")]
+    pub(crate) fn new(entered: Option<tracing::span::EnteredSpan>) -> Self {
+        Self { _entered: entered }
+    }
+
+    #[cfg(not(feature = "tracing"))]
+    pub(crate) fn disabled() -> Self {
+        Self {}
+    }
+}
+
+/// Enter the `tracing` span `$name` with the given fields if `$enabled` and the `tracing` feature is enabled
+///
+/// The fields are only evaluated if the span is entered.
+macro_rules! span {
+    ($enabled:expr, $name:literal $(, $($field:tt)+)?) => {{
+        #[cfg(feature = "tracing")]
+        let span = $crate::log::Span::new(($enabled).then(|| tracing::info_span!($name $(, $($field)+)?).entered()));
+        #[cfg(not(feature = "tracing"))]
+        let span = {
+            let _ = $enabled;
+            $crate::log::Span::disabled()
+        };
+        span
+    }};
+}
+
+/// Log a message at debug level to the given [`Logger`]
+macro_rules! debug {
+    ($logger:expr, $($arg:tt)+) => {
+        $logger.log($crate::log::Level::Debug, format_args!($($arg)+))
+    };
+}
+
+/// Log a message at trace level to the given [`Logger`]
+macro_rules! trace {
+    ($logger:expr, $($arg:tt)+) => {
+        $logger.log($crate::log::Level::Trace, format_args!($($arg)+))
+    };
+}
+
+pub(crate) use {debug, span, trace};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct Capture(Mutex<Vec<(Level, String)>>);
+
+    impl Log for Arc<Capture> {
+        fn enabled(&self, level: Level) -> bool {
+            level <= Level::Debug
+        }
+
+        fn log(&self, level: Level, message: fmt::Arguments<'_>) {
+            self.0.lock().unwrap().push((level, message.to_string()));
+        }
+    }
+
+    #[test]
+    fn levels_are_filtered() {
+        let capture = Arc::new(Capture::default());
+        let logger = Logger::new(capture.clone());
+        debug!(logger, "seen {}", 1);
+        trace!(logger, "filtered");
+        assert!(logger.enabled(Level::Error));
+        assert!(!logger.enabled(Level::Trace));
+        assert_eq!(*capture.0.lock().unwrap(), vec![(Level::Debug, "seen 1".to_string())]);
+
+        let silent = Logger::default();
+        assert!(!silent.enabled(Level::Error));
+        debug!(silent, "discarded");
+    }
+}
@@ -5,6 +5,7 @@ use std::time::Duration;
 
 use gix_upload_pack::config::ServerOptions;
 use gix_upload_pack::server::Server;
+use gix_upload_pack::services::{IdleMonitor, TimedOut};
 
 /// Git upload-pack server implementation for gitoxide
 ///
@@ -136,19 +137,32 @@ struct Args {
     )]
     no_strict: bool,
 
+    /// Never write to the filesystem while serving
+    ///
+    /// For repositories on read-only mounts. Options that would write, like hooks, are refused.
+    #[arg(
+        long = "read-only",
+        help = "Never write to the filesystem while serving",
+        long_help = "Serve without writing to the filesystem, for repositories on read-only mounts.\n\
+                     \n\
+                     Options that would write while serving, like hooks keeping pack\n\
+                     caches or trace files, are refused with a configuration error."
+    )]
+    read_only: bool,
+
     /// Interrupt transfer after <n> seconds of inactivity
     ///
-    /// Sets a timeout for client connections. If no data is received from
-    /// the client for the specified number of seconds, the connection will
-    /// be terminated. A value of 0 disables the timeout.
+    /// Sets a timeout for client connections. If the client neither sends
+    /// nor receives data for the specified number of seconds, the connection
+    /// will be terminated. A value of 0 disables the timeout.
     #[arg(
         long = "timeout",
         value_name = "SECONDS",
         help = "Interrupt transfer after <n> seconds of inactivity",
         long_help = "Set connection timeout in seconds.\n\
                      \n\
-                     If no data is received from the client for this many seconds,\n\
-                     the connection will be terminated with an error.\n\
+                     If the client neither sends nor receives data for this many\n\
+                     seconds, the connection will be terminated with an error.\n\
                      \n\
                      Values:\n\
                      - 0: No timeout (wait indefinitely)\n\
@@ -175,11 +189,6 @@ impl Args {
             }
         }
 
-        // Validate directory path
-        if !self.directory.exists() {
-            return Err(format!("Directory does not exist: {}", self.directory.display()));
-        }
-
         Ok(())
     }
 
@@ -195,14 +204,55 @@ impl Args {
             stateless_rpc: self.stateless_rpc,
             strict,
             timeout,
+            read_only: self.read_only,
+            // Like git, serve only the refs of the namespace in the environment, if any
+            namespace: std::env::var("GIT_NAMESPACE")
+                .ok()
+                .filter(|namespace| !namespace.is_empty())
+                .map(Into::into),
             ..Default::default()
         }
     }
 }
 
+/// The environment variable that, set to `native`, runs the native command for arguments we don't support
+const FALLBACK_ENV: &str = "GIX_SERVE_FALLBACK";
+
+/// Run `git upload-pack` with our own arguments in place of this process, if enabled via [`FALLBACK_ENV`]
+///
+/// This allows to install this binary as `git-upload-pack` even if clients use flags we don't know yet.
+fn fall_back_to_native_git() -> Option<io::Error> {
+    if std::env::var_os(FALLBACK_ENV).as_deref() != Some(std::ffi::OsStr::new("native")) {
+        return None;
+    }
+    let mut git = std::process::Command::new("git");
+    git.arg("upload-pack").args(std::env::args_os().skip(1));
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        Some(git.exec())
+    }
+    #[cfg(not(unix))]
+    match git.status() {
+        Ok(status) => std::process::exit(status.code().unwrap_or(1)),
+        Err(err) => Some(err),
+    }
+}
+
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     // Parse command line arguments using clap derive
-    let args = Args::parse();
+    let args = match Args::try_parse() {
+        Ok(args) => args,
+        Err(err) => {
+            if err.kind() == clap::error::ErrorKind::UnknownArgument {
+                if let Some(exec_err) = fall_back_to_native_git() {
+                    eprintln!("Error running native git upload-pack: {exec_err}");
+                    std::process::exit(1);
+                }
+            }
+            err.exit()
+        }
+    };
 
     // Validate argument combinations
     if let Err(msg) = args.validate() {
@@ -216,6 +266,14 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     // Initialize server with validated directory path
     let mut server = match Server::new(args.directory.clone(), options) {
         Ok(server) => server,
+        Err(gix_upload_pack::Error::Repository(_)) => {
+            // Missing and unreadable repositories are reported like `git upload-pack` does, as clients show it
+            eprintln!(
+                "fatal: '{}' does not appear to be a git repository",
+                args.directory.display()
+            );
+            std::process::exit(128);
+        }
         Err(e) => {
             eprintln!("Error initializing server: {e}");
             std::process::exit(1);
@@ -225,13 +283,25 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     // Handle the protocol on stdin/stdout
     let stdin = io::stdin();
     let stdout = io::stdout();
-    let mut stdin_lock = stdin.lock();
-    let mut stdout_lock = stdout.lock();
+    let timeout = server.options().timeout;
+    let idle = IdleMonitor::default();
+    if let Some(timeout) = timeout {
+        // Like git, which is interrupted by an alarm, exit once the client blocked a read or write for too long
+        let idle = idle.clone();
+        std::thread::spawn(move || loop {
+            std::thread::sleep(timeout.min(Duration::from_secs(1)));
+            if idle.idle().is_some_and(|idle| idle > timeout) {
+                eprintln!("fatal: {}", TimedOut { timeout });
+                std::process::exit(128);
+            }
+        });
+    }
 
     // Process the upload-pack protocol
-    if let Err(e) = server.serve(&mut stdin_lock, &mut stdout_lock) {
-        eprintln!("Error serving upload-pack protocol: {e}");
-        std::process::exit(1);
+    if let Err(e) = server.serve_monitored(stdin.lock(), stdout.lock(), &idle) {
+        // Like `git upload-pack`, which dies with the message
+        eprintln!("fatal: {}", server.error_message(&e));
+        std::process::exit(128);
     }
 
     Ok(())
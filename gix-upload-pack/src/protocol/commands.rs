@@ -0,0 +1,162 @@
+//! The commands served by protocol version 2, looked up by name for each request
+//!
+//! Each request names its command with a `command=<name>` line, which the [`Commands`] of the
+//! [`ServerOptions`](crate::ServerOptions) map to the [`Command`] serving it. `ls-refs` and `fetch` are registered by
+//! default, and further commands like `object-info` or `bundle-uri` are added with
+//! [`ServerOptions::with_v2_command()`](crate::ServerOptions::with_v2_command()) without touching the loop reading
+//! the requests.
+//!
+//! Requests for commands that aren't registered fail with [`Error::UnsupportedCommand`], which is described like
+//! `git upload-pack` does as `invalid command '<name>'`.
+
+use crate::{
+    error::{Error, Result},
+    protocol::{request::Request, v2::Handler},
+    services::packet_io::EnhancedPacketWriter,
+    types::SessionContext,
+};
+use std::{collections::BTreeMap, fmt, io::Write, sync::Arc};
+
+/// The writer of the response to a command, which may switch to a sideband once the pack is sent
+pub type Response<'w> = EnhancedPacketWriter<&'w mut dyn Write>;
+
+/// A protocol v2 command
+pub trait Command: Send + Sync {
+    /// The name clients request the command by, like `ls-refs`
+    fn name(&self) -> &str;
+
+    /// The capability line advertising the command, like `bundle-uri`, or `None` to serve it without advertising it
+    ///
+    /// Commands the [`CapabilityManager`](crate::services::CapabilityManager) advertises itself, like `ls-refs` and
+    /// `fetch`, aren't advertised a second time.
+    fn advertisement(&self) -> Option<String> {
+        Some(self.name().to_owned())
+    }
+
+    /// Serve `request` with the services of `handler`, writing the complete response to `response`
+    ///
+    /// The response of a command ends with a flush packet, unless it ends with the sideband of a pack.
+    fn serve(
+        &self,
+        handler: &Handler<'_>,
+        request: &Request,
+        response: &mut Response<'_>,
+        session: &mut SessionContext,
+    ) -> Result<()>;
+}
+
+/// The `ls-refs` command, listing the refs of the repository
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LsRefs;
+
+impl Command for LsRefs {
+    fn name(&self) -> &str {
+        "ls-refs"
+    }
+
+    fn serve(
+        &self,
+        handler: &Handler<'_>,
+        request: &Request,
+        response: &mut Response<'_>,
+        _session: &mut SessionContext,
+    ) -> Result<()> {
+        let (args, _parameters) = Handler::split_arguments(request);
+        handler.handle_ls_refs(response.inner_mut(), &args)
+    }
+}
+
+/// The `fetch` command, negotiating and sending a pack
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Fetch;
+
+impl Command for Fetch {
+    fn name(&self) -> &str {
+        "fetch"
+    }
+
+    fn serve(
+        &self,
+        handler: &Handler<'_>,
+        request: &Request,
+        response: &mut Response<'_>,
+        session: &mut SessionContext,
+    ) -> Result<()> {
+        let (args, parameters) = Handler::split_arguments(request);
+        handler.handle_fetch(response, &args, &parameters, session)
+    }
+}
+
+/// The commands a server serves by name, see the [module documentation](self)
+#[derive(Clone)]
+pub struct Commands {
+    commands: BTreeMap<String, Arc<dyn Command>>,
+}
+
+impl Default for Commands {
+    fn default() -> Self {
+        Commands::empty().with(LsRefs).with(Fetch)
+    }
+}
+
+impl Commands {
+    /// No commands at all, so every request fails
+    pub fn empty() -> Self {
+        Commands {
+            commands: BTreeMap::new(),
+        }
+    }
+
+    /// Serve `command` for requests of its name, replacing the command registered with it before, if any
+    pub fn with(mut self, command: impl Command + 'static) -> Self {
+        self.commands.insert(command.name().to_owned(), Arc::new(command));
+        self
+    }
+
+    /// Stop serving the command called `name`
+    pub fn without(mut self, name: &str) -> Self {
+        self.commands.remove(name);
+        self
+    }
+
+    /// Return the command called `name`, if registered
+    pub fn get(&self, name: &str) -> Option<&dyn Command> {
+        self.commands.get(name).map(AsRef::as_ref)
+    }
+
+    /// Return the registered commands, ordered by name
+    pub fn iter(&self) -> impl Iterator<Item = &dyn Command> {
+        self.commands.values().map(AsRef::as_ref)
+    }
+
+    /// Return the advertisements of the registered commands that aren't among the capability lines `advertised` yet
+    pub(crate) fn advertisements_besides(&self, advertised: &[String]) -> Vec<String> {
+        let name = |line: &str| line.split_once('=').map_or(line, |(name, _)| name).to_owned();
+        let advertised: Vec<String> = advertised.iter().map(|line| name(line)).collect();
+        self.iter()
+            .filter_map(|command| command.advertisement())
+            .filter(|line| !advertised.contains(&name(line)))
+            .collect()
+    }
+
+    /// Serve `request` with the command it names, or fail if there is none
+    pub(crate) fn serve(
+        &self,
+        handler: &Handler<'_>,
+        request: &Request,
+        response: &mut Response<'_>,
+        session: &mut SessionContext,
+    ) -> Result<()> {
+        let name = request.command.to_string();
+        match self.get(&name) {
+            Some(command) => command.serve(handler, request, response, session),
+            None => Err(Error::UnsupportedCommand { command: name }),
+        }
+    }
+}
+
+impl fmt::Debug for Commands {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.commands.keys()).finish()
+    }
+}
@@ -1,14 +1,74 @@
 //! Protocol version implementations
+//!
+//! The handlers of each protocol version can be used without a [`Server`](crate::Server) by embedders that open
+//! repositories and detect protocol versions themselves, like servers multiplexing sessions over their own transport:
+//!
+//! ```no_run
+//! use gix_upload_pack::protocol::{v2, ProtocolHandler, Services};
+//! use gix_upload_pack::{ProtocolVersion, ServerOptions, SessionContext};
+//!
+//! let repository = gix::open("/path/to/repo.git")?;
+//! let options = ServerOptions::default().with_stateless_rpc(true);
+//! let services = Services::new(&repository, &options);
+//! let mut session = SessionContext::from_options(repository.git_dir(), &options, ProtocolVersion::V2);
+//! let (input, output) = (std::io::stdin().lock(), std::io::stdout().lock());
+//! v2::Handler::from_services(&repository, &options, &services).handle_session(input, output, &mut session)?;
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
 
+pub mod commands;
+pub mod request;
 pub mod v1;
 pub mod v2;
 
-use crate::{error::Result, types::SessionContext};
+use crate::{
+    config::ServerOptions,
+    error::Result,
+    services::{pack::PackGenerator, CapabilityManager, CommandParser, PacketIOFactory, ReferenceManager},
+    types::SessionContext,
+};
+use gix::Repository;
 
 use std::io::{Read, Write};
 
 /// Common trait for protocol handlers
 pub trait ProtocolHandler {
-    /// Handle a complete upload-pack session
+    /// Handle a complete upload-pack session, reading the requests of the client from `reader` and writing the
+    /// responses to `writer`
+    ///
+    /// `session` is served as is: its `protocol_version` must match the handler, and its `stateless_rpc` decides
+    /// whether the advertisement is sent before the first request. Nothing is read from the process environment.
+    /// Resource limits applied by [`Server`](crate::Server), like stall detection and sizing object caches, are up to
+    /// the caller, and errors are returned without being reported to the client or the auditor.
     fn handle_session<R: Read, W: Write>(&mut self, reader: R, writer: W, session: &mut SessionContext) -> Result<()>;
 }
+
+/// The services protocol handlers depend on, configured for one repository and the options of one session
+pub struct Services<'a> {
+    /// Capability advertisement and negotiation
+    pub capability_manager: CapabilityManager<'a>,
+    /// Parsing of client requests
+    pub command_parser: CommandParser<'a>,
+    /// Collecting references to advertise
+    pub reference_manager: ReferenceManager<'a>,
+    /// Generating packs
+    pub pack_generator: PackGenerator<'a>,
+    /// Creating packet readers and writers
+    pub packet_io_factory: PacketIOFactory,
+}
+
+impl<'a> Services<'a> {
+    /// Create the services for serving `repository` with `options`, configured like [`Server`](crate::Server) does
+    pub fn new(repository: &'a Repository, options: &'a ServerOptions) -> Self {
+        Self {
+            capability_manager: CapabilityManager::new(repository, options),
+            command_parser: CommandParser::new(repository),
+            reference_manager: ReferenceManager::new(repository, &options.hidden_refs)
+                .with_snapshot(options.ref_snapshot.as_deref())
+                .with_authorization(options.ref_authorization.as_ref())
+                .with_peel_budget(options.peel_budget),
+            pack_generator: PackGenerator::new(repository, options),
+            packet_io_factory: PacketIOFactory::new(),
+        }
+    }
+}
@@ -0,0 +1,287 @@
+//! Protocol version 2 request parsing
+//!
+//! A request follows the grammar
+//!
+//! ```text
+//! request         = empty-request | command-request
+//! empty-request   = flush-pkt
+//! command-request = command *capability [delim-pkt *command-arg] flush-pkt
+//! ```
+//!
+//! where `command` is a `command=<name>` line which may appear anywhere among the capability lines.
+//! Illegal sequences are reported as [`Diagnostic`], which pinpoints the offending packet by its index
+//! and byte offset within the request.
+
+use crate::error::{Error, Result};
+use bstr::{BString, ByteSlice};
+use std::io::{ErrorKind, Read};
+
+/// The largest legal pkt-line, including its 4 byte length prefix
+const MAX_LINE_LEN: usize = 65520;
+
+/// A parsed protocol v2 command request
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Request {
+    /// The name of the requested command, like `fetch`
+    pub command: BString,
+    /// Capability lines sent along with the command, like `agent=git/2.44.0`
+    pub capabilities: Vec<BString>,
+    /// Command-specific argument lines following the delimiter packet
+    pub arguments: Vec<BString>,
+}
+
+/// The location and kind of a grammar violation in a request
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("packet {packet} at offset {offset}: {kind}")]
+pub struct Diagnostic {
+    /// The index of the offending packet within the request, starting at 0
+    pub packet: usize,
+    /// The byte offset of the offending packet within the request
+    pub offset: usize,
+    /// What is wrong with the packet
+    pub kind: DiagnosticKind,
+}
+
+/// The kinds of grammar violations detected in a request
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum DiagnosticKind {
+    /// The length prefix isn't four hexadecimal digits
+    #[error("invalid pkt-line length {0:?}")]
+    InvalidLength(BString),
+    /// The length prefix is `0003`, which no packet type uses
+    #[error("reserved pkt-line length {0}")]
+    ReservedLength(usize),
+    /// The length prefix exceeds the largest legal pkt-line
+    #[error("pkt-line length {0} exceeds the maximum of {MAX_LINE_LEN}")]
+    TooLong(usize),
+    /// The input ended in the middle of a packet
+    #[error("input ended in the middle of a packet")]
+    Truncated,
+    /// The input ended before the terminating flush packet
+    #[error("input ended before the terminating flush packet")]
+    MissingFlush,
+    /// A data packet without payload, which is indistinguishable from a flush for older peers
+    #[error("empty data packet")]
+    EmptyPacket,
+    /// A delimiter or flush packet was received without a preceding `command=` line
+    #[error("no command requested")]
+    MissingCommand,
+    /// The `command=` line has no command name
+    #[error("empty command name")]
+    EmptyCommand,
+    /// A second `command=` line was received
+    #[error("command {second:?} requested after already requesting command {first:?}")]
+    DuplicateCommand {
+        /// The command requested first
+        first: BString,
+        /// The command requested by the offending packet
+        second: BString,
+    },
+    /// A second delimiter packet was received among the command arguments
+    #[error("unexpected delimiter packet in command arguments")]
+    UnexpectedDelimiter,
+    /// A response-end packet, which only servers may send
+    #[error("unexpected response-end packet in request")]
+    UnexpectedResponseEnd,
+}
+
+/// A single packet of a request
+enum Packet {
+    Flush,
+    Delimiter,
+    ResponseEnd,
+    Data(Vec<u8>),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Section {
+    Capabilities,
+    Arguments,
+}
+
+impl Request {
+    /// Read the next request from `reader`
+    ///
+    /// Returns `None` for an empty request, or if the input ended before the request started.
+    pub fn read_from<R: Read>(reader: &mut R) -> Result<Option<Request>> {
+        let mut command: Option<BString> = None;
+        let mut capabilities = Vec::new();
+        let mut arguments = Vec::new();
+        let mut section = Section::Capabilities;
+        let mut offset = 0;
+
+        for packet in 0.. {
+            let at = |kind| Diagnostic { packet, offset, kind };
+            let (line, len) = match read_packet(reader).map_err(|err| err.at(packet, offset))? {
+                Some(read) => read,
+                None if packet == 0 => return Ok(None),
+                None => return Err(at(DiagnosticKind::MissingFlush).into()),
+            };
+            match line {
+                Packet::Flush if packet == 0 => return Ok(None),
+                Packet::Flush => {
+                    let command = command.ok_or_else(|| at(DiagnosticKind::MissingCommand))?;
+                    return Ok(Some(Request {
+                        command,
+                        capabilities,
+                        arguments,
+                    }));
+                }
+                Packet::Delimiter => {
+                    if command.is_none() {
+                        return Err(at(DiagnosticKind::MissingCommand).into());
+                    }
+                    if section == Section::Arguments {
+                        return Err(at(DiagnosticKind::UnexpectedDelimiter).into());
+                    }
+                    section = Section::Arguments;
+                }
+                Packet::ResponseEnd => return Err(at(DiagnosticKind::UnexpectedResponseEnd).into()),
+                Packet::Data(data) => {
+                    let line = data.strip_suffix(b"\n").unwrap_or(&data);
+                    match section {
+                        Section::Capabilities => match line.strip_prefix(b"command=") {
+                            Some(b"") => return Err(at(DiagnosticKind::EmptyCommand).into()),
+                            Some(name) => match &command {
+                                Some(first) => {
+                                    return Err(at(DiagnosticKind::DuplicateCommand {
+                                        first: first.clone(),
+                                        second: name.into(),
+                                    })
+                                    .into())
+                                }
+                                None => command = Some(name.into()),
+                            },
+                            None => capabilities.push(line.into()),
+                        },
+                        Section::Arguments => arguments.push(line.into()),
+                    }
+                }
+            }
+            offset += len;
+        }
+        unreachable!("the packet loop only ends by returning")
+    }
+
+    /// Parse a complete request from `data`, see [`read_from()`](Self::read_from())
+    pub fn parse(mut data: &[u8]) -> Result<Option<Request>> {
+        Self::read_from(&mut data)
+    }
+
+    /// Return `true` if `name` was sent as capability, either alone or with a value
+    pub fn has_capability(&self, name: &str) -> bool {
+        self.capabilities.iter().any(|cap| {
+            cap.strip_prefix(name.as_bytes())
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with(b"="))
+        })
+    }
+}
+
+/// A failure to read a single packet, before its location is known
+enum PacketError {
+    Io(std::io::Error),
+    Invalid(DiagnosticKind),
+}
+
+impl PacketError {
+    fn at(self, packet: usize, offset: usize) -> Error {
+        match self {
+            PacketError::Io(err) => Error::Io(err),
+            PacketError::Invalid(kind) => Diagnostic { packet, offset, kind }.into(),
+        }
+    }
+}
+
+/// Read a single packet and return it along with its length on the wire, or `None` at the end of input
+fn read_packet<R: Read>(reader: &mut R) -> std::result::Result<Option<(Packet, usize)>, PacketError> {
+    let mut hex = [0u8; 4];
+    match read_exact_or_eof(reader, &mut hex)? {
+        0 => return Ok(None),
+        4 => {}
+        _ => return Err(PacketError::Invalid(DiagnosticKind::Truncated)),
+    }
+    let len = std::str::from_utf8(&hex)
+        .ok()
+        .filter(|hex| hex.bytes().all(|b| b.is_ascii_hexdigit()))
+        .and_then(|hex| usize::from_str_radix(hex, 16).ok())
+        .ok_or_else(|| PacketError::Invalid(DiagnosticKind::InvalidLength(hex.as_bstr().into())))?;
+    let packet = match len {
+        0 => Packet::Flush,
+        1 => Packet::Delimiter,
+        2 => Packet::ResponseEnd,
+        3 => return Err(PacketError::Invalid(DiagnosticKind::ReservedLength(len))),
+        4 => return Err(PacketError::Invalid(DiagnosticKind::EmptyPacket)),
+        len if len > MAX_LINE_LEN => return Err(PacketError::Invalid(DiagnosticKind::TooLong(len))),
+        len => {
+            let mut data = vec![0; len - 4];
+            if read_exact_or_eof(reader, &mut data)? != data.len() {
+                return Err(PacketError::Invalid(DiagnosticKind::Truncated));
+            }
+            Packet::Data(data)
+        }
+    };
+    Ok(Some((packet, len.max(4))))
+}
+
+/// Fill `buf` from `reader`, returning how many bytes were read before the input ended
+fn read_exact_or_eof<R: Read>(reader: &mut R, buf: &mut [u8]) -> std::result::Result<usize, PacketError> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(err) if err.kind() == ErrorKind::Interrupted => {}
+            Err(err) => return Err(PacketError::Io(err)),
+        }
+    }
+    Ok(filled)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn diagnostic(data: &[u8]) -> Diagnostic {
+        match Request::parse(data) {
+            Err(Error::MalformedRequest(diagnostic)) => diagnostic,
+            other => panic!("expected a diagnostic, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn full_grammar() {
+        let request = Request::parse(b"0014command=ls-refs\n0015agent=git/2.44.0\n00010009peel\n0000")
+            .unwrap()
+            .expect("command request");
+        assert_eq!(request.command, "ls-refs");
+        assert_eq!(request.capabilities, vec![BString::from("agent=git/2.44.0")]);
+        assert_eq!(request.arguments, vec![BString::from("peel")]);
+        assert!(request.has_capability("agent"));
+        assert!(!request.has_capability("age"));
+
+        let request = Request::parse(b"0015agent=git/2.44.0\n0014command=ls-refs\n0000")
+            .unwrap()
+            .expect("command may follow capabilities, and arguments are optional");
+        assert_eq!(request.command, "ls-refs");
+        assert!(request.arguments.is_empty());
+
+        assert_eq!(Request::parse(b"0000").unwrap(), None, "empty request");
+        assert_eq!(Request::parse(b"").unwrap(), None, "no request");
+    }
+
+    #[test]
+    fn diagnostics_pinpoint_the_offending_packet() {
+        let d = diagnostic(b"0014command=ls-refs\n000100010000");
+        assert_eq!(
+            (d.packet, d.offset, &d.kind),
+            (2, 24, &DiagnosticKind::UnexpectedDelimiter)
+        );
+        assert_eq!(
+            d.to_string(),
+            "packet 2 at offset 24: unexpected delimiter packet in command arguments"
+        );
+
+        let d = diagnostic(b"0014command=ls-refs\n0001");
+        assert_eq!((d.packet, d.offset, d.kind), (2, 24, DiagnosticKind::MissingFlush));
+    }
+}
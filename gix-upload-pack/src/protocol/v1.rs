@@ -4,23 +4,28 @@
 //! stateful protocol used by Git for communication between client and server.
 
 use crate::{
+    cancel,
     config::ServerOptions,
     error::{Error, Result},
-    protocol::ProtocolHandler,
+    log::{debug, span},
+    protocol::{ProtocolHandler, Services},
     services::{
+        negotiation::{Acknowledgements, Answer},
         pack::PackGenerator,
         packet_io::{EnhancedPacketReader, EnhancedPacketWriter},
-        CapabilityManager,
+        CapabilityManager, CommitGraph, ShallowBoundary,
     },
     types::*,
 };
 use gix::Repository;
+use gix_serve_core::{metrics::Phase, protocol::ServiceKind};
 use std::io::{Read, Write};
 
 // Async support removed - now fully synchronous
 
 /// Protocol V1 handler with dependency injection
 pub struct Handler<'a> {
+    repository: &'a Repository,
     options: &'a ServerOptions,
     capability_manager: &'a CapabilityManager<'a>,
     command_parser: &'a crate::services::CommandParser<'a>,
@@ -30,9 +35,24 @@ pub struct Handler<'a> {
 }
 
 impl<'a> Handler<'a> {
+    /// Create a new V1 protocol handler serving `repository` with `options` through `services`
+    ///
+    /// `services` must be created for the same `repository` and `options`, as with [`Services::new()`].
+    pub fn from_services(repository: &'a Repository, options: &'a ServerOptions, services: &'a Services<'a>) -> Self {
+        Self::new(
+            repository,
+            options,
+            &services.capability_manager,
+            &services.command_parser,
+            &services.reference_manager,
+            &services.pack_generator,
+            &services.packet_io_factory,
+        )
+    }
+
     /// Create a new V1 protocol handler with dependency injection
     pub fn new(
-        _repository: &'a Repository,
+        repository: &'a Repository,
         options: &'a ServerOptions,
         capability_manager: &'a CapabilityManager<'a>,
         command_parser: &'a crate::services::CommandParser<'a>,
@@ -41,6 +61,7 @@ impl<'a> Handler<'a> {
         packet_io_factory: &'a crate::services::PacketIOFactory,
     ) -> Self {
         Self {
+            repository,
             options,
             capability_manager,
             command_parser,
@@ -51,12 +72,16 @@ impl<'a> Handler<'a> {
     }
 
     /// Advertise references and capabilities using passed EnhancedPacketWriter
-    fn advertise_refs<W: Write>(&self, writer: &mut EnhancedPacketWriter<W>) -> Result<()> {
-        // Check if we're explicitly using protocol v1 (not v0/default)
-        let is_explicit_v1 = crate::server::protocol_detection::ProtocolDetector::is_explicit_v1();
-
-        // For explicit v1, send version announcement first
-        if is_explicit_v1 {
+    fn advertise_refs<W: Write>(&self, writer: &mut EnhancedPacketWriter<W>, session: &SessionContext) -> Result<()> {
+        let _span = span!(
+            self.options.enable_tracing,
+            "handshake",
+            version = ?session.protocol_version
+        );
+        let _phase = self.options.metrics.phase(ServiceKind::UploadPack, Phase::Handshake);
+        // For explicit v1, send version announcement first. The version is the one of this session, not the one of
+        // the process environment, as servers embedding us may serve clients of different versions concurrently.
+        if session.protocol_version == ProtocolVersion::V1 {
             writer.write_protocol_message(b"version 1\n")?;
         }
 
@@ -86,17 +111,41 @@ impl<'a> Handler<'a> {
         writer: &mut EnhancedPacketWriter<W>,
         session: &mut SessionContext,
     ) -> Result<()> {
+        let _span = span!(self.options.enable_tracing, "negotiation");
+        let _phase = self.options.metrics.phase(ServiceKind::UploadPack, Phase::Negotiation);
         // Phase 1: Collect wants and capabilities
         self.collect_wants(line_reader, session)?;
+        // Clients that want nothing, like when cloning an empty repository, end the session with their flush
+        if session.negotiation.wants.is_empty() {
+            return Ok(());
+        }
+        self.reference_manager
+            .validate_wants(&session.negotiation.wants, self.options)?;
+        self.pack_generator.check_wants(&mut session.negotiation.wants)?;
+        session.negotiation.shallow_boundary = ShallowBoundary::compute(
+            self.repository,
+            self.options,
+            &session.negotiation,
+            session.capabilities.deepen_relative(),
+        )?;
+        // Like git, deepening requests are answered before negotiating, and the client waits for the answer
+        if session.negotiation.deepen.is_some() {
+            for line in session.negotiation.shallow_boundary.lines() {
+                writer.write_protocol_message(&line)?;
+            }
+            writer.write_flush()?;
+            writer.flush()?;
+        }
 
         // Update writer's sideband mode based on negotiated capabilities
         // For advertise-refs mode, never use sideband (Git protocol requirement)
         let sideband_mode = if self.options.advertise_refs {
             crate::types::SideBandMode::None
         } else {
-            session.capabilities.side_band
+            session.capabilities.sideband_kind()
         };
         writer.set_sideband_mode(sideband_mode);
+        writer.set_progress(session.capabilities.wants_progress());
 
         // Phase 2: Handle haves and send acks using EnhancedPacketWriter
         self.handle_haves(line_reader, writer, session)?;
@@ -106,25 +155,26 @@ impl<'a> Handler<'a> {
 
     /// Collect want lines from client
     fn collect_wants<R: Read>(&self, reader: &mut EnhancedPacketReader<R>, session: &mut SessionContext) -> Result<()> {
-        eprintln!("Debug: Starting collect_wants");
+        debug!(self.options.logger, "Starting collect_wants");
         let mut packet_count = 0;
         loop {
             match reader.read_line() {
                 Some(line_result) => {
                     packet_count += 1;
                     let line = line_result??;
-                    eprintln!("Debug: Packet {}: {:?}", packet_count, line);
+                    debug!(self.options.logger, "Packet {}: {:?}", packet_count, line);
                     if EnhancedPacketReader::<R>::is_flush_packet(&line) {
-                        eprintln!(
-                            "Debug: Received flush packet in collect_wants after {} packets",
-                            packet_count
+                        debug!(
+                            self.options.logger,
+                            "Received flush packet in collect_wants after {} packets", packet_count
                         );
                         break;
                     }
 
                     if let Some(line_data) = line.as_slice() {
-                        eprintln!(
-                            "Debug: Received packet in collect_wants: {:?}",
+                        debug!(
+                            self.options.logger,
+                            "Received packet in collect_wants: {:?}",
                             String::from_utf8_lossy(line_data)
                         );
                         if let Some(want_line) = line_data.strip_prefix(b"want ") {
@@ -138,10 +188,12 @@ impl<'a> Handler<'a> {
                             let capabilities_str = if line_str.len() > 40 { line_str[40..].trim() } else { "" };
 
                             // Parse capabilities if present (only on first want line)
-                            if !capabilities_str.is_empty() && session.capabilities == ClientCapabilities::default() {
+                            if !capabilities_str.is_empty() && session.capabilities == NegotiatedCapabilities::default()
+                            {
                                 // Use centralized capability parsing from CapabilityManager
-                                session.capabilities =
-                                    self.capability_manager.parse_client_capabilities(capabilities_str)?;
+                                session.capabilities = NegotiatedCapabilities::v1(
+                                    self.capability_manager.parse_client_capabilities(capabilities_str)?,
+                                );
                             }
                         } else if let Some(shallow_line) = line_data.strip_prefix(b"shallow ") {
                             // Use centralized command parser
@@ -156,6 +208,15 @@ impl<'a> Handler<'a> {
                         } else if let Some(deepen_not_line) = line_data.strip_prefix(b"deepen-not ") {
                             // Use centralized command parser
                             self.command_parser.parse_deepen_not_line(deepen_not_line, session)?;
+                        } else if let Some(filter_line) = line_data.strip_prefix(b"filter ") {
+                            // Like git, only clients that asked for the advertised capability may filter
+                            if !session.capabilities.filter_allowed() {
+                                return Err(Error::InvalidFilter {
+                                    message: "filtering capability not negotiated".into(),
+                                });
+                            }
+                            self.command_parser
+                                .parse_filter_line(filter_line, self.options, session)?;
                         } else if line_data.trim_ascii() == b"done" {
                             // Client sent "done" directly after wants (no have phase)
                             // Use centralized command parser
@@ -173,138 +234,114 @@ impl<'a> Handler<'a> {
                     }
                 }
                 None => {
-                    eprintln!("Debug: read_line() returned None after {} packets", packet_count);
+                    debug!(
+                        self.options.logger,
+                        "read_line() returned None after {} packets", packet_count
+                    );
                     break;
                 }
             }
         }
 
-        eprintln!(
-            "Debug: Finished collect_wants after {} packets, done={}",
-            packet_count, session.negotiation.done
+        debug!(
+            self.options.logger,
+            "Finished collect_wants after {} packets, done={}", packet_count, session.negotiation.done
         );
         Ok(())
     }
 
-    /// Handle have/ack negotiation loop using EnhancedPacketWriter
+    /// Answer the rounds of haves of the client until it sends `done`, or until the pack follows without it
+    ///
+    /// See [`Acknowledgements`] for how haves are acknowledged. Each round ends with a flush, whose answer has to
+    /// reach the client as it waits for it. Over stateless-rpc, the request ends with the first round.
     fn handle_haves<R: Read, W: Write>(
         &self,
         reader: &mut EnhancedPacketReader<R>,
         writer: &mut EnhancedPacketWriter<W>,
         session: &mut SessionContext,
     ) -> Result<()> {
-        let mut common_found = false;
+        let mut acknowledgements =
+            Acknowledgements::new(session.capabilities.multi_ack(), session.capabilities.no_done());
+        if session.negotiation.done {
+            self.options.metrics.negotiation_round(ServiceKind::UploadPack);
+            return send_answers(writer, acknowledgements.done());
+        }
+        let graph = CommitGraph::new(self.repository);
 
         while let Some(line_result) = reader.read_line() {
-            let line = line_result??;
-            if EnhancedPacketReader::<R>::is_flush_packet(&line) {
-                eprintln!("Debug: Received flush packet in handle_haves");
-                break;
-            }
-
-            if let Some(line_data) = line.as_slice() {
-                eprintln!(
-                    "Debug: Received packet in handle_haves: {:?}",
-                    String::from_utf8_lossy(line_data)
-                );
-                if let Some(have_line) = line_data.strip_prefix(b"have ") {
-                    // Use centralized command parser
-                    let is_common = self.command_parser.parse_have_line(have_line, session)?;
-
-                    // Extract OID for compatibility with existing logic
-                    let oid_str = std::str::from_utf8(have_line.trim_ascii())
-                        .map_err(|_| Error::custom("Invalid UTF-8 in have line"))?;
-                    let oid = gix_hash::ObjectId::from_hex(oid_str.as_bytes()).map_err(|_| Error::InvalidObjectId {
-                        oid: oid_str.to_string(),
-                    })?;
-
-                    // Object existence check is now handled by centralized parser
-                    if is_common {
-                        common_found = true;
-
-                        // Send appropriate ACK based on capabilities using EnhancedPacketWriter
-                        match session.capabilities.multi_ack {
-                            MultiAckMode::None => {
-                                if self.can_send_pack(session)? {
-                                    writer.send_ack(&oid, AckStatus::Common)?;
-                                }
-                            }
-                            MultiAckMode::Basic => {
-                                writer.send_ack(&oid, AckStatus::Continue)?;
-                            }
-                            MultiAckMode::Detailed => {
-                                if self.can_send_pack(session)? {
-                                    writer.send_ack(&oid, AckStatus::Ready)?;
-                                    return Ok(());
-                                } else {
-                                    writer.send_ack(&oid, AckStatus::Common)?;
-                                }
-                            }
-                        }
-                    }
-                } else if line_data.trim_ascii() == b"done" {
-                    eprintln!("Debug: Received 'done' packet in handle_haves");
-                    // Use centralized command parser
-                    self.command_parser.parse_done_line(session)?;
+            let line = match line_result {
+                // Stateless requests of clients with nothing to offer end after their wants, there is nothing to answer
+                Err(err)
+                    if session.stateless_rpc
+                        && err.kind() == std::io::ErrorKind::UnexpectedEof
+                        && session.negotiation.haves.is_empty()
+                        && session.negotiation.common.is_empty() =>
+                {
+                    debug!(self.options.logger, "Stateless request ended after the wants");
                     break;
                 }
+                line_result => line_result??,
+            };
+            if EnhancedPacketReader::<R>::is_flush_packet(&line) {
+                let (answers, send_pack) = acknowledgements.end_round(&graph, &session.negotiation.wants)?;
+                self.options.metrics.negotiation_round(ServiceKind::UploadPack);
+                send_answers(writer, answers)?;
+                if send_pack {
+                    debug!(self.options.logger, "Ready to send the pack without waiting for 'done'");
+                    session.negotiation.done = true;
+                    return Ok(());
+                }
+                if session.stateless_rpc {
+                    // Like git, the client sends the next round in a new request
+                    return Ok(());
+                }
+                writer.flush()?;
+                continue;
             }
-        }
 
-        // Send final response using EnhancedPacketWriter
-        eprintln!(
-            "Debug: About to check final response, negotiation.done={}",
-            session.negotiation.done
-        );
-        if session.negotiation.done {
-            eprintln!(
-                "Debug: Negotiation done, common_found={}, can_send_pack={}",
-                common_found,
-                self.can_send_pack(session)?
+            let Some(line_data) = line.as_slice() else {
+                continue;
+            };
+            debug!(
+                self.options.logger,
+                "Received packet in handle_haves: {:?}",
+                String::from_utf8_lossy(line_data)
             );
-            if common_found && self.can_send_pack(session)? {
-                eprintln!("Debug: Sending ACK for common object");
-                if let Some(common_oid) = session.negotiation.common.iter().next() {
-                    writer.send_ack(common_oid, AckStatus::Common)?;
-                }
+            if let Some(have_line) = line_data.strip_prefix(b"have ") {
+                let common = self.command_parser.parse_have_line(have_line, session)?;
+                let oid = gix_hash::ObjectId::from_hex(have_line.trim_ascii()).map_err(|_| Error::InvalidObjectId {
+                    oid: String::from_utf8_lossy(have_line.trim_ascii()).into_owned(),
+                })?;
+                let answer = acknowledgements.have(&graph, &session.negotiation.wants, oid, common)?;
+                send_answers(writer, answer)?;
+            } else if line_data.trim_ascii() == b"done" {
+                debug!(self.options.logger, "Received 'done' packet in handle_haves");
+                self.command_parser.parse_done_line(session)?;
+                self.options.metrics.negotiation_round(ServiceKind::UploadPack);
+                return send_answers(writer, acknowledgements.done());
             } else {
-                eprintln!("Debug: Sending NAK");
-                writer.send_nak()?;
+                return Err(Error::ProtocolParsing(format!(
+                    "expected SHA1 list, got '{}'",
+                    String::from_utf8_lossy(line_data.trim_ascii_end())
+                )));
             }
-        } else {
-            eprintln!("Debug: Negotiation not done, no final response");
         }
-
         Ok(())
     }
 
-    /// Check if we can send pack (have enough common objects)
-    fn can_send_pack(&self, session: &SessionContext) -> Result<bool> {
-        // Protocol compliance logic based on Git's behavior:
-        // 1. Negotiation must be done
-        // 2. For non-sideband modes: only send pack if we have common objects (send NAK only otherwise)
-        // 3. For sideband modes: always send pack when negotiation is done (Git's behavior)
-
-        if !session.negotiation.done {
-            return Ok(false);
-        }
-
-        match session.capabilities.side_band {
-            SideBandMode::None => {
-                // Non-sideband mode: only send pack if we have common objects
-                Ok(!session.negotiation.common.is_empty())
-            }
-            SideBandMode::Basic | SideBandMode::SideBand64k => {
-                // Sideband mode: Git sends pack even without common objects
-                Ok(true)
-            }
-        }
-    }
-
     /// Generate and send pack file using EnhancedPacketWriter
     fn send_pack<W: Write>(&self, writer: &mut EnhancedPacketWriter<W>, session: &SessionContext) -> Result<()> {
-        let pack_generator = self.pack_generator;
-        pack_generator.generate_pack(writer, session)?;
+        // Negotiation is over, the pack and its multiplexed progress follow
+        writer.establish_sideband()?;
+        let _phase = self
+            .options
+            .metrics
+            .phase(ServiceKind::UploadPack, Phase::PackGeneration);
+        let result = self.pack_generator.generate_pack(writer, session);
+        let pack_stats = cancel::notify(writer, result)?;
+        self.options
+            .metrics
+            .pack_sent(pack_stats.object_count.into(), pack_stats.pack_size);
         Ok(())
     }
 
@@ -317,35 +354,51 @@ impl<'a> Handler<'a> {
     ) -> Result<()> {
         if self.options.advertise_refs {
             // Just advertise refs and exit (for git ls-remote, etc.)
-            self.advertise_refs(writer)?;
+            self.advertise_refs(writer, session)?;
         } else if session.stateless_rpc {
             // Stateless RPC mode: client sends complete request, server responds directly
             // Handle negotiation using EnhancedPacketWriter
             self.handle_negotiation(reader, writer, session)?;
 
-            // Generate and send pack if needed
-            if !session.negotiation.wants.is_empty() && self.can_send_pack(session)? {
+            // Like git, the pack follows once negotiation is done
+            if !session.negotiation.wants.is_empty() && session.negotiation.done {
                 self.send_pack(writer, session)?;
             }
         } else {
             // Full stateful upload-pack session
 
-            // Step 1: Advertise refs and capabilities
-            self.advertise_refs(writer)?;
+            // Step 1: Advertise refs and capabilities, and make sure they reach the waiting client
+            self.advertise_refs(writer, session)?;
+            writer.flush()?;
 
             // Step 2: Handle negotiation
             self.handle_negotiation(reader, writer, session)?;
 
-            // Step 3: Generate and send pack if needed
-            if !session.negotiation.wants.is_empty() && self.can_send_pack(session)? {
+            // Step 3: Like git, send the pack once negotiation is done
+            if !session.negotiation.wants.is_empty() && session.negotiation.done {
                 self.send_pack(writer, session)?;
             }
         }
 
+        writer.flush()?;
         Ok(())
     }
 }
 
+/// Send `answers` to the client as they are
+fn send_answers<W: Write>(
+    writer: &mut EnhancedPacketWriter<W>,
+    answers: impl IntoIterator<Item = Answer>,
+) -> Result<()> {
+    for answer in answers {
+        match answer {
+            Answer::Ack(oid, status) => writer.send_ack(&oid, status)?,
+            Answer::Nak => writer.send_nak()?,
+        }
+    }
+    Ok(())
+}
+
 impl<'a> ProtocolHandler for Handler<'a> {
     fn handle_session<R: Read, W: Write>(&mut self, input: R, output: W, session: &mut SessionContext) -> Result<()> {
         // Use injected packet I/O factory
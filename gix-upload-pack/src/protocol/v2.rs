@@ -4,28 +4,34 @@
 //! modern, stateless protocol that provides better performance and extensibility.
 
 use crate::{
+    cancel,
     config::ServerOptions,
-    error::{Error, Result},
-    protocol::ProtocolHandler,
+    error::Result,
+    log::{debug, span},
+    protocol::{commands::Response, request::Request, ProtocolHandler, Services},
     services::{
+        capabilities::object_format_name,
+        negotiation,
         pack::PackGenerator,
         packet_io::{EnhancedPacketReader, EnhancedPacketWriter},
-        CapabilityManager,
+        CapabilityManager, CommitGraph,
     },
     types::*,
 };
-use bstr::ByteSlice;
+use bstr::{BString, ByteSlice};
 use gix::Repository;
-use gix_pack::Find;
+use gix_serve_core::{metrics::Phase, protocol::ServiceKind};
 
-use gix_packetline::{PacketLineRef, StreamingPeekableIter};
 use std::{
     collections::HashMap,
-    io::{BufRead, BufReader, Read, Write},
+    io::{BufReader, Read, Write},
 };
 
 // Async support removed - now fully synchronous
 
+/// The number of `ref-prefix` arguments from which `ls-refs` lists all refs instead, like git
+const TOO_MANY_PREFIXES: usize = 65536;
+
 /// Protocol V2 handler with dependency injection
 pub struct Handler<'a> {
     repository: &'a Repository,
@@ -38,6 +44,21 @@ pub struct Handler<'a> {
 }
 
 impl<'a> Handler<'a> {
+    /// Create a new V2 protocol handler serving `repository` with `options` through `services`
+    ///
+    /// `services` must be created for the same `repository` and `options`, as with [`Services::new()`].
+    pub fn from_services(repository: &'a Repository, options: &'a ServerOptions, services: &'a Services<'a>) -> Self {
+        Self::new(
+            repository,
+            options,
+            &services.capability_manager,
+            &services.command_parser,
+            &services.reference_manager,
+            &services.pack_generator,
+            &services.packet_io_factory,
+        )
+    }
+
     /// Create a new V2 protocol handler with dependency injection
     pub fn new(
         repository: &'a Repository,
@@ -59,8 +80,20 @@ impl<'a> Handler<'a> {
         }
     }
 
+    /// The repository being served
+    pub fn repository(&self) -> &'a Repository {
+        self.repository
+    }
+
+    /// The options of the session being served
+    pub fn options(&self) -> &'a ServerOptions {
+        self.options
+    }
+
     /// Send capability advertisement using streamlined approach
     fn advertise_capabilities<W: Write>(&self, writer: &mut W) -> Result<()> {
+        let _span = span!(self.options.enable_tracing, "handshake", version = "2");
+        let _phase = self.options.metrics.phase(ServiceKind::UploadPack, Phase::Handshake);
         // Use injected packet I/O factory
         let mut packet_writer = self.packet_io_factory.create_temp_writer(writer);
 
@@ -82,6 +115,8 @@ impl<'a> Handler<'a> {
 
     /// Advertise protocol v2 capabilities and commands (equivalent to --advertise-refs for v2)
     fn advertise_refs<W: Write>(&self, writer: &mut EnhancedPacketWriter<W>) -> Result<()> {
+        let _span = span!(self.options.enable_tracing, "handshake", version = "2");
+        let _phase = self.options.metrics.phase(ServiceKind::UploadPack, Phase::Handshake);
         // For advertise-refs mode, use the exact format that native git uses
         // This is simpler than the full capability negotiation format
 
@@ -92,12 +127,19 @@ impl<'a> Handler<'a> {
         let agent = format!("agent=git/gitoxide-{}\n", crate::VERSION);
         writer.write_protocol_message(agent.as_bytes())?;
 
-        // Commands in the exact order that native git uses
-        writer.write_protocol_message(b"ls-refs=unborn\n")?;
-        writer.write_protocol_message(b"fetch=shallow wait-for-done\n")?;
-        writer.write_protocol_message(b"server-option\n")?;
-        writer.write_protocol_message(b"object-format=sha1\n")?;
-        writer.write_protocol_message(b"object-info\n")?;
+        // Commands in the exact order that native git uses, followed by further registered ones
+        let mut lines = vec![
+            "ls-refs=unborn".to_owned(),
+            "fetch=shallow wait-for-done".to_owned(),
+            "server-option".to_owned(),
+            format!("object-format={}", object_format_name(self.repository.object_hash())),
+            "object-info".to_owned(),
+        ];
+        let registered = self.options.v2_commands.advertisements_besides(&lines);
+        lines.extend(registered);
+        for line in lines {
+            writer.write_protocol_message(format!("{line}\n").as_bytes())?;
+        }
 
         // End with flush packet
         writer.write_flush()?;
@@ -105,92 +147,41 @@ impl<'a> Handler<'a> {
         Ok(())
     }
 
-    /// Parse command arguments for V2
-    fn parse_command_arguments_v2<R: BufRead>(
-        &self,
-        reader: &mut StreamingPeekableIter<R>,
-    ) -> Result<HashMap<String, String>> {
+    /// Split the argument lines of `request` into the named arguments of its command and the fetch parameters
+    ///
+    /// Capabilities like `agent` and `object-format` apply to the request as a whole and aren't command arguments.
+    pub(crate) fn split_arguments(request: &Request) -> (HashMap<String, String>, Vec<BString>) {
         let mut args = HashMap::new();
-
-        loop {
-            // Peek at the next line to see if it's a command argument or fetch parameter
-            let should_break = match reader.peek_line() {
-                Some(Ok(Ok(line))) => {
-                    if matches!(line, gix_packetline::PacketLineRef::Flush) {
-                        true // Will consume the flush below
-                    } else if let Some(line_data) = line.as_slice() {
-                        let line_str = std::str::from_utf8(line_data)
-                            .map_err(|_| Error::custom("Invalid UTF-8 in argument line"))?
-                            .trim();
-
-                        // Stop parsing arguments if we hit fetch-specific commands
-                        if line_str.starts_with("want ")
-                            || line_str.starts_with("have ")
-                            || line_str.starts_with("shallow ")
-                            || line_str.starts_with("deepen")
-                            || line_str == "done"
-                        {
-                            true // Don't consume this line, leave it for read_fetch_parameters
-                        } else {
-                            false // This is an argument line, will process it below
-                        }
-                    } else {
-                        false
-                    }
-                }
-                Some(Ok(Err(_))) => {
-                    true // Decode error, break
-                }
-                Some(Err(_)) => {
-                    true // IO error, break
-                }
-                None => {
-                    true // EOF
-                }
-            };
-
-            if should_break {
-                // Only consume flush packets, leave fetch parameters for later
-                if let Some(Ok(Ok(line))) = reader.peek_line() {
-                    if matches!(line, gix_packetline::PacketLineRef::Flush) {
-                        reader.read_line(); // consume the flush
-                    }
-                }
-                break;
-            }
-
-            // Now consume the line we know is an argument
-            if let Some(line_result) = reader.read_line() {
-                let line = line_result??;
-                if let Some(line_data) = line.as_slice() {
-                    let line_str = std::str::from_utf8(line_data)
-                        .map_err(|_| Error::custom("Invalid UTF-8 in argument line"))?
-                        .trim();
-
-                    if let Some(equals_pos) = line_str.find('=') {
-                        let key = line_str[..equals_pos].to_string();
-                        let value = line_str[equals_pos + 1..].to_string();
-                        args.insert(key, value);
-                    } else {
-                        // Flag argument (no value)
-                        args.insert(line_str.to_string(), String::new());
-                    }
-                }
-            } else {
-                break;
+        let mut parameters = Vec::new();
+        for line in &request.arguments {
+            let is_parameter = [
+                &b"want "[..],
+                b"have ",
+                b"shallow ",
+                b"deepen ",
+                b"deepen-since ",
+                b"deepen-not ",
+            ]
+            .iter()
+            .any(|prefix| line.starts_with(prefix))
+                || line.as_slice() == b"done";
+            if is_parameter {
+                parameters.push(line.clone());
+                continue;
             }
+            let line = line.to_str_lossy();
+            // Values following a space, like the spec of `filter blob:limit=1k`, may contain `=` themselves
+            match line.split_once('=').filter(|(key, _)| !key.contains(' ')) {
+                Some((key, value)) => args.insert(key.to_string(), value.to_string()),
+                // Flag argument (no value)
+                None => args.insert(line.into_owned(), String::new()),
+            };
         }
-
-        Ok(args)
+        (args, parameters)
     }
 
     /// Handle ls-refs command
-    fn handle_ls_refs<R: BufRead, W: Write>(
-        &self,
-        _reader: &mut StreamingPeekableIter<R>,
-        writer: &mut W,
-        args: &HashMap<String, String>,
-    ) -> Result<()> {
+    pub(crate) fn handle_ls_refs<W: Write>(&self, writer: &mut W, args: &HashMap<String, String>) -> Result<()> {
         // Get server capabilities for validation
         let server_caps = self.capability_manager.build_server_capabilities(ProtocolVersion::V2)?;
 
@@ -212,10 +203,10 @@ impl<'a> Handler<'a> {
         // Parse arguments
         let symrefs = args.get("symrefs").is_some();
         let peel = args.get("peel").is_some();
-        let _unborn = args.get("unborn").is_some();
+        let unborn = args.get("unborn").is_some();
 
         // Collect all ref-prefix arguments (they come as separate keys like "ref-prefix HEAD", "ref-prefix refs/heads/")
-        let ref_prefixes: Vec<String> = args
+        let mut ref_prefixes: Vec<String> = args
             .keys()
             .filter_map(|key| {
                 if key.starts_with("ref-prefix ") {
@@ -225,9 +216,27 @@ impl<'a> Handler<'a> {
                 }
             })
             .collect();
+        if ref_prefixes.len() >= TOO_MANY_PREFIXES {
+            // Like git, listing all refs is cheaper than matching them against this many prefixes
+            ref_prefixes.clear();
+        }
 
         // Get references using injected reference manager
-        let refs = self.reference_manager.collect_references_with_prefixes(&ref_prefixes)?;
+        let refs = self
+            .reference_manager
+            .collect_references_with_prefixes(&ref_prefixes, peel)?;
+
+        // An unborn HEAD still tells clients of empty repositories which branch to create, which only its
+        // symref-target does, so like git it's only sent along with symrefs
+        let head_requested =
+            ref_prefixes.is_empty() || ref_prefixes.iter().any(|prefix| "HEAD".starts_with(prefix.as_str()));
+        if unborn && symrefs && head_requested {
+            if let Some(target) = self.reference_manager.unborn_head_target() {
+                let line = format!("unborn HEAD symref-target:{}\n", target.to_str_lossy());
+                let mut packet_writer = self.packet_io_factory.create_temp_writer(&mut *writer);
+                packet_writer.write_protocol_message(line.as_bytes())?;
+            }
+        }
 
         // Send references
         for reference in refs {
@@ -267,13 +276,19 @@ impl<'a> Handler<'a> {
     }
 
     /// Handle fetch command
-    fn handle_fetch<R: BufRead, W: Write>(
+    pub(crate) fn handle_fetch<W: Write>(
         &self,
-        reader: &mut StreamingPeekableIter<R>,
         writer: &mut EnhancedPacketWriter<W>,
         args: &HashMap<String, String>,
+        parameters: &[BString],
         session: &mut SessionContext,
     ) -> Result<()> {
+        let negotiation = (
+            span!(self.options.enable_tracing, "negotiation"),
+            self.options.metrics.phase(ServiceKind::UploadPack, Phase::Negotiation),
+        );
+        // Each fetch command is a round of negotiation, the last of which ends with the pack
+        self.options.metrics.negotiation_round(ServiceKind::UploadPack);
         // Get server capabilities for validation
         let server_caps = self.capability_manager.build_server_capabilities(ProtocolVersion::V2)?;
 
@@ -296,123 +311,170 @@ impl<'a> Handler<'a> {
         self.capability_manager
             .validate_v2_command(Command::Fetch, &args_vec, &server_caps)?;
 
-        // Parse fetch arguments
-        let thin_pack = args.get("thin-pack").is_some();
-        let ofs_delta = args.get("ofs-delta").is_some();
-        let include_tag = args.get("include-tag").is_some();
-        let no_progress = args.get("no-progress").is_some();
-        let sideband_all = args.get("sideband-all").is_some();
-        let _wait_for_done = args.get("wait-for-done").is_some();
-
-        // Parse filter if present
-        let filter = args.get("filter").map(|f| f.as_str().into());
-
-        // Set session capabilities based on arguments
-        session.capabilities.thin_pack = thin_pack;
-        session.capabilities.ofs_delta = ofs_delta;
-        session.capabilities.include_tag = include_tag;
-        session.capabilities.no_progress = no_progress;
-        session.capabilities.filter = filter;
-
-        // Protocol v2 defaults to sideband support (matches Git behavior)
-        // Git always uses sideband in v2 protocol for progress messages
-        session.capabilities.side_band = if sideband_all {
-            SideBandMode::SideBand64k
-        } else {
-            // Default to SideBand64k for protocol v2 (Git's behavior)
-            SideBandMode::SideBand64k
-        };
+        // Derive the capabilities of this fetch from its arguments, and check the filter against the allowlist
+        session.capabilities = NegotiatedCapabilities::v2(args);
+        if let Some(spec) = args.keys().find_map(|key| key.strip_prefix("filter ")) {
+            self.command_parser
+                .parse_filter_line(spec.as_bytes(), self.options, session)?;
+        }
+        if let Some(protocols) = args.keys().find_map(|key| key.strip_prefix("packfile-uris ")) {
+            self.command_parser
+                .parse_packfile_uris_line(protocols.as_bytes(), self.options, session)?;
+        }
+        let wait_for_done = args.get("wait-for-done").is_some();
 
         // Update writer's sideband mode based on negotiated capabilities
-        writer.set_sideband_mode(session.capabilities.side_band);
+        writer.set_sideband_mode(session.capabilities.sideband_kind());
+        writer.set_sideband_all(session.capabilities.sideband_all());
+        writer.set_progress(session.capabilities.wants_progress());
 
         // Read fetch parameters
-        self.read_fetch_parameters(reader, session)?;
-
-        // Perform negotiation if needed
-        if !session.negotiation.wants.is_empty() {
-            // In V2, we can send pack immediately if we have wants
-            // and don't need negotiation (simplified for this example)
-
-            // Find common commits and collect acknowledgments
-            let mut acks = Vec::new();
-            for have in &session.negotiation.haves {
-                if self.repository.objects.contains(have) {
-                    session.negotiation.common.insert(*have);
-                    acks.push(*have);
-                }
-            }
+        self.read_fetch_parameters(parameters, session)?;
+        self.reference_manager
+            .validate_wants(&session.negotiation.wants, self.options)?;
+        self.pack_generator.check_wants(&mut session.negotiation.wants)?;
 
-            // Only send acknowledgments section if we have acknowledgments to send
-            if !acks.is_empty() {
-                // Send acknowledgments section
-                // Use injected packet I/O factory for consistent packet encoding
-                let mut packet_writer = self.packet_io_factory.create_temp_writer(&mut *writer);
-                packet_writer.write_protocol_message(b"acknowledgments\n")?;
+        // Without wants there is nothing to send, unless the client only negotiates until it sends `done`
+        if session.negotiation.wants.is_empty() && !wait_for_done {
+            return Ok(());
+        }
 
-                for ack in acks {
-                    let ack_line = format!("ACK {}\n", ack.to_hex());
-                    // Use injected packet I/O factory for consistent packet encoding
-                    let mut packet_writer = self.packet_io_factory.create_temp_writer(&mut *writer);
-                    packet_writer.write_protocol_message(ack_line.as_bytes())?;
-                }
+        // Acknowledge the haves unless the client is done negotiating, and only send a pack once ready
+        let seen_haves = parameters.iter().any(|line| line.starts_with(b"have "));
+        if seen_haves
+            && !session.negotiation.done
+            && !self.send_acknowledgments(writer, parameters, session, wait_for_done)?
+        {
+            writer.write_flush()?;
+            return Ok(());
+        }
 
-                // End acknowledgments
-                // Use injected packet I/O factory for consistent packet encoding
-                let mut packet_writer = self.packet_io_factory.create_temp_writer(&mut *writer);
-                packet_writer.write_flush()?;
+        // Like git, shallow clients learn about their boundary with each pack, even if it doesn't change
+        session.negotiation.shallow_boundary = negotiation::ShallowBoundary::compute(
+            self.repository,
+            self.options,
+            &session.negotiation,
+            session.capabilities.deepen_relative(),
+        )?;
+        if negotiation::shallow::is_shallow(&session.negotiation) {
+            writer.write_protocol_message(b"shallow-info\n")?;
+            for line in session.negotiation.shallow_boundary.lines() {
+                writer.write_protocol_message(&line)?;
             }
+            writer.write_delimiter()?;
+        }
+        drop(negotiation);
+
+        let _phase = self
+            .options
+            .metrics
+            .phase(ServiceKind::UploadPack, Phase::PackGeneration);
+        // Blobs offloaded to packfile URIs are only known after counting, which has to precede the sections
+        let estimate =
+            if session.capabilities.packfile_uri_protocols().is_empty() || self.options.packfile_uris.is_empty() {
+                None
+            } else {
+                let result = self.pack_generator.estimate(session);
+                Some(cancel::notify(writer, result)?)
+            };
+        if let Some(estimate) = estimate.as_ref().filter(|estimate| !estimate.packfile_uris.is_empty()) {
+            writer.write_protocol_message(b"packfile-uris\n")?;
+            for uri in &estimate.packfile_uris {
+                writer.write_protocol_message(&uri.to_line())?;
+            }
+            writer.write_delimiter()?;
+        }
 
-            // Send packfile section
-            writer.write_protocol_message(b"packfile\n")?;
-
-            // Generate and send pack using EnhancedPacketWriter for proper sideband handling
-            let pack_generator = self.pack_generator;
-            let pack_stats = pack_generator.generate_pack(writer, session)?;
+        // Send packfile section
+        writer.write_protocol_message(b"packfile\n")?;
+        writer.establish_sideband()?;
 
-            eprintln!("Debug: Pack generation complete - stats: {:?}", pack_stats);
-        }
+        // Generate and send pack using EnhancedPacketWriter for proper sideband handling
+        let pack_generator = self.pack_generator;
+        let result = match estimate {
+            Some(estimate) => pack_generator.generate_pack_from_estimate(writer, session, estimate),
+            None => pack_generator.generate_pack(writer, session),
+        };
+        let pack_stats = cancel::notify(writer, result)?;
+        self.options
+            .metrics
+            .pack_sent(pack_stats.object_count.into(), pack_stats.pack_size);
+
+        debug!(
+            self.options.logger,
+            "Pack generation complete - stats: {:?}", pack_stats
+        );
 
         Ok(())
     }
 
-    /// Read fetch parameters from client
-    fn read_fetch_parameters<R: BufRead>(
+    /// Send the `acknowledgments` section for the haves among `parameters`, and return `true` if it ended with `ready`
+    ///
+    /// Common haves are acknowledged in the order the client sent them, with `NAK` if there are none. Once ready, a
+    /// delimiter separates the section from the pack that follows in the same response. Otherwise the caller ends the
+    /// response and the client continues with another request.
+    fn send_acknowledgments<W: Write>(
         &self,
-        reader: &mut StreamingPeekableIter<R>,
-        session: &mut SessionContext,
-    ) -> Result<()> {
-        while let Some(line_result) = reader.read_line() {
-            let line = line_result??;
-            if matches!(line, gix_packetline::PacketLineRef::Flush) {
-                break;
+        writer: &mut EnhancedPacketWriter<W>,
+        parameters: &[BString],
+        session: &SessionContext,
+        wait_for_done: bool,
+    ) -> Result<bool> {
+        writer.write_protocol_message(b"acknowledgments\n")?;
+        let mut acknowledged = std::collections::HashSet::new();
+        for have in parameters.iter().filter_map(|line| line.strip_prefix(b"have ")) {
+            let Ok(oid) = gix_hash::ObjectId::from_hex(have.trim_ascii()) else {
+                continue;
+            };
+            if session.negotiation.common.contains(&oid) && acknowledged.insert(oid) {
+                writer.write_protocol_message(format!("ACK {}\n", oid.to_hex()).as_bytes())?;
             }
+        }
+        if acknowledged.is_empty() {
+            writer.write_protocol_message(b"NAK\n")?;
+        }
 
-            if let Some(line_data) = line.as_slice() {
-                if let Some(want_line) = line_data.strip_prefix(b"want ") {
-                    // Use centralized command parser
-                    self.command_parser.parse_want_line(want_line, session)?;
-                } else if let Some(have_line) = line_data.strip_prefix(b"have ") {
-                    // Use centralized command parser
-                    let _is_common = self.command_parser.parse_have_line(have_line, session)?;
-                } else if let Some(shallow_line) = line_data.strip_prefix(b"shallow ") {
-                    // Use centralized command parser
-                    self.command_parser.parse_shallow_line(shallow_line, session)?;
-                } else if let Some(deepen_line) = line_data.strip_prefix(b"deepen ") {
-                    // Use centralized command parser
-                    self.command_parser.parse_deepen_line(deepen_line, session)?;
-                } else if let Some(deepen_since_line) = line_data.strip_prefix(b"deepen-since ") {
-                    // Use centralized command parser
-                    self.command_parser
-                        .parse_deepen_since_line(deepen_since_line, session)?;
-                } else if let Some(deepen_not_line) = line_data.strip_prefix(b"deepen-not ") {
-                    // Use centralized command parser
-                    self.command_parser.parse_deepen_not_line(deepen_not_line, session)?;
-                } else if line_data.trim_ascii() == b"done" {
-                    // Use centralized command parser
-                    self.command_parser.parse_done_line(session)?;
-                    break;
-                }
+        let ready = !wait_for_done
+            && negotiation::ready_to_send_pack(&CommitGraph::new(self.repository), &session.negotiation)?;
+        if ready {
+            writer.write_protocol_message(b"ready\n")?;
+            writer.write_delimiter()?;
+        }
+        debug!(
+            self.options.logger,
+            "Acknowledged {} of the client's haves, ready: {}",
+            acknowledged.len(),
+            ready
+        );
+        Ok(ready)
+    }
+
+    /// Read fetch parameters from the arguments of the request
+    fn read_fetch_parameters(&self, parameters: &[BString], session: &mut SessionContext) -> Result<()> {
+        for line_data in parameters {
+            if let Some(want_line) = line_data.strip_prefix(b"want ") {
+                // Use centralized command parser
+                self.command_parser.parse_want_line(want_line, session)?;
+            } else if let Some(have_line) = line_data.strip_prefix(b"have ") {
+                // Use centralized command parser
+                let _is_common = self.command_parser.parse_have_line(have_line, session)?;
+            } else if let Some(shallow_line) = line_data.strip_prefix(b"shallow ") {
+                // Use centralized command parser
+                self.command_parser.parse_shallow_line(shallow_line, session)?;
+            } else if let Some(deepen_line) = line_data.strip_prefix(b"deepen ") {
+                // Use centralized command parser
+                self.command_parser.parse_deepen_line(deepen_line, session)?;
+            } else if let Some(deepen_since_line) = line_data.strip_prefix(b"deepen-since ") {
+                // Use centralized command parser
+                self.command_parser
+                    .parse_deepen_since_line(deepen_since_line, session)?;
+            } else if let Some(deepen_not_line) = line_data.strip_prefix(b"deepen-not ") {
+                // Use centralized command parser
+                self.command_parser.parse_deepen_not_line(deepen_not_line, session)?;
+            } else if line_data.trim_ascii() == b"done" {
+                // Use centralized command parser
+                self.command_parser.parse_done_line(session)?;
+                break;
             }
         }
 
@@ -420,10 +482,10 @@ impl<'a> Handler<'a> {
     }
 
     /// Handle session with injected packet I/O
-    pub fn handle_session_with_io<R: Read, W: Write>(
+    pub fn handle_session_with_io<R: Read>(
         &mut self,
         reader: EnhancedPacketReader<R>,
-        writer: &mut EnhancedPacketWriter<W>,
+        writer: &mut Response<'_>,
         session: &mut SessionContext,
     ) -> Result<()> {
         // Check if we're in advertise-refs mode
@@ -433,65 +495,45 @@ impl<'a> Handler<'a> {
             return Ok(());
         }
 
-        let input = reader.into_inner();
-        let mut buffered_input = BufReader::new(input);
-        let mut line_reader = gix_packetline::StreamingPeekableIter::new(
-            &mut buffered_input,
-            &[PacketLineRef::Flush],
-            false, // trace
-        );
+        let mut input = BufReader::new(reader.into_inner());
 
         // Protocol V2 only advertises capabilities in non-stateless RPC mode
         // In stateless RPC mode (--stateless-rpc), we wait for client command first
         if !session.stateless_rpc {
             self.advertise_capabilities(writer.inner_mut())?;
+            writer.flush()?;
         }
 
-        // Wait for command
-        let mut command = None;
-        while let Some(line_result) = line_reader.read_line() {
-            let line = line_result??;
-            if matches!(line, gix_packetline::PacketLineRef::Flush) {
-                break;
-            }
+        // Stateful connections serve one command after another, like `ls-refs` followed by rounds of `fetch`, until
+        // the client disconnects, while stateless ones serve a single command per connection.
+        loop {
+            // An empty request, or none at all, ends the session
+            let Some(request) = Request::read_from(&mut input)? else {
+                return Ok(());
+            };
+            self.capability_manager.negotiate_object_format(&request.capabilities)?;
 
-            if let Some(line_data) = line.as_slice() {
-                if let Some(cmd_line) = line_data.strip_prefix(b"command=") {
-                    let cmd_str = std::str::from_utf8(cmd_line.trim_ascii())
-                        .map_err(|_| Error::custom("Invalid UTF-8 in command line"))?;
-
-                    command = match cmd_str {
-                        "ls-refs" => Some(Command::LsRefs),
-                        "fetch" => Some(Command::Fetch),
-                        _ => None, // Unsupported command
-                    };
-                    break;
-                }
-            }
-        }
+            // Common commits found in previous rounds carry over, even though clients repeat them
+            session.negotiation.next_round();
 
-        // Parse command arguments - but STOP at first flush or want/have line
-        let args = self.parse_command_arguments_v2(&mut line_reader)?;
+            self.options.v2_commands.serve(self, &request, writer, session)?;
+            writer.flush()?;
 
-        // Handle the command
-        match command {
-            Some(Command::LsRefs) => {
-                self.handle_ls_refs(&mut line_reader, writer.inner_mut(), &args)?;
-            }
-            Some(Command::Fetch) => {
-                self.handle_fetch(&mut line_reader, writer, &args, session)?;
-            }
-            None => {
-                return Err(Error::custom(format!("Unsupported command: {:?}", command)));
+            if session.stateless_rpc {
+                return Ok(());
             }
+            writer.start_response();
         }
-
-        Ok(())
     }
 }
 
 impl<'a> ProtocolHandler for Handler<'a> {
-    fn handle_session<R: Read, W: Write>(&mut self, input: R, output: W, session: &mut SessionContext) -> Result<()> {
+    fn handle_session<R: Read, W: Write>(
+        &mut self,
+        input: R,
+        mut output: W,
+        session: &mut SessionContext,
+    ) -> Result<()> {
         // Use injected packet I/O factory
         let reader = self.packet_io_factory.create_reader(input, false);
 
@@ -503,7 +545,10 @@ impl<'a> ProtocolHandler for Handler<'a> {
             crate::types::SideBandMode::None
         };
 
-        let mut writer = self.packet_io_factory.create_writer(output, sideband_mode);
+        // Commands are trait objects, which write their responses without knowing the type of the output
+        let mut writer = self
+            .packet_io_factory
+            .create_writer(&mut output as &mut dyn Write, sideband_mode);
 
         // Delegate to the method with injected I/O
         self.handle_session_with_io(reader, &mut writer, session)
@@ -0,0 +1,312 @@
+//! Serving sessions over [`AsyncRead`] and [`AsyncWrite`], available with the `async-io` feature
+//!
+//! This is a thread bridge, not an async implementation of the protocol: the protocol handlers and packet I/O are
+//! synchronous, as pack generation is CPU-bound and blocking either way. [`Server::serve_async()`] runs the session on
+//! a dedicated OS thread with a copy of the server, one thread per session, and moves the bytes between it and the
+//! async streams over channels, so the executor is never blocked. Writes are buffered and wait for the async output
+//! to accept them, which applies its backpressure to the session. Handlers over the async I/O of `gix-packetline` are
+//! out of scope, they would duplicate every handler without sparing the thread that pack generation needs anyway.
+//!
+//! Sessions end once the client was inactive for the [timeout](crate::ServerOptions::timeout) like over blocking
+//! streams, as the session thread stops waiting for reads and writes that didn't complete in time, and the future
+//! completes with the error right away.
+//!
+//! No runtime is required, the future can be polled by any executor. The session thread isn't joined, it ends on its
+//! own once it reported the result of the session. If the future is dropped before completion, the session fails with
+//! [`BrokenPipe`](std::io::ErrorKind::BrokenPipe) at its next read or write and its thread ends.
+
+use super::{protocol_detection, Server, SessionOutcome};
+use crate::{
+    error::{Error, Result},
+    log::debug,
+    services::IdleMonitor,
+    types::ProtocolVersion,
+};
+use futures_io::{AsyncRead, AsyncWrite};
+use std::cell::Cell;
+use std::collections::VecDeque;
+use std::future::{poll_fn, Future};
+use std::io::{self, Read, Write};
+use std::pin::Pin;
+use std::sync::{mpsc, Arc, Mutex};
+use std::task::{Poll, Waker};
+use std::time::Duration;
+
+/// The amount of output collected before it is written to the async output, and the most input read at once
+const CHUNK_SIZE: usize = 64 * 1024;
+
+impl Server {
+    /// Serve upload-pack protocol over the given async input/output streams, like [`serve()`](Self::serve) does
+    ///
+    /// The protocol version is the one the client asked for in `GIT_PROTOCOL` and is negotiated like for blocking
+    /// streams, use [`serve_async_with_version()`](Self::serve_async_with_version) to pass the version the client asked
+    /// for instead.
+    pub async fn serve_async<R, W>(&mut self, input: R, output: W) -> Result<SessionOutcome>
+    where
+        R: AsyncRead + Unpin,
+        W: AsyncWrite + Unpin,
+    {
+        let protocol_version = protocol_detection::ProtocolDetector::detect_version()?;
+        self.serve_async_with_version(input, output, protocol_version).await
+    }
+
+    /// Serve upload-pack protocol for a client of `protocol_version` over the given async input/output streams
+    ///
+    /// This is for frontends which learn the version from the transport, like the `Git-Protocol` header of HTTP.
+    /// Stateless requests of an older version are served with it, see
+    /// [`ProtocolDetector::negotiate()`](protocol_detection::ProtocolDetector::negotiate). The session occupies an OS
+    /// thread until it ends, see the [module documentation](self).
+    pub async fn serve_async_with_version<R, W>(
+        &mut self,
+        mut input: R,
+        mut output: W,
+        protocol_version: ProtocolVersion,
+    ) -> Result<SessionOutcome>
+    where
+        R: AsyncRead + Unpin,
+        W: AsyncWrite + Unpin,
+    {
+        let options = self.session_options()?;
+        debug!(
+            options.logger,
+            "Serving asynchronously with protocol version: {}",
+            protocol_detection::ProtocolDetector::version_string(protocol_version)
+        );
+        let mut server = self.detached();
+        let requests = Arc::new(Requests::default());
+        let (replies, reply_rx) = mpsc::channel();
+        let session = Session {
+            requests: requests.clone(),
+            replies: reply_rx,
+            timeout: options.timeout,
+            abandoned: Cell::new(false),
+        };
+        // The thread is detached, it reports its result last and ends right after
+        std::thread::Builder::new()
+            .name("gix-upload-pack session".into())
+            .spawn(move || {
+                let mut report = Report {
+                    requests: session.requests.clone(),
+                    result: None,
+                };
+                let mut bridge = Output {
+                    session: &session,
+                    buf: Vec::new(),
+                };
+                let result = server
+                    .serve_with(
+                        Input(&session),
+                        &mut bridge,
+                        &options,
+                        protocol_version,
+                        IdleMonitor::default(),
+                    )
+                    .and_then(|outcome| {
+                        bridge.flush()?;
+                        Ok(outcome)
+                    });
+                report.result = Some(result);
+            })?;
+
+        let result = loop {
+            let reply = match poll_fn(|cx| requests.poll_next(cx.waker())).await {
+                Request::Read(capacity) => {
+                    let mut buf = vec![0; capacity];
+                    let read = poll_fn(|cx| Pin::new(&mut input).poll_read(cx, &mut buf));
+                    unless_abandoned(&requests, read).await.map(|read| {
+                        Reply::Read(read.map(|len| {
+                            buf.truncate(len);
+                            buf
+                        }))
+                    })
+                }
+                Request::Write(data) => unless_abandoned(&requests, write_all(&mut output, &data))
+                    .await
+                    .map(Reply::Written),
+                Request::Flush => {
+                    let flush = poll_fn(|cx| Pin::new(&mut output).poll_flush(cx));
+                    unless_abandoned(&requests, flush).await.map(Reply::Written)
+                }
+                Request::Done(result) => break result,
+            };
+            // The session thread only goes away after sending `Done`, and expects no reply once it stopped waiting
+            if let Some(reply) = reply {
+                replies.send(reply).ok();
+            }
+        };
+        result.ok_or_else(|| Error::custom("the session thread panicked"))?
+    }
+}
+
+/// Run `operation` for the session thread, or return `None` once the thread made another request instead of waiting
+/// for it to complete, as it timed out.
+async fn unless_abandoned<T>(requests: &Requests, operation: impl Future<Output = T>) -> Option<T> {
+    let mut operation = std::pin::pin!(operation);
+    poll_fn(|cx| match operation.as_mut().poll(cx) {
+        Poll::Ready(value) => Poll::Ready(Some(value)),
+        Poll::Pending => requests.poll_queued(cx.waker()).map(|()| None),
+    })
+    .await
+}
+
+/// Write all of `data` to `output`
+async fn write_all<W: AsyncWrite + Unpin>(output: &mut W, mut data: &[u8]) -> io::Result<()> {
+    while !data.is_empty() {
+        let written = poll_fn(|cx| Pin::new(&mut *output).poll_write(cx, data)).await?;
+        if written == 0 {
+            return Err(io::ErrorKind::WriteZero.into());
+        }
+        data = &data[written..];
+    }
+    Ok(())
+}
+
+/// What the session thread asks the async side to do
+enum Request {
+    /// Read up to this many bytes of input
+    Read(usize),
+    /// Write all of the data to the output
+    Write(Vec<u8>),
+    /// Flush the output
+    Flush,
+    /// The session is over with the given result, or `None` if the session thread panicked
+    Done(Option<Result<SessionOutcome>>),
+}
+
+/// The answer of the async side to a [`Request`]
+enum Reply {
+    Read(io::Result<Vec<u8>>),
+    Written(io::Result<()>),
+}
+
+/// The requests of the session thread, and the waker of the async side waiting for them
+#[derive(Default)]
+struct Requests(Mutex<(VecDeque<Request>, Option<Waker>)>);
+
+impl Requests {
+    fn push(&self, request: Request) {
+        let mut state = self.0.lock().expect("not poisoned");
+        state.0.push_back(request);
+        if let Some(waker) = state.1.take() {
+            waker.wake();
+        }
+    }
+
+    /// Return ready once a request is queued, without taking it.
+    fn poll_queued(&self, waker: &Waker) -> Poll<()> {
+        let mut state = self.0.lock().expect("not poisoned");
+        if state.0.is_empty() {
+            state.1 = Some(waker.clone());
+            Poll::Pending
+        } else {
+            Poll::Ready(())
+        }
+    }
+
+    fn poll_next(&self, waker: &Waker) -> Poll<Request> {
+        let mut state = self.0.lock().expect("not poisoned");
+        match state.0.pop_front() {
+            Some(request) => Poll::Ready(request),
+            None => {
+                state.1 = Some(waker.clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+/// Sends the result of the session to the async side, even if the session thread panics
+struct Report {
+    requests: Arc<Requests>,
+    result: Option<Result<SessionOutcome>>,
+}
+
+impl Drop for Report {
+    fn drop(&mut self) {
+        self.requests.push(Request::Done(self.result.take()));
+    }
+}
+
+/// The session thread's end of the connection to the async side
+struct Session {
+    requests: Arc<Requests>,
+    replies: mpsc::Receiver<Reply>,
+    /// How long to wait for a reply before the client counts as inactive
+    timeout: Option<Duration>,
+    /// Whether a reply didn't arrive in time, after which the replies can't be matched to requests anymore
+    abandoned: Cell<bool>,
+}
+
+impl Session {
+    fn request(&self, request: Request) -> io::Result<Reply> {
+        let timed_out = || io::Error::new(io::ErrorKind::TimedOut, "the client was inactive");
+        if self.abandoned.get() {
+            return Err(timed_out());
+        }
+        self.requests.push(request);
+        let dropped = || io::Error::new(io::ErrorKind::BrokenPipe, "the session was dropped");
+        match self.timeout {
+            Some(timeout) => self.replies.recv_timeout(timeout).map_err(|err| match err {
+                mpsc::RecvTimeoutError::Timeout => {
+                    self.abandoned.set(true);
+                    timed_out()
+                }
+                mpsc::RecvTimeoutError::Disconnected => dropped(),
+            }),
+            None => self.replies.recv().map_err(|_| dropped()),
+        }
+    }
+}
+
+/// The blocking input of the session thread, read by the async side
+struct Input<'a>(&'a Session);
+
+impl Read for Input<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.0.request(Request::Read(buf.len().min(CHUNK_SIZE)))? {
+            Reply::Read(data) => {
+                let data = data?;
+                buf[..data.len()].copy_from_slice(&data);
+                Ok(data.len())
+            }
+            Reply::Written(_) => unreachable!("reads are answered with data"),
+        }
+    }
+}
+
+/// The buffered output of the session thread, written by the async side
+struct Output<'a> {
+    session: &'a Session,
+    buf: Vec<u8>,
+}
+
+impl Output<'_> {
+    /// Write the buffered output
+    fn send(&mut self) -> io::Result<()> {
+        if self.buf.is_empty() {
+            return Ok(());
+        }
+        match self.session.request(Request::Write(std::mem::take(&mut self.buf)))? {
+            Reply::Written(result) => result,
+            Reply::Read(_) => unreachable!("writes are answered with the result"),
+        }
+    }
+}
+
+impl Write for Output<'_> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.buf.extend_from_slice(data);
+        if self.buf.len() >= CHUNK_SIZE {
+            self.send()?;
+        }
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.send()?;
+        match self.session.request(Request::Flush)? {
+            Reply::Written(result) => result,
+            Reply::Read(_) => unreachable!("flushes are answered with the result"),
+        }
+    }
+}
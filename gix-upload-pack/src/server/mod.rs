@@ -3,14 +3,35 @@
 use crate::{
     config::ServerOptions,
     error::{Error, Result},
-    protocol::{v1, v2, ProtocolHandler},
+    log::debug,
+    protocol::{v1, v2, ProtocolHandler, Services},
+    services::{IdleMonitor, IdleTimeout},
     types::*,
 };
+use bstr::ByteSlice;
 use gix::Repository;
+use gix_serve_core::advertise::Fingerprint;
+use gix_serve_core::audit::Denial;
+use gix_serve_core::protocol::ServiceKind;
+use gix_serve_core::wire::{CountingReader, CountingWriter, WireStats};
 use std::io::{Read, Write};
 use std::path::{Path, PathBuf};
 
+#[cfg(feature = "async-io")]
+mod async_io;
 pub mod protocol_detection;
+#[cfg(feature = "serve-core")]
+mod service;
+pub mod step;
+
+pub use step::{Step, StepSession};
+
+/// What happened during a session served by [`Server::serve()`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SessionOutcome {
+    /// The bytes exchanged with the client, including the pack and its framing
+    pub wire: WireStats,
+}
 
 /// The main upload-pack server implementation
 #[derive(Debug)]
@@ -31,10 +52,11 @@ impl Server {
         let repository_path = repository_path.as_ref().to_path_buf();
 
         // Validate and open the repository
-        let repository = gix::open(&repository_path).map_err(|e| Error::Repository(e))?;
+        let mut repository = gix::open(&repository_path).map_err(Error::Repository)?;
 
         // Validate configuration
         options.validate()?;
+        set_namespace(&mut repository, &options)?;
 
         Ok(Self {
             repository,
@@ -57,69 +79,229 @@ impl Server {
     }
 
     /// Serve upload-pack protocol over the given input/output streams
-    pub fn serve<R: Read, W: Write>(&mut self, input: R, output: W) -> Result<()> {
-        let mut session = SessionContext::new(&self.repository_path);
-        session.stateless_rpc = self.options.stateless_rpc;
+    ///
+    /// The protocol version is the one the client asked for in `GIT_PROTOCOL`, see
+    /// [`ProtocolDetector`](protocol_detection::ProtocolDetector) for how it may be downgraded or overridden.
+    pub fn serve<R: Read, W: Write>(&mut self, input: R, output: W) -> Result<SessionOutcome> {
+        let options = self.session_options()?;
 
         // Determine protocol version using centralized detection
-        session.protocol_version = protocol_detection::ProtocolDetector::detect_version()?;
-        eprintln!(
-            "Debug: Using protocol version: {}",
-            protocol_detection::ProtocolDetector::version_string(session.protocol_version)
+        let protocol_version = protocol_detection::ProtocolDetector::detect_version()?;
+        debug!(
+            options.logger,
+            "Using protocol version: {}",
+            protocol_detection::ProtocolDetector::version_string(protocol_version)
         );
 
-        match session.protocol_version {
-            ProtocolVersion::V0 | ProtocolVersion::V1 => self.serve_v1(input, output, session),
-            ProtocolVersion::V2 => self.serve_v2(input, output, session),
+        self.serve_with(input, output, &options, protocol_version, IdleMonitor::default())
+    }
+
+    /// Serve like [`serve()`](Self::serve), reporting the inactivity of the client to `idle`
+    ///
+    /// Reads and writes that block can't be interrupted, so servers watch [`IdleMonitor::idle()`] from another thread
+    /// to end sessions whose client stopped for good, see [`IdleTimeout`].
+    pub fn serve_monitored<R: Read, W: Write>(
+        &mut self,
+        input: R,
+        output: W,
+        idle: &IdleMonitor,
+    ) -> Result<SessionOutcome> {
+        let options = self.session_options()?;
+        let protocol_version = protocol_detection::ProtocolDetector::detect_version()?;
+        self.serve_with(input, output, &options, protocol_version, idle.clone())
+    }
+
+    /// Start a resumable session for `protocol_version` that is driven with [`StepSession::serve_step()`]
+    ///
+    /// Use this instead of [`serve()`](Self::serve) when the output may return `WouldBlock`.
+    pub fn step_session(&mut self, protocol_version: ProtocolVersion) -> Result<StepSession<'_>> {
+        let options = self.session_options()?;
+        StepSession::new(self, options, protocol_version)
+    }
+
+    /// Return a copy of this server for serving a session on another thread
+    fn detached(&self) -> Server {
+        Server {
+            repository: self.repository.clone(),
+            options: self.options.clone(),
+            repository_path: self.repository_path.clone(),
         }
     }
 
-    /// Serve using protocol version 1
-    fn serve_v1<R: Read, W: Write>(&mut self, input: R, output: W, mut session: SessionContext) -> Result<()> {
-        // Create service dependencies
-        use crate::services::*;
-        let capability_manager = CapabilityManager::new(&self.repository, &self.options);
-        let command_parser = CommandParser::new(&self.repository);
-        let reference_manager = ReferenceManager::new(&self.repository, &self.options.hidden_refs);
-        let pack_generator = pack::PackGenerator::new(&self.repository, &self.options);
-        let packet_io_factory = PacketIOFactory::new();
-
-        // Create handler with dependency injection
-        let mut handler = v1::Handler::new(
-            &self.repository,
-            &self.options,
-            &capability_manager,
-            &command_parser,
-            &reference_manager,
-            &pack_generator,
-            &packet_io_factory,
+    /// Serve one session with the given `options` and `protocol_version`, reporting the inactivity of the client to `idle`
+    pub(crate) fn serve_with<R: Read, W: Write>(
+        &mut self,
+        input: R,
+        output: W,
+        options: &ServerOptions,
+        protocol_version: ProtocolVersion,
+        idle: IdleMonitor,
+    ) -> Result<SessionOutcome> {
+        if let Some(snapshot) = &options.ref_snapshot {
+            debug!(options.logger, "Serving ref snapshot {}", snapshot.id());
+        }
+        if let Some(remote_addr) = options.remote_addr {
+            debug!(options.logger, "Serving client {}", remote_addr);
+        }
+        if options.read_only {
+            debug!(options.logger, "Serving read-only");
+        }
+        let metrics = options.metrics.session(ServiceKind::UploadPack);
+        // Start with empty caches, sized for this session instead of whatever the previous one was configured for
+        options.object_caches().apply(&mut self.repository);
+
+        // Clients count as inactive while reads or writes block, which the same timeout applies to
+        let input = crate::services::StallReader::new(
+            IdleTimeout::new(input, options.timeout, idle.clone()),
+            options.stall_detection,
+        );
+        let monitor = input.monitor();
+        let mut input = CountingReader::new(input);
+        // Stateless clients send their request right away, which tells if they speak an older version than asked for
+        let mut request_start = Vec::new();
+        let negotiated = match options.protocol {
+            Some(version) => Ok(version),
+            None if options.stateless_rpc && !options.advertise_refs => (&mut input)
+                .take(protocol_detection::ProtocolDetector::REQUEST_START_LEN)
+                .read_to_end(&mut request_start)
+                .map(|_| protocol_detection::ProtocolDetector::negotiate(protocol_version, &request_start))
+                .map_err(Error::from),
+            None => Ok(protocol_version),
+        };
+        let requested = protocol_version;
+        let protocol_version = *negotiated.as_ref().unwrap_or(&requested);
+        if protocol_version != requested {
+            debug!(
+                options.logger,
+                "Serving protocol version {} instead of {}",
+                protocol_detection::ProtocolDetector::version_string(protocol_version),
+                protocol_detection::ProtocolDetector::version_string(requested)
+            );
+        }
+        let session = SessionContext::from_options(&self.repository_path, options, protocol_version);
+        let mut input = std::io::Cursor::new(request_start).chain(input);
+        let mut output = crate::services::GrammarWriter::new(
+            CountingWriter::new(IdleTimeout::new(output, options.timeout, idle.clone())),
+            protocol_version,
+            options.check_packet_grammar,
+            options.logger.clone(),
         );
+        let result = if options.cancellation.is_cancelled() {
+            // Sessions started after cancelling are refused before anything else is sent
+            let mut writer = crate::services::packet_io::EnhancedPacketWriter::new(&mut output, SideBandMode::None);
+            crate::cancel::notify(&mut writer, Err(Error::Cancelled))
+        } else if let Err(err) = negotiated {
+            Err(err)
+        } else {
+            match session.protocol_version {
+                ProtocolVersion::V0 | ProtocolVersion::V1 => self.serve_v1(&mut input, &mut output, session, options),
+                ProtocolVersion::V2 => self.serve_v2(&mut input, &mut output, session, options),
+            }
+        };
+        if result.is_ok() {
+            output.finish();
+        }
+        let input = input.get_ref().1;
+        metrics.finish(&(input.stats() + output.get_ref().stats()), result.is_ok());
+        let result = match (monitor.stalled(), idle.timed_out()) {
+            (Some(stalled), _) if result.is_err() => Err(Error::Resource(stalled.to_string())),
+            (None, Some(timed_out)) if result.is_err() => Err(Error::Resource(timed_out.to_string())),
+            _ => result.map(|()| SessionOutcome {
+                wire: input.stats() + output.get_ref().stats(),
+            }),
+        };
+        if let Err(err) = &result {
+            self.audit(err, options);
+        }
+        result
+    }
+
+    /// Record `err` with the auditor of `options` if it denied the request
+    fn audit(&self, err: &Error, options: &ServerOptions) {
+        let Some(reason) = err.denial_reason() else {
+            return;
+        };
+        let mut denial = Denial::new(ServiceKind::UploadPack, &self.repository_path, reason, err.to_string());
+        denial.principal.clone_from(&options.principal);
+        denial.remote_addr = options.remote_addr;
+        options.auditor.record(&denial);
+    }
+
+    /// Return the options for a new session, with the repository's `serve.*` overrides merged in if enabled
+    pub fn session_options(&self) -> Result<ServerOptions> {
+        if !self.options.repository_overrides {
+            return Ok(self.options.clone());
+        }
+        let overrides = crate::config::RepositoryOverrides::from_repository(&self.repository)?;
+        self.options.merged_with(&overrides)
+    }
+
+    /// Compute the fingerprint of the advertisement for `protocol_version`, without generating it
+    ///
+    /// It covers the advertised refs with their targets and the capabilities, so it changes whenever the
+    /// advertisement does. Annotated tags aren't peeled for it, as their peeled values can't change.
+    pub fn advertisement_fingerprint(&self, protocol_version: ProtocolVersion) -> Result<Fingerprint> {
+        use crate::services::*;
+        let options = self.session_options()?;
+        let capability_manager = CapabilityManager::new(&self.repository, &options);
+        let mut hasher = Fingerprint::hasher();
+        hasher
+            .update(protocol_detection::ProtocolDetector::version_string(protocol_version))
+            .update(crate::VERSION)
+            .update(self.repository.object_hash().to_string());
+        if protocol_version == ProtocolVersion::V2 {
+            // Protocol v2 advertises no refs
+            for line in capability_manager.get_v2_capability_lines(&options.capabilities) {
+                hasher.update(line);
+            }
+            return Ok(hasher.finalize());
+        }
+        for capability in capability_manager.get_v1_capability_strings(&options.capabilities) {
+            hasher.update(capability);
+        }
+        let refs = ReferenceManager::new(&self.repository, &options.hidden_refs)
+            .with_snapshot(options.ref_snapshot.as_deref())
+            .with_authorization(options.ref_authorization.as_ref())
+            .collect_references_with_prefixes(&[], false)?;
+        for reference in &refs {
+            let (name, target, _) = reference.unpack();
+            hasher.update(name);
+            hasher.update(target.map(|target| target.as_bytes()).unwrap_or_default());
+        }
+        Ok(hasher.finalize())
+    }
+
+    /// Serve using protocol version 1
+    fn serve_v1<R: Read, W: Write>(
+        &mut self,
+        input: R,
+        output: W,
+        mut session: SessionContext,
+        options: &ServerOptions,
+    ) -> Result<()> {
+        let services = Services::new(&self.repository, options);
+        let mut handler = v1::Handler::from_services(&self.repository, options, &services);
         handler.handle_session(input, output, &mut session)
     }
 
     /// Serve using protocol version 2
-    fn serve_v2<R: Read, W: Write>(&mut self, input: R, output: W, mut session: SessionContext) -> Result<()> {
-        // Create service dependencies
-        use crate::services::*;
-        let capability_manager = CapabilityManager::new(&self.repository, &self.options);
-        let command_parser = CommandParser::new(&self.repository);
-        let reference_manager = ReferenceManager::new(&self.repository, &self.options.hidden_refs);
-        let pack_generator = pack::PackGenerator::new(&self.repository, &self.options);
-        let packet_io_factory = PacketIOFactory::new();
-
-        // Create handler with dependency injection
-        let mut handler = v2::Handler::new(
-            &self.repository,
-            &self.options,
-            &capability_manager,
-            &command_parser,
-            &reference_manager,
-            &pack_generator,
-            &packet_io_factory,
-        );
+    fn serve_v2<R: Read, W: Write>(
+        &mut self,
+        input: R,
+        output: W,
+        mut session: SessionContext,
+        options: &ServerOptions,
+    ) -> Result<()> {
+        let services = Services::new(&self.repository, options);
+        let mut handler = v2::Handler::from_services(&self.repository, options, &services);
         handler.handle_session(input, output, &mut session)
     }
 
+    /// Describe `err` like `git upload-pack` does, with object ids abbreviated if the server options ask for it
+    pub fn error_message(&self, err: &Error) -> String {
+        err.native_message(&self.repository, self.options.abbreviate_object_ids)
+    }
+
     /// Get repository reference
     pub fn repository(&self) -> &Repository {
         &self.repository
@@ -138,6 +320,7 @@ impl Server {
     /// Update server options
     pub fn set_options(&mut self, options: ServerOptions) -> Result<()> {
         options.validate()?;
+        set_namespace(&mut self.repository, &options)?;
         self.options = options;
         Ok(())
     }
@@ -154,6 +337,26 @@ impl Server {
     }
 }
 
+/// Make `repository` read and write the refs of the namespace of `options`, or all refs if there is none
+///
+/// The ref store of `gix` strips the namespace from the names it returns and adds it to those it looks up, so all
+/// services see the refs of the namespace like the refs of a repository of its own.
+fn set_namespace(repository: &mut Repository, options: &ServerOptions) -> Result<()> {
+    match &options.namespace {
+        Some(namespace) => {
+            repository
+                .set_namespace(namespace.as_bstr())
+                .map_err(|err| Error::Config {
+                    message: format!("Invalid namespace '{namespace}': {err}"),
+                })?;
+        }
+        None => {
+            repository.clear_namespace();
+        }
+    }
+    Ok(())
+}
+
 /// Builder for creating and configuring a Server
 #[derive(Debug, Default)]
 pub struct ServerBuilder {
@@ -2,39 +2,65 @@
 //!
 //! This module centralizes protocol version detection logic and provides
 //! utilities for protocol-specific behavior.
+//!
+//! Clients ask for a version with `version=` parameters, passed in `GIT_PROTOCOL` by ssh and file transports or as
+//! extra parameters of the request line of `git://`, and get v0 if they didn't ask. Versions are only ever downgraded from
+//! there: clients of stateless sessions that asked for v2 but send a v0 request are served with v0, see
+//! [`ProtocolDetector::negotiate()`], and [`ServerOptions::protocol`](crate::ServerOptions::protocol) overrides all of it.
 
 use crate::{error::Result, types::ProtocolVersion};
+use gix_serve_core::classify::{classify, Classification, RequestKind};
 
 /// Protocol detection service
 pub struct ProtocolDetector;
 
 impl ProtocolDetector {
+    /// The number of bytes at the start of stateless requests that [`negotiate()`](Self::negotiate) needs to see,
+    /// enough for the length of the first pkt-line and a `command=` at its start
+    pub const REQUEST_START_LEN: u64 = 12;
+
     /// Detect protocol version from environment variable
     ///
-    /// This matches native git's determine_protocol_version_server behavior exactly
+    /// This matches native git's determine_protocol_version_server behavior, see [`from_git_protocol()`](Self::from_git_protocol)
     pub fn detect_version() -> Result<ProtocolVersion> {
-        // Check GIT_PROTOCOL environment variable exactly like native git
-        if let Ok(protocol) = std::env::var("GIT_PROTOCOL") {
-            match protocol.as_str() {
-                "version=0" => Ok(ProtocolVersion::V0),
-                "version=1" => Ok(ProtocolVersion::V1),
-                "version=2" => Ok(ProtocolVersion::V2),
-                _ => {
-                    // Invalid protocol version - native git would return protocol_unknown_version
-                    // For now, fall back to v0 (most conservative)
-                    Ok(ProtocolVersion::V0)
-                }
+        // No GIT_PROTOCOL environment variable - default to v0 like native git
+        Ok(std::env::var("GIT_PROTOCOL")
+            .map(|protocol| Self::from_git_protocol(&protocol))
+            .unwrap_or(ProtocolVersion::V0))
+    }
+
+    /// Return the version asked for by the colon-separated parameters of `GIT_PROTOCOL`
+    ///
+    /// Like native git, the highest of all `version=` parameters wins and unknown versions are ignored, so clients
+    /// that asked for nothing this server speaks get v0.
+    pub fn from_git_protocol(value: &str) -> ProtocolVersion {
+        match gix_serve_core::protocol::ProtocolVersion::from_git_protocol(value) {
+            gix_serve_core::protocol::ProtocolVersion::V0 => ProtocolVersion::V0,
+            gix_serve_core::protocol::ProtocolVersion::V1 => ProtocolVersion::V1,
+            gix_serve_core::protocol::ProtocolVersion::V2 => ProtocolVersion::V2,
+        }
+    }
+
+    /// Return the version a client that asked for `requested` speaks, judging by the first pkt-line at the `start` of
+    /// its stateless request
+    ///
+    /// Clients that asked for v2 but start their request with a `want` line speak v0, whose requests are the same as
+    /// those of v1, like when a proxy added `version=2` for them. Requests starting with `command=` or a flush packet
+    /// are valid in the requested version, and clients are never upgraded, as they have to ask for a version before
+    /// they may speak it. `start` needs [`REQUEST_START_LEN`](Self::REQUEST_START_LEN) bytes to tell, and the requested
+    /// version is kept if it is shorter or no request at all.
+    pub fn negotiate(requested: ProtocolVersion, start: &[u8]) -> ProtocolVersion {
+        match classify(start) {
+            Classification::Request(RequestKind::UploadPackV1) if requested == ProtocolVersion::V2 => {
+                ProtocolVersion::V0
             }
-        } else {
-            // No GIT_PROTOCOL environment variable - default to v0 like native git
-            // Native git uses v0 as the default when no protocol is specified
-            Ok(ProtocolVersion::V0)
+            Classification::Request(_) | Classification::Incomplete | Classification::Garbage => requested,
         }
     }
 
-    /// Check if we're using explicit protocol v1 (vs v0/default)
-    pub fn is_explicit_v1() -> bool {
-        std::env::var("GIT_PROTOCOL").map(|p| p == "version=1").unwrap_or(false)
+    /// Check if `version` is the explicitly requested protocol v1 (vs v0/default)
+    pub fn is_explicit_v1(version: ProtocolVersion) -> bool {
+        matches!(version, ProtocolVersion::V1)
     }
 
     /// Get protocol version string for logging/debugging
@@ -0,0 +1,79 @@
+//! The [`GitService`] implementation for [`Server`], available with the `serve-core` feature
+//!
+//! Orchestrators and HTTP adapters can hold the server as `Box<dyn GitService>` next to receive-pack. Protocol
+//! version and statelessness come from the [`ServiceContext`] instead of `GIT_PROTOCOL` and the server options.
+
+use super::Server;
+use crate::error::Error;
+use crate::services::IdleMonitor;
+use crate::types::ProtocolVersion;
+use gix_serve_core::advertise::Fingerprint;
+use gix_serve_core::protocol::{ProtocolVersion as ServiceVersion, ServiceKind};
+use gix_serve_core::service::{self, GitService, Outcome, ServiceContext};
+use std::io::{Read, Write};
+
+impl GitService for Server {
+    fn kind(&self) -> ServiceKind {
+        ServiceKind::UploadPack
+    }
+
+    fn advertise(&mut self, out: &mut dyn Write, ctx: &ServiceContext) -> Result<(), service::Error> {
+        let mut options = self.session_options().map_err(to_service_error)?;
+        options.advertise_refs = true;
+        options.stateless_rpc = ctx.stateless;
+        options.remote_addr = ctx.remote_addr.or(options.remote_addr);
+        options.principal = ctx.principal.clone().or(options.principal);
+        self.serve_with(
+            std::io::empty(),
+            out,
+            &options,
+            protocol_version(ctx),
+            IdleMonitor::default(),
+        )
+        .map(|_| ())
+        .map_err(to_service_error)
+    }
+
+    fn advertisement_fingerprint(&mut self, ctx: &ServiceContext) -> Result<Option<Fingerprint>, service::Error> {
+        Server::advertisement_fingerprint(self, protocol_version(ctx))
+            .map(Some)
+            .map_err(to_service_error)
+    }
+
+    fn serve(
+        &mut self,
+        input: &mut dyn Read,
+        output: &mut dyn Write,
+        ctx: &ServiceContext,
+    ) -> Result<Outcome, service::Error> {
+        let mut options = self.session_options().map_err(to_service_error)?;
+        options.advertise_refs = false;
+        options.stateless_rpc = ctx.stateless;
+        options.remote_addr = ctx.remote_addr.or(options.remote_addr);
+        options.principal = ctx.principal.clone().or(options.principal);
+        let outcome = self
+            .serve_with(input, output, &options, protocol_version(ctx), IdleMonitor::default())
+            .map_err(to_service_error)?;
+        Ok(Outcome {
+            wire: outcome.wire,
+            ..Outcome::default()
+        })
+    }
+}
+
+fn protocol_version(ctx: &ServiceContext) -> ProtocolVersion {
+    match ctx.version {
+        ServiceVersion::V0 => ProtocolVersion::V0,
+        ServiceVersion::V1 => ProtocolVersion::V1,
+        ServiceVersion::V2 => ProtocolVersion::V2,
+    }
+}
+
+/// Keep I/O errors as they are, and tell errors caused by the client apart from those of the server
+fn to_service_error(err: Error) -> service::Error {
+    match err {
+        Error::Io(err) => service::Error::Io(err),
+        err if err.is_client_error() => service::Error::Validation(err.to_string()),
+        err => service::Error::Internal(err.to_string()),
+    }
+}
@@ -0,0 +1,436 @@
+//! Resumable upload-pack sessions for non-blocking writers
+//!
+//! [`StepSession`] lets integrators with their own event loop drive a session incrementally:
+//! feed input as it arrives with [`StepSession::push_input()`], call [`StepSession::serve_step()`]
+//! whenever input was added or the output became writable, and act on the returned [`Step`].
+//!
+//! The session is the one [`Server::serve()`] would serve, stateful or stateless as configured, so
+//! stateful v0/v1 clients can negotiate over as many rounds of haves as they need. It runs on a
+//! dedicated thread with a copy of the server, which waits for input that wasn't pushed yet and
+//! hands over its output in chunks of at most [`CHUNK_SIZE`]. It only produces the next chunk once
+//! the previous one was written, so writes returning [`std::io::ErrorKind::WouldBlock`] hold up
+//! the session instead of letting its output pile up.
+//!
+//! Dropping the session before it is done makes its thread fail with
+//! [`BrokenPipe`](std::io::ErrorKind::BrokenPipe) at its next read or write, which ends it.
+
+use super::{Server, SessionOutcome};
+use crate::{
+    config::ServerOptions,
+    error::{Error, Result},
+    services::IdleMonitor,
+    types::ProtocolVersion,
+};
+use gix_serve_core::wire::WireStats;
+use std::io::{self, ErrorKind, Read, Write};
+use std::marker::PhantomData;
+use std::sync::mpsc;
+use std::thread::JoinHandle;
+
+/// The most output the session thread collects before handing it over
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// What a [`StepSession`] needs before it can make progress
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Step {
+    /// All output was written, more client input is needed to continue
+    NeedInput,
+    /// The writer returned `WouldBlock`, call again once it is writable
+    WantWrite,
+    /// The session is complete and all output was written and flushed
+    Done,
+}
+
+/// What the session thread tells the [`StepSession`]
+enum Event {
+    /// Write this output, and flush it
+    Output(Vec<u8>),
+    /// All input pushed so far was read, the count of chunks read is given, and more is needed
+    NeedInput(usize),
+    /// The session is over with the given result, or `None` if the session thread panicked
+    Done(Option<Result<SessionOutcome>>),
+}
+
+/// A resumable upload-pack session, see the [module documentation](self)
+pub struct StepSession<'a> {
+    /// The chunks of input for the session thread, or `None` once all input was pushed
+    input: Option<mpsc::Sender<Vec<u8>>>,
+    /// The number of chunks sent to the session thread
+    pushed: usize,
+    /// The number of chunks the session thread had read when it asked for more, if it waits for input
+    waiting: Option<usize>,
+    events: mpsc::Receiver<Event>,
+    thread: Option<JoinHandle<()>>,
+    output: Vec<u8>,
+    written: usize,
+    flushed: bool,
+    result: Option<Result<SessionOutcome>>,
+    done: bool,
+    wire: WireStats,
+    /// The server isn't used while the session runs on its copy, but stays borrowed like it is for [`Server::serve()`]
+    _server: PhantomData<&'a mut Server>,
+}
+
+impl<'a> StepSession<'a> {
+    pub(crate) fn new(
+        server: &'a mut Server,
+        options: ServerOptions,
+        protocol_version: ProtocolVersion,
+    ) -> Result<Self> {
+        let mut server = server.detached();
+        let (input, input_rx) = mpsc::channel();
+        // Without capacity, the session thread waits for each chunk to be taken before producing the next one
+        let (events_tx, events) = mpsc::sync_channel(0);
+        let thread = std::thread::Builder::new()
+            .name("gix-upload-pack step session".into())
+            .spawn(move || {
+                let mut report = Report {
+                    events: events_tx.clone(),
+                    result: None,
+                };
+                let mut output = Output {
+                    events: events_tx.clone(),
+                    buf: Vec::new(),
+                };
+                let input = Input {
+                    chunks: input_rx,
+                    events: events_tx,
+                    received: 0,
+                    chunk: Vec::new(),
+                    pos: 0,
+                };
+                let result = server.serve_with(input, &mut output, &options, protocol_version, IdleMonitor::default());
+                // Whatever was written is sent, like the error messages of failed sessions
+                let flushed = output.flush();
+                report.result = Some(result.and_then(|outcome| {
+                    flushed?;
+                    Ok(outcome)
+                }));
+            })?;
+        Ok(Self {
+            input: Some(input),
+            pushed: 0,
+            waiting: None,
+            events,
+            thread: Some(thread),
+            output: Vec::new(),
+            written: 0,
+            flushed: true,
+            result: None,
+            done: false,
+            wire: WireStats::default(),
+            _server: PhantomData,
+        })
+    }
+
+    /// Add client input as it was received
+    pub fn push_input(&mut self, data: &[u8]) {
+        let Some(input) = &self.input else {
+            return;
+        };
+        if data.is_empty() {
+            return;
+        }
+        // The session thread only goes away once it is done, and then it doesn't need input anymore
+        if input.send(data.to_vec()).is_ok() {
+            self.pushed += 1;
+        }
+    }
+
+    /// Signal that the client will not send any more input
+    pub fn finish_input(&mut self) {
+        self.input = None;
+    }
+
+    /// Return the amount of output that was handed over by the session but still has to be written
+    pub fn pending_output(&self) -> usize {
+        self.output.len() - self.written
+    }
+
+    /// Return the bytes of all requests and responses of the session once it is [`Done`](Step::Done)
+    pub fn wire(&self) -> WireStats {
+        self.wire
+    }
+
+    /// Make as much progress as possible, writing to `out` until it would block or more input is needed
+    ///
+    /// This blocks while the session computes its next output, like the pack, but never waits for `out` or for input.
+    pub fn serve_step<W: Write>(&mut self, out: &mut W) -> Result<Step> {
+        loop {
+            if !self.drain(out)? {
+                return Ok(Step::WantWrite);
+            }
+            if let Some(result) = self.result.take() {
+                self.done = true;
+                self.wire = result?.wire;
+            }
+            if self.done {
+                return Ok(Step::Done);
+            }
+            if let Some(received) = self.waiting {
+                if received == self.pushed && self.input.is_some() {
+                    return Ok(Step::NeedInput);
+                }
+                self.waiting = None;
+            }
+
+            match self.events.recv() {
+                Ok(Event::Output(data)) => {
+                    self.output = data;
+                    self.written = 0;
+                    self.flushed = false;
+                }
+                Ok(Event::NeedInput(received)) => self.waiting = Some(received),
+                Ok(Event::Done(result)) => {
+                    let thread = self.thread.take().expect("the session thread reports its result once");
+                    if let Err(panic) = thread.join() {
+                        std::panic::resume_unwind(panic);
+                    }
+                    self.result = Some(result.expect("the session thread reports its result unless it panicked"));
+                }
+                Err(_) => unreachable!("the session thread reports its result before going away"),
+            }
+        }
+    }
+
+    /// Write and flush the output handed over last, returning `false` if `out` would block
+    fn drain<W: Write>(&mut self, out: &mut W) -> Result<bool> {
+        while self.written < self.output.len() {
+            match out.write(&self.output[self.written..]) {
+                Ok(0) => return Err(Error::Io(ErrorKind::WriteZero.into())),
+                Ok(n) => self.written += n,
+                Err(err) if err.kind() == ErrorKind::WouldBlock => return Ok(false),
+                Err(err) if err.kind() == ErrorKind::Interrupted => {}
+                Err(err) => return Err(err.into()),
+            }
+        }
+        if !self.flushed {
+            match out.flush() {
+                Ok(()) => self.flushed = true,
+                Err(err) if err.kind() == ErrorKind::WouldBlock => return Ok(false),
+                Err(err) => return Err(err.into()),
+            }
+        }
+        Ok(true)
+    }
+}
+
+/// Sends the result of the session to the [`StepSession`], even if the session thread panics
+struct Report {
+    events: mpsc::SyncSender<Event>,
+    result: Option<Result<SessionOutcome>>,
+}
+
+impl Drop for Report {
+    fn drop(&mut self) {
+        self.events.send(Event::Done(self.result.take())).ok();
+    }
+}
+
+/// Turn a failure to reach the [`StepSession`] into an I/O error
+fn dropped<T>(_: T) -> io::Error {
+    io::Error::new(ErrorKind::BrokenPipe, "the session was dropped")
+}
+
+/// The input of the session thread, pushed by the [`StepSession`]
+struct Input {
+    chunks: mpsc::Receiver<Vec<u8>>,
+    events: mpsc::SyncSender<Event>,
+    received: usize,
+    chunk: Vec<u8>,
+    pos: usize,
+}
+
+impl Read for Input {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos == self.chunk.len() {
+            let chunk = match self.chunks.try_recv() {
+                Ok(chunk) => chunk,
+                Err(mpsc::TryRecvError::Disconnected) => return Ok(0),
+                Err(mpsc::TryRecvError::Empty) => {
+                    self.events.send(Event::NeedInput(self.received)).map_err(dropped)?;
+                    match self.chunks.recv() {
+                        Ok(chunk) => chunk,
+                        Err(mpsc::RecvError) => return Ok(0),
+                    }
+                }
+            };
+            self.received += 1;
+            self.chunk = chunk;
+            self.pos = 0;
+        }
+        let len = buf.len().min(self.chunk.len() - self.pos);
+        buf[..len].copy_from_slice(&self.chunk[self.pos..][..len]);
+        self.pos += len;
+        Ok(len)
+    }
+}
+
+/// The buffered output of the session thread, written by the [`StepSession`]
+struct Output {
+    events: mpsc::SyncSender<Event>,
+    buf: Vec<u8>,
+}
+
+impl Output {
+    /// Hand over the buffered output and wait until it was taken
+    fn send(&mut self) -> io::Result<()> {
+        if self.buf.is_empty() {
+            return Ok(());
+        }
+        self.events
+            .send(Event::Output(std::mem::take(&mut self.buf)))
+            .map_err(dropped)
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        let len = data.len().min(CHUNK_SIZE - self.buf.len());
+        self.buf.extend_from_slice(&data[..len]);
+        if self.buf.len() == CHUNK_SIZE {
+            self.send()?;
+        }
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.send()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A writer that accepts at most `chunk` bytes per call and blocks on every other call
+    struct Choppy {
+        out: Vec<u8>,
+        chunk: usize,
+        block_next: bool,
+        blocked: usize,
+    }
+
+    impl Write for Choppy {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.block_next = !self.block_next;
+            if !self.block_next {
+                self.blocked += 1;
+                return Err(ErrorKind::WouldBlock.into());
+            }
+            let n = buf.len().min(self.chunk);
+            self.out.extend_from_slice(&buf[..n]);
+            Ok(n)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn git(dir: &std::path::Path, args: &[&str]) -> String {
+        let output = std::process::Command::new("git")
+            .args(args)
+            .current_dir(dir)
+            .env("GIT_AUTHOR_NAME", "author")
+            .env("GIT_AUTHOR_EMAIL", "author@example.com")
+            .env("GIT_COMMITTER_NAME", "committer")
+            .env("GIT_COMMITTER_EMAIL", "committer@example.com")
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "git {args:?} failed");
+        String::from_utf8(output.stdout).unwrap().trim().to_owned()
+    }
+
+    fn pkt(line: &str) -> String {
+        format!("{:04x}{line}", line.len() + 4)
+    }
+
+    #[test]
+    fn resumes_after_would_block() {
+        let dir = tempfile::tempdir().unwrap();
+        git(dir.path(), &["init", "--bare", "--quiet"]);
+        let options = ServerOptions::default().with_advertise_refs(true);
+        let mut server = Server::new(dir.path(), options).unwrap();
+
+        let mut expected = Vec::new();
+        server
+            .serve_with(
+                &b""[..],
+                &mut expected,
+                &server.options().clone().with_stateless_rpc(true),
+                ProtocolVersion::V2,
+                IdleMonitor::default(),
+            )
+            .unwrap();
+
+        let mut session = server.step_session(ProtocolVersion::V2).unwrap();
+        let mut out = Choppy {
+            out: Vec::new(),
+            chunk: 7,
+            block_next: false,
+            blocked: 0,
+        };
+        let mut steps = 0;
+        loop {
+            steps += 1;
+            assert!(steps < 1000, "session makes progress");
+            match session.serve_step(&mut out).unwrap() {
+                Step::WantWrite => continue,
+                Step::NeedInput => session.finish_input(),
+                Step::Done => break,
+            }
+        }
+        assert!(out.blocked > 0, "the writer blocked and the session resumed");
+        assert_eq!(session.pending_output(), 0);
+        assert_eq!(out.out, expected, "output is identical to a blocking session");
+    }
+
+    #[test]
+    fn stateful_negotiation_spans_rounds() {
+        let dir = tempfile::tempdir().unwrap();
+        git(dir.path(), &["init", "--quiet"]);
+        git(dir.path(), &["commit", "--quiet", "--allow-empty", "-m", "base"]);
+        let base = git(dir.path(), &["rev-parse", "HEAD"]);
+        git(dir.path(), &["commit", "--quiet", "--allow-empty", "-m", "head"]);
+        let head = git(dir.path(), &["rev-parse", "HEAD"]);
+        let options = ServerOptions::default().with_repository_overrides(false);
+        let mut server = Server::new(dir.path(), options).unwrap();
+        let mut session = server.step_session(ProtocolVersion::V1).unwrap();
+
+        let mut out = Vec::new();
+        assert_eq!(session.serve_step(&mut out).unwrap(), Step::NeedInput);
+        let advertisement = String::from_utf8(std::mem::take(&mut out)).unwrap();
+        assert!(advertisement.contains(&head), "{advertisement}");
+
+        let unknown = "1111111111111111111111111111111111111111";
+        session.push_input(
+            format!(
+                "{}0000{}0000",
+                pkt(&format!("want {head} multi_ack_detailed no-progress\n")),
+                pkt(&format!("have {unknown}\n"))
+            )
+            .as_bytes(),
+        );
+        assert_eq!(session.serve_step(&mut out).unwrap(), Step::NeedInput);
+        assert_eq!(String::from_utf8(std::mem::take(&mut out)).unwrap(), pkt("NAK\n"));
+
+        session.push_input(format!("{}0000", pkt(&format!("have {base}\n"))).as_bytes());
+        assert_eq!(
+            session.serve_step(&mut out).unwrap(),
+            Step::NeedInput,
+            "the second round of haves is answered with the wants of the first"
+        );
+        let answer = String::from_utf8(std::mem::take(&mut out)).unwrap();
+        assert!(answer.contains(&pkt(&format!("ACK {base} common\n"))), "{answer}");
+        assert!(answer.ends_with(&pkt("NAK\n")), "{answer}");
+
+        session.push_input(pkt("done\n").as_bytes());
+        session.finish_input();
+        assert_eq!(session.serve_step(&mut out).unwrap(), Step::Done);
+        assert!(out.starts_with(pkt(&format!("ACK {base}\n")).as_bytes()));
+        assert!(out.windows(4).any(|window| window == b"PACK"), "the pack follows");
+        assert_eq!(session.pending_output(), 0);
+        assert!(session.wire().pack_bytes > 0);
+    }
+}
@@ -0,0 +1,70 @@
+//! Per-ref authorization for fetches
+//!
+//! Hidden refs apply to all clients alike, but some deployments decide per user which refs are visible, like hiding
+//! release branches from contractors. A [`RefAuthorizer`] set with
+//! [`ServerOptions::with_ref_authorizer()`](crate::ServerOptions::with_ref_authorizer()) is asked about every ref
+//! that would be advertised or listed by `ls-refs`, and refs it rejects are left out as if they were hidden.
+//! Wants are then only accepted if they can be fetched from authorized refs, so rejected refs can't be fetched by
+//! object id either.
+//!
+//! Each ref name is passed to the authorizer at most once per session, so it may do expensive lookups.
+
+use bstr::{BStr, BString};
+use std::{cell::RefCell, collections::HashMap, fmt, sync::Arc};
+
+/// Decides which refs a client may see and fetch
+pub trait RefAuthorizer: Send + Sync {
+    /// Return `true` if the client may see and fetch the ref `name`, like `refs/heads/main` or `HEAD`
+    fn is_authorized(&self, name: &BStr) -> bool;
+}
+
+impl<F> RefAuthorizer for F
+where
+    F: Fn(&BStr) -> bool + Send + Sync,
+{
+    fn is_authorized(&self, name: &BStr) -> bool {
+        self(name)
+    }
+}
+
+/// A cheaply clonable handle to a [`RefAuthorizer`] implementation
+#[derive(Clone)]
+pub struct RefAuthorization(Arc<dyn RefAuthorizer>);
+
+impl RefAuthorization {
+    /// Create a handle to `authorizer`
+    pub fn new(authorizer: impl RefAuthorizer + 'static) -> Self {
+        Self(Arc::new(authorizer))
+    }
+}
+
+impl fmt::Debug for RefAuthorization {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("RefAuthorization")
+    }
+}
+
+/// Remembers the decisions of a [`RefAuthorizer`] for the duration of a session
+pub(crate) struct CachedAuthorization<'a> {
+    authorizer: &'a dyn RefAuthorizer,
+    decisions: RefCell<HashMap<BString, bool>>,
+}
+
+impl<'a> CachedAuthorization<'a> {
+    pub(crate) fn new(authorization: &'a RefAuthorization) -> Self {
+        Self {
+            authorizer: authorization.0.as_ref(),
+            decisions: RefCell::default(),
+        }
+    }
+
+    /// Return `true` if `name` is authorized, asking the authorizer only the first time
+    pub(crate) fn is_authorized(&self, name: &BStr) -> bool {
+        if let Some(authorized) = self.decisions.borrow().get(name) {
+            return *authorized;
+        }
+        let authorized = self.authorizer.is_authorized(name);
+        self.decisions.borrow_mut().insert(name.to_owned(), authorized);
+        authorized
+    }
+}
@@ -4,18 +4,26 @@
 //! between the client and server during the upload-pack protocol.
 
 use crate::{
+    capability::Capability,
     config::ServerOptions,
     error::{Error, Result},
     types::*,
 };
-use bstr::ByteSlice;
+use bstr::{BString, ByteSlice};
 use gix::Repository;
 use gix_protocol::Command;
 use gix_transport::client::Capabilities;
 
+/// The object formats git knows, which are mismatched rather than unknown if they can't be served
+const KNOWN_OBJECT_FORMATS: &[&str] = &["sha1", "sha256"];
+
+/// Return the name of `kind` as used in `object-format` capabilities, like `sha1`
+pub fn object_format_name(kind: gix_hash::Kind) -> String {
+    kind.to_string().to_ascii_lowercase()
+}
+
 /// Capability manager for handling server and client capabilities
 pub struct CapabilityManager<'a> {
-    #[allow(dead_code)]
     repository: &'a Repository,
     options: &'a ServerOptions,
 }
@@ -135,6 +143,40 @@ impl<'a> CapabilityManager<'a> {
         caps.join("\n")
     }
 
+    /// Determine the object format of a v2 request from its `capabilities`, and fail like git if it can't be served
+    ///
+    /// Clients that don't send `object-format` use SHA-1. The format must be among
+    /// [`hash_algorithms`](ServerOptions::hash_algorithms) and be the one of the repository, as objects aren't
+    /// translated between formats.
+    pub fn negotiate_object_format(&self, capabilities: &[BString]) -> Result<gix_hash::Kind> {
+        let error = |message: String| Error::ObjectFormat { message };
+        let mut requested = None;
+        for line in capabilities {
+            if line == "object-format" {
+                return Err(error("object-format capability requires an argument".into()));
+            }
+            if let Some(name) = line.strip_prefix(b"object-format=") {
+                requested = Some(name.to_str_lossy().into_owned());
+            }
+        }
+
+        let client = requested.unwrap_or_else(|| object_format_name(gix_hash::Kind::Sha1));
+        if !KNOWN_OBJECT_FORMATS.contains(&client.as_str()) {
+            return Err(error(format!("unknown object format '{client}'")));
+        }
+        let server = self.repository.object_hash();
+        client
+            .parse::<gix_hash::Kind>()
+            .ok()
+            .filter(|kind| *kind == server && self.options.hash_algorithms.contains(kind))
+            .ok_or_else(|| {
+                error(format!(
+                    "mismatched object format: server {}; client {client}",
+                    object_format_name(server)
+                ))
+            })
+    }
+
     /// Get the default server capabilities based on repository and configuration
     pub fn default_server_capabilities(&self) -> ServerCapabilities {
         ServerCapabilities::default()
@@ -143,11 +185,22 @@ impl<'a> CapabilityManager<'a> {
     /// Parse client capabilities from capability string (centralized from v1)
     /// This replaces the duplicate parsing logic in v1 protocol
     pub fn parse_client_capabilities(&self, caps_str: &str) -> Result<ClientCapabilities> {
+        // Like git, capabilities of other protocol versions are ignored as unknown ones
+        let requested: Vec<_> = caps_str
+            .split_whitespace()
+            .filter_map(Capability::from_wire)
+            .filter(|cap| cap.is_valid_for(ProtocolVersion::V1))
+            .collect();
+        Capability::validate_set(&requested, ProtocolVersion::V1, true)?;
+
         let mut capabilities = ClientCapabilities::default();
 
         for cap in caps_str.split_whitespace() {
             match cap {
-                "multi_ack" => capabilities.multi_ack = MultiAckMode::Basic,
+                // Like git, `multi_ack_detailed` wins whatever the order
+                "multi_ack" if capabilities.multi_ack == MultiAckMode::None => {
+                    capabilities.multi_ack = MultiAckMode::Basic
+                }
                 "multi_ack_detailed" => capabilities.multi_ack = MultiAckMode::Detailed,
                 "thin-pack" => capabilities.thin_pack = true,
                 cap if SideBandMode::from_capability_string(cap).is_some() => {
@@ -159,7 +212,9 @@ impl<'a> CapabilityManager<'a> {
                 "allow-tip-sha1-in-want" => capabilities.allow_tip_sha1_in_want = true,
                 "allow-reachable-sha1-in-want" => capabilities.allow_reachable_sha1_in_want = true,
                 "deepen-relative" => capabilities.deepen_relative = true,
+                "no-done" => capabilities.no_done = true,
                 "shallow" => capabilities.shallow = true,
+                "filter" => capabilities.filter_requested = true,
                 cap if cap.starts_with("filter=") => {
                     capabilities.filter = Some(cap["filter=".len()..].into());
                 }
@@ -169,17 +224,9 @@ impl<'a> CapabilityManager<'a> {
                 cap if cap.starts_with("session-id=") => {
                     capabilities.session_id = Some(cap["session-id=".len()..].into());
                 }
+                // Like git, formats that can't be served are ignored, as v0 and v1 clients check the advertised one
                 cap if cap.starts_with("object-format=") => {
-                    let format_name = &cap["object-format=".len()..];
-                    match format_name {
-                        "sha1" => capabilities.object_format = Some(gix_hash::Kind::Sha1),
-                        "sha256" => capabilities.object_format = Some(gix_hash::Kind::Sha1), // Use Sha1 as fallback since Sha256 variant doesn't exist
-                        _ => {
-                            return Err(Error::UnsupportedCapability {
-                                capability: cap.to_string(),
-                            })
-                        }
-                    }
+                    capabilities.object_format = cap["object-format=".len()..].parse().ok();
                 }
                 _ => {
                     // Unknown capabilities are ignored for forward compatibility
@@ -190,97 +237,105 @@ impl<'a> CapabilityManager<'a> {
         Ok(capabilities)
     }
 
-    /// Get V1 capability strings (without writing to any writer)
-    pub fn get_v1_capability_strings(&self, caps: &ServerCapabilities) -> Vec<String> {
-        let mut cap_strings = Vec::new();
-
-        // Multi-ack capabilities - Git advertises both when detailed is supported
-        match caps.multi_ack {
-            MultiAckMode::None => {}
-            MultiAckMode::Basic => cap_strings.push("multi_ack".to_string()),
-            MultiAckMode::Detailed => {
-                cap_strings.push("multi_ack".to_string());
-                // multi_ack_detailed will be added later in the correct position
+    /// The values `capability` is advertised with in protocol `version`, if it's advertised at all
+    ///
+    /// Capabilities that don't exist in `version` are never advertised, whatever `caps` says.
+    fn advertised_values(
+        &self,
+        capability: Capability,
+        version: ProtocolVersion,
+        caps: &ServerCapabilities,
+    ) -> Vec<String> {
+        if !capability.is_valid_for(version) {
+            return Vec::new();
+        }
+        let flag = |enabled: bool| {
+            if enabled {
+                vec![capability.name().to_string()]
+            } else {
+                Vec::new()
             }
-        }
-
-        if caps.thin_pack {
-            cap_strings.push("thin-pack".to_string());
-        }
-
-        // Side-band capabilities
-        cap_strings.extend(caps.side_band.to_capability_strings().iter().map(|s| s.to_string()));
-
-        if caps.ofs_delta {
-            cap_strings.push("ofs-delta".to_string());
-        }
-
-        if caps.shallow {
-            cap_strings.push("shallow".to_string());
-        }
-
-        if caps.deepen_since {
-            cap_strings.push("deepen-since".to_string());
-        }
-
-        if caps.deepen_not {
-            cap_strings.push("deepen-not".to_string());
-        }
-
-        if caps.deepen_relative {
-            cap_strings.push("deepen-relative".to_string());
-        }
-
-        if caps.no_progress {
-            cap_strings.push("no-progress".to_string());
-        }
-
-        if caps.include_tag {
-            cap_strings.push("include-tag".to_string());
-        }
-
-        // Add multi_ack_detailed after no-progress and include-tag (Git's order)
-        if caps.multi_ack == MultiAckMode::Detailed {
-            cap_strings.push("multi_ack_detailed".to_string());
-        }
-
-        if caps.no_done {
-            cap_strings.push("no-done".to_string());
-        }
-
-        if caps.filter {
-            cap_strings.push("filter".to_string());
-        }
-
-        if caps.allow_tip_sha1_in_want {
-            cap_strings.push("allow-tip-sha1-in-want".to_string());
-        }
-
-        if caps.allow_reachable_sha1_in_want {
-            cap_strings.push("allow-reachable-sha1-in-want".to_string());
-        }
-
-        // Add symref capability for HEAD
-        if let Ok(head) = self.repository.head() {
-            if let gix::head::Kind::Symbolic(target_ref) = head.kind {
-                cap_strings.push(format!("symref=HEAD:{}", target_ref.name.as_bstr().to_str_lossy()));
+        };
+        match capability {
+            Capability::MultiAck => flag(caps.multi_ack != MultiAckMode::None),
+            Capability::MultiAckDetailed => flag(caps.multi_ack == MultiAckMode::Detailed),
+            Capability::NoDone => flag(caps.no_done),
+            Capability::ThinPack => flag(caps.thin_pack),
+            Capability::SideBand => flag(caps.side_band != SideBandMode::None),
+            Capability::SideBand64k => flag(caps.side_band == SideBandMode::SideBand64k),
+            Capability::OfsDelta => flag(caps.ofs_delta),
+            Capability::Shallow => flag(caps.shallow),
+            Capability::DeepenSince => flag(caps.deepen_since),
+            Capability::DeepenNot => flag(caps.deepen_not),
+            Capability::DeepenRelative => flag(caps.deepen_relative),
+            Capability::NoProgress => flag(caps.no_progress),
+            Capability::IncludeTag => flag(caps.include_tag),
+            Capability::AllowTipSha1InWant => flag(caps.allow_tip_sha1_in_want),
+            Capability::AllowReachableSha1InWant => flag(caps.allow_reachable_sha1_in_want),
+            Capability::Filter => flag(caps.filter || (version != ProtocolVersion::V2 && self.options.allow_filter)),
+            Capability::WaitForDone => flag(caps.wait_for_done),
+            Capability::PackfileUris => flag(caps.packfile_uris || self.options.allow_packfile_uris),
+            Capability::SidebandAll => flag(caps.side_band == SideBandMode::SideBand64k),
+            Capability::ObjectInfo => flag(caps.object_info),
+            Capability::Agent => vec![format!("agent={}", caps.agent.to_str_lossy())],
+            Capability::SessionId => caps
+                .session_id
+                .iter()
+                .map(|session_id| format!("session-id={}", session_id.to_str_lossy()))
+                .collect(),
+            // Protocol v2 lists all formats, while v0 and v1 advertise the one of the repository - native git uses lowercase
+            Capability::ObjectFormat => match version {
+                ProtocolVersion::V2 => caps
+                    .object_format
+                    .iter()
+                    .map(|format| format!("object-format={}", object_format_name(*format)))
+                    .collect(),
+                _ if caps.object_format.is_empty() => Vec::new(),
+                _ => vec![format!(
+                    "object-format={}",
+                    object_format_name(self.repository.object_hash())
+                )],
+            },
+            Capability::Symref => {
+                let head_target = match &self.options.ref_snapshot {
+                    Some(snapshot) => snapshot.head_target().map(ToOwned::to_owned),
+                    None => match self.repository.head().map(|head| head.kind) {
+                        Ok(gix::head::Kind::Symbolic(target_ref)) => Some(target_ref.name.as_bstr().to_owned()),
+                        _ => None,
+                    },
+                };
+                head_target
+                    .map(|target| format!("symref=HEAD:{}", target.to_str_lossy()))
+                    .into_iter()
+                    .collect()
             }
         }
+    }
 
-        // Object format - native git uses lowercase
-        if !caps.object_format.is_empty() {
-            cap_strings.push("object-format=sha1".to_string());
-        }
-
-        // Agent
-        cap_strings.push(format!("agent={}", caps.agent.to_str_lossy()));
-
-        // Session ID if available
-        if let Some(session_id) = &caps.session_id {
-            cap_strings.push(format!("session-id={}", session_id.to_str_lossy()));
-        }
+    /// The capabilities advertised in protocol `version` with their values, in order
+    ///
+    /// Capabilities whose [dependencies](Capability::dependencies()) aren't advertised are left out.
+    fn advertised_capabilities(
+        &self,
+        version: ProtocolVersion,
+        caps: &ServerCapabilities,
+    ) -> Vec<(Capability, Vec<String>)> {
+        let mut advertised: Vec<_> = Capability::advertised_in(version)
+            .iter()
+            .map(|cap| (*cap, self.advertised_values(*cap, version, caps)))
+            .filter(|(_cap, values)| !values.is_empty())
+            .collect();
+        let names: Vec<_> = advertised.iter().map(|(cap, _values)| *cap).collect();
+        advertised.retain(|(cap, _values)| cap.dependencies().iter().all(|dep| names.contains(dep)));
+        advertised
+    }
 
-        cap_strings
+    /// Get V1 capability strings (without writing to any writer)
+    pub fn get_v1_capability_strings(&self, caps: &ServerCapabilities) -> Vec<String> {
+        self.advertised_capabilities(ProtocolVersion::V1, caps)
+            .into_iter()
+            .flat_map(|(_cap, values)| values)
+            .collect()
     }
 
     /// Convert server capabilities to wire format string for V1 protocol (convenience method)
@@ -322,65 +377,37 @@ impl<'a> CapabilityManager<'a> {
 
     /// Get V2 capability lines (without writing to any writer)
     pub fn get_v2_capability_lines(&self, capabilities: &ServerCapabilities) -> Vec<String> {
-        let mut lines = Vec::new();
-
-        // Version line
-        lines.push("version 2".to_string());
-
-        // Agent capability
-        lines.push(format!("agent={}", capabilities.agent.to_str_lossy()));
-
-        // Object format capabilities
-        for format in &capabilities.object_format {
-            lines.push(format!("object-format={}", format));
-        }
+        let advertised = |cap: Capability| self.advertised_values(cap, ProtocolVersion::V2, capabilities);
+        let mut lines = vec!["version 2".to_string()];
+        lines.extend(advertised(Capability::Agent));
+        lines.extend(advertised(Capability::ObjectFormat));
 
         // ls-refs command
         lines.push("ls-refs=unborn".to_string());
 
-        // fetch command with sub-capabilities
-        let mut fetch_caps = Vec::new();
-
-        if capabilities.shallow {
-            fetch_caps.push("shallow");
-        }
-
-        if capabilities.filter {
-            fetch_caps.push("filter");
-        }
-
-        // Add v2-specific sideband capabilities
-        for cap in capabilities.side_band.to_v2_capability_strings() {
-            fetch_caps.push(cap);
-        }
-
-        if capabilities.packfile_uris {
-            fetch_caps.push("packfile-uris");
-        }
-
-        if capabilities.wait_for_done {
-            fetch_caps.push("wait-for-done");
-        }
-
-        let fetch_line = if fetch_caps.is_empty() {
+        // fetch command with its features
+        let fetch_features: Vec<_> = self
+            .advertised_capabilities(ProtocolVersion::V2, capabilities)
+            .into_iter()
+            .filter(|(cap, _values)| cap.is_fetch_feature())
+            .flat_map(|(_cap, values)| values)
+            .collect();
+        lines.push(if fetch_features.is_empty() {
             "fetch".to_string()
         } else {
-            format!("fetch={}", fetch_caps.join(" "))
-        };
-        lines.push(fetch_line);
+            format!("fetch={}", fetch_features.join(" "))
+        });
 
         // server-info command
         lines.push("server-info".to_string());
 
-        // object-info command if enabled
-        if capabilities.object_info {
-            lines.push("object-info".to_string());
-        }
+        // object-info command, and the session ID if available
+        lines.extend(advertised(Capability::ObjectInfo));
+        lines.extend(advertised(Capability::SessionId));
 
-        // Session ID if available
-        if let Some(ref session_id) = capabilities.session_id {
-            lines.push(format!("session-id={}", session_id.to_str_lossy()));
-        }
+        // Further commands registered for protocol v2, unless advertised above
+        let registered = self.options.v2_commands.advertisements_besides(&lines);
+        lines.extend(registered);
 
         lines
     }
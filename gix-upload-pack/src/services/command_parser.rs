@@ -1,9 +1,10 @@
 //! Centralized command parsing for upload-pack protocol
 //!
 //! This module consolidates the parsing logic for want, have, done, shallow,
-//! and deepen commands that was previously duplicated between v1 and v2 protocols.
+//! deepen, filter and packfile-uris commands that was previously duplicated between v1 and v2 protocols.
 
 use crate::{
+    config::ServerOptions,
     error::{Error, Result},
     types::*,
 };
@@ -37,11 +38,7 @@ impl<'a> CommandParser<'a> {
             oid: oid_str.to_string(),
         })?;
 
-        // Validate that the object exists
-        if !self.repository.objects.contains(&oid) {
-            return Err(Error::ObjectNotFound { oid });
-        }
-
+        // Existence is checked once all wants are known, see `PackGenerator::check_wants()`
         session.negotiation.wants.insert(oid);
 
         Ok(())
@@ -91,6 +88,12 @@ impl<'a> CommandParser<'a> {
             std::str::from_utf8(line.trim_ascii()).map_err(|_| Error::custom("Invalid UTF-8 in deepen line"))?;
 
         let depth: u32 = line_str.parse().map_err(|_| Error::custom("Invalid depth value"))?;
+        if depth == 0 {
+            return Err(Error::custom("Invalid depth value"));
+        }
+        if matches!(session.negotiation.deepen, Some(DeepenSpec::Revisions { .. })) {
+            return Err(deepen_conflict());
+        }
 
         session.negotiation.deepen = Some(DeepenSpec::Depth(depth));
         Ok(())
@@ -106,7 +109,16 @@ impl<'a> CommandParser<'a> {
             .map_err(|_| Error::custom("Invalid timestamp in deepen-since"))?;
 
         let time = gix_date::Time::new(timestamp, 0);
-        session.negotiation.deepen = Some(DeepenSpec::Since(time));
+        match &mut session.negotiation.deepen {
+            Some(DeepenSpec::Revisions { since, .. }) => *since = Some(time),
+            Some(DeepenSpec::Depth(_)) => return Err(deepen_conflict()),
+            None => {
+                session.negotiation.deepen = Some(DeepenSpec::Revisions {
+                    since: Some(time),
+                    not: Vec::new(),
+                })
+            }
+        }
         Ok(())
     }
 
@@ -115,11 +127,61 @@ impl<'a> CommandParser<'a> {
         let ref_str =
             std::str::from_utf8(line.trim_ascii()).map_err(|_| Error::custom("Invalid UTF-8 in deepen-not line"))?;
 
-        if let Some(DeepenSpec::Not(ref mut refs)) = session.negotiation.deepen {
-            refs.push(ref_str.into());
-        } else {
-            session.negotiation.deepen = Some(DeepenSpec::Not(vec![ref_str.into()]));
+        match &mut session.negotiation.deepen {
+            Some(DeepenSpec::Revisions { not, .. }) => not.push(ref_str.into()),
+            Some(DeepenSpec::Depth(_)) => return Err(deepen_conflict()),
+            None => {
+                session.negotiation.deepen = Some(DeepenSpec::Revisions {
+                    since: None,
+                    not: vec![ref_str.into()],
+                })
+            }
+        }
+        Ok(())
+    }
+
+    /// Parse a filter line, checking the spec against the filter allowlist (shared by v1 and v2)
+    pub fn parse_filter_line(&self, line: &[u8], options: &ServerOptions, session: &mut SessionContext) -> Result<()> {
+        let spec = std::str::from_utf8(line.trim_ascii()).map_err(|_| Error::InvalidFilter {
+            message: "invalid UTF-8 in filter specification".into(),
+        })?;
+        if !options.is_filter_allowed(spec) {
+            return Err(Error::InvalidFilter {
+                message: format!("filter '{}' is not allowed", spec),
+            });
+        }
+
+        session.capabilities.set_filter(spec);
+        Ok(())
+    }
+
+    /// Parse the comma-separated protocols of a `packfile-uris` argument and add them to the session
+    pub fn parse_packfile_uris_line(
+        &self,
+        line: &[u8],
+        options: &ServerOptions,
+        session: &mut SessionContext,
+    ) -> Result<()> {
+        if !options.is_packfile_uris_allowed() {
+            return Err(Error::UnsupportedCapability {
+                capability: "packfile-uris".into(),
+            });
         }
+
+        let protocols = line
+            .trim_ascii()
+            .split(|byte| *byte == b',')
+            .filter(|protocol| !protocol.is_empty())
+            .map(Into::into)
+            .collect();
+        session.capabilities.set_packfile_uri_protocols(protocols);
         Ok(())
     }
 }
+
+/// Like git, depths can't be combined with `deepen-since` and `deepen-not`
+fn deepen_conflict() -> Error {
+    Error::Shallow {
+        message: "deepen and deepen-since (or deepen-not) cannot be used together".into(),
+    }
+}
@@ -0,0 +1,586 @@
+//! Checking the packets sent to clients against the grammar of the protocol
+//!
+//! Clients are strict about where flush-pkts and delim-pkts go: a delim-pkt after the last section of a protocol v2
+//! `fetch` response, or a negotiation line after the pack started, leaves them waiting or aborting with little
+//! to go on. [`PacketGrammar`] follows the packets of a session like native git would emit them and reports the
+//! first one out of place, and [`GrammarWriter`] applies it to the output stream of a session.
+//!
+//! The grammar covers the framing of responses, not their contents:
+//! - Protocol v0 and v1 know no delim-pkts or response-end-pkts. `version 1` is only sent first, and once sideband
+//!   packets started nothing but sideband packets may follow until the flush-pkt ending the pack, after which
+//!   the session is over. Raw packs end the checking.
+//! - Protocol v2 responses end with a flush-pkt. Only `fetch` responses have sections, which are separated by
+//!   delim-pkts and appear in the order of `acknowledgments`, `shallow-info`, `wanted-refs`, `packfile-uris` and
+//!   `packfile`. The `acknowledgments` section ends the response unless it contains `ready`, and `packfile` is
+//!   always last.
+//!
+//! Errors sent to the client, like `ERR` packets and sideband channel 3, end the checking, as the session ends with
+//! them wherever it was.
+
+use crate::{log::Logger, types::ProtocolVersion};
+use bstr::ByteSlice;
+use std::io::{self, Write};
+
+/// A packet of a response, as far as the grammar is concerned
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Packet<'a> {
+    /// A flush-pkt, `0000`
+    Flush,
+    /// A delim-pkt, `0001`
+    Delimiter,
+    /// A response-end-pkt, `0002`
+    ResponseEnd,
+    /// A packet with data, without its length
+    Data(&'a [u8]),
+}
+
+/// A packet that is out of place
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("packet {packet} violates the grammar of protocol v{}: {message}", *.version as u8)]
+pub struct GrammarViolation {
+    /// The protocol version of the session
+    pub version: ProtocolVersion,
+    /// The number of the offending packet, starting at 1
+    pub packet: usize,
+    /// What is wrong with it
+    pub message: String,
+}
+
+/// The sections of a protocol v2 `fetch` response, in the order they have to appear in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Section {
+    Acknowledgments,
+    ShallowInfo,
+    WantedRefs,
+    PackfileUris,
+    Packfile,
+}
+
+impl Section {
+    const ALL: [(Section, &'static [u8]); 5] = [
+        (Section::Acknowledgments, b"acknowledgments"),
+        (Section::ShallowInfo, b"shallow-info"),
+        (Section::WantedRefs, b"wanted-refs"),
+        (Section::PackfileUris, b"packfile-uris"),
+        (Section::Packfile, b"packfile"),
+    ];
+
+    fn from_header(line: &[u8]) -> Option<Self> {
+        let line = line.strip_suffix(b"\n").unwrap_or(line);
+        Self::ALL
+            .iter()
+            .find_map(|(section, header)| (*header == line).then_some(*section))
+    }
+
+    fn name(self) -> &'static str {
+        Self::ALL
+            .iter()
+            .find_map(|(section, header)| (*section == self).then(|| header.to_str().expect("ascii")))
+            .expect("all sections are listed")
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    /// Protocol v0 and v1: before the first packet
+    Start,
+    /// Protocol v0 and v1: advertisement and negotiation lines
+    Lines,
+    /// Protocol v0 and v1: sideband packets until the flush-pkt ending the pack
+    Pack,
+    /// Protocol v0 and v1: the pack was sent completely
+    PackDone,
+    /// Protocol v2: between responses
+    Idle,
+    /// Protocol v2: the capability advertisement
+    Advertisement,
+    /// Protocol v2: a response without sections, like the one to `ls-refs`
+    Response,
+    /// Protocol v2: within `section` of a `fetch` response
+    Fetch {
+        section: Section,
+        ready: bool,
+        after_delimiter: bool,
+    },
+    /// Nothing is checked anymore
+    Stopped,
+}
+
+/// Follows the packets of the responses of one session, see the [module documentation](self)
+#[derive(Debug, Clone)]
+pub struct PacketGrammar {
+    version: ProtocolVersion,
+    state: State,
+    packets: usize,
+}
+
+impl PacketGrammar {
+    /// Check the packets of a session using `version`
+    pub fn new(version: ProtocolVersion) -> Self {
+        Self {
+            version,
+            state: match version {
+                ProtocolVersion::V0 | ProtocolVersion::V1 => State::Start,
+                ProtocolVersion::V2 => State::Idle,
+            },
+            packets: 0,
+        }
+    }
+
+    /// Return `true` if packets aren't checked anymore, after an error was sent or a raw pack started
+    pub fn is_stopped(&self) -> bool {
+        self.state == State::Stopped
+    }
+
+    /// Stop checking, as the rest of the stream is a raw pack
+    pub fn raw_pack(&mut self) {
+        self.state = State::Stopped;
+    }
+
+    /// Check `packet`, the next one sent to the client
+    pub fn check(&mut self, packet: Packet<'_>) -> Result<(), GrammarViolation> {
+        if self.is_stopped() {
+            return Ok(());
+        }
+        self.packets += 1;
+        if let Packet::Data(data) = packet {
+            if data.starts_with(b"ERR ") || data.first() == Some(&3) {
+                self.state = State::Stopped;
+                return Ok(());
+            }
+        }
+        let next = match self.version {
+            ProtocolVersion::V0 | ProtocolVersion::V1 => self.next_v1(packet),
+            ProtocolVersion::V2 => self.next_v2(packet),
+        };
+        match next {
+            Ok(state) => {
+                self.state = state;
+                Ok(())
+            }
+            Err(message) => {
+                self.state = State::Stopped;
+                Err(self.violation(message))
+            }
+        }
+    }
+
+    /// Check that the session may end here, which it can't in the middle of a response
+    pub fn finish(&self) -> Result<(), GrammarViolation> {
+        let unfinished = match self.state {
+            State::Pack => "the pack isn't terminated by a flush-pkt",
+            State::Advertisement => "the capability advertisement isn't terminated by a flush-pkt",
+            State::Response | State::Fetch { .. } => "the response isn't terminated by a flush-pkt",
+            _ => return Ok(()),
+        };
+        Err(self.violation(unfinished.into()))
+    }
+
+    fn violation(&self, message: String) -> GrammarViolation {
+        GrammarViolation {
+            version: self.version,
+            packet: self.packets,
+            message,
+        }
+    }
+
+    fn next_v1(&self, packet: Packet<'_>) -> Result<State, String> {
+        let data = match packet {
+            Packet::Delimiter => return Err("delim-pkts aren't part of the protocol".into()),
+            Packet::ResponseEnd => return Err("response-end-pkts aren't part of the protocol".into()),
+            Packet::Flush => {
+                return match self.state {
+                    State::PackDone => Err("flush-pkt after the end of the pack".into()),
+                    State::Pack => Ok(State::PackDone),
+                    _ => Ok(State::Lines),
+                }
+            }
+            Packet::Data(data) => data,
+        };
+        match self.state {
+            State::PackDone => Err(format!("{:?} after the end of the pack", data.as_bstr())),
+            State::Pack if matches!(data.first(), Some(1 | 2)) => Ok(State::Pack),
+            State::Pack => Err(format!("{:?} after the pack started", data.as_bstr())),
+            _ if data == b"version 1\n" && self.state != State::Start => {
+                Err("version 1 is only sent as first packet".into())
+            }
+            _ if matches!(data.first(), Some(1 | 2)) => Ok(State::Pack),
+            _ => Ok(State::Lines),
+        }
+    }
+
+    fn next_v2(&self, packet: Packet<'_>) -> Result<State, String> {
+        // With `sideband-all`, all lines of `fetch` responses are sent on channel 1, and progress on channel 2
+        let line = match packet {
+            Packet::Data([2, ..]) => return Ok(self.state),
+            Packet::Data([1, line @ ..]) => Some(line),
+            Packet::Data(line) => Some(line),
+            _ => None,
+        };
+        match (self.state, packet) {
+            (State::Idle, Packet::Flush | Packet::ResponseEnd) => Ok(State::Idle),
+            (_, Packet::ResponseEnd) => Err("response-end-pkt in the middle of a response".into()),
+            (State::Idle, Packet::Delimiter) => Err("delim-pkt before the first section".into()),
+            (State::Idle, Packet::Data(_)) => {
+                let line = line.expect("data");
+                if line == b"version 2\n" {
+                    return if self.packets == 1 {
+                        Ok(State::Advertisement)
+                    } else {
+                        Err("version 2 is only sent as first packet".into())
+                    };
+                }
+                Ok(match Section::from_header(line) {
+                    Some(section) => State::Fetch {
+                        section,
+                        ready: false,
+                        after_delimiter: false,
+                    },
+                    None => State::Response,
+                })
+            }
+            (State::Advertisement | State::Response, Packet::Flush) => Ok(State::Idle),
+            (State::Advertisement | State::Response, Packet::Delimiter) => {
+                Err("delim-pkt in a response without sections".into())
+            }
+            (State::Advertisement | State::Response, Packet::Data(_)) => Ok(self.state),
+            (
+                State::Fetch {
+                    section,
+                    ready,
+                    after_delimiter,
+                },
+                packet,
+            ) => Self::next_fetch(section, ready, after_delimiter, packet, line),
+            (state, _) => unreachable!("{state:?} isn't a state of protocol v2"),
+        }
+    }
+
+    fn next_fetch(
+        section: Section,
+        ready: bool,
+        after_delimiter: bool,
+        packet: Packet<'_>,
+        line: Option<&[u8]>,
+    ) -> Result<State, String> {
+        let name = section.name();
+        match packet {
+            _ if after_delimiter => {
+                let Some(next) = line.and_then(Section::from_header) else {
+                    return Err(format!(
+                        "delim-pkt after the {name} section isn't followed by a section"
+                    ));
+                };
+                if next <= section {
+                    return Err(format!("{} section after the {name} section", next.name()));
+                }
+                Ok(State::Fetch {
+                    section: next,
+                    ready: false,
+                    after_delimiter: false,
+                })
+            }
+            Packet::Delimiter if section == Section::Packfile => {
+                Err("delim-pkt after the packfile section, which is always last".into())
+            }
+            Packet::Delimiter if section == Section::Acknowledgments && !ready => {
+                Err("delim-pkt after the acknowledgments section without ready".into())
+            }
+            Packet::Delimiter => Ok(State::Fetch {
+                section,
+                ready,
+                after_delimiter: true,
+            }),
+            Packet::Flush if section == Section::Packfile => Ok(State::Idle),
+            Packet::Flush if section == Section::Acknowledgments && !ready => Ok(State::Idle),
+            Packet::Flush => Err(format!(
+                "flush-pkt after the {name} section, which has to be followed by more"
+            )),
+            Packet::Data(data) if section == Section::Packfile => match data.first() {
+                Some(1 | 2) => Ok(State::Fetch {
+                    section,
+                    ready,
+                    after_delimiter,
+                }),
+                _ => Err(format!(
+                    "{:?} in the packfile section isn't a sideband packet",
+                    data.as_bstr()
+                )),
+            },
+            Packet::Data(_) => Ok(State::Fetch {
+                section,
+                ready: ready || line.is_some_and(|line| line.strip_suffix(b"\n").unwrap_or(line) == b"ready"),
+                after_delimiter,
+            }),
+            Packet::ResponseEnd => unreachable!("handled by the caller"),
+        }
+    }
+}
+
+/// A writer checking the packets written through it with a [`PacketGrammar`]
+///
+/// Violations are logged as errors, and panic in debug builds so tests catch them. Only the first violation of
+/// a session is reported, as the packets following it are out of context.
+pub struct GrammarWriter<W> {
+    inner: W,
+    grammar: Option<PacketGrammar>,
+    logger: Logger,
+    buf: Vec<u8>,
+}
+
+impl<W> GrammarWriter<W> {
+    /// Wrap `inner`, checking the packets of a session using `version` if `check` is `true`, and logging
+    /// violations to `logger`
+    pub fn new(inner: W, version: ProtocolVersion, check: bool, logger: Logger) -> Self {
+        Self {
+            inner,
+            grammar: check.then(|| PacketGrammar::new(version)),
+            logger,
+            buf: Vec::new(),
+        }
+    }
+
+    /// The wrapped writer
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    /// Report if the session ends in the middle of a packet or a response
+    pub fn finish(&mut self) {
+        let Some(grammar) = self.grammar.take() else {
+            return;
+        };
+        let result = if self.buf.is_empty() || grammar.is_stopped() {
+            grammar.finish()
+        } else {
+            Err(grammar.violation("the session ends in the middle of a packet".into()))
+        };
+        if let Err(violation) = result {
+            self.report(violation);
+        }
+    }
+
+    /// Check the packets completed by `data`
+    fn record(&mut self, data: &[u8]) {
+        let Some(grammar) = self.grammar.as_mut() else {
+            return;
+        };
+        self.buf.extend_from_slice(data);
+        let mut consumed = 0;
+        let mut result = Ok(());
+        while result.is_ok() && !grammar.is_stopped() {
+            let pending = &self.buf[consumed..];
+            let Some(header) = pending.get(..4) else {
+                break;
+            };
+            if header == b"PACK" && grammar.version != ProtocolVersion::V2 {
+                grammar.raw_pack();
+                break;
+            }
+            let Some(length) = std::str::from_utf8(header)
+                .ok()
+                .and_then(|hex| u16::from_str_radix(hex, 16).ok())
+            else {
+                result = Err(grammar.violation(format!("invalid packet length {:?}", header.as_bstr())));
+                break;
+            };
+            let length = usize::from(length);
+            let packet = match length {
+                0 => Packet::Flush,
+                1 => Packet::Delimiter,
+                2 => Packet::ResponseEnd,
+                3 => {
+                    result = Err(grammar.violation("invalid packet length 0003".into()));
+                    break;
+                }
+                _ => match pending.get(4..length) {
+                    Some(data) => Packet::Data(data),
+                    None => break,
+                },
+            };
+            consumed += length.max(4);
+            result = grammar.check(packet);
+        }
+        if grammar.is_stopped() {
+            self.buf = Vec::new();
+        } else {
+            self.buf.drain(..consumed);
+        }
+        if let Err(violation) = result {
+            self.grammar = None;
+            self.report(violation);
+        }
+    }
+
+    fn report(&self, violation: GrammarViolation) {
+        self.logger.log(crate::log::Level::Error, format_args!("{violation}"));
+        if cfg!(debug_assertions) {
+            panic!("{violation}");
+        }
+    }
+}
+
+impl<W: Write> Write for GrammarWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.record(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(version: ProtocolVersion, packets: &[Packet<'_>]) -> Result<(), GrammarViolation> {
+        let mut grammar = PacketGrammar::new(version);
+        for packet in packets {
+            grammar.check(*packet)?;
+        }
+        grammar.finish()
+    }
+
+    fn message(version: ProtocolVersion, packets: &[Packet<'_>]) -> String {
+        check(version, packets).unwrap_err().to_string()
+    }
+
+    use Packet::{Data, Delimiter as Delim, Flush};
+
+    #[test]
+    fn v1_sessions_end_with_the_pack() {
+        let v1 = ProtocolVersion::V1;
+        let advertisement = [Data(b"version 1\n"), Data(b"1111 refs/heads/main\0ofs-delta\n"), Flush];
+        assert_eq!(check(v1, &advertisement), Ok(()));
+        let fetch = [Data(b"NAK\n"), Data(b"\x02Counting\n"), Data(b"\x01PACK"), Flush];
+        assert_eq!(check(v1, &[&advertisement[..], &[Flush], &fetch].concat()), Ok(()));
+        assert_eq!(
+            check(v1, &[Data(b"NAK\n"), Data(b"ERR no\n"), Delim]),
+            Ok(()),
+            "errors end the checking"
+        );
+
+        assert_eq!(
+            message(v1, &[Data(b"NAK\n"), Data(b"\x01PACK"), Data(b"ACK 1111\n")]),
+            "packet 3 violates the grammar of protocol v1: \"ACK 1111\\n\" after the pack started"
+        );
+        assert_eq!(
+            message(v1, &fetch[..3]),
+            "packet 3 violates the grammar of protocol v1: the pack isn't terminated by a flush-pkt"
+        );
+        assert_eq!(
+            message(ProtocolVersion::V0, &[&fetch[..], &[Flush]].concat()),
+            "packet 5 violates the grammar of protocol v0: flush-pkt after the end of the pack"
+        );
+        assert_eq!(
+            message(v1, &[Flush, Delim]),
+            "packet 2 violates the grammar of protocol v1: delim-pkts aren't part of the protocol"
+        );
+        assert_eq!(
+            message(v1, &[Flush, Data(b"version 1\n")]),
+            "packet 2 violates the grammar of protocol v1: version 1 is only sent as first packet"
+        );
+    }
+
+    #[test]
+    fn v2_fetch_sections_are_ordered() {
+        let v2 = ProtocolVersion::V2;
+        let advertisement = [Data(b"version 2\n"), Data(b"ls-refs=unborn\n"), Flush];
+        let ls_refs = [Data(b"1111 HEAD\n"), Flush];
+        let negotiation = [Data(b"acknowledgments\n"), Data(b"NAK\n"), Flush];
+        let fetch = [
+            Data(b"acknowledgments\n"),
+            Data(b"ACK 1111\n"),
+            Data(b"ready\n"),
+            Delim,
+            Data(b"shallow-info\n"),
+            Data(b"shallow 2222\n"),
+            Delim,
+            Data(b"packfile\n"),
+            Data(b"\x02Counting\n"),
+            Data(b"\x01PACK"),
+            Flush,
+        ];
+        let stateful = [&advertisement[..], &ls_refs, &negotiation, &fetch].concat();
+        assert_eq!(check(v2, &stateful), Ok(()));
+        let sideband_all = [
+            Data(b"\x01packfile\n"),
+            Data(b"\x02Counting\n"),
+            Data(b"\x01PACK"),
+            Flush,
+        ];
+        assert_eq!(check(v2, &sideband_all), Ok(()));
+
+        for (packets, expected) in [
+            (
+                &[&ls_refs[..], &fetch[3..]].concat()[..],
+                "packet 3 violates the grammar of protocol v2: delim-pkt before the first section",
+            ),
+            (
+                &[&ls_refs[..], &advertisement].concat()[..],
+                "packet 3 violates the grammar of protocol v2: version 2 is only sent as first packet",
+            ),
+            (
+                &[Data(b"1111 HEAD\n"), Delim],
+                "packet 2 violates the grammar of protocol v2: delim-pkt in a response without sections",
+            ),
+            (
+                &[Data(b"packfile\n"), Delim],
+                "packet 2 violates the grammar of protocol v2: delim-pkt after the packfile section, which is always last",
+            ),
+            (
+                &[Data(b"shallow-info\n"), Delim, Data(b"acknowledgments\n")],
+                "packet 3 violates the grammar of protocol v2: acknowledgments section after the shallow-info section",
+            ),
+            (
+                &[Data(b"acknowledgments\n"), Data(b"NAK\n"), Delim],
+                "packet 3 violates the grammar of protocol v2: delim-pkt after the acknowledgments section without ready",
+            ),
+            (
+                &fetch[..3],
+                "packet 3 violates the grammar of protocol v2: the response isn't terminated by a flush-pkt",
+            ),
+            (
+                &[&fetch[..3], &[Flush]].concat()[..],
+                "packet 4 violates the grammar of protocol v2: flush-pkt after the acknowledgments section, which has to be followed by more",
+            ),
+            (
+                &[&fetch[..4], &[Data(b"NAK\n")]].concat()[..],
+                "packet 5 violates the grammar of protocol v2: delim-pkt after the acknowledgments section isn't followed by a section",
+            ),
+            (
+                &[Data(b"packfile\n"), Data(b"PACK")],
+                "packet 2 violates the grammar of protocol v2: \"PACK\" in the packfile section isn't a sideband packet",
+            ),
+        ] {
+            assert_eq!(message(v2, packets), expected);
+        }
+    }
+
+    #[test]
+    fn writers_parse_packets_across_writes() {
+        let mut writer = GrammarWriter::new(Vec::new(), ProtocolVersion::V2, true, Logger::default());
+        for chunk in [&b"000dpack"[..], b"file\n0009\x01P", b"ACK00", b"00"] {
+            writer.write_all(chunk).unwrap();
+        }
+        writer.finish();
+        assert_eq!(writer.get_ref().as_slice(), b"000dpackfile\n0009\x01PACK0000");
+
+        let mut writer = GrammarWriter::new(Vec::new(), ProtocolVersion::V0, true, Logger::default());
+        writer
+            .write_all(b"0008NAK\nPACK\0\0\0\x02 and no packet lines")
+            .unwrap();
+        writer.finish();
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "packet 2 violates the grammar of protocol v2: delim-pkt in a response without sections")]
+    fn violations_panic_in_debug_builds() {
+        let mut writer = GrammarWriter::new(Vec::new(), ProtocolVersion::V2, true, Logger::default());
+        writer.write_all(b"000funborn HEAD0001").unwrap();
+    }
+}
@@ -0,0 +1,118 @@
+//! Looking up commits in the commit-graph of a repository
+//!
+//! Like git, negotiation and counting read the parents and commit times of commits from the commit-graph of the
+//! repository if it has one, and only decode the commits it doesn't contain, typically those added since it was last
+//! written. Generation numbers let walks looking for commits stop early, as a commit only reaches commits of a lower
+//! generation than its own.
+//!
+//! Like git's `commit_graph_compatible()`, the commit-graph isn't used by shallow repositories, whose history it
+//! doesn't cut, or if `core.commitGraph` turns it off. A commit-graph that can't be read is ignored as well.
+
+use crate::error::{Error, Result};
+use gix::Repository;
+use gix_hash::ObjectId;
+
+/// The commits of a repository, read from its commit-graph if possible
+pub struct CommitGraph<'repo> {
+    repository: &'repo Repository,
+    graph: Option<gix::commitgraph::Graph>,
+}
+
+/// What walks need to know about a commit
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Commit {
+    /// The id of the commit
+    pub id: ObjectId,
+    /// The commit time in seconds since the epoch
+    pub time: gix_date::SecondsSinceUnixEpoch,
+    /// The parents of the commit
+    pub parents: Vec<ObjectId>,
+    /// The generation number of the commit if it's in the commit-graph
+    pub generation: Option<u32>,
+}
+
+impl<'repo> CommitGraph<'repo> {
+    /// Load the commit-graph of `repository`, if present and usable
+    pub fn new(repository: &'repo Repository) -> Self {
+        let graph = if repository.is_shallow() {
+            None
+        } else {
+            repository.commit_graph_if_enabled().ok().flatten()
+        };
+        Self { repository, graph }
+    }
+
+    /// Return `true` if commits are read from a commit-graph
+    pub fn is_loaded(&self) -> bool {
+        self.graph.is_some()
+    }
+
+    /// Return `true` if `id` is a commit, without decoding it if it's in the commit-graph
+    pub fn is_commit(&self, id: &gix_hash::oid) -> bool {
+        self.generation(id).is_some() || self.repository.find_commit(id).is_ok()
+    }
+
+    /// Return the generation number of `id` if it's a commit in the commit-graph
+    pub fn generation(&self, id: &gix_hash::oid) -> Option<u32> {
+        self.graph
+            .as_ref()
+            .and_then(|graph| graph.commit_by_id(id))
+            .map(|commit| commit.generation())
+    }
+
+    /// Look up the commit `id`, or return `None` if it's missing or not a commit
+    pub fn try_find(&self, id: ObjectId) -> Result<Option<Commit>> {
+        if let Some((graph, commit)) = self
+            .graph
+            .as_ref()
+            .and_then(|graph| graph.commit_by_id(id).map(|commit| (graph, commit)))
+        {
+            let parents = commit
+                .iter_parents()
+                .map(|parent| parent.map(|position| graph.id_at(position).to_owned()))
+                .collect::<std::result::Result<_, _>>()
+                .map_err(|err| Error::custom(format!("Failed to read commit {id} from the commit-graph: {err}")))?;
+            return Ok(Some(Commit {
+                id,
+                time: commit.committer_timestamp() as gix_date::SecondsSinceUnixEpoch,
+                parents,
+                generation: Some(commit.generation()),
+            }));
+        }
+        let Ok(commit) = self.repository.find_commit(id) else {
+            return Ok(None);
+        };
+        let time = commit
+            .time()
+            .map_err(|err| Error::custom(format!("Failed to decode commit {id}: {err}")))?
+            .seconds;
+        Ok(Some(Commit {
+            id,
+            time,
+            parents: commit.parent_ids().map(|id| id.detach()).collect(),
+            generation: None,
+        }))
+    }
+
+    /// Look up the commit `id`, which has to exist
+    pub fn find(&self, id: ObjectId) -> Result<Commit> {
+        self.try_find(id)?
+            .ok_or_else(|| Error::custom(format!("Failed to look up commit {id}")))
+    }
+
+    /// Look up the commit `id` points to, peeling annotated tags, or return `None` if it doesn't lead to a commit
+    pub fn peel_to_commit(&self, id: ObjectId) -> Result<Option<Commit>> {
+        if let Some(commit) = self.try_find(id)? {
+            return Ok(Some(commit));
+        }
+        let Some(commit) = self
+            .repository
+            .try_find_object(id)
+            .map_err(|e| Error::custom(format!("Failed to look up {id}: {e}")))?
+            .and_then(|object| object.peel_to_commit().ok())
+        else {
+            return Ok(None);
+        };
+        self.try_find(commit.id)
+    }
+}
@@ -0,0 +1,195 @@
+//! Timing out clients that stop sending or receiving
+//!
+//! Like `git upload-pack --timeout`, a session ends once the client was inactive for the
+//! [timeout](crate::ServerOptions::timeout): [`IdleTimeout`] fails reads and writes that blocked for longer than that,
+//! so clients that neither send their request nor receive the response can't hold a session open. Time the server
+//! spends on its own, like counting objects, doesn't count as inactivity.
+//!
+//! Blocking reads and writes can't be interrupted in general, so they fail once they return. Servers that need to
+//! end sessions blocked for good pass an [`IdleMonitor`] to [`Server::serve_monitored()`](crate::Server::serve_monitored),
+//! watch [`IdleMonitor::idle()`] from another thread and close the connection.
+
+use std::io::{self, Read, Write};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// The reason an [`IdleTimeout`] stopped reading or writing
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("client was inactive for more than {}s", .timeout.as_secs_f32())]
+pub struct TimedOut {
+    /// The timeout that was exceeded
+    pub timeout: Duration,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    blocked_since: Option<Instant>,
+    timed_out: Option<TimedOut>,
+}
+
+/// A handle to the inactivity of the streams wrapped with it, shared by all of its clones
+#[derive(Debug, Clone, Default)]
+pub struct IdleMonitor(Arc<Mutex<State>>);
+
+impl IdleMonitor {
+    /// Return how long the current read or write has been blocked, or `None` if none is in progress
+    pub fn idle(&self) -> Option<Duration> {
+        self.0
+            .lock()
+            .expect("not poisoned")
+            .blocked_since
+            .map(|since| since.elapsed())
+    }
+
+    /// Return the timeout that was exceeded, if any
+    pub fn timed_out(&self) -> Option<TimedOut> {
+        self.0.lock().expect("not poisoned").timed_out.clone()
+    }
+
+    /// Run `call` on a stream, failing with [`io::ErrorKind::TimedOut`] if it blocked for longer than `timeout`
+    fn watch<T>(&self, timeout: Option<Duration>, call: impl FnOnce() -> io::Result<T>) -> io::Result<T> {
+        let Some(timeout) = timeout else {
+            return call();
+        };
+        if let Some(timed_out) = self.timed_out() {
+            return Err(io::Error::new(io::ErrorKind::TimedOut, timed_out));
+        }
+        let start = Instant::now();
+        self.0.lock().expect("not poisoned").blocked_since = Some(start);
+        let result = call();
+        let mut state = self.0.lock().expect("not poisoned");
+        state.blocked_since = None;
+        if start.elapsed() > timeout {
+            let timed_out = TimedOut { timeout };
+            state.timed_out = Some(timed_out.clone());
+            return Err(io::Error::new(io::ErrorKind::TimedOut, timed_out));
+        }
+        result
+    }
+}
+
+/// A reader or writer that fails with [`io::ErrorKind::TimedOut`] once a read or write blocked for too long
+pub struct IdleTimeout<T> {
+    inner: T,
+    timeout: Option<Duration>,
+    monitor: IdleMonitor,
+}
+
+impl<T> IdleTimeout<T> {
+    /// Wrap `inner`, failing reads and writes that block for longer than `timeout`, or never if it is `None`, and
+    /// report its inactivity to `monitor`
+    pub fn new(inner: T, timeout: Option<Duration>, monitor: IdleMonitor) -> Self {
+        Self {
+            inner,
+            timeout,
+            monitor,
+        }
+    }
+
+    /// Return the wrapped stream
+    pub fn get_ref(&self) -> &T {
+        &self.inner
+    }
+}
+
+impl<R: Read> Read for IdleTimeout<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let inner = &mut self.inner;
+        self.monitor.watch(self.timeout, || inner.read(buf))
+    }
+}
+
+impl<W: Write> Write for IdleTimeout<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let inner = &mut self.inner;
+        self.monitor.watch(self.timeout, || inner.write(buf))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let inner = &mut self.inner;
+        self.monitor.watch(self.timeout, || inner.flush())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Blocks for `delay` before each read and write
+    struct Slow {
+        delay: Duration,
+    }
+
+    impl Read for Slow {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            std::thread::sleep(self.delay);
+            buf[0] = b'x';
+            Ok(1)
+        }
+    }
+
+    impl Write for Slow {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            std::thread::sleep(self.delay);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn blocking_longer_than_the_timeout_fails() {
+        let monitor = IdleMonitor::default();
+        let slow = Slow {
+            delay: Duration::from_millis(30),
+        };
+        let mut writer = IdleTimeout::new(slow, Some(Duration::from_millis(10)), monitor.clone());
+        assert_eq!(writer.write(b"data").unwrap_err().kind(), io::ErrorKind::TimedOut);
+        assert_eq!(
+            monitor.timed_out(),
+            Some(TimedOut {
+                timeout: Duration::from_millis(10)
+            })
+        );
+        assert_eq!(monitor.idle(), None, "nothing blocks anymore");
+        assert_eq!(
+            writer.flush().unwrap_err().kind(),
+            io::ErrorKind::TimedOut,
+            "streams stay timed out"
+        );
+    }
+
+    #[test]
+    fn active_streams_and_disabled_timeouts_pass() {
+        let monitor = IdleMonitor::default();
+        let fast = Slow {
+            delay: Duration::from_millis(1),
+        };
+        let mut reader = IdleTimeout::new(fast, Some(Duration::from_secs(10)), monitor.clone());
+        assert_eq!(reader.read(&mut [0; 4]).unwrap(), 1);
+
+        let slow = Slow {
+            delay: Duration::from_millis(20),
+        };
+        let mut reader = IdleTimeout::new(slow, None, monitor.clone());
+        assert_eq!(reader.read(&mut [0; 4]).unwrap(), 1);
+        assert_eq!(monitor.timed_out(), None);
+    }
+
+    #[test]
+    fn the_monitor_sees_blocked_calls() {
+        let monitor = IdleMonitor::default();
+        let slow = Slow {
+            delay: Duration::from_millis(50),
+        };
+        let mut reader = IdleTimeout::new(slow, Some(Duration::from_secs(10)), monitor.clone());
+        std::thread::scope(|scope| {
+            scope.spawn(|| reader.read(&mut [0; 4]).unwrap());
+            std::thread::sleep(Duration::from_millis(25));
+            assert!(monitor.idle().is_some(), "the read is still blocked");
+        });
+        assert_eq!(monitor.idle(), None);
+    }
+}
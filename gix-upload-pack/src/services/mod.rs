@@ -5,15 +5,29 @@
 //! dependency-injected into protocol handlers for better testability and
 //! separation of concerns.
 
+pub mod authorization;
 pub mod capabilities;
 pub mod command_parser;
+pub mod grammar;
+pub mod graph;
+pub mod idle;
+pub mod negotiation;
 pub mod pack;
 pub mod packet_io;
 pub mod references;
+pub mod snapshot;
+pub mod stall;
 
 // Re-export commonly used types for convenience
+pub use authorization::{RefAuthorization, RefAuthorizer};
 pub use capabilities::CapabilityManager;
 pub use command_parser::CommandParser;
-pub use pack::{PackGenerator, ProgressReporter};
+pub use grammar::{GrammarViolation, GrammarWriter, PacketGrammar};
+pub use graph::CommitGraph;
+pub use idle::{IdleMonitor, IdleTimeout, TimedOut};
+pub use negotiation::ShallowBoundary;
+pub use pack::{ObjectExclusion, ObjectFirewall, PackGenerator, PackfileUri, ProgressReporter};
 pub use packet_io::PacketIOFactory;
 pub use references::ReferenceManager;
+pub use snapshot::{RefSnapshot, SnapshotRef};
+pub use stall::{StallDetection, StallMonitor, StallReader, Stalled};
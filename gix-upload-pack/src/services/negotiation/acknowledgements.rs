@@ -0,0 +1,158 @@
+//! Acknowledging the haves of protocol v0 and v1 clients
+//!
+//! Like git's `get_common_commits()`, each have is answered as it arrives, in the way the client asked for with its
+//! [`MultiAckMode`]:
+//! - Without `multi_ack`, only the first common have is acknowledged with `ACK <id>`, and each round of haves ends
+//!   with `NAK` until there is one.
+//! - With `multi_ack`, common haves are acknowledged with `ACK <id> continue`, and once the server is ready as each
+//!   want reaches a commit the client has, so are the haves it doesn't know, so the client stops walking their
+//!   history. Each round ends with `NAK`.
+//! - With `multi_ack_detailed`, common haves are acknowledged with `ACK <id> common`, and the server tells when it's
+//!   ready with `ACK <id> ready`. Clients asking for `no-done` then receive the pack right after that round.
+//!
+//! `done` is answered with `ACK <id>` of the last common have in multi-ack modes, or with `NAK` if nothing is common,
+//! and the pack follows. Over stateless-rpc, each request negotiates on its own, as clients send the haves known to
+//! be common again with the next round.
+
+use crate::{
+    error::Result,
+    services::CommitGraph,
+    types::{AckStatus, MultiAckMode},
+};
+use gix_hash::ObjectId;
+use std::collections::HashSet;
+
+/// A line answering haves or `done`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Answer {
+    /// Acknowledge an object
+    Ack(ObjectId, AckStatus),
+    /// Tell the client nothing is common, or that the round is over
+    Nak,
+}
+
+/// The acknowledgements of a protocol v0 or v1 negotiation, which lasts a session or a stateless request
+#[derive(Debug)]
+pub struct Acknowledgements {
+    mode: MultiAckMode,
+    no_done: bool,
+    /// Commits the client has, along with the parents of those it sent, like git's `THEY_HAVE` flag
+    they_have: HashSet<ObjectId>,
+    /// The number of haves that told something new, like git's `have_obj`
+    haves: usize,
+    /// The commit time of the oldest commit the client sent, below which walks looking for its commits stop
+    oldest_have: Option<i64>,
+    last_common: Option<ObjectId>,
+    got_common: bool,
+    got_other: bool,
+    sent_ready: bool,
+}
+
+impl Acknowledgements {
+    /// Acknowledge haves in `mode`, sending the pack without waiting for `done` once ready if `no_done`
+    ///
+    /// Like in git, `no_done` only has an effect with [`MultiAckMode::Detailed`].
+    pub fn new(mode: MultiAckMode, no_done: bool) -> Self {
+        Self {
+            mode,
+            no_done,
+            they_have: HashSet::new(),
+            haves: 0,
+            oldest_have: None,
+            last_common: None,
+            got_common: false,
+            got_other: false,
+            sent_ready: false,
+        }
+    }
+
+    /// Answer the have `id` of a client asking for `wants`, which the repository of `graph` has if `common`
+    pub fn have(
+        &mut self,
+        graph: &CommitGraph<'_>,
+        wants: &HashSet<ObjectId>,
+        id: ObjectId,
+        common: bool,
+    ) -> Result<Option<Answer>> {
+        if !common {
+            self.got_other = true;
+            if self.mode == MultiAckMode::None || !self.ready(graph, wants)? {
+                return Ok(None);
+            }
+            return Ok(Some(match self.mode {
+                MultiAckMode::Detailed => {
+                    self.sent_ready = true;
+                    Answer::Ack(id, AckStatus::Ready)
+                }
+                _ => Answer::Ack(id, AckStatus::Continue),
+            }));
+        }
+
+        if self.record(graph, id)? {
+            self.haves += 1;
+        }
+        self.got_common = true;
+        self.last_common = Some(id);
+        Ok(match self.mode {
+            MultiAckMode::Detailed => Some(Answer::Ack(id, AckStatus::DetailedCommon)),
+            MultiAckMode::Basic => Some(Answer::Ack(id, AckStatus::Continue)),
+            MultiAckMode::None => (self.haves == 1).then_some(Answer::Ack(id, AckStatus::Common)),
+        })
+    }
+
+    /// Answer the flush ending a round of haves of a client asking for `wants`, and return the answers along with
+    /// `true` if the pack follows without `done`
+    pub fn end_round(&mut self, graph: &CommitGraph<'_>, wants: &HashSet<ObjectId>) -> Result<(Vec<Answer>, bool)> {
+        let mut answers = Vec::new();
+        if let Some(last_common) = self.last_common {
+            if self.mode == MultiAckMode::Detailed && self.got_common && !self.got_other && self.ready(graph, wants)? {
+                self.sent_ready = true;
+                answers.push(Answer::Ack(last_common, AckStatus::Ready));
+            }
+        }
+        if self.haves == 0 || self.mode != MultiAckMode::None {
+            answers.push(Answer::Nak);
+        }
+        if self.no_done && self.sent_ready {
+            if let Some(last_common) = self.last_common {
+                answers.push(Answer::Ack(last_common, AckStatus::Common));
+                return Ok((answers, true));
+            }
+        }
+        self.got_common = false;
+        self.got_other = false;
+        Ok((answers, false))
+    }
+
+    /// Answer `done`, after which the pack follows
+    pub fn done(&self) -> Option<Answer> {
+        match self.last_common {
+            Some(last_common) if self.haves > 0 => {
+                (self.mode != MultiAckMode::None).then_some(Answer::Ack(last_common, AckStatus::Common))
+            }
+            _ => Some(Answer::Nak),
+        }
+    }
+
+    /// Return `true` if the server could send the pack now as each of `wants` reaches a commit the client has, like
+    /// git's `ok_to_give_up()`
+    fn ready(&self, graph: &CommitGraph<'_>, wants: &HashSet<ObjectId>) -> Result<bool> {
+        if self.haves == 0 {
+            return Ok(false);
+        }
+        super::wants_reach(graph, wants, &self.they_have, self.oldest_have.unwrap_or(0))
+    }
+
+    /// Remember that the client has the object `id` and return `true` if that's news, like git's `do_got_oid()`
+    ///
+    /// Commits are news only once, and mark their parents as known to the client as well.
+    fn record(&mut self, graph: &CommitGraph<'_>, id: ObjectId) -> Result<bool> {
+        let Some(commit) = graph.try_find(id)? else {
+            return Ok(true);
+        };
+        self.oldest_have = Some(self.oldest_have.map_or(commit.time, |oldest| oldest.min(commit.time)));
+        let news = self.they_have.insert(id);
+        self.they_have.extend(commit.parents);
+        Ok(news)
+    }
+}
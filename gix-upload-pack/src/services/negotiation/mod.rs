@@ -0,0 +1,101 @@
+//! Deciding when negotiation can end
+//!
+//! Like git's `ok_to_give_up()`, the server is ready to send a pack once every wanted commit has a commit the client
+//! has in its history. Sending more haves wouldn't shrink the pack noticeably then, so protocol v2 tells the client
+//! with `ready` and sends the pack in the same response.
+//!
+//! Protocol v0 and v1 clients learn about common commits and readiness through [`Acknowledgements`]. Fetches creating
+//! or deepening shallow clones negotiate the [shallow boundary](ShallowBoundary) of the client as well.
+
+pub mod acknowledgements;
+pub use acknowledgements::{Acknowledgements, Answer};
+pub mod shallow;
+pub use shallow::ShallowBoundary;
+
+use crate::error::Result;
+use crate::services::CommitGraph;
+use crate::types::NegotiationState;
+use gix_hash::ObjectId;
+use std::collections::HashSet;
+
+/// Return `true` if each want of `negotiation` reaches one of its common commits in `graph`, or one of their parents
+///
+/// Wants that aren't commits, and don't peel to one, don't need a common base. Without any common commits, the
+/// server is never ready and the client has to keep sending haves or send `done`. Common objects that aren't commits
+/// are ignored.
+pub fn ready_to_send_pack(graph: &CommitGraph<'_>, negotiation: &NegotiationState) -> Result<bool> {
+    if negotiation.common.is_empty() {
+        return Ok(false);
+    }
+    // Commits older than the oldest common commit can't lead to one, so walks stop there like git's do
+    let mut cutoff = None;
+    let mut they_have = negotiation.common.clone();
+    for id in &negotiation.common {
+        if let Some(commit) = graph.try_find(*id)? {
+            cutoff = Some(cutoff.map_or(commit.time, |seconds: i64| seconds.min(commit.time)));
+            they_have.extend(commit.parents);
+        }
+    }
+    let Some(cutoff) = cutoff else {
+        return Ok(false);
+    };
+    wants_reach(graph, &negotiation.wants, &they_have, cutoff)
+}
+
+/// Return `true` if each of `wants` reaches one of the commits in `they_have`, walking commits not older than
+/// `cutoff` like git's `can_all_from_reach_with_flag()`
+///
+/// Wants that aren't commits, and don't peel to one, don't need to reach any. Commits of the commit-graph with a
+/// generation number below those of all commits in `they_have` can't reach them and aren't walked either.
+pub(crate) fn wants_reach(
+    graph: &CommitGraph<'_>,
+    wants: &HashSet<ObjectId>,
+    they_have: &HashSet<ObjectId>,
+    cutoff: i64,
+) -> Result<bool> {
+    // Commits missing in the commit-graph are newer than all in it, so these can't reach them
+    let min_generation = they_have
+        .iter()
+        .map(|id| graph.generation(id).unwrap_or(u32::MAX))
+        .min()
+        .unwrap_or(u32::MAX);
+    for want in wants {
+        if they_have.contains(want) {
+            continue;
+        }
+        let Some(commit) = graph.peel_to_commit(*want)? else {
+            continue;
+        };
+        let mut seen = HashSet::from([commit.id]);
+        let mut stack = vec![commit];
+        let mut reaches = false;
+        'walk: while let Some(commit) = stack.pop() {
+            if they_have.contains(&commit.id) {
+                reaches = true;
+                break;
+            }
+            for parent in commit.parents {
+                // Like in git, commits the client has count even if they are older than the cutoff
+                if they_have.contains(&parent) {
+                    reaches = true;
+                    break 'walk;
+                }
+                if !seen.insert(parent)
+                    || graph
+                        .generation(&parent)
+                        .is_some_and(|generation| generation < min_generation)
+                {
+                    continue;
+                }
+                let parent = graph.find(parent)?;
+                if parent.time >= cutoff {
+                    stack.push(parent);
+                }
+            }
+        }
+        if !reaches {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
@@ -0,0 +1,334 @@
+//! Computing the shallow boundary of fetches creating or deepening shallow clones
+//!
+//! Clients ask for a shallow history with `deepen <depth>`, `deepen-since <timestamp>` and `deepen-not <ref>`, and
+//! tell about the shallow commits they already have with `shallow <id>` lines. Like git,
+//! [`ShallowBoundary::compute()`] determines the commits the client receives without their parents, announced with
+//! `shallow` lines, and the shallow commits of the client it now receives the parents of, announced with
+//! `unshallow` lines:
+//! - `deepen <depth>` cuts the history `depth` commits below the wants, which count as the first. With
+//!   `deepen-relative`, the commits are counted below the shallow commits of the client instead.
+//! - `deepen-since` and `deepen-not`, which may be combined, keep the commits not older than the timestamp and not
+//!   reachable from the refs, and cut the history below them.
+//!
+//! Nothing is persisted, the boundary only lasts for the request. Protocol v0 and v1 send the lines right after the
+//! wants, and protocol v2 in the `shallow-info` section of the response with the pack. The pack then stops at the
+//! boundary, see [`commits()`]. Shallow commits of the repository itself are a boundary as well.
+
+use crate::{
+    config::ServerOptions,
+    error::{Error, Result},
+    services::CommitGraph,
+    types::{DeepenSpec, NegotiationState},
+};
+use bstr::{BStr, BString};
+use gix::Repository;
+use gix_hash::ObjectId;
+use std::collections::{HashMap, HashSet, VecDeque};
+
+/// The depth clients send to fetch the complete history, like `git fetch --unshallow` does
+pub const INFINITE_DEPTH: u32 = 0x7fff_ffff;
+
+/// How the shallow boundary of the client changes with a fetch
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ShallowBoundary {
+    /// The commits the client receives without their parents, which it doesn't know to be shallow yet
+    pub shallow: Vec<ObjectId>,
+    /// The shallow commits of the client it now receives the parents of
+    pub unshallow: Vec<ObjectId>,
+    /// The parents of the `unshallow` commits, which are sent along with their history like the wants
+    pub parents: Vec<ObjectId>,
+}
+
+impl ShallowBoundary {
+    /// Compute the boundary for the deepening `negotiation` asks for, counting the depth from the shallow commits of
+    /// the client if `relative`
+    ///
+    /// Without deepening the boundary is empty, and the shallow commits of the client stay as they are.
+    pub fn compute(
+        repository: &Repository,
+        options: &ServerOptions,
+        negotiation: &NegotiationState,
+        relative: bool,
+    ) -> Result<Self> {
+        let Some(deepen) = negotiation.deepen.as_ref() else {
+            return Ok(Self::default());
+        };
+        if !options.allow_shallow {
+            return Err(shallow_error("shallow fetches are not allowed".into()));
+        }
+        let graph = Graph::new(repository, HashSet::new())?;
+        let client_shallow = &negotiation.shallow;
+        let (shallow, not_shallow) = match deepen {
+            DeepenSpec::Depth(depth) => {
+                if let Some(max) = options.max_shallow_depth.filter(|max| depth > max) {
+                    return Err(shallow_error(format!(
+                        "deepen {depth} exceeds the maximum depth of {max}"
+                    )));
+                }
+                if relative && !options.allow_deepen_relative {
+                    return Err(Error::UnsupportedCapability {
+                        capability: "deepen-relative".into(),
+                    });
+                }
+                if *depth >= INFINITE_DEPTH && graph.grafted.is_empty() {
+                    // The complete history has no boundary
+                    (Vec::new(), client_shallow.clone())
+                } else if relative {
+                    let mut heads = Vec::new();
+                    for id in client_shallow {
+                        if repository.find_commit(*id).is_ok() {
+                            heads.push(*id);
+                        }
+                    }
+                    graph.by_depth(heads, depth.saturating_add(1))?
+                } else {
+                    graph.by_depth(commit_wants(repository, &negotiation.wants)?, *depth)?
+                }
+            }
+            DeepenSpec::Revisions { since, not } => {
+                let mut hidden = Vec::new();
+                for name in not {
+                    hidden.extend(resolve_deepen_not(repository, name.as_ref())?);
+                }
+                graph.by_revisions(
+                    commit_wants(repository, &negotiation.wants)?,
+                    since.map(|since| since.seconds),
+                    hidden,
+                )?
+            }
+        };
+
+        let mut boundary = Self {
+            shallow: shallow.into_iter().filter(|id| !client_shallow.contains(id)).collect(),
+            unshallow: client_shallow
+                .iter()
+                .filter(|id| not_shallow.contains(*id))
+                .copied()
+                .collect(),
+            parents: Vec::new(),
+        };
+        boundary.shallow.sort();
+        boundary.unshallow.sort();
+        let mut seen = HashSet::new();
+        for id in &boundary.unshallow {
+            for parent in graph.parents(*id)? {
+                if seen.insert(parent) {
+                    boundary.parents.push(parent);
+                }
+            }
+        }
+        Ok(boundary)
+    }
+
+    /// The `shallow` and `unshallow` lines announcing the boundary to the client, in this order
+    ///
+    /// Like git, the lines don't end with a newline.
+    pub fn lines(&self) -> Vec<BString> {
+        let shallow = self.shallow.iter().map(|id| format!("shallow {id}").into());
+        let unshallow = self.unshallow.iter().map(|id| format!("unshallow {id}").into());
+        shallow.chain(unshallow).collect()
+    }
+}
+
+/// Return `true` if the client of `negotiation` is shallow or asks to be, so the pack stops at a boundary
+pub fn is_shallow(negotiation: &NegotiationState) -> bool {
+    !negotiation.shallow.is_empty() || negotiation.deepen.is_some()
+}
+
+/// The commits whose parents the pack for `negotiation` doesn't cross, the shallow commits of the client including
+/// the new ones, sorted
+pub fn boundary_commits(negotiation: &NegotiationState) -> Vec<ObjectId> {
+    let mut commits: Vec<_> = negotiation
+        .shallow
+        .iter()
+        .chain(&negotiation.shallow_boundary.shallow)
+        .copied()
+        .collect::<HashSet<_>>()
+        .into_iter()
+        .collect();
+    commits.sort();
+    commits
+}
+
+/// Return the commits to pack for `negotiation`, reachable from `tips` but not from `hidden` without crossing the
+/// shallow boundary of the client
+///
+/// The parents of unshallowed commits are walked like `tips`, while the unshallowed commits themselves are hidden
+/// as the client has them.
+pub(crate) fn commits(
+    repository: &Repository,
+    negotiation: &NegotiationState,
+    tips: impl IntoIterator<Item = ObjectId>,
+    hidden: impl IntoIterator<Item = ObjectId>,
+) -> Result<Vec<ObjectId>> {
+    let boundary = &negotiation.shallow_boundary;
+    let graph = Graph::new(repository, boundary_commits(negotiation).into_iter().collect())?;
+    let mut seen = graph.ancestry(hidden.into_iter().chain(boundary.unshallow.iter().copied()))?;
+    let mut queue: VecDeque<_> = tips
+        .into_iter()
+        .chain(boundary.parents.iter().copied())
+        .filter(|id| seen.insert(*id))
+        .collect();
+    let mut commits = Vec::new();
+    while let Some(id) = queue.pop_front() {
+        commits.push(id);
+        for parent in graph.parents(id)? {
+            if seen.insert(parent) {
+                queue.push_back(parent);
+            }
+        }
+    }
+    Ok(commits)
+}
+
+/// The commit history of a repository, with the commits of `grafted` having no parents
+struct Graph<'repo> {
+    commits: CommitGraph<'repo>,
+    grafted: HashSet<ObjectId>,
+}
+
+impl<'repo> Graph<'repo> {
+    /// Cut the history at `grafted` and the shallow commits of `repository`
+    fn new(repository: &'repo Repository, mut grafted: HashSet<ObjectId>) -> Result<Self> {
+        let own = repository
+            .shallow_commits()
+            .map_err(|err| shallow_error(format!("unable to read the shallow commits of the repository: {err}")))?;
+        grafted.extend(own.iter().flat_map(|commits| commits.iter().copied()));
+        Ok(Self {
+            commits: CommitGraph::new(repository),
+            grafted,
+        })
+    }
+
+    fn parents(&self, id: ObjectId) -> Result<Vec<ObjectId>> {
+        if self.grafted.contains(&id) {
+            return Ok(Vec::new());
+        }
+        Ok(self.commits.find(id)?.parents)
+    }
+
+    /// The commits reachable from `tips`, including themselves
+    fn ancestry(&self, tips: impl IntoIterator<Item = ObjectId>) -> Result<HashSet<ObjectId>> {
+        let mut seen = HashSet::new();
+        let mut queue: VecDeque<_> = tips.into_iter().filter(|id| seen.insert(*id)).collect();
+        while let Some(id) = queue.pop_front() {
+            for parent in self.parents(id)? {
+                if seen.insert(parent) {
+                    queue.push_back(parent);
+                }
+            }
+        }
+        Ok(seen)
+    }
+
+    /// Cut the history below `heads` at `depth` commits, and return the commits at the boundary along with those
+    /// above it
+    ///
+    /// Commits reachable along several paths count at their shallowest depth.
+    fn by_depth(&self, heads: Vec<ObjectId>, depth: u32) -> Result<(Vec<ObjectId>, HashSet<ObjectId>)> {
+        let mut depths = HashMap::new();
+        // Breadth-first, so each commit is seen at its shallowest depth first
+        let mut queue = VecDeque::new();
+        for head in heads {
+            if depths.insert(head, 1u32).is_none() {
+                queue.push_back(head);
+            }
+        }
+        let mut shallow = Vec::new();
+        let mut not_shallow = HashSet::new();
+        while let Some(id) = queue.pop_front() {
+            let current = depths[&id];
+            if current >= depth || self.grafted.contains(&id) {
+                shallow.push(id);
+                continue;
+            }
+            not_shallow.insert(id);
+            for parent in self.parents(id)? {
+                if let std::collections::hash_map::Entry::Vacant(entry) = depths.entry(parent) {
+                    entry.insert(current + 1);
+                    queue.push_back(parent);
+                }
+            }
+        }
+        Ok((shallow, not_shallow))
+    }
+
+    /// Keep the commits reachable from `heads` that aren't older than `since` and not reachable from `hidden`, and
+    /// return those with parents that aren't kept along with the others
+    fn by_revisions(
+        &self,
+        heads: Vec<ObjectId>,
+        since: Option<gix_date::SecondsSinceUnixEpoch>,
+        hidden: Vec<ObjectId>,
+    ) -> Result<(Vec<ObjectId>, HashSet<ObjectId>)> {
+        let hidden = self.ancestry(hidden)?;
+        let mut kept = HashSet::new();
+        let mut seen = HashSet::new();
+        let mut queue: VecDeque<_> = heads.into_iter().filter(|id| seen.insert(*id)).collect();
+        while let Some(id) = queue.pop_front() {
+            if hidden.contains(&id) {
+                continue;
+            }
+            if let Some(since) = since {
+                if self.commits.find(id)?.time < since {
+                    continue;
+                }
+            }
+            kept.insert(id);
+            for parent in self.parents(id)? {
+                if seen.insert(parent) {
+                    queue.push_back(parent);
+                }
+            }
+        }
+        if kept.is_empty() {
+            return Err(shallow_error("no commits selected for shallow requests".into()));
+        }
+
+        let mut shallow = Vec::new();
+        for id in &kept {
+            if self.parents(*id)?.iter().any(|parent| !kept.contains(parent)) {
+                shallow.push(*id);
+            }
+        }
+        for id in &shallow {
+            kept.remove(id);
+        }
+        Ok((shallow, kept))
+    }
+}
+
+/// The commits among `wants`, and those the annotated tags among them point to
+fn commit_wants(repository: &Repository, wants: &HashSet<ObjectId>) -> Result<Vec<ObjectId>> {
+    let mut commits = Vec::new();
+    for want in wants {
+        let object = repository
+            .find_object(*want)
+            .and_then(|object| object.peel_tags_to_end())
+            .map_err(|err| Error::custom(format!("Failed to look up want {want}: {err}")))?;
+        if object.kind == gix_object::Kind::Commit {
+            commits.push(object.id);
+        }
+    }
+    Ok(commits)
+}
+
+/// Resolve the ref `name` of a `deepen-not` line to the commit it points to, if it points to one
+fn resolve_deepen_not(repository: &Repository, name: &BStr) -> Result<Option<ObjectId>> {
+    let invalid = || shallow_error(format!("deepen-not doesn't name a ref: {name}"));
+    let mut reference = repository
+        .try_find_reference(name)
+        .map_err(|_| invalid())?
+        .ok_or_else(invalid)?;
+    let peel_failed = |err: &dyn std::fmt::Display| Error::custom(format!("Failed to peel {name}: {err}"));
+    let id = reference.peel_to_id_in_place().map_err(|err| peel_failed(&err))?;
+    let object = id
+        .object()
+        .and_then(|object| object.peel_tags_to_end())
+        .map_err(|err| peel_failed(&err))?;
+    Ok((object.kind == gix_object::Kind::Commit).then_some(object.id))
+}
+
+fn shallow_error(message: String) -> Error {
+    Error::Shallow { message }
+}
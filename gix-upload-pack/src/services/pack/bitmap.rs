@@ -0,0 +1,267 @@
+//! Enumerating the objects of a pack with reachability bitmaps
+//!
+//! Repositories repacked with `git repack -b`, or by `git gc` with `repack.writeBitmaps`, have a `.bitmap` file next to
+//! their pack with the objects reachable from selected commits. Like git, [`BitmapIndex::objects()`] combines the
+//! bitmaps of the wants and removes those of the haves, so only the objects between the tips and the selected commits
+//! are traversed. Each bit stands for an object of the pack, in the order of their offsets.
+//!
+//! Only bitmaps of a single pack are read. Enumeration falls back to traversal if the wants or haves reach objects
+//! outside the bitmapped pack, like loose objects written since, which git would add to an extended bitmap instead.
+
+use crate::error::{Error, Result};
+use gix::Repository;
+use gix_bitmap::ewah;
+use gix_hash::ObjectId;
+use gix_object::Kind;
+use std::collections::HashMap;
+use std::path::Path;
+
+/// The reachability bitmaps of a pack
+pub struct BitmapIndex {
+    index: gix_pack::index::File,
+    /// The index position of each object of the pack, by bit
+    objects: Vec<u32>,
+    /// The bit of each object of the pack, by index position
+    bits: Vec<u32>,
+    /// The entry of each commit with a bitmap
+    commits: HashMap<ObjectId, usize>,
+    entries: Vec<Entry>,
+}
+
+/// The bitmap of a selected commit, stored as XOR of the bitmap of the entry `xor_offset` entries before it
+struct Entry {
+    xor_offset: usize,
+    bitmap: ewah::Vec,
+}
+
+/// A set of objects of the pack
+#[derive(Clone)]
+struct Bits(Vec<u64>);
+
+impl Bits {
+    fn new(len: usize) -> Self {
+        Bits(vec![0; len.div_ceil(64)])
+    }
+
+    fn get(&self, bit: u32) -> bool {
+        self.0[bit as usize / 64] & (1 << (bit % 64)) != 0
+    }
+
+    fn set(&mut self, bit: usize) {
+        self.0[bit / 64] |= 1 << (bit % 64);
+    }
+
+    fn xor(&mut self, bitmap: &ewah::Vec) {
+        bitmap.for_each_set_bit(|bit| {
+            self.0[bit / 64] ^= 1 << (bit % 64);
+            Some(())
+        });
+    }
+
+    fn or(&mut self, other: &Bits) {
+        for (word, other) in self.0.iter_mut().zip(&other.0) {
+            *word |= other;
+        }
+    }
+
+    fn and_not(&mut self, other: &Bits) {
+        for (word, other) in self.0.iter_mut().zip(&other.0) {
+            *word &= !other;
+        }
+    }
+
+    fn ones(&self) -> impl Iterator<Item = usize> + '_ {
+        self.0.iter().enumerate().flat_map(|(index, word)| {
+            (0..64)
+                .filter(move |bit| word & (1 << bit) != 0)
+                .map(move |bit| index * 64 + bit)
+        })
+    }
+}
+
+impl BitmapIndex {
+    /// Open the bitmap of a pack of `repository`, or return `None` if none of its packs has one
+    ///
+    /// Like git, the first bitmap is used if there are several.
+    pub fn open(repository: &Repository) -> Result<Option<Self>> {
+        let Ok(entries) = std::fs::read_dir(repository.objects.store_ref().path().join("pack")) else {
+            return Ok(None);
+        };
+        let mut paths: Vec<_> = entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension().is_some_and(|extension| extension == "bitmap"))
+            .filter(|path| path.with_extension("idx").is_file())
+            .collect();
+        paths.sort();
+        paths
+            .first()
+            .map(|path| Self::at(path, repository.object_hash()))
+            .transpose()
+    }
+
+    /// Read the bitmap at `path` of the pack next to it, whose objects are hashed with `object_hash`
+    pub fn at(path: &Path, object_hash: gix_hash::Kind) -> Result<Self> {
+        let corrupt = |message: &str| Error::Pack(format!("Corrupt bitmap {}: {message}", path.display()));
+        let data =
+            std::fs::read(path).map_err(|err| Error::Pack(format!("Failed to read {}: {err}", path.display())))?;
+        let hash_len = object_hash.len_in_bytes();
+        let Some((header, mut rest)) = data.split_at_checked(12 + hash_len) else {
+            return Err(corrupt("truncated header"));
+        };
+        if &header[..4] != b"BITM" {
+            return Err(corrupt("not a bitmap"));
+        }
+        if header[4..6] != [0, 1] {
+            return Err(corrupt("unsupported version"));
+        }
+        let entry_count = u32::from_be_bytes(header[8..12].try_into().expect("4 bytes"));
+        let index = gix_pack::index::File::at(path.with_extension("idx"), object_hash)
+            .map_err(|err| Error::Pack(format!("Failed to open the index of {}: {err}", path.display())))?;
+        if index.pack_checksum().as_bytes() != &header[12..] {
+            return Err(corrupt("checksum doesn't match the pack"));
+        }
+
+        let mut objects: Vec<u32> = (0..index.num_objects()).collect();
+        objects.sort_by_key(|position| index.pack_offset_at_index(*position));
+        let mut bits = vec![0; objects.len()];
+        for (bit, position) in objects.iter().enumerate() {
+            bits[*position as usize] = bit as u32;
+        }
+
+        // The bitmaps of all commits, trees, blobs and tags aren't needed as objects are looked up anyway
+        for _ in 0..4 {
+            rest = ewah::decode(rest).map_err(|err| corrupt(&err.to_string()))?.1;
+        }
+        let mut commits = HashMap::new();
+        let mut entries = Vec::with_capacity(entry_count as usize);
+        for _ in 0..entry_count {
+            let Some((header, data)) = rest.split_at_checked(6) else {
+                return Err(corrupt("truncated entry"));
+            };
+            let position = u32::from_be_bytes(header[..4].try_into().expect("4 bytes"));
+            let xor_offset = header[4] as usize;
+            if position >= index.num_objects() || xor_offset > entries.len() {
+                return Err(corrupt("invalid entry"));
+            }
+            let (bitmap, data) = ewah::decode(data).map_err(|err| corrupt(&err.to_string()))?;
+            rest = data;
+            commits.insert(index.oid_at_index(position).to_owned(), entries.len());
+            entries.push(Entry { xor_offset, bitmap });
+        }
+        Ok(Self {
+            index,
+            objects,
+            bits,
+            commits,
+            entries,
+        })
+    }
+
+    /// The number of commits with a bitmap
+    pub fn num_bitmaps(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Return the objects of `repository` reachable from `wants` but not from `haves`, in the order of the pack, or
+    /// `None` if some of them aren't in the bitmapped pack
+    ///
+    /// Haves missing in the repository are ignored.
+    pub fn objects(
+        &self,
+        repository: &Repository,
+        wants: impl IntoIterator<Item = ObjectId>,
+        haves: impl IntoIterator<Item = ObjectId>,
+    ) -> Result<Option<Vec<ObjectId>>> {
+        let mut resolved = HashMap::new();
+        let haves = haves.into_iter().filter(|id| repository.has_object(id));
+        let Some(haves) = self.reachable(repository, haves, None, &mut resolved)? else {
+            return Ok(None);
+        };
+        let Some(mut wants) = self.reachable(repository, wants, Some(&haves), &mut resolved)? else {
+            return Ok(None);
+        };
+        wants.and_not(&haves);
+        Ok(Some(
+            wants
+                .ones()
+                .map(|bit| self.index.oid_at_index(self.objects[bit]).to_owned())
+                .collect(),
+        ))
+    }
+
+    /// The objects reachable from `tips`, without walking past those in `known`, or `None` if objects outside of
+    /// the pack are reachable
+    fn reachable(
+        &self,
+        repository: &Repository,
+        tips: impl IntoIterator<Item = ObjectId>,
+        known: Option<&Bits>,
+        resolved: &mut HashMap<usize, Bits>,
+    ) -> Result<Option<Bits>> {
+        let mut reachable = Bits::new(self.objects.len());
+        let mut stack: Vec<_> = tips.into_iter().collect();
+        while let Some(id) = stack.pop() {
+            let Some(position) = self.index.lookup(id) else {
+                return Ok(None);
+            };
+            let bit = self.bits[position as usize];
+            if reachable.get(bit) || known.is_some_and(|known| known.get(bit)) {
+                continue;
+            }
+            if let Some(entry) = self.commits.get(&id) {
+                reachable.or(self.bitmap(*entry, resolved));
+                continue;
+            }
+            reachable.set(bit as usize);
+            let lookup = |err: &dyn std::fmt::Display| Error::Pack(format!("Failed to look up {id}: {err}"));
+            let object = repository.find_object(id).map_err(|err| lookup(&err))?;
+            match object.kind {
+                Kind::Commit => {
+                    let mut commit = gix_object::CommitRefIter::from_bytes(&object.data);
+                    stack.push(commit.tree_id().map_err(|err| lookup(&err))?);
+                    stack.extend(commit.parent_ids());
+                }
+                Kind::Tree => {
+                    for entry in gix_object::TreeRefIter::from_bytes(&object.data) {
+                        let entry = entry.map_err(|err| lookup(&err))?;
+                        // Submodule commits aren't part of the repository
+                        if !entry.mode.is_commit() {
+                            stack.push(entry.oid.to_owned());
+                        }
+                    }
+                }
+                Kind::Tag => stack.push(
+                    gix_object::TagRefIter::from_bytes(&object.data)
+                        .target_id()
+                        .map_err(|err| lookup(&err))?,
+                ),
+                Kind::Blob => {}
+            }
+        }
+        Ok(Some(reachable))
+    }
+
+    /// The bitmap of `entry`, resolving the entries it's stored relative to into `resolved`
+    fn bitmap<'a>(&self, entry: usize, resolved: &'a mut HashMap<usize, Bits>) -> &'a Bits {
+        let mut chain = vec![entry];
+        while let Some(&last) = chain.last() {
+            let xor_offset = self.entries[last].xor_offset;
+            if resolved.contains_key(&last) || xor_offset == 0 {
+                break;
+            }
+            chain.push(last - xor_offset);
+        }
+        let mut bits = match resolved.get(chain.last().expect("at least the entry")) {
+            Some(bits) => {
+                chain.pop();
+                bits.clone()
+            }
+            None => Bits::new(self.objects.len()),
+        };
+        for entry in chain.into_iter().rev() {
+            bits.xor(&self.entries[entry].bitmap);
+            resolved.insert(entry, bits.clone());
+        }
+        &resolved[&entry]
+    }
+}
@@ -0,0 +1,145 @@
+//! Batched existence checks for many objects at once
+//!
+//! Checking objects one by one costs a lookup in each pack index, and for every object that isn't packed a
+//! `stat()` of its loose object file. Objects that don't exist at all additionally make the object database rescan
+//! its pack directory, as a pack with them may have appeared in the meantime. With thousands of wants, these system
+//! calls dominate.
+//!
+//! [`find_existing()`] probes the pack indexes with all ids in sorted order instead, then reads each loose object
+//! directory that may contain one of the remaining ids once. Ids found in neither are probed against the packs
+//! written in the meantime, with a single rescan of the pack directories for all of them, as `git repack` may have
+//! moved loose objects into a new pack while they were checked.
+
+use crate::error::{Error, Result};
+use gix::Repository;
+use gix_hash::ObjectId;
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Path, PathBuf};
+
+/// Return the ids of `ids` that exist in `repository`, packed or loose, including its alternates
+pub fn find_existing<'a>(
+    repository: &Repository,
+    ids: impl IntoIterator<Item = &'a ObjectId>,
+) -> Result<HashSet<ObjectId>> {
+    let mut ids: Vec<ObjectId> = ids.into_iter().copied().collect();
+    ids.sort_unstable();
+    ids.dedup();
+    let mut existing = HashSet::with_capacity(ids.len());
+    if ids.is_empty() {
+        return Ok(existing);
+    }
+
+    let store = repository.objects.store_ref();
+    let mut dbs = vec![store.path().to_owned()];
+    dbs.extend(
+        store
+            .alternate_db_paths()
+            .map_err(|err| Error::custom(format!("Failed to load alternate object databases: {err}")))?,
+    );
+    let object_hash = repository.object_hash();
+    let mut seen_indexes = HashSet::new();
+    let indexes = pack_indexes(&dbs, object_hash, &mut seen_indexes)?;
+    let mut remaining = probe(&indexes, ids, &mut existing);
+    if remaining.is_empty() {
+        return Ok(existing);
+    }
+
+    let mut by_directory = BTreeMap::<u8, Vec<ObjectId>>::new();
+    for id in remaining {
+        by_directory.entry(id.as_bytes()[0]).or_default().push(id);
+    }
+    remaining = Vec::new();
+    for (first_byte, ids) in by_directory {
+        let directory = format!("{first_byte:02x}");
+        let mut files = HashSet::new();
+        for db in &dbs {
+            files.extend(loose_objects(&db.join(&directory), object_hash)?);
+        }
+        for id in ids {
+            if files.contains(&id) {
+                existing.insert(id);
+            } else {
+                remaining.push(id);
+            }
+        }
+    }
+    if remaining.is_empty() {
+        return Ok(existing);
+    }
+
+    // Loose objects may have been packed after the pack indexes were opened
+    let indexes = pack_indexes(&dbs, object_hash, &mut seen_indexes)?;
+    remaining.sort_unstable();
+    probe(&indexes, remaining, &mut existing);
+    Ok(existing)
+}
+
+/// Insert the `ids` found in `indexes` into `existing` and return the others, keeping their order
+fn probe(indexes: &[gix_pack::index::File], ids: Vec<ObjectId>, existing: &mut HashSet<ObjectId>) -> Vec<ObjectId> {
+    ids.into_iter()
+        .filter(|id| {
+            let found = indexes.iter().any(|index| index.lookup(id).is_some());
+            if found {
+                existing.insert(*id);
+            }
+            !found
+        })
+        .collect()
+}
+
+/// Open the pack indexes in the object databases `dbs` that aren't in `seen` yet, and add them to it
+fn pack_indexes(
+    dbs: &[PathBuf],
+    object_hash: gix_hash::Kind,
+    seen: &mut HashSet<PathBuf>,
+) -> Result<Vec<gix_pack::index::File>> {
+    let mut indexes = Vec::new();
+    for db in dbs {
+        for path in directory_entries(&db.join("pack"))? {
+            if path.extension().and_then(|ext| ext.to_str()) != Some("idx") || !seen.insert(path.clone()) {
+                continue;
+            }
+            match gix_pack::index::File::at(&path, object_hash) {
+                Ok(index) => indexes.push(index),
+                // The pack was removed after its directory was read, its objects are in a newer one
+                Err(gix_pack::index::init::Error::Io { source, .. })
+                    if source.kind() == std::io::ErrorKind::NotFound => {}
+                Err(err) => {
+                    return Err(Error::custom(format!(
+                        "Failed to open pack index {}: {err}",
+                        path.display()
+                    )))
+                }
+            }
+        }
+    }
+    Ok(indexes)
+}
+
+/// Return the ids of the objects in the loose object directory `directory`, or none if it doesn't exist
+fn loose_objects(directory: &Path, object_hash: gix_hash::Kind) -> std::io::Result<Vec<ObjectId>> {
+    let mut hex = Vec::with_capacity(object_hash.len_in_hex());
+    Ok(directory_entries(directory)?
+        .into_iter()
+        .filter_map(|path| {
+            hex.clear();
+            hex.extend_from_slice(directory.file_name()?.as_encoded_bytes());
+            hex.extend_from_slice(path.file_name()?.as_encoded_bytes());
+            ObjectId::from_hex(&hex).ok()
+        })
+        .collect())
+}
+
+/// Return the paths of the entries of `directory`, or none if it doesn't exist
+fn directory_entries(directory: &Path) -> std::io::Result<Vec<PathBuf>> {
+    let entries = match std::fs::read_dir(directory) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err),
+    };
+    let mut paths = Vec::new();
+    for entry in entries {
+        paths.push(entry?.path());
+    }
+    Ok(paths)
+}
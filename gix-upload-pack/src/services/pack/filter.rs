@@ -0,0 +1,277 @@
+//! Leaving objects out of packs according to the filter of partial clones
+//!
+//! Clients fetching with `--filter` send a [`FilterSpec`], which [`ObjectFilter`] applies to the counted objects like
+//! `git rev-list --filter` does:
+//! - `blob:none` omits all blobs, and `blob:limit=<n>` the blobs of at least `n` bytes.
+//! - `tree:<depth>` omits trees and blobs at least `depth` levels below a root tree, which is at depth 0. Objects
+//!   reachable at several depths count at the shallowest one.
+//! - `sparse:oid=<blob-ish>` omits blobs whose path doesn't match the sparse-checkout patterns in that blob. Paths
+//!   matched by no pattern are decided by their closest matched parent directory, and omitted if there is none.
+//! - `object:type=<type>` omits all objects of other types.
+//! - `combine:<spec>+...` omits the objects any of its parts omits.
+//!
+//! Like in git, filters don't apply to the objects the client asked for by id and the objects the annotated tags
+//! among them point to. The entries of such trees are at depth 0, like the root trees of commits.
+
+use crate::{
+    config::FilterSpec,
+    error::{Error, Result},
+};
+use bstr::{BStr, BString, ByteSlice, ByteVec};
+use gix::Repository;
+use gix_hash::ObjectId;
+use gix_object::Kind;
+use gix_pack::data::output;
+use std::collections::{HashMap, HashSet, VecDeque};
+
+/// A [`FilterSpec`] prepared to be applied to the objects of a pack
+#[derive(Debug)]
+pub struct ObjectFilter {
+    rule: Rule,
+}
+
+/// A part of a filter, with the patterns of `sparse:oid` filters loaded
+#[derive(Debug)]
+enum Rule {
+    BlobLimit(u64),
+    TreeDepth(u64),
+    Sparse(gix_ignore::Search),
+    ObjectType(Kind),
+    All(Vec<Rule>),
+}
+
+/// What a rule decides on for an object
+struct Object<'a> {
+    kind: Kind,
+    size: u64,
+    /// The shallowest depth below a root tree, or `None` if the object wasn't reached within the depth needed
+    depth: Option<u64>,
+    /// The paths of a blob below a root tree, empty if it's a root itself
+    paths: &'a [BString],
+}
+
+impl ObjectFilter {
+    /// Prepare `spec` for objects of `repository`, loading the patterns of `sparse:oid` filters
+    pub fn new(repository: &Repository, spec: &FilterSpec) -> Result<Self> {
+        Ok(Self {
+            rule: Rule::new(repository, spec)?,
+        })
+    }
+
+    /// Remove the objects the filter omits from `counts`, except for `wants` and what tags point to, and return how
+    /// many were removed
+    pub fn apply(
+        &self,
+        repository: &Repository,
+        counts: &mut Vec<output::Count>,
+        wants: &HashSet<ObjectId>,
+    ) -> Result<usize> {
+        let mut headers = HashMap::with_capacity(counts.len());
+        for count in counts.iter() {
+            let header = repository
+                .find_header(count.id)
+                .map_err(|err| Error::Pack(format!("Object header lookup failed: {err}")))?;
+            headers.insert(count.id, (header.kind(), header.size()));
+        }
+        // Objects the client asked for are provided, along with what the tags among them point to
+        let mut provided = wants.clone();
+        for (id, (kind, _)) in &headers {
+            if *kind == Kind::Tag {
+                let target = repository
+                    .find_object(*id)
+                    .and_then(|tag| tag.peel_tags_to_end())
+                    .map_err(|err| Error::Pack(format!("Object lookup failed: {err}")))?;
+                provided.insert(target.id);
+            }
+        }
+        let walk = match self.rule.walk_depth() {
+            Some(max_depth) => {
+                let commits = headers
+                    .iter()
+                    .filter(|(_, (kind, _))| *kind == Kind::Commit)
+                    .map(|(id, _)| *id);
+                Walk::new(repository, commits, &provided, max_depth, self.rule.needs_paths())?
+            }
+            None => Walk::default(),
+        };
+
+        let before = counts.len();
+        counts.retain(|count| {
+            let (kind, size) = headers[&count.id];
+            provided.contains(&count.id)
+                || self.rule.includes(&Object {
+                    kind,
+                    size,
+                    depth: walk.depths.get(&count.id).copied(),
+                    paths: walk.paths.get(&count.id).map_or(&[], Vec::as_slice),
+                })
+        });
+        Ok(before - counts.len())
+    }
+}
+
+impl Rule {
+    fn new(repository: &Repository, spec: &FilterSpec) -> Result<Self> {
+        Ok(match spec {
+            FilterSpec::BlobNone => Rule::BlobLimit(0),
+            FilterSpec::BlobLimit(limit) => Rule::BlobLimit(*limit),
+            FilterSpec::TreeDepth(depth) => Rule::TreeDepth(*depth),
+            FilterSpec::SparseOid(blob) => Rule::Sparse(sparse_patterns(repository, blob.as_bstr())?),
+            FilterSpec::ObjectType(kind) => {
+                Rule::ObjectType(Kind::from_bytes(kind).map_err(|_| Error::InvalidFilter {
+                    message: format!("invalid object type '{kind}'"),
+                })?)
+            }
+            FilterSpec::Combine(parts) => Rule::All(
+                parts
+                    .iter()
+                    .map(|part| Rule::new(repository, part))
+                    .collect::<Result<_>>()?,
+            ),
+        })
+    }
+
+    /// The depth up to which trees have to be walked to decide, unlimited for sparse patterns, or `None` for no walk
+    fn walk_depth(&self) -> Option<u64> {
+        match self {
+            Rule::TreeDepth(depth) => Some(*depth),
+            Rule::Sparse(_) => Some(u64::MAX),
+            Rule::All(rules) => rules.iter().filter_map(Rule::walk_depth).max(),
+            Rule::BlobLimit(_) | Rule::ObjectType(_) => None,
+        }
+    }
+
+    fn needs_paths(&self) -> bool {
+        match self {
+            Rule::Sparse(_) => true,
+            Rule::All(rules) => rules.iter().any(Rule::needs_paths),
+            _ => false,
+        }
+    }
+
+    fn includes(&self, object: &Object<'_>) -> bool {
+        match self {
+            Rule::BlobLimit(limit) => object.kind != Kind::Blob || object.size < *limit,
+            Rule::TreeDepth(max) => {
+                !matches!(object.kind, Kind::Tree | Kind::Blob) || object.depth.is_some_and(|depth| depth < *max)
+            }
+            Rule::Sparse(patterns) => {
+                object.kind != Kind::Blob
+                    || object.paths.is_empty()
+                    || object
+                        .paths
+                        .iter()
+                        .any(|path| sparse_includes(patterns, path.as_bstr()))
+            }
+            Rule::ObjectType(kind) => object.kind == *kind,
+            Rule::All(rules) => rules.iter().all(|rule| rule.includes(object)),
+        }
+    }
+}
+
+/// Load the sparse-checkout patterns from the blob `spec` names, like `main:.sparse` or an object id
+fn sparse_patterns(repository: &Repository, spec: &BStr) -> Result<gix_ignore::Search> {
+    let unavailable = || Error::InvalidFilter {
+        message: format!("unable to access sparse blob in '{spec}'"),
+    };
+    let id = repository.rev_parse_single(spec).map_err(|_| unavailable())?;
+    let blob = id.object().map_err(|_| unavailable())?;
+    if blob.kind != Kind::Blob {
+        return Err(unavailable());
+    }
+    let mut search = gix_ignore::Search::default();
+    search.add_patterns_buffer(&blob.data, spec.to_string(), None, Default::default());
+    Ok(search)
+}
+
+/// Return `true` if the last pattern matching `path`, or else its closest matched parent directory, includes it
+fn sparse_includes(patterns: &gix_ignore::Search, mut path: &BStr) -> bool {
+    let mut is_dir = false;
+    loop {
+        if let Some(matched) =
+            patterns.pattern_matching_relative_path(path, Some(is_dir), gix_ignore::glob::pattern::Case::Sensitive)
+        {
+            return !matched.pattern.is_negative();
+        }
+        match path.rfind_byte(b'/') {
+            Some(parent) => path = path[..parent].as_bstr(),
+            None => return false,
+        }
+        is_dir = true;
+    }
+}
+
+/// The depths and paths of trees and blobs below the roots of the counted objects
+#[derive(Default)]
+struct Walk {
+    depths: HashMap<ObjectId, u64>,
+    paths: HashMap<ObjectId, Vec<BString>>,
+}
+
+impl Walk {
+    /// Walk the trees of `commits` and the `provided` objects up to `max_depth`, recording the paths of blobs if
+    /// `with_paths`
+    ///
+    /// Like in git, the entries of provided trees are at depth 0, as filters don't apply to the trees themselves.
+    fn new(
+        repository: &Repository,
+        commits: impl IntoIterator<Item = ObjectId>,
+        provided: &HashSet<ObjectId>,
+        max_depth: u64,
+        with_paths: bool,
+    ) -> Result<Self> {
+        let lookup = |err: &dyn std::fmt::Display| Error::Pack(format!("Object lookup failed: {err}"));
+        let mut walk = Walk::default();
+        // Breadth-first, so each object is seen at its shallowest depth first
+        let mut queue: VecDeque<(ObjectId, u64, BString)> = VecDeque::new();
+        for id in provided {
+            if repository.find_header(*id).map_err(|err| lookup(&err))?.kind() == Kind::Tree {
+                walk.depths.insert(*id, 0);
+                queue.push_back((*id, 0, BString::default()));
+            }
+        }
+        for commit in commits {
+            let commit = repository.find_commit(commit).map_err(|err| lookup(&err))?;
+            let tree = commit.tree_id().map_err(|err| lookup(&err))?.detach();
+            if walk.depths.insert(tree, 0).is_none() {
+                queue.push_back((tree, 1, BString::default()));
+            }
+        }
+
+        while let Some((id, depth, path)) = queue.pop_front() {
+            if depth >= max_depth && !with_paths {
+                // Nothing below this tree is kept for its depth
+                continue;
+            }
+            let tree = repository.find_tree(id).map_err(|err| lookup(&err))?;
+            for entry in gix_object::TreeRefIter::from_bytes(&tree.data) {
+                let entry = entry?;
+                if entry.mode.is_commit() {
+                    continue;
+                }
+                let id = entry.oid.to_owned();
+                let entry_path = if with_paths {
+                    let mut entry_path = path.clone();
+                    if !entry_path.is_empty() {
+                        entry_path.push_byte(b'/');
+                    }
+                    entry_path.push_str(entry.filename);
+                    entry_path
+                } else {
+                    BString::default()
+                };
+                if !entry.mode.is_tree() && with_paths {
+                    walk.paths.entry(id).or_default().push(entry_path.clone());
+                }
+                if walk.depths.contains_key(&id) {
+                    continue;
+                }
+                walk.depths.insert(id, depth);
+                if entry.mode.is_tree() {
+                    queue.push_back((id, depth + 1, entry_path));
+                }
+            }
+        }
+        Ok(walk)
+    }
+}
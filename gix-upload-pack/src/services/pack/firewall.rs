@@ -0,0 +1,119 @@
+//! Leaving objects out of generated packs
+//!
+//! Hosting layers sometimes have to stop serving objects that can't be removed from the repository yet, like a
+//! blob with an accidentally committed secret. An [`ObjectFirewall`] set with
+//! [`ServerOptions::with_object_firewall()`](crate::ServerOptions::with_object_firewall()) is asked about every
+//! object that would be packed for a fetch, and the objects it excludes are left out.
+//!
+//! The pack stays valid as thin packs are disabled whenever objects are excluded, so entries stored as deltas
+//! against an excluded object are sent in full instead of referring to a base the client doesn't have.
+//! Only blobs can be left out, and only for clients that requested a filter, as partial clones are the only ones
+//! expecting objects to be missing. Fetches that would lose wants, commits, trees or tags, or blobs of full clones,
+//! are refused with [`Error::ExcludedObjects`] instead.
+
+use crate::error::{Error, Result};
+use gix::Repository;
+use gix_hash::{oid, ObjectId};
+use gix_pack::data::output;
+use std::{collections::HashSet, fmt, sync::Arc};
+
+/// Decides which objects must not be sent to clients
+pub trait ObjectFirewall: Send + Sync {
+    /// Return `true` if the object `id` must not be sent
+    fn excludes(&self, id: &oid) -> bool;
+}
+
+impl<F> ObjectFirewall for F
+where
+    F: Fn(&oid) -> bool + Send + Sync,
+{
+    fn excludes(&self, id: &oid) -> bool {
+        self(id)
+    }
+}
+
+impl ObjectFirewall for HashSet<ObjectId> {
+    fn excludes(&self, id: &oid) -> bool {
+        self.contains(id)
+    }
+}
+
+/// A cheaply clonable handle to an [`ObjectFirewall`] implementation
+#[derive(Clone)]
+pub struct ObjectExclusion(Arc<dyn ObjectFirewall>);
+
+impl ObjectExclusion {
+    /// Create a handle to `firewall`
+    pub fn new(firewall: impl ObjectFirewall + 'static) -> Self {
+        Self(Arc::new(firewall))
+    }
+}
+
+impl fmt::Debug for ObjectExclusion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ObjectExclusion")
+    }
+}
+
+/// An object the firewall excluded, but which can't be left out of the pack
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExcludedObject {
+    /// The excluded object
+    pub id: ObjectId,
+    /// The kind of the excluded object
+    pub kind: gix_object::Kind,
+    /// Whether the client asked for the object directly
+    pub want: bool,
+}
+
+impl fmt::Display for ExcludedObject {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let want = if self.want { "wanted " } else { "" };
+        write!(f, "{} ({want}{})", self.id, self.kind)
+    }
+}
+
+/// Remove the objects `exclusion` excludes from `counts` and return how many were removed
+///
+/// `wants` are the objects the client asked for, and `partial` is `true` if the client requested a filter and thus
+/// tolerates missing blobs. Fail with all excluded objects if any of them can't be left out.
+pub(crate) fn exclude(
+    repository: &Repository,
+    exclusion: &ObjectExclusion,
+    counts: &mut Vec<output::Count>,
+    wants: &HashSet<ObjectId>,
+    partial: bool,
+) -> Result<usize> {
+    let before = counts.len();
+    let mut refused = Vec::new();
+    let mut lookup_error = None;
+    counts.retain(|count| {
+        if !exclusion.0.excludes(&count.id) {
+            return true;
+        }
+        match repository.find_header(count.id) {
+            Ok(header) => {
+                let want = wants.contains(&count.id);
+                let kind = header.kind();
+                if want || kind != gix_object::Kind::Blob || !partial {
+                    refused.push(ExcludedObject {
+                        id: count.id,
+                        kind,
+                        want,
+                    });
+                }
+            }
+            Err(err) => {
+                lookup_error.get_or_insert(err);
+            }
+        }
+        false
+    });
+    if let Some(err) = lookup_error {
+        return Err(Error::Pack(format!("Object kind lookup failed: {err}")));
+    }
+    if !refused.is_empty() {
+        return Err(Error::ExcludedObjects(refused));
+    }
+    Ok(before - counts.len())
+}
@@ -3,22 +3,47 @@
 //! This module replaces our manual pack generation with the sophisticated
 //! gix-pack system, providing delta compression, streaming output, and
 //! better performance.
+//!
+//! Fetches with a filter omitting all trees, like `tree:0` for metadata-only mirrors, take a fast path: the
+//! commits of the revision walk are counted as they are, without decoding a single tree, and so are the objects
+//! the client already has.
+//!
+//! Like git, commits are looked up in the [commit-graph](CommitGraph) of the repository when it has one, so telling
+//! commits from other wants and haves and walking the history of shallow clients doesn't decode them. The revision
+//! walk of other fetches uses the commit-graph as well, unless `core.commitGraph` turns it off.
+//!
+//! Repositories with a [reachability bitmap](BitmapIndex) have the objects between the wants and the haves enumerated
+//! from it instead, without traversing the trees of the commits it covers.
+//!
+//! Packs begin with the leading objects of a pack of the repository if they contain them, copied verbatim like git's
+//! pack reuse, see [`ReusedObjects`]. The entries of the other objects are copied from their packs one by one.
+//! Entries are generated [`max_in_flight_entries`](ServerOptions::max_in_flight_entries) at a time and sent as they
+//! are written, instead of holding the whole pack in memory.
+//!
+//! Like git, keep-alive packets are sent while counting and compressing take longer than
+//! [`keepalive`](ServerOptions::keepalive), see [`keepalive`].
 
 use crate::{
-    config::ServerOptions,
+    config::{FilterSpec, MissingObjectPolicy, ServerOptions},
     error::{Error, Result},
-    services::pack::ProgressReporter,
-    services::packet_io::EnhancedPacketWriter,
+    log::{debug, span, trace, Logger},
+    services::pack::{
+        filter::ObjectFilter, find_missing, firewall, keepalive, uris, verify_pack, BitmapIndex, ObjectExclusion,
+        PackObjectsHook, PackWriter, PackfileUri, ProgressReporter, ReusedObjects, Verification,
+    },
+    services::{negotiation::shallow, packet_io::EnhancedPacketWriter, CommitGraph},
     types::*,
 };
+use bstr::ByteSlice;
 use gix::Repository;
 use gix_features::{
     parallel,
     progress::{self},
 };
+use gix_hash::ObjectId;
 use gix_pack::data::output;
+use std::collections::HashSet;
 use std::io::Write;
-use std::sync::atomic::AtomicBool;
 
 /// Adapter to make Repository objects compatible with gix_pack::Find trait
 #[derive(Clone)]
@@ -63,6 +88,18 @@ impl gix_pack::Find for RepositoryFindAdapter {
         self.objects.contains(id)
     }
 
+    fn try_find<'a>(
+        &self,
+        id: &gix_hash::oid,
+        buffer: &'a mut Vec<u8>,
+    ) -> std::result::Result<
+        Option<(gix_object::Data<'a>, Option<gix_pack::data::entry::Location>)>,
+        Box<dyn std::error::Error + Send + Sync + 'static>,
+    > {
+        // Use the caches of the session, see `ObjectCaches`
+        gix_pack::Find::try_find(&*self.objects, id, buffer)
+    }
+
     fn try_find_cached<'a>(
         &self,
         id: &gix_hash::oid,
@@ -97,6 +134,16 @@ impl gix_pack::Find for RepositoryFindAdapter {
 /// Pack generator using gix-pack infrastructure for advanced pack generation
 pub struct PackGenerator<'a> {
     repository: &'a Repository,
+    /// The options the generator was created with, to create it anew on other threads
+    options: &'a ServerOptions,
+    commits: CommitGraph<'a>,
+    logger: Logger,
+    verify: bool,
+    max_in_flight_entries: usize,
+    missing_objects: MissingObjectPolicy,
+    exclusion: Option<ObjectExclusion>,
+    hook: Option<PackObjectsHook>,
+    packfile_uris: Vec<PackfileUri>,
 }
 
 /// Statistics about pack generation
@@ -110,6 +157,30 @@ pub struct PackStats {
     pub delta_objects: u32,
     /// Compression ratio achieved
     pub compression_ratio: f64,
+    /// The outcome of verifying the pack before sending it, if enabled
+    pub verification: Option<Verification>,
+    /// Number of objects copied verbatim from the beginning of a pack, see [`ReusedObjects`]
+    pub pack_reused: u32,
+}
+
+/// The size of a pack without entries, its header and trailing checksum
+const EMPTY_PACK_SIZE: u64 = 32;
+
+/// The objects a pack would contain and its approximate size, see [`PackGenerator::estimate()`]
+#[derive(Debug, Clone)]
+pub struct Estimate {
+    /// Number of objects in the pack
+    pub objects: usize,
+    /// Approximate size of the pack, based on the size the objects have in the object database
+    pub approx_bytes: u64,
+    /// Number of objects left out by the filter the client requested, see [`ObjectFilter`]
+    pub filtered: usize,
+    /// Number of objects left out by the [`ObjectFirewall`](crate::services::pack::ObjectFirewall)
+    pub excluded: usize,
+    /// The pre-built packs the client downloads for blobs left out of the pack, see [`PackfileUri`]
+    pub packfile_uris: Vec<PackfileUri>,
+    /// The counted objects along with the amount enumerated before removing those the client has, if any
+    counted: Option<(Vec<output::Count>, usize)>,
 }
 
 /// Git-native pack configuration values for compatibility
@@ -117,12 +188,48 @@ pub struct PackStats {
 struct PackConfig {
     threads: usize,
     window: usize,
+    allow_pack_reuse: bool,
 }
 
 impl<'a> PackGenerator<'a> {
     /// Create a new pack generator
-    pub fn new(repository: &'a Repository, _options: &'a ServerOptions) -> Self {
-        Self { repository }
+    pub fn new(repository: &'a Repository, options: &'a ServerOptions) -> Self {
+        Self {
+            repository,
+            options,
+            commits: CommitGraph::new(repository),
+            logger: options.logger.clone(),
+            verify: options.verify_pack,
+            max_in_flight_entries: options.max_in_flight_entries.max(1),
+            missing_objects: options.missing_objects,
+            exclusion: options.object_exclusion.clone(),
+            hook: options
+                .pack_objects_hook
+                .as_ref()
+                .map(|command| PackObjectsHook::new(command, options.keepalive)),
+            packfile_uris: options.packfile_uris.clone(),
+        }
+    }
+
+    /// Check that `wants` and the objects they directly lead to exist, see [`find_missing()`]
+    ///
+    /// Depending on the configured [`MissingObjectPolicy`], fail with all missing objects or remove the affected
+    /// wants so the pack is generated for the remaining ones.
+    pub fn check_wants(&self, wants: &mut HashSet<ObjectId>) -> Result<()> {
+        let missing = find_missing(self.repository, wants.iter())?;
+        if missing.is_empty() {
+            return Ok(());
+        }
+        match self.missing_objects {
+            MissingObjectPolicy::Error => Err(Error::MissingObjects(missing)),
+            MissingObjectPolicy::Skip => {
+                for object in missing {
+                    debug!(self.logger, "Skipping want {}, missing {}", object.want, object);
+                    wants.remove(&object.want);
+                }
+                Ok(())
+            }
+        }
     }
 
     /// Create an optimized RepositoryFindAdapter with buffer pool optimization
@@ -135,41 +242,143 @@ impl<'a> PackGenerator<'a> {
     }
 
     /// Get Git-native pack configuration values optimized for performance
-    fn get_pack_config(&self) -> PackConfig {
+    fn get_pack_config(&self) -> Result<PackConfig> {
         let config = self.repository.config_snapshot();
         let available_threads = std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
 
-        PackConfig {
+        Ok(PackConfig {
             // Optimize thread count: use available cores but cap at 8 to avoid overhead
-            threads: config
-                .integer("pack.threads")
+            threads: crate::config::keys::pack::THREADS
+                .get(&config)?
                 .unwrap_or(available_threads as i64)
-                .max(1)
-                .min(8) as usize,
+                .clamp(1, 8) as usize,
             // Larger window for better delta compression but not too large to avoid memory pressure
-            window: config.integer("pack.window").unwrap_or(50).max(10).min(250) as usize,
+            window: crate::config::keys::pack::WINDOW
+                .get(&config)?
+                .unwrap_or(50)
+                .clamp(10, 250) as usize,
+            allow_pack_reuse: crate::config::keys::pack::ALLOW_PACK_REUSE
+                .get(&config)?
+                .unwrap_or(true),
+        })
+    }
+
+    /// Count the objects a pack for `session` would contain and approximate its size, without generating it
+    ///
+    /// This runs the counting phase only, so embedders can enforce quotas or warn about large fetches before any
+    /// data is streamed. Pass the result to [`generate_pack_from_estimate()`](Self::generate_pack_from_estimate())
+    /// to avoid counting again. Objects are enumerated with the reachability bitmap of a pack if possible, see
+    /// [`BitmapIndex`], and traversed otherwise.
+    pub fn estimate(&self, session: &SessionContext) -> Result<Estimate> {
+        let _span = span!(self.options.enable_tracing, "counting");
+        self.options.cancellation.check()?;
+        let (mut counts, total_objects) = match self.count_with_bitmaps(session)? {
+            Some(counted) => counted,
+            None => {
+                let object_ids = self.prepare_minimal_objects(session)?;
+                if object_ids.is_empty() {
+                    (Vec::new(), 0)
+                } else {
+                    self.count_objects(object_ids, session)?
+                }
+            }
+        };
+        if total_objects == 0 {
+            return Ok(Estimate {
+                objects: 0,
+                approx_bytes: EMPTY_PACK_SIZE,
+                filtered: 0,
+                excluded: 0,
+                packfile_uris: Vec::new(),
+                counted: None,
+            });
         }
+
+        let (filtered, excluded) = self.omit_objects(&mut counts, session)?;
+        let packfile_uris = uris::offload(
+            self.repository,
+            &self.packfile_uris,
+            session.capabilities.packfile_uri_protocols(),
+            &mut counts,
+        )?;
+        if !packfile_uris.is_empty() {
+            debug!(self.logger, "Offloading blobs to {} packfile URIs", packfile_uris.len());
+        }
+        let mut approx_bytes = EMPTY_PACK_SIZE;
+        for count in &counts {
+            approx_bytes += match count.entry_pack_location.as_ref() {
+                Some(location) => location.entry_size as u64,
+                // Loose objects are compressed when packed, so their size is an upper bound
+                None => self
+                    .repository
+                    .find_header(count.id)
+                    .map_err(|e| Error::Pack(format!("Object size lookup failed: {}", e)))?
+                    .size(),
+            };
+        }
+        Ok(Estimate {
+            objects: counts.len(),
+            approx_bytes,
+            filtered,
+            excluded,
+            packfile_uris,
+            counted: Some((counts, total_objects)),
+        })
     }
 
     /// Generate a pack file using EnhancedPacketWriter for proper sideband handling
+    ///
+    /// If a [`PackObjectsHook`] is configured, it generates the pack instead, unless objects have to be left out by
+    /// the [`ObjectFirewall`](crate::services::ObjectFirewall). Blobs offloaded to [packfile URIs](PackfileUri) are
+    /// left out as well, use [`estimate()`](Self::estimate()) to learn about the packs the client has to download.
     pub fn generate_pack<W: Write>(
         &self,
         writer: &mut EnhancedPacketWriter<W>,
         session: &SessionContext,
     ) -> Result<PackStats> {
-        let object_ids = self.prepare_minimal_objects(session)?;
+        let offloads = !self.packfile_uris.is_empty() && !session.capabilities.packfile_uri_protocols().is_empty();
+        match (&self.hook, &self.exclusion) {
+            (Some(hook), None) if !offloads => {
+                return hook.generate_pack(self.repository.git_dir(), writer, session, &self.logger);
+            }
+            (Some(_), Some(_)) => debug!(
+                self.logger,
+                "Generating the pack internally to apply the object firewall"
+            ),
+            (Some(_), None) => debug!(self.logger, "Generating the pack internally to offload blobs"),
+            (None, _) => {}
+        }
+        // The repository can't be shared with the thread counting the objects, but a handle to it can be moved there
+        let repository = self.repository.clone();
+        let options = self.options;
+        let estimate = keepalive::during(writer, self.options.keepalive, move || {
+            PackGenerator::new(&repository, options).estimate(session)
+        })??;
+        self.generate_pack_from_estimate(writer, session, estimate)
+    }
 
-        if object_ids.is_empty() {
+    /// Generate the pack that `estimate` was computed for with [`estimate()`](Self::estimate()) on the same `session`
+    pub fn generate_pack_from_estimate<W: Write>(
+        &self,
+        writer: &mut EnhancedPacketWriter<W>,
+        session: &SessionContext,
+        estimate: Estimate,
+    ) -> Result<PackStats> {
+        let Some((counts, total_objects)) = estimate.counted else {
             // Return empty pack
-            return self.write_empty_pack(writer.inner_mut(), session);
-        }
+            return self.write_empty_pack(writer, session);
+        };
 
-        // Step 2: Use gix-pack's count::objects to analyze and expand the objects
-        // This replaces our manual enumeration - gix-pack will do tree traversal for us
-        let (counts, count_stats) = self.count_objects_with_expansion(object_ids, writer, session)?;
+        // Step 2: Report the objects counted by gix-pack's count::objects
+        self.report_counted_objects(writer, &counts, total_objects)?;
 
         // Step 3: Compress and stream pack data using gix-pack's FromEntriesIter
-        let pack_stats = self.stream_pack_data(writer, counts, count_stats.total_objects, session)?;
+        // Deltas may not refer to omitted objects, which thin packs would do for bases missing in the pack
+        let allow_thin_pack = session.capabilities.thin_pack()
+            && estimate.filtered == 0
+            && estimate.excluded == 0
+            && estimate.packfile_uris.is_empty();
+        let pack_stats = self.stream_pack_data(writer, counts, total_objects, allow_thin_pack)?;
 
         // Step 4: Send final status message (Git-compatible)
         self.send_final_status(writer, &pack_stats, session)?;
@@ -179,6 +388,8 @@ impl<'a> PackGenerator<'a> {
             pack_size: pack_stats.pack_size,
             delta_objects: pack_stats.delta_objects,
             compression_ratio: pack_stats.compression_ratio,
+            verification: pack_stats.verification,
+            pack_reused: pack_stats.pack_reused,
         })
     }
 
@@ -209,15 +420,16 @@ impl<'a> PackGenerator<'a> {
             }
 
             // Separate commits from other objects
-            if let Ok(_commit) = self.repository.find_commit(*want) {
+            if self.commits.is_commit(want) {
                 commit_wants.push(*want);
             } else {
                 non_commit_wants.push(*want);
             }
         }
 
-        // For commits, use gix repository's revision walker for better performance
-        if !commit_wants.is_empty() {
+        // For commits, use gix repository's revision walker for better performance. Deepening shallow clients may not
+        // want new commits, but the parents of their unshallowed ones.
+        if !commit_wants.is_empty() || !session.negotiation.shallow_boundary.parents.is_empty() {
             let traverse_start = std::time::Instant::now();
 
             // Create excluded commits list for efficient filtering
@@ -226,37 +438,47 @@ impl<'a> PackGenerator<'a> {
                 .chain(common.iter())
                 .filter(|id| {
                     // Only exclude commits, not other object types
-                    self.repository.find_commit(***id).is_ok()
+                    self.commits.is_commit(id)
                 })
                 .map(|id| **id)
                 .collect();
 
-            // Use gix repository's optimized revision walker
-            let walk = self
-                .repository
-                .rev_walk(commit_wants)
-                .with_hidden(excluded_commits)
-                .sorting(gix::revision::walk::Sorting::ByCommitTime(
-                    gix_traverse::commit::simple::CommitTimeOrder::NewestFirst,
-                ))
-                .all()
-                .map_err(|e| Error::custom(format!("Revision walk setup failed: {}", e)))?;
-
-            // Collect all reachable commits efficiently
-            for commit_info in walk {
-                let commit_info = commit_info.map_err(|e| Error::custom(format!("Revision walk failed: {}", e)))?;
-                all_objects.push(commit_info.id);
+            if shallow::is_shallow(&session.negotiation) {
+                // The revision walker doesn't know the shallow commits of the client, which it must not cross
+                all_objects = shallow::commits(self.repository, &session.negotiation, commit_wants, excluded_commits)?;
+            } else {
+                // Use gix repository's optimized revision walker
+                let walk = self
+                    .repository
+                    .rev_walk(commit_wants)
+                    .with_hidden(excluded_commits)
+                    .sorting(gix::revision::walk::Sorting::ByCommitTime(
+                        gix_traverse::commit::simple::CommitTimeOrder::NewestFirst,
+                    ))
+                    .all()
+                    .map_err(|e| Error::custom(format!("Revision walk setup failed: {}", e)))?;
+
+                // Collect all reachable commits efficiently
+                for commit_info in walk {
+                    let commit_info = commit_info.map_err(|e| Error::custom(format!("Revision walk failed: {}", e)))?;
+                    all_objects.push(commit_info.id);
+                }
             }
 
             let traverse_duration = traverse_start.elapsed();
-            eprintln!("Prepare objects timing: Commit traversal took {:?}", traverse_duration);
+            trace!(
+                self.logger,
+                "Prepare objects timing: Commit traversal took {:?}",
+                traverse_duration
+            );
         }
 
         // Add non-commit objects directly
         all_objects.extend(non_commit_wants);
 
         let prepare_duration = prepare_start.elapsed();
-        eprintln!(
+        trace!(
+            self.logger,
             "Prepare objects timing: Collected {} total objects in {:?}",
             all_objects.len(),
             prepare_duration
@@ -266,23 +488,29 @@ impl<'a> PackGenerator<'a> {
     }
 
     /// Use gix-pack's count::objects with TreeContents expansion to do all the work
-    fn count_objects_with_expansion<W: Write>(
+    fn count_objects(
         &self,
         object_ids: Vec<gix_hash::ObjectId>,
-        writer: &mut EnhancedPacketWriter<W>,
         session: &SessionContext,
-    ) -> Result<(Vec<output::Count>, output::count::objects::Outcome)> {
+    ) -> Result<(Vec<output::Count>, usize)> {
         let count_start = std::time::Instant::now();
 
-        // Progress reporting will be handled directly through EnhancedPacketWriter
-
         // Start the gix-pack counting with optimized adapter and Git-native configuration
         let find_adapter = self.create_optimized_find_adapter();
-        let pack_config = self.get_pack_config();
+        let pack_config = self.get_pack_config()?;
 
-        // For now, always use TreeContents to match our original behavior
+        // For now, always use TreeContents to match our original behavior, unless no trees are sent at all
         // The TreeAdditionsComparedToAncestor mode might be filtering too aggressively
-        let expansion_mode = output::count::objects::ObjectExpansion::TreeContents;
+        let commits_only = omits_trees(session);
+        let (expansion_mode, object_ids) = if commits_only {
+            trace!(self.logger, "Count objects: Filter omits trees, counting commits only");
+            (
+                output::count::objects::ObjectExpansion::AsIs,
+                self.with_tag_targets(object_ids)?,
+            )
+        } else {
+            (output::count::objects::ObjectExpansion::TreeContents, object_ids)
+        };
 
         let counting_start = std::time::Instant::now();
 
@@ -296,31 +524,201 @@ impl<'a> PackGenerator<'a> {
             find_adapter.clone(),
             Box::new(objects_iter),
             &progress::Discard,
-            &AtomicBool::new(false),
+            self.options.cancellation.as_atomic(),
             output::count::objects::Options {
                 input_object_expansion: expansion_mode,
                 thread_limit: Some(pack_config.threads.min(8)), // Limit threads to avoid overhead
                 chunk_size: pack_config.window.max(50),         // Larger chunks for better efficiency
             },
         )
-        .map_err(|e| Error::Pack(format!("Object counting failed: {}", e)))?;
+        .map_err(|e| match e {
+            output::count::objects::Error::Interrupted => Error::Cancelled,
+            e => Error::Pack(format!("Object counting failed: {}", e)),
+        })?;
         let counting_duration = counting_start.elapsed();
-        eprintln!("Count objects timing: Actual counting took {:?}", counting_duration);
+        trace!(
+            self.logger,
+            "Count objects timing: Actual counting took {:?}",
+            counting_duration
+        );
 
-        // Now we need to filter out objects that the client already has
-        if !session.negotiation.haves.is_empty() || !session.negotiation.common.is_empty() {
+        // Now we need to filter out objects that the client already has, including its unshallowed commits
+        let negotiation = &session.negotiation;
+        if !negotiation.haves.is_empty()
+            || !negotiation.common.is_empty()
+            || !negotiation.shallow_boundary.unshallow.is_empty()
+        {
             let filter_start = std::time::Instant::now();
-            counts = self.filter_existing_objects(counts, session)?;
+            counts = if commits_only {
+                // The revision walk already hid the commits the client has, so only the haves themselves remain
+                let existing: HashSet<_> = session
+                    .negotiation
+                    .haves
+                    .iter()
+                    .chain(&session.negotiation.common)
+                    .collect();
+                counts
+                    .into_iter()
+                    .filter(|count| !existing.contains(&count.id))
+                    .collect()
+            } else {
+                self.filter_existing_objects(counts, session)?
+            };
             let filter_duration = filter_start.elapsed();
-            eprintln!("Count objects timing: Object filtering took {:?}", filter_duration);
+            trace!(
+                self.logger,
+                "Count objects timing: Object filtering took {:?}",
+                filter_duration
+            );
         }
-        // Send progress message if progress is enabled
-        if !session.capabilities.no_progress {
-            writer.send_progress(&format!("Enumerating objects: {}, done.", stats.total_objects))?;
+
+        // Report final completion
+        let count_total_duration = count_start.elapsed();
+        trace!(
+            self.logger,
+            "Count objects timing: Total counting took {:?} - {} total objects (expanded from {} input objects)",
+            count_total_duration,
+            stats.total_objects,
+            stats.input_objects
+        );
+
+        Ok((counts, stats.total_objects))
+    }
+
+    /// Enumerate the objects for `session` with the reachability bitmap of a pack, along with the amount enumerated,
+    /// or return `None` to traverse them instead
+    ///
+    /// Like git, bitmaps aren't used for shallow clients, whose pack stops at their boundary, in shallow repositories
+    /// or if `pack.useBitmaps` is off. Fetches omitting all trees take their own fast path.
+    fn count_with_bitmaps(&self, session: &SessionContext) -> Result<Option<(Vec<output::Count>, usize)>> {
+        let negotiation = &session.negotiation;
+        if shallow::is_shallow(negotiation)
+            || !negotiation.shallow_boundary.unshallow.is_empty()
+            || self.repository.is_shallow()
+            || omits_trees(session)
+            || crate::config::keys::pack::USE_BITMAPS
+                .get(&self.repository.config_snapshot())?
+                .is_some_and(|use_bitmaps| !use_bitmaps)
+        {
+            return Ok(None);
         }
+        let bitmaps = match BitmapIndex::open(self.repository) {
+            Ok(Some(bitmaps)) => bitmaps,
+            Ok(None) => return Ok(None),
+            Err(err) => {
+                // Like git, a broken bitmap only makes counting slower
+                debug!(self.logger, "Ignoring the bitmap: {}", err);
+                return Ok(None);
+            }
+        };
 
-        let mut progress_reporter =
-            ProgressReporter::new(writer, "Counting objects".to_string(), Some(stats.total_objects));
+        let count_start = std::time::Instant::now();
+        let haves = negotiation.haves.iter().chain(&negotiation.common).copied();
+        let Some(object_ids) = bitmaps.objects(self.repository, negotiation.wants.iter().copied(), haves)? else {
+            debug!(
+                self.logger,
+                "Counting objects by traversal, as some are missing in the bitmapped pack"
+            );
+            return Ok(None);
+        };
+        let find_adapter = self.create_optimized_find_adapter();
+        let mut buf = Vec::new();
+        let counts: Vec<_> = object_ids
+            .into_iter()
+            .map(|id| {
+                let location = gix_pack::Find::location_by_oid(&find_adapter, &id, &mut buf);
+                output::Count::from_data(id, location)
+            })
+            .collect();
+        trace!(
+            self.logger,
+            "Count objects timing: Enumerating {} objects with {} bitmaps took {:?}",
+            counts.len(),
+            bitmaps.num_bitmaps(),
+            count_start.elapsed()
+        );
+        let total_objects = counts.len();
+        Ok(Some((counts, total_objects)))
+    }
+
+    /// Leave out the objects the filter of `session` omits and those the object firewall excludes from `counts`, and
+    /// return how many were filtered and excluded
+    fn omit_objects(&self, counts: &mut Vec<output::Count>, session: &SessionContext) -> Result<(usize, usize)> {
+        // Leave out what the client's filter omits, before the firewall refuses objects the client doesn't want anyway
+        let filtered = match session.capabilities.filter() {
+            Some(spec) => {
+                let spec = FilterSpec::parse(&spec.to_str_lossy())?;
+                let filtered = ObjectFilter::new(self.repository, &spec)?.apply(
+                    self.repository,
+                    counts,
+                    &session.negotiation.wants,
+                )?;
+                debug!(self.logger, "Object filter {:?}: Omitted {} objects", spec, filtered);
+                filtered
+            }
+            None => 0,
+        };
+
+        let excluded = match self.exclusion.as_ref() {
+            Some(exclusion) => {
+                let partial = session.capabilities.filter().is_some();
+                let excluded =
+                    firewall::exclude(self.repository, exclusion, counts, &session.negotiation.wants, partial)?;
+                debug!(self.logger, "Object firewall: Excluded {} objects", excluded);
+                excluded
+            }
+            None => 0,
+        };
+
+        Ok((filtered, excluded))
+    }
+
+    /// Add the objects annotated tags among `object_ids` point to, if these are tags or commits
+    ///
+    /// Counting objects as they are doesn't peel tags, which would otherwise be sent without their commits.
+    fn with_tag_targets(&self, mut object_ids: Vec<ObjectId>) -> Result<Vec<ObjectId>> {
+        let mut seen: HashSet<_> = object_ids.iter().copied().collect();
+        let mut idx = 0;
+        while idx < object_ids.len() {
+            let id = object_ids[idx];
+            idx += 1;
+            let header = self
+                .repository
+                .find_header(id)
+                .map_err(|e| Error::Pack(format!("Object header lookup failed: {}", e)))?;
+            if header.kind() != gix_object::Kind::Tag {
+                continue;
+            }
+            let target = self
+                .repository
+                .find_tag(id)
+                .map_err(|e| Error::Pack(format!("Tag lookup failed: {}", e)))?
+                .target_id()
+                .map_err(|e| Error::Pack(format!("Tag decoding failed: {}", e)))?
+                .detach();
+            let target_kind = self
+                .repository
+                .find_header(target)
+                .map_err(|e| Error::Pack(format!("Object header lookup failed: {}", e)))?
+                .kind();
+            if matches!(target_kind, gix_object::Kind::Tag | gix_object::Kind::Commit) && seen.insert(target) {
+                object_ids.push(target);
+            }
+        }
+        Ok(object_ids)
+    }
+
+    /// Send the progress of enumerating and counting `counts` out of `total_objects`
+    fn report_counted_objects<W: Write>(
+        &self,
+        writer: &mut EnhancedPacketWriter<W>,
+        counts: &[output::Count],
+        total_objects: usize,
+    ) -> Result<()> {
+        // The writer drops progress messages unless the session wants them
+        writer.send_progress(&format!("Enumerating objects: {}, done.", total_objects))?;
+
+        let mut progress_reporter = ProgressReporter::new(writer, "Counting objects".to_string(), Some(total_objects));
 
         let _actual_count = counts.iter().fold(ObjectCount::default(), |mut c, _e| {
             c.add(gix_pack::data::output::entry::Kind::Base(gix_object::Kind::Blob));
@@ -330,150 +728,252 @@ impl<'a> PackGenerator<'a> {
         });
 
         // Send final completion message (Git-style)
-        progress_reporter.finish()?;
-
-        // Report final completion
-        let count_total_duration = count_start.elapsed();
-        eprintln!(
-            "Count objects timing: Total counting took {:?} - {} total objects (expanded from {} input objects)",
-            count_total_duration, stats.total_objects, stats.input_objects
-        );
-
-        Ok((counts, stats))
+        progress_reporter.finish()
     }
 
-    /// Stream pack data using gix-pack's FromEntriesIter
+    /// Stream pack data batch by batch, copying the leading objects of a pack verbatim if possible, see
+    /// [`ReusedObjects`]
+    ///
+    /// The entries of up to `max_in_flight_entries` objects are generated at a time and sent as they are written,
+    /// unless the pack is verified before any of it is sent.
     fn stream_pack_data<W: Write>(
         &self,
         writer: &mut EnhancedPacketWriter<W>,
-        counts: Vec<output::Count>,
+        mut counts: Vec<output::Count>,
         total_objects: usize,
-        session: &SessionContext,
+        allow_thin_pack: bool,
     ) -> Result<PackGenerationStats> {
         let find_adapter = self.create_optimized_find_adapter();
-        let pack_config = self.get_pack_config();
-
-        let entries_iter_start = std::time::Instant::now();
-        let mut entries_iter = output::entry::iter_from_counts(
-            counts,
-            find_adapter,
-            Box::new(progress::Discard),
-            output::entry::iter_from_counts::Options {
-                allow_thin_pack: session.capabilities.thin_pack,
-                thread_limit: Some(pack_config.threads.min(8)), // Limit threads to avoid overhead
-                chunk_size: pack_config.window.max(100),        // Larger chunks for better efficiency
-                ..Default::default()
-            },
-        );
-        let entries_iter_duration = entries_iter_start.elapsed();
-        eprintln!(
-            "Pack streaming timing: Entries iterator creation took {:?}",
-            entries_iter_duration
-        );
+        let pack_config = self.get_pack_config()?;
 
-        // Use InOrderIter to properly sort the parallel chunks by sequence ID, following the example
-        let entries_collect_start = std::time::Instant::now();
-        let entries: Vec<_> = parallel::InOrderIter::from(entries_iter.by_ref())
-            .collect::<std::result::Result<Vec<_>, _>>()
-            .map_err(|e| Error::Pack(format!("Entry generation failed: {}", e)))?
-            .into_iter()
-            .flatten()
-            .collect();
-        let entries_collect_duration = entries_collect_start.elapsed();
-        eprintln!(
-            "Pack streaming timing: Entries collection took {:?}",
-            entries_collect_duration
+        let reuse_start = std::time::Instant::now();
+        let reused = if pack_config.allow_pack_reuse {
+            ReusedObjects::take(&mut counts, &find_adapter)?
+        } else {
+            ReusedObjects::default()
+        };
+        trace!(
+            self.logger,
+            "Pack streaming timing: Reusing {} objects verbatim took {:?}",
+            reused.len(),
+            reuse_start.elapsed()
+        );
+        let num_objects = reused.len() + counts.len();
+        let _span = span!(
+            self.options.enable_tracing,
+            "streaming",
+            objects = num_objects,
+            pack_reused = reused.len()
         );
 
-        let actual_count = entries.len();
+        // Order objects like each batch does, so delta bases are written before their deltas
+        counts.sort_by_key(|count| {
+            count
+                .entry_pack_location
+                .as_ref()
+                .map(|location| (location.pack_id, location.pack_offset))
+        });
 
         // send compressing status to sideband (this is the compression/writing phase)
         let mut progress_reporter =
             ProgressReporter::new(writer, "Compressing objects".to_string(), Some(total_objects));
+        let mut pack_writer = PackWriter::new(Vec::new(), num_objects as u32, &reused, self.repository.object_hash())?;
+        // Remember which objects the entries are for, as they are consumed by the pack writer
+        let mut ids = Vec::new();
+        if self.verify {
+            ids.extend_from_slice(&reused.ids);
+        }
 
-        // Count entry types for debugging, following the example pattern
-        let entry_stats = entries.iter().fold(ObjectCount::default(), |mut c, e| {
-            c.add(e.kind);
-            progress_reporter.update(c.total()).unwrap_or(());
-            c
-        });
-
-        progress_reporter.finish()?;
-
-        // CRITICAL FIX: Use a temporary buffer to collect all pack data first,
-        // then write it in properly sized sideband packets
-        let pack_writer_start = std::time::Instant::now();
-
-        // Write pack data to a temporary buffer first
-        let mut pack_buffer = Vec::new();
-        let mut pack_writer = output::bytes::FromEntriesIter::new(
-            std::iter::once(Ok::<_, output::entry::iter_from_counts::Error>(entries)),
-            &mut pack_buffer,
-            actual_count as u32,
-            gix_pack::data::Version::V2,
-            self.repository.object_hash(),
-        );
-        let pack_writer_duration = pack_writer_start.elapsed();
-        eprintln!(
-            "Pack streaming timing: Pack writer creation took {:?}",
-            pack_writer_duration
-        );
-
-        let mut total_bytes_written = 0u64;
+        // Like with git, compression is done before the last of the pack is sent, which is all of it for packs of
+        // a single batch. Reused objects need no compression.
+        let mut counts = counts.into_iter();
+        let mut compressed = counts.len() == 0;
+        progress_reporter.update(reused.len()).unwrap_or(());
+        if compressed {
+            progress_reporter.finish()?;
+        }
 
-        // Stream the pack data to the buffer first
         let streaming_start = std::time::Instant::now();
-        for result in &mut pack_writer {
-            let bytes_written = result.map_err(|e| Error::Pack(format!("Pack streaming failed: {}", e)))?;
-            total_bytes_written += bytes_written;
+        loop {
+            self.options.cancellation.check()?;
+            let written = pack_writer.write_reused(&find_adapter, self.max_in_flight_entries)?;
+            if written == 0 {
+                break;
+            }
+            self.send_written(progress_reporter.writer(), &mut pack_writer)?;
         }
-        let streaming_duration = streaming_start.elapsed();
 
-        // Get the final pack digest before we use the buffer
-        let pack_digest = pack_writer
-            .digest()
-            .ok_or_else(|| Error::Pack("Pack generation incomplete".to_string()))?;
+        let mut entry_stats = ObjectCount::default();
+        let mut copied = 0;
+        let mut recompressed = 0;
+        loop {
+            self.options.cancellation.check()?;
+            let batch: Vec<_> = counts.by_ref().take(self.max_in_flight_entries).collect();
+            if batch.is_empty() {
+                break;
+            }
+            let options = output::entry::iter_from_counts::Options {
+                // Deltas against objects of other batches can only refer to their base by id
+                allow_thin_pack: true,
+                thread_limit: Some(pack_config.threads.min(8)), // Limit threads to avoid overhead
+                chunk_size: pack_config.window.max(100),        // Larger chunks for better efficiency
+                ..Default::default()
+            };
+            let (find, cancellation) = (find_adapter.clone(), &self.options.cancellation);
+            let compression = span!(self.options.enable_tracing, "compression", objects = batch.len());
+            let (chunks, outcome) = keepalive::during(progress_reporter.writer(), self.options.keepalive, move || {
+                let mut entries_iter =
+                    output::entry::iter_from_counts(batch, find, Box::new(progress::Discard), options);
+                // Use InOrderIter to write the parallel chunks in the order of their sequence ID, until cancelled
+                let chunks: std::result::Result<Vec<_>, _> = parallel::InOrderIter::from(entries_iter.by_ref())
+                    .take_while(|_| !cancellation.is_cancelled())
+                    .collect();
+                // Finalizing compresses the remaining chunks, while dropping the iterator stops its threads
+                let outcome =
+                    (!cancellation.is_cancelled()).then(|| parallel::reduce::Finalize::finalize(entries_iter));
+                (chunks, outcome)
+            })?;
+            drop(compression);
+            let outcome = outcome.ok_or(Error::Cancelled)?;
+            let chunks = chunks.map_err(|e| Error::Pack(format!("Entry generation failed: {}", e)))?;
+            pack_writer.next_batch();
+            if counts.len() == 0 {
+                progress_reporter.set_current(num_objects);
+                progress_reporter.finish()?;
+                compressed = true;
+            }
+
+            for mut entries in chunks {
+                if entries.iter().any(output::Entry::is_invalid) {
+                    return Err(Error::Pack("Objects vanished while generating the pack".into()));
+                }
+                if !allow_thin_pack {
+                    recompressed += self.recompress_thin_deltas(&mut entries, &pack_writer, &find_adapter)?;
+                }
+                for entry in &entries {
+                    entry_stats.add(entry.kind);
+                    if self.verify {
+                        ids.push(entry.id);
+                    }
+                }
+                if !compressed {
+                    progress_reporter
+                        .update(reused.len() + entry_stats.total())
+                        .unwrap_or(());
+                }
+                pack_writer.write_entries(entries)?;
+                self.send_written(progress_reporter.writer(), &mut pack_writer)?;
+            }
+            copied += outcome
+                .map_err(|e| Error::Pack(format!("Entry generation failed: {}", e)))?
+                .objects_copied_from_pack;
+        }
 
-        eprintln!(
+        let (pack_digest, total_bytes_written) = pack_writer.finish()?;
+        trace!(
+            self.logger,
             "Pack streaming timing: Pack data generation took {:?}, {} bytes",
-            streaming_duration,
-            pack_buffer.len()
+            streaming_start.elapsed(),
+            total_bytes_written
         );
 
-        // Now write the complete pack data through the sideband writer in proper chunks
-        let sideband_start = std::time::Instant::now();
-        writer.send_data(&pack_buffer)?;
-        let sideband_duration = sideband_start.elapsed();
-        eprintln!(
-            "Pack streaming timing: Sideband transmission took {:?}",
-            sideband_duration
-        );
+        // Check the pack before any of it reaches the client, so corrupt objects fail the request instead
+        let verification = if self.verify {
+            let (pack, object_hash) = (pack_writer.out_mut(), self.repository.object_hash());
+            let verification = keepalive::during(writer, self.options.keepalive, move || {
+                verify_pack(pack, object_hash, &ids)
+            })??;
+            trace!(
+                self.logger,
+                "Pack verification: {} entries, {} objects hashed in {:?}",
+                verification.entries,
+                verification.hashed_objects,
+                verification.duration
+            );
+            Some(verification)
+        } else {
+            None
+        };
+        writer.send_data(pack_writer.out_mut())?;
 
-        eprintln!(
+        trace!(
+            self.logger,
             "Debug: Pack generation complete - {} bytes written, digest: {}",
             total_bytes_written,
             pack_digest.to_hex()
         );
 
+        let written_deltas = (entry_stats.delta_ref + entry_stats.delta_oid) as u32;
         Ok(PackGenerationStats {
-            object_count: actual_count as u32,
+            object_count: num_objects as u32,
             pack_size: total_bytes_written,
-            delta_objects: entry_stats.delta_ref as u32,
+            delta_objects: reused.deltas as u32 + written_deltas,
             compression_ratio: 0.0,
+            verification,
+            written_deltas,
+            // Deltas are copied from packs as well, as they aren't computed
+            reused_objects: (copied - recompressed) as u32,
+            pack_reused: reused.len() as u32,
         })
     }
 
+    /// Send what `pack_writer` wrote so far, unless the pack is verified before any of it is sent
+    fn send_written<W: Write>(
+        &self,
+        writer: &mut EnhancedPacketWriter<W>,
+        pack_writer: &mut PackWriter<'_, Vec<u8>>,
+    ) -> Result<()> {
+        if !self.verify {
+            writer.send_data(pack_writer.out_mut())?;
+            pack_writer.out_mut().clear();
+        }
+        Ok(())
+    }
+
+    /// Turn the deltas among `entries` against objects `pack_writer` didn't write into full objects, and return
+    /// how many
+    ///
+    /// Bases are written before their deltas, so these aren't sent at all.
+    fn recompress_thin_deltas(
+        &self,
+        entries: &mut [output::Entry],
+        pack_writer: &PackWriter<'_, Vec<u8>>,
+        find_adapter: &RepositoryFindAdapter,
+    ) -> Result<usize> {
+        let mut recompressed = 0;
+        let mut buf = Vec::new();
+        for entry in entries {
+            match entry.kind {
+                output::entry::Kind::DeltaOid { id } if !pack_writer.contains(&id) => {
+                    let (object, _location) = gix_pack::Find::try_find(find_adapter, &entry.id, &mut buf)
+                        .map_err(|e| Error::Pack(format!("Object lookup failed: {}", e)))?
+                        .ok_or_else(|| Error::Pack(format!("Object {} vanished", entry.id)))?;
+                    *entry = output::Entry::from_data(&output::Count::from_data(entry.id, None), &object)
+                        .map_err(|e| Error::Pack(format!("Entry generation failed: {}", e)))?;
+                    recompressed += 1;
+                }
+                _ => {}
+            }
+        }
+        Ok(recompressed)
+    }
+
     // Async support removed - stream_pack_data is now the only implementation
 
-    /// Write an empty pack when no objects need to be sent
-    fn write_empty_pack<W: Write>(&self, writer: W, _session: &SessionContext) -> Result<PackStats> {
+    /// Write an empty pack when no objects need to be sent, like deepening a client whose boundary doesn't move
+    fn write_empty_pack<W: Write>(
+        &self,
+        writer: &mut EnhancedPacketWriter<W>,
+        _session: &SessionContext,
+    ) -> Result<PackStats> {
         // Write empty pack: header + no entries + checksum
         let empty_entries: Vec<output::Entry> = Vec::new();
         let entries_iter = std::iter::once(Ok(empty_entries));
 
+        let mut pack_buffer = Vec::new();
         let mut pack_writer = output::bytes::FromEntriesIter::new(
             entries_iter,
-            writer,
+            &mut pack_buffer,
             0,
             gix_pack::data::Version::V2,
             self.repository.object_hash(),
@@ -485,12 +985,18 @@ impl<'a> PackGenerator<'a> {
                 Error::Pack(format!("Empty pack generation failed: {}", e))
             })?;
         }
+        drop(pack_writer);
+        // Like any other pack, it's sent on the data channel and terminated with a flush packet
+        writer.send_data(&pack_buffer)?;
+        writer.write_flush()?;
 
         Ok(PackStats {
             object_count: 0,
-            pack_size: 32, // Empty pack size (header + checksum)
+            pack_size: EMPTY_PACK_SIZE,
             delta_objects: 0,
             compression_ratio: 1.0,
+            verification: None,
+            pack_reused: 0,
         })
     }
 
@@ -500,8 +1006,12 @@ impl<'a> PackGenerator<'a> {
         counts: Vec<output::Count>,
         session: &SessionContext,
     ) -> Result<Vec<output::Count>> {
+        let negotiation = &session.negotiation;
         // If no haves/common, no filtering needed
-        if session.negotiation.haves.is_empty() && session.negotiation.common.is_empty() {
+        if negotiation.haves.is_empty()
+            && negotiation.common.is_empty()
+            && negotiation.shallow_boundary.unshallow.is_empty()
+        {
             return Ok(counts);
         }
 
@@ -509,17 +1019,19 @@ impl<'a> PackGenerator<'a> {
         let mut existing_objects = std::collections::HashSet::new();
 
         // Add haves and common directly
-        for have in &session.negotiation.haves {
+        for have in &negotiation.haves {
             existing_objects.insert(*have);
         }
-        for common in &session.negotiation.common {
+        for common in &negotiation.common {
             existing_objects.insert(*common);
         }
 
-        // For each have/common that's a commit, add all reachable objects
+        // For each have/common that's a commit, add all reachable objects. Like in git, the commits the client
+        // unshallows are edges it has the trees of.
         let mut to_traverse = Vec::new();
-        to_traverse.extend(session.negotiation.haves.iter());
-        to_traverse.extend(session.negotiation.common.iter());
+        to_traverse.extend(negotiation.haves.iter());
+        to_traverse.extend(negotiation.common.iter());
+        to_traverse.extend(negotiation.shallow_boundary.unshallow.iter());
 
         for &obj_id in &to_traverse {
             // If it's a commit, traverse its history
@@ -539,7 +1051,8 @@ impl<'a> PackGenerator<'a> {
             }
         }
 
-        eprintln!(
+        trace!(
+            self.logger,
             "Filter objects: Excluding {} existing objects from {} total",
             existing_objects.len(),
             counts.len()
@@ -551,7 +1064,11 @@ impl<'a> PackGenerator<'a> {
             .filter(|count| !existing_objects.contains(&count.id))
             .collect();
 
-        eprintln!("Filter objects: Kept {} objects after filtering", filtered.len());
+        trace!(
+            self.logger,
+            "Filter objects: Kept {} objects after filtering",
+            filtered.len()
+        );
 
         Ok(filtered)
     }
@@ -607,14 +1124,16 @@ impl<'a> PackGenerator<'a> {
         stats: &PackGenerationStats,
         _session: &SessionContext,
     ) -> Result<()> {
-        // For now, we don't track delta stats like Git does, so we'll use approximations
-        // Git sends: "Total X (delta Y), reused Z (delta W), pack-reused 0 (from 0)"
-        let total = stats.object_count;
-        let delta_count = stats.delta_objects; // This might be 0 for now
-
+        // Like git, deltas are only counted among the objects that aren't reused verbatim, all of which are copied
+        // from a pack unless they are loose or can't be sent as deltas
         let status_message = format!(
-            "Total {} (delta {}), reused {} (delta {}), pack-reused 0 (from 0)",
-            total, delta_count, total, delta_count
+            "Total {} (delta {}), reused {} (delta {}), pack-reused {} (from {})",
+            stats.object_count,
+            stats.written_deltas,
+            stats.reused_objects,
+            stats.written_deltas,
+            stats.pack_reused,
+            u32::from(stats.pack_reused > 0)
         );
 
         writer.send_progress(&status_message)?;
@@ -632,4 +1151,20 @@ struct PackGenerationStats {
     pack_size: u64,
     delta_objects: u32,
     compression_ratio: f64,
+    verification: Option<Verification>,
+    /// Deltas among the objects that weren't reused verbatim
+    written_deltas: u32,
+    /// Objects copied from a pack entry by entry
+    reused_objects: u32,
+    /// Objects copied verbatim from the beginning of a pack
+    pack_reused: u32,
+}
+
+/// Return `true` if the filter of `session` leaves out all trees, so only commits and tags have to be counted
+fn omits_trees(session: &SessionContext) -> bool {
+    session
+        .capabilities
+        .filter()
+        .and_then(|spec| FilterSpec::parse(&spec.to_str_lossy()).ok())
+        .is_some_and(|spec| spec.omits_trees())
 }
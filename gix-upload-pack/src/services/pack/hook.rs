@@ -0,0 +1,238 @@
+//! Running `uploadpack.packObjectsHook` instead of generating packs internally
+//!
+//! Hosts use the hook to cache packs of popular fetches or to account for the work of generating them. Like git,
+//! the hook is run with the command line of `git pack-objects` as arguments, through the shell if it looks like a
+//! script, and gets the wants, `--not` and the common objects on stdin. Its stdout is the pack, which is sent on the
+//! data channel, while its stderr is relayed on the progress channel as it arrives.
+//!
+//! As the pack is produced by another program, the [`ObjectFirewall`](crate::services::ObjectFirewall) can't be
+//! applied to it, and packs aren't [verified](crate::services::pack::verify_pack()) before sending them.
+//! [`PackGenerator`](crate::services::PackGenerator) generates packs internally while a firewall is configured.
+
+use crate::{
+    error::{Error, Result},
+    log::{debug, Logger},
+    services::negotiation::shallow,
+    services::pack::PackStats,
+    services::packet_io::EnhancedPacketWriter,
+    types::SessionContext,
+};
+use gix_hash::ObjectId;
+use std::collections::HashSet;
+use std::ffi::OsString;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::mpsc;
+use std::time::Duration;
+
+/// The most bytes read from the hook at once
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// The program configured as `uploadpack.packObjectsHook`, and how to run it
+#[derive(Debug, Clone)]
+pub struct PackObjectsHook {
+    /// The hook, a program or a shell script
+    pub command: PathBuf,
+    /// Send keep-alive packets while the hook produces no output for this long
+    pub keepalive: Option<Duration>,
+}
+
+/// Output of the hook
+enum Output {
+    Pack(Vec<u8>),
+    Progress(Vec<u8>),
+}
+
+impl PackObjectsHook {
+    /// Run `command` as hook, sending keep-alive packets after `keepalive` without output
+    pub fn new(command: impl Into<PathBuf>, keepalive: Option<Duration>) -> Self {
+        Self {
+            command: command.into(),
+            keepalive,
+        }
+    }
+
+    /// The arguments the hook is called with for `session`, the command line of `git pack-objects`
+    pub fn args(session: &SessionContext) -> Vec<OsString> {
+        let shallow = !shallow::boundary_commits(&session.negotiation).is_empty();
+        let capabilities = &session.capabilities;
+        let mut args: Vec<OsString> = vec!["git".into()];
+        if shallow {
+            // The repository's own shallow commits are no boundary for the client
+            args.extend(["--shallow-file".into(), "".into()]);
+        }
+        args.extend(["pack-objects".into(), "--revs".into()]);
+        if capabilities.thin_pack() {
+            args.push("--thin".into());
+        }
+        args.push("--stdout".into());
+        if shallow {
+            args.push("--shallow".into());
+        }
+        if capabilities.wants_progress() {
+            args.push("--progress".into());
+        }
+        if capabilities.ofs_delta() {
+            args.push("--delta-base-offset".into());
+        }
+        if capabilities.include_tag() {
+            args.push("--include-tag".into());
+        }
+        if let Some(filter) = capabilities.filter() {
+            args.push(format!("--filter={filter}").into());
+        }
+        args
+    }
+
+    /// The input the hook gets for `session`, the revisions to pack
+    pub fn stdin(session: &SessionContext) -> Vec<u8> {
+        fn sorted(ids: &HashSet<ObjectId>) -> Vec<&ObjectId> {
+            let mut ids: Vec<_> = ids.iter().collect();
+            ids.sort();
+            ids
+        }
+        let negotiation = &session.negotiation;
+        let boundary = &negotiation.shallow_boundary;
+        let mut input = Vec::new();
+        for id in shallow::boundary_commits(negotiation) {
+            input.extend_from_slice(format!("--shallow {id}\n").as_bytes());
+        }
+        // Like git, unshallowed commits are edges the client has, and their parents are wanted
+        for id in sorted(&negotiation.wants).into_iter().chain(&boundary.parents) {
+            input.extend_from_slice(format!("{id}\n").as_bytes());
+        }
+        input.extend_from_slice(b"--not\n");
+        for id in sorted(&negotiation.common).into_iter().chain(&boundary.unshallow) {
+            input.extend_from_slice(format!("{id}\n").as_bytes());
+        }
+        input.push(b'\n');
+        input
+    }
+
+    /// Run the hook in `git_dir` to generate the pack for `session`, and send it through `writer`
+    ///
+    /// The pack is terminated with a flush packet, and the hook failing to start or exiting with an error fails the
+    /// request. Progress of the hook is relayed as is, including its final `Total` line.
+    pub fn generate_pack<W: Write>(
+        &self,
+        git_dir: &Path,
+        writer: &mut EnhancedPacketWriter<W>,
+        session: &SessionContext,
+        logger: &Logger,
+    ) -> Result<PackStats> {
+        let args = Self::args(session);
+        debug!(logger, "Running pack-objects hook {:?} with {:?}", self.command, args);
+        let mut child: Command = gix_command::prepare(self.command.as_os_str())
+            .command_may_be_shell_script()
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .into();
+        let mut child = child.current_dir(git_dir).spawn().map_err(|err| self.error(err))?;
+
+        // The hook reads all revisions before it writes anything, but write from a thread not to rely on it
+        let input = Self::stdin(session);
+        let mut stdin = child.stdin.take().expect("piped");
+        let stdin = std::thread::spawn(move || stdin.write_all(&input));
+        let (tx, rx) = mpsc::channel();
+        let readers = [
+            relay(child.stdout.take().expect("piped"), tx.clone(), Output::Pack),
+            relay(child.stderr.take().expect("piped"), tx, Output::Progress),
+        ];
+
+        let mut pack_size = 0u64;
+        let mut header = Vec::with_capacity(12);
+        let result = loop {
+            let output = match self.keepalive {
+                Some(keepalive) if pack_size == 0 => match rx.recv_timeout(keepalive) {
+                    Ok(output) => output,
+                    Err(mpsc::RecvTimeoutError::Timeout) => match writer.send_keepalive() {
+                        Ok(_) => continue,
+                        Err(err) => break Err(err),
+                    },
+                    Err(mpsc::RecvTimeoutError::Disconnected) => break Ok(()),
+                },
+                _ => match rx.recv() {
+                    Ok(output) => output,
+                    Err(_) => break Ok(()),
+                },
+            };
+            let sent = match output {
+                Output::Pack(data) => {
+                    let missing = 12usize.saturating_sub(header.len());
+                    header.extend_from_slice(&data[..missing.min(data.len())]);
+                    pack_size += data.len() as u64;
+                    writer.send_data(&data)
+                }
+                Output::Progress(data) => writer.relay_progress(&data),
+            };
+            if let Err(err) = sent {
+                break Err(err);
+            }
+        };
+        if result.is_err() {
+            // The client is gone, don't wait for the whole pack to be generated
+            child.kill().ok();
+        }
+        drop(rx);
+        let read = readers
+            .into_iter()
+            .try_for_each(|reader| reader.join().expect("no panic"));
+        let written = stdin.join().expect("no panic");
+        let status = child.wait().map_err(|err| self.error(err))?;
+        result?;
+        read.map_err(|err| self.error(err))?;
+        if !status.success() {
+            return Err(Error::Pack(format!(
+                "pack-objects hook {:?} failed with {status}",
+                self.command
+            )));
+        }
+        match written {
+            // Hooks serving cached packs may not need the revisions
+            Err(err) if err.kind() != std::io::ErrorKind::BrokenPipe => return Err(self.error(err)),
+            _ => {}
+        }
+        writer.write_flush()?;
+
+        Ok(PackStats {
+            object_count: header
+                .get(8..12)
+                .map_or(0, |count| u32::from_be_bytes(count.try_into().expect("4 bytes"))),
+            pack_size,
+            delta_objects: 0,
+            compression_ratio: 0.0,
+            verification: None,
+            pack_reused: 0,
+        })
+    }
+
+    fn error(&self, err: std::io::Error) -> Error {
+        Error::Pack(format!("pack-objects hook {:?} failed: {err}", self.command))
+    }
+}
+
+/// Read `stream` until it ends, sending each chunk as `kind` to `tx`
+fn relay(
+    mut stream: impl Read + Send + 'static,
+    tx: mpsc::Sender<Output>,
+    kind: fn(Vec<u8>) -> Output,
+) -> std::thread::JoinHandle<std::io::Result<()>> {
+    std::thread::spawn(move || {
+        let mut buf = vec![0; CHUNK_SIZE];
+        loop {
+            let read = match stream.read(&mut buf) {
+                Ok(0) => return Ok(()),
+                Ok(read) => read,
+                Err(err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            };
+            if tx.send(kind(buf[..read].to_vec())).is_err() {
+                // Nobody is listening anymore, the pack isn't needed
+                return Ok(());
+            }
+        }
+    })
+}
@@ -0,0 +1,52 @@
+//! Keeping the connection alive while the pack is prepared
+//!
+//! Like git with `uploadpack.keepAlive`, an empty packet is sent on the data channel whenever nothing was sent for
+//! the configured interval, so proxies and clients don't give up on clones that count or compress for a long time.
+//! As the writer of the session can't be shared, the slow work runs on another thread meanwhile, see [`during()`].
+//!
+//! Keep-alive packets need sideband, so they are withheld before it's established or if it wasn't negotiated.
+
+use crate::error::Result;
+use crate::services::packet_io::EnhancedPacketWriter;
+use std::io::Write;
+use std::sync::mpsc;
+use std::time::Duration;
+
+/// Run `work` on another thread and send a keep-alive packet through `writer` each `interval` until it's done, and
+/// return its result
+///
+/// Without an interval, `work` runs on the current thread, and otherwise within its current `tracing` span. If sending
+/// a keep-alive fails, the error is returned once `work` finished.
+pub fn during<W: Write, T: Send>(
+    writer: &mut EnhancedPacketWriter<W>,
+    interval: Option<Duration>,
+    work: impl FnOnce() -> T + Send,
+) -> Result<T> {
+    let Some(interval) = interval else {
+        return Ok(work());
+    };
+    #[cfg(feature = "tracing")]
+    let work = {
+        let span = tracing::Span::current();
+        move || span.in_scope(work)
+    };
+    std::thread::scope(|scope| {
+        let (tx, rx) = mpsc::channel();
+        let worker = scope.spawn(move || tx.send(work()).ok());
+        let mut sent = Ok(());
+        loop {
+            match rx.recv_timeout(interval) {
+                Ok(output) => return sent.map(|()| output),
+                Err(mpsc::RecvTimeoutError::Timeout) => {
+                    if sent.is_ok() {
+                        sent = writer.send_keepalive().map(|_| ());
+                    }
+                }
+                Err(mpsc::RecvTimeoutError::Disconnected) => match worker.join() {
+                    Err(panic) => std::panic::resume_unwind(panic),
+                    Ok(_) => unreachable!("the output is sent before the thread ends"),
+                },
+            }
+        }
+    })
+}
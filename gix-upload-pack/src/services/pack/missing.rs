@@ -0,0 +1,99 @@
+//! Checking that wanted objects exist before counting
+//!
+//! With `uploadpack.allowAnySHA1InWant`, wants aren't limited to ref tips, so clients can ask for any object id,
+//! including ones the repository doesn't have or whose tree was never received. Counting such wants would fail
+//! halfway into the response, so [`find_missing()`] checks each want up front, along with what it leads to: the
+//! target of annotated tags, recursively, and the tree of commits. What happens with the affected wants is decided
+//! by [`MissingObjectPolicy`](crate::config::MissingObjectPolicy).
+//!
+//! Parents aren't checked as the commits at a shallow boundary legitimately lack them, so objects missing deeper
+//! in the history still fail during counting.
+//!
+//! The objects are checked level by level, first all wants, then all the objects they lead to, each level with a
+//! single [`find_existing()`] call.
+
+use super::existence::find_existing;
+use crate::error::{Error, Result};
+use gix::Repository;
+use gix_hash::ObjectId;
+
+/// How a missing object relates to the want that needs it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Relation {
+    /// The wanted object itself is missing
+    Want,
+    /// The object an annotated tag points to is missing
+    TagTarget,
+    /// The tree of a commit is missing
+    Tree,
+}
+
+/// An object that a want needs to be packed, but which isn't in the repository
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MissingObject {
+    /// The want that needs the object
+    pub want: ObjectId,
+    /// The missing object
+    pub id: ObjectId,
+    /// How the missing object relates to `want`
+    pub relation: Relation,
+}
+
+impl std::fmt::Display for MissingObject {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.relation {
+            Relation::Want => write!(f, "{}", self.id),
+            Relation::TagTarget => write!(f, "{} (tag target needed by want {})", self.id, self.want),
+            Relation::Tree => write!(f, "{} (tree needed by want {})", self.id, self.want),
+        }
+    }
+}
+
+/// Return the missing objects needed by `wants`, at most one per want, sorted by want
+pub fn find_missing<'a>(
+    repository: &Repository,
+    wants: impl IntoIterator<Item = &'a ObjectId>,
+) -> Result<Vec<MissingObject>> {
+    let lookup_failed =
+        |id: ObjectId, err: &dyn std::fmt::Display| Error::custom(format!("Failed to look up {id}: {err}"));
+    let mut missing = Vec::new();
+    // The object to check next for each want that wasn't found to miss anything yet
+    let mut level: Vec<(ObjectId, ObjectId, Relation)> =
+        wants.into_iter().map(|want| (*want, *want, Relation::Want)).collect();
+    while !level.is_empty() {
+        let existing = find_existing(repository, level.iter().map(|(_, id, _)| id))?;
+        let mut next_level = Vec::new();
+        for (want, id, relation) in level {
+            if !existing.contains(&id) {
+                missing.push(MissingObject { want, id, relation });
+                continue;
+            }
+            let header = repository.find_header(id).map_err(|err| lookup_failed(id, &err))?;
+            let (next, next_relation) = match header.kind() {
+                gix_object::Kind::Tag => (
+                    repository
+                        .find_tag(id)
+                        .map_err(|err| lookup_failed(id, &err))?
+                        .target_id()
+                        .map_err(|err| lookup_failed(id, &err))?
+                        .detach(),
+                    Relation::TagTarget,
+                ),
+                gix_object::Kind::Commit => (
+                    repository
+                        .find_commit(id)
+                        .map_err(|err| lookup_failed(id, &err))?
+                        .tree_id()
+                        .map_err(|err| lookup_failed(id, &err))?
+                        .detach(),
+                    Relation::Tree,
+                ),
+                gix_object::Kind::Tree | gix_object::Kind::Blob => continue,
+            };
+            next_level.push((want, next, next_relation));
+        }
+        level = next_level;
+    }
+    missing.sort_by_key(|object| object.want);
+    Ok(missing)
+}
@@ -3,9 +3,28 @@
 //! This module contains all functionality related to pack file generation,
 //! streaming, and progress reporting during upload-pack operations.
 
+pub mod bitmap;
+pub mod existence;
+pub mod filter;
+pub mod firewall;
 pub mod generation;
+pub mod hook;
+pub mod keepalive;
+pub mod missing;
 pub mod progress;
+pub mod reuse;
+pub mod uris;
+pub mod verify;
 
 // Re-export commonly used types
-pub use generation::{PackGenerator, PackStats};
+pub use bitmap::BitmapIndex;
+pub use existence::find_existing;
+pub use filter::ObjectFilter;
+pub use firewall::{ExcludedObject, ObjectExclusion, ObjectFirewall};
+pub use generation::{Estimate, PackGenerator, PackStats};
+pub use hook::PackObjectsHook;
+pub use missing::{find_missing, MissingObject};
 pub use progress::ProgressReporter;
+pub use reuse::{PackWriter, ReusedObjects};
+pub use uris::PackfileUri;
+pub use verify::{verify_pack, Verification};
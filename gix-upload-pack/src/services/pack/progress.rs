@@ -98,6 +98,11 @@ impl<'a, W: std::io::Write> ProgressReporter<'a, W> {
         self.formatter.send_progress(&message)
     }
 
+    /// The writer progress is sent to, for sending other data in between
+    pub fn writer(&mut self) -> &mut EnhancedPacketWriter<W> {
+        self.formatter
+    }
+
     /// Get the total if known
     pub fn total(&self) -> Option<usize> {
         self.total
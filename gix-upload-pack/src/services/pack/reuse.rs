@@ -0,0 +1,221 @@
+//! Reusing the leading objects of a pack verbatim
+//!
+//! Like git's pack reuse, a pack that contains the first objects of a pack of the repository begins with their
+//! entries as they are stored, copied byte for byte instead of being parsed and encoded again. As they keep their offsets,
+//! deltas among them stay valid, and deltas written after them refer to their bases by offset, see [`PackWriter`].
+//! Counting with the [bitmap](super::BitmapIndex) puts the objects in the order of its pack, so clones reuse most of it.
+//!
+//! Git only reuses the pack its bitmap is for, while here it's the pack with the longest run of leading objects to
+//! send. Entries stored as deltas against an object id end the run, as their base may not be sent. Like in git,
+//! `pack.allowPackReuse` turns reuse off.
+
+use crate::error::{Error, Result};
+use gix_hash::ObjectId;
+use gix_pack::data::{self, output};
+use std::collections::HashMap;
+use std::io::Write;
+
+/// The offset of the first entry of a pack, after its header
+const FIRST_ENTRY_OFFSET: data::Offset = 12;
+
+/// Objects copied verbatim from the beginning of a pack
+#[derive(Default)]
+pub struct ReusedObjects {
+    /// The objects in the order of the pack
+    pub ids: Vec<ObjectId>,
+    /// The number of deltas among them
+    pub deltas: usize,
+    /// Where each object is stored
+    locations: Vec<data::entry::Location>,
+}
+
+impl ReusedObjects {
+    /// Take the objects at the beginning of a pack out of `counts`, checking their entries with `find`
+    ///
+    /// Nothing is taken if no pack begins with objects in `counts`. The entries are only read again when they are
+    /// written, see [`PackWriter::write_reused()`].
+    pub fn take(counts: &mut Vec<output::Count>, find: &impl gix_pack::Find) -> Result<Self> {
+        let mut by_pack: HashMap<u32, Vec<usize>> = HashMap::new();
+        for (index, count) in counts.iter().enumerate() {
+            if let Some(location) = count.entry_pack_location.as_ref() {
+                by_pack.entry(location.pack_id).or_default().push(index);
+            }
+        }
+        let location = |index: usize| counts[index].entry_pack_location.as_ref().expect("packed");
+        let Some(run) = by_pack
+            .into_values()
+            .map(|mut indices| {
+                indices.sort_by_key(|index| location(*index).pack_offset);
+                let mut next_offset = FIRST_ENTRY_OFFSET;
+                indices
+                    .into_iter()
+                    .take_while(|index| {
+                        let location = location(*index);
+                        let is_next = location.pack_offset == next_offset;
+                        next_offset += location.entry_size as data::Offset;
+                        is_next
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .max_by_key(Vec::len)
+        else {
+            return Ok(Self::default());
+        };
+
+        let mut reused = Self::default();
+        let mut taken = vec![false; counts.len()];
+        for index in run {
+            let count = &counts[index];
+            let location = location(index);
+            let Some(entry) = find.entry_by_location(location) else {
+                break;
+            };
+            if entry.version != data::Version::V2 {
+                break;
+            }
+            let header = data::Entry::from_bytes(&entry.data, location.pack_offset, count.id.as_slice().len())
+                .map_err(|err| Error::Pack(format!("Failed to read the entry of {}: {err}", count.id)))?
+                .header;
+            if matches!(header, data::entry::Header::RefDelta { .. }) {
+                break;
+            }
+            reused.deltas += usize::from(header.is_delta());
+            reused.locations.push(location.clone());
+            reused.ids.push(count.id);
+            taken[index] = true;
+        }
+        let mut taken = taken.into_iter();
+        counts.retain(|_| !taken.next().expect("one per count"));
+        Ok(reused)
+    }
+
+    /// The number of reused objects
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    /// Return `true` if no objects are reused
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+}
+
+/// Writes a pack beginning with [reused objects](ReusedObjects), followed by the entries of the other objects
+///
+/// Entries stored as delta against an object written before refer to it by offset instead of by id, be it a reused
+/// object or an entry of a previous batch. The pack can be written a few entries at a time, taking what was written
+/// from [`out_mut()`](Self::out_mut()) in between.
+pub struct PackWriter<'a, W> {
+    out: gix_hash::io::Write<W>,
+    reused: &'a ReusedObjects,
+    /// The number of reused objects written so far
+    reused_written: usize,
+    /// The offset of each entry of the current batch, or `None` if it was invalid and left out
+    batch: Vec<Option<data::Offset>>,
+    /// The offset of each written object
+    offsets: HashMap<ObjectId, data::Offset>,
+    written: u64,
+}
+
+impl<'a, W: Write> PackWriter<'a, W> {
+    /// Write the header of a pack of `num_objects` objects hashed with `object_hash` to `out`, which begins with
+    /// `reused`
+    pub fn new(out: W, num_objects: u32, reused: &'a ReusedObjects, object_hash: gix_hash::Kind) -> Result<Self> {
+        let mut out = gix_hash::io::Write::new(out, object_hash);
+        out.write_all(&data::header::encode(data::Version::V2, num_objects))
+            .map_err(write_failed)?;
+        Ok(Self {
+            out,
+            reused,
+            reused_written: 0,
+            batch: Vec::new(),
+            offsets: HashMap::new(),
+            written: FIRST_ENTRY_OFFSET,
+        })
+    }
+
+    /// Copy up to `max` of the reused objects not written yet, read with `find`, and return how many were written
+    pub fn write_reused(&mut self, find: &impl gix_pack::Find, max: usize) -> Result<usize> {
+        let end = (self.reused_written + max).min(self.reused.len());
+        for (id, location) in self.reused.ids[self.reused_written..end]
+            .iter()
+            .zip(&self.reused.locations[self.reused_written..end])
+        {
+            debug_assert_eq!(location.pack_offset, self.written, "reused objects keep their offsets");
+            let entry = find
+                .entry_by_location(location)
+                .ok_or_else(|| Error::Pack(format!("The entry of {id} vanished")))?;
+            self.out.write_all(&entry.data).map_err(write_failed)?;
+            self.offsets.insert(*id, self.written);
+            self.written += entry.data.len() as u64;
+        }
+        let written = end - self.reused_written;
+        self.reused_written = end;
+        Ok(written)
+    }
+
+    /// Start a batch of entries generated separately from those written before, after all reused objects
+    ///
+    /// Deltas refer to the entries of their batch by index, see [`output::entry::Kind::DeltaRef`].
+    pub fn next_batch(&mut self) {
+        debug_assert_eq!(self.reused_written, self.reused.len(), "reused objects come first");
+        self.batch.clear();
+    }
+
+    /// Write `entries`, which continue those of the current batch
+    pub fn write_entries(&mut self, entries: impl IntoIterator<Item = output::Entry>) -> Result<()> {
+        for entry in entries {
+            if entry.is_invalid() {
+                self.batch.push(None);
+                continue;
+            }
+            let written = self.written;
+            let header = match entry.kind {
+                output::entry::Kind::DeltaOid { id } if self.offsets.contains_key(&id) => {
+                    data::entry::Header::OfsDelta {
+                        base_distance: written - self.offsets[&id],
+                    }
+                }
+                _ => entry.to_entry_header(data::Version::V2, |index| {
+                    written - self.batch[index].expect("deltas refer to written objects")
+                }),
+            };
+            self.batch.push(Some(written));
+            self.offsets.insert(entry.id, written);
+            self.written += header
+                .write_to(entry.decompressed_size as u64, &mut self.out)
+                .map_err(write_failed)? as u64;
+            self.out.write_all(&entry.compressed_data).map_err(write_failed)?;
+            self.written += entry.compressed_data.len() as u64;
+        }
+        Ok(())
+    }
+
+    /// Write the trailing checksum, and return it along with the size of the pack
+    pub fn finish(&mut self) -> Result<(ObjectId, u64)> {
+        let digest = self
+            .out
+            .hash
+            .clone()
+            .try_finalize()
+            .map_err(|err| Error::Pack(format!("Pack checksum failed: {err}")))?;
+        self.out.inner.write_all(digest.as_slice()).map_err(write_failed)?;
+        self.out.inner.flush().map_err(write_failed)?;
+        self.written += digest.as_slice().len() as u64;
+        Ok((digest, self.written))
+    }
+
+    /// Return `true` if the object `id` was written
+    pub fn contains(&self, id: &ObjectId) -> bool {
+        self.offsets.contains_key(id)
+    }
+
+    /// Where the pack is written to
+    pub fn out_mut(&mut self) -> &mut W {
+        &mut self.out.inner
+    }
+}
+
+fn write_failed(err: std::io::Error) -> Error {
+    Error::Pack(format!("Pack streaming failed: {err}"))
+}
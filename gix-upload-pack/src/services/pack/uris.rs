@@ -0,0 +1,160 @@
+//! Offloading blobs to pre-built packs, the `packfile-uris` feature of protocol v2 `fetch`
+//!
+//! Hosts put large blobs into packs served from a CDN and configure each of them with `uploadpack.blobPackfileUri`,
+//! as `<object-hash> <pack-hash> <uri>` like git does. Clients fetching with `packfile-uris <protocols>` get these
+//! blobs left out of the generated pack if the protocol of their URI is among the ones they accept. The
+//! `packfile-uris` section of the response then lists `<pack-hash> <uri>` of each pack the client downloads itself.
+//!
+//! Only blobs are offloaded, as the packs are meant for large files and a missing commit or tree would leave the
+//! client unable to check out what it fetched. The generated pack isn't thin whenever blobs were offloaded, so no
+//! delta refers to a base the client only gets from another pack, and it's always generated internally, without
+//! the [`PackObjectsHook`](super::PackObjectsHook).
+
+use crate::error::{Error, Result};
+use bstr::{BStr, BString, ByteSlice};
+use gix::Repository;
+use gix_hash::ObjectId;
+use gix_pack::data::output;
+use std::collections::HashMap;
+
+/// A blob that clients may download as part of a pre-built pack instead of receiving it in the generated pack
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PackfileUri {
+    /// The blob contained in the pack
+    pub object: ObjectId,
+    /// The checksum of the pack, which the client verifies after downloading it
+    pub pack_hash: ObjectId,
+    /// Where the client downloads the pack from
+    pub uri: BString,
+}
+
+impl PackfileUri {
+    /// Offload `object` to the pack with checksum `pack_hash` at `uri`
+    pub fn new(object: ObjectId, pack_hash: ObjectId, uri: impl Into<BString>) -> Self {
+        Self {
+            object,
+            pack_hash,
+            uri: uri.into(),
+        }
+    }
+
+    /// Parse a value of `uploadpack.blobPackfileUri`, `<object-hash> <pack-hash> <uri>`
+    pub fn from_config_value(value: &BStr) -> Result<Self> {
+        let invalid = |reason: &str| Error::Config {
+            message: format!(
+                "invalid value for {}: '{value}' {reason}",
+                crate::config::keys::upload_pack::BLOB_PACKFILE_URI.name()
+            ),
+        };
+        let mut fields = value.trim().splitn_str(3, " ");
+        let (Some(object), Some(pack_hash), Some(uri)) = (fields.next(), fields.next(), fields.next()) else {
+            return Err(invalid("isn't '<object-hash> <pack-hash> <uri>'"));
+        };
+        let object = ObjectId::from_hex(object).map_err(|_| invalid("has an invalid object hash"))?;
+        let pack_hash = ObjectId::from_hex(pack_hash).map_err(|_| invalid("has an invalid pack hash"))?;
+        let uri = uri.trim();
+        if !uri.contains_str("://") {
+            return Err(invalid("has no protocol in its URI"));
+        }
+        Ok(Self::new(object, pack_hash, uri))
+    }
+
+    /// The protocol of the URI, like `https`
+    pub fn protocol(&self) -> &BStr {
+        self.uri
+            .find("://")
+            .map_or(self.uri.as_bstr(), |end| self.uri[..end].as_bstr())
+    }
+
+    /// The line announcing the pack in the `packfile-uris` section, `<pack-hash> <uri>`
+    pub fn to_line(&self) -> BString {
+        format!("{} {}\n", self.pack_hash, self.uri).into()
+    }
+}
+
+/// Remove the blobs among `counts` which `uris` offloads with one of `protocols`, and return the packs to download
+///
+/// Each pack is returned once, in the order of `uris`, however many of its blobs were removed.
+pub(crate) fn offload(
+    repository: &Repository,
+    uris: &[PackfileUri],
+    protocols: &[BString],
+    counts: &mut Vec<output::Count>,
+) -> Result<Vec<PackfileUri>> {
+    let offloadable: HashMap<_, _> = uris
+        .iter()
+        .enumerate()
+        .filter(|(_, uri)| protocols.iter().any(|protocol| protocol == uri.protocol()))
+        .map(|(index, uri)| (uri.object, index))
+        .collect();
+    if offloadable.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let mut used = vec![false; uris.len()];
+    let mut result = Ok(());
+    counts.retain(|count| {
+        let Some(&index) = offloadable.get(&count.id) else {
+            return true;
+        };
+        match repository.find_header(count.id) {
+            Ok(header) if header.kind() == gix_object::Kind::Blob => {
+                used[index] = true;
+                false
+            }
+            Ok(_) => true,
+            Err(err) => {
+                result = Err(Error::Pack(format!("Object header lookup failed: {err}")));
+                true
+            }
+        }
+    });
+    result?;
+
+    let mut packs: Vec<PackfileUri> = Vec::new();
+    for uri in uris.iter().zip(used).filter_map(|(uri, used)| used.then_some(uri)) {
+        if !packs
+            .iter()
+            .any(|pack| pack.pack_hash == uri.pack_hash && pack.uri == uri.uri)
+        {
+            packs.push(uri.clone());
+        }
+    }
+    Ok(packs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn config_values() {
+        let object = "1111111111111111111111111111111111111111";
+        let pack = "2222222222222222222222222222222222222222";
+        let uri = PackfileUri::from_config_value(
+            format!("{object} {pack} https://cdn.example.com/a b.pack")
+                .as_bytes()
+                .as_bstr(),
+        )
+        .unwrap();
+        assert_eq!(
+            uri,
+            PackfileUri::new(
+                ObjectId::from_hex(object.as_bytes()).unwrap(),
+                ObjectId::from_hex(pack.as_bytes()).unwrap(),
+                "https://cdn.example.com/a b.pack"
+            )
+        );
+        assert_eq!(uri.protocol(), "https");
+        assert_eq!(uri.to_line(), format!("{pack} https://cdn.example.com/a b.pack\n"));
+
+        for (value, reason) in [
+            (format!("{object} {pack}"), "isn't '<object-hash> <pack-hash> <uri>'"),
+            (format!("{object} pack https://cdn"), "has an invalid pack hash"),
+            (format!("{object} {pack} /srv/a.pack"), "has no protocol in its URI"),
+        ] {
+            let err = PackfileUri::from_config_value(value.as_bytes().as_bstr()).unwrap_err();
+            assert!(err.to_string().ends_with(reason), "{err}");
+        }
+    }
+}
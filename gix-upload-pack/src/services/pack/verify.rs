@@ -0,0 +1,120 @@
+//! Self-check of generated packs before they are sent
+//!
+//! Entries are copied from existing packs where possible, so latent corruption in the object database would
+//! otherwise be passed on to clients, which only notice it after receiving the whole pack. Verification parses
+//! the generated pack, checks its trailer, inflates every entry, and re-hashes all non-delta objects to compare
+//! them with the ids they were generated for.
+
+use crate::error::{Error, Result};
+use gix_hash::ObjectId;
+use gix_pack::data::input;
+use std::time::{Duration, Instant};
+
+/// The outcome of a successful pack verification
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Verification {
+    /// The number of entries parsed and inflated
+    pub entries: u32,
+    /// The number of non-delta entries whose object id was recomputed
+    pub hashed_objects: u32,
+    /// The time it took to verify the pack
+    pub duration: Duration,
+}
+
+/// Verify that `pack` is a well-formed pack of objects hashed with `object_hash`, whose entries are the objects
+/// `ids` in order
+pub fn verify_pack(pack: &[u8], object_hash: gix_hash::Kind, ids: &[ObjectId]) -> Result<Verification> {
+    let start = Instant::now();
+    let failed = |message: String| Error::PackVerification(message);
+    let entries =
+        input::BytesToEntriesIter::new_from_header(pack, input::Mode::Verify, input::EntryDataMode::Keep, object_hash)
+            .map_err(|err| failed(err.to_string()))?;
+    if entries.len() != ids.len() {
+        return Err(failed(format!(
+            "pack header announces {} entries, but {} were written",
+            entries.len(),
+            ids.len()
+        )));
+    }
+
+    let mut inflate = gix_features::zlib::Inflate::default();
+    let mut data = Vec::new();
+    let mut verification = Verification::default();
+    for (entry, expected) in entries.zip(ids) {
+        let entry = entry.map_err(|err| failed(format!("entry for object {expected}: {err}")))?;
+        verification.entries += 1;
+        let Some(kind) = entry.header.as_kind() else {
+            continue;
+        };
+        let compressed = entry.compressed.as_deref().unwrap_or_default();
+        data.resize(entry.decompressed_size as usize, 0);
+        inflate.reset();
+        inflate
+            .once(compressed, &mut data)
+            .map_err(|err| failed(format!("entry for object {expected}: {err}")))?;
+        let actual = gix_object::compute_hash(object_hash, kind, &data)
+            .map_err(|err| failed(format!("entry for object {expected}: {err}")))?;
+        if actual != *expected {
+            return Err(failed(format!("entry for object {expected} hashes to {actual}")));
+        }
+        verification.hashed_objects += 1;
+    }
+    verification.duration = start.elapsed();
+    Ok(verification)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gix_pack::data::output;
+
+    fn pack_of_blobs(blobs: &[&[u8]]) -> (Vec<u8>, Vec<ObjectId>) {
+        let entries: Vec<_> = blobs
+            .iter()
+            .map(|data| {
+                let id = gix_object::compute_hash(gix_hash::Kind::Sha1, gix_object::Kind::Blob, data).unwrap();
+                let count = output::Count::from_data(id, None);
+                output::Entry::from_data(&count, &gix_object::Data::new(gix_object::Kind::Blob, data)).unwrap()
+            })
+            .collect();
+        let ids = entries.iter().map(|entry| entry.id).collect();
+        let mut pack = Vec::new();
+        let num_entries = entries.len() as u32;
+        for result in output::bytes::FromEntriesIter::new(
+            std::iter::once(Ok::<_, std::convert::Infallible>(entries)),
+            &mut pack,
+            num_entries,
+            gix_pack::data::Version::V2,
+            gix_hash::Kind::Sha1,
+        ) {
+            result.unwrap();
+        }
+        (pack, ids)
+    }
+
+    #[test]
+    fn intact_pack() {
+        let (pack, ids) = pack_of_blobs(&[b"first\n", b"second\n"]);
+        let verification = verify_pack(&pack, gix_hash::Kind::Sha1, &ids).unwrap();
+        assert_eq!((verification.entries, verification.hashed_objects), (2, 2));
+    }
+
+    #[test]
+    fn corrupt_packs_are_rejected() {
+        let (pack, ids) = pack_of_blobs(&[b"first\n", b"second\n"]);
+
+        let mut flipped = pack.clone();
+        let trailer = flipped.len() - 1;
+        flipped[trailer] ^= 0xff;
+        assert!(matches!(
+            verify_pack(&flipped, gix_hash::Kind::Sha1, &ids),
+            Err(Error::PackVerification(_))
+        ));
+
+        let swapped: Vec<_> = ids.iter().rev().copied().collect();
+        let err = verify_pack(&pack, gix_hash::Kind::Sha1, &swapped).unwrap_err();
+        assert!(err.to_string().contains("hashes to"), "{err}");
+
+        assert!(verify_pack(&pack, gix_hash::Kind::Sha1, &ids[..1]).is_err());
+    }
+}
@@ -159,17 +159,78 @@ impl<R: Read> EnhancedPacketReader<R> {
     }
 }
 
+/// The maximum number of completed progress messages held back until sideband is established
+const MAX_HELD_PROGRESS: usize = 32;
+
+/// The phase of a response with regard to side-band multiplexing
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResponsePhase {
+    /// Sideband isn't usable yet, as capabilities are still negotiated or the pack section hasn't started
+    PreSideband,
+    /// The pack section started, and data on the negotiated side-band channels may be sent
+    Sideband,
+}
+
 /// Enhanced packet writer with side-band support using gix-packetline
-#[derive(Clone, Copy)]
+///
+/// Progress, errors on band 3 and keep-alive packets are only sent once the pack section of the
+/// response started and [`establish_sideband()`](Self::establish_sideband()) was called, even if a
+/// side-band mode was already negotiated. Until then, completed progress messages are held back and
+/// transient progress updates are dropped.
+#[derive(Clone)]
 pub struct EnhancedPacketWriter<W: Write> {
     writer: W,
     mode: SideBandMode,
+    phase: ResponsePhase,
+    held_progress: Vec<String>,
+    sideband_all: bool,
+    progress: bool,
 }
 
 impl<W: Write> EnhancedPacketWriter<W> {
     /// Create a new enhanced packet writer
     pub fn new(writer: W, mode: SideBandMode) -> Self {
-        Self { writer, mode }
+        Self {
+            writer,
+            mode,
+            phase: ResponsePhase::PreSideband,
+            held_progress: Vec::new(),
+            sideband_all: false,
+            progress: true,
+        }
+    }
+
+    /// The phase of the response, see [`ResponsePhase`]
+    pub fn phase(&self) -> ResponsePhase {
+        self.phase
+    }
+
+    /// Mark the start of the pack section, from which on side-band channels may be used
+    ///
+    /// Progress messages held back until now are sent if a side-band mode was negotiated, and dropped otherwise.
+    pub fn establish_sideband(&mut self) -> Result<()> {
+        self.phase = ResponsePhase::Sideband;
+        for message in std::mem::take(&mut self.held_progress) {
+            self.send_progress(&message)?;
+        }
+        Ok(())
+    }
+
+    /// Start the next response of a stateful session, which establishes sideband and negotiates its modes anew
+    pub fn start_response(&mut self) {
+        self.mode = SideBandMode::None;
+        self.phase = ResponsePhase::PreSideband;
+        self.held_progress.clear();
+        self.sideband_all = false;
+        self.progress = true;
+    }
+
+    /// The side-band mode usable right now, which is [`SideBandMode::None`] before sideband is established
+    fn effective_mode(&self) -> SideBandMode {
+        match self.phase {
+            ResponsePhase::PreSideband => SideBandMode::None,
+            ResponsePhase::Sideband => self.mode,
+        }
     }
 
     /// Send data through the appropriate channel
@@ -192,6 +253,16 @@ impl<W: Write> EnhancedPacketWriter<W> {
 
     /// Send progress message through the progress channel
     pub fn send_progress(&mut self, message: &str) -> Result<()> {
+        if !self.progress {
+            return Ok(());
+        }
+        if self.phase == ResponsePhase::PreSideband {
+            // Only completed messages are worth showing later, updates would be outdated by then
+            if message.ends_with(", done.") && self.held_progress.len() < MAX_HELD_PROGRESS {
+                self.held_progress.push(message.to_owned());
+            }
+            return Ok(());
+        }
         if self.mode == SideBandMode::None {
             return Ok(()); // Cannot send progress without side-band
         }
@@ -211,9 +282,24 @@ impl<W: Write> EnhancedPacketWriter<W> {
         Ok(())
     }
 
+    /// Send `data` produced by another program as is through the progress channel, without adding line endings
+    ///
+    /// Like [`send_progress()`](Self::send_progress()), nothing is sent without side-band or if progress is
+    /// disabled. Before the side-band is established, the data is dropped.
+    pub fn relay_progress(&mut self, data: &[u8]) -> Result<()> {
+        if !self.progress || self.effective_mode() == SideBandMode::None {
+            return Ok(());
+        }
+        let max_size = self.mode.max_data_size().unwrap_or(65515);
+        for chunk in data.chunks(max_size) {
+            band_to_write(SideBandChannel::Progress, chunk, &mut self.writer)?;
+        }
+        Ok(())
+    }
+
     /// Send error message through the error channel or as ERR packet
     pub fn send_error(&mut self, error: &str) -> Result<()> {
-        match self.mode {
+        match self.effective_mode() {
             SideBandMode::None => {
                 // Use gix-packetline's error_to_write function
                 error_to_write(error.as_bytes(), &mut self.writer)?;
@@ -229,6 +315,18 @@ impl<W: Write> EnhancedPacketWriter<W> {
         Ok(())
     }
 
+    /// Send an empty packet on the data channel to keep the connection alive while the pack is prepared
+    ///
+    /// Returns `false` if the keep-alive was withheld as sideband isn't established or wasn't negotiated.
+    pub fn send_keepalive(&mut self) -> Result<bool> {
+        if self.effective_mode() == SideBandMode::None {
+            return Ok(false);
+        }
+        // Encoders refuse empty packets, but an empty data band is exactly what git sends as keep-alive
+        self.writer.write_all(b"0005\x01")?;
+        Ok(true)
+    }
+
     /// Write a flush packet using gix-packetline
     pub fn write_flush(&mut self) -> Result<()> {
         flush_to_write(&mut self.writer)?;
@@ -257,12 +355,23 @@ impl<W: Write> EnhancedPacketWriter<W> {
         self.send_data(line)
     }
 
-    /// Write protocol message as packet-line (bypasses sideband)
+    /// Write protocol message as packet-line, which bypasses sideband unless `sideband-all` was requested
     pub fn write_protocol_message(&mut self, data: &[u8]) -> Result<()> {
-        data_to_write(data, &mut self.writer)?;
+        if self.sideband_all {
+            band_to_write(SideBandChannel::Data, data, &mut self.writer)?;
+        } else {
+            data_to_write(data, &mut self.writer)?;
+        }
         Ok(())
     }
 
+    /// Send protocol messages on band 1 as well, as the v2 `fetch` argument `sideband-all` requires
+    ///
+    /// Flush and delimiter packets are never multiplexed.
+    pub fn set_sideband_all(&mut self, enabled: bool) {
+        self.sideband_all = enabled;
+    }
+
     /// Get access to the underlying writer for direct packet writing
     pub fn inner_mut(&mut self) -> &mut W {
         &mut self.writer
@@ -273,6 +382,11 @@ impl<W: Write> EnhancedPacketWriter<W> {
         self.writer
     }
 
+    /// Drop all progress messages unless `enabled`, as clients requesting `no-progress` expect
+    pub fn set_progress(&mut self, enabled: bool) {
+        self.progress = enabled;
+    }
+
     /// Update the sideband mode (used after capability negotiation)
     pub fn set_sideband_mode(&mut self, mode: SideBandMode) {
         self.mode = mode;
@@ -282,6 +396,7 @@ impl<W: Write> EnhancedPacketWriter<W> {
     pub fn send_ack(&mut self, oid: &gix_hash::ObjectId, status: AckStatus) -> Result<()> {
         let status_str = match status {
             AckStatus::Common => "",
+            AckStatus::DetailedCommon => protocol::ACK_COMMON_SUFFIX,
             AckStatus::Continue => protocol::ACK_CONTINUE_SUFFIX,
             AckStatus::Ready => protocol::ACK_READY_SUFFIX,
         };
@@ -416,7 +531,6 @@ impl<W: Write> Write for EnhancedPacketWriter<W> {
             SideBandMode::Basic | SideBandMode::SideBand64k => {
                 // This should not be used directly for pack data!
                 // Use BufferedSideBandWriter instead to prevent fragmentation
-                eprintln!("WARNING: Direct write to EnhancedPacketWriter in sideband mode - this will fragment data!");
                 let max_size = self.mode.max_data_size().unwrap_or(65515);
                 for chunk in buf.chunks(max_size) {
                     band_to_write(SideBandChannel::Data, chunk, &mut self.writer)
@@ -473,6 +587,51 @@ mod tests {
         // The actual packet reading functionality is tested through integration tests
     }
 
+    #[test]
+    fn progress_is_withheld_until_sideband_is_established() {
+        let mut writer = EnhancedPacketWriter::new(Vec::new(), SideBandMode::None);
+        writer.set_sideband_mode(SideBandMode::SideBand64k);
+        writer.send_progress("Enumerating objects: 1").unwrap();
+        writer.send_progress("Enumerating objects: 2, done.").unwrap();
+        assert!(!writer.send_keepalive().unwrap());
+        writer.send_error("fatal").unwrap();
+        assert_eq!(writer.phase(), ResponsePhase::PreSideband);
+        assert_eq!(
+            writer.inner_mut().as_slice(),
+            b"000dERR fatal",
+            "errors use ERR packets"
+        );
+
+        writer.write_protocol_message(b"packfile\n").unwrap();
+        writer.establish_sideband().unwrap();
+        assert_eq!(writer.phase(), ResponsePhase::Sideband);
+        assert!(writer.send_keepalive().unwrap());
+        assert_eq!(
+            writer.into_inner(),
+            b"000dERR fatal000dpackfile\n0023\x02Enumerating objects: 2, done.\n0005\x01",
+            "only completed progress is sent after the pack section started"
+        );
+    }
+
+    #[test]
+    fn withheld_progress_is_dropped_without_sideband() {
+        let mut writer = EnhancedPacketWriter::new(Vec::new(), SideBandMode::None);
+        writer.send_progress("Enumerating objects: 2, done.").unwrap();
+        writer.establish_sideband().unwrap();
+        assert!(!writer.send_keepalive().unwrap());
+        assert!(writer.into_inner().is_empty());
+    }
+
+    #[test]
+    fn progress_can_be_disabled() {
+        let mut writer = EnhancedPacketWriter::new(Vec::new(), SideBandMode::SideBand64k);
+        writer.set_progress(false);
+        writer.send_progress("Counting objects: 2, done.").unwrap();
+        writer.establish_sideband().unwrap();
+        writer.send_progress("Total 2 (delta 0)").unwrap();
+        assert!(writer.into_inner().is_empty());
+    }
+
     #[test]
     fn test_enhanced_packet_writer() {
         let output = Vec::new();
@@ -3,56 +3,164 @@
 //! This module centralizes all reference-related operations including
 //! collection, filtering, and advertisement formatting.
 
+use super::authorization::{CachedAuthorization, RefAuthorization};
+use super::snapshot::RefSnapshot;
 use crate::{
     error::{Error, Result},
     types::*,
 };
-use bstr::{BStr, ByteSlice, ByteVec};
+use bstr::{BStr, BString, ByteSlice};
 use gix::Repository;
+use gix_hash::ObjectId;
+use gix_serve_core::visibility::HiddenRefs;
+use std::collections::HashSet;
+use std::time::{Duration, Instant};
+
+/// The number of references peeled between checks of the peel budget
+const PEEL_BATCH: usize = 64;
+
+/// How annotated tags are peeled while collecting references
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Peeling {
+    /// Leave all references unpeeled
+    Skip,
+    /// Peel until the peel budget is spent
+    Budgeted,
+    /// Peel all references, regardless of the peel budget
+    Complete,
+}
 
 /// Reference manager for handling reference operations
 pub struct ReferenceManager<'a> {
     repository: &'a Repository,
-    hidden_patterns: &'a [bstr::BString],
+    hidden: HiddenRefs,
+    snapshot: Option<&'a RefSnapshot>,
+    authorization: Option<CachedAuthorization<'a>>,
+    peel_budget: Option<Duration>,
 }
 
 impl<'a> ReferenceManager<'a> {
-    /// Create a new reference manager
+    /// Create a new reference manager hiding the refs matched by the `hideRefs` patterns `hidden_patterns`
     pub fn new(repository: &'a Repository, hidden_patterns: &'a [bstr::BString]) -> Self {
         Self {
             repository,
-            hidden_patterns,
+            hidden: hidden_patterns.iter().map(|pattern| pattern.to_str_lossy()).collect(),
+            snapshot: None,
+            authorization: None,
+            peel_budget: None,
         }
     }
 
+    /// Serve the references of `snapshot` instead of reading them from the repository
+    pub fn with_snapshot(mut self, snapshot: Option<&'a RefSnapshot>) -> Self {
+        self.snapshot = snapshot;
+        self
+    }
+
+    /// Only serve the refs `authorization` allows, remembering its decisions for the lifetime of this instance
+    pub fn with_authorization(mut self, authorization: Option<&'a RefAuthorization>) -> Self {
+        self.authorization = authorization.map(CachedAuthorization::new);
+        self
+    }
+
+    /// Stop peeling annotated tags once collecting references took longer than `budget`
+    ///
+    /// References that weren't peeled in time are advertised without their peeled value, which clients have to
+    /// cope with as it's optional in both protocols, keeping the latency of advertisements in tag-heavy
+    /// repositories bounded.
+    pub fn with_peel_budget(mut self, budget: Option<Duration>) -> Self {
+        self.peel_budget = budget;
+        self
+    }
+
     /// Collect all references that should be advertised
     /// Following the same logical flow as v2 protocol without sorting
     pub fn collect_advertised_references(&self) -> Result<Vec<Reference>> {
-        self.collect_references_with_prefixes(&[])
+        self.collect_references(&[], false, Peeling::Budgeted)
+    }
+
+    /// Collect references with optional prefix filtering (for v2 protocol), with annotated tags peeled if `peel` is set
+    pub fn collect_references_with_prefixes(&self, prefixes: &[String], peel: bool) -> Result<Vec<Reference>> {
+        let peeling = if peel { Peeling::Budgeted } else { Peeling::Skip };
+        self.collect_references(prefixes, false, peeling)
+    }
+
+    /// Collect the references the client is authorized to see, with hidden ones only if `include_hidden` is set
+    ///
+    /// Annotated tags are [`Reference::Peeled`] if they were peeled according to `peeling`.
+    fn collect_references(
+        &self,
+        prefixes: &[String],
+        include_hidden: bool,
+        peeling: Peeling,
+    ) -> Result<Vec<Reference>> {
+        let start = Instant::now();
+        let mut refs = match self.snapshot {
+            Some(snapshot) => self.collect_snapshot_references(snapshot, prefixes, include_hidden),
+            None => self.collect_repository_references(prefixes, include_hidden)?,
+        };
+        let deadline = match peeling {
+            Peeling::Skip => return Ok(refs),
+            Peeling::Budgeted => self.peel_budget.map(|budget| start + budget),
+            Peeling::Complete => None,
+        };
+        self.peel(&mut refs, deadline);
+        Ok(refs)
     }
 
-    /// Collect references with optional prefix filtering (for v2 protocol)
-    pub fn collect_references_with_prefixes(&self, prefixes: &[String]) -> Result<Vec<Reference>> {
+    /// Peel the annotated tags among `refs` in batches, leaving the remaining ones unpeeled once `deadline` passed
+    fn peel(&self, refs: &mut [Reference], deadline: Option<Instant>) {
+        for batch in refs.chunks_mut(PEEL_BATCH) {
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                return;
+            }
+            for reference in batch {
+                let ProtocolRef::Direct { full_ref_name, object } = reference else {
+                    continue;
+                };
+                let Some(peeled) = self.peeled_tag(full_ref_name.as_ref(), *object) else {
+                    continue;
+                };
+                *reference = ProtocolRef::Peeled {
+                    full_ref_name: std::mem::take(full_ref_name),
+                    tag: *object,
+                    object: peeled,
+                };
+            }
+        }
+    }
+
+    /// Collect the unpeeled references of the repository
+    fn collect_repository_references(&self, prefixes: &[String], include_hidden: bool) -> Result<Vec<Reference>> {
         let mut refs = Vec::new();
 
-        // Add HEAD first if it exists - following v2 pattern
-        if let Ok(head) = self.repository.head() {
+        // Add HEAD first if it exists and is asked for - following v2 pattern
+        let head = self
+            .repository
+            .head()
+            .ok()
+            .filter(|_| matches_prefixes(prefixes, "HEAD".into()));
+        if let Some(head) = head {
             match head.kind {
                 gix::head::Kind::Symbolic(target_ref) => {
                     if let gix::refs::Target::Object(oid) = &target_ref.target {
-                        refs.push(ProtocolRef::Symbolic {
-                            full_ref_name: "HEAD".into(),
-                            target: target_ref.name.as_bstr().to_owned(),
-                            tag: None,
-                            object: *oid,
-                        });
+                        if !self.is_unauthorized("HEAD".into(), Some(target_ref.name.as_bstr())) {
+                            refs.push(ProtocolRef::Symbolic {
+                                full_ref_name: "HEAD".into(),
+                                target: target_ref.name.as_bstr().to_owned(),
+                                tag: None,
+                                object: *oid,
+                            });
+                        }
                     }
                 }
                 gix::head::Kind::Detached { target, .. } => {
-                    refs.push(ProtocolRef::Direct {
-                        full_ref_name: "HEAD".into(),
-                        object: target,
-                    });
+                    if !self.is_unauthorized("HEAD".into(), None) {
+                        refs.push(ProtocolRef::Direct {
+                            full_ref_name: "HEAD".into(),
+                            object: target,
+                        });
+                    }
                 }
                 gix::head::Kind::Unborn(_) => {
                     // Skip unborn HEAD as it has no commit to advertise
@@ -60,35 +168,27 @@ impl<'a> ReferenceManager<'a> {
             }
         }
 
-        // Get all other references - process in natural order like v2
+        // Get all other references, only iterating the parts of the namespace the prefixes ask for
         let reference_store = self.repository.references().map_err(|e| Error::RefPackedBuffer(e))?;
-        let references = reference_store.all().map_err(|e| Error::RefIterInit(e))?;
-
-        // Collect references that match prefixes (if any)
-        let mut filtered_refs = Vec::new();
-        for reference in references {
-            if let Ok(reference) = reference {
-                let ref_name = reference.name().as_bstr().to_str_lossy();
-
-                // Apply prefix filtering if prefixes are specified
-                if !prefixes.is_empty() {
-                    let matches_prefix = prefixes.iter().any(|prefix| ref_name.starts_with(prefix));
-                    if matches_prefix {
-                        filtered_refs.push(reference);
-                    }
-                } else {
-                    // No prefix filtering - include all references
-                    filtered_refs.push(reference);
-                }
-            }
-        }
+        let filtered_refs = prefixed_references(&reference_store, prefixes)?;
 
         // Process the filtered references
         for reference in filtered_refs {
             let name = reference.name().as_bstr().to_owned();
+            // While serving a namespace, its `HEAD` is iterated as well, but it's advertised above already
+            if !name.starts_with(b"refs/") {
+                continue;
+            }
 
             // Skip hidden references
-            if self.is_ref_hidden(name.as_ref()) {
+            if !include_hidden && self.is_ref_hidden(name.as_ref()) {
+                continue;
+            }
+            let symref_target = match reference.target() {
+                gix::refs::TargetRef::Symbolic(target) => Some(target.as_bstr()),
+                gix::refs::TargetRef::Object(_) => None,
+            };
+            if self.is_unauthorized(name.as_ref(), symref_target) {
                 continue;
             }
 
@@ -106,32 +206,10 @@ impl<'a> ReferenceManager<'a> {
                     }
                 }
                 gix::refs::TargetRef::Object(oid) => {
-                    let target = oid.to_owned();
-
-                    // Add the main reference
                     refs.push(ProtocolRef::Direct {
-                        full_ref_name: name.clone(),
-                        object: target,
+                        full_ref_name: name,
+                        object: oid.to_owned(),
                     });
-
-                    // For tags, immediately add peeled version if it's an annotated tag
-                    if name.starts_with_str("refs/tags/") {
-                        if let Some(peeled) = self
-                            .repository
-                            .find_tag(target)
-                            .ok()
-                            .and_then(|tag| tag.target_id().ok())
-                            .map(|id| id.detach())
-                        {
-                            // Add peeled tag immediately after the tag reference
-                            let mut peeled_name = name.clone();
-                            peeled_name.push_str("^{}");
-                            refs.push(ProtocolRef::Direct {
-                                full_ref_name: peeled_name,
-                                object: peeled,
-                            });
-                        }
-                    }
                 }
             }
         }
@@ -140,29 +218,163 @@ impl<'a> ReferenceManager<'a> {
         Ok(refs)
     }
 
-    /// Check if a reference should be hidden based on patterns
-    fn is_ref_hidden(&self, ref_name: &BStr) -> bool {
-        let ref_str = ref_name.to_str_lossy();
-
-        for pattern in self.hidden_patterns {
-            if self.matches_pattern(&ref_str, pattern.to_str_lossy().as_ref()) {
-                return true;
+    /// Collect the unpeeled references of `snapshot` in the same shape as live references
+    fn collect_snapshot_references(
+        &self,
+        snapshot: &RefSnapshot,
+        prefixes: &[String],
+        include_hidden: bool,
+    ) -> Vec<Reference> {
+        let mut refs = Vec::new();
+        for reference in snapshot.refs() {
+            let name = &reference.name;
+            if !matches_prefixes(prefixes, name.as_ref()) {
+                continue;
+            }
+            if name != "HEAD" && !include_hidden && self.is_ref_hidden(name.as_ref()) {
+                continue;
+            }
+            if self.is_unauthorized(name.as_ref(), reference.symref_target.as_ref().map(AsRef::as_ref)) {
+                continue;
+            }
+            match &reference.symref_target {
+                Some(target) => refs.push(ProtocolRef::Symbolic {
+                    full_ref_name: name.clone(),
+                    target: target.clone(),
+                    tag: None,
+                    object: reference.object,
+                }),
+                None => refs.push(ProtocolRef::Direct {
+                    full_ref_name: name.clone(),
+                    object: reference.object,
+                }),
             }
         }
+        refs
+    }
 
-        false
+    /// Return the object the annotated tag `object` points to after peeling all tags, if `name` is a tag
+    fn peeled_tag(&self, name: &BStr, object: ObjectId) -> Option<ObjectId> {
+        if !name.starts_with_str("refs/tags/") {
+            return None;
+        }
+        if self.repository.find_header(object).ok()?.kind() != gix_object::Kind::Tag {
+            return None;
+        }
+        let peeled = self.repository.find_object(object).ok()?.peel_tags_to_end().ok()?;
+        Some(peeled.id)
     }
 
-    /// Check if a reference name matches a pattern (simple glob matching)
-    fn matches_pattern(&self, ref_name: &str, pattern: &str) -> bool {
-        if pattern.ends_with("*") {
-            let prefix = &pattern[..pattern.len() - 1];
-            ref_name.starts_with(prefix)
-        } else if pattern.starts_with("*") {
-            let suffix = &pattern[1..];
-            ref_name.ends_with(suffix)
+    /// The name of the reference `HEAD` points to, if it's symbolic and the client may see it
+    pub fn head_target(&self) -> Option<BString> {
+        let target = match self.snapshot {
+            Some(snapshot) => snapshot.head_target().map(ToOwned::to_owned),
+            None => match self.repository.head().ok()?.kind {
+                gix::head::Kind::Symbolic(target_ref) => Some(target_ref.name.as_bstr().to_owned()),
+                _ => None,
+            },
+        }?;
+        (!self.is_unauthorized("HEAD".into(), Some(target.as_ref()))).then_some(target)
+    }
+
+    /// The name of the reference an unborn `HEAD` points to, like in repositories without commits
+    ///
+    /// Ref snapshots only contain refs with an object, so their `HEAD` is never unborn.
+    pub fn unborn_head_target(&self) -> Option<BString> {
+        if self.snapshot.is_some() {
+            return None;
+        }
+        match self.repository.head().ok()?.kind {
+            gix::head::Kind::Unborn(name) if !self.is_unauthorized("HEAD".into(), Some(name.as_bstr())) => {
+                Some(name.as_bstr().to_owned())
+            }
+            _ => None,
+        }
+    }
+
+    /// Check that all `wants` may be fetched from the configured ref snapshot, or from the authorized refs
+    ///
+    /// Wants must be advertised tips, or hidden tips with `allow_tip_sha1_in_want`, or commits reachable from any tip
+    /// with `allow_reachable_sha1_in_want`. Without a snapshot and authorization, and with `allow_any_sha1_in_want`,
+    /// all wants pass. With authorization, `allow_any_sha1_in_want` is limited to commits reachable from authorized
+    /// refs, so refs the client may not see can't be fetched by object id.
+    pub fn validate_wants(&self, wants: &HashSet<ObjectId>, options: &crate::config::ServerOptions) -> Result<()> {
+        if self.snapshot.is_none() && self.authorization.is_none() {
+            return Ok(());
+        }
+        if options.allow_any_sha1_in_want && self.authorization.is_none() {
+            return Ok(());
+        }
+        let mut advertised = HashSet::new();
+        let mut all_tips = HashSet::new();
+        for reference in self.collect_references(&[], true, Peeling::Complete)? {
+            let (name, Some(object), peeled) = reference.unpack() else {
+                continue;
+            };
+            let hidden = self.is_ref_hidden(name);
+            for object in std::iter::once(object).chain(peeled) {
+                if !hidden {
+                    advertised.insert(object.to_owned());
+                }
+                all_tips.insert(object.to_owned());
+            }
+        }
+        let reachable = options.allow_reachable_sha1_in_want || options.allow_any_sha1_in_want;
+        let allowed = if options.allow_tip_sha1_in_want || reachable {
+            &all_tips
         } else {
-            ref_name == pattern
+            &advertised
+        };
+        let mut unknown: Vec<ObjectId> = wants.iter().filter(|want| !allowed.contains(*want)).copied().collect();
+        if !unknown.is_empty() && reachable {
+            let tips: Vec<_> = all_tips
+                .iter()
+                .copied()
+                .filter(|tip| self.repository.find_commit(*tip).is_ok())
+                .collect();
+            if !tips.is_empty() {
+                let walk = self
+                    .repository
+                    .rev_walk(tips)
+                    .all()
+                    .map_err(|e| Error::custom(format!("Revision walk setup failed: {}", e)))?;
+                for info in walk {
+                    let id = info
+                        .map_err(|e| Error::custom(format!("Revision walk failed: {}", e)))?
+                        .id;
+                    unknown.retain(|want| *want != id);
+                    if unknown.is_empty() {
+                        break;
+                    }
+                }
+            }
+        }
+        unknown.sort();
+        match unknown.first() {
+            Some(oid) => Err(Error::NotOurRef { oid: *oid }),
+            None => Ok(()),
+        }
+    }
+
+    /// Return `true` if the client may not see `name`, or the ref it points to if it's symbolic
+    fn is_unauthorized(&self, name: &BStr, symref_target: Option<&BStr>) -> bool {
+        let Some(authorization) = &self.authorization else {
+            return false;
+        };
+        !authorization.is_authorized(name) || symref_target.is_some_and(|target| !authorization.is_authorized(target))
+    }
+
+    /// Check if a reference is hidden by the `hideRefs` patterns
+    ///
+    /// While serving a namespace, `ref_name` is stripped of it, and `^` patterns match its full name.
+    fn is_ref_hidden(&self, ref_name: &BStr) -> bool {
+        let name = ref_name.to_str_lossy();
+        match self.repository.namespace() {
+            Some(namespace) => {
+                let full_name = format!("{}{name}", namespace.as_bstr());
+                self.hidden.is_hidden_in_namespace(Some(&name), &full_name)
+            }
+            None => self.hidden.is_hidden(&name),
         }
     }
 
@@ -175,26 +387,26 @@ impl<'a> ReferenceManager<'a> {
             let null_oid = gix_hash::ObjectId::null(self.repository.object_hash());
             lines.push(format!("{} capabilities^{{}}\0{}", null_oid.to_hex(), capabilities));
         } else {
-            // Send first ref with capabilities
-            let first_ref = &refs[0];
-            let (name, target, _) = first_ref.unpack();
             let null_oid = gix_hash::ObjectId::null(self.repository.object_hash());
-            let target_oid = target.unwrap_or(&null_oid);
-            lines.push(format!(
-                "{} {}\0{}",
-                target_oid.to_hex(),
-                name.to_str_lossy(),
-                capabilities
-            ));
-
-            // Send remaining refs without capabilities
-            for reference in refs.iter().skip(1) {
-                let (name, target, _) = reference.unpack();
+            for (index, reference) in refs.iter().enumerate() {
+                let (name, target, peeled) = reference.unpack();
                 let target_oid = target.unwrap_or(&null_oid);
-                lines.push(format!("{} {}", target_oid.to_hex(), name.to_str_lossy()));
+                // Send the first ref with capabilities
+                if index == 0 {
+                    lines.push(format!(
+                        "{} {}\0{}",
+                        target_oid.to_hex(),
+                        name.to_str_lossy(),
+                        capabilities
+                    ));
+                } else {
+                    lines.push(format!("{} {}", target_oid.to_hex(), name.to_str_lossy()));
+                }
+                // Annotated tags are followed by the object they point to
+                if let Some(peeled) = peeled {
+                    lines.push(format!("{} {}^{{}}", peeled.to_hex(), name.to_str_lossy()));
+                }
             }
-
-            // Peeled refs are already included in the main reference list
         }
 
         Ok(lines)
@@ -229,24 +441,65 @@ impl<'a> ReferenceManager<'a> {
 
                 // Add symref info if requested and this is HEAD
                 if show_symrefs && name == "HEAD" {
-                    if let Ok(head) = self.repository.head() {
-                        if let gix::head::Kind::Symbolic(target_ref) = head.kind {
-                            line.push_str(&format!(" symref-target:{}", target_ref.name.as_bstr().to_str_lossy()));
-                        }
+                    if let Some(target) = self.head_target() {
+                        line.push_str(&format!(" symref-target:{}", target.to_str_lossy()));
                     }
                 }
 
-                lines.push(line);
-
                 // Add peeled info if requested and available
                 if show_peeled {
                     if let Some(peeled_oid) = peeled {
-                        lines.push(format!("{} {}^{{}}", peeled_oid.to_hex(), name.to_str_lossy()));
+                        line.push_str(&format!(" peeled:{}", peeled_oid.to_hex()));
                     }
                 }
+
+                lines.push(line);
             }
         }
 
         Ok(lines)
     }
 }
+
+/// Return `true` if `name` starts with any of `prefixes`, or if there are none
+fn matches_prefixes(prefixes: &[String], name: &BStr) -> bool {
+    prefixes.is_empty() || prefixes.iter().any(|prefix| name.starts_with(prefix.as_bytes()))
+}
+
+/// Collect the references of `platform` starting with any of `prefixes`, or all of them if there are none
+///
+/// Like git, only the prefixes no other prefix is a prefix of are iterated, in order, so each reference is returned
+/// once and the parts of the namespace nobody asked for, like the tags of a client only listing branches, aren't
+/// read at all.
+fn prefixed_references<'p>(
+    platform: &'p gix::reference::iter::Platform<'_>,
+    prefixes: &[String],
+) -> Result<Vec<gix::Reference<'p>>> {
+    let mut sorted: Vec<&str> = prefixes.iter().map(String::as_str).collect();
+    sorted.sort_unstable();
+    let mut minimal: Vec<&str> = Vec::with_capacity(sorted.len());
+    for prefix in sorted {
+        if !minimal.last().is_some_and(|shorter| prefix.starts_with(shorter)) {
+            minimal.push(prefix);
+        }
+    }
+    // All references are below `refs/`, so prefixes of it match all of them and others outside of it none
+    if minimal.is_empty() || minimal.iter().any(|prefix| "refs/".starts_with(prefix)) {
+        return Ok(platform.all()?.filter_map(std::result::Result::ok).collect());
+    }
+
+    let mut references = Vec::new();
+    for prefix in minimal.into_iter().filter(|prefix| prefix.starts_with("refs/")) {
+        match platform.prefixed(prefix) {
+            Ok(iter) => references.extend(iter.filter_map(std::result::Result::ok)),
+            // Prefixes that aren't valid paths still match the names they start
+            Err(_) => references.extend(
+                platform
+                    .all()?
+                    .filter_map(std::result::Result::ok)
+                    .filter(|reference| reference.name().as_bstr().starts_with(prefix.as_bytes())),
+            ),
+        }
+    }
+    Ok(references)
+}
@@ -0,0 +1,137 @@
+//! Fixed ref snapshots for serving a consistent view from replicas
+//!
+//! Read replicas of a mirror receive ref updates at slightly different times, so two requests may see different
+//! refs depending on which replica serves them, or even on the same replica in the middle of a replication cycle.
+//! An embedder that knows a globally consistent set of refs passes it as a [`RefSnapshot`], and upload-pack then
+//! advertises exactly these refs and validates wants against them without reading any refs from the repository.
+//! Objects are still read from the repository, which is expected to contain everything the snapshot references.
+
+use bstr::{BStr, BString, ByteSlice};
+use gix_hash::ObjectId;
+
+/// A reference of a [`RefSnapshot`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SnapshotRef {
+    /// The full name of the reference, like `refs/heads/main` or `HEAD`
+    pub name: BString,
+    /// The object the reference points to, after following symbolic references
+    pub object: ObjectId,
+    /// The name of the reference this one points to if it's symbolic
+    pub symref_target: Option<BString>,
+}
+
+/// A fixed set of references to serve instead of the repository's live references
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RefSnapshot {
+    id: String,
+    head: Option<SnapshotRef>,
+    refs: Vec<SnapshotRef>,
+}
+
+impl RefSnapshot {
+    /// Create a snapshot identified by `id` of the references `refs`, given as `(name, object)` pairs
+    ///
+    /// References are advertised sorted by name with `HEAD` first. If a name occurs more than once, the last
+    /// occurrence wins.
+    pub fn new<N: Into<BString>>(id: impl Into<String>, refs: impl IntoIterator<Item = (N, ObjectId)>) -> Self {
+        let mut head = None;
+        let mut by_name = std::collections::BTreeMap::new();
+        for (name, object) in refs {
+            let name = name.into();
+            let reference = SnapshotRef {
+                name: name.clone(),
+                object,
+                symref_target: None,
+            };
+            if name == "HEAD" {
+                head = Some(reference);
+            } else {
+                by_name.insert(name, reference);
+            }
+        }
+        Self {
+            id: id.into(),
+            head,
+            refs: by_name.into_values().collect(),
+        }
+    }
+
+    /// Make `name` a symbolic reference to `target`, pointing to the same object
+    ///
+    /// This is ignored if `target` isn't part of the snapshot, just like an unborn `HEAD` isn't advertised.
+    pub fn with_symref(mut self, name: impl Into<BString>, target: impl Into<BString>) -> Self {
+        let (name, target) = (name.into(), target.into());
+        let Some(object) = self.refs.iter().find(|r| r.name == target).map(|r| r.object) else {
+            return self;
+        };
+        let reference = SnapshotRef {
+            name: name.clone(),
+            object,
+            symref_target: Some(target),
+        };
+        if name == "HEAD" {
+            self.head = Some(reference);
+        } else {
+            match self.refs.binary_search_by(|r| r.name.cmp(&name)) {
+                Ok(pos) => self.refs[pos] = reference,
+                Err(pos) => self.refs.insert(pos, reference),
+            }
+        }
+        self
+    }
+
+    /// The identifier of this snapshot, for correlating requests with replication cycles
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// All references in advertisement order, `HEAD` first
+    pub fn refs(&self) -> impl Iterator<Item = &SnapshotRef> {
+        self.head.iter().chain(&self.refs)
+    }
+
+    /// The reference `HEAD` points to if it's symbolic
+    pub fn head_target(&self) -> Option<&BStr> {
+        self.head
+            .as_ref()?
+            .symref_target
+            .as_ref()
+            .map(|target| target.as_bstr())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn id(byte: u8) -> ObjectId {
+        ObjectId::from_bytes_or_panic(&[byte; 20])
+    }
+
+    #[test]
+    fn refs_are_ordered_with_head_first() {
+        let snapshot = RefSnapshot::new(
+            "cycle-7",
+            [
+                ("refs/tags/v1", id(3)),
+                ("HEAD", id(9)),
+                ("refs/heads/main", id(1)),
+                ("refs/heads/main", id(2)),
+            ],
+        )
+        .with_symref("HEAD", "refs/heads/main")
+        .with_symref("refs/remotes/origin/HEAD", "refs/heads/gone");
+
+        assert_eq!(snapshot.id(), "cycle-7");
+        assert_eq!(snapshot.head_target(), Some("refs/heads/main".into()));
+        let refs: Vec<_> = snapshot.refs().map(|r| (r.name.to_string(), r.object)).collect();
+        assert_eq!(
+            refs,
+            [
+                ("HEAD".to_string(), id(2)),
+                ("refs/heads/main".to_string(), id(2)),
+                ("refs/tags/v1".to_string(), id(3)),
+            ]
+        );
+    }
+}
@@ -0,0 +1,195 @@
+//! Detection of stalled clients while reading requests
+//!
+//! The session timeout bounds the total time a client may take, but a client trickling a few bytes at a time can
+//! hold a connection open for the whole timeout at almost no cost to itself. Like git's `http.lowSpeedLimit`,
+//! [`StallReader`] aborts reading if fewer than a minimum number of bytes arrived within a time window.
+//!
+//! The check runs whenever a read returns, so a client that sends nothing at all is left to the session timeout.
+//! The first window starts with the first byte, so the time a client needs to start its request isn't counted.
+
+use std::io::{self, Read};
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
+
+/// The minimum transfer rate below which a client is considered stalled
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StallDetection {
+    /// The number of bytes that must arrive within each window
+    pub min_bytes: u64,
+    /// The length of the window
+    pub window: Duration,
+}
+
+impl StallDetection {
+    /// Abort if fewer than `min_bytes` arrive within `window`
+    pub fn new(min_bytes: u64, window: Duration) -> Self {
+        Self { min_bytes, window }
+    }
+
+    /// Abort if the rate stays below `bytes_per_second` for `seconds`, like `http.lowSpeedLimit` and
+    /// `http.lowSpeedTime`, or return `None` if either is zero
+    pub fn from_low_speed(bytes_per_second: u64, seconds: u64) -> Option<Self> {
+        (bytes_per_second > 0 && seconds > 0)
+            .then(|| Self::new(bytes_per_second.saturating_mul(seconds), Duration::from_secs(seconds)))
+    }
+}
+
+/// The reason a [`StallReader`] stopped reading
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("client sent only {received} bytes within {}s, at least {min_bytes} are required", .window.as_secs_f32())]
+pub struct Stalled {
+    /// The bytes received within the window
+    pub received: u64,
+    /// The minimum number of bytes required within the window
+    pub min_bytes: u64,
+    /// The length of the window that was exceeded
+    pub window: Duration,
+}
+
+/// A handle to learn whether a [`StallReader`] detected a stall, after the reader itself was handed off
+#[derive(Debug, Clone, Default)]
+pub struct StallMonitor(Arc<OnceLock<Stalled>>);
+
+impl StallMonitor {
+    /// Return the detected stall, if any
+    pub fn stalled(&self) -> Option<&Stalled> {
+        self.0.get()
+    }
+}
+
+/// A reader that fails with [`io::ErrorKind::TimedOut`] once the client stalls
+pub struct StallReader<R> {
+    inner: R,
+    detection: Option<StallDetection>,
+    window_start: Option<Instant>,
+    window_bytes: u64,
+    monitor: StallMonitor,
+}
+
+impl<R> StallReader<R> {
+    /// Wrap `inner`, detecting stalls according to `detection`, or not at all if it is `None`
+    pub fn new(inner: R, detection: Option<StallDetection>) -> Self {
+        Self {
+            inner,
+            detection,
+            window_start: None,
+            window_bytes: 0,
+            monitor: StallMonitor::default(),
+        }
+    }
+
+    /// A handle to check for stalls once the reader was moved elsewhere
+    pub fn monitor(&self) -> StallMonitor {
+        self.monitor.clone()
+    }
+
+    fn record(&mut self, bytes: usize) -> io::Result<()> {
+        let Some(detection) = self.detection else {
+            return Ok(());
+        };
+        if let Some(stalled) = self.monitor.stalled() {
+            return Err(io::Error::new(io::ErrorKind::TimedOut, stalled.clone()));
+        }
+        if bytes == 0 {
+            return Ok(());
+        }
+        let now = Instant::now();
+        let start = *self.window_start.get_or_insert(now);
+        self.window_bytes += bytes as u64;
+        if now.duration_since(start) < detection.window {
+            return Ok(());
+        }
+        if self.window_bytes < detection.min_bytes {
+            let stalled = Stalled {
+                received: self.window_bytes,
+                min_bytes: detection.min_bytes,
+                window: detection.window,
+            };
+            let _ = self.monitor.0.set(stalled.clone());
+            return Err(io::Error::new(io::ErrorKind::TimedOut, stalled));
+        }
+        self.window_start = Some(now);
+        self.window_bytes = 0;
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for StallReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.record(0)?;
+        let bytes = self.inner.read(buf)?;
+        self.record(bytes)?;
+        Ok(bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns `chunk` bytes per read, sleeping `delay` before each
+    struct Trickle {
+        remaining: usize,
+        chunk: usize,
+        delay: Duration,
+    }
+
+    impl Read for Trickle {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            std::thread::sleep(self.delay);
+            let n = self.remaining.min(self.chunk).min(buf.len());
+            buf[..n].fill(b'x');
+            self.remaining -= n;
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn slow_clients_are_stopped() {
+        let trickle = Trickle {
+            remaining: 1000,
+            chunk: 1,
+            delay: Duration::from_millis(5),
+        };
+        let mut reader = StallReader::new(trickle, Some(StallDetection::new(100, Duration::from_millis(30))));
+        let monitor = reader.monitor();
+        let err = reader.read_to_end(&mut Vec::new()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        let stalled = monitor.stalled().expect("stall was recorded");
+        assert!(stalled.received < 100);
+        assert!(err.to_string().contains("at least 100 are required"), "{err}");
+        assert_eq!(reader.read(&mut [0; 8]).unwrap_err().kind(), io::ErrorKind::TimedOut);
+    }
+
+    #[test]
+    fn fast_clients_and_disabled_detection_pass() {
+        let fast = Trickle {
+            remaining: 1000,
+            chunk: 100,
+            delay: Duration::from_millis(5),
+        };
+        let mut reader = StallReader::new(fast, Some(StallDetection::new(100, Duration::from_millis(20))));
+        let mut out = Vec::new();
+        reader.read_to_end(&mut out).unwrap();
+        assert_eq!(out.len(), 1000);
+
+        let slow = Trickle {
+            remaining: 10,
+            chunk: 1,
+            delay: Duration::from_millis(2),
+        };
+        let mut reader = StallReader::new(slow, None);
+        assert_eq!(reader.read_to_end(&mut Vec::new()).unwrap(), 10);
+        assert!(reader.monitor().stalled().is_none());
+    }
+
+    #[test]
+    fn low_speed_settings() {
+        assert_eq!(
+            StallDetection::from_low_speed(1000, 30),
+            Some(StallDetection::new(30_000, Duration::from_secs(30)))
+        );
+        assert_eq!(StallDetection::from_low_speed(0, 30), None);
+        assert_eq!(StallDetection::from_low_speed(1000, 0), None);
+    }
+}
@@ -1,9 +1,9 @@
 //! Common types and structures used throughout the upload-pack implementation
 
-use bstr::BString;
+use bstr::{BStr, BString, ByteSlice};
 use gix_hash::ObjectId;
 use smallvec::SmallVec;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 
 /// Protocol message constants
 pub mod protocol {
@@ -12,6 +12,7 @@ pub mod protocol {
 
     /// ACK message prefixes
     pub const ACK_PREFIX: &str = "ACK ";
+    pub const ACK_COMMON_SUFFIX: &str = " common";
     pub const ACK_CONTINUE_SUFFIX: &str = " continue";
     pub const ACK_READY_SUFFIX: &str = " ready";
 }
@@ -209,10 +210,14 @@ pub struct ClientCapabilities {
     pub allow_reachable_sha1_in_want: bool,
     /// Deepen capability
     pub deepen_relative: bool,
+    /// Send the pack without waiting for `done` once ready (multi_ack_detailed)
+    pub no_done: bool,
     /// Shallow capability
     pub shallow: bool,
     /// Filter capability with spec
     pub filter: Option<BString>,
+    /// Whether the client asked for the `filter` capability, allowing it to send `filter` lines
+    pub filter_requested: bool,
     /// Session ID for tracing
     pub session_id: Option<BString>,
     /// Agent string
@@ -221,6 +226,130 @@ pub struct ClientCapabilities {
     pub object_format: Option<gix_hash::Kind>,
 }
 
+/// The capabilities in effect for a session, derived in one place from what the client requested
+///
+/// Protocol v0 and v1 clients request capabilities on their first want line, see [`v1()`](Self::v1()), and
+/// protocol v2 clients with the arguments of each `fetch` command, see [`v2()`](Self::v2()). Code deciding what
+/// to send asks the methods here instead of combining the requested capabilities itself.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct NegotiatedCapabilities {
+    client: ClientCapabilities,
+    sideband_all: bool,
+    filter: Option<BString>,
+    packfile_uri_protocols: Vec<BString>,
+}
+
+impl NegotiatedCapabilities {
+    /// The capabilities of a protocol v0 or v1 session whose client requested `client`
+    pub fn v1(client: ClientCapabilities) -> Self {
+        Self {
+            client,
+            sideband_all: false,
+            filter: None,
+            packfile_uri_protocols: Vec::new(),
+        }
+    }
+
+    /// The capabilities of a protocol v2 `fetch` command with `arguments`, the argument lines without values
+    /// mapped to an empty string
+    ///
+    /// Like git, v2 responses always use the 64k side-band once the pack section starts, and filter lines are
+    /// arguments themselves, so clients don't need to request the capability.
+    pub fn v2(arguments: &HashMap<String, String>) -> Self {
+        let has = |name: &str| arguments.contains_key(name);
+        Self {
+            client: ClientCapabilities {
+                thin_pack: has("thin-pack"),
+                side_band: SideBandMode::SideBand64k,
+                ofs_delta: has("ofs-delta"),
+                include_tag: has("include-tag"),
+                no_progress: has("no-progress"),
+                deepen_relative: has("deepen-relative"),
+                filter_requested: true,
+                ..Default::default()
+            },
+            sideband_all: has("sideband-all"),
+            filter: None,
+            packfile_uri_protocols: Vec::new(),
+        }
+    }
+
+    /// The capabilities as requested by the client
+    pub fn client(&self) -> &ClientCapabilities {
+        &self.client
+    }
+
+    /// How acknowledgements are sent during negotiation
+    pub fn multi_ack(&self) -> MultiAckMode {
+        self.client.multi_ack
+    }
+
+    /// Whether the pack is sent without waiting for `done` once the server is ready
+    pub fn no_done(&self) -> bool {
+        self.client.no_done
+    }
+
+    /// The side-band used once the pack section of the response starts
+    pub fn sideband_kind(&self) -> SideBandMode {
+        self.client.side_band
+    }
+
+    /// Whether side-band packets may be used for the whole response, and not just the pack section
+    pub fn sideband_all(&self) -> bool {
+        self.sideband_all
+    }
+
+    /// Whether progress messages should be sent, which needs a side-band to send them on
+    pub fn wants_progress(&self) -> bool {
+        !self.client.no_progress && self.sideband_kind() != SideBandMode::None
+    }
+
+    /// Whether depths of shallow fetches count from the shallow commits of the client instead of the wants
+    pub fn deepen_relative(&self) -> bool {
+        self.client.deepen_relative
+    }
+
+    /// Whether deltas may refer to bases the client has, but that aren't in the pack
+    pub fn thin_pack(&self) -> bool {
+        self.client.thin_pack
+    }
+
+    /// Whether deltas may refer to their base by offset
+    pub fn ofs_delta(&self) -> bool {
+        self.client.ofs_delta
+    }
+
+    /// Whether annotated tags pointing to sent objects should be sent as well
+    pub fn include_tag(&self) -> bool {
+        self.client.include_tag
+    }
+
+    /// Whether the client may send `filter` lines
+    pub fn filter_allowed(&self) -> bool {
+        self.client.filter_requested
+    }
+
+    /// The filter specification sent by the client, making this a partial clone or fetch
+    pub fn filter(&self) -> Option<&BStr> {
+        self.filter.as_ref().map(|spec| spec.as_bstr())
+    }
+
+    /// Set the filter specification sent by the client, after it was checked to be allowed
+    pub(crate) fn set_filter(&mut self, spec: impl Into<BString>) {
+        self.filter = Some(spec.into());
+    }
+
+    /// The protocols of packfile URIs the client accepts, like `https`, empty unless it sent `packfile-uris`
+    pub fn packfile_uri_protocols(&self) -> &[BString] {
+        &self.packfile_uri_protocols
+    }
+
+    /// Set the protocols of packfile URIs sent by the client, after packfile URIs were checked to be allowed
+    pub(crate) fn set_packfile_uri_protocols(&mut self, protocols: Vec<BString>) {
+        self.packfile_uri_protocols = protocols;
+    }
+}
+
 /// Request from client during negotiation
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum ClientRequest {
@@ -295,6 +424,8 @@ pub enum ServerResponse {
 pub enum AckStatus {
     /// Simple acknowledgment
     Common,
+    /// A common object while the server isn't ready yet (multi_ack_detailed)
+    DetailedCommon,
     /// Ready to receive more (multi-ack mode)
     Continue,
     /// Ready to send pack
@@ -305,7 +436,7 @@ impl AckStatus {
     /// Convert to client-side Acknowledgement when possible
     pub fn to_acknowledgement(self, oid: ObjectId) -> Option<Acknowledgement> {
         match self {
-            AckStatus::Common => Some(Acknowledgement::Common(oid)),
+            AckStatus::Common | AckStatus::DetailedCommon => Some(Acknowledgement::Common(oid)),
             AckStatus::Ready => Some(Acknowledgement::Ready),
             AckStatus::Continue => None, // Continue doesn't map to client-side enum
         }
@@ -327,19 +458,39 @@ pub struct NegotiationState {
     pub done: bool,
     /// Deepen specification
     pub deepen: Option<DeepenSpec>,
+    /// How the shallow boundary of the client changes, computed once the wants are known
+    pub shallow_boundary: crate::services::negotiation::ShallowBoundary,
     /// Filter specification
     pub filter: Option<BString>,
 }
 
+impl NegotiationState {
+    /// Start the next request of a stateful session, keeping what is known about the objects of the client
+    ///
+    /// The common commits, haves and shallow commits of previous requests remain, while the wants, the deepening and
+    /// the filter only apply to the request that sent them, as clients repeat them with each request.
+    pub fn next_round(&mut self) {
+        *self = Self {
+            haves: std::mem::take(&mut self.haves),
+            common: std::mem::take(&mut self.common),
+            shallow: std::mem::take(&mut self.shallow),
+            ..Self::default()
+        };
+    }
+}
+
 /// Specification for deepening shallow clones
 #[derive(Debug, Clone)]
 pub enum DeepenSpec {
     /// Deepen by commit count
     Depth(u32),
-    /// Deepen since timestamp
-    Since(gix_date::Time),
-    /// Deepen excluding refs
-    Not(Vec<BString>),
+    /// Deepen to the commits selected by `deepen-since` and `deepen-not` lines, which may be combined
+    Revisions {
+        /// Leave out the commits older than this timestamp
+        since: Option<gix_date::Time>,
+        /// Leave out the commits reachable from these refs
+        not: Vec<BString>,
+    },
 }
 
 /// Statistics about pack generation
@@ -358,8 +509,8 @@ pub struct PackStats {
 /// Upload pack session context
 #[derive(Debug)]
 pub struct SessionContext {
-    /// Client capabilities
-    pub capabilities: ClientCapabilities,
+    /// Capabilities in effect for this session
+    pub capabilities: NegotiatedCapabilities,
     /// Server capabilities
     pub server_capabilities: Option<ServerCapabilities>,
     /// Negotiation state
@@ -368,6 +519,8 @@ pub struct SessionContext {
     pub protocol_version: ProtocolVersion,
     /// Whether this is a stateless RPC session
     pub stateless_rpc: bool,
+    /// The address of the client, if known
+    pub remote_addr: Option<std::net::SocketAddr>,
     /// Session start time
     pub start_time: std::time::Instant,
     /// Repository being served
@@ -378,16 +531,31 @@ impl SessionContext {
     /// Create a new session context
     pub fn new(repository_path: impl Into<std::path::PathBuf>) -> Self {
         Self {
-            capabilities: ClientCapabilities::default(),
+            capabilities: NegotiatedCapabilities::default(),
             server_capabilities: None,
             negotiation: NegotiationState::default(),
             protocol_version: ProtocolVersion::default(),
             stateless_rpc: false,
+            remote_addr: None,
             start_time: std::time::Instant::now(),
             repository_path: repository_path.into(),
         }
     }
 
+    /// Create the context of a session serving the repository at `repository_path` with `options` over
+    /// `protocol_version`, like [`Server`](crate::Server) does after detecting the version
+    pub fn from_options(
+        repository_path: impl Into<std::path::PathBuf>,
+        options: &crate::ServerOptions,
+        protocol_version: ProtocolVersion,
+    ) -> Self {
+        let mut session = Self::new(repository_path);
+        session.stateless_rpc = options.stateless_rpc;
+        session.remote_addr = options.remote_addr;
+        session.protocol_version = protocol_version;
+        session
+    }
+
     /// Get session duration
     pub fn duration(&self) -> std::time::Duration {
         self.start_time.elapsed()
@@ -399,6 +567,34 @@ mod tests {
     use super::*;
     use gix_packetline::Channel as SideBandChannel;
 
+    #[test]
+    fn negotiated_capabilities_depend_on_the_protocol() {
+        let v1 = NegotiatedCapabilities::v1(ClientCapabilities {
+            multi_ack: MultiAckMode::Detailed,
+            thin_pack: true,
+            ..Default::default()
+        });
+        assert_eq!(v1.multi_ack(), MultiAckMode::Detailed);
+        assert!(v1.thin_pack());
+        assert_eq!(v1.sideband_kind(), SideBandMode::None);
+        assert!(!v1.wants_progress(), "progress needs a side-band");
+        assert!(!v1.filter_allowed());
+
+        let arguments: HashMap<String, String> = ["thin-pack", "no-progress", "sideband-all"]
+            .into_iter()
+            .map(|name| (name.to_owned(), String::new()))
+            .collect();
+        let mut v2 = NegotiatedCapabilities::v2(&arguments);
+        assert!(v2.thin_pack() && !v2.ofs_delta() && !v2.include_tag());
+        assert_eq!(v2.sideband_kind(), SideBandMode::SideBand64k);
+        assert!(v2.sideband_all());
+        assert!(!v2.wants_progress(), "the client asked for no progress");
+        assert!(v2.filter_allowed() && v2.filter().is_none());
+        v2.set_filter("blob:none");
+        assert_eq!(v2.filter(), Some("blob:none".as_bytes().as_bstr()));
+        assert!(NegotiatedCapabilities::v2(&HashMap::new()).wants_progress());
+    }
+
     #[test]
     fn test_sideband_mode_max_data_size() {
         assert_eq!(SideBandMode::None.max_data_size(), None);
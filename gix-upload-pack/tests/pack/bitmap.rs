@@ -0,0 +1,208 @@
+//! Enumerating objects with the reachability bitmaps written by `git repack -b`, compared to native git
+
+use crate::util::{git, pkt};
+use gix_hash::ObjectId;
+use gix_upload_pack::{
+    log::{Level, Log, Logger},
+    services::pack::BitmapIndex,
+    ProtocolVersion, Server, ServerOptions,
+};
+use std::collections::BTreeSet;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+/// Commit a change to a file of its own and to a shared one in a directory, and return the commit
+fn commit(dir: &Path, revision: &str) -> ObjectId {
+    std::fs::create_dir_all(dir.join("dir")).unwrap();
+    std::fs::write(dir.join(revision), format!("{revision}\n")).unwrap();
+    std::fs::write(dir.join("dir/shared"), format!("{revision}\n")).unwrap();
+    git(dir, &["add", "."]);
+    git(dir, &["commit", "--quiet", "-m", revision]);
+    id(&git(dir, &["rev-parse", "HEAD"]))
+}
+
+fn id(hex: &str) -> ObjectId {
+    ObjectId::from_hex(hex.as_bytes()).unwrap()
+}
+
+/// A repository with a history of `count` commits on `main` and a tagged side branch, repacked with a bitmap
+fn repository(dir: &Path, count: usize) -> Vec<ObjectId> {
+    git(dir, &["init", "--quiet", "--initial-branch=main"]);
+    let commits: Vec<_> = (0..count).map(|n| commit(dir, &format!("c{n}"))).collect();
+    git(dir, &["checkout", "--quiet", "-b", "side", "HEAD~2"]);
+    commit(dir, "side");
+    git(dir, &["tag", "-m", "tag", "annotated"]);
+    git(dir, &["checkout", "--quiet", "main"]);
+    git(dir, &["repack", "-a", "-d", "-b", "--quiet"]);
+    commits
+}
+
+/// The objects `git rev-list` lists for `args` using the bitmap
+fn rev_list(dir: &Path, args: &[&str]) -> BTreeSet<ObjectId> {
+    let mut command = vec!["rev-list", "--objects", "--use-bitmap-index"];
+    command.extend_from_slice(args);
+    git(dir, &command)
+        .lines()
+        .map(|line| id(line.split(' ').next().unwrap()))
+        .collect()
+}
+
+fn objects(dir: &Path, wants: &[ObjectId], haves: &[ObjectId]) -> Option<BTreeSet<ObjectId>> {
+    let repo = gix::open(dir).unwrap();
+    let bitmaps = BitmapIndex::open(&repo).unwrap().expect("the repository has a bitmap");
+    assert!(bitmaps.num_bitmaps() > 0);
+    bitmaps
+        .objects(&repo, wants.iter().copied(), haves.iter().copied())
+        .unwrap()
+        .map(|objects| objects.into_iter().collect())
+}
+
+#[test]
+fn objects_between_wants_and_haves_match_native_git() {
+    let tmp = tempfile::tempdir().unwrap();
+    let dir = tmp.path();
+    let commits = repository(dir, 30);
+    let (first, middle, tip) = (commits[0], commits[15], commits[29]);
+    let tag = id(&git(dir, &["rev-parse", "annotated"]));
+    let unknown = id(&"1".repeat(40));
+
+    assert_eq!(objects(dir, &[tip], &[]), Some(rev_list(dir, &[&tip.to_string()])));
+    assert_eq!(
+        objects(dir, &[tip], &[middle, unknown]),
+        Some(rev_list(dir, &[&tip.to_string(), &format!("^{middle}")])),
+        "unknown haves are ignored"
+    );
+    assert_eq!(
+        objects(dir, &[tip, tag], &[first]),
+        Some(rev_list(dir, &[&tip.to_string(), "annotated", &format!("^{first}")])),
+    );
+    assert_eq!(objects(dir, &[middle], &[tip]), Some(BTreeSet::new()));
+}
+
+#[test]
+fn objects_outside_of_the_bitmapped_pack_need_traversal() {
+    let tmp = tempfile::tempdir().unwrap();
+    let dir = tmp.path();
+    let commits = repository(dir, 3);
+    let loose = commit(dir, "loose");
+
+    assert_eq!(objects(dir, &[loose], &[]), None);
+    assert_eq!(objects(dir, &[commits[2]], &[loose]), None);
+    assert!(objects(dir, &[commits[2]], &[commits[0]]).is_some());
+}
+
+#[test]
+fn broken_bitmaps_are_reported() {
+    let tmp = tempfile::tempdir().unwrap();
+    let dir = tmp.path();
+    repository(dir, 3);
+    let pack_dir = dir.join(".git/objects/pack");
+    let bitmap = std::fs::read_dir(&pack_dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .find(|path| path.extension().is_some_and(|extension| extension == "bitmap"))
+        .unwrap();
+    let data = std::fs::read(&bitmap).unwrap();
+    std::fs::write(&bitmap, &data[..data.len() / 2]).unwrap();
+
+    let repo = gix::open(dir).unwrap();
+    assert!(BitmapIndex::open(&repo).is_err());
+}
+
+#[test]
+fn fetches_count_objects_with_the_bitmap() {
+    #[derive(Default, Clone)]
+    struct Capture(Arc<Mutex<Vec<String>>>);
+
+    impl Log for Capture {
+        fn enabled(&self, _level: Level) -> bool {
+            true
+        }
+
+        fn log(&self, _level: Level, message: std::fmt::Arguments<'_>) {
+            self.0.lock().unwrap().push(message.to_string());
+        }
+    }
+
+    let tmp = tempfile::tempdir().unwrap();
+    let dir = tmp.path();
+    let commits = repository(dir, 10);
+    let (middle, tip) = (commits[5], commits[9]);
+    let request = pkt("command=fetch\n")
+        + &pkt("object-format=sha1\n")
+        + "0001"
+        + &pkt("no-progress\n")
+        + &pkt(&format!("want {tip}\n"))
+        + &pkt(&format!("have {middle}\n"))
+        + &pkt("done\n")
+        + "0000";
+    let fetch = || {
+        let capture = Capture::default();
+        let options = ServerOptions::default()
+            .with_stateless_rpc(true)
+            .with_repository_overrides(false)
+            .with_protocol(ProtocolVersion::V2)
+            .with_logger(Logger::new(capture.clone()));
+        let mut output = Vec::new();
+        Server::new(dir, options)
+            .unwrap()
+            .serve(request.as_bytes(), &mut output)
+            .unwrap();
+        let pack = output.windows(4).position(|window| window == b"PACK").expect("a pack");
+        let objects = u32::from_be_bytes(output[pack + 8..pack + 12].try_into().unwrap());
+        let messages = capture.0.lock().unwrap().clone();
+        (
+            objects as usize,
+            messages.iter().any(|message| message.contains("bitmaps took")),
+        )
+    };
+
+    let expected = rev_list(dir, &[&tip.to_string(), &format!("^{middle}")]).len();
+    assert_eq!(fetch(), (expected, true));
+    git(dir, &["config", "pack.useBitmaps", "false"]);
+    assert_eq!(fetch(), (expected, false), "bitmaps can be turned off");
+}
+
+#[test]
+fn native_clients_clone_and_fetch_from_bitmapped_repositories() {
+    let tmp = tempfile::tempdir().unwrap();
+    let upstream = tmp.path().join("upstream");
+    std::fs::create_dir(&upstream).unwrap();
+    repository(&upstream, 10);
+    let url = format!("file://{}", upstream.display());
+    let upload_pack = assert_cmd::cargo::cargo_bin("gix-upload-pack");
+    let upload_pack = upload_pack.to_str().unwrap();
+
+    for version in ["0", "2"] {
+        let protocol = format!("protocol.version={version}");
+        let name = format!("clone-v{version}");
+        git(
+            tmp.path(),
+            &[
+                "-c",
+                &protocol,
+                "clone",
+                "--quiet",
+                "--upload-pack",
+                upload_pack,
+                &url,
+                &name,
+            ],
+        );
+        let clone = tmp.path().join(&name);
+        git(&clone, &["fsck", "--no-progress"]);
+
+        commit(&upstream, &format!("new-v{version}"));
+        git(&upstream, &["repack", "-a", "-d", "-b", "--quiet"]);
+        git(
+            &clone,
+            &["-c", &protocol, "fetch", "--quiet", "--upload-pack", upload_pack],
+        );
+        git(&clone, &["fsck", "--no-progress"]);
+        assert_eq!(
+            git(&clone, &["rev-parse", "origin/main"]),
+            git(&upstream, &["rev-parse", "main"]),
+            "v{version}"
+        );
+    }
+}
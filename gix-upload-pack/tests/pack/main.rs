@@ -0,0 +1,19 @@
+//! The objects sent to clients and how their packs are generated
+
+#[path = "../util/mod.rs"]
+mod util;
+
+mod bitmap;
+mod missing_objects;
+mod object_caches;
+mod object_existence;
+mod object_filter;
+mod object_firewall;
+mod pack_estimate;
+mod pack_objects_hook;
+mod pack_reuse;
+mod pack_streaming;
+mod pack_verification;
+mod packfile_uris;
+mod tree_filter;
+mod v1_filter;
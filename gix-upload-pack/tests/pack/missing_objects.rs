@@ -0,0 +1,120 @@
+//! Wants that are missing, or lead to missing objects, are caught before counting
+
+use crate::util::{git, pkt};
+use gix_upload_pack::{config::MissingObjectPolicy, Error, ProtocolVersion, Server, ServerOptions};
+use std::path::Path;
+
+fn fetch(wants: &[&str]) -> String {
+    let mut request = pkt("command=fetch\n") + "0001" + &pkt("no-progress\n");
+    for want in wants {
+        request += &pkt(&format!("want {want}\n"));
+    }
+    request + &pkt("done\n") + "0000"
+}
+
+fn serve(dir: &Path, options: ServerOptions, request: String) -> Result<Vec<u8>, Error> {
+    let mut server = Server::new(
+        dir,
+        options
+            .with_repository_overrides(false)
+            .with_protocol(ProtocolVersion::V2),
+    )?;
+    let mut output = Vec::new();
+    server.serve(request.as_bytes(), &mut output)?;
+    Ok(output)
+}
+
+/// The number of objects announced in the header of the pack within `response`
+fn pack_entries(response: &[u8]) -> u32 {
+    let start = response
+        .windows(4)
+        .position(|window| window == b"PACK")
+        .expect("response contains a pack");
+    u32::from_be_bytes(response[start + 8..start + 12].try_into().unwrap())
+}
+
+/// Create a commit of a single file whose tree is then deleted, returning the commit and its tree
+fn commit_without_tree(dir: &Path) -> (String, String) {
+    std::fs::write(dir.join("detached"), "detached\n").unwrap();
+    git(dir, &["add", "detached"]);
+    let tree = git(dir, &["write-tree"]);
+    let commit = git(dir, &["commit-tree", &tree, "-m", "detached"]);
+    git(dir, &["rm", "--quiet", "--cached", "detached"]);
+    std::fs::remove_file(dir.join(".git/objects").join(&tree[..2]).join(&tree[2..])).unwrap();
+    (commit, tree)
+}
+
+fn any_want() -> ServerOptions {
+    ServerOptions {
+        allow_any_sha1_in_want: true,
+        ..Default::default()
+    }
+}
+
+#[test]
+fn unreachable_objects_that_exist_are_sent() {
+    let tmp = tempfile::tempdir().unwrap();
+    let dir = tmp.path();
+    git(dir, &["init", "--quiet", "--initial-branch=main"]);
+    std::fs::write(dir.join("dangling"), "not referenced by any commit\n").unwrap();
+    let blob = git(dir, &["hash-object", "-w", "dangling"]);
+
+    let output = serve(dir, any_want(), fetch(&[&blob])).unwrap();
+    assert_eq!(pack_entries(&output), 1);
+}
+
+#[test]
+fn missing_objects_fail_the_request_with_all_ids() {
+    let tmp = tempfile::tempdir().unwrap();
+    let dir = tmp.path();
+    git(dir, &["init", "--quiet", "--initial-branch=main"]);
+    let (commit, tree) = commit_without_tree(dir);
+    let unknown = "1111111111111111111111111111111111111111";
+
+    let err = serve(dir, any_want(), fetch(&[unknown, &commit])).unwrap_err();
+    let Error::MissingObjects(missing) = &err else {
+        panic!("expected missing objects, got {err}");
+    };
+    assert_eq!(missing.len(), 2);
+    let message = err.to_string();
+    assert!(message.contains(unknown), "{message}");
+    assert!(
+        message.contains(&format!("{tree} (tree needed by want {commit})")),
+        "{message}"
+    );
+    assert!(err.is_client_error());
+}
+
+#[test]
+fn missing_objects_can_be_skipped() {
+    let tmp = tempfile::tempdir().unwrap();
+    let dir = tmp.path();
+    git(dir, &["init", "--quiet", "--initial-branch=main"]);
+    let (commit, _tree) = commit_without_tree(dir);
+    std::fs::write(dir.join("file"), "content\n").unwrap();
+    git(dir, &["add", "file"]);
+    git(dir, &["commit", "--quiet", "-m", "present"]);
+    let present = git(dir, &["rev-parse", "HEAD"]);
+    let unknown = "1111111111111111111111111111111111111111";
+
+    let options = any_want().with_missing_objects(MissingObjectPolicy::Skip);
+    let output = serve(dir, options, fetch(&[unknown, &commit, &present])).unwrap();
+    assert_eq!(pack_entries(&output), 3, "commit, tree and blob of the present want");
+}
+
+#[test]
+fn the_policy_is_configurable() {
+    let tmp = tempfile::tempdir().unwrap();
+    let dir = tmp.path();
+    git(dir, &["init", "--quiet", "--initial-branch=main"]);
+
+    git(dir, &["config", "uploadpack.missingObjects", "skip"]);
+    let repo = gix::open(dir).unwrap();
+    let options = ServerOptions::from_repository(&repo).unwrap();
+    assert_eq!(options.missing_objects, MissingObjectPolicy::Skip);
+
+    git(dir, &["config", "uploadpack.missingObjects", "ignore"]);
+    let repo = gix::open(dir).unwrap();
+    let err = ServerOptions::from_repository(&repo).unwrap_err();
+    assert!(matches!(err, Error::Config { .. }), "{err}");
+}
@@ -0,0 +1,94 @@
+//! Each session sets up the object caches configured for it, which don't change what is sent
+
+use crate::util::{extract_pack, git, index_pack, pkt};
+use gix_hash::ObjectId;
+use gix_upload_pack::config::ObjectCaches;
+use gix_upload_pack::{server::Step, ProtocolVersion, Server, ServerOptions};
+use std::collections::HashSet;
+use std::path::Path;
+
+/// A repository whose file changes a little in each commit, packed so that its revisions are stored as deltas
+fn repository(dir: &Path) {
+    git(dir, &["init", "--quiet", "--initial-branch=main"]);
+    let mut content: String = (0..200).map(|line| format!("line {line}\n")).collect();
+    for revision in 1..=12 {
+        content.push_str(&format!("revision {revision}\n"));
+        std::fs::write(dir.join("file"), &content).unwrap();
+        git(dir, &["add", "file"]);
+        git(dir, &["commit", "--quiet", "-m", &format!("revision {revision}")]);
+    }
+    git(dir, &["repack", "-adfq", "--depth=50"]);
+}
+
+/// Fetch `head` with protocol v2 in a new session of `server` and return the received pack
+fn fetch(server: &mut Server, head: &str) -> Vec<u8> {
+    let request = format!(
+        "{}0001{}{}0000",
+        pkt("command=fetch\n"),
+        pkt(&format!("want {head}\n")),
+        pkt("done\n")
+    );
+    let mut session = server.step_session(ProtocolVersion::V2).unwrap();
+    session.push_input(request.as_bytes());
+    session.finish_input();
+    let mut output = Vec::new();
+    while session.serve_step(&mut output).unwrap() != Step::Done {}
+    extract_pack(&output)
+}
+
+#[test]
+fn packs_are_the_same_with_any_cache_sizes() {
+    let tmp = tempfile::tempdir().unwrap();
+    repository(tmp.path());
+    let head = git(tmp.path(), &["rev-parse", "HEAD"]);
+    let expected: HashSet<ObjectId> = git(tmp.path(), &["rev-list", "--objects", "--all"])
+        .lines()
+        .map(|line| ObjectId::from_hex(line.split_whitespace().next().unwrap().as_bytes()).unwrap())
+        .collect();
+
+    for caches in [
+        ObjectCaches::NONE,
+        ObjectCaches::NONE.with_delta_cache(1024).with_object_cache(1024),
+        ObjectCaches::for_sessions(1),
+    ] {
+        let options = ServerOptions::default()
+            .with_stateless_rpc(true)
+            .with_repository_overrides(false)
+            .with_object_caches(caches);
+        let mut server = Server::new(tmp.path(), options).unwrap();
+        for _ in 0..2 {
+            let received = index_pack(tmp.path(), &fetch(&mut server, &head));
+            assert_eq!(received, expected, "{caches:?}");
+        }
+    }
+}
+
+#[test]
+fn caches_are_derived_from_concurrent_sessions_unless_configured() {
+    let options = ServerOptions::default();
+    assert_eq!(
+        options.object_caches(),
+        ObjectCaches::for_sessions(options.max_concurrent_sessions)
+    );
+    let options = options.with_max_concurrent_sessions(64);
+    assert_eq!(options.object_caches(), ObjectCaches::for_sessions(64));
+    assert!(options.object_caches().delta_cache_bytes < ObjectCaches::for_sessions(8).delta_cache_bytes);
+    assert_eq!(
+        options.with_object_caches(ObjectCaches::NONE).object_caches(),
+        ObjectCaches::NONE
+    );
+    assert!(ServerOptions::default()
+        .with_max_concurrent_sessions(0)
+        .validate()
+        .is_err());
+
+    let tmp = tempfile::tempdir().unwrap();
+    git(tmp.path(), &["init", "--quiet"]);
+    git(tmp.path(), &["config", "core.deltaBaseCacheLimit", "2m"]);
+    let repository = gix::open(tmp.path()).unwrap();
+    let options = ServerOptions::from_repository(&repository).unwrap();
+    assert_eq!(
+        options.object_caches(),
+        ObjectCaches::for_sessions(options.max_concurrent_sessions).with_delta_cache(2 * 1024 * 1024)
+    );
+}
@@ -0,0 +1,57 @@
+//! Objects are checked for existence in batches, whether they are packed, loose or in alternates
+
+use crate::util::git;
+use gix_hash::ObjectId;
+use gix_upload_pack::services::pack::find_existing;
+use std::collections::HashSet;
+use std::path::Path;
+
+fn commit(dir: &Path, message: &str) -> ObjectId {
+    git(dir, &["commit", "--quiet", "--allow-empty", "-m", message]);
+    ObjectId::from_hex(git(dir, &["rev-parse", "HEAD"]).as_bytes()).unwrap()
+}
+
+#[test]
+fn packed_loose_and_alternate_objects_are_found() {
+    let tmp = tempfile::tempdir().unwrap();
+    let base = tmp.path().join("base");
+    std::fs::create_dir(&base).unwrap();
+    git(&base, &["init", "--quiet"]);
+    let alternate = commit(&base, "in alternate");
+
+    let repo = tmp.path().join("repo");
+    git(tmp.path(), &["clone", "--quiet", "--shared", "base", "repo"]);
+    let packed = commit(&repo, "packed");
+    git(&repo, &["repack", "-dq"]);
+    let loose = commit(&repo, "loose");
+    let missing = ObjectId::from_hex(b"1111111111111111111111111111111111111111").unwrap();
+    let missing_in_loose_directory = {
+        let mut hex = loose.to_string();
+        hex.replace_range(39.., if hex.ends_with('0') { "1" } else { "0" });
+        ObjectId::from_hex(hex.as_bytes()).unwrap()
+    };
+
+    let repository = gix::open(&repo).unwrap();
+    let ids = [packed, loose, alternate, missing, missing_in_loose_directory, packed];
+    assert_eq!(
+        find_existing(&repository, &ids).unwrap(),
+        HashSet::from([packed, loose, alternate])
+    );
+    assert!(find_existing(&repository, &[]).unwrap().is_empty());
+}
+
+#[test]
+fn objects_packed_after_the_indexes_were_loaded_are_found() {
+    let tmp = tempfile::tempdir().unwrap();
+    git(tmp.path(), &["init", "--quiet"]);
+    let first = commit(tmp.path(), "first");
+    let repository = gix::open(tmp.path()).unwrap();
+    assert_eq!(find_existing(&repository, &[first]).unwrap(), HashSet::from([first]));
+
+    let second = commit(tmp.path(), "second");
+    git(tmp.path(), &["repack", "-adq"]);
+    assert_eq!(
+        find_existing(&repository, &[first, second]).unwrap(),
+        HashSet::from([first, second])
+    );
+}
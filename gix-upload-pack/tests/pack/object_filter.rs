@@ -0,0 +1,108 @@
+//! Fetches with a filter receive the objects `git rev-list --filter` lists for it
+#![cfg(feature = "serve-core")]
+
+use crate::util::{fetch_filtered, git, index_pack};
+use gix_hash::ObjectId;
+use gix_upload_pack::{Error, ServerOptions};
+use std::collections::HashSet;
+use std::path::Path;
+
+/// A repository with small and large files at several depths, sparse-checkout patterns and an annotated tag of a tree
+fn repository(dir: &Path) {
+    git(dir, &["init", "--quiet", "--initial-branch=main"]);
+    for revision in 1..=3 {
+        std::fs::create_dir_all(dir.join("docs/api/v1")).unwrap();
+        std::fs::create_dir_all(dir.join("src/nested")).unwrap();
+        std::fs::write(dir.join("README"), format!("revision {revision}\n")).unwrap();
+        std::fs::write(dir.join("large.bin"), format!("large {revision}\n").repeat(200)).unwrap();
+        std::fs::write(dir.join("docs/guide.md"), format!("guide {revision}\n")).unwrap();
+        std::fs::write(dir.join("docs/api/v1/index.md"), format!("api {revision}\n")).unwrap();
+        std::fs::write(dir.join("src/lib.rs"), format!("// lib {revision}\n")).unwrap();
+        std::fs::write(dir.join("src/nested/mod.rs"), format!("// mod {revision}\n")).unwrap();
+        std::fs::write(dir.join(".sparse"), "/docs/\n!/docs/api/\n/src/lib.rs\n").unwrap();
+        git(dir, &["add", "."]);
+        git(dir, &["commit", "--quiet", "-m", &format!("revision {revision}")]);
+    }
+    git(dir, &["tag", "-a", "-m", "the sources", "sources", "HEAD:src"]);
+}
+
+/// Fetch `wants` with `filter`, telling the server about `haves`, and return the received objects
+fn fetch(dir: &Path, wants: &[&str], haves: &[&str], filter: &str) -> Result<HashSet<ObjectId>, Error> {
+    let options = ServerOptions::default().with_allowed_filters(vec![
+        "blob:limit=1m".into(),
+        "tree:8".into(),
+        "sparse:oid=".into(),
+        "object:type=blob".into(),
+        "object:type=tree".into(),
+    ]);
+    Ok(index_pack(dir, &fetch_filtered(dir, options, wants, haves, filter)?))
+}
+
+/// The objects `git rev-list` lists for `args` with `filter`
+fn native_objects(dir: &Path, filter: &str, args: &[&str]) -> HashSet<ObjectId> {
+    let filter = format!("--filter={filter}");
+    let mut rev_list = vec!["rev-list", "--objects", &filter];
+    rev_list.extend_from_slice(args);
+    git(dir, &rev_list)
+        .lines()
+        .map(|line| ObjectId::from_hex(line.split_whitespace().next().unwrap().as_bytes()).unwrap())
+        .collect()
+}
+
+#[test]
+fn filters_omit_what_git_omits() {
+    let tmp = tempfile::tempdir().unwrap();
+    repository(tmp.path());
+    let head = git(tmp.path(), &["rev-parse", "HEAD"]);
+    let all = native_objects(tmp.path(), "blob:limit=1m", &[&head]);
+
+    for filter in [
+        "blob:none",
+        "blob:limit=100",
+        "tree:1",
+        "tree:2",
+        "tree:3",
+        "sparse:oid=main:.sparse",
+        "object:type=tree",
+        "combine:blob:limit=100+tree:2",
+    ] {
+        let received = fetch(tmp.path(), &[&head], &[], filter).unwrap();
+        let expected = native_objects(tmp.path(), filter, &[&head]);
+        assert!(received.len() < all.len(), "{filter}: objects are omitted");
+        assert_eq!(received, expected, "{filter}");
+    }
+}
+
+#[test]
+fn filters_apply_to_fetches_and_tagged_trees() {
+    let tmp = tempfile::tempdir().unwrap();
+    repository(tmp.path());
+    let head = git(tmp.path(), &["rev-parse", "HEAD"]);
+    let first = git(tmp.path(), &["rev-parse", "HEAD~2"]);
+    let received = fetch(tmp.path(), &[&head], &[&first], "blob:limit=100").unwrap();
+    assert_eq!(
+        received,
+        native_objects(tmp.path(), "blob:limit=100", &[&head, &format!("^{first}")])
+    );
+
+    let tag = git(tmp.path(), &["rev-parse", "sources"]);
+    let received = fetch(tmp.path(), &[&tag], &[], "tree:1").unwrap();
+    assert_eq!(received, native_objects(tmp.path(), "tree:1", &[&tag]));
+    assert_eq!(
+        received.len(),
+        4,
+        "the tag, its tree and the entries of the tree, which are at depth 0"
+    );
+}
+
+#[test]
+fn sparse_filters_need_a_blob() {
+    let tmp = tempfile::tempdir().unwrap();
+    repository(tmp.path());
+    let head = git(tmp.path(), &["rev-parse", "HEAD"]);
+    let err = fetch(tmp.path(), &[&head], &[], "sparse:oid=main:missing").unwrap_err();
+    assert!(
+        matches!(&err, Error::InvalidFilter { message } if message == "unable to access sparse blob in 'main:missing'"),
+        "{err}"
+    );
+}
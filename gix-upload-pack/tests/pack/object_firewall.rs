@@ -0,0 +1,125 @@
+//! Objects excluded by an `ObjectFirewall` are left out of packs, which stay valid, or the fetch is refused
+
+use crate::util::{extract_pack, git, pkt};
+use gix_hash::ObjectId;
+use gix_upload_pack::{Error, ProtocolVersion, Server, ServerOptions};
+use std::collections::HashSet;
+use std::path::Path;
+
+/// A repository with three revisions of a file, packed so that some of them are stored as deltas
+fn repository(dir: &Path) -> String {
+    git(dir, &["init", "--quiet"]);
+    for revision in 1..=3 {
+        let content: String = (0..revision * 200).map(|line| format!("line {line}\n")).collect();
+        std::fs::write(dir.join("file"), content).unwrap();
+        git(dir, &["add", "file"]);
+        git(dir, &["commit", "--quiet", "-m", &format!("revision {revision}")]);
+    }
+    git(dir, &["repack", "-adfq"]);
+    git(dir, &["rev-parse", "HEAD"])
+}
+
+/// Return the object ids of the blobs in the repository at `dir` along with the id of their delta base, if any
+fn blobs(dir: &Path) -> Vec<(ObjectId, Option<ObjectId>)> {
+    let packs = dir.join(".git/objects/pack");
+    let index = std::fs::read_dir(&packs)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .find(|path| path.extension().is_some_and(|ext| ext == "idx"))
+        .expect("repository is packed");
+    git(dir, &["verify-pack", "-v", index.to_str().unwrap()])
+        .lines()
+        .map(|line| line.split_whitespace().collect::<Vec<_>>())
+        .filter(|fields| fields.get(1) == Some(&"blob"))
+        .map(|fields| {
+            let id = ObjectId::from_hex(fields[0].as_bytes()).unwrap();
+            (
+                id,
+                fields.get(6).map(|base| ObjectId::from_hex(base.as_bytes()).unwrap()),
+            )
+        })
+        .collect()
+}
+
+/// Fetch `head` with protocol v2 as a thin-pack capable client, optionally asking for `filter`
+fn fetch(dir: &Path, options: ServerOptions, head: &str, filter: Option<&str>) -> Result<Vec<u8>, Error> {
+    let mut request = format!(
+        "{}0001{}{}",
+        pkt("command=fetch\n"),
+        pkt("thin-pack\n"),
+        pkt(&format!("want {head}\n"))
+    );
+    if let Some(filter) = filter {
+        request += &pkt(&format!("filter {filter}\n"));
+    }
+    request += &format!("{}0000", pkt("done\n"));
+
+    let options = options.with_stateless_rpc(true).with_repository_overrides(false);
+    let mut server = Server::new(dir, options).unwrap();
+    let mut session = server.step_session(ProtocolVersion::V2)?;
+    session.push_input(request.as_bytes());
+    session.finish_input();
+    let mut output = Vec::new();
+    while session.serve_step(&mut output)? != gix_upload_pack::server::Step::Done {}
+    Ok(output)
+}
+
+/// Index `pack` like a client would, failing on deltas against objects it doesn't contain, and return its objects
+fn index_pack(dir: &Path, pack: &[u8]) -> HashSet<ObjectId> {
+    let path = dir.join("received.pack");
+    std::fs::write(&path, pack).unwrap();
+    git(dir, &["index-pack", "received.pack"]);
+    git(dir, &["verify-pack", "-v", "received.idx"])
+        .lines()
+        .filter_map(|line| ObjectId::from_hex(line.split_whitespace().next()?.as_bytes()).ok())
+        .collect()
+}
+
+#[test]
+fn excluded_delta_bases_leave_a_valid_pack() {
+    let tmp = tempfile::tempdir().unwrap();
+    let repo = tmp.path().join("repo");
+    std::fs::create_dir(&repo).unwrap();
+    let head = repository(&repo);
+    let blobs = blobs(&repo);
+    let bases: HashSet<_> = blobs.iter().filter_map(|(_, base)| *base).collect();
+    assert!(!bases.is_empty(), "the fixture stores blobs as deltas");
+
+    for excluded in bases {
+        // A partial clone whose filter keeps all blobs of the fixture
+        let options = ServerOptions::default()
+            .with_allowed_filters(vec!["blob:limit=1m".into()])
+            .with_object_firewall(HashSet::from([excluded]));
+        let output = fetch(&repo, options, &head, Some("blob:limit=1m")).unwrap();
+        let received = index_pack(tmp.path(), &extract_pack(&output));
+        assert!(!received.contains(&excluded), "{excluded} is left out");
+        for (blob, _) in blobs.iter().filter(|(blob, _)| *blob != excluded) {
+            assert!(received.contains(blob), "{blob} is sent in full, without its base");
+        }
+    }
+}
+
+#[test]
+fn fetches_that_need_excluded_objects_are_refused() {
+    let tmp = tempfile::tempdir().unwrap();
+    let head = repository(tmp.path());
+    let blob = blobs(tmp.path())[0].0;
+    let head_id = ObjectId::from_hex(head.as_bytes()).unwrap();
+
+    let options = ServerOptions::default().with_object_firewall(move |id: &gix_hash::oid| id == blob);
+    let err = fetch(tmp.path(), options, &head, None).unwrap_err();
+    assert!(
+        matches!(&err, Error::ExcludedObjects(objects) if objects.len() == 1 && objects[0].id == blob),
+        "full clones can't do without blobs: {err}"
+    );
+
+    let options = ServerOptions::default().with_object_firewall(HashSet::from([head_id]));
+    let err = fetch(tmp.path(), options, &head, Some("blob:none")).unwrap_err();
+    let Error::ExcludedObjects(objects) = &err else {
+        panic!("commits can't be left out: {err}");
+    };
+    assert_eq!(objects[0].id, head_id);
+    assert!(objects[0].want);
+    assert_eq!(objects[0].kind, gix_object::Kind::Commit);
+    assert!(err.to_string().contains("wanted commit"), "{err}");
+}
@@ -0,0 +1,60 @@
+//! Packs can be estimated before they are generated, and generated from the estimate
+
+use crate::util::git;
+use gix_upload_pack::services::pack::PackGenerator;
+use gix_upload_pack::services::packet_io::EnhancedPacketWriter;
+use gix_upload_pack::{ServerOptions, SessionContext, SideBandMode};
+use std::path::Path;
+
+fn commit(dir: &Path, revision: usize) -> String {
+    let content: String = (0..revision * 200).map(|line| format!("line {line}\n")).collect();
+    std::fs::write(dir.join("file"), content).unwrap();
+    git(dir, &["add", "file"]);
+    git(dir, &["commit", "--quiet", "-m", &format!("revision {revision}")]);
+    git(dir, &["rev-parse", "HEAD"])
+}
+
+#[test]
+fn the_estimate_counts_the_objects_of_the_generated_pack() {
+    let tmp = tempfile::tempdir().unwrap();
+    git(tmp.path(), &["init", "--quiet"]);
+    let first = commit(tmp.path(), 1);
+    commit(tmp.path(), 2);
+    git(tmp.path(), &["repack", "-adq"]);
+    let head = commit(tmp.path(), 3);
+
+    let repository = gix::open(tmp.path()).unwrap();
+    let options = ServerOptions::default();
+    let generator = PackGenerator::new(&repository, &options);
+    let mut session = SessionContext::new(tmp.path());
+    session.negotiation.wants.insert(head.parse().unwrap());
+
+    let estimate = generator.estimate(&session).unwrap();
+    assert_eq!(
+        estimate.objects, 9,
+        "three commits, trees and blobs, both packed and loose"
+    );
+
+    let mut pack = Vec::new();
+    let mut writer = EnhancedPacketWriter::new(&mut pack, SideBandMode::None);
+    let stats = generator
+        .generate_pack_from_estimate(&mut writer, &session, estimate.clone())
+        .unwrap();
+    assert_eq!(stats.object_count as usize, estimate.objects);
+    assert_eq!(&pack[..4], b"PACK");
+    assert_eq!(u32::from_be_bytes(pack[8..12].try_into().unwrap()), 9);
+    assert!(
+        estimate.approx_bytes >= stats.pack_size / 2 && estimate.approx_bytes <= stats.pack_size * 2,
+        "{} bytes estimated for a pack of {} bytes",
+        estimate.approx_bytes,
+        stats.pack_size
+    );
+
+    session.negotiation.haves.insert(first.parse().unwrap());
+    let estimate = generator.estimate(&session).unwrap();
+    assert_eq!(estimate.objects, 6, "the objects of the first commit are excluded");
+
+    session.negotiation.wants.clear();
+    let estimate = generator.estimate(&session).unwrap();
+    assert_eq!((estimate.objects, estimate.approx_bytes), (0, 32), "an empty pack");
+}
@@ -0,0 +1,105 @@
+//! `uploadpack.packObjectsHook` generates packs with the command line and input of `git pack-objects`
+#![cfg(all(feature = "serve-core", unix))]
+
+use crate::util::{git, pkt};
+use gix_serve_core::protocol::ProtocolVersion;
+use gix_serve_core::service::{GitService, ServiceContext};
+use gix_serve_core::testing::SidebandDemux;
+use gix_upload_pack::{Server, ServerOptions};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+/// Write an executable script with `body` to `dir`
+fn hook(dir: &Path, body: &str) -> PathBuf {
+    let path = dir.join("hook.sh");
+    std::fs::write(&path, format!("#!/bin/sh\n{body}")).unwrap();
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+    path
+}
+
+/// Fetch the head of a new repository in `dir` through `hook`, returning the head and the response
+fn fetch(dir: &Path, hook: PathBuf) -> (String, Result<Vec<u8>, gix_serve_core::service::Error>) {
+    let repo = dir.join("repo");
+    std::fs::create_dir(&repo).unwrap();
+    git(&repo, &["init", "--quiet"]);
+    git(&repo, &["commit", "--quiet", "--allow-empty", "-m", "initial"]);
+    let head = git(&repo, &["rev-parse", "HEAD"]);
+    let options = ServerOptions {
+        pack_objects_hook: Some(hook),
+        ..ServerOptions::default().with_repository_overrides(false)
+    };
+    let mut server = Server::new(&repo, options).unwrap();
+    let request = pkt("command=fetch\n")
+        + "0001"
+        + &pkt("ofs-delta\n")
+        + &pkt(&format!("want {head}\n"))
+        + &pkt("done\n")
+        + "0000";
+    let ctx = ServiceContext::new(ProtocolVersion::V2).with_stateless(true);
+    let mut response = Vec::new();
+    let result = GitService::serve(&mut server, &mut request.as_bytes(), &mut response, &ctx);
+    (head, result.map(|_| response))
+}
+
+#[test]
+fn hooks_generate_the_pack() {
+    let tmp = tempfile::tempdir().unwrap();
+    let log = tmp.path().join("log");
+    let hook = hook(
+        tmp.path(),
+        &format!(
+            "printf '%s\\n' \"$@\" >{log}.args\ntee {log}.stdin | \"$@\"\necho 'from the hook' >&2\n",
+            log = log.display()
+        ),
+    );
+    let (head, response) = fetch(tmp.path(), hook);
+    let response = SidebandDemux::default().demux(&response.unwrap());
+
+    assert_eq!(response.lines, [b"packfile\n".to_vec()]);
+    let pack = response.pack().expect("the hook's pack is sent");
+    assert_eq!(&pack[8..12], &2u32.to_be_bytes(), "the commit and its empty tree");
+    assert_eq!(response.progress.last().map(String::as_str), Some("from the hook"));
+    assert_eq!(response.flushes, 1);
+    assert_eq!(
+        std::fs::read_to_string(log.with_extension("args")).unwrap(),
+        "git\npack-objects\n--revs\n--stdout\n--progress\n--delta-base-offset\n"
+    );
+    assert_eq!(
+        std::fs::read_to_string(log.with_extension("stdin")).unwrap(),
+        format!("{head}\n--not\n\n")
+    );
+}
+
+#[test]
+fn failing_hooks_fail_the_request() {
+    let tmp = tempfile::tempdir().unwrap();
+    let (_, response) = fetch(tmp.path(), hook(tmp.path(), "exit 3\n"));
+    let err = response.unwrap_err();
+    assert!(
+        err.to_string().contains("hook.sh\" failed with exit status: 3"),
+        "{err}"
+    );
+}
+
+#[test]
+fn hooks_are_only_read_from_protected_configuration() {
+    let tmp = tempfile::tempdir().unwrap();
+    git(tmp.path(), &["init", "--quiet", "--bare"]);
+    git(tmp.path(), &["config", "uploadpack.packObjectsHook", "/usr/bin/cat"]);
+    let repo = gix::open(tmp.path()).unwrap();
+    assert_eq!(
+        ServerOptions::from_repository(&repo).unwrap().pack_objects_hook,
+        None,
+        "the served repository can't run programs"
+    );
+
+    let repo = gix::open_opts(
+        tmp.path(),
+        gix::open::Options::default().config_overrides(["uploadpack.packObjectsHook=/usr/bin/env"]),
+    )
+    .unwrap();
+    assert_eq!(
+        ServerOptions::from_repository(&repo).unwrap().pack_objects_hook,
+        Some(PathBuf::from("/usr/bin/env"))
+    );
+}
@@ -0,0 +1,177 @@
+//! Copying the leading objects of a pack verbatim, compared to native git's pack reuse
+
+use crate::util::{git, git_command, pkt};
+use gix_hash::ObjectId;
+use gix_upload_pack::{ProtocolVersion, Server, ServerOptions};
+use std::collections::BTreeSet;
+use std::path::Path;
+
+/// A repository with a growing file stored as deltas and a file per commit, repacked with a bitmap, followed by a
+/// loose commit, returning the commits
+fn repository(dir: &Path) -> Vec<String> {
+    git(dir, &["init", "--quiet", "--initial-branch=main"]);
+    let mut commits = Vec::new();
+    for revision in 1..=20 {
+        let content: String = (0..revision * 100).map(|line| format!("line {line}\n")).collect();
+        std::fs::write(dir.join("file"), content).unwrap();
+        std::fs::write(dir.join(format!("file-{revision}")), format!("{revision}\n")).unwrap();
+        git(dir, &["add", "."]);
+        git(dir, &["commit", "--quiet", "-m", &format!("revision {revision}")]);
+        commits.push(git(dir, &["rev-parse", "HEAD"]));
+        if revision == 19 {
+            git(dir, &["repack", "-a", "-d", "-b", "--quiet"]);
+        }
+    }
+    commits
+}
+
+/// The final status line of a clone of `url` into `name` with `upload_pack`, without the number of reused packs
+/// newer versions of git add
+fn clone(dir: &Path, upload_pack: &str, version: &str, url: &str, name: &str) -> String {
+    let protocol = format!("protocol.version={version}");
+    let output = git_command(dir)
+        .args([
+            "-c",
+            &protocol,
+            "clone",
+            "--progress",
+            "--no-local",
+            "--upload-pack",
+            upload_pack,
+            url,
+            name,
+        ])
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(output.status.success(), "clone failed: {stderr}");
+    git(&dir.join(name), &["fsck", "--no-progress"]);
+    let status = stderr
+        .split(['\r', '\n'])
+        .find_map(|line| line.strip_prefix("remote: Total "))
+        .expect("a final status");
+    let status = status.trim_end();
+    status.split(" (from ").next().unwrap().to_owned()
+}
+
+#[test]
+fn clones_reuse_packs_like_native_git() {
+    let tmp = tempfile::tempdir().unwrap();
+    let upstream = tmp.path().join("upstream");
+    std::fs::create_dir(&upstream).unwrap();
+    repository(&upstream);
+    let url = format!("file://{}", upstream.display());
+    let upload_pack = assert_cmd::cargo::cargo_bin("gix-upload-pack");
+    let upload_pack = upload_pack.to_str().unwrap();
+
+    let native = clone(tmp.path(), "git-upload-pack", "2", &url, "native");
+    assert!(!native.ends_with("pack-reused 0"), "{native}");
+    for version in ["0", "2"] {
+        let ours = clone(tmp.path(), upload_pack, version, &url, &format!("clone-v{version}"));
+        assert_eq!(ours, native, "v{version}");
+    }
+
+    git(&upstream, &["config", "pack.allowPackReuse", "false"]);
+    let ours = clone(tmp.path(), upload_pack, "2", &url, "without-reuse");
+    assert!(ours.ends_with("pack-reused 0"), "reuse can be turned off: {ours}");
+}
+
+/// Fetch `head` with protocol v2 without the thin-pack capability, asking for `filter` and leaving out `excluded`,
+/// and return the pack along with the number of objects reused verbatim
+fn fetch(dir: &Path, head: &str, filter: &str, excluded: Option<ObjectId>) -> (Vec<u8>, usize) {
+    let request = pkt("command=fetch\n")
+        + &pkt("object-format=sha1\n")
+        + "0001"
+        + &pkt(&format!("want {head}\n"))
+        + &pkt(&format!("filter {filter}\n"))
+        + &pkt("done\n")
+        + "0000";
+    let options = ServerOptions::default()
+        .with_stateless_rpc(true)
+        .with_repository_overrides(false)
+        .with_protocol(ProtocolVersion::V2)
+        .with_pack_verification(true)
+        .with_allowed_filters(vec!["blob:limit=1m".into()])
+        .with_object_firewall(excluded.into_iter().collect::<std::collections::HashSet<_>>());
+    let mut output = Vec::new();
+    Server::new(dir, options)
+        .unwrap()
+        .serve(request.as_bytes(), &mut output)
+        .unwrap();
+
+    let (mut pack, mut progress) = (Vec::new(), String::new());
+    let mut in_packfile = false;
+    let mut output = output.as_slice();
+    while output.len() >= 4 {
+        let len = usize::from_str_radix(std::str::from_utf8(&output[..4]).unwrap(), 16).unwrap();
+        if len < 4 {
+            output = &output[4..];
+            continue;
+        }
+        let data = &output[4..len];
+        match data[0] {
+            1 if in_packfile => pack.extend_from_slice(&data[1..]),
+            2 if in_packfile => progress.push_str(&String::from_utf8_lossy(&data[1..])),
+            _ => {}
+        }
+        in_packfile |= data == b"packfile\n";
+        output = &output[len..];
+    }
+    let reused = progress
+        .split("pack-reused ")
+        .nth(1)
+        .and_then(|rest| rest.split(' ').next())
+        .expect("a final status")
+        .parse()
+        .unwrap();
+    (pack, reused)
+}
+
+#[test]
+fn packs_with_objects_left_out_refer_to_reused_bases() {
+    let tmp = tempfile::tempdir().unwrap();
+    let repo = tmp.path().join("repo");
+    std::fs::create_dir(&repo).unwrap();
+    let commits = repository(&repo);
+    let head = &commits[18];
+    let index = std::fs::read_dir(repo.join(".git/objects/pack"))
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .find(|path| path.extension().is_some_and(|extension| extension == "idx"))
+        .unwrap();
+    let deltas = git(&repo, &["verify-pack", "-v", index.to_str().unwrap()])
+        .lines()
+        .filter(|line| line.split_whitespace().count() == 7)
+        .count();
+    assert!(deltas > 0, "the fixture stores blobs as deltas");
+
+    let file = ObjectId::from_hex(git(&repo, &["rev-parse", &format!("{head}:file")]).as_bytes()).unwrap();
+    let small_file = ObjectId::from_hex(git(&repo, &["rev-parse", &format!("{head}:file-1")]).as_bytes()).unwrap();
+    for (filter, excluded, left_out) in [
+        // The largest versions of the file, which smaller ones are stored as deltas against, are left out
+        ("blob:limit=10000", None, file),
+        // Objects after the largest version, which is reused, are stored as deltas against it
+        ("blob:limit=1m", Some(small_file), small_file),
+    ] {
+        let (pack, reused) = fetch(&repo, head, filter, excluded);
+        let total = u32::from_be_bytes(pack[8..12].try_into().unwrap()) as usize;
+        assert!(reused > 0 && reused < total, "{reused} of {total} objects are reused");
+
+        // Without `--fix-thin`, deltas against objects missing in the pack fail
+        std::fs::write(tmp.path().join("received.pack"), &pack).unwrap();
+        git(tmp.path(), &["index-pack", "received.pack"]);
+        let received: BTreeSet<_> = git(tmp.path(), &["verify-pack", "-v", "received.idx"])
+            .lines()
+            .filter_map(|line| ObjectId::from_hex(line.split_whitespace().next()?.as_bytes()).ok())
+            .collect();
+        let mut expected: BTreeSet<_> = git(&repo, &["rev-list", "--objects", &format!("--filter={filter}"), head])
+            .lines()
+            .map(|line| ObjectId::from_hex(line.split(' ').next().unwrap().as_bytes()).unwrap())
+            .collect();
+        expected.remove(&left_out);
+        assert_eq!(received, expected, "{filter}");
+        for path in ["received.pack", "received.idx"] {
+            std::fs::remove_file(tmp.path().join(path)).unwrap();
+        }
+    }
+}
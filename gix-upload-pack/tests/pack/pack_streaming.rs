@@ -0,0 +1,92 @@
+//! Sending packs as their entries are generated, with a bounded number of entries in memory
+
+use crate::util::{git, pkt};
+use gix_upload_pack::{ProtocolVersion, Server, ServerOptions};
+use std::path::Path;
+
+/// A repository with a growing file stored as deltas, whose pack isn't reused verbatim, returning its head
+fn repository(dir: &Path) -> String {
+    git(dir, &["init", "--quiet"]);
+    for revision in 1..=10 {
+        let content: String = (0..revision * 100).map(|line| format!("line {line}\n")).collect();
+        std::fs::write(dir.join("file"), content).unwrap();
+        git(dir, &["add", "."]);
+        git(dir, &["commit", "--quiet", "-m", &format!("revision {revision}")]);
+    }
+    git(dir, &["repack", "-a", "-d", "--quiet"]);
+    git(dir, &["config", "pack.allowPackReuse", "false"]);
+    git(dir, &["rev-parse", "HEAD"])
+}
+
+/// Clone `head` with protocol v2 and return the data packets of the pack
+fn clone(dir: &Path, head: &str, options: ServerOptions) -> Vec<Vec<u8>> {
+    let request = pkt("command=fetch\n")
+        + &pkt("object-format=sha1\n")
+        + "0001"
+        + &pkt(&format!("want {head}\n"))
+        + &pkt("done\n")
+        + "0000";
+    let options = options
+        .with_stateless_rpc(true)
+        .with_repository_overrides(false)
+        .with_protocol(ProtocolVersion::V2);
+    let mut output = Vec::new();
+    Server::new(dir, options)
+        .unwrap()
+        .serve(request.as_bytes(), &mut output)
+        .unwrap();
+
+    let mut packets = Vec::new();
+    let mut in_packfile = false;
+    let mut output = output.as_slice();
+    while output.len() >= 4 {
+        let len = usize::from_str_radix(std::str::from_utf8(&output[..4]).unwrap(), 16).unwrap();
+        if len < 4 {
+            output = &output[4..];
+            continue;
+        }
+        let data = &output[4..len];
+        if in_packfile && data[0] == 1 {
+            packets.push(data[1..].to_vec());
+        }
+        in_packfile |= data == b"packfile\n";
+        output = &output[len..];
+    }
+    packets
+}
+
+#[test]
+fn packs_are_sent_as_entries_are_generated() {
+    let tmp = tempfile::tempdir().unwrap();
+    let head = repository(tmp.path());
+
+    let streamed = clone(
+        tmp.path(),
+        &head,
+        ServerOptions::default().with_max_in_flight_entries(1),
+    );
+    let held_back = clone(
+        tmp.path(),
+        &head,
+        ServerOptions::default()
+            .with_max_in_flight_entries(1)
+            .with_pack_verification(true),
+    );
+    assert!(streamed.len() > 10, "the pack is sent in {} parts", streamed.len());
+    assert_eq!(held_back.len(), 1, "verified packs are sent once complete");
+    assert_eq!(streamed.concat(), held_back.concat());
+    assert_eq!(
+        clone(tmp.path(), &head, ServerOptions::default()).concat(),
+        streamed.concat(),
+        "deltas refer to bases generated separately by offset"
+    );
+
+    let received = tmp.path().join("received.pack");
+    std::fs::write(&received, streamed.concat()).unwrap();
+    git(tmp.path(), &["index-pack", received.to_str().unwrap()]);
+    let deltas = git(tmp.path(), &["verify-pack", "-v", "received.idx"])
+        .lines()
+        .filter(|line| line.split_whitespace().count() == 7)
+        .count();
+    assert!(deltas > 0, "deltas are kept");
+}
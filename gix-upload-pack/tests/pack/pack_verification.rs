@@ -0,0 +1,37 @@
+//! Generated packs pass their self-check, including delta entries
+
+use crate::util::{git, pkt};
+use gix_upload_pack::{ProtocolVersion, Server, ServerOptions};
+
+#[test]
+fn verified_fetch_sends_the_pack() {
+    let tmp = tempfile::tempdir().unwrap();
+    git(tmp.path(), &["init", "--quiet"]);
+    for revision in 1..=3 {
+        let content: String = (0..revision * 200).map(|line| format!("line {line}\n")).collect();
+        std::fs::write(tmp.path().join("file"), content).unwrap();
+        git(tmp.path(), &["add", "file"]);
+        git(
+            tmp.path(),
+            &["commit", "--quiet", "-m", &format!("revision {revision}")],
+        );
+    }
+    git(tmp.path(), &["repack", "-adq"]);
+    git(tmp.path(), &["config", "uploadpack.verifyPack", "true"]);
+    let head = git(tmp.path(), &["rev-parse", "HEAD"]);
+
+    let repo = gix::open(tmp.path()).unwrap();
+    let options = ServerOptions::from_repository(&repo)
+        .unwrap()
+        .with_protocol(ProtocolVersion::V2);
+    let mut server = Server::new(tmp.path(), options).unwrap();
+    let request = format!(
+        "{}0001{}{}0000",
+        pkt("command=fetch\n"),
+        pkt(&format!("want {head}\n")),
+        pkt("done\n")
+    );
+    let mut output = Vec::new();
+    server.serve(request.as_bytes(), &mut output).unwrap();
+    assert!(output.windows(4).any(|window| window == b"PACK"));
+}
@@ -0,0 +1,119 @@
+//! Blobs configured with `uploadpack.blobPackfileUri` are offloaded to their packs for clients accepting packfile URIs
+#![cfg(feature = "serve-core")]
+
+use crate::util::{git, pkt};
+use gix_serve_core::protocol::ProtocolVersion;
+use gix_serve_core::service::{GitService, ServiceContext};
+use gix_serve_core::testing::{Demuxed, SidebandDemux};
+use gix_upload_pack::services::{CapabilityManager, PackfileUri};
+use gix_upload_pack::{Server, ServerCapabilities, ServerOptions};
+use std::path::Path;
+
+const PACK_HASH: &str = "2222222222222222222222222222222222222222";
+
+/// A repository with a large and a small file in its only commit, returning the head and the large blob
+fn repository(dir: &Path) -> (String, String) {
+    git(dir, &["init", "--quiet"]);
+    std::fs::write(dir.join("large.bin"), "large content\n".repeat(1000)).unwrap();
+    std::fs::write(dir.join("small.txt"), "small content\n").unwrap();
+    git(dir, &["add", "."]);
+    git(dir, &["commit", "--quiet", "-m", "initial"]);
+    (
+        git(dir, &["rev-parse", "HEAD"]),
+        git(dir, &["rev-parse", "HEAD:large.bin"]),
+    )
+}
+
+/// Fetch `head` from `dir` with the `packfile-uris` argument `protocols`, if any
+fn fetch(
+    dir: &Path,
+    options: ServerOptions,
+    head: &str,
+    protocols: Option<&str>,
+) -> Result<Demuxed, gix_serve_core::service::Error> {
+    let mut server = Server::new(dir, options.with_repository_overrides(false)).unwrap();
+    let mut request = pkt("command=fetch\n") + "0001" + &pkt("ofs-delta\n");
+    if let Some(protocols) = protocols {
+        request += &pkt(&format!("packfile-uris {protocols}\n"));
+    }
+    request += &(pkt(&format!("want {head}\n")) + &pkt("done\n") + "0000");
+    let ctx = ServiceContext::new(ProtocolVersion::V2).with_stateless(true);
+    let mut response = Vec::new();
+    GitService::serve(&mut server, &mut request.as_bytes(), &mut response, &ctx)?;
+    Ok(SidebandDemux::default().demux(&response))
+}
+
+fn object_count(response: &Demuxed) -> u32 {
+    let pack = response.pack().expect("a pack is sent");
+    u32::from_be_bytes(pack[8..12].try_into().unwrap())
+}
+
+fn options(blob: &str) -> ServerOptions {
+    ServerOptions::default().with_packfile_uri(PackfileUri::new(
+        gix_hash::ObjectId::from_hex(blob.as_bytes()).unwrap(),
+        gix_hash::ObjectId::from_hex(PACK_HASH.as_bytes()).unwrap(),
+        "https://cdn.example.com/large.pack",
+    ))
+}
+
+#[test]
+fn configured_blobs_are_offloaded() {
+    let tmp = tempfile::tempdir().unwrap();
+    let (head, large) = repository(tmp.path());
+
+    let response = fetch(tmp.path(), options(&large), &head, Some("http,https")).unwrap();
+    assert_eq!(
+        response.lines,
+        [
+            b"packfile-uris\n".to_vec(),
+            format!("{PACK_HASH} https://cdn.example.com/large.pack\n").into_bytes(),
+            b"packfile\n".to_vec(),
+        ]
+    );
+    assert_eq!(object_count(&response), 3, "the commit, its tree and the small blob");
+
+    for protocols in [None, Some("ftp")] {
+        let response = fetch(tmp.path(), options(&large), &head, protocols).unwrap();
+        assert_eq!(response.lines, [b"packfile\n".to_vec()], "{protocols:?}");
+        assert_eq!(object_count(&response), 4, "nothing is offloaded for {protocols:?}");
+    }
+}
+
+#[test]
+fn packfile_uris_have_to_be_allowed() {
+    let tmp = tempfile::tempdir().unwrap();
+    let (head, _) = repository(tmp.path());
+    let err = fetch(tmp.path(), ServerOptions::default(), &head, Some("https")).unwrap_err();
+    assert!(err.to_string().contains("packfile-uris"), "{err}");
+
+    let repo = gix::open(tmp.path()).unwrap();
+    let advertised = |options: &ServerOptions| {
+        CapabilityManager::new(&repo, options)
+            .get_v2_capability_lines(&ServerCapabilities::default())
+            .iter()
+            .any(|line| line.starts_with("fetch=") && line.contains("packfile-uris"))
+    };
+    assert!(!advertised(&ServerOptions::default()));
+    assert!(advertised(&options(&head)));
+}
+
+#[test]
+fn configuration_offloads_blobs() {
+    let tmp = tempfile::tempdir().unwrap();
+    let (_, large) = repository(tmp.path());
+    git(
+        tmp.path(),
+        &[
+            "config",
+            "uploadpack.blobPackfileUri",
+            &format!("{large} {PACK_HASH} https://cdn.example.com/large.pack"),
+        ],
+    );
+    let options = ServerOptions::from_repository(&gix::open(tmp.path()).unwrap()).unwrap();
+    assert!(options.allow_packfile_uris);
+    assert_eq!(options.packfile_uris, self::options(&large).packfile_uris);
+
+    git(tmp.path(), &["config", "uploadpack.blobPackfileUri", "invalid"]);
+    let err = ServerOptions::from_repository(&gix::open(tmp.path()).unwrap()).unwrap_err();
+    assert!(err.to_string().contains("uploadpack.blobPackfileUri"), "{err}");
+}
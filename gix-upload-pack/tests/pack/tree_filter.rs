@@ -0,0 +1,80 @@
+//! Fetches with `tree:0` receive commits and tags only, like from `git rev-list --filter=tree:0`
+
+use crate::util::{fetch_filtered, git, index_pack};
+use gix_hash::ObjectId;
+use gix_upload_pack::ServerOptions;
+use std::collections::HashSet;
+use std::path::Path;
+
+/// A repository with a few commits changing files in nested directories and an annotated tag of the first one
+fn repository(dir: &Path) {
+    git(dir, &["init", "--quiet", "--initial-branch=main"]);
+    for revision in 1..=4 {
+        std::fs::create_dir_all(dir.join("src/nested")).unwrap();
+        std::fs::write(dir.join("README"), format!("revision {revision}\n")).unwrap();
+        std::fs::write(dir.join("src/nested/file"), format!("content {revision}\n")).unwrap();
+        git(dir, &["add", "."]);
+        git(dir, &["commit", "--quiet", "-m", &format!("revision {revision}")]);
+        if revision == 1 {
+            git(dir, &["tag", "-a", "-m", "first", "v1"]);
+        }
+    }
+}
+
+/// Fetch `wants` with `filter`, telling the server about `haves`, and return the received objects
+fn fetch(dir: &Path, wants: &[&str], haves: &[&str], filter: &str) -> HashSet<ObjectId> {
+    index_pack(
+        dir,
+        &fetch_filtered(dir, ServerOptions::default(), wants, haves, filter).unwrap(),
+    )
+}
+
+/// The objects `git rev-list` lists for `args` with the `tree:0` filter
+fn native_objects(dir: &Path, args: &[&str]) -> HashSet<ObjectId> {
+    let mut rev_list = vec!["rev-list", "--objects", "--filter=tree:0"];
+    rev_list.extend_from_slice(args);
+    git(dir, &rev_list)
+        .lines()
+        .map(|line| ObjectId::from_hex(line.split_whitespace().next().unwrap().as_bytes()).unwrap())
+        .collect()
+}
+
+#[test]
+fn tree_depth_zero_sends_commits_only() {
+    let tmp = tempfile::tempdir().unwrap();
+    repository(tmp.path());
+    let head = git(tmp.path(), &["rev-parse", "HEAD"]);
+
+    let received = fetch(tmp.path(), &[&head], &[], "tree:0");
+    assert_eq!(received.len(), 4, "one object per commit");
+    assert_eq!(received, native_objects(tmp.path(), &[&head]));
+
+    let first = git(tmp.path(), &["rev-parse", "HEAD~2"]);
+    let received = fetch(tmp.path(), &[&head], &[&first], "combine:blob:none+tree:0");
+    assert_eq!(received, native_objects(tmp.path(), &[&head, &format!("^{first}")]));
+    assert_eq!(received.len(), 2, "commits the client has are left out");
+}
+
+#[test]
+fn tags_are_sent_with_their_commits() {
+    let tmp = tempfile::tempdir().unwrap();
+    repository(tmp.path());
+    let tag = git(tmp.path(), &["rev-parse", "v1"]);
+    let commit = git(tmp.path(), &["rev-parse", "v1^{commit}"]);
+
+    let received = fetch(tmp.path(), &[&tag], &[], "tree:0");
+    let expected: HashSet<_> = [&tag, &commit]
+        .into_iter()
+        .map(|id| ObjectId::from_hex(id.as_bytes()).unwrap())
+        .collect();
+    assert_eq!(received, expected);
+}
+
+#[test]
+fn other_filters_still_send_trees() {
+    let tmp = tempfile::tempdir().unwrap();
+    repository(tmp.path());
+    let head = git(tmp.path(), &["rev-parse", "HEAD"]);
+    let tree = ObjectId::from_hex(git(tmp.path(), &["rev-parse", "HEAD^{tree}"]).as_bytes()).unwrap();
+    assert!(fetch(tmp.path(), &[&head], &[], "blob:none").contains(&tree));
+}
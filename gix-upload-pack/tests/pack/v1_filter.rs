@@ -0,0 +1,101 @@
+//! Object filters requested by v0 and v1 clients with `filter <spec>` lines, like partial clones do
+
+use crate::util::{git, git_command, pkt};
+use gix_upload_pack::server::Step;
+use gix_upload_pack::{Error, ProtocolVersion, Server, ServerOptions};
+use std::path::Path;
+
+fn repository(dir: &Path) -> String {
+    git(dir, &["init", "--quiet", "--initial-branch=main"]);
+    std::fs::write(dir.join("file"), "content\n").unwrap();
+    git(dir, &["add", "file"]);
+    git(dir, &["commit", "--quiet", "-m", "initial"]);
+    git(dir, &["rev-parse", "HEAD"])
+}
+
+/// Serve a stateless request of a client asking for `capabilities` and sending `filter` lines after its wants
+fn fetch(dir: &Path, version: ProtocolVersion, capabilities: &str, filters: &[&str]) -> Result<Vec<u8>, Error> {
+    let head = repository(dir);
+    let mut request = pkt(&format!("want {head} side-band-64k {capabilities}\n"));
+    for filter in filters {
+        request += &pkt(&format!("filter {filter}\n"));
+    }
+    request += &format!("0000{}", pkt("done\n"));
+
+    let options = ServerOptions::default()
+        .with_stateless_rpc(true)
+        .with_repository_overrides(false);
+    let mut server = Server::new(dir, options).unwrap();
+    let mut session = server.step_session(version)?;
+    session.push_input(request.as_bytes());
+    session.finish_input();
+    let mut output = Vec::new();
+    while session.serve_step(&mut output)? != Step::Done {}
+    Ok(output)
+}
+
+#[test]
+fn filters_are_checked_against_the_allowlist() {
+    for version in [ProtocolVersion::V0, ProtocolVersion::V1] {
+        let tmp = tempfile::tempdir().unwrap();
+        let output = fetch(tmp.path(), version, "filter", &["blob:none"]).unwrap();
+        assert!(output.windows(4).any(|window| window == b"PACK"), "{version:?}");
+
+        let tmp = tempfile::tempdir().unwrap();
+        let err = fetch(tmp.path(), version, "filter", &["blob:limit=1m"]).unwrap_err();
+        assert!(
+            matches!(&err, Error::InvalidFilter { message } if message.contains("blob:limit=1m")),
+            "{version:?}: limits above the allowed one are rejected: {err}"
+        );
+
+        let tmp = tempfile::tempdir().unwrap();
+        let err = fetch(tmp.path(), version, "", &["blob:none"]).unwrap_err();
+        assert!(
+            matches!(&err, Error::InvalidFilter { message } if message.contains("not negotiated")),
+            "{version:?}: filtering requires the capability: {err}"
+        );
+    }
+}
+
+#[test]
+fn native_partial_clones_over_v0_and_v1() {
+    let tmp = tempfile::tempdir().unwrap();
+    let upstream = tmp.path().join("upstream");
+    std::fs::create_dir(&upstream).unwrap();
+    let head = repository(&upstream);
+
+    let upload_pack = assert_cmd::cargo::cargo_bin("gix-upload-pack");
+    let upload_pack = upload_pack.to_str().unwrap();
+    let url = format!("file://{}", upstream.display());
+    for version in ["0", "1"] {
+        let clone = format!("clone-v{version}");
+        // Checking out would fetch the omitted blobs with the upload-pack of the installed git
+        let output = git_command(tmp.path())
+            .args([
+                "-c",
+                &format!("protocol.version={version}"),
+                "clone",
+                "--quiet",
+                "--no-checkout",
+                "--filter=blob:none",
+                "--upload-pack",
+                upload_pack,
+                &url,
+                &clone,
+            ])
+            .output()
+            .unwrap();
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(output.status.success(), "v{version}: clone failed: {stderr}");
+        assert!(
+            !stderr.contains("filtering not recognized"),
+            "v{version}: the filter is advertised and sent: {stderr}"
+        );
+
+        let clone = tmp.path().join(clone);
+        let filter = git(&clone, &["config", "remote.origin.partialclonefilter"]);
+        assert_eq!(filter, "blob:none");
+        let tip = git(&clone, &["rev-parse", "HEAD"]);
+        assert_eq!(tip, head);
+    }
+}
@@ -0,0 +1,117 @@
+//! Sessions are served over `futures_io` streams like over blocking ones
+#![cfg(feature = "async-io")]
+
+use crate::util::{git, pkt};
+use gix_upload_pack::{ProtocolVersion, Server, ServerOptions};
+use std::time::Duration;
+
+#[tokio::test]
+async fn stateless_requests_are_answered() {
+    let tmp = tempfile::tempdir().unwrap();
+    git(tmp.path(), &["init", "--quiet", "--initial-branch=main"]);
+    // Larger than the chunks output is written in, which are limited to 64KiB
+    let content: String = (0..20_000u32)
+        .map(|n| format!("{:x}", n.wrapping_mul(2_654_435_761)))
+        .collect();
+    std::fs::write(tmp.path().join("file"), content).unwrap();
+    git(tmp.path(), &["add", "file"]);
+    git(tmp.path(), &["commit", "--quiet", "-m", "initial"]);
+    let head = git(tmp.path(), &["rev-parse", "HEAD"]);
+    let options = ServerOptions::default()
+        .with_stateless_rpc(true)
+        .with_repository_overrides(false);
+    let mut server = Server::new(tmp.path(), options).unwrap();
+
+    let request = pkt("command=ls-refs\n") + "0001" + &pkt("symrefs\n") + "0000";
+    let mut output = Vec::new();
+    server
+        .serve_async_with_version(request.as_bytes(), &mut output, ProtocolVersion::V2)
+        .await
+        .unwrap();
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        format!(
+            "{}{}0000",
+            pkt(&format!("{head} HEAD symref-target:refs/heads/main\n")),
+            pkt(&format!("{head} refs/heads/main\n"))
+        )
+    );
+
+    let request = pkt("command=fetch\n") + "0001" + &pkt(&format!("want {head}\n")) + &pkt("done\n") + "0000";
+    let mut output = Vec::new();
+    let outcome = server
+        .serve_async_with_version(request.as_bytes(), &mut output, ProtocolVersion::V2)
+        .await
+        .unwrap();
+    assert!(output.starts_with(pkt("packfile\n").as_bytes()));
+    assert!(output.ends_with(b"0000"));
+    assert_eq!(outcome.wire.response_bytes, output.len() as u64);
+    assert!(outcome.wire.pack_bytes > 64 * 1024, "{outcome:?}");
+}
+
+#[tokio::test]
+async fn errors_are_returned() {
+    let tmp = tempfile::tempdir().unwrap();
+    git(tmp.path(), &["init", "--quiet"]);
+    let options = ServerOptions::default()
+        .with_stateless_rpc(true)
+        .with_repository_overrides(false);
+    let mut server = Server::new(tmp.path(), options).unwrap();
+    let request = pkt("command=unknown\n") + "0000";
+    let err = server
+        .serve_async_with_version(request.as_bytes(), &mut Vec::new(), ProtocolVersion::V2)
+        .await
+        .unwrap_err();
+    assert!(
+        matches!(err, gix_upload_pack::Error::UnsupportedCommand { .. }),
+        "{err:?}"
+    );
+}
+
+#[tokio::test]
+async fn v0_requests_of_clients_asking_for_v2_are_served_with_v0() {
+    let tmp = tempfile::tempdir().unwrap();
+    git(tmp.path(), &["init", "--quiet"]);
+    git(tmp.path(), &["commit", "--quiet", "--allow-empty", "-m", "initial"]);
+    let head = git(tmp.path(), &["rev-parse", "HEAD"]);
+    let options = ServerOptions::default()
+        .with_stateless_rpc(true)
+        .with_repository_overrides(false);
+    let mut server = Server::new(tmp.path(), options).unwrap();
+    let request = format!("{}0000{}", pkt(&format!("want {head} no-progress\n")), pkt("done\n"));
+    let mut output = Vec::new();
+    server
+        .serve_async_with_version(request.as_bytes(), &mut output, ProtocolVersion::V2)
+        .await
+        .unwrap();
+    assert!(output.starts_with(b"0008NAK\n"), "the v0 response follows right away");
+}
+
+/// Input of a client that never sends anything
+struct Silent;
+
+impl futures_io::AsyncRead for Silent {
+    fn poll_read(
+        self: std::pin::Pin<&mut Self>,
+        _cx: &mut std::task::Context<'_>,
+        _buf: &mut [u8],
+    ) -> std::task::Poll<std::io::Result<usize>> {
+        std::task::Poll::Pending
+    }
+}
+
+#[tokio::test]
+async fn inactive_clients_time_out() {
+    let tmp = tempfile::tempdir().unwrap();
+    git(tmp.path(), &["init", "--quiet"]);
+    let options = ServerOptions::default()
+        .with_stateless_rpc(true)
+        .with_timeout(Duration::from_secs(1))
+        .with_repository_overrides(false);
+    let mut server = Server::new(tmp.path(), options).unwrap();
+    let err = server
+        .serve_async_with_version(Silent, &mut Vec::new(), ProtocolVersion::V2)
+        .await
+        .unwrap_err();
+    assert!(matches!(err, gix_upload_pack::Error::Resource(_)), "{err:?}");
+}
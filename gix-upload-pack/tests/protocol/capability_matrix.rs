@@ -0,0 +1,311 @@
+//! Capability advertisement and acceptance conformance against the compatibility matrix from git's documentation
+
+use gix_upload_pack::{
+    services::CapabilityManager, Capability, MultiAckMode, ProtocolVersion, ServerCapabilities, ServerOptions,
+};
+
+/// The capability compatibility matrix, transcribed from `gitprotocol-capabilities(5)` and `gitprotocol-v2(5)`
+mod matrix {
+    use gix_upload_pack::Capability;
+
+    /// A row of the matrix
+    pub struct Row {
+        /// The capability name on the wire
+        pub name: &'static str,
+        /// Exists in protocol v0/v1
+        pub v1: bool,
+        /// Exists in protocol v2, as top-level capability or `fetch` feature
+        pub v2: bool,
+        /// Capabilities an advertisement needs for this one to be meaningful
+        pub requires: &'static [&'static str],
+        /// Capabilities a client must not request together with this one
+        pub conflicts: &'static [&'static str],
+    }
+
+    const fn row(
+        name: &'static str,
+        v1: bool,
+        v2: bool,
+        requires: &'static [&'static str],
+        conflicts: &'static [&'static str],
+    ) -> Row {
+        Row {
+            name,
+            v1,
+            v2,
+            requires,
+            conflicts,
+        }
+    }
+
+    pub const ROWS: &[Row] = &[
+        row("multi_ack", true, false, &[], &[]),
+        // "an extension of multi_ack", which clients request instead of it
+        row("multi_ack_detailed", true, false, &[], &[]),
+        // "If multi_ack_detailed and no-done are both present, then the sender is free to immediately send a pack"
+        row("no-done", true, false, &["multi_ack_detailed"], &[]),
+        row("thin-pack", true, false, &[], &[]),
+        // "The client MUST send only one of side-band and side-band-64k"
+        row("side-band", true, false, &[], &["side-band-64k"]),
+        row("side-band-64k", true, false, &[], &["side-band"]),
+        row("ofs-delta", true, false, &[], &[]),
+        row("agent", true, true, &[], &[]),
+        row("object-format", true, true, &[], &[]),
+        row("symref", true, false, &[], &[]),
+        row("shallow", true, true, &[], &[]),
+        // Refinements of shallow fetches, which git requests without "shallow"
+        row("deepen-since", true, false, &["shallow"], &[]),
+        row("deepen-not", true, false, &["shallow"], &[]),
+        row("deepen-relative", true, false, &["shallow"], &[]),
+        row("no-progress", true, false, &[], &[]),
+        row("include-tag", true, false, &[], &[]),
+        row("allow-tip-sha1-in-want", true, false, &[], &[]),
+        row("allow-reachable-sha1-in-want", true, false, &[], &[]),
+        row("filter", true, true, &[], &[]),
+        row("session-id", true, true, &[], &[]),
+        row("wait-for-done", false, true, &[], &[]),
+        row("packfile-uris", false, true, &[], &[]),
+        row("sideband-all", false, true, &[], &[]),
+        row("object-info", false, true, &[], &[]),
+    ];
+
+    /// Look up the row for `capability`
+    pub fn row_for(capability: Capability) -> &'static Row {
+        ROWS.iter()
+            .find(|row| row.name == capability.name())
+            .unwrap_or_else(|| panic!("{capability} is missing from the matrix"))
+    }
+}
+
+fn names(caps: &[Capability]) -> Vec<&'static str> {
+    caps.iter().map(Capability::name).collect()
+}
+
+fn test_repository() -> (tempfile::TempDir, gix::Repository) {
+    let dir = tempfile::tempdir().expect("temp dir");
+    let status = std::process::Command::new("git")
+        .args(["init", "--bare", "--quiet"])
+        .arg(dir.path())
+        .status()
+        .expect("git is available");
+    assert!(status.success());
+    let repo = gix::open(dir.path()).expect("freshly initialized repository opens");
+    (dir, repo)
+}
+
+#[test]
+fn capability_model_matches_matrix() {
+    assert_eq!(
+        Capability::ALL.len(),
+        matrix::ROWS.len(),
+        "every documented capability is modelled"
+    );
+    for cap in Capability::ALL {
+        let row = matrix::row_for(*cap);
+        assert_eq!(Capability::from_wire(row.name), Some(*cap));
+        assert_eq!(cap.is_valid_for(ProtocolVersion::V1), row.v1, "{cap} in v1");
+        assert_eq!(cap.is_valid_for(ProtocolVersion::V0), row.v1, "{cap} in v0");
+        assert_eq!(cap.is_valid_for(ProtocolVersion::V2), row.v2, "{cap} in v2");
+        assert_eq!(names(cap.dependencies()), row.requires, "dependencies of {cap}");
+        assert_eq!(names(cap.conflicts()), row.conflicts, "conflicts of {cap}");
+    }
+}
+
+/// The capabilities of the v1 advertisement for `caps`
+fn v1_advertisement(manager: &CapabilityManager<'_>, caps: &ServerCapabilities) -> Vec<Capability> {
+    manager
+        .get_v1_capability_strings(caps)
+        .iter()
+        .map(|cap| Capability::from_wire(cap).unwrap_or_else(|| panic!("unknown capability advertised: {cap}")))
+        .collect()
+}
+
+/// The capabilities and `fetch` features of the v2 advertisement for `caps`
+fn v2_advertisement(manager: &CapabilityManager<'_>, caps: &ServerCapabilities) -> Vec<Capability> {
+    let mut advertised = Vec::new();
+    for line in manager.get_v2_capability_lines(caps) {
+        let (name, value) = line.split_once('=').unwrap_or((line.as_str(), ""));
+        match name {
+            "version 2" | "ls-refs" | "server-info" => {}
+            "fetch" => advertised.extend(value.split_whitespace().map(|feature| {
+                Capability::from_wire(feature).unwrap_or_else(|| panic!("unknown fetch feature: {feature}"))
+            })),
+            _ => advertised.push(Capability::from_wire(&line).unwrap_or_else(|| panic!("unknown capability: {line}"))),
+        }
+    }
+    advertised
+}
+
+/// The names of the rows of `version`, in the order of the matrix
+fn documented_for(version: ProtocolVersion) -> Vec<&'static str> {
+    matrix::ROWS
+        .iter()
+        .filter(|row| match version {
+            ProtocolVersion::V0 | ProtocolVersion::V1 => row.v1,
+            ProtocolVersion::V2 => row.v2,
+        })
+        .map(|row| row.name)
+        .collect()
+}
+
+#[test]
+fn advertisements_offer_the_documented_capabilities() {
+    let (_dir, repo) = test_repository();
+    let options = ServerOptions::default();
+    let manager = CapabilityManager::new(&repo, &options);
+
+    for (version, advertised) in [
+        (ProtocolVersion::V1, v1_advertisement(&manager, &everything_enabled())),
+        (ProtocolVersion::V2, v2_advertisement(&manager, &everything_enabled())),
+    ] {
+        let mut names = names(&advertised);
+        names.sort_unstable();
+        let mut documented = documented_for(version);
+        // HEAD of the empty repository is unborn, so there is nothing to advertise a symref for
+        documented.retain(|name| *name != "symref");
+        documented.sort_unstable();
+        assert_eq!(names, documented, "{version:?}");
+
+        for cap in &advertised {
+            for required in matrix::row_for(*cap).requires {
+                assert!(
+                    advertised.iter().any(|cap| cap.name() == *required),
+                    "{version:?}: {cap} is advertised without {required}"
+                );
+            }
+        }
+        Capability::validate_set(&advertised, version, false).expect("advertisements are valid sets");
+    }
+}
+
+/// Everything enabled but `capability`
+fn without(capability: &str) -> ServerCapabilities {
+    let mut caps = everything_enabled();
+    match capability {
+        "multi_ack_detailed" => caps.multi_ack = MultiAckMode::Basic,
+        "shallow" => caps.shallow = false,
+        _ => unreachable!("{capability} can't be turned off yet"),
+    }
+    caps
+}
+
+#[test]
+fn advertisements_leave_out_capabilities_without_their_requirements() {
+    let (_dir, repo) = test_repository();
+    let options = ServerOptions::default();
+    let manager = CapabilityManager::new(&repo, &options);
+
+    for row in matrix::ROWS.iter().filter(|row| row.v1) {
+        for required in row.requires {
+            let advertised = v1_advertisement(&manager, &without(required));
+            assert!(!names(&advertised).contains(required), "{required} can be turned off");
+            assert!(
+                !names(&advertised).contains(&row.name),
+                "{} isn't advertised without {required}",
+                row.name
+            );
+        }
+    }
+}
+
+#[test]
+fn requests_are_accepted_unless_they_conflict() {
+    let (_dir, repo) = test_repository();
+    let options = ServerOptions::default();
+    let manager = CapabilityManager::new(&repo, &options);
+
+    for row in matrix::ROWS {
+        let request = match row.name {
+            "agent" => "agent=git/2.40",
+            "session-id" => "session-id=s-1",
+            "object-format" => "object-format=sha1",
+            "symref" => "symref=HEAD:refs/heads/main",
+            name => name,
+        };
+        assert!(
+            manager.parse_client_capabilities(request).is_ok(),
+            "{request} alone is accepted, as prerequisites are only needed in advertisements and \
+             capabilities of other protocol versions are ignored like unknown ones"
+        );
+        for conflict in row.conflicts {
+            assert!(
+                manager
+                    .parse_client_capabilities(&format!("{} {conflict}", row.name))
+                    .is_err(),
+                "{} can't be requested with {conflict}",
+                row.name
+            );
+        }
+    }
+
+    let client = manager
+        .parse_client_capabilities(
+            "multi_ack_detailed no-done side-band-64k thin-pack ofs-delta deepen-since deepen-not agent=git/2.40",
+        )
+        .expect("the request of a shallow git fetch over HTTP is accepted");
+    assert_eq!(client.multi_ack, MultiAckMode::Detailed);
+    assert!(client.no_done && client.thin_pack && client.ofs_delta);
+}
+
+/// Server capabilities with everything enabled, to see that nothing leaks into the wrong protocol version
+fn everything_enabled() -> ServerCapabilities {
+    ServerCapabilities {
+        filter: true,
+        allow_tip_sha1_in_want: true,
+        allow_reachable_sha1_in_want: true,
+        agent: "git/golden".into(),
+        session_id: Some("s-1".into()),
+        packfile_uris: true,
+        object_info: true,
+        ..Default::default()
+    }
+}
+
+#[test]
+fn golden_v1_advertisement() {
+    let (_dir, repo) = test_repository();
+    let options = ServerOptions::default();
+    let manager = CapabilityManager::new(&repo, &options);
+
+    assert_eq!(
+        manager.server_capabilities_to_v1_string(&everything_enabled()),
+        "multi_ack thin-pack side-band side-band-64k ofs-delta shallow deepen-since deepen-not deepen-relative \
+         no-progress include-tag multi_ack_detailed no-done filter allow-tip-sha1-in-want allow-reachable-sha1-in-want \
+         object-format=sha1 agent=git/golden session-id=s-1",
+        "neither sideband-all, wait-for-done, packfile-uris nor object-info"
+    );
+}
+
+#[test]
+fn golden_v2_advertisement() {
+    let (_dir, repo) = test_repository();
+    let options = ServerOptions::default();
+    let manager = CapabilityManager::new(&repo, &options);
+
+    assert_eq!(
+        manager.get_v2_capability_lines(&everything_enabled()),
+        [
+            "version 2",
+            "agent=git/golden",
+            "object-format=sha1",
+            "ls-refs=unborn",
+            "fetch=shallow filter sideband-all packfile-uris wait-for-done",
+            "server-info",
+            "object-info",
+            "session-id=s-1",
+        ],
+        "neither multi_ack, side-band, thin-pack nor deepen-* capabilities"
+    );
+
+    let basic_sideband = ServerCapabilities {
+        side_band: gix_upload_pack::SideBandMode::Basic,
+        ..everything_enabled()
+    };
+    assert!(
+        manager
+            .get_v2_capability_lines(&basic_sideband)
+            .iter()
+            .all(|line| !line.contains("sideband")),
+        "only side-band-64k translates to sideband-all"
+    );
+}
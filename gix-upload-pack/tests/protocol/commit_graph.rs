@@ -0,0 +1,206 @@
+//! Commits read from the commit-graph, and negotiation using their generation numbers, compared to native git
+
+use crate::util::{git, pkt};
+use gix_hash::ObjectId;
+use gix_upload_pack::{services::CommitGraph, ProtocolVersion, Server, ServerOptions};
+use std::io::Write;
+use std::path::Path;
+use std::process::Stdio;
+
+fn commit(dir: &Path, revision: &str) -> String {
+    std::fs::write(dir.join(revision), format!("{revision}\n")).unwrap();
+    git(dir, &["add", revision]);
+    git(dir, &["commit", "--quiet", "-m", revision]);
+    git(dir, &["rev-parse", "HEAD"])
+}
+
+/// A repository with a merge and a side branch in its commit-graph, and commits on both branches added after it was
+/// written, returning the commits by name
+fn repository(dir: &Path) -> std::collections::HashMap<&'static str, String> {
+    git(dir, &["init", "--quiet", "--initial-branch=main"]);
+    let mut commits = std::collections::HashMap::new();
+    commits.insert("base", commit(dir, "base"));
+    git(dir, &["checkout", "--quiet", "-b", "side"]);
+    commits.insert("side", commit(dir, "side"));
+    git(dir, &["checkout", "--quiet", "-b", "topic", "main"]);
+    commits.insert("topic", commit(dir, "topic"));
+    git(dir, &["checkout", "--quiet", "main"]);
+    commits.insert("middle", commit(dir, "middle"));
+    git(dir, &["merge", "--quiet", "--no-ff", "--no-edit", "topic"]);
+    commits.insert("merge", git(dir, &["rev-parse", "HEAD"]));
+    git(dir, &["commit-graph", "write", "--reachable"]);
+
+    commits.insert("tip", commit(dir, "tip"));
+    git(dir, &["checkout", "--quiet", "side"]);
+    commits.insert("new-side", commit(dir, "new-side"));
+    git(dir, &["checkout", "--quiet", "main"]);
+    commits
+}
+
+/// A fetch request as git sends it over a stateless transport
+fn fetch(wants: &[&str], haves: &[&str]) -> String {
+    let mut request = pkt("command=fetch\n") + &pkt("agent=git/2.39.5\n") + &pkt("object-format=sha1\n") + "0001";
+    for want in wants {
+        request += &pkt(&format!("want {want}\n"));
+    }
+    for have in haves {
+        request += &pkt(&format!("have {have}\n"));
+    }
+    request + "0000"
+}
+
+fn serve_stateless(dir: &Path, request: &str) -> Vec<u8> {
+    let options = ServerOptions::default()
+        .with_stateless_rpc(true)
+        .with_repository_overrides(false)
+        .with_protocol(ProtocolVersion::V2);
+    let mut output = Vec::new();
+    Server::new(dir, options)
+        .unwrap()
+        .serve(request.as_bytes(), &mut output)
+        .unwrap();
+    output
+}
+
+fn native_stateless(dir: &Path, request: &str) -> Vec<u8> {
+    let mut child = std::process::Command::new("git")
+        .args(["upload-pack", "--stateless-rpc", "."])
+        .current_dir(dir)
+        .env("GIT_PROTOCOL", "version=2")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .expect("git is installed");
+    child.stdin.take().unwrap().write_all(request.as_bytes()).unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success(), "native upload-pack failed");
+    output.stdout
+}
+
+/// The response up to and including the `packfile` line, as pack contents differ between implementations
+fn negotiation(response: &[u8]) -> String {
+    let end = response
+        .windows(b"packfile\n".len())
+        .position(|window| window == b"packfile\n")
+        .map_or(response.len(), |pos| pos + b"packfile\n".len());
+    String::from_utf8_lossy(&response[..end]).into_owned()
+}
+
+#[test]
+fn commits_read_from_the_commit_graph_match_decoded_ones() {
+    let tmp = tempfile::tempdir().unwrap();
+    let dir = tmp.path();
+    let commits = repository(dir);
+    let repo = gix::open(dir).unwrap();
+    let graph = CommitGraph::new(&repo);
+    assert!(graph.is_loaded());
+
+    for line in git(dir, &["log", "--all", "--format=%H %ct %P"]).lines() {
+        let mut fields = line.split_whitespace();
+        let id = ObjectId::from_hex(fields.next().unwrap().as_bytes()).unwrap();
+        let time: i64 = fields.next().unwrap().parse().unwrap();
+        let parents: Vec<_> = fields.map(|id| ObjectId::from_hex(id.as_bytes()).unwrap()).collect();
+
+        let commit = graph.find(id).unwrap();
+        assert_eq!((commit.time, commit.parents), (time, parents), "{id}");
+        let in_graph = ![&commits["tip"], &commits["new-side"]].contains(&&id.to_string());
+        assert_eq!(commit.generation.is_some(), in_graph, "{id}");
+        assert!(graph.is_commit(&id));
+    }
+    assert_eq!(
+        graph.generation(&ObjectId::from_hex(commits["base"].as_bytes()).unwrap()),
+        Some(1)
+    );
+    assert_eq!(
+        graph.generation(&ObjectId::from_hex(commits["merge"].as_bytes()).unwrap()),
+        Some(3)
+    );
+
+    let tree = ObjectId::from_hex(git(dir, &["rev-parse", "HEAD^{tree}"]).as_bytes()).unwrap();
+    assert!(!graph.is_commit(&tree));
+    assert_eq!(graph.try_find(tree).unwrap(), None);
+    assert_eq!(graph.peel_to_commit(tree).unwrap(), None, "trees don't lead to commits");
+
+    git(dir, &["config", "core.commitGraph", "false"]);
+    let repo = gix::open(dir).unwrap();
+    assert!(
+        !CommitGraph::new(&repo).is_loaded(),
+        "the commit-graph can be turned off"
+    );
+}
+
+#[test]
+fn shallow_repositories_decode_commits() {
+    let tmp = tempfile::tempdir().unwrap();
+    let origin = tmp.path().join("origin");
+    std::fs::create_dir(&origin).unwrap();
+    repository(&origin);
+    let url = format!("file://{}", origin.display());
+    git(tmp.path(), &["clone", "--quiet", "--depth=1", &url, "clone"]);
+    let clone = tmp.path().join("clone");
+    std::fs::copy(
+        origin.join(".git/objects/info/commit-graph"),
+        clone.join(".git/objects/info/commit-graph"),
+    )
+    .unwrap();
+
+    let repo = gix::open(&clone).unwrap();
+    let graph = CommitGraph::new(&repo);
+    assert!(!graph.is_loaded(), "the commit-graph doesn't know the shallow commits");
+    let head = repo.head_id().unwrap().detach();
+    assert_eq!(graph.find(head).unwrap().generation, None);
+}
+
+#[test]
+fn readiness_with_a_commit_graph_matches_native_git() {
+    let tmp = tempfile::tempdir().unwrap();
+    let dir = tmp.path();
+    let commits = repository(dir);
+    let unknown = "1111111111111111111111111111111111111111";
+
+    let cases = [
+        (
+            "common commit in the graph",
+            fetch(&[&commits["tip"]], &[&commits["topic"]]),
+            true,
+        ),
+        (
+            "want after the graph the client has",
+            fetch(&[&commits["tip"]], &[&commits["tip"]]),
+            true,
+        ),
+        (
+            "common merge in the graph",
+            fetch(&[&commits["tip"]], &[unknown, &commits["merge"]]),
+            true,
+        ),
+        // Like in git, the parents of common commits count as well
+        (
+            "side branch in the graph",
+            fetch(&[&commits["tip"]], &[&commits["side"]]),
+            true,
+        ),
+        (
+            "unrelated commit after the graph",
+            fetch(&[&commits["tip"]], &[&commits["new-side"]]),
+            false,
+        ),
+        (
+            "want after the graph, have in it",
+            fetch(&[&commits["new-side"]], &[&commits["base"]]),
+            true,
+        ),
+        (
+            "want in the graph, have after it",
+            fetch(&[&commits["side"]], &[&commits["tip"]]),
+            false,
+        ),
+    ];
+    for (name, request, ready) in cases {
+        let ours = negotiation(&serve_stateless(dir, &request));
+        let native = negotiation(&native_stateless(dir, &request));
+        assert_eq!(ours, native, "{name}");
+        assert_eq!(ours.contains("ready"), ready, "{name}: {ours}");
+    }
+}
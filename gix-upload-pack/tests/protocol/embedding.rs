@@ -0,0 +1,46 @@
+//! Protocol handlers serve sessions without a `Server`, with the repository and the protocol version chosen by the embedder
+
+use crate::util::{git, pkt};
+use gix_upload_pack::protocol::{v1, v2, ProtocolHandler, Services};
+use gix_upload_pack::{ProtocolVersion, ServerOptions, SessionContext};
+
+#[test]
+fn handlers_serve_the_version_of_the_session() {
+    let tmp = tempfile::tempdir().unwrap();
+    git(tmp.path(), &["init", "--quiet", "--initial-branch=main"]);
+    git(tmp.path(), &["commit", "--quiet", "--allow-empty", "-m", "initial"]);
+    let head = git(tmp.path(), &["rev-parse", "HEAD"]);
+    let repository = gix::open(tmp.path()).unwrap();
+
+    let options = ServerOptions::default().with_stateless_rpc(true);
+    let services = Services::new(&repository, &options);
+    let mut session = SessionContext::from_options(tmp.path(), &options, ProtocolVersion::V2);
+    let request = pkt("command=ls-refs\n") + "0000";
+    let mut output = Vec::new();
+    v2::Handler::from_services(&repository, &options, &services)
+        .handle_session(request.as_bytes(), &mut output, &mut session)
+        .unwrap();
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        format!(
+            "{}{}0000",
+            pkt(&format!("{head} HEAD\n")),
+            pkt(&format!("{head} refs/heads/main\n"))
+        ),
+        "stateless sessions answer the request without advertising capabilities"
+    );
+
+    let options = ServerOptions::default().with_advertise_refs(true);
+    let services = Services::new(&repository, &options);
+    let mut session = SessionContext::from_options(tmp.path(), &options, ProtocolVersion::V1);
+    let mut output = Vec::new();
+    v1::Handler::from_services(&repository, &options, &services)
+        .handle_session(&b""[..], &mut output, &mut session)
+        .unwrap();
+    let output = String::from_utf8(output).unwrap();
+    assert!(
+        output.starts_with(&pkt("version 1\n")),
+        "the version comes from the session, not GIT_PROTOCOL: {output}"
+    );
+    assert!(output.contains(&format!("{head} refs/heads/main\n")), "{output}");
+}
@@ -0,0 +1,149 @@
+//! Serving repositories without any refs, as many hosting flows start with one
+
+use crate::util::{git, git_command, pkt};
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+fn upload_pack() -> String {
+    assert_cmd::cargo::cargo_bin("gix-upload-pack")
+        .to_str()
+        .expect("valid UTF-8")
+        .to_owned()
+}
+
+/// Run `program` as upload-pack on `repo` for protocol `version`, sending `input`
+fn run(program: &str, repo: &Path, version: u8, args: &[&str], input: &str) -> String {
+    let mut command = Command::new(program);
+    if program == "git" {
+        command.arg("upload-pack");
+    }
+    let mut child = command
+        .args(args)
+        .arg(repo)
+        .env("GIT_PROTOCOL", format!("version={version}"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .expect("upload-pack can be started");
+    child.stdin.take().unwrap().write_all(input.as_bytes()).unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success(), "{program} failed for protocol v{version}");
+    String::from_utf8(output.stdout).unwrap()
+}
+
+fn empty_repository(dir: &Path) -> std::path::PathBuf {
+    let repo = dir.join("empty.git");
+    git(dir, &["init", "--quiet", "--bare", "empty.git"]);
+    git(&repo, &["symbolic-ref", "HEAD", "refs/heads/trunk"]);
+    repo
+}
+
+#[test]
+fn v0_and_v1_advertise_capabilities_with_the_null_id() {
+    let tmp = tempfile::tempdir().unwrap();
+    let repo = empty_repository(tmp.path());
+    let null = "0".repeat(40);
+
+    for version in [0, 1] {
+        // The client wants nothing and ends the session with its flush
+        let output = run(&upload_pack(), &repo, version, &[], "0000");
+        let advertisement = output
+            .strip_prefix(&if version == 1 {
+                pkt("version 1\n")
+            } else {
+                String::new()
+            })
+            .expect("version 1 is announced");
+        let (line, rest) = advertisement.split_at(usize::from_str_radix(&advertisement[..4], 16).unwrap());
+        assert!(
+            line[4..].starts_with(&format!("{null} capabilities^{{}}\0")),
+            "v{version}: {output}"
+        );
+        assert!(line.contains(" object-format=sha1 "), "v{version}: {output}");
+        assert!(!line.contains("symref="), "an unborn HEAD isn't advertised: {output}");
+        assert_eq!(rest, "0000", "v{version}: nothing but the flush follows");
+    }
+}
+
+#[test]
+fn v2_matches_native_git() {
+    let tmp = tempfile::tempdir().unwrap();
+    let repo = empty_repository(tmp.path());
+    let ls_refs = |prefixes: &[&str]| {
+        let mut request = pkt("command=ls-refs\n") + "0001" + &pkt("symrefs\n") + &pkt("unborn\n") + &pkt("peel\n");
+        for prefix in prefixes {
+            request += &pkt(&format!("ref-prefix {prefix}\n"));
+        }
+        request + "0000"
+    };
+    let requests = [
+        ("ls-refs of HEAD and branches", ls_refs(&["HEAD", "refs/heads/"])),
+        ("ls-refs of all refs", ls_refs(&[])),
+        ("ls-refs of tags", ls_refs(&["refs/tags/"])),
+        (
+            "ls-refs without unborn",
+            pkt("command=ls-refs\n") + "0001" + &pkt("symrefs\n") + "0000",
+        ),
+        (
+            "fetch without wants",
+            pkt("command=fetch\n") + "0001" + &pkt("thin-pack\n") + &pkt("done\n") + "0000",
+        ),
+    ];
+    for (name, request) in requests {
+        let ours = run(&upload_pack(), &repo, 2, &["--stateless-rpc"], &request);
+        let native = run("git", &repo, 2, &["--stateless-rpc"], &request);
+        assert_eq!(ours, native, "{name}");
+    }
+    assert_eq!(
+        run(&upload_pack(), &repo, 2, &["--stateless-rpc"], &ls_refs(&["HEAD"])),
+        pkt("unborn HEAD symref-target:refs/heads/trunk\n") + "0000"
+    );
+}
+
+#[test]
+fn native_clients_clone_empty_repositories() {
+    let tmp = tempfile::tempdir().unwrap();
+    let repo = empty_repository(tmp.path());
+    let url = format!("file://{}", repo.display());
+    let upload_pack = upload_pack();
+
+    for version in [0, 1, 2] {
+        let clone = format!("clone-v{version}");
+        let output = git_command(tmp.path())
+            .args([
+                "-c",
+                &format!("protocol.version={version}"),
+                "-c",
+                "init.defaultBranch=main",
+                "clone",
+                "--upload-pack",
+                &upload_pack,
+                &url,
+                &clone,
+            ])
+            .output()
+            .unwrap();
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(output.status.success(), "v{version}: {stderr}");
+        assert!(stderr.contains("empty repository"), "v{version}: {stderr}");
+
+        let clone = tmp.path().join(clone);
+        assert!(!git_command(&clone)
+            .args(["rev-parse", "--verify", "--quiet", "HEAD"])
+            .status()
+            .unwrap()
+            .success());
+        let head = git(&clone, &["symbolic-ref", "HEAD"]);
+        let expected = if version == 2 {
+            "refs/heads/trunk"
+        } else {
+            "refs/heads/main"
+        };
+        assert_eq!(
+            head, expected,
+            "v{version}: only protocol v2 tells about the unborn branch"
+        );
+    }
+}
@@ -0,0 +1,130 @@
+//! Errors are described with the text of `git upload-pack`, with object ids optionally abbreviated
+
+use crate::util::{git, pkt};
+use bstr::BStr;
+use gix_upload_pack::{server::Step, Error, ProtocolVersion, Server, ServerOptions};
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Output, Stdio};
+
+/// A repository with `main` and a hidden ref pointing to another commit, returning the hidden commit
+fn repo_with_hidden_ref(dir: &Path) -> String {
+    git(dir, &["init", "--quiet", "--initial-branch=main"]);
+    git(dir, &["commit", "--quiet", "--allow-empty", "-m", "public"]);
+    git(dir, &["commit", "--quiet", "--allow-empty", "-m", "secret"]);
+    let secret = git(dir, &["rev-parse", "HEAD"]);
+    git(dir, &["update-ref", "refs/hidden/secret", &secret]);
+    git(dir, &["reset", "--quiet", "--hard", "HEAD~1"]);
+    secret
+}
+
+fn fetch_request(want: &str) -> String {
+    format!(
+        "{}0001{}{}0000",
+        pkt("command=fetch\n"),
+        pkt(&format!("want {want}\n")),
+        pkt("done\n")
+    )
+}
+
+/// Run `program` as stateless v2 upload-pack on `repo` with `request`
+fn run(program: &Path, repo: &Path, request: &str) -> Output {
+    let mut command = Command::new(program);
+    if program == Path::new("git") {
+        command.arg("upload-pack");
+    }
+    let mut child = command
+        .arg("--stateless-rpc")
+        .arg(repo)
+        .env("GIT_PROTOCOL", "version=2")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("upload-pack can be started");
+    child.stdin.take().unwrap().write_all(request.as_bytes()).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+/// Fetch `want` from the server at `dir`, which hides `refs/hidden/*`, returning the error message
+fn fetch_error(dir: &Path, options: ServerOptions, want: &str) -> String {
+    let options = options
+        .with_ref_authorizer(|name: &BStr| !name.starts_with(b"refs/hidden/"))
+        .with_stateless_rpc(true)
+        .with_repository_overrides(false);
+    let mut server = Server::new(dir, options).unwrap();
+    let err = {
+        let mut session = server.step_session(ProtocolVersion::V2).unwrap();
+        session.push_input(fetch_request(want).as_bytes());
+        session.finish_input();
+        loop {
+            match session.serve_step(&mut Vec::new()) {
+                Ok(Step::Done) => panic!("fetching {want} should fail"),
+                Ok(_) => {}
+                Err(err) => break err,
+            }
+        }
+    };
+    server.error_message(&err)
+}
+
+#[test]
+fn unknown_wants_fail_with_the_text_of_git() {
+    let tmp = tempfile::tempdir().unwrap();
+    repo_with_hidden_ref(tmp.path());
+    let upload_pack = assert_cmd::cargo::cargo_bin("gix-upload-pack");
+
+    let request = fetch_request("1111111111111111111111111111111111111111");
+    let expected = run(Path::new("git"), tmp.path(), &request);
+    let actual = run(&upload_pack, tmp.path(), &request);
+    assert_eq!(
+        String::from_utf8_lossy(&actual.stderr),
+        "fatal: git upload-pack: not our ref 1111111111111111111111111111111111111111\n"
+    );
+    assert_eq!(
+        String::from_utf8_lossy(&actual.stderr),
+        String::from_utf8_lossy(&expected.stderr)
+    );
+    assert_eq!(actual.status.code(), expected.status.code());
+}
+
+#[test]
+fn object_ids_are_shown_in_full_unless_abbreviation_is_requested() {
+    let tmp = tempfile::tempdir().unwrap();
+    let secret = repo_with_hidden_ref(tmp.path());
+    let missing = "1111111111111111111111111111111111111111";
+
+    assert_eq!(
+        fetch_error(tmp.path(), ServerOptions::default(), &secret),
+        format!("git upload-pack: not our ref {secret}")
+    );
+    let abbreviated = ServerOptions::default().with_abbreviated_object_ids(true);
+    assert_eq!(
+        fetch_error(tmp.path(), abbreviated.clone(), &secret),
+        format!("git upload-pack: not our ref {}", &secret[..7])
+    );
+    assert_eq!(
+        fetch_error(tmp.path(), abbreviated.clone(), missing),
+        "git upload-pack: not our ref 1111111",
+        "missing objects are abbreviated to the default length"
+    );
+
+    git(tmp.path(), &["config", "core.abbrev", "12"]);
+    assert_eq!(
+        fetch_error(tmp.path(), abbreviated.clone(), &secret),
+        format!("git upload-pack: not our ref {}", &secret[..12])
+    );
+    assert_eq!(
+        fetch_error(tmp.path(), abbreviated, missing),
+        "git upload-pack: not our ref 111111111111"
+    );
+}
+
+#[test]
+fn errors_without_native_counterpart_keep_their_description() {
+    let tmp = tempfile::tempdir().unwrap();
+    repo_with_hidden_ref(tmp.path());
+    let server = Server::new(tmp.path(), ServerOptions::default()).unwrap();
+    let err = Error::Resource("the client sent its request too slowly".into());
+    assert_eq!(server.error_message(&err), err.to_string());
+}
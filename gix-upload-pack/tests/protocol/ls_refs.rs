@@ -0,0 +1,145 @@
+//! `ls-refs` lists the refs matching its prefixes, with symref targets and peeled tags on request, like git
+
+use crate::util::{git, native_v2, pkt};
+use gix_upload_pack::{server::Step, ProtocolVersion, Server, ServerOptions};
+use std::path::Path;
+
+/// A repository with nested branches, a symbolic ref besides `HEAD`, and lightweight and annotated tags, some of
+/// them packed
+fn repository(dir: &Path) {
+    git(dir, &["init", "--quiet", "--initial-branch=main"]);
+    git(dir, &["commit", "--quiet", "--allow-empty", "-m", "initial"]);
+    for branch in ["maint", "feature/one", "feature/two"] {
+        git(dir, &["branch", branch]);
+    }
+    git(dir, &["tag", "v1"]);
+    git(dir, &["tag", "-a", "-m", "annotated", "v1.1"]);
+    git(dir, &["pack-refs", "--all"]);
+    git(dir, &["tag", "-a", "-m", "annotated", "v2"]);
+    git(dir, &["branch", "feature/three"]);
+    git(dir, &["symbolic-ref", "refs/remotes/origin/HEAD", "refs/heads/maint"]);
+}
+
+fn serve(dir: &Path, request: &str) -> String {
+    let options = ServerOptions::default()
+        .with_stateless_rpc(true)
+        .with_repository_overrides(false);
+    let mut server = Server::new(dir, options).unwrap();
+    let mut session = server.step_session(ProtocolVersion::V2).unwrap();
+    session.push_input(request.as_bytes());
+    session.finish_input();
+    let mut output = Vec::new();
+    while session.serve_step(&mut output).unwrap() != Step::Done {}
+    String::from_utf8(output).unwrap()
+}
+
+fn ls_refs(options: &[&str], prefixes: &[&str]) -> String {
+    let mut request = pkt("command=ls-refs\n") + "0001";
+    for option in options {
+        request += &pkt(&format!("{option}\n"));
+    }
+    for prefix in prefixes {
+        request += &pkt(&format!("ref-prefix {prefix}\n"));
+    }
+    request + "0000"
+}
+
+#[test]
+fn prefixes_and_options_match_git() {
+    let tmp = tempfile::tempdir().unwrap();
+    repository(tmp.path());
+
+    for options in [&[][..], &["symrefs"], &["peel"], &["symrefs", "peel"]] {
+        for prefixes in [
+            &[][..],
+            &["HEAD"],
+            &["H"],
+            &["refs/heads/"],
+            &["refs/heads/ma"],
+            &["refs/heads/feature/", "refs/heads/"],
+            &["refs/heads/feature"],
+            &["refs/tags/v1", "HEAD", "refs/remotes/"],
+            &["refs/tags/v1."],
+            &["refs/"],
+            &["refs"],
+            &[""],
+            &["refs/notes/"],
+            &["refs/heads/../tags/"],
+            &["refs/heads/main", "refs/heads/main"],
+        ] {
+            let request = ls_refs(options, prefixes);
+            assert_eq!(
+                serve(tmp.path(), &request),
+                native_v2(tmp.path(), &request),
+                "{options:?} {prefixes:?}"
+            );
+        }
+    }
+}
+
+#[test]
+fn unborn_head_needs_symrefs() {
+    let tmp = tempfile::tempdir().unwrap();
+    git(tmp.path(), &["init", "--quiet", "--initial-branch=trunk"]);
+
+    for options in [&["unborn"][..], &["unborn", "symrefs"]] {
+        let request = ls_refs(options, &["HEAD"]);
+        assert_eq!(
+            serve(tmp.path(), &request),
+            native_v2(tmp.path(), &request),
+            "{options:?}"
+        );
+    }
+    assert_eq!(
+        serve(tmp.path(), &ls_refs(&["unborn"], &[])),
+        "0000",
+        "without symrefs there is nothing to tell about an unborn HEAD"
+    );
+}
+
+#[test]
+fn too_many_prefixes_list_all_refs() {
+    let tmp = tempfile::tempdir().unwrap();
+    repository(tmp.path());
+
+    let prefixes: Vec<_> = (0..65536).map(|index| format!("refs/none/{index}")).collect();
+    let prefixes: Vec<_> = prefixes.iter().map(String::as_str).collect();
+    assert_eq!(
+        serve(tmp.path(), &ls_refs(&["symrefs"], &prefixes)),
+        serve(tmp.path(), &ls_refs(&["symrefs"], &[]))
+    );
+}
+
+#[test]
+fn hidden_refs_match_git() {
+    let tmp = tempfile::tempdir().unwrap();
+    repository(tmp.path());
+    for (key, pattern) in [
+        ("transfer.hideRefs", "refs/heads/feature"),
+        ("uploadpack.hideRefs", "!refs/heads/feature/two"),
+        ("receive.hideRefs", "refs/heads/main"),
+        ("uploadpack.hideRefs", "refs/tags/v1/"),
+        ("transfer.hideRefs", "^refs/remotes/origin/HEAD"),
+        ("uploadpack.hideRefs", "refs/heads/ma"),
+    ] {
+        git(tmp.path(), &["config", "--add", key, pattern]);
+    }
+
+    for prefixes in [&[][..], &["refs/heads/"], &["refs/tags/"]] {
+        let request = ls_refs(&["symrefs", "peel"], prefixes);
+        let options = ServerOptions::from_repository(&gix::open(tmp.path()).unwrap())
+            .unwrap()
+            .with_stateless_rpc(true);
+        let mut server = Server::new(tmp.path(), options).unwrap();
+        let mut session = server.step_session(ProtocolVersion::V2).unwrap();
+        session.push_input(request.as_bytes());
+        session.finish_input();
+        let mut output = Vec::new();
+        while session.serve_step(&mut output).unwrap() != Step::Done {}
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            native_v2(tmp.path(), &request),
+            "{prefixes:?}"
+        );
+    }
+}
@@ -0,0 +1,29 @@
+//! Conversations with clients, from protocol detection and advertisements to negotiation and its errors
+
+#[path = "../util/mod.rs"]
+mod util;
+
+mod async_io;
+mod capability_matrix;
+mod commit_graph;
+mod embedding;
+mod empty_repository;
+mod error_messages;
+mod ls_refs;
+mod missing_repository;
+mod namespace;
+mod native_fallback;
+mod object_format;
+mod protocol_detection;
+mod protocol_snapshots;
+mod ref_authorization;
+mod ref_peeling;
+mod ref_snapshot;
+mod request_grammar;
+mod service;
+mod shallow;
+mod smart_http;
+mod stateless_rpc;
+mod v1_negotiation;
+mod v2_commands;
+mod v2_negotiation;
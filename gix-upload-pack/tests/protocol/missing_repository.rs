@@ -0,0 +1,37 @@
+//! Repositories that can't be opened are reported with the text of `git upload-pack`, as clients show it to users
+
+use std::path::Path;
+use std::process::{Command, Output};
+
+fn upload_pack(program: &str, repo: &Path) -> Output {
+    let mut command = Command::new(program);
+    if program == "git" {
+        command.arg("upload-pack");
+    }
+    command
+        .args(["--stateless-rpc", "--advertise-refs"])
+        .arg(repo)
+        .env("GIT_PROTOCOL", "version=2")
+        .output()
+        .expect("program can be run")
+}
+
+#[test]
+fn missing_and_invalid_repositories_fail_like_native_git() {
+    let tmp = tempfile::tempdir().unwrap();
+    let empty = tmp.path().join("empty");
+    std::fs::create_dir(&empty).unwrap();
+    let ours = assert_cmd::cargo::cargo_bin("gix-upload-pack");
+
+    for repo in [tmp.path().join("missing.git"), empty] {
+        let expected = upload_pack("git", &repo);
+        let actual = upload_pack(ours.to_str().unwrap(), &repo);
+        assert_eq!(actual.status.code(), expected.status.code(), "{repo:?}");
+        assert_eq!(
+            String::from_utf8_lossy(&actual.stderr),
+            String::from_utf8_lossy(&expected.stderr),
+            "{repo:?}"
+        );
+        assert!(actual.stdout.is_empty(), "nothing is advertised");
+    }
+}
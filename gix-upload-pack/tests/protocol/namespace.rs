@@ -0,0 +1,174 @@
+//! Serving a namespace advertises its refs without the namespace prefix, like `git upload-pack` with `GIT_NAMESPACE`
+
+use crate::util::{git, pkt};
+use gix_upload_pack::{server::Step, ProtocolVersion, Server, ServerOptions};
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+/// A repository with refs outside of any namespace, in namespace `a` with its own `HEAD`, and in namespace `a/b`
+fn repository(dir: &Path) {
+    git(dir, &["init", "--quiet", "--initial-branch=main"]);
+    git(dir, &["commit", "--quiet", "--allow-empty", "-m", "initial"]);
+    git(dir, &["tag", "-a", "-m", "annotated", "v1"]);
+    git(dir, &["commit", "--quiet", "--allow-empty", "-m", "second"]);
+    for (name, rev) in [
+        ("refs/namespaces/a/refs/heads/main", "main~1"),
+        ("refs/namespaces/a/refs/heads/feature", "main"),
+        ("refs/namespaces/a/refs/heads/secret", "main"),
+        ("refs/namespaces/a/refs/heads/hidden", "main"),
+        ("refs/namespaces/a/refs/tags/v1", "v1"),
+        ("refs/namespaces/a/refs/namespaces/b/refs/heads/nested", "main"),
+    ] {
+        git(dir, &["update-ref", name, rev]);
+    }
+    git(
+        dir,
+        &[
+            "symbolic-ref",
+            "refs/namespaces/a/HEAD",
+            "refs/namespaces/a/refs/heads/feature",
+        ],
+    );
+    git(dir, &["pack-refs", "--all"]);
+    git(dir, &["update-ref", "refs/namespaces/a/refs/heads/loose", "main"]);
+    git(dir, &["config", "--add", "uploadpack.hideRefs", "refs/heads/hidden"]);
+    git(
+        dir,
+        &[
+            "config",
+            "--add",
+            "uploadpack.hideRefs",
+            "^refs/namespaces/a/refs/heads/secret",
+        ],
+    );
+}
+
+fn serve(dir: &Path, namespace: &str, version: ProtocolVersion, request: &str) -> String {
+    let options = ServerOptions::from_repository(&gix::open(dir).unwrap())
+        .unwrap()
+        .with_stateless_rpc(true)
+        .with_advertise_refs(request.is_empty())
+        .with_namespace(namespace);
+    let mut server = Server::new(dir, options).unwrap();
+    let mut session = server.step_session(version).unwrap();
+    session.push_input(request.as_bytes());
+    session.finish_input();
+    let mut output = Vec::new();
+    while session.serve_step(&mut output).unwrap() != Step::Done {}
+    String::from_utf8(output).unwrap()
+}
+
+/// Run `git upload-pack` on `dir` for `namespace` with `request`, or only advertise refs if it's empty
+fn serve_natively(dir: &Path, namespace: &str, version: ProtocolVersion, request: &str) -> String {
+    let mut child = Command::new("git")
+        .args(["upload-pack", "--stateless-rpc"])
+        .args(request.is_empty().then_some("--advertise-refs"))
+        .arg(".")
+        .current_dir(dir)
+        .env("GIT_NAMESPACE", namespace)
+        .env("GIT_PROTOCOL", format!("version={}", version as u8))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("git is installed");
+    std::io::Write::write_all(&mut child.stdin.take().unwrap(), request.as_bytes()).unwrap();
+    String::from_utf8(child.wait_with_output().unwrap().stdout).unwrap()
+}
+
+/// The refs of the v0 advertisement `output`, with the `symref` capabilities instead of all capabilities
+fn advertised_refs(output: &str) -> Vec<String> {
+    let mut refs = Vec::new();
+    let mut rest = output;
+    while rest.len() >= 4 {
+        let len = usize::from_str_radix(&rest[..4], 16).unwrap();
+        if len == 0 {
+            rest = &rest[4..];
+            continue;
+        }
+        let line = rest[4..len].trim_end();
+        refs.push(match line.split_once('\0') {
+            Some((reference, capabilities)) => {
+                let symrefs: Vec<_> = capabilities
+                    .split(' ')
+                    .filter(|capability| capability.starts_with("symref="))
+                    .collect();
+                format!("{reference} {}", symrefs.join(" "))
+            }
+            None => line.to_owned(),
+        });
+        rest = &rest[len..];
+    }
+    refs
+}
+
+/// Remove the `HEAD` lines of the ls-refs `output` but the first.
+///
+/// Git lists the `HEAD` of the namespace twice, as it finds it among the refs of the namespace as well.
+fn without_repeated_head(output: &str) -> String {
+    let mut head_seen = false;
+    let mut lines: Vec<&str> = output.split_inclusive('\n').collect();
+    lines.retain(|line| {
+        let is_head = line.get(4..).is_some_and(|line| line.split(' ').nth(1) == Some("HEAD"));
+        let repeated = is_head && head_seen;
+        head_seen |= is_head;
+        !repeated
+    });
+    lines.concat()
+}
+
+#[test]
+fn ls_refs_lists_the_refs_of_the_namespace_like_git() {
+    let tmp = tempfile::tempdir().unwrap();
+    repository(tmp.path());
+
+    for namespace in ["a", "a/b", "missing"] {
+        for prefixes in [
+            &[][..],
+            &["HEAD"],
+            &["refs/heads/"],
+            &["refs/namespaces/"],
+            &["refs/tags/", "HEA"],
+        ] {
+            let mut request = pkt("command=ls-refs\n") + "0001" + &pkt("symrefs\n") + &pkt("peel\n");
+            for prefix in prefixes {
+                request += &pkt(&format!("ref-prefix {prefix}\n"));
+            }
+            request += "0000";
+            assert_eq!(
+                serve(tmp.path(), namespace, ProtocolVersion::V2, &request),
+                without_repeated_head(&serve_natively(tmp.path(), namespace, ProtocolVersion::V2, &request)),
+                "{namespace} {prefixes:?}"
+            );
+        }
+    }
+}
+
+#[test]
+fn v0_advertises_the_refs_of_the_namespace_like_git() {
+    let tmp = tempfile::tempdir().unwrap();
+    repository(tmp.path());
+
+    for namespace in ["a", "a/b"] {
+        let ours = advertised_refs(&serve(tmp.path(), namespace, ProtocolVersion::V0, ""));
+        assert_eq!(
+            ours,
+            advertised_refs(&serve_natively(tmp.path(), namespace, ProtocolVersion::V0, "")),
+            "{namespace}"
+        );
+        assert!(ours.iter().all(|line| !line.contains("refs/namespaces/a/")), "{ours:?}");
+    }
+    let ours = advertised_refs(&serve(tmp.path(), "a", ProtocolVersion::V0, ""));
+    assert!(
+        ours[0].ends_with(" HEAD symref=HEAD:refs/heads/feature"),
+        "the namespace has its own HEAD: {ours:?}"
+    );
+}
+
+#[test]
+fn invalid_namespaces_are_refused() {
+    let tmp = tempfile::tempdir().unwrap();
+    repository(tmp.path());
+
+    let err = Server::new(tmp.path(), ServerOptions::default().with_namespace("a..b")).unwrap_err();
+    assert!(err.to_string().contains("a..b"), "{err}");
+}
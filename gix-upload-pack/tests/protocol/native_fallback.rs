@@ -0,0 +1,60 @@
+//! Arguments we don't support can be passed on to the native `git upload-pack`
+
+use assert_cmd::Command;
+
+#[test]
+fn unknown_arguments_fail_without_fallback() {
+    let tmp = tempfile::tempdir().unwrap();
+    let assert = Command::cargo_bin("gix-upload-pack")
+        .unwrap()
+        .arg("--not-a-flag")
+        .arg(tmp.path())
+        .env_remove("GIX_SERVE_FALLBACK")
+        .assert()
+        .failure();
+    let stderr = String::from_utf8_lossy(&assert.get_output().stderr).into_owned();
+    assert!(stderr.contains("--not-a-flag"), "{stderr}");
+    assert!(!stderr.contains("usage: git-upload-pack"), "{stderr}");
+}
+
+#[test]
+fn unknown_arguments_run_native_git_with_fallback() {
+    let tmp = tempfile::tempdir().unwrap();
+    let assert = Command::cargo_bin("gix-upload-pack")
+        .unwrap()
+        .arg("--not-a-flag")
+        .arg(tmp.path())
+        .env("GIX_SERVE_FALLBACK", "native")
+        .assert()
+        .code(129);
+    let stderr = String::from_utf8_lossy(&assert.get_output().stderr).into_owned();
+    assert!(
+        stderr.contains("usage: git-upload-pack"),
+        "git rejects the flag as well: {stderr}"
+    );
+}
+
+#[cfg(feature = "git-upload-pack")]
+#[test]
+fn the_alias_serves_like_the_original() {
+    let tmp = tempfile::tempdir().unwrap();
+    let status = std::process::Command::new("git")
+        .args(["init", "--quiet", "--bare"])
+        .arg(tmp.path())
+        .status()
+        .expect("git is installed");
+    assert!(status.success());
+    let advertise = |name: &str| {
+        Command::cargo_bin(name)
+            .unwrap()
+            .args(["--stateless-rpc", "--advertise-refs"])
+            .arg(tmp.path())
+            .env("GIT_PROTOCOL", "version=2")
+            .assert()
+            .success()
+            .get_output()
+            .stdout
+            .clone()
+    };
+    assert_eq!(advertise("git-upload-pack"), advertise("gix-upload-pack"));
+}
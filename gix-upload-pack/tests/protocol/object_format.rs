@@ -0,0 +1,119 @@
+//! Object format negotiation behaves like native git, including towards clients of SHA-256 repositories
+
+use crate::util::{git, git_command, pkt};
+use gix_upload_pack::{ProtocolVersion, Server, ServerOptions};
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+fn repository(dir: &Path, name: &str, object_format: &str) -> std::path::PathBuf {
+    let format = format!("--object-format={object_format}");
+    git(dir, &["init", "--quiet", &format, name]);
+    let repo = dir.join(name);
+    git(&repo, &["commit", "--quiet", "--allow-empty", "-m", "initial"]);
+    repo
+}
+
+/// Run `program` as stateless v2 upload-pack on `repo` with `request`, returning its output and its error, if it failed
+fn run(program: &Path, repo: &Path, request: &str) -> (String, Option<String>) {
+    let mut command = Command::new(program);
+    if program == Path::new("git") {
+        command.arg("upload-pack");
+    }
+    let mut child = command
+        .arg("--stateless-rpc")
+        .arg(repo)
+        .env("GIT_PROTOCOL", "version=2")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("upload-pack can be started");
+    child.stdin.take().unwrap().write_all(request.as_bytes()).unwrap();
+    let output = child.wait_with_output().unwrap();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    let error = (!output.status.success()).then(|| stderr.trim().lines().next().unwrap_or_default().to_owned());
+    (String::from_utf8(output.stdout).unwrap(), error)
+}
+
+#[test]
+fn requests_are_answered_or_rejected_like_git() {
+    let tmp = tempfile::tempdir().unwrap();
+    let repo = repository(tmp.path(), "sha1", "sha1");
+    let upload_pack = assert_cmd::cargo::cargo_bin("gix-upload-pack");
+
+    for capability in [
+        None,
+        Some("object-format=sha1"),
+        Some("object-format=sha256"),
+        Some("object-format=md5"),
+        Some("object-format"),
+    ] {
+        let request = format!(
+            "{}{}00010000",
+            pkt("command=ls-refs\n"),
+            capability.map(|line| pkt(&format!("{line}\n"))).unwrap_or_default()
+        );
+        let (output, error) = run(&upload_pack, &repo, &request);
+        let (expected_output, expected_error) = run(Path::new("git"), &repo, &request);
+        assert_eq!(output, expected_output, "{capability:?}");
+        match (error, expected_error) {
+            (None, None) => {}
+            (Some(error), Some(expected)) => {
+                let expected = expected.strip_prefix("fatal: ").unwrap();
+                assert!(
+                    error.ends_with(expected),
+                    "{capability:?}: '{error}' should end with '{expected}'"
+                );
+            }
+            (error, expected) => panic!("{capability:?}: {error:?} != {expected:?}"),
+        }
+    }
+}
+
+#[test]
+fn clients_of_sha256_repositories_reject_the_advertisement_like_with_git() {
+    let tmp = tempfile::tempdir().unwrap();
+    let served = repository(tmp.path(), "sha1", "sha1");
+    let client = repository(tmp.path(), "sha256", "sha256");
+    let upload_pack = assert_cmd::cargo::cargo_bin("gix-upload-pack");
+
+    for version in ["0", "1", "2"] {
+        let fetch = |program: &Path| {
+            let output = git_command(&client)
+                .args([
+                    "-c",
+                    &format!("protocol.version={version}"),
+                    "fetch",
+                    &format!("--upload-pack={}", program.display()),
+                    served.to_str().unwrap(),
+                ])
+                .output()
+                .unwrap();
+            assert!(!output.status.success(), "v{version}: formats can't be mixed");
+            String::from_utf8(output.stderr)
+                .unwrap()
+                .lines()
+                .next()
+                .unwrap()
+                .to_owned()
+        };
+        assert_eq!(fetch(&upload_pack), fetch(Path::new("git-upload-pack")), "v{version}");
+    }
+}
+
+#[test]
+fn only_configured_hash_algorithms_are_served() {
+    let tmp = tempfile::tempdir().unwrap();
+    let repo = repository(tmp.path(), "sha1", "sha1");
+    let options = ServerOptions {
+        hash_algorithms: Vec::new(),
+        ..Default::default()
+    };
+    let mut server = Server::new(&repo, options).unwrap();
+    let mut session = server.step_session(ProtocolVersion::V2).unwrap();
+    session.push_input(format!("{}00010000", pkt("command=ls-refs\n")).as_bytes());
+    session.finish_input();
+    let err = session.serve_step(&mut Vec::new()).unwrap_err();
+    assert_eq!(err.to_string(), "mismatched object format: server sha1; client sha1");
+}
@@ -0,0 +1,139 @@
+//! The protocol version is the one clients asked for, downgraded for v0 requests or overridden by the server options
+
+use crate::util::{git, pkt};
+use gix_upload_pack::server::protocol_detection::ProtocolDetector;
+use gix_upload_pack::{ProtocolVersion, Server, ServerOptions};
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+/// Run `gix-upload-pack` with `args` on `repo` with `GIT_PROTOCOL` set to `protocol`, sending `request`
+fn run(repo: &Path, args: &[&str], protocol: &str, request: &str) -> Vec<u8> {
+    let mut child = Command::new(assert_cmd::cargo::cargo_bin("gix-upload-pack"))
+        .args(args)
+        .arg(repo)
+        .env("GIT_PROTOCOL", protocol)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .expect("upload-pack can be started");
+    child.stdin.take().unwrap().write_all(request.as_bytes()).unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success(), "upload-pack failed for {protocol:?}");
+    output.stdout
+}
+
+#[test]
+fn git_protocol_parameters_are_parsed_like_git() {
+    for (value, expected) in [
+        ("version=2", ProtocolVersion::V2),
+        ("version=1", ProtocolVersion::V1),
+        ("version=2:object-format=sha1", ProtocolVersion::V2),
+        ("version=1:version=2", ProtocolVersion::V2),
+        ("version=2:version=1", ProtocolVersion::V2),
+        ("version=3", ProtocolVersion::V0),
+        ("version=3:version=1", ProtocolVersion::V1),
+        ("version=", ProtocolVersion::V0),
+        ("", ProtocolVersion::V0),
+    ] {
+        assert_eq!(ProtocolDetector::from_git_protocol(value), expected, "{value:?}");
+    }
+}
+
+#[test]
+fn versions_are_only_ever_downgraded() {
+    let want = pkt(&format!("want {} side-band-64k\n", "1".repeat(40)));
+    let command = pkt("command=fetch\n");
+    assert_eq!(
+        ProtocolDetector::negotiate(ProtocolVersion::V2, want.as_bytes()),
+        ProtocolVersion::V0
+    );
+    assert_eq!(
+        ProtocolDetector::negotiate(ProtocolVersion::V2, command.as_bytes()),
+        ProtocolVersion::V2
+    );
+    assert_eq!(
+        ProtocolDetector::negotiate(ProtocolVersion::V2, b"0000"),
+        ProtocolVersion::V2
+    );
+    assert_eq!(
+        ProtocolDetector::negotiate(ProtocolVersion::V2, b"0032wa"),
+        ProtocolVersion::V2,
+        "too short to tell"
+    );
+    assert_eq!(
+        ProtocolDetector::negotiate(ProtocolVersion::V2, b"0032want"),
+        ProtocolVersion::V2,
+        "may still be a longer line"
+    );
+    assert_eq!(
+        ProtocolDetector::negotiate(ProtocolVersion::V2, b"want "),
+        ProtocolVersion::V2,
+        "not a pkt-line"
+    );
+    assert_eq!(
+        ProtocolDetector::negotiate(ProtocolVersion::V1, want.as_bytes()),
+        ProtocolVersion::V1
+    );
+    assert_eq!(
+        ProtocolDetector::negotiate(ProtocolVersion::V0, command.as_bytes()),
+        ProtocolVersion::V0,
+        "clients have to ask for v2"
+    );
+}
+
+#[test]
+fn v0_requests_of_clients_asking_for_v2_are_served_with_v0() {
+    let tmp = tempfile::tempdir().unwrap();
+    git(tmp.path(), &["init", "--quiet"]);
+    git(tmp.path(), &["commit", "--quiet", "--allow-empty", "-m", "initial"]);
+    let head = git(tmp.path(), &["rev-parse", "HEAD"]);
+    let request = format!(
+        "{}0000{}",
+        pkt(&format!("want {head} side-band-64k no-progress\n")),
+        pkt("done\n")
+    );
+
+    let output = run(tmp.path(), &["--stateless-rpc"], "version=2", &request);
+    assert!(output.starts_with(b"0008NAK\n"), "the v0 response follows right away");
+    assert!(output.windows(4).any(|window| window == b"PACK"));
+
+    let output = run(
+        tmp.path(),
+        &["--stateless-rpc", "--advertise-refs"],
+        "version=2:object-format=sha1",
+        "",
+    );
+    assert!(
+        output.starts_with(b"000eversion 2\n"),
+        "other parameters don't hide the version"
+    );
+}
+
+#[test]
+fn the_protocol_override_wins() {
+    let tmp = tempfile::tempdir().unwrap();
+    git(tmp.path(), &["init", "--quiet"]);
+    git(tmp.path(), &["commit", "--quiet", "--allow-empty", "-m", "initial"]);
+    let options = ServerOptions::default()
+        .with_stateless_rpc(true)
+        .with_advertise_refs(true)
+        .with_repository_overrides(false);
+
+    for (version, expected) in [
+        (ProtocolVersion::V2, "000eversion 2\n"),
+        (ProtocolVersion::V1, "000eversion 1\n"),
+    ] {
+        let mut output = Vec::new();
+        Server::new(tmp.path(), options.clone().with_protocol(version))
+            .unwrap()
+            .serve(&b""[..], &mut output)
+            .unwrap();
+        assert!(
+            output.starts_with(expected.as_bytes()),
+            "{version:?}: {}",
+            output.escape_ascii()
+        );
+    }
+}
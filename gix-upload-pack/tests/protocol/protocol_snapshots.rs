@@ -0,0 +1,106 @@
+//! Complete conversations match the transcripts in `tests/snapshots/`
+//!
+//! Run with `GIX_SERVE_UPDATE_SNAPSHOTS=1` to rewrite the transcripts after intended protocol changes
+#![cfg(feature = "serve-core")]
+
+use crate::util::{git_command, pkt, run};
+use gix_serve_core::protocol::ProtocolVersion;
+use gix_serve_core::service::{GitService, ServiceContext};
+use gix_serve_core::testing::snapshot::{Normalization, Transcript};
+use gix_upload_pack::{Server, ServerOptions};
+use std::path::{Path, PathBuf};
+
+/// Run git in `dir` with a fixed date for new commits, so their ids are the same on every run
+fn git(dir: &Path, args: &[&str]) -> String {
+    run(git_command(dir)
+        .env("GIT_AUTHOR_DATE", "1700000000 +0000")
+        .env("GIT_COMMITTER_DATE", "1700000000 +0000")
+        .args(args))
+}
+
+/// A repository with two commits on `main` and an annotated tag, with the same object ids on every run
+fn repository(dir: &Path) -> (String, String) {
+    git(dir, &["init", "--quiet", "--initial-branch=main"]);
+    for revision in 1..=2 {
+        std::fs::write(dir.join("file"), format!("revision {revision}\n")).unwrap();
+        git(dir, &["add", "file"]);
+        git(dir, &["commit", "--quiet", "-m", &format!("revision {revision}")]);
+    }
+    git(dir, &["tag", "-a", "-m", "release", "v1.0", "HEAD~1"]);
+    (git(dir, &["rev-parse", "HEAD~1"]), git(dir, &["rev-parse", "HEAD"]))
+}
+
+fn golden(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/snapshots")
+        .join(format!("{name}.txt"))
+}
+
+/// Record the advertisement and the conversation of serving the request made by `request` from the first and second commit
+fn converse(title: &str, version: ProtocolVersion, request: impl FnOnce(&str, &str) -> String) -> Transcript {
+    let tmp = tempfile::tempdir().unwrap();
+    let (first, second) = repository(tmp.path());
+    let ctx = ServiceContext::new(version).with_stateless(true);
+    let mut server = Server::new(tmp.path(), ServerOptions::default().with_repository_overrides(false)).unwrap();
+
+    let mut transcript = Transcript::new(title).with_normalization(
+        Normalization::default()
+            .with_replacement(&first, "<first>")
+            .with_replacement(&second, "<second>"),
+    );
+    let mut advertisement = Vec::new();
+    server.advertise(&mut advertisement, &ctx).unwrap();
+    transcript.response(&advertisement);
+    let request = request(&first, &second);
+    let mut response = Vec::new();
+    GitService::serve(&mut server, &mut request.as_bytes(), &mut response, &ctx).unwrap();
+    transcript.request(request.as_bytes()).response(&response);
+    transcript
+}
+
+#[test]
+fn v0_fetch() {
+    converse(
+        "protocol v0 fetch with a common commit",
+        ProtocolVersion::V0,
+        |first, second| {
+            pkt(&format!("want {second} multi_ack_detailed side-band-64k ofs-delta\n"))
+                + "0000"
+                + &pkt(&format!("have {first}\n"))
+                + &pkt("done\n")
+        },
+    )
+    .assert_golden(golden("v0-fetch"));
+}
+
+#[test]
+fn v2_ls_refs() {
+    converse(
+        "protocol v2 ls-refs with peeled tags and symrefs",
+        ProtocolVersion::V2,
+        |_, _| {
+            pkt("command=ls-refs\n") + &pkt("agent=git/2.45.0\n") + "0001" + &pkt("peel\n") + &pkt("symrefs\n") + "0000"
+        },
+    )
+    .assert_golden(golden("v2-ls-refs"));
+}
+
+#[test]
+fn v2_fetch() {
+    converse(
+        "protocol v2 fetch with a common commit",
+        ProtocolVersion::V2,
+        |first, second| {
+            pkt("command=fetch\n")
+                + &pkt("agent=git/2.45.0\n")
+                + "0001"
+                + &pkt("thin-pack\n")
+                + &pkt("ofs-delta\n")
+                + &pkt(&format!("want {second}\n"))
+                + &pkt(&format!("have {first}\n"))
+                + &pkt("done\n")
+                + "0000"
+        },
+    )
+    .assert_golden(golden("v2-fetch"));
+}
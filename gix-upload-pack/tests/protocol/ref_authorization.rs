@@ -0,0 +1,117 @@
+//! Refs a client isn't authorized for are neither advertised nor fetchable
+
+use crate::util::{git, pkt};
+use bstr::{BStr, BString};
+use gix_upload_pack::server::Step;
+use gix_upload_pack::{Error, ProtocolVersion, Server, ServerOptions};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+/// A repository with `main` and a `release` branch one commit ahead, returning the tips of both
+fn repo_with_release_branch(dir: &Path) -> (String, String) {
+    git(dir, &["init", "--quiet", "--initial-branch=main"]);
+    git(dir, &["commit", "--quiet", "--allow-empty", "-m", "initial"]);
+    let main = git(dir, &["rev-parse", "HEAD"]);
+    git(dir, &["checkout", "--quiet", "-b", "release"]);
+    git(dir, &["commit", "--quiet", "--allow-empty", "-m", "release"]);
+    let release = git(dir, &["rev-parse", "HEAD"]);
+    git(dir, &["checkout", "--quiet", "main"]);
+    (main, release)
+}
+
+/// Options hiding `refs/heads/release` from the client, recording each ref name the authorizer is asked about
+fn options(asked: &Arc<Mutex<Vec<BString>>>) -> ServerOptions {
+    let asked = asked.clone();
+    ServerOptions::default()
+        .with_repository_overrides(false)
+        .with_ref_authorizer(move |name: &BStr| {
+            asked.lock().unwrap().push(name.to_owned());
+            name != "refs/heads/release"
+        })
+}
+
+fn serve(server: &mut Server, version: ProtocolVersion, request: &str) -> Result<String, Error> {
+    let mut session = server.step_session(version)?;
+    session.push_input(request.as_bytes());
+    session.finish_input();
+    let mut output = Vec::new();
+    while session.serve_step(&mut output)? != Step::Done {}
+    Ok(String::from_utf8_lossy(&output).into_owned())
+}
+
+#[test]
+fn unauthorized_refs_are_not_advertised() {
+    let tmp = tempfile::tempdir().unwrap();
+    let (main, release) = repo_with_release_branch(tmp.path());
+    let asked = Arc::default();
+
+    let mut server = Server::new(tmp.path(), options(&asked).with_advertise_refs(true)).unwrap();
+    let advertisement = serve(&mut server, ProtocolVersion::V0, "").unwrap();
+    assert!(
+        advertisement.contains(&format!("{main} refs/heads/main")),
+        "{advertisement}"
+    );
+    assert!(!advertisement.contains(&release), "{advertisement}");
+
+    let mut server = Server::new(tmp.path(), options(&asked)).unwrap();
+    let ls_refs = format!("{}0001{}0000", pkt("command=ls-refs\n"), pkt("symrefs\n"));
+    let output = serve(&mut server, ProtocolVersion::V2, &ls_refs).unwrap();
+    assert!(
+        output.contains(&format!("{main} HEAD symref-target:refs/heads/main")),
+        "{output}"
+    );
+    assert!(!output.contains("refs/heads/release"), "{output}");
+
+    asked.lock().unwrap().clear();
+    let mut server = Server::new(tmp.path(), options(&asked).with_protocol(ProtocolVersion::V2)).unwrap();
+    let mut output = Vec::new();
+    server
+        .serve(format!("{ls_refs}{ls_refs}").as_bytes(), &mut output)
+        .unwrap();
+    let output = String::from_utf8(output).unwrap();
+    assert_eq!(
+        output.matches("refs/heads/main").count(),
+        4,
+        "both requests of the connection were answered: {output}"
+    );
+    let mut asked = asked.lock().unwrap().clone();
+    asked.sort();
+    assert_eq!(
+        asked,
+        ["HEAD", "refs/heads/main", "refs/heads/release"],
+        "each ref is only authorized once per connection"
+    );
+}
+
+#[test]
+fn unauthorized_refs_cannot_be_fetched() {
+    let tmp = tempfile::tempdir().unwrap();
+    let (main, release) = repo_with_release_branch(tmp.path());
+    let fetch = |want: &str| {
+        format!(
+            "{}0001{}{}0000",
+            pkt("command=fetch\n"),
+            pkt(&format!("want {want}\n")),
+            pkt("done\n")
+        )
+    };
+
+    let mut server = Server::new(tmp.path(), options(&Arc::default())).unwrap();
+    let output = serve(&mut server, ProtocolVersion::V2, &fetch(&main)).unwrap();
+    assert!(output.contains("PACK"), "authorized tips can be fetched");
+
+    let err = serve(&mut server, ProtocolVersion::V2, &fetch(&release)).unwrap_err();
+    assert!(
+        matches!(err, Error::NotOurRef { oid } if oid.to_string() == release),
+        "{err}"
+    );
+
+    let mut any = options(&Arc::default());
+    any.allow_any_sha1_in_want = true;
+    let mut server = Server::new(tmp.path(), any).unwrap();
+    let err = serve(&mut server, ProtocolVersion::V2, &fetch(&release)).unwrap_err();
+    assert!(
+        matches!(err, Error::NotOurRef { .. }),
+        "authorization also applies to any object: {err}"
+    );
+}
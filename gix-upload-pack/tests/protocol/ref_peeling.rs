@@ -0,0 +1,89 @@
+//! Annotated tags are advertised with their peeled value, unless the peel budget is spent
+
+use crate::util::{git, native_v2, pkt};
+use gix_upload_pack::{server::Step, ProtocolVersion, Server, ServerOptions};
+use std::path::Path;
+use std::time::Duration;
+
+/// A repository with a lightweight tag, an annotated tag and an annotated tag of that tag
+fn repo_with_tags(dir: &Path) {
+    git(dir, &["init", "--quiet", "--initial-branch=main"]);
+    git(dir, &["commit", "--quiet", "--allow-empty", "-m", "initial"]);
+    git(dir, &["tag", "light"]);
+    git(dir, &["tag", "-a", "-m", "annotated", "annotated"]);
+    git(dir, &["tag", "-a", "-m", "nested", "nested", "annotated"]);
+}
+
+fn serve(dir: &Path, options: ServerOptions, version: ProtocolVersion, request: &str) -> String {
+    let options = options.with_stateless_rpc(true).with_repository_overrides(false);
+    let mut server = Server::new(dir, options).unwrap();
+    let mut session = server.step_session(version).unwrap();
+    session.push_input(request.as_bytes());
+    session.finish_input();
+    let mut output = Vec::new();
+    while session.serve_step(&mut output).unwrap() != Step::Done {}
+    String::from_utf8(output).unwrap()
+}
+
+fn ls_refs(peel: bool) -> String {
+    let peel = if peel { pkt("peel\n") } else { String::new() };
+    format!("{}0001{peel}{}0000", pkt("command=ls-refs\n"), pkt("symrefs\n"))
+}
+
+#[test]
+fn ls_refs_peels_like_git() {
+    let tmp = tempfile::tempdir().unwrap();
+    repo_with_tags(tmp.path());
+    let commit = git(tmp.path(), &["rev-parse", "HEAD"]);
+
+    for peel in [true, false] {
+        let output = serve(
+            tmp.path(),
+            ServerOptions::default(),
+            ProtocolVersion::V2,
+            &ls_refs(peel),
+        );
+        assert_eq!(output, native_v2(tmp.path(), &ls_refs(peel)), "peel: {peel}");
+        assert!(!output.contains("^{}"), "v2 has no peeled ref lines");
+    }
+    let output = serve(
+        tmp.path(),
+        ServerOptions::default(),
+        ProtocolVersion::V2,
+        &ls_refs(true),
+    );
+    assert!(
+        output.contains(&format!("refs/tags/nested peeled:{commit}")),
+        "tags of tags are peeled to the end: {output}"
+    );
+}
+
+#[test]
+fn a_spent_peel_budget_leaves_refs_unpeeled() {
+    let tmp = tempfile::tempdir().unwrap();
+    repo_with_tags(tmp.path());
+    let commit = git(tmp.path(), &["rev-parse", "HEAD"]);
+    let budget = ServerOptions::default().with_peel_budget(Duration::ZERO);
+
+    let output = serve(tmp.path(), budget.clone(), ProtocolVersion::V2, &ls_refs(true));
+    assert!(output.contains("refs/tags/annotated\n"), "{output}");
+    assert!(!output.contains("peeled:"), "{output}");
+
+    let advertisement = |options: ServerOptions| {
+        let output = serve(tmp.path(), options.with_advertise_refs(true), ProtocolVersion::V0, "");
+        output.lines().filter(|line| line.ends_with("^{}")).count()
+    };
+    assert_eq!(
+        advertisement(ServerOptions::default()),
+        2,
+        "both annotated tags are peeled"
+    );
+    assert_eq!(advertisement(budget), 0, "peeled lines are omitted");
+
+    let generous = ServerOptions::default().with_peel_budget(Duration::from_secs(60));
+    let output = serve(tmp.path(), generous, ProtocolVersion::V2, &ls_refs(true));
+    assert!(
+        output.contains(&format!("refs/tags/annotated peeled:{commit}")),
+        "{output}"
+    );
+}
@@ -0,0 +1,91 @@
+//! Fixed ref snapshots replace live refs for advertisement and want validation
+
+use crate::util::{git, pkt};
+use gix_hash::ObjectId;
+use gix_upload_pack::{services::RefSnapshot, Error, ProtocolVersion, Server, ServerOptions};
+use std::path::Path;
+
+/// A repository with two commits on `main`, returning the first and second commit
+fn repo_with_two_commits(dir: &Path) -> (String, String) {
+    git(dir, &["init", "--quiet", "--initial-branch=main"]);
+    let mut commits = Vec::new();
+    for revision in 1..=2 {
+        std::fs::write(dir.join("file"), format!("revision {revision}\n")).unwrap();
+        git(dir, &["add", "file"]);
+        git(dir, &["commit", "--quiet", "-m", &format!("revision {revision}")]);
+        commits.push(git(dir, &["rev-parse", "HEAD"]));
+    }
+    (commits[0].clone(), commits[1].clone())
+}
+
+fn serve(dir: &Path, options: ServerOptions, request: String) -> Result<String, Error> {
+    let mut server = Server::new(
+        dir,
+        options
+            .with_repository_overrides(false)
+            .with_protocol(ProtocolVersion::V2),
+    )?;
+    let mut output = Vec::new();
+    server.serve(request.as_bytes(), &mut output)?;
+    Ok(String::from_utf8_lossy(&output).into_owned())
+}
+
+fn snapshot_at(commit: &str) -> RefSnapshot {
+    let id = ObjectId::from_hex(commit.as_bytes()).unwrap();
+    RefSnapshot::new("cycle-1", [("refs/heads/main", id), ("HEAD", id)]).with_symref("HEAD", "refs/heads/main")
+}
+
+#[test]
+fn ls_refs_advertises_the_snapshot() {
+    let tmp = tempfile::tempdir().unwrap();
+    let (first, second) = repo_with_two_commits(tmp.path());
+
+    let request = format!(
+        "{}0001{}{}{}0000",
+        pkt("command=ls-refs\n"),
+        pkt("symrefs\n"),
+        pkt("ref-prefix HEAD\n"),
+        pkt("ref-prefix refs/heads/\n")
+    );
+    let output = serve(
+        tmp.path(),
+        ServerOptions::default().with_ref_snapshot(snapshot_at(&first)),
+        request,
+    )
+    .unwrap();
+    assert!(
+        output.contains(&format!("{first} HEAD symref-target:refs/heads/main")),
+        "{output}"
+    );
+    assert!(output.contains(&format!("{first} refs/heads/main")), "{output}");
+    assert!(!output.contains(&second), "live refs must not be read: {output}");
+}
+
+#[test]
+fn wants_are_validated_against_the_snapshot() {
+    let tmp = tempfile::tempdir().unwrap();
+    let (first, second) = repo_with_two_commits(tmp.path());
+    let fetch = |want: &str| {
+        format!(
+            "{}0001{}{}0000",
+            pkt("command=fetch\n"),
+            pkt(&format!("want {want}\n")),
+            pkt("done\n")
+        )
+    };
+
+    let options = ServerOptions::default().with_ref_snapshot(snapshot_at(&first));
+    let output = serve(tmp.path(), options.clone(), fetch(&first)).unwrap();
+    assert!(output.contains("PACK"), "snapshot tips can be fetched");
+
+    let err = serve(tmp.path(), options.clone(), fetch(&second)).unwrap_err();
+    assert!(
+        matches!(err, Error::NotOurRef { oid } if oid.to_string() == second),
+        "the live tip isn't part of the snapshot: {err}"
+    );
+
+    let mut reachable = ServerOptions::default().with_ref_snapshot(snapshot_at(&second));
+    reachable.allow_reachable_sha1_in_want = true;
+    let output = serve(tmp.path(), reachable, fetch(&first)).unwrap();
+    assert!(output.contains("PACK"), "ancestors of snapshot tips are reachable");
+}
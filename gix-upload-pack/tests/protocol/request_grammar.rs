@@ -0,0 +1,98 @@
+//! Protocol v2 request grammar conformance against recorded requests and a corpus of malformed ones
+
+use gix_upload_pack::{
+    protocol::request::{DiagnosticKind, Request},
+    Error,
+};
+use std::path::Path;
+
+const MALFORMED: &str = "tests/fixtures/v2-malformed";
+
+/// The expected diagnostic for each fixture in the malformed corpus, as `(name, packet, offset, kind)`
+fn expectations() -> Vec<(&'static str, usize, usize, DiagnosticKind)> {
+    use DiagnosticKind::*;
+    vec![
+        ("no-command", 1, 21, MissingCommand),
+        ("only-capabilities", 2, 44, MissingCommand),
+        ("delim-first", 0, 0, MissingCommand),
+        ("empty-command", 0, 0, EmptyCommand),
+        (
+            "duplicate-command",
+            2,
+            41,
+            DuplicateCommand {
+                first: "ls-refs".into(),
+                second: "fetch".into(),
+            },
+        ),
+        ("double-delim", 2, 24, UnexpectedDelimiter),
+        ("double-delim-after-arguments", 3, 72, UnexpectedDelimiter),
+        ("delim-between-arguments", 5, 66, UnexpectedDelimiter),
+        ("response-end-first", 0, 0, UnexpectedResponseEnd),
+        ("response-end-in-capabilities", 1, 20, UnexpectedResponseEnd),
+        ("response-end-in-arguments", 3, 72, UnexpectedResponseEnd),
+        ("missing-flush-after-command", 1, 20, MissingFlush),
+        ("missing-flush-after-delim", 3, 45, MissingFlush),
+        ("missing-flush-after-arguments", 5, 102, MissingFlush),
+        ("truncated-length", 1, 20, Truncated),
+        ("truncated-payload", 2, 24, Truncated),
+        ("non-hex-length", 1, 20, InvalidLength("zz14".into())),
+        ("signed-length", 1, 20, InvalidLength("+014".into())),
+        ("reserved-length", 1, 20, ReservedLength(3)),
+        ("empty-data-packet", 1, 20, EmptyPacket),
+        ("empty-data-packet-in-arguments", 2, 24, EmptyPacket),
+        ("too-long", 1, 20, TooLong(0xfff1)),
+        ("max-length-header", 1, 20, TooLong(0xffff)),
+    ]
+}
+
+#[test]
+fn recorded_requests_are_legal() {
+    let data = std::fs::read("tests/fixtures/v2-ls-refs.tcp").unwrap();
+    let request = Request::parse(&data).unwrap().expect("command request");
+    assert_eq!(request.command, "ls-refs");
+    assert_eq!(request.capabilities.len(), 2);
+    assert_eq!(request.arguments.len(), 6);
+    assert!(request.arguments.contains(&"ref-prefix refs/tags/".into()));
+
+    let data = std::fs::read("tests/fixtures/v2-fetch.tcp").unwrap();
+    let request = Request::parse(&data).unwrap().expect("command request");
+    assert_eq!(request.command, "fetch");
+    assert!(request.has_capability("object-format"));
+    assert_eq!(request.arguments.last().map(|line| line.as_slice()), Some(&b"done"[..]));
+}
+
+#[test]
+fn malformed_requests_are_diagnosed() {
+    let expectations = expectations();
+    let mut corpus: Vec<String> = std::fs::read_dir(MALFORMED)
+        .unwrap()
+        .map(|entry| {
+            let path = entry.unwrap().path();
+            path.file_stem().unwrap().to_str().unwrap().to_owned()
+        })
+        .collect();
+    corpus.sort();
+    let mut expected: Vec<_> = expectations.iter().map(|(name, ..)| name.to_string()).collect();
+    expected.sort();
+    assert_eq!(corpus, expected, "every fixture has exactly one expectation");
+
+    for (name, packet, offset, kind) in expectations {
+        let data = std::fs::read(Path::new(MALFORMED).join(format!("{name}.pkt"))).unwrap();
+        match Request::parse(&data) {
+            Err(Error::MalformedRequest(diagnostic)) => {
+                assert_eq!(
+                    (diagnostic.packet, diagnostic.offset, &diagnostic.kind),
+                    (packet, offset, &kind),
+                    "{name}"
+                );
+                let message = Error::MalformedRequest(diagnostic).to_string();
+                assert!(
+                    message.contains(&format!("packet {packet} at offset {offset}")),
+                    "{name}: {message}"
+                );
+            }
+            other => panic!("{name}: expected a diagnostic, got {other:?}"),
+        }
+    }
+}
@@ -0,0 +1,125 @@
+//! Serving through the uniform `GitService` trait of gix-serve-core
+#![cfg(feature = "serve-core")]
+
+use crate::util::{git, pkt};
+use gix_serve_core::protocol::{ProtocolVersion, ServiceKind};
+use gix_serve_core::service::{Error, GitService, ServiceContext};
+use gix_upload_pack::log::{Level, Log, Logger};
+use gix_upload_pack::{Server, ServerOptions};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+fn service(dir: &Path) -> Box<dyn GitService> {
+    git(dir, &["init", "--quiet", "--initial-branch=main"]);
+    git(dir, &["commit", "--quiet", "--allow-empty", "-m", "initial"]);
+    let options = ServerOptions::default().with_repository_overrides(false);
+    Box::new(Server::new(dir, options).unwrap())
+}
+
+#[test]
+fn stateless_v2_requests() {
+    let tmp = tempfile::tempdir().unwrap();
+    let mut service = service(tmp.path());
+    assert_eq!(service.kind(), ServiceKind::UploadPack);
+    let ctx = ServiceContext::new(ProtocolVersion::V2).with_stateless(true);
+
+    let mut advertisement = Vec::new();
+    service.advertise(&mut advertisement, &ctx).unwrap();
+    let advertisement = String::from_utf8(advertisement).unwrap();
+    assert!(advertisement.starts_with(&pkt("version 2\n")), "{advertisement}");
+    assert!(advertisement.contains("ls-refs"), "{advertisement}");
+
+    let head = git(tmp.path(), &["rev-parse", "HEAD"]);
+    let request = pkt("command=ls-refs\n") + "0000";
+    let mut output = Vec::new();
+    let outcome = service.serve(&mut request.as_bytes(), &mut output, &ctx).unwrap();
+    assert!(outcome.ref_updates.is_empty());
+    assert_eq!(
+        (outcome.wire.request_bytes, outcome.wire.response_bytes),
+        (request.len() as u64, output.len() as u64)
+    );
+    let output = String::from_utf8(output).unwrap();
+    assert!(output.contains(&pkt(&format!("{head} refs/heads/main\n"))), "{output}");
+    assert!(output.ends_with("0000"), "{output}");
+}
+
+#[test]
+fn client_errors_are_validation_errors() {
+    let tmp = tempfile::tempdir().unwrap();
+    let mut service = service(tmp.path());
+    let ctx = ServiceContext::new(ProtocolVersion::V2).with_stateless(true);
+    let request = pkt("command=fetch\n")
+        + "0001"
+        + &pkt("want 1111111111111111111111111111111111111111\n")
+        + &pkt("done\n")
+        + "0000";
+    let err = service
+        .serve(&mut request.as_bytes(), &mut Vec::new(), &ctx)
+        .unwrap_err();
+    assert!(matches!(err, Error::Validation(_)), "{err}");
+}
+
+#[test]
+fn the_remote_address_is_logged() {
+    #[derive(Default, Clone)]
+    struct Capture(Arc<Mutex<Vec<String>>>);
+
+    impl Log for Capture {
+        fn enabled(&self, level: Level) -> bool {
+            level <= Level::Debug
+        }
+
+        fn log(&self, _level: Level, message: std::fmt::Arguments<'_>) {
+            self.0.lock().unwrap().push(message.to_string());
+        }
+    }
+
+    let tmp = tempfile::tempdir().unwrap();
+    service(tmp.path());
+    let capture = Capture::default();
+    let options = ServerOptions::default()
+        .with_repository_overrides(false)
+        .with_logger(Logger::new(capture.clone()));
+    let mut service: Box<dyn GitService> = Box::new(Server::new(tmp.path(), options).unwrap());
+
+    let client = "192.0.2.1:51234".parse().unwrap();
+    let ctx = ServiceContext::new(ProtocolVersion::V2)
+        .with_stateless(true)
+        .with_remote_addr(client);
+    let request = pkt("command=ls-refs\n") + "0000";
+    service.serve(&mut request.as_bytes(), &mut Vec::new(), &ctx).unwrap();
+    assert!(
+        capture.0.lock().unwrap().contains(&format!("Serving client {client}")),
+        "the address of the client is known to the session"
+    );
+}
+
+#[test]
+fn advertisement_fingerprints_change_with_the_advertised_refs() {
+    let tmp = tempfile::tempdir().unwrap();
+    let mut service = service(tmp.path());
+    let fingerprint = |service: &mut Box<dyn GitService>, version| {
+        let ctx = ServiceContext::new(version).with_stateless(true);
+        service
+            .advertisement_fingerprint(&ctx)
+            .unwrap()
+            .expect("upload-pack computes one")
+    };
+    let v0 = fingerprint(&mut service, ProtocolVersion::V0);
+    let v2 = fingerprint(&mut service, ProtocolVersion::V2);
+    assert_eq!(v0, fingerprint(&mut service, ProtocolVersion::V0), "it's stable");
+    assert_ne!(v0, fingerprint(&mut service, ProtocolVersion::V1));
+    assert_ne!(v0, v2);
+
+    git(tmp.path(), &["commit", "--quiet", "--allow-empty", "-m", "second"]);
+    assert_ne!(v0, fingerprint(&mut service, ProtocolVersion::V0), "refs moved");
+    assert_eq!(
+        v2,
+        fingerprint(&mut service, ProtocolVersion::V2),
+        "v2 advertises no refs"
+    );
+
+    let v0 = fingerprint(&mut service, ProtocolVersion::V0);
+    git(tmp.path(), &["branch", "feature"]);
+    assert_ne!(v0, fingerprint(&mut service, ProtocolVersion::V0), "refs were added");
+}
@@ -0,0 +1,287 @@
+//! Shallow clones and fetches deepening them get the boundary and the pack native git computes
+#![cfg(feature = "serve-core")]
+
+use crate::util::{git, git_command, pkt, run};
+use gix_hash::ObjectId;
+use gix_upload_pack::{server::Step, Error, ProtocolVersion, Server, ServerOptions};
+use std::collections::HashSet;
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+/// Run git in `dir` with new commits `time - 1000` seconds after `START`
+fn git_at(dir: &Path, time: u32, args: &[&str]) -> String {
+    let date = format!("{} +0000", START + time - 1000);
+    run(git_command(dir)
+        .env("GIT_AUTHOR_DATE", &date)
+        .env("GIT_COMMITTER_DATE", &date)
+        .args(args))
+}
+
+/// The time of the first commit, with the next one following every 1000 seconds
+const START: u32 = 1_700_001_000;
+
+/// A history with a merge, with commits at `START` and every 1000 seconds after it:
+///
+/// ```text
+/// one - two - three - merge - four   (main)
+///          \- side -/
+/// ```
+fn repository(dir: &Path) {
+    git(dir, &["init", "--quiet", "--initial-branch=main"]);
+    let commit = |message: &str, time: u32| {
+        std::fs::write(dir.join(format!("{message}.txt")), format!("{message}\n")).unwrap();
+        git(dir, &["add", "."]);
+        git_at(dir, time, &["commit", "--quiet", "-m", message]);
+    };
+    commit("one", 1000);
+    commit("two", 2000);
+    git(dir, &["tag", "base"]);
+    git(dir, &["checkout", "--quiet", "-b", "side"]);
+    commit("side", 3000);
+    git(dir, &["checkout", "--quiet", "main"]);
+    commit("three", 4000);
+    git_at(dir, 5000, &["merge", "--quiet", "--no-ff", "-m", "merge", "side"]);
+    commit("four", 6000);
+}
+
+/// Build a stateless `version` request wanting `main` with `lines` following the wants
+///
+/// `deepen-relative` is a capability of v0 clients and an argument of v2 clients.
+fn request(dir: &Path, version: ProtocolVersion, lines: &[String]) -> String {
+    let head = git(dir, &["rev-parse", "main"]);
+    let relative = lines.iter().any(|line| line == "deepen-relative");
+    let lines = lines.iter().filter(|line| *line != "deepen-relative");
+    match version {
+        ProtocolVersion::V2 => {
+            let mut request = pkt("command=fetch\n") + "0001" + &pkt(&format!("want {head}\n"));
+            if relative {
+                request += &pkt("deepen-relative\n");
+            }
+            for line in lines {
+                request += &pkt(&format!("{line}\n"));
+            }
+            request + &pkt("done\n") + "0000"
+        }
+        _ => {
+            let mut capabilities = "side-band-64k shallow deepen-since deepen-not".to_owned();
+            if relative {
+                capabilities += " deepen-relative";
+            }
+            let mut request = pkt(&format!("want {head} {capabilities}\n"));
+            for line in lines {
+                request += &pkt(&format!("{line}\n"));
+            }
+            request + "0000" + &pkt("done\n")
+        }
+    }
+}
+
+fn serve(dir: &Path, version: ProtocolVersion, request: &str, options: ServerOptions) -> Result<Vec<u8>, Error> {
+    let options = options.with_stateless_rpc(true).with_repository_overrides(false);
+    let mut server = Server::new(dir, options).unwrap();
+    let mut session = server.step_session(version)?;
+    session.push_input(request.as_bytes());
+    session.finish_input();
+    let mut output = Vec::new();
+    while session.serve_step(&mut output)? != Step::Done {}
+    Ok(output)
+}
+
+fn serve_natively(dir: &Path, version: ProtocolVersion, request: &str) -> Vec<u8> {
+    let mut upload_pack = Command::new("git")
+        .args(["upload-pack", "--stateless-rpc", "."])
+        .current_dir(dir)
+        .env("GIT_PROTOCOL", format!("version={}", version as u8))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("git is installed");
+    upload_pack.stdin.take().unwrap().write_all(request.as_bytes()).unwrap();
+    let output = upload_pack.wait_with_output().unwrap();
+    assert!(output.status.success(), "native upload-pack fails");
+    output.stdout
+}
+
+/// A response split into its sorted `shallow` and `unshallow` lines, the other lines and the objects of its pack
+#[derive(Debug, PartialEq, Eq)]
+struct Response {
+    shallow: Vec<String>,
+    lines: Vec<String>,
+    objects: HashSet<ObjectId>,
+}
+
+fn parse(dir: &Path, mut output: &[u8]) -> Response {
+    let mut response = Response {
+        shallow: Vec::new(),
+        lines: Vec::new(),
+        objects: HashSet::new(),
+    };
+    let mut pack = Vec::new();
+    while output.len() >= 4 {
+        let len = usize::from_str_radix(std::str::from_utf8(&output[..4]).unwrap(), 16).unwrap();
+        if len < 4 {
+            output = &output[4..];
+            continue;
+        }
+        let data = &output[4..len];
+        output = &output[len..];
+        match data[0] {
+            1 => pack.extend_from_slice(&data[1..]),
+            2 => {}
+            _ => {
+                let line = String::from_utf8(data.to_vec()).unwrap();
+                if line.starts_with("shallow ") || line.starts_with("unshallow ") {
+                    response.shallow.push(line);
+                } else {
+                    response.lines.push(line);
+                }
+            }
+        }
+    }
+    response.shallow.sort();
+    response.objects = index_pack(dir, &pack);
+    response
+}
+
+/// Index `pack` like a client would and return its objects
+fn index_pack(dir: &Path, pack: &[u8]) -> HashSet<ObjectId> {
+    let tmp = tempfile::tempdir().unwrap();
+    let path = tmp.path().join("received.pack");
+    std::fs::write(&path, pack).unwrap();
+    let idx = tmp.path().join("received.idx");
+    git(
+        dir,
+        &["index-pack", "-o", idx.to_str().unwrap(), path.to_str().unwrap()],
+    );
+    git(dir, &["verify-pack", "-v", idx.to_str().unwrap()])
+        .lines()
+        .filter_map(|line| ObjectId::from_hex(line.split_whitespace().next()?.as_bytes()).ok())
+        .collect()
+}
+
+#[test]
+fn boundaries_and_packs_match_git() {
+    let tmp = tempfile::tempdir().unwrap();
+    repository(tmp.path());
+    let merge = git(tmp.path(), &["rev-parse", "main~1"]);
+
+    for version in [ProtocolVersion::V0, ProtocolVersion::V2] {
+        for lines in [
+            vec!["deepen 1".to_owned()],
+            vec!["deepen 3".into()],
+            vec!["deepen 10".into()],
+            vec![format!("deepen-since {}", START + 2500)],
+            vec!["deepen-not base".into()],
+            vec![format!("deepen-since {}", START + 1500), "deepen-not side".into()],
+            vec![format!("shallow {merge}"), "deepen 3".into()],
+            vec![format!("shallow {merge}"), "deepen-relative".into(), "deepen 1".into()],
+            vec![format!("shallow {merge}"), "deepen 2147483647".into()],
+            vec![format!("shallow {merge}")],
+        ] {
+            let request = request(tmp.path(), version, &lines);
+            let ours = serve(tmp.path(), version, &request, ServerOptions::default()).unwrap();
+            let native = serve_natively(tmp.path(), version, &request);
+            assert_eq!(
+                parse(tmp.path(), &ours),
+                parse(tmp.path(), &native),
+                "{version:?} {lines:?}"
+            );
+        }
+    }
+}
+
+#[test]
+fn invalid_deepening_fails() {
+    let tmp = tempfile::tempdir().unwrap();
+    repository(tmp.path());
+    let fetch = |lines: &[&str], options: ServerOptions| {
+        let lines: Vec<_> = lines.iter().map(|line| line.to_string()).collect();
+        let request = request(tmp.path(), ProtocolVersion::V2, &lines);
+        serve(tmp.path(), ProtocolVersion::V2, &request, options)
+    };
+
+    for (lines, message) in [
+        (
+            &["deepen 2", "deepen-since 100"][..],
+            "deepen and deepen-since (or deepen-not) cannot be used together",
+        ),
+        (&["deepen-not missing"], "deepen-not doesn't name a ref: missing"),
+        (
+            &[&format!("deepen-since {}", START + 9000)],
+            "no commits selected for shallow requests",
+        ),
+    ] {
+        match fetch(lines, ServerOptions::default()) {
+            Err(Error::Shallow { message: actual }) => assert_eq!(actual, message),
+            other => panic!("{lines:?} fails, got {other:?}"),
+        }
+    }
+
+    let err = fetch(&["deepen 1"], ServerOptions::default().with_shallow_support(false)).unwrap_err();
+    assert!(matches!(err, Error::Shallow { .. }), "{err:?}");
+
+    let mut limited = ServerOptions::default();
+    limited.max_shallow_depth = Some(2);
+    assert!(fetch(&["deepen 2"], limited.clone()).is_ok());
+    let err = fetch(&["deepen 3"], limited).unwrap_err();
+    assert!(err.to_string().contains("exceeds the maximum depth of 2"), "{err}");
+}
+
+#[test]
+fn native_shallow_clones_deepen_like_with_native_upload_pack() {
+    let tmp = tempfile::tempdir().unwrap();
+    let upstream = tmp.path().join("upstream");
+    std::fs::create_dir(&upstream).unwrap();
+    repository(&upstream);
+    let url = format!("file://{}", upstream.display());
+    let upload_pack = assert_cmd::cargo::cargo_bin("gix-upload-pack");
+
+    for version in ["0", "1", "2"] {
+        let protocol = format!("protocol.version={version}");
+        let clone = |name: &str, upload_pack: &str| {
+            git(
+                tmp.path(),
+                &[
+                    "-c",
+                    &protocol,
+                    "clone",
+                    "--quiet",
+                    "--depth=2",
+                    "--upload-pack",
+                    upload_pack,
+                    &url,
+                    name,
+                ],
+            );
+            tmp.path().join(name)
+        };
+        let ours = clone(&format!("ours-v{version}"), upload_pack.to_str().unwrap());
+        let native = clone(&format!("native-v{version}"), "git-upload-pack");
+        let shallow = |clone: &Path| {
+            let mut commits: Vec<_> = std::fs::read_to_string(clone.join(".git/shallow"))
+                .unwrap_or_default()
+                .lines()
+                .map(ToOwned::to_owned)
+                .collect();
+            commits.sort();
+            commits
+        };
+        assert_eq!(shallow(&ours), shallow(&native), "v{version}: clone");
+
+        for args in [&["--deepen=1"][..], &["--shallow-exclude=base"], &["--unshallow"]] {
+            for (clone, upload_pack) in [(&ours, upload_pack.to_str().unwrap()), (&native, "git-upload-pack")] {
+                let mut fetch = vec!["-c", &protocol, "fetch", "--quiet", "--upload-pack", upload_pack];
+                fetch.extend_from_slice(args);
+                git(clone, &fetch);
+            }
+            assert_eq!(shallow(&ours), shallow(&native), "v{version}: {args:?}");
+            git(&ours, &["fsck", "--connectivity-only", "--no-progress"]);
+        }
+        assert!(!ours.join(".git/shallow").exists(), "complete history");
+        assert_eq!(
+            git(&ours, &["rev-list", "--count", "main"]),
+            git(&upstream, &["rev-list", "--count", "main"])
+        );
+    }
+}
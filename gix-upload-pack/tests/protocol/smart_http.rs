@@ -0,0 +1,148 @@
+//! Native git clients cloning over smart HTTP, served by hyper with the `SmartHttp` tower service
+#![cfg(feature = "hyper")]
+
+use crate::util::git;
+use gix_serve_core::protocol::ServiceKind;
+use gix_serve_core::service::GitService;
+use gix_serve_core::smart_http::SmartHttp;
+use gix_upload_pack::{Server, ServerOptions};
+use hyper::StatusCode;
+use std::path::PathBuf;
+
+/// Serve the repositories in `root` with upload-pack
+fn smart_http(
+    root: PathBuf,
+) -> SmartHttp<impl Fn(&str, ServiceKind) -> Result<Box<dyn GitService + Send>, StatusCode> + Send + Sync + 'static> {
+    SmartHttp::new(move |repository: &str, kind| {
+        if kind != ServiceKind::UploadPack {
+            return Err(StatusCode::FORBIDDEN);
+        }
+        let options = ServerOptions::default().with_repository_overrides(false);
+        let server =
+            Server::new(root.join(repository.trim_start_matches('/')), options).map_err(|_| StatusCode::NOT_FOUND)?;
+        Ok(Box::new(server) as Box<dyn GitService + Send>)
+    })
+}
+
+/// Serve the repositories in `root` on a local port in the background, returning the base URL
+fn serve(root: PathBuf) -> String {
+    let http = smart_http(root);
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let listener = runtime.block_on(tokio::net::TcpListener::bind("127.0.0.1:0")).unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    std::thread::spawn(move || {
+        runtime.block_on(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let service = hyper_util::service::TowerToHyperService::new(http.clone());
+                tokio::spawn(
+                    hyper::server::conn::http1::Builder::new()
+                        .serve_connection(hyper_util::rt::TokioIo::new(stream), service),
+                );
+            }
+        })
+    });
+    url
+}
+
+#[test]
+fn native_clients_clone_and_fetch() {
+    let tmp = tempfile::tempdir().unwrap();
+    let upstream = tmp.path().join("upstream");
+    std::fs::create_dir(&upstream).unwrap();
+    git(&upstream, &["init", "--quiet", "--initial-branch=main"]);
+    git(&upstream, &["commit", "--quiet", "--allow-empty", "-m", "initial"]);
+    // Enough branches for git to compress its requests with gzip
+    for branch in 0..50 {
+        git(
+            &upstream,
+            &["checkout", "--quiet", "-b", &format!("branch-{branch}"), "main"],
+        );
+        std::fs::write(upstream.join("file"), format!("{branch}\n")).unwrap();
+        git(&upstream, &["add", "file"]);
+        git(&upstream, &["commit", "--quiet", "-m", &format!("branch {branch}")]);
+    }
+    git(&upstream, &["checkout", "--quiet", "main"]);
+    let url = serve(tmp.path().to_owned());
+
+    for version in ["0", "1", "2"] {
+        let clone = format!("clone-v{version}");
+        git(
+            tmp.path(),
+            &[
+                "-c",
+                &format!("protocol.version={version}"),
+                "clone",
+                "--quiet",
+                &format!("{url}/upstream"),
+                &clone,
+            ],
+        );
+        assert_eq!(
+            git(&tmp.path().join(clone), &["rev-parse", "origin/branch-49"]),
+            git(&upstream, &["rev-parse", "branch-49"]),
+            "v{version}"
+        );
+    }
+
+    git(&upstream, &["commit", "--quiet", "--allow-empty", "-m", "new"]);
+    let clone = tmp.path().join("clone-v2");
+    git(&clone, &["-c", "protocol.version=2", "fetch", "--quiet", "origin"]);
+    assert_eq!(
+        git(&clone, &["rev-parse", "origin/main"]),
+        git(&upstream, &["rev-parse", "main"])
+    );
+    git(&clone, &["fsck", "--connectivity-only"]);
+
+    let output = std::process::Command::new("git")
+        .args(["ls-remote", &format!("{url}/missing")])
+        .output()
+        .unwrap();
+    assert!(!output.status.success(), "unknown repositories aren't found");
+}
+
+#[test]
+fn concurrent_clients_get_the_advertisement_of_their_version() {
+    let tmp = tempfile::tempdir().unwrap();
+    git(tmp.path(), &["init", "--quiet", "--initial-branch=main"]);
+    git(tmp.path(), &["commit", "--quiet", "--allow-empty", "-m", "initial"]);
+    let head = git(tmp.path(), &["rev-parse", "HEAD"]);
+    let http = smart_http(tmp.path().to_owned());
+
+    let advertise = |version: &'static str| {
+        let http = hyper_util::service::TowerToHyperService::new(http.clone());
+        async move {
+            let request = hyper::Request::get("/info/refs?service=git-upload-pack")
+                .header("Git-Protocol", format!("version={version}"))
+                .body(http_body_util::Full::<hyper::body::Bytes>::default())
+                .unwrap();
+            let response = hyper::service::Service::call(&http, request).await.unwrap();
+            let body = http_body_util::BodyExt::collect(response.into_body()).await.unwrap();
+            (version, String::from_utf8(body.to_bytes().to_vec()).unwrap())
+        }
+    };
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let responses = runtime.block_on(async {
+        let tasks: Vec<_> = (0..30)
+            .map(|round| tokio::spawn(advertise(["0", "1", "2"][round % 3])))
+            .collect();
+        let mut responses = Vec::new();
+        for task in tasks {
+            responses.push(task.await.unwrap());
+        }
+        responses
+    });
+
+    let service = "001e# service=git-upload-pack\n0000";
+    for (version, body) in responses {
+        match version {
+            "0" => assert!(
+                body.strip_prefix(service)
+                    .is_some_and(|refs| refs[4..].starts_with(&format!("{head} HEAD\0"))),
+                "v0: {body:?}"
+            ),
+            "1" => assert!(body.starts_with(&format!("{service}000eversion 1\n")), "v1: {body}"),
+            _ => assert!(body.starts_with("000eversion 2\n"), "v2: {body}"),
+        }
+    }
+}
@@ -0,0 +1,65 @@
+//! Stateless-rpc fetch requests of v0 and v1 clients are answered without advertising refs again
+
+use crate::util::{git, pkt};
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+/// Run `program` as upload-pack in stateless-rpc mode on `repo` for protocol `version`, sending `request`
+fn run(program: &Path, repo: &Path, version: u8, request: &str) -> Vec<u8> {
+    let mut command = Command::new(program);
+    if program == Path::new("git") {
+        command.arg("upload-pack");
+    }
+    let mut child = command
+        .arg("--stateless-rpc")
+        .arg(repo)
+        .env("GIT_PROTOCOL", format!("version={version}"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .expect("upload-pack can be started");
+    child.stdin.take().unwrap().write_all(request.as_bytes()).unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success(), "{program:?} failed for protocol v{version}");
+    output.stdout
+}
+
+#[test]
+fn requests_continue_where_the_advertisement_left_off() {
+    let tmp = tempfile::tempdir().unwrap();
+    git(tmp.path(), &["init", "--quiet"]);
+    git(tmp.path(), &["commit", "--quiet", "--allow-empty", "-m", "initial"]);
+    let head = git(tmp.path(), &["rev-parse", "HEAD"]);
+    let wants = format!(
+        "{}0000",
+        pkt(&format!("want {head} multi_ack_detailed side-band-64k no-progress\n"))
+    );
+    let unknown = pkt(&format!("have {}\n", "1".repeat(40)));
+    let upload_pack = assert_cmd::cargo::cargo_bin("gix-upload-pack");
+
+    for version in [0, 1] {
+        for (request, expected) in [
+            (wants.clone(), "nothing to answer without haves"),
+            (format!("{wants}{unknown}0000"), "a round of haves is answered"),
+        ] {
+            assert_eq!(
+                String::from_utf8(run(&upload_pack, tmp.path(), version, &request)).unwrap(),
+                String::from_utf8(run(Path::new("git"), tmp.path(), version, &request)).unwrap(),
+                "v{version}: {expected}"
+            );
+        }
+
+        let output = run(&upload_pack, tmp.path(), version, &format!("{wants}{}", pkt("done\n")));
+        assert!(
+            output.starts_with(b"0008NAK\n"),
+            "v{version}: the pack follows right away"
+        );
+        assert!(output.windows(4).any(|window| window == b"PACK"));
+        assert!(
+            !output.windows(6).any(|window| window == b"Total "),
+            "v{version}: no-progress silences all progress"
+        );
+    }
+}
@@ -0,0 +1,121 @@
+//! Protocol v0 and v1 acknowledgements in each multi-ack mode, compared to native git
+
+use crate::util::{git, pkt};
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+fn commit(dir: &Path, message: &str) -> String {
+    git(dir, &["commit", "--quiet", "--allow-empty", "-m", message]);
+    git(dir, &["rev-parse", "HEAD"])
+}
+
+/// Run `program` as upload-pack on `repo` for protocol `version`, with `--stateless-rpc` if `stateless`, sending
+/// `request`
+fn run(program: &Path, repo: &Path, version: u8, stateless: bool, request: &str) -> Vec<u8> {
+    let mut command = Command::new(program);
+    if program == Path::new("git") {
+        command.arg("upload-pack");
+    }
+    let mut child = command
+        .args(stateless.then_some("--stateless-rpc"))
+        .arg(repo)
+        .env("GIT_PROTOCOL", format!("version={version}"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .expect("upload-pack can be started");
+    child.stdin.take().unwrap().write_all(request.as_bytes()).unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success(), "{program:?} failed for protocol v{version}");
+    output.stdout
+}
+
+/// The lines of `response` after the advertisement of stateful sessions and before the pack, and whether a pack
+/// follows
+fn negotiation(response: &[u8], stateless: bool) -> (Vec<String>, bool) {
+    let mut rest = response;
+    let mut lines = Vec::new();
+    let mut advertised = stateless;
+    while rest.len() >= 4 && !rest.starts_with(b"PACK") {
+        let len = usize::from_str_radix(std::str::from_utf8(&rest[..4]).unwrap(), 16).unwrap();
+        if len == 0 {
+            advertised = true;
+            rest = &rest[4..];
+            continue;
+        }
+        if matches!(rest[4], 1 | 2) {
+            break;
+        }
+        if advertised {
+            lines.push(String::from_utf8_lossy(&rest[4..len]).into_owned());
+        }
+        rest = &rest[len..];
+    }
+    (lines, rest.windows(4).any(|window| window == b"PACK"))
+}
+
+#[test]
+fn haves_are_acknowledged_like_git_in_each_mode() {
+    let tmp = tempfile::tempdir().unwrap();
+    let dir = tmp.path();
+    git(dir, &["init", "--quiet", "--initial-branch=main"]);
+    let base = commit(dir, "base");
+    git(dir, &["checkout", "--quiet", "-b", "side"]);
+    let side = commit(dir, "side");
+    git(dir, &["checkout", "--quiet", "main"]);
+    let parent = commit(dir, "parent");
+    let tip = commit(dir, "tip");
+    let tree = git(dir, &["rev-parse", &format!("{base}^{{tree}}")]);
+    let unknown = "1".repeat(40);
+    let upload_pack = assert_cmd::cargo::cargo_bin("gix-upload-pack");
+
+    let rounds: &[&[&[&str]]] = &[
+        &[],
+        &[&[&unknown]],
+        &[&[&parent, &base]],
+        &[&[&unknown, &parent]],
+        &[&[&parent, &unknown]],
+        &[&[&side]],
+        &[&[&side], &[&parent]],
+        &[&[&base, &side], &[&unknown]],
+        &[&[&tree, &tree]],
+        &[&[&tip]],
+    ];
+    for capabilities in [
+        "no-progress",
+        "multi_ack side-band-64k no-progress",
+        "multi_ack_detailed side-band-64k no-progress",
+        "multi_ack_detailed no-done side-band-64k no-progress",
+        "multi_ack multi_ack_detailed ofs-delta",
+    ] {
+        let wants = pkt(&format!("want {tip} {capabilities}\n")) + "0000";
+        for rounds in rounds {
+            let haves =
+                |round: &[&str]| -> String { round.iter().map(|have| pkt(&format!("have {have}\n"))).collect() };
+            let mut stateful = wants.clone();
+            for round in *rounds {
+                stateful += &(haves(round) + "0000");
+            }
+            stateful += &pkt("done\n");
+            let mut requests = vec![(true, format!("{wants}{}", pkt("done\n")))];
+            for round in *rounds {
+                requests.push((true, format!("{wants}{}0000", haves(round))));
+                requests.push((true, format!("{wants}{}{}", haves(round), pkt("done\n"))));
+            }
+            requests.push((false, stateful));
+
+            for version in [0, 1] {
+                for (stateless, request) in &requests {
+                    let ours = negotiation(&run(&upload_pack, dir, version, *stateless, request), *stateless);
+                    let native = negotiation(&run(Path::new("git"), dir, version, *stateless, request), *stateless);
+                    assert_eq!(
+                        ours, native,
+                        "v{version} {capabilities:?} {rounds:?}, stateless: {stateless}"
+                    );
+                }
+            }
+        }
+    }
+}
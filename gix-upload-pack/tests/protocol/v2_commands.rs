@@ -0,0 +1,137 @@
+//! Protocol v2 requests are served by the command registered for their name
+
+use crate::util::{git, pkt};
+use gix_upload_pack::protocol::commands::{Command, Commands, Response};
+use gix_upload_pack::protocol::request::Request;
+use gix_upload_pack::protocol::v2::Handler;
+use gix_upload_pack::services::CapabilityManager;
+use gix_upload_pack::{server::Step, Error, ProtocolVersion, Server, ServerOptions, SessionContext};
+use std::path::Path;
+use std::process::{Command as Process, Stdio};
+
+fn repository(dir: &Path) {
+    git(dir, &["init", "--quiet", "--initial-branch=main"]);
+    git(dir, &["commit", "--quiet", "--allow-empty", "-m", "initial"]);
+}
+
+/// Send `request` to a stateless protocol v2 session and return the response, or the error described like git does
+fn serve(dir: &Path, options: ServerOptions, request: &str) -> Result<String, String> {
+    let options = options.with_stateless_rpc(true).with_repository_overrides(false);
+    let mut server = Server::new(dir, options).unwrap();
+    let mut session = server.step_session(ProtocolVersion::V2).unwrap();
+    session.push_input(request.as_bytes());
+    session.finish_input();
+    let mut output = Vec::new();
+    let result = loop {
+        match session.serve_step(&mut output) {
+            Ok(Step::Done) => break Ok(()),
+            Ok(_) => {}
+            Err(err) => break Err(err),
+        }
+    };
+    drop(session);
+    match result {
+        Ok(()) => Ok(String::from_utf8(output).unwrap()),
+        Err(err) => Err(server.error_message(&err)),
+    }
+}
+
+/// Counts the arguments of a request
+struct CountArguments;
+
+impl Command for CountArguments {
+    fn name(&self) -> &str {
+        "count-arguments"
+    }
+
+    fn advertisement(&self) -> Option<String> {
+        Some("count-arguments=v1".into())
+    }
+
+    fn serve(
+        &self,
+        handler: &Handler<'_>,
+        request: &Request,
+        response: &mut Response<'_>,
+        _session: &mut SessionContext,
+    ) -> Result<(), Error> {
+        assert!(handler.repository().head_id().is_ok());
+        response.write_protocol_message(format!("{} arguments\n", request.arguments.len()).as_bytes())?;
+        response.write_flush()
+    }
+}
+
+#[test]
+fn registered_commands_are_advertised_and_served() {
+    let tmp = tempfile::tempdir().unwrap();
+    repository(tmp.path());
+    let options = ServerOptions::default().with_v2_command(CountArguments);
+
+    let mut server = Server::new(tmp.path(), options.clone().with_advertise_refs(true)).unwrap();
+    let mut session = server.step_session(ProtocolVersion::V2).unwrap();
+    session.finish_input();
+    let mut advertisement = Vec::new();
+    while session.serve_step(&mut advertisement).unwrap() != Step::Done {}
+    let advertisement = String::from_utf8(advertisement).unwrap();
+    assert!(advertisement.contains(&pkt("count-arguments=v1\n")), "{advertisement}");
+    assert_eq!(advertisement.matches("ls-refs").count(), 1, "{advertisement}");
+    let manager = CapabilityManager::new(server.repository(), &options);
+    let lines = manager.get_v2_capability_lines(&options.capabilities);
+    assert_eq!(
+        lines.last().map(String::as_str),
+        Some("count-arguments=v1"),
+        "{lines:?}"
+    );
+    assert_eq!(lines.iter().filter(|line| line.starts_with("fetch")).count(), 1);
+
+    let request = format!(
+        "{}0001{}{}0000",
+        pkt("command=count-arguments\n"),
+        pkt("first\n"),
+        pkt("second\n")
+    );
+    assert_eq!(
+        serve(tmp.path(), options.clone(), &request).unwrap(),
+        format!("{}0000", pkt("2 arguments\n"))
+    );
+
+    let ls_refs = format!("{}0000", pkt("command=ls-refs\n"));
+    let head = git(tmp.path(), &["rev-parse", "HEAD"]);
+    let refs = serve(tmp.path(), options, &ls_refs).unwrap();
+    assert!(refs.contains(&format!("{head} refs/heads/main")), "{refs}");
+}
+
+#[test]
+fn unknown_commands_are_reported_like_git() {
+    let tmp = tempfile::tempdir().unwrap();
+    repository(tmp.path());
+    let request = format!("{}0000", pkt("command=frobnicate\n"));
+
+    let native = Process::new("git")
+        .args(["upload-pack", "--stateless-rpc", "."])
+        .current_dir(tmp.path())
+        .env("GIT_PROTOCOL", "version=2")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    std::io::Write::write_all(&mut native.stdin.as_ref().unwrap(), request.as_bytes()).unwrap();
+    let native = native.wait_with_output().unwrap();
+    assert_eq!(native.status.code(), Some(128));
+    let native_message = String::from_utf8(native.stderr).unwrap();
+
+    let message = serve(tmp.path(), ServerOptions::default(), &request).unwrap_err();
+    assert_eq!(format!("fatal: {message}\n"), native_message);
+    assert_eq!(message, "invalid command 'frobnicate'");
+
+    let options = ServerOptions {
+        v2_commands: Commands::default().without("ls-refs"),
+        ..ServerOptions::default()
+    };
+    let ls_refs = format!("{}0000", pkt("command=ls-refs\n"));
+    assert_eq!(
+        serve(tmp.path(), options, &ls_refs).unwrap_err(),
+        "invalid command 'ls-refs'"
+    );
+}
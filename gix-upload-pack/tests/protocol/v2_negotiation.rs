@@ -0,0 +1,229 @@
+//! Protocol v2 acknowledgments, `ready` and the pack following in the same response, compared to native git
+
+use crate::util::{git, pkt};
+use gix_upload_pack::{ProtocolVersion, Server, ServerOptions};
+use std::io::Write;
+use std::path::Path;
+use std::process::Stdio;
+
+fn commit(dir: &Path, revision: &str) -> String {
+    std::fs::write(dir.join("file"), format!("{revision}\n")).unwrap();
+    git(dir, &["add", "file"]);
+    git(dir, &["commit", "--quiet", "-m", revision]);
+    git(dir, &["rev-parse", "HEAD"])
+}
+
+/// A fetch request as git sends it over a stateless transport, with `extra` argument lines before the haves
+fn fetch(wants: &[&str], haves: &[&str], extra: &[&str], done: bool) -> String {
+    let mut request = pkt("command=fetch\n") + &pkt("agent=git/2.39.5\n") + &pkt("object-format=sha1\n") + "0001";
+    for line in ["thin-pack", "ofs-delta"].iter().chain(extra) {
+        request += &pkt(&format!("{line}\n"));
+    }
+    for want in wants {
+        request += &pkt(&format!("want {want}\n"));
+    }
+    for have in haves {
+        request += &pkt(&format!("have {have}\n"));
+    }
+    if done {
+        request += &pkt("done\n");
+    }
+    request + "0000"
+}
+
+fn serve_stateless(dir: &Path, request: &str) -> Vec<u8> {
+    let options = ServerOptions::default()
+        .with_stateless_rpc(true)
+        .with_repository_overrides(false)
+        .with_protocol(ProtocolVersion::V2);
+    let mut output = Vec::new();
+    Server::new(dir, options)
+        .unwrap()
+        .serve(request.as_bytes(), &mut output)
+        .unwrap();
+    output
+}
+
+/// Serve `requests` one after another on a single stateful connection, which starts with the capabilities
+fn serve_stateful(dir: &Path, requests: &[String]) -> Vec<u8> {
+    let options = ServerOptions::default()
+        .with_stateless_rpc(false)
+        .with_repository_overrides(false)
+        .with_protocol(ProtocolVersion::V2);
+    let mut output = Vec::new();
+    Server::new(dir, options)
+        .unwrap()
+        .serve(requests.concat().as_bytes(), &mut output)
+        .unwrap();
+    output
+}
+
+/// The number of objects in each pack of `response`, in order
+fn pack_object_counts(response: &[u8]) -> Vec<u32> {
+    response
+        .windows(b"\x01PACK".len())
+        .enumerate()
+        .filter(|(_, window)| *window == b"\x01PACK")
+        .map(|(pos, _)| u32::from_be_bytes(response[pos + 9..pos + 13].try_into().unwrap()))
+        .collect()
+}
+
+fn native_stateless(dir: &Path, request: &str) -> Vec<u8> {
+    let mut child = std::process::Command::new("git")
+        .args(["upload-pack", "--stateless-rpc", "."])
+        .current_dir(dir)
+        .env("GIT_PROTOCOL", "version=2")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .expect("git is installed");
+    child.stdin.take().unwrap().write_all(request.as_bytes()).unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success(), "native upload-pack failed");
+    output.stdout
+}
+
+/// The response up to and including the `packfile` line, as pack contents differ between implementations
+fn negotiation(response: &[u8]) -> String {
+    let end = response
+        .windows(b"packfile\n".len())
+        .position(|window| window == b"packfile\n")
+        .map_or(response.len(), |pos| pos + b"packfile\n".len());
+    String::from_utf8_lossy(&response[..end]).into_owned()
+}
+
+#[test]
+fn stateless_responses_match_native_git() {
+    let tmp = tempfile::tempdir().unwrap();
+    let dir = tmp.path();
+    git(dir, &["init", "--quiet", "--initial-branch=main"]);
+    let base = commit(dir, "base");
+    git(dir, &["checkout", "--quiet", "-b", "side"]);
+    let side = commit(dir, "side");
+    git(dir, &["checkout", "--quiet", "main"]);
+    let middle = commit(dir, "middle");
+    let tip = commit(dir, "tip");
+    let unknown = "1111111111111111111111111111111111111111";
+
+    let cases = [
+        ("unknown haves", fetch(&[&tip], &[unknown], &[], false), "0000"),
+        ("common haves", fetch(&[&tip], &[unknown, &middle], &[], false), "ready"),
+        ("unrelated common haves", fetch(&[&side], &[&tip], &[], false), "0000"),
+        (
+            "waiting for done",
+            fetch(&[&tip], &[&middle], &["wait-for-done"], false),
+            "0000",
+        ),
+        ("done", fetch(&[&tip], &[&base], &[], true), "packfile"),
+        ("no haves", fetch(&[&tip], &[], &[], false), "packfile"),
+    ];
+    for (name, request, expected) in cases {
+        let ours = negotiation(&serve_stateless(dir, &request));
+        let native = negotiation(&native_stateless(dir, &request));
+        assert_eq!(ours, native, "{name}");
+        assert!(ours.contains(expected), "{name}: {ours}");
+    }
+}
+
+#[test]
+fn stateless_clients_continue_with_a_new_request() {
+    let tmp = tempfile::tempdir().unwrap();
+    let dir = tmp.path();
+    git(dir, &["init", "--quiet", "--initial-branch=main"]);
+    let base = commit(dir, "base");
+    let tip = commit(dir, "tip");
+    let unknown = "2222222222222222222222222222222222222222";
+
+    let first = negotiation(&serve_stateless(dir, &fetch(&[&tip], &[unknown], &[], false)));
+    assert_eq!(first, pkt("acknowledgments\n") + &pkt("NAK\n") + "0000");
+
+    // The next request repeats the wants and continues with further haves
+    let second = negotiation(&serve_stateless(dir, &fetch(&[&tip], &[&base], &[], false)));
+    assert_eq!(
+        second,
+        pkt("acknowledgments\n") + &pkt(&format!("ACK {base}\n")) + &pkt("ready\n") + "0001" + &pkt("packfile\n")
+    );
+}
+
+#[test]
+fn stateful_connections_keep_negotiating_after_a_pack() {
+    let tmp = tempfile::tempdir().unwrap();
+    let dir = tmp.path();
+    git(dir, &["init", "--quiet", "--initial-branch=main"]);
+    let base = commit(dir, "base");
+    let tip = commit(dir, "tip");
+    let ls_refs = pkt("command=ls-refs\n") + "0001" + &pkt("ref-prefix refs/heads/\n") + "0000";
+
+    let output = serve_stateful(
+        dir,
+        &[
+            ls_refs.clone(),
+            // The client finds `base` to be common and is done, without repeating it
+            fetch(&[&tip], &[&base], &["wait-for-done"], false),
+            fetch(&[&tip], &[], &[], true),
+            // Further requests are served until the client disconnects
+            ls_refs,
+            fetch(&[&tip], &[], &[], true),
+            "0000".into(),
+        ],
+    );
+    let text = String::from_utf8_lossy(&output);
+    assert!(text.starts_with(&pkt("version 2\n")), "capabilities come first: {text}");
+    assert_eq!(
+        text.matches("version 2\n").count(),
+        1,
+        "capabilities aren't advertised again"
+    );
+    assert_eq!(text.matches(&format!("{tip} refs/heads/main\n")).count(), 2);
+    assert_eq!(
+        pack_object_counts(&output),
+        [3, 3],
+        "the common commit carries over, leaving the tip commit, its tree and blob"
+    );
+}
+
+#[test]
+fn native_clients_negotiate_over_stateful_connections() {
+    let tmp = tempfile::tempdir().unwrap();
+    let upstream = tmp.path().join("upstream");
+    std::fs::create_dir(&upstream).unwrap();
+    git(&upstream, &["init", "--quiet", "--initial-branch=main"]);
+    for revision in ["one", "two", "three"] {
+        commit(&upstream, revision);
+    }
+
+    let upload_pack = assert_cmd::cargo::cargo_bin("gix-upload-pack");
+    let upload_pack = upload_pack.to_str().unwrap();
+    let url = format!("file://{}", upstream.display());
+    git(
+        tmp.path(),
+        &[
+            "-c",
+            "protocol.version=2",
+            "clone",
+            "--quiet",
+            "--upload-pack",
+            upload_pack,
+            &url,
+            "clone",
+        ],
+    );
+
+    let tip = commit(&upstream, "four");
+    let clone = tmp.path().join("clone");
+    git(
+        &clone,
+        &[
+            "-c",
+            "protocol.version=2",
+            "fetch",
+            "--quiet",
+            "--upload-pack",
+            upload_pack,
+            "origin",
+        ],
+    );
+    assert_eq!(git(&clone, &["rev-parse", "origin/main"]), tip);
+    git(&clone, &["fsck", "--connectivity-only"]);
+}
@@ -0,0 +1,88 @@
+//! Denied requests are recorded with the auditor of the server options
+
+use crate::util::{git, pkt};
+use bstr::BStr;
+use gix_serve_core::audit::{Auditor, Denial, Reason};
+use gix_serve_core::protocol::ServiceKind;
+use gix_upload_pack::{server::Step, Error, ProtocolVersion, Server, ServerOptions};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+/// A repository with `main` and a ref pointing to another commit that clients may not see, returning both commits
+fn repo_with_hidden_ref(dir: &Path) -> (String, String) {
+    git(dir, &["init", "--quiet", "--initial-branch=main"]);
+    git(dir, &["commit", "--quiet", "--allow-empty", "-m", "public"]);
+    let public = git(dir, &["rev-parse", "HEAD"]);
+    git(dir, &["commit", "--quiet", "--allow-empty", "-m", "secret"]);
+    let secret = git(dir, &["rev-parse", "HEAD"]);
+    git(dir, &["update-ref", "refs/hidden/secret", &secret]);
+    git(dir, &["reset", "--quiet", "--hard", &public]);
+    (public, secret)
+}
+
+fn fetch(dir: &Path, options: ServerOptions, want: &str) -> Result<(), Error> {
+    let options = options
+        .with_ref_authorizer(|name: &BStr| !name.starts_with(b"refs/hidden/"))
+        .with_stateless_rpc(true)
+        .with_repository_overrides(false);
+    let mut server = Server::new(dir, options)?;
+    let mut session = server.step_session(ProtocolVersion::V2)?;
+    let request = format!(
+        "{}0001{}{}0000",
+        pkt("command=fetch\n"),
+        pkt(&format!("want {want}\n")),
+        pkt("done\n")
+    );
+    session.push_input(request.as_bytes());
+    session.finish_input();
+    while session.serve_step(&mut Vec::new())? != Step::Done {}
+    Ok(())
+}
+
+fn recording_auditor() -> (Auditor, Arc<Mutex<Vec<Denial>>>) {
+    let records = Arc::new(Mutex::new(Vec::new()));
+    let auditor = Auditor::new({
+        let records = records.clone();
+        move |denial: &Denial| records.lock().unwrap().push(denial.clone())
+    });
+    (auditor, records)
+}
+
+#[test]
+fn wants_of_hidden_refs_are_recorded() {
+    let tmp = tempfile::tempdir().unwrap();
+    let (public, secret) = repo_with_hidden_ref(tmp.path());
+    let (auditor, records) = recording_auditor();
+    let options = ServerOptions::default()
+        .with_auditor(auditor)
+        .with_principal("alice")
+        .with_remote_addr("192.0.2.1:51234".parse().unwrap());
+
+    fetch(tmp.path(), options.clone(), &public).unwrap();
+    assert!(
+        records.lock().unwrap().is_empty(),
+        "successful requests aren't recorded"
+    );
+
+    let err = fetch(tmp.path(), options, &secret).unwrap_err();
+    assert!(matches!(err, Error::NotOurRef { .. }), "{err}");
+    let records = records.lock().unwrap();
+    assert_eq!(records.len(), 1);
+    let denial = &records[0];
+    assert_eq!(denial.service, ServiceKind::UploadPack);
+    assert_eq!(denial.reason, Reason::HiddenRef);
+    assert_eq!(denial.principal.as_deref(), Some("alice"));
+    assert_eq!(denial.remote_addr, Some("192.0.2.1:51234".parse().unwrap()));
+    assert_eq!(denial.repository, tmp.path());
+    assert!(denial.message.contains(&secret), "{}", denial.message);
+}
+
+#[test]
+fn failures_that_are_no_denials_are_not_recorded() {
+    let tmp = tempfile::tempdir().unwrap();
+    repo_with_hidden_ref(tmp.path());
+    let (auditor, records) = recording_auditor();
+    let err = fetch(tmp.path(), ServerOptions::default().with_auditor(auditor), "not-a-hash").unwrap_err();
+    assert_eq!(err.denial_reason(), None, "{err}");
+    assert!(records.lock().unwrap().is_empty());
+}
@@ -0,0 +1,89 @@
+//! Aborting sessions with a `CancellationToken`
+
+use crate::util::{git, pkt};
+use gix_upload_pack::{CancellationToken, Error, ProtocolVersion, Server, ServerOptions};
+use std::io::Write;
+use std::path::Path;
+
+/// Cancels its token once `line` was written, like an operator aborting the session at that point
+struct CancelAfter {
+    out: Vec<u8>,
+    line: &'static [u8],
+    token: CancellationToken,
+}
+
+impl Write for CancelAfter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.out.extend_from_slice(buf);
+        if !self.line.is_empty() && self.out.windows(self.line.len()).any(|window| window == self.line) {
+            self.token.cancel();
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Serve `request` in protocol `version`, cancelling once `line` was sent or right away if it's empty, and return the
+/// output
+fn fetch(dir: &Path, version: ProtocolVersion, request: &str, line: &'static [u8]) -> (Result<(), Error>, String) {
+    let token = CancellationToken::new();
+    if line.is_empty() {
+        token.cancel();
+    }
+    let options = ServerOptions::default()
+        .with_stateless_rpc(true)
+        .with_repository_overrides(false)
+        .with_protocol(version)
+        .with_cancellation(token.clone());
+    let mut output = CancelAfter {
+        out: Vec::new(),
+        line,
+        token,
+    };
+    let result = Server::new(dir, options)
+        .unwrap()
+        .serve(request.as_bytes(), &mut output)
+        .map(|_| ());
+    (result, String::from_utf8_lossy(&output.out).into_owned())
+}
+
+#[test]
+fn cancelled_sessions_fail_and_tell_the_client() {
+    let tmp = tempfile::tempdir().unwrap();
+    git(tmp.path(), &["init", "--quiet"]);
+    std::fs::write(tmp.path().join("file"), "content\n").unwrap();
+    git(tmp.path(), &["add", "."]);
+    git(tmp.path(), &["commit", "--quiet", "-m", "initial"]);
+    let head = git(tmp.path(), &["rev-parse", "HEAD"]);
+
+    let v2 = pkt("command=fetch\n")
+        + &pkt("object-format=sha1\n")
+        + "0001"
+        + &pkt(&format!("want {head}\n"))
+        + &pkt("done\n")
+        + "0000";
+    let (result, output) = fetch(tmp.path(), ProtocolVersion::V2, &v2, b"packfile\n");
+    assert!(matches!(result, Err(Error::Cancelled)), "{result:?}");
+    assert!(
+        output.ends_with("packfile\n001e\x03error: Session cancelled\n"),
+        "the pack is replaced by an error on the sideband: {output:?}"
+    );
+
+    let v1 = pkt(&format!("want {head} side-band-64k\n")) + "0000" + &pkt("done\n");
+    let (result, output) = fetch(tmp.path(), ProtocolVersion::V0, &v1, b"NAK\n");
+    assert!(matches!(result, Err(Error::Cancelled)), "{result:?}");
+    assert_eq!(output, "0008NAK\n001e\x03error: Session cancelled\n");
+
+    let (result, output) = fetch(tmp.path(), ProtocolVersion::V2, &v2, b"");
+    assert!(matches!(result, Err(Error::Cancelled)), "{result:?}");
+    assert_eq!(
+        output, "0019ERR Session cancelled",
+        "cancelled tokens refuse new sessions"
+    );
+
+    let (result, _) = fetch(tmp.path(), ProtocolVersion::V2, &v2, b"never sent");
+    assert!(result.is_ok(), "sessions whose token isn't cancelled succeed");
+}
@@ -0,0 +1,76 @@
+//! Keep-alive packets while the pack is prepared, like git's `uploadpack.keepAlive`
+
+use crate::util::{git, pkt};
+use gix_upload_pack::{ProtocolVersion, Server, ServerOptions};
+use std::path::Path;
+use std::time::Duration;
+
+/// A repository with a few commits, returning its head
+fn repository(dir: &Path) -> String {
+    git(dir, &["init", "--quiet"]);
+    for revision in 1..=10 {
+        std::fs::write(dir.join(format!("file-{revision}")), format!("{revision}\n")).unwrap();
+        git(dir, &["add", "."]);
+        git(dir, &["commit", "--quiet", "-m", &format!("revision {revision}")]);
+    }
+    git(dir, &["rev-parse", "HEAD"])
+}
+
+/// Clone `head` with protocol v2, sending keep-alive packets each `keepalive`, and return the pack along with the
+/// number of keep-alive packets
+fn clone(dir: &Path, head: &str, keepalive: Option<Duration>) -> (Vec<u8>, usize) {
+    let request = pkt("command=fetch\n")
+        + &pkt("object-format=sha1\n")
+        + "0001"
+        + &pkt(&format!("want {head}\n"))
+        + &pkt("done\n")
+        + "0000";
+    let mut options = ServerOptions::default()
+        .with_stateless_rpc(true)
+        .with_repository_overrides(false)
+        .with_protocol(ProtocolVersion::V2);
+    options.keepalive = keepalive;
+    let mut output = Vec::new();
+    Server::new(dir, options)
+        .unwrap()
+        .serve(request.as_bytes(), &mut output)
+        .unwrap();
+
+    let (mut pack, mut keepalives) = (Vec::new(), 0);
+    let mut in_packfile = false;
+    let mut output = output.as_slice();
+    while output.len() >= 4 {
+        let len = usize::from_str_radix(std::str::from_utf8(&output[..4]).unwrap(), 16).unwrap();
+        if len < 4 {
+            output = &output[4..];
+            continue;
+        }
+        let data = &output[4..len];
+        match data {
+            [1] => keepalives += 1,
+            [1, rest @ ..] if in_packfile => pack.extend_from_slice(rest),
+            _ => {}
+        }
+        in_packfile |= data == b"packfile\n";
+        output = &output[len..];
+    }
+    (pack, keepalives)
+}
+
+#[test]
+fn keepalives_are_sent_while_the_pack_is_prepared() {
+    let tmp = tempfile::tempdir().unwrap();
+    let head = repository(tmp.path());
+
+    let (pack, keepalives) = clone(tmp.path(), &head, Some(Duration::from_nanos(1)));
+    assert!(keepalives > 0, "counting takes longer than the interval");
+    let (expected, none) = clone(tmp.path(), &head, None);
+    assert_eq!(none, 0, "keep-alives can be turned off");
+    assert_eq!(pack, expected, "keep-alives don't change the pack");
+    let (_, default) = clone(tmp.path(), &head, ServerOptions::default().keepalive);
+    assert_eq!(default, 0, "small packs are ready within the default interval");
+
+    let received = tmp.path().join("received.pack");
+    std::fs::write(&received, pack).unwrap();
+    git(tmp.path(), &["index-pack", received.to_str().unwrap()]);
+}
@@ -0,0 +1,14 @@
+//! How sessions are run, limited and observed, from cancellation and timeouts to auditing and metrics
+
+#[path = "../util/mod.rs"]
+mod util;
+
+mod audit;
+mod cancel;
+mod keepalive;
+mod metrics;
+mod read_only;
+mod silent_stderr;
+mod stall;
+mod timeout;
+mod wire_stats;
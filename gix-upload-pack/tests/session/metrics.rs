@@ -0,0 +1,97 @@
+//! Counters and timings of sessions reported to `ServerOptions::metrics`
+
+use crate::util::{git, pkt};
+use gix_serve_core::metrics::{Meter, Metrics, Phase};
+use gix_serve_core::protocol::ServiceKind;
+use gix_upload_pack::{ProtocolVersion, Server, ServerOptions, WireStats};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Records all measurements
+#[derive(Default, Clone)]
+struct Recorder(Arc<Mutex<Vec<String>>>);
+
+impl Metrics for Recorder {
+    fn session_started(&self, service: ServiceKind, active: usize) {
+        self.0.lock().unwrap().push(format!("start {service:?} {active}"));
+    }
+
+    fn session_finished(&self, _: ServiceKind, wire: &WireStats, _: Duration, success: bool, active: usize) {
+        let sent = wire.response_bytes > 0;
+        self.0
+            .lock()
+            .unwrap()
+            .push(format!("finish sent={sent} success={success} {active}"));
+    }
+
+    fn phase(&self, _: ServiceKind, phase: Phase, _: Duration) {
+        self.0.lock().unwrap().push(format!("phase {phase}"));
+    }
+
+    fn negotiation_round(&self, _: ServiceKind) {
+        self.0.lock().unwrap().push("round".into());
+    }
+
+    fn pack_sent(&self, objects: u64, bytes: u64) {
+        let nonempty = bytes > 32;
+        self.0
+            .lock()
+            .unwrap()
+            .push(format!("pack objects={objects} nonempty={nonempty}"));
+    }
+}
+
+#[test]
+fn sessions_phases_and_packs_are_measured() {
+    let tmp = tempfile::tempdir().unwrap();
+    git(tmp.path(), &["init", "--quiet"]);
+    std::fs::write(tmp.path().join("file"), "content\n").unwrap();
+    git(tmp.path(), &["add", "."]);
+    git(tmp.path(), &["commit", "--quiet", "-m", "initial"]);
+    let head = git(tmp.path(), &["rev-parse", "HEAD"]);
+
+    let recorder = Recorder::default();
+    let options = ServerOptions::default()
+        .with_stateless_rpc(true)
+        .with_repository_overrides(false)
+        .with_protocol(ProtocolVersion::V2)
+        .with_metrics(Meter::new(recorder.clone()));
+    Server::new(tmp.path(), options.clone().with_advertise_refs(true))
+        .unwrap()
+        .serve(&b""[..], Vec::new())
+        .unwrap();
+    let request = pkt("command=fetch\n")
+        + &pkt("object-format=sha1\n")
+        + "0001"
+        + &pkt(&format!("want {head}\n"))
+        + &pkt("done\n")
+        + "0000";
+    Server::new(tmp.path(), options.clone())
+        .unwrap()
+        .serve(request.as_bytes(), Vec::new())
+        .unwrap();
+    let unknown = pkt("command=fetch\n") + &pkt("object-format=sha1\n") + "0001" + &pkt("want 1234\n") + "0000";
+    Server::new(tmp.path(), options)
+        .unwrap()
+        .serve(unknown.as_bytes(), Vec::new())
+        .unwrap_err();
+
+    assert_eq!(
+        *recorder.0.lock().unwrap(),
+        [
+            "start UploadPack 1",
+            "phase handshake",
+            "finish sent=true success=true 0",
+            "start UploadPack 1",
+            "round",
+            "phase negotiation",
+            "pack objects=3 nonempty=true",
+            "phase pack-generation",
+            "finish sent=true success=true 0",
+            "start UploadPack 1",
+            "round",
+            "phase negotiation",
+            "finish sent=false success=false 0",
+        ]
+    );
+}
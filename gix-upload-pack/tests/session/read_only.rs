@@ -0,0 +1,120 @@
+//! In read-only mode nothing is written while serving, and options that would write are refused
+
+use crate::util::git;
+use gix_upload_pack::{Error, Server, ServerOptions};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::SystemTime;
+
+/// A bare repository at `dir` with a few commits, made from a repository at `work`
+fn repository(work: &Path, dir: &Path) {
+    git(work, &["init", "--quiet", "--initial-branch=main"]);
+    for revision in 1..=3 {
+        std::fs::write(work.join("file"), format!("revision {revision}\n")).unwrap();
+        git(work, &["add", "file"]);
+        git(work, &["commit", "--quiet", "-m", &format!("revision {revision}")]);
+    }
+    git(work, &["clone", "--quiet", "--bare", ".", dir.to_str().unwrap()]);
+}
+
+/// The length and modification time of every file and directory below `dir`
+fn snapshot(dir: &Path) -> BTreeMap<PathBuf, (u64, SystemTime)> {
+    let mut entries = BTreeMap::new();
+    let mut pending = vec![dir.to_owned()];
+    while let Some(dir) = pending.pop() {
+        for entry in std::fs::read_dir(&dir).unwrap() {
+            let entry = entry.unwrap();
+            let metadata = entry.metadata().unwrap();
+            if metadata.is_dir() {
+                pending.push(entry.path());
+            }
+            entries.insert(entry.path(), (metadata.len(), metadata.modified().unwrap()));
+        }
+    }
+    entries
+}
+
+#[test]
+fn clones_leave_the_repository_untouched() {
+    let tmp = tempfile::tempdir().unwrap();
+    let work = tmp.path().join("work");
+    std::fs::create_dir(&work).unwrap();
+    let repo = tmp.path().join("repo.git");
+    repository(&work, &repo);
+    let before = snapshot(&repo);
+
+    let ours = assert_cmd::cargo::cargo_bin("gix-upload-pack");
+    for version in ["0", "2"] {
+        let clone = tmp.path().join(format!("clone-v{version}"));
+        let output = Command::new("git")
+            .args([
+                "-c",
+                &format!("protocol.version={version}"),
+                "clone",
+                "--quiet",
+                "--upload-pack",
+            ])
+            .arg(format!("'{}' --read-only", ours.display()))
+            .arg(format!("file://{}", repo.display()))
+            .arg(&clone)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        assert!(clone.join("file").is_file());
+    }
+
+    assert_eq!(snapshot(&repo), before, "nothing was written to the served repository");
+}
+
+#[test]
+fn options_that_write_are_refused() {
+    let hook = std::env::current_exe().unwrap();
+    for options in [
+        ServerOptions {
+            enable_tracing: true,
+            ..ServerOptions::default().with_read_only(true)
+        },
+        ServerOptions {
+            pack_objects_hook: Some(hook.clone()),
+            ..ServerOptions::default().with_read_only(true)
+        },
+        ServerOptions {
+            post_upload_pack_hook: Some(hook.clone()),
+            ..ServerOptions::default().with_read_only(true)
+        },
+    ] {
+        let err = options.validate().unwrap_err();
+        assert!(matches!(err, Error::Config { .. }), "{err:?}");
+        assert!(err.to_string().contains("Read-only mode conflicts with"), "{err}");
+    }
+
+    let options = ServerOptions {
+        pack_objects_hook: Some(hook),
+        ..ServerOptions::default()
+    };
+    assert!(options.validate().is_ok(), "hooks are allowed unless read-only");
+}
+
+#[test]
+fn repository_configuration_enables_read_only_mode() {
+    let tmp = tempfile::tempdir().unwrap();
+    git(tmp.path(), &["init", "--quiet", "--bare"]);
+    git(tmp.path(), &["config", "uploadpack.readOnly", "true"]);
+    let repo = gix::open(tmp.path()).unwrap();
+    assert!(ServerOptions::from_repository(&repo).unwrap().read_only);
+
+    // The hook is only honored from configuration outside of the repository
+    let repo = gix::open_opts(
+        tmp.path(),
+        gix::open::Options::default().config_overrides(["uploadpack.packObjectsHook=/usr/bin/cat"]),
+    )
+    .unwrap();
+    let err = ServerOptions::from_repository(&repo).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Configuration error: Read-only mode conflicts with the pack-objects hook, which may write to the filesystem"
+    );
+    git(tmp.path(), &["config", "uploadpack.packObjectsHook", "/usr/bin/cat"]);
+    assert!(Server::from_repository(tmp.path()).is_ok(), "the local hook is ignored");
+}
@@ -0,0 +1,56 @@
+//! Diagnostics are silent by default, as stderr may be forwarded to clients
+
+use crate::util::{git, pkt};
+use assert_cmd::Command;
+
+#[test]
+fn normal_sessions_write_nothing_to_stderr() {
+    let tmp = tempfile::tempdir().unwrap();
+    git(tmp.path(), &["init", "--quiet"]);
+    std::fs::write(tmp.path().join("file"), "content\n").unwrap();
+    git(tmp.path(), &["add", "file"]);
+    git(tmp.path(), &["commit", "--quiet", "-m", "initial"]);
+    let head = git(tmp.path(), &["rev-parse", "HEAD"]);
+
+    let assert = Command::cargo_bin("gix-upload-pack")
+        .unwrap()
+        .args(["--stateless-rpc", "--advertise-refs"])
+        .arg(tmp.path())
+        .env("GIT_PROTOCOL", "version=2")
+        .assert()
+        .success();
+    let output = assert.get_output();
+    assert!(!output.stdout.is_empty());
+    assert_eq!(String::from_utf8_lossy(&output.stderr), "");
+
+    for (protocol, request) in [
+        (
+            "version=2",
+            format!(
+                "{}0001{}{}0000",
+                pkt("command=fetch\n"),
+                pkt(&format!("want {head}\n")),
+                pkt("done\n")
+            ),
+        ),
+        (
+            "version=1",
+            format!("{}0000{}", pkt(&format!("want {head} side-band-64k\n")), pkt("done\n")),
+        ),
+    ] {
+        let assert = Command::cargo_bin("gix-upload-pack")
+            .unwrap()
+            .arg("--stateless-rpc")
+            .arg(tmp.path())
+            .env("GIT_PROTOCOL", protocol)
+            .write_stdin(request)
+            .assert()
+            .success();
+        let output = assert.get_output();
+        assert!(
+            output.stdout.windows(4).any(|window| window == b"PACK"),
+            "{protocol}: a pack is sent"
+        );
+        assert_eq!(String::from_utf8_lossy(&output.stderr), "", "{protocol}");
+    }
+}
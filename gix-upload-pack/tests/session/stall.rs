@@ -0,0 +1,48 @@
+//! Clients sending their request too slowly are aborted with a resource error
+
+use crate::util::{git, pkt};
+use gix_upload_pack::{services::StallDetection, Error, ProtocolVersion, Server, ServerOptions};
+use std::io::Read;
+use std::time::Duration;
+
+/// Hands out one byte per read, sleeping before each
+struct Trickle(std::io::Cursor<Vec<u8>>);
+
+impl Read for Trickle {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        std::thread::sleep(Duration::from_millis(5));
+        let len = buf.len().min(1);
+        self.0.read(&mut buf[..len])
+    }
+}
+
+#[test]
+fn trickling_requests_are_aborted() {
+    let tmp = tempfile::tempdir().unwrap();
+    git(tmp.path(), &["init", "--quiet", "--initial-branch=main"]);
+    git(tmp.path(), &["commit", "--quiet", "--allow-empty", "-m", "initial"]);
+
+    let request = format!(
+        "{}0001{}0000",
+        pkt("command=ls-refs\n"),
+        pkt("ref-prefix refs/heads/\n")
+    );
+    let options = ServerOptions::default()
+        .with_repository_overrides(false)
+        .with_protocol(ProtocolVersion::V2)
+        .with_stall_detection(StallDetection::new(1000, Duration::from_millis(50)));
+    let mut server = Server::new(tmp.path(), options.clone()).unwrap();
+    let err = server
+        .serve(Trickle(std::io::Cursor::new(request.clone().into_bytes())), Vec::new())
+        .unwrap_err();
+    assert!(matches!(err, Error::Resource(_)), "{err:?}");
+    assert!(err.is_client_error());
+    assert!(err.to_string().contains("at least 1000 are required"), "{err}");
+
+    let mut output = Vec::new();
+    Server::new(tmp.path(), options)
+        .unwrap()
+        .serve(request.as_bytes(), &mut output)
+        .expect("fast clients aren't affected");
+    assert!(String::from_utf8_lossy(&output).contains("refs/heads/main"));
+}
@@ -0,0 +1,112 @@
+//! Ending sessions of inactive clients, like `git upload-pack --timeout`
+
+use crate::util::git;
+use gix_upload_pack::services::IdleMonitor;
+use gix_upload_pack::{Error, ProtocolVersion, Server, ServerOptions};
+use std::io::Read;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+fn repository(dir: &Path) {
+    git(dir, &["init", "--quiet"]);
+    std::fs::write(dir.join("file"), "content\n").unwrap();
+    git(dir, &["add", "."]);
+    git(dir, &["commit", "--quiet", "-m", "initial"]);
+}
+
+/// Sends nothing for `delay`, and then nothing at all
+struct Silent {
+    delay: Duration,
+}
+
+impl Read for Silent {
+    fn read(&mut self, _buf: &mut [u8]) -> std::io::Result<usize> {
+        std::thread::sleep(self.delay);
+        Ok(0)
+    }
+}
+
+#[test]
+fn clients_that_stay_silent_for_longer_than_the_timeout_fail() {
+    let tmp = tempfile::tempdir().unwrap();
+    repository(tmp.path());
+    let options = ServerOptions::default()
+        .with_stateless_rpc(true)
+        .with_repository_overrides(false)
+        .with_protocol(ProtocolVersion::V2)
+        .with_timeout(Duration::from_secs(1));
+
+    let silent = Silent {
+        delay: Duration::from_millis(1100),
+    };
+    let err = Server::new(tmp.path(), options.clone())
+        .unwrap()
+        .serve(silent, Vec::new())
+        .unwrap_err();
+    assert!(
+        matches!(&err, Error::Resource(message) if message == "client was inactive for more than 1s"),
+        "{err:?}"
+    );
+
+    let prompt = Silent {
+        delay: Duration::from_millis(1),
+    };
+    Server::new(tmp.path(), options)
+        .unwrap()
+        .serve(prompt, Vec::new())
+        .expect("clients within the timeout are served");
+}
+
+#[test]
+fn callers_watch_the_inactivity_of_the_session() {
+    let tmp = tempfile::tempdir().unwrap();
+    repository(tmp.path());
+    let options = ServerOptions::default()
+        .with_stateless_rpc(true)
+        .with_repository_overrides(false)
+        .with_protocol(ProtocolVersion::V2)
+        .with_timeout(Duration::from_secs(10));
+    let mut server = Server::new(tmp.path(), options).unwrap();
+
+    let idle = IdleMonitor::default();
+    let silent = Silent {
+        delay: Duration::from_millis(200),
+    };
+    std::thread::scope(|scope| {
+        let session = scope.spawn(|| server.serve_monitored(silent, Vec::new(), &idle));
+        std::thread::sleep(Duration::from_millis(100));
+        assert!(idle.idle().is_some(), "the session is blocked on the client");
+        session.join().unwrap().expect("the client is within the timeout");
+    });
+    assert_eq!(idle.idle(), None);
+    assert_eq!(idle.timed_out(), None);
+}
+
+#[test]
+fn the_binary_exits_once_a_read_blocks_for_longer_than_the_timeout() {
+    let tmp = tempfile::tempdir().unwrap();
+    repository(tmp.path());
+    let start = Instant::now();
+    let mut child = Command::new(assert_cmd::cargo::cargo_bin("gix-upload-pack"))
+        .args(["--timeout=1"])
+        .arg(tmp.path())
+        .env("GIT_PROTOCOL", "version=2")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("upload-pack can be started");
+    // Keep stdin open without sending anything, like a client that hangs
+    let _stdin = child.stdin.take();
+    let status = child.wait().unwrap();
+    let mut stderr = String::new();
+    child.stderr.take().unwrap().read_to_string(&mut stderr).unwrap();
+
+    assert_eq!(status.code(), Some(128));
+    assert_eq!(stderr, "fatal: client was inactive for more than 1s\n");
+    assert!(
+        start.elapsed() < Duration::from_secs(10),
+        "the timeout ended the session"
+    );
+}
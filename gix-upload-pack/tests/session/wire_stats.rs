@@ -0,0 +1,80 @@
+//! Sessions report the bytes they exchanged, with pack data told apart from pkt-line framing
+
+use crate::util::{git, pkt};
+use gix_upload_pack::server::Step;
+use gix_upload_pack::{ProtocolVersion, Server, ServerOptions, WireStats};
+
+/// Return the pack sent on sideband channel 1 in `response`, and the bytes of its pkt-line lengths and bands
+fn sideband_pack(response: &[u8]) -> (Vec<u8>, u64) {
+    let (mut pack, mut framing) = (Vec::new(), 0);
+    let mut rest = response;
+    while rest.len() >= 4 {
+        let len = usize::from_str_radix(std::str::from_utf8(&rest[..4]).unwrap(), 16).unwrap();
+        framing += 4;
+        if len < 4 {
+            rest = &rest[4..];
+            continue;
+        }
+        if (1..=3).contains(&rest[4]) {
+            framing += 1;
+        }
+        if rest[4] == 1 {
+            pack.extend_from_slice(&rest[5..len]);
+        }
+        rest = &rest[len..];
+    }
+    (pack, framing)
+}
+
+#[test]
+fn sessions_count_their_requests_responses_and_pack() {
+    let tmp = tempfile::tempdir().unwrap();
+    git(tmp.path(), &["init", "--quiet"]);
+    std::fs::write(tmp.path().join("file"), "content\n").unwrap();
+    git(tmp.path(), &["add", "file"]);
+    git(tmp.path(), &["commit", "--quiet", "-m", "initial"]);
+    let head = git(tmp.path(), &["rev-parse", "HEAD"]);
+    let options = ServerOptions::default()
+        .with_stateless_rpc(true)
+        .with_repository_overrides(false);
+    let mut server = Server::new(tmp.path(), options).unwrap();
+
+    let request = format!(
+        "{}0000{}",
+        pkt(&format!("want {head} side-band-64k no-progress\n")),
+        pkt("done\n")
+    );
+    let mut output = Vec::new();
+    let outcome = server.serve(request.as_bytes(), &mut output).unwrap();
+    let (pack, framing) = sideband_pack(&output);
+    assert!(pack.starts_with(b"PACK"));
+    assert_eq!(
+        outcome.wire,
+        WireStats {
+            request_bytes: request.len() as u64,
+            response_bytes: output.len() as u64,
+            pack_bytes: pack.len() as u64,
+            framing_bytes: 3 * 4 + framing, // the request has three pkt-lines
+        }
+    );
+
+    let request = format!(
+        "{}0001{}{}0000",
+        pkt("command=fetch\n"),
+        pkt(&format!("want {head}\n")),
+        pkt("done\n")
+    );
+    let mut session = server.step_session(ProtocolVersion::V2).unwrap();
+    session.push_input(request.as_bytes());
+    session.finish_input();
+    let mut output = Vec::new();
+    while session.serve_step(&mut output).unwrap() != Step::Done {}
+    let wire = session.wire();
+    assert_eq!(wire.request_bytes, request.len() as u64);
+    assert_eq!(wire.response_bytes, output.len() as u64);
+    assert_eq!(
+        wire.pack_bytes,
+        pack.len() as u64,
+        "the same pack is sent with protocol v2"
+    );
+}
@@ -0,0 +1,87 @@
+//! `tracing` spans for the phases of sessions with `ServerOptions::enable_tracing`
+#![cfg(feature = "tracing")]
+
+// A test binary of its own, as the spans of all tests running in it would be recorded
+mod util;
+
+use gix_upload_pack::{ProtocolVersion, Server, ServerOptions};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use tracing::{span, Event, Metadata, Subscriber};
+use util::{git, pkt};
+
+/// Records the names of all spans
+#[derive(Default, Clone)]
+struct Spans(Arc<Mutex<Vec<&'static str>>>);
+
+impl Subscriber for Spans {
+    fn enabled(&self, _metadata: &Metadata<'_>) -> bool {
+        true
+    }
+
+    fn new_span(&self, span: &span::Attributes<'_>) -> span::Id {
+        let mut names = self.0.lock().unwrap();
+        names.push(span.metadata().name());
+        span::Id::from_u64(names.len() as u64)
+    }
+
+    fn record(&self, _span: &span::Id, _values: &span::Record<'_>) {}
+
+    fn record_follows_from(&self, _span: &span::Id, _follows: &span::Id) {}
+
+    fn event(&self, _event: &Event<'_>) {}
+
+    fn enter(&self, _span: &span::Id) {}
+
+    fn exit(&self, _span: &span::Id) {}
+}
+
+const PHASES: [&str; 5] = ["handshake", "negotiation", "counting", "compression", "streaming"];
+
+/// The phases among the spans recorded while advertising the capabilities of `dir` and fetching `head` with protocol
+/// v2, in the order they started
+fn phases(spans: &Spans, dir: &Path, head: &str, enable_tracing: bool) -> Vec<&'static str> {
+    spans.0.lock().unwrap().clear();
+    let options = ServerOptions::default()
+        .with_stateless_rpc(true)
+        .with_repository_overrides(false)
+        .with_protocol(ProtocolVersion::V2)
+        .with_tracing(enable_tracing);
+    Server::new(dir, options.clone().with_advertise_refs(true))
+        .unwrap()
+        .serve(&b""[..], Vec::new())
+        .unwrap();
+    let request = pkt("command=fetch\n")
+        + &pkt("object-format=sha1\n")
+        + "0001"
+        + &pkt(&format!("want {head}\n"))
+        + &pkt("done\n")
+        + "0000";
+    Server::new(dir, options)
+        .unwrap()
+        .serve(request.as_bytes(), Vec::new())
+        .unwrap();
+
+    let mut phases = spans.0.lock().unwrap().clone();
+    phases.retain(|name| PHASES.contains(name));
+    phases
+}
+
+#[test]
+fn session_phases_are_spans_if_enabled() {
+    let spans = Spans::default();
+    // Counting runs on another thread, which only sees a global subscriber
+    tracing::subscriber::set_global_default(spans.clone()).unwrap();
+    let tmp = tempfile::tempdir().unwrap();
+    git(tmp.path(), &["init", "--quiet"]);
+    std::fs::write(tmp.path().join("file"), "content\n").unwrap();
+    git(tmp.path(), &["add", "."]);
+    git(tmp.path(), &["commit", "--quiet", "-m", "initial"]);
+    let head = git(tmp.path(), &["rev-parse", "HEAD"]);
+
+    assert_eq!(
+        phases(&spans, tmp.path(), &head, true),
+        ["handshake", "negotiation", "counting", "streaming", "compression"]
+    );
+    assert_eq!(phases(&spans, tmp.path(), &head, false), Vec::<&str>::new());
+}
@@ -0,0 +1,122 @@
+//! Helpers shared by the tests and benchmarks, to set up repositories with native git, write requests and read the
+//! packs sent for them
+
+// Each test binary and benchmark uses only some of them
+#![allow(dead_code)]
+
+use gix_hash::ObjectId;
+use gix_upload_pack::{server::Step, ProtocolVersion, Server, ServerOptions};
+use std::collections::HashSet;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+/// A `git` command running in `dir`, with an author and committer to make commits
+pub fn git_command(dir: &Path) -> Command {
+    let mut git = Command::new("git");
+    git.current_dir(dir)
+        .env("GIT_AUTHOR_NAME", "author")
+        .env("GIT_AUTHOR_EMAIL", "author@example.com")
+        .env("GIT_COMMITTER_NAME", "committer")
+        .env("GIT_COMMITTER_EMAIL", "committer@example.com");
+    git
+}
+
+/// Run `command` and return its trimmed standard output, failing with its standard error unless it succeeded
+pub fn run(command: &mut Command) -> String {
+    let output = command.output().expect("git is installed");
+    assert!(
+        output.status.success(),
+        "git {:?} failed: {}",
+        command.get_args().collect::<Vec<_>>(),
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8(output.stdout).unwrap().trim().to_owned()
+}
+
+/// Run `git` with `args` in `dir`, see [`run()`]
+pub fn git(dir: &Path, args: &[&str]) -> String {
+    run(git_command(dir).args(args))
+}
+
+/// `line` as packet line
+pub fn pkt(line: &str) -> String {
+    format!("{:04x}{line}", line.len() + 4)
+}
+
+/// Fetch `wants` from the repository at `dir` with protocol v2 and `filter`, telling the server about `haves`, and
+/// return the pack it sent
+pub fn fetch_filtered(
+    dir: &Path,
+    options: ServerOptions,
+    wants: &[&str],
+    haves: &[&str],
+    filter: &str,
+) -> Result<Vec<u8>, gix_upload_pack::Error> {
+    let mut request = format!("{}0001", pkt("command=fetch\n"));
+    for want in wants {
+        request += &pkt(&format!("want {want}\n"));
+    }
+    for have in haves {
+        request += &pkt(&format!("have {have}\n"));
+    }
+    request += &format!("{}{}0000", pkt(&format!("filter {filter}\n")), pkt("done\n"));
+
+    let options = options.with_stateless_rpc(true).with_repository_overrides(false);
+    let mut server = Server::new(dir, options).unwrap();
+    let mut session = server.step_session(ProtocolVersion::V2).unwrap();
+    session.push_input(request.as_bytes());
+    session.finish_input();
+    let mut output = Vec::new();
+    while session.serve_step(&mut output)? != Step::Done {}
+    Ok(extract_pack(&output))
+}
+
+/// Return the pack sent on the first sideband channel after the `packfile` section header
+pub fn extract_pack(mut output: &[u8]) -> Vec<u8> {
+    let mut pack = Vec::new();
+    let mut in_packfile = false;
+    while output.len() >= 4 {
+        let len = usize::from_str_radix(std::str::from_utf8(&output[..4]).unwrap(), 16).unwrap();
+        if len < 4 {
+            output = &output[4..];
+            continue;
+        }
+        let data = &output[4..len];
+        if in_packfile && data[0] == 1 {
+            pack.extend_from_slice(&data[1..]);
+        }
+        in_packfile |= data == b"packfile\n";
+        output = &output[len..];
+    }
+    pack
+}
+
+/// Index `pack` like a client would and return its objects
+pub fn index_pack(dir: &Path, pack: &[u8]) -> HashSet<ObjectId> {
+    let tmp = tempfile::tempdir().unwrap();
+    let path = tmp.path().join("received.pack");
+    std::fs::write(&path, pack).unwrap();
+    let idx = tmp.path().join("received.idx");
+    git(
+        dir,
+        &["index-pack", "-o", idx.to_str().unwrap(), path.to_str().unwrap()],
+    );
+    git(dir, &["verify-pack", "-v", idx.to_str().unwrap()])
+        .lines()
+        .filter_map(|line| ObjectId::from_hex(line.split_whitespace().next()?.as_bytes()).ok())
+        .collect()
+}
+
+/// Run `git upload-pack` on `dir` for protocol v2 with `request`
+pub fn native_v2(dir: &Path, request: &str) -> String {
+    let mut child = Command::new("git")
+        .args(["upload-pack", "--stateless-rpc", "."])
+        .current_dir(dir)
+        .env("GIT_PROTOCOL", "version=2")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("git is installed");
+    std::io::Write::write_all(&mut child.stdin.take().unwrap(), request.as_bytes()).unwrap();
+    String::from_utf8(child.wait_with_output().unwrap().stdout).unwrap()
+}